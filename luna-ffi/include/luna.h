@@ -0,0 +1,65 @@
+/*
+ * luna.h - C ABI of LUNA (luna-ffi)
+ *
+ * Requests and results are UTF-8 JSON in versioned schemas: luna.do/v1,
+ * luna.find/v1, luna.analyze/v1, luna.event/v1 and luna.error/v1. Fields
+ * are only ever added within a version; LUNA_ABI_VERSION changes when a
+ * function below does.
+ *
+ * Calls block until their result is ready and may come from any thread.
+ * Failures return NULL (or -1) and leave a luna.error/v1 document for
+ * luna_last_error() on the calling thread; bad JSON arguments have kind
+ * "invalid_argument", and a panic inside LUNA fails the call with kind
+ * "internal" rather than aborting the process. Strings returned by the
+ * library are freed with luna_string_free().
+ */
+
+#ifndef LUNA_H
+#define LUNA_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define LUNA_ABI_VERSION 1
+
+typedef struct LunaInstance LunaInstance;
+
+/* Runs on the instance's worker thread with a luna.event/v1 document */
+typedef void (*luna_event_callback)(const char *event_json, void *user_data);
+
+uint32_t luna_abi_version(void);
+
+/* config_json is laid over the defaults; NULL uses them as they are */
+LunaInstance *luna_new(const char *config_json);
+void luna_free(LunaInstance *luna);
+
+/* options_json: {"dry_run": bool, "full": bool, "region": [x, y, w, h],
+ * "speed": number, "restore": bool}, every field optional, or NULL.
+ * Returns luna.do/v1. */
+char *luna_execute(const LunaInstance *luna, const char *command, const char *options_json);
+
+/* Answers the question of a command that failed with kind
+ * "needs_clarification" and runs it; returns luna.do/v1 */
+char *luna_pick(const LunaInstance *luna, const char *answer, const char *options_json);
+
+char *luna_analyze(const LunaInstance *luna);
+char *luna_find(const LunaInstance *luna, const char *query);
+
+/* Returns 0, or -1 on failure */
+int luna_subscribe(const LunaInstance *luna, luna_event_callback callback, void *user_data);
+
+/* Stops running commands before their next action, from any thread */
+void luna_cancel(const LunaInstance *luna);
+
+/* Owned by the library; valid until the next failing call on this thread */
+const char *luna_last_error(void);
+void luna_string_free(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LUNA_H */
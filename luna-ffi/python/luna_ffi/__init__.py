@@ -0,0 +1,198 @@
+"""Python bindings for LUNA over its C ABI (luna-ffi).
+
+    from luna_ffi import Luna
+
+    with Luna({"speed": {"countdown_ms": 0}}) as luna:
+        luna.on_event(lambda event: print(event["event"]))
+        result = luna.execute("click the save button", dry_run=True)
+        print(result["actions"])
+
+Calls block until LUNA is done and release the GIL meanwhile, so other
+Python threads keep running; `cancel()` from another thread stops a running
+command. Event callbacks run on LUNA's worker thread.
+
+The shared library is found through $LUNA_FFI_LIBRARY, next to this file,
+or in the cargo target directory of the repository this package sits in.
+"""
+
+import ctypes
+import json
+import os
+import sys
+from pathlib import Path
+
+ABI_VERSION = 1
+
+__all__ = ["ABI_VERSION", "Luna", "LunaError", "ClarificationNeeded"]
+
+
+class LunaError(Exception):
+    """A failed call; `kind` is the luna.error/v1 kind ("unsafe", "not_found", ...)."""
+
+    def __init__(self, document):
+        super().__init__(document.get("error", "unknown error"))
+        self.kind = document.get("kind", "internal")
+        self.document = document
+
+
+class ClarificationNeeded(LunaError):
+    """The command was ambiguous; answer `question` with `Luna.pick`."""
+
+    def __init__(self, document):
+        super().__init__(document)
+        clarification = document.get("clarification") or {}
+        self.question = clarification.get("question", "")
+        self.options = [option["label"] for option in clarification.get("options", [])]
+
+
+_EventCallback = ctypes.CFUNCTYPE(None, ctypes.c_char_p, ctypes.c_void_p)
+
+
+def _library_name():
+    if sys.platform == "win32":
+        return "luna_ffi.dll"
+    if sys.platform == "darwin":
+        return "libluna_ffi.dylib"
+    return "libluna_ffi.so"
+
+
+def _load():
+    name = _library_name()
+    here = Path(__file__).resolve().parent
+    candidates = [os.environ.get("LUNA_FFI_LIBRARY"), here / name]
+    candidates += [here.parents[2] / "target" / profile / name for profile in ("release", "debug")]
+    for candidate in candidates:
+        if candidate and Path(candidate).exists():
+            library = ctypes.CDLL(str(candidate))
+            break
+    else:
+        raise OSError(f"{name} not found; build it with `cargo build -p luna-ffi --release` or set LUNA_FFI_LIBRARY")
+
+    library.luna_abi_version.restype = ctypes.c_uint32
+    library.luna_new.argtypes = [ctypes.c_char_p]
+    library.luna_new.restype = ctypes.c_void_p
+    library.luna_free.argtypes = [ctypes.c_void_p]
+    for function, arguments in [
+        ("luna_execute", [ctypes.c_char_p, ctypes.c_char_p]),
+        ("luna_pick", [ctypes.c_char_p, ctypes.c_char_p]),
+        ("luna_analyze", []),
+        ("luna_find", [ctypes.c_char_p]),
+    ]:
+        getattr(library, function).argtypes = [ctypes.c_void_p] + arguments
+        # A void pointer, not c_char_p, so the string can be freed afterwards
+        getattr(library, function).restype = ctypes.c_void_p
+    library.luna_subscribe.argtypes = [ctypes.c_void_p, _EventCallback, ctypes.c_void_p]
+    library.luna_subscribe.restype = ctypes.c_int
+    library.luna_cancel.argtypes = [ctypes.c_void_p]
+    library.luna_last_error.restype = ctypes.c_char_p
+    library.luna_string_free.argtypes = [ctypes.c_void_p]
+
+    if library.luna_abi_version() != ABI_VERSION:
+        raise OSError(f"luna-ffi ABI {library.luna_abi_version()} does not match these bindings ({ABI_VERSION})")
+    return library
+
+
+_library = None
+
+
+def _lib():
+    global _library
+    if _library is None:
+        _library = _load()
+    return _library
+
+
+def _error():
+    raw = _lib().luna_last_error()
+    document = json.loads(raw) if raw else {"kind": "internal", "error": "unknown error"}
+    if document.get("kind") == "needs_clarification":
+        return ClarificationNeeded(document)
+    return LunaError(document)
+
+
+def _encode(value):
+    return None if value is None else value.encode("utf-8")
+
+
+class Luna:
+    """One LUNA instance with its own worker thread."""
+
+    def __init__(self, config=None):
+        self._handle = _lib().luna_new(_encode(None if config is None else json.dumps(config)))
+        if not self._handle:
+            raise _error()
+        # Callbacks must outlive the instance; ctypes frees them with their last reference
+        self._callbacks = []
+
+    def _call(self, function, *arguments):
+        if not self._handle:
+            raise LunaError({"kind": "invalid_argument", "error": "instance is closed"})
+        raw = getattr(_lib(), function)(self._handle, *arguments)
+        if not raw:
+            raise _error()
+        try:
+            return json.loads(ctypes.string_at(raw).decode("utf-8"))
+        finally:
+            _lib().luna_string_free(raw)
+
+    @staticmethod
+    def _options(dry_run, full, region, speed, restore):
+        options = {"dry_run": dry_run, "full": full}
+        if region is not None:
+            options["region"] = list(region)
+        if speed is not None:
+            options["speed"] = speed
+        if restore is not None:
+            options["restore"] = restore
+        return json.dumps(options).encode("utf-8")
+
+    def execute(self, command, dry_run=False, full=False, region=None, speed=None, restore=None):
+        """Plan and execute `command`; returns the luna.do/v1 document.
+
+        Raises ClarificationNeeded when it fits several elements."""
+        return self._call("luna_execute", _encode(command), self._options(dry_run, full, region, speed, restore))
+
+    def pick(self, answer, dry_run=False, full=False, region=None, speed=None, restore=None):
+        """Answer the last ClarificationNeeded ("the second one") and run the command."""
+        return self._call("luna_pick", _encode(answer), self._options(dry_run, full, region, speed, restore))
+
+    def analyze(self):
+        """Elements on screen now, as a luna.analyze/v1 document."""
+        return self._call("luna_analyze")
+
+    def find(self, query):
+        """Elements matching "TYPE:TEXT", "TYPE:" or "TEXT", as luna.find/v1."""
+        return self._call("luna_find", _encode(query))["elements"]
+
+    def on_event(self, callback):
+        """Call `callback(event)` with every luna.event/v1 document from now on."""
+
+        def trampoline(event_json, _user_data):
+            try:
+                callback(json.loads(event_json.decode("utf-8")))
+            except Exception as error:  # noqa: BLE001 - never unwind into Rust
+                print(f"luna event callback failed: {error}", file=sys.stderr)
+
+        c_callback = _EventCallback(trampoline)
+        self._callbacks.append(c_callback)
+        if _lib().luna_subscribe(self._handle, c_callback, None) != 0:
+            raise _error()
+
+    def cancel(self):
+        """Stop running commands before their next action (from any thread)."""
+        if self._handle:
+            _lib().luna_cancel(self._handle)
+
+    def close(self):
+        if self._handle:
+            _lib().luna_free(self._handle)
+            self._handle = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *_):
+        self.close()
+
+    def __del__(self):
+        self.close()
@@ -0,0 +1,498 @@
+//! # luna-ffi
+//!
+//! A stable C ABI over the LUNA facade, for Python (see `python/`) and any
+//! other language with a C FFI. The header is `include/luna.h`.
+//!
+//! Everything crosses the boundary as UTF-8 JSON, in the same versioned
+//! schemas `luna do --json` and `luna find --json` print (`luna.do/v1`,
+//! `luna.find/v1`, `luna.error/v1`, plus `luna.analyze/v1` and
+//! `luna.event/v1` here), defined in `luna::core::schema` with JSON Schemas
+//! in `schemas/`. Fields are only ever added within a version, and
+//! `luna_abi_version` changes only when a function's signature does.
+//!
+//! Each instance runs a `LunaHandle` worker thread. Calls block until their
+//! result is ready and may come from any thread; `luna_cancel` stops running
+//! commands from another one. Event callbacks run on the worker thread.
+//!
+//! Failed calls return NULL (or -1) and leave a `luna.error/v1` document
+//! for `luna_last_error` on the calling thread. A panic inside LUNA fails
+//! the call the same way, with kind `internal`, instead of unwinding into
+//! (and aborting) the host. Strings returned to the caller are freed with
+//! `luna_string_free`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+
+use luna::core::handle::CancelToken;
+use luna::core::query::ElementQuery;
+use luna::core::schema::{self, AnalysisRecord, CommandRecord, ElementRecord, ErrorRecord, EventRecord, FindRecord};
+use luna::core::ElementBounds;
+use luna::{CommandResult, ExecuteOptions, LunaConfig, LunaError, LunaHandle};
+
+/// Version of the functions and their signatures in `luna.h`
+pub const LUNA_ABI_VERSION: u32 = 1;
+
+/// Called with a `luna.event/v1` document and the `user_data` given to
+/// `luna_subscribe`
+pub type EventCallback = extern "C" fn(event_json: *const c_char, user_data: *mut c_void);
+
+/// One LUNA instance, opaque to C
+pub struct LunaInstance {
+    handle: LunaHandle,
+    /// Cancel tokens of commands in flight, by call
+    running: Mutex<HashMap<u64, CancelToken>>,
+    next_call: AtomicU64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// `luna_execute` options; every field is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OptionsInput {
+    dry_run: bool,
+    /// Always capture and analyze
+    full: bool,
+    /// x, y, width, height
+    region: Option<[i32; 4]>,
+    speed: Option<f64>,
+    /// Put the cursor and focus back afterwards
+    restore: Option<bool>,
+}
+
+impl From<OptionsInput> for ExecuteOptions {
+    fn from(input: OptionsInput) -> Self {
+        ExecuteOptions {
+            region_constraint: input.region.map(|[x, y, width, height]| ElementBounds::new(x, y, width, height)),
+            force_full_pipeline: input.full,
+            dry_run: input.dry_run,
+            speed: input.speed,
+            restore: input.restore,
+            ..ExecuteOptions::default()
+        }
+    }
+}
+
+/// `config_json` laid over the defaults, so callers only name what they change
+fn config_from_json(config_json: Option<&str>) -> Result<LunaConfig> {
+    let invalid = |e: &dyn std::fmt::Display| LunaError::InvalidArgument(format!("config_json: {}", e));
+    let mut config = serde_json::to_value(LunaConfig::default())?;
+    if let Some(overrides) = config_json {
+        merge(&mut config, serde_json::from_str(overrides).map_err(|e| invalid(&e))?);
+    }
+    let config: LunaConfig = serde_json::from_value(config).map_err(|e| invalid(&e))?;
+    config.validate().map_err(|e| invalid(&e))?;
+    Ok(config)
+}
+
+/// # Safety
+///
+/// As `optional_str`.
+unsafe fn options_from_json(options_json: *const c_char) -> Result<ExecuteOptions> {
+    let options: Option<OptionsInput> = optional_str(options_json)?
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|e| LunaError::InvalidArgument(format!("options_json: {}", e)))?;
+    Ok(options.unwrap_or_default().into())
+}
+
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+fn set_last_error(error: &anyhow::Error, clarification: Option<Value>) {
+    let output = schema::to_document(&ErrorRecord { clarification, ..ErrorRecord::from(error) });
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(output.to_string()).ok());
+}
+
+/// Run the body of an `extern "C"` function; a panic is recorded as the
+/// call's error and `failed` returned, since unwinding into C aborts
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(&anyhow::anyhow!("LUNA panicked: {}", message), None);
+            failed
+        }
+    }
+}
+
+/// JSON result for C, or NULL with the error recorded
+fn respond(result: Result<Value>) -> *mut c_char {
+    match result.and_then(|value| Ok(CString::new(value.to_string())?)) {
+        Ok(text) => text.into_raw(),
+        Err(e) => {
+            set_last_error(&e, None);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `text` must be NULL or a NUL-terminated string that outlives `'a`.
+unsafe fn optional_str<'a>(text: *const c_char) -> Result<Option<&'a str>> {
+    if text.is_null() {
+        return Ok(None);
+    }
+    let text = CStr::from_ptr(text).to_str().map_err(|_| LunaError::InvalidArgument("string is not UTF-8".to_string()))?;
+    Ok(Some(text))
+}
+
+/// # Safety
+///
+/// As `optional_str`, but NULL is an error.
+unsafe fn required_str<'a>(text: *const c_char, what: &str) -> Result<&'a str> {
+    optional_str(text)?.ok_or_else(|| LunaError::InvalidArgument(format!("{} is NULL", what)).into())
+}
+
+/// # Safety
+///
+/// `luna` must be NULL or a pointer returned by `luna_new` and not yet freed.
+unsafe fn instance<'a>(luna: *const LunaInstance) -> Result<&'a LunaInstance> {
+    luna.as_ref().ok_or_else(|| LunaError::InvalidArgument("instance is NULL".to_string()).into())
+}
+
+impl LunaInstance {
+    /// Run a command-like request, cancellable with `luna_cancel`
+    fn run<T>(&self, pending: luna::core::handle::Pending<T>) -> Result<T> {
+        let call = self.next_call.fetch_add(1, Ordering::SeqCst);
+        self.running.lock().unwrap_or_else(std::sync::PoisonError::into_inner).insert(call, pending.cancel_token());
+        let result = pending.wait();
+        self.running.lock().unwrap_or_else(std::sync::PoisonError::into_inner).remove(&call);
+        result
+    }
+
+    /// The question a failed command left open, if it failed for want of one
+    fn clarification(&self, error: &anyhow::Error) -> Option<Value> {
+        if !matches!(error.downcast_ref::<LunaError>(), Some(LunaError::NeedsClarification(_))) {
+            return None;
+        }
+        let clarification = self.handle.pending_clarification().wait().ok()??;
+        serde_json::to_value(clarification).ok()
+    }
+
+    fn command_response(&self, command: &str, result: Result<CommandResult>) -> *mut c_char {
+        match result {
+            Ok(result) => respond(Ok(schema::to_document(&CommandRecord::new(command, &result)))),
+            Err(e) => {
+                set_last_error(&e, self.clarification(&e));
+                std::ptr::null_mut()
+            }
+        }
+    }
+}
+
+/// Version of the functions in `luna.h`; check it before any other call
+#[no_mangle]
+pub extern "C" fn luna_abi_version() -> u32 {
+    LUNA_ABI_VERSION
+}
+
+/// Start an instance. `config_json` is laid over the default configuration
+/// (`{"safety": {"max_actions_per_command": 5}}`); NULL uses the defaults.
+/// Returns NULL on failure.
+///
+/// # Safety
+///
+/// `config_json` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn luna_new(config_json: *const c_char) -> *mut LunaInstance {
+    guard(std::ptr::null_mut(), || {
+        let started = optional_str(config_json)
+            .and_then(config_from_json)
+            .and_then(LunaHandle::spawn);
+        match started {
+            Ok(handle) => Box::into_raw(Box::new(LunaInstance {
+                handle,
+                running: Mutex::new(HashMap::new()),
+                next_call: AtomicU64::new(0),
+            })),
+            Err(e) => {
+                set_last_error(&e, None);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Stop the worker and free the instance. NULL is ignored.
+///
+/// # Safety
+///
+/// `luna` must be NULL or a pointer returned by `luna_new`, with no calls
+/// on it still running; it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn luna_free(luna: *mut LunaInstance) {
+    guard((), || {
+        if !luna.is_null() {
+            let luna = Box::from_raw(luna);
+            luna.handle.shutdown();
+        }
+    })
+}
+
+/// Plan and (unless `dry_run`) execute a command; blocks until it finishes.
+/// `options_json` holds `dry_run`, `full`, `region` ([x, y, w, h]), `speed`
+/// and `restore`, all optional; NULL uses the defaults. Returns a `luna.do/v1`
+/// document. An ambiguous command fails with kind `needs_clarification` and
+/// the question under `clarification`; answer it with `luna_pick`.
+///
+/// # Safety
+///
+/// `luna` must come from `luna_new`; the strings must be NULL or
+/// NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn luna_execute(luna: *const LunaInstance, command: *const c_char, options_json: *const c_char) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let request = || -> Result<(&LunaInstance, &str, ExecuteOptions)> {
+            Ok((instance(luna)?, required_str(command, "command")?, options_from_json(options_json)?))
+        };
+        match request() {
+            Ok((luna, command, options)) => {
+                let result = luna.run(luna.handle.execute_command(command, options));
+                luna.command_response(command, result)
+            }
+            Err(e) => respond(Err(e)),
+        }
+    })
+}
+
+/// Answer the question the last command failed with ("the second one",
+/// "Notepad") and run it against the element picked. Takes the same
+/// options as `luna_execute` and returns a `luna.do/v1` document.
+///
+/// # Safety
+///
+/// As `luna_execute`.
+#[no_mangle]
+pub unsafe extern "C" fn luna_pick(luna: *const LunaInstance, answer: *const c_char, options_json: *const c_char) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let request = || -> Result<(&LunaInstance, &str, ExecuteOptions)> {
+            Ok((instance(luna)?, required_str(answer, "answer")?, options_from_json(options_json)?))
+        };
+        match request() {
+            Ok((luna, answer, options)) => {
+                let result = luna.run(luna.handle.answer_clarification(answer, options));
+                luna.command_response(answer, result)
+            }
+            Err(e) => respond(Err(e)),
+        }
+    })
+}
+
+/// Capture and analyze the screen; returns a `luna.analyze/v1` document
+///
+/// # Safety
+///
+/// `luna` must come from `luna_new`.
+#[no_mangle]
+pub unsafe extern "C" fn luna_analyze(luna: *const LunaInstance) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        respond(instance(luna).and_then(|luna| {
+            let analysis = luna.run(luna.handle.analyze_current_screen())?;
+            Ok(schema::to_document(&AnalysisRecord::from(&analysis)))
+        }))
+    })
+}
+
+/// Elements on screen matching `TYPE:TEXT`, `TYPE:` or `TEXT`; returns a
+/// `luna.find/v1` document
+///
+/// # Safety
+///
+/// `luna` must come from `luna_new`; `query` must be NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn luna_find(luna: *const LunaInstance, query: *const c_char) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        respond((|| {
+            let (luna, query) = (instance(luna)?, required_str(query, "query")?.to_string());
+            let normalized = ElementQuery::parse(&query).to_string();
+            let elements = luna.run(luna.handle.call(move |luna, _| luna.find(&query)))?;
+            Ok(schema::to_document(&FindRecord { query: normalized, elements: elements.iter().map(ElementRecord::from).collect() }))
+        })())
+    })
+}
+
+/// Call `callback` with a `luna.event/v1` document for every event from now
+/// on. It runs on the instance's worker thread, between steps of a command,
+/// and should return quickly. Returns 0, or -1 on failure.
+///
+/// # Safety
+///
+/// `luna` must come from `luna_new`. `callback` must stay callable, and
+/// `user_data` valid from any thread, until the instance is freed.
+#[no_mangle]
+pub unsafe extern "C" fn luna_subscribe(luna: *const LunaInstance, callback: Option<EventCallback>, user_data: *mut c_void) -> c_int {
+    /// The caller vouches that `user_data` may be used from the worker thread
+    struct UserData(*mut c_void);
+    unsafe impl Send for UserData {}
+    unsafe impl Sync for UserData {}
+    impl UserData {
+        // A method, so closures capture the wrapper and not the bare pointer
+        fn get(&self) -> *mut c_void {
+            self.0
+        }
+    }
+
+    guard(-1, || {
+        let subscribed = instance(luna).and_then(|luna| {
+            let callback = callback.ok_or_else(|| LunaError::InvalidArgument("callback is NULL".to_string()))?;
+            let user_data = UserData(user_data);
+            luna.handle
+                .subscribe_to_events(move |event| {
+                    if let Ok(text) = CString::new(schema::to_document(&EventRecord::from(&event)).to_string()) {
+                        callback(text.as_ptr(), user_data.get());
+                    }
+                })
+                .wait()
+        });
+        match subscribed {
+            Ok(()) => 0,
+            Err(e) => {
+                set_last_error(&e, None);
+                -1
+            }
+        }
+    })
+}
+
+/// Cancel every command running on the instance; each stops before its
+/// next action and fails with kind `cancelled`. Safe to call from any
+/// thread while another is blocked in `luna_execute`.
+///
+/// # Safety
+///
+/// `luna` must be NULL or come from `luna_new`.
+#[no_mangle]
+pub unsafe extern "C" fn luna_cancel(luna: *const LunaInstance) {
+    guard((), || {
+        if let Ok(luna) = instance(luna) {
+            for token in luna.running.lock().unwrap_or_else(std::sync::PoisonError::into_inner).values() {
+                token.cancel();
+            }
+        }
+    })
+}
+
+/// `luna.error/v1` document of the last failed call on this thread, or NULL.
+/// Valid until the next failing call on the thread; do not free it.
+#[no_mangle]
+pub extern "C" fn luna_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |error| error.as_ptr()))
+}
+
+/// Free a string returned by this library. NULL is ignored.
+///
+/// # Safety
+///
+/// `text` must be NULL or a string returned by a `luna_*` function, freed
+/// only once.
+#[no_mangle]
+pub unsafe extern "C" fn luna_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn take(text: *mut c_char) -> Value {
+        assert!(!text.is_null(), "{}", last_error());
+        let value = serde_json::from_str(unsafe { CStr::from_ptr(text) }.to_str().unwrap()).unwrap();
+        unsafe { luna_string_free(text) };
+        value
+    }
+
+    fn last_error() -> Value {
+        let error = luna_last_error();
+        if error.is_null() {
+            return Value::Null;
+        }
+        serde_json::from_str(unsafe { CStr::from_ptr(error) }.to_str().unwrap()).unwrap()
+    }
+
+    extern "C" fn collect(event_json: *const c_char, user_data: *mut c_void) {
+        let events = unsafe { &*(user_data as *const Mutex<Vec<String>>) };
+        let event = unsafe { CStr::from_ptr(event_json) }.to_str().unwrap().to_string();
+        events.lock().unwrap().push(event);
+    }
+
+    #[test]
+    fn test_execute_and_events_through_the_c_abi() {
+        assert_eq!(luna_abi_version(), LUNA_ABI_VERSION);
+        let config = CString::new(r#"{"speed": {"countdown_ms": 0}}"#).unwrap();
+        let luna = unsafe { luna_new(config.as_ptr()) };
+        assert!(!luna.is_null(), "{}", last_error());
+
+        let events: &'static Mutex<Vec<String>> = Box::leak(Box::default());
+        let user_data = events as *const _ as *mut c_void;
+        assert_eq!(unsafe { luna_subscribe(luna, Some(collect), user_data) }, 0);
+
+        let command = CString::new("press ctrl+s").unwrap();
+        let options = CString::new(r#"{"dry_run": true}"#).unwrap();
+        let output = take(unsafe { luna_execute(luna, command.as_ptr(), options.as_ptr()) });
+        assert_eq!(output["schema"], "luna.do/v1");
+        assert_eq!(output["dry_run"], true);
+        assert_eq!(output["actions"][0], json!({"type": "keys", "keys": ["ctrl", "s"]}));
+        let events = events.lock().unwrap();
+        assert!(events.iter().any(|e| e.contains(r#""event":"actions_planned""#)), "{:?}", events);
+
+        let bad = CString::new(r#"{"dryrun": true}"#).unwrap();
+        assert!(unsafe { luna_execute(luna, command.as_ptr(), bad.as_ptr()) }.is_null());
+        assert_eq!((last_error()["schema"].as_str(), last_error()["kind"].as_str()), (Some("luna.error/v1"), Some("invalid_argument")));
+        assert!(unsafe { luna_execute(luna, std::ptr::null(), std::ptr::null()) }.is_null());
+        assert_eq!(last_error()["kind"], "invalid_argument");
+
+        let analysis = take(unsafe { luna_analyze(luna) });
+        assert_eq!(analysis["schema"], "luna.analyze/v1");
+        assert!(analysis["elements"].is_array());
+
+        unsafe { luna_free(luna) };
+    }
+
+    #[test]
+    fn test_config_overrides_defaults() {
+        let config = config_from_json(Some(r#"{"safety": {"max_actions_per_command": 3}}"#)).unwrap();
+        assert_eq!(config.safety.max_actions_per_command, 3);
+        assert_eq!(config.safety.enabled, LunaConfig::default().safety.enabled);
+        assert!(config_from_json(Some(r#"{"safety": {"max_actions_per_command": 0}}"#)).is_err());
+        let luna = unsafe { luna_new(c"{not json".as_ptr()) };
+        assert!(luna.is_null());
+        assert_eq!(last_error()["kind"], "invalid_argument");
+    }
+
+    #[test]
+    fn test_panics_become_errors_instead_of_unwinding_into_c() {
+        let result = guard(std::ptr::null_mut::<c_char>(), || panic!("detector blew up"));
+        assert!(result.is_null());
+        let error = last_error();
+        assert_eq!(error["kind"], "internal");
+        assert!(error["error"].as_str().unwrap().contains("detector blew up"), "{}", error);
+        assert_eq!(guard(-1, || -> c_int { panic!("{}", 7) }), -1);
+    }
+}
@@ -0,0 +1,145 @@
+// Clarifying questions for ambiguous commands
+// When a command fits several elements equally well ("close it" with two
+// windows open, "click Apply" with two Apply buttons) the planner asks which
+// one was meant instead of guessing. The answer picks one of the offered
+// options and planning resumes against that element, without starting over.
+
+use serde::Serialize;
+use std::fmt;
+
+use super::text_match;
+
+/// Answer words that say nothing about which option was meant
+const FILLER: &[&str] = &["one", "it", "please", "window", "dialog", "button", "link"];
+/// Ordinal words, by position
+const ORDINALS: &[&[&str]] = &[
+    &["1", "1st", "first", "one"],
+    &["2", "2nd", "second", "two"],
+    &["3", "3rd", "third", "three"],
+    &["4", "4th", "fourth", "four"],
+    &["5", "5th", "fifth", "five"],
+];
+
+/// One element the user can pick
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClarificationOption {
+    /// How the option is named in the question
+    pub label: String,
+    /// Index of the element in the analysis the question was asked about
+    pub element: usize,
+}
+
+/// A question the planner cannot answer on its own
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Clarification {
+    pub question: String,
+    pub options: Vec<ClarificationOption>,
+}
+
+impl Clarification {
+    /// "Close which window: Chrome or Notepad?" from `(element, name, click point)`
+    /// candidates. Options sharing a name are told apart by where they are.
+    pub fn which(verb: &str, noun: &str, candidates: Vec<(usize, String, (i32, i32))>) -> Self {
+        let options: Vec<ClarificationOption> = candidates
+            .iter()
+            .map(|(element, name, (x, y))| {
+                let shared = candidates.iter().filter(|(_, other, _)| other.eq_ignore_ascii_case(name)).count() > 1;
+                let label = if shared { format!("{} at ({}, {})", name, x, y) } else { name.clone() };
+                ClarificationOption { label, element: *element }
+            })
+            .collect();
+
+        let labels: Vec<&str> = options.iter().map(|o| o.label.as_str()).collect();
+        let listed = match labels.split_last() {
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => String::new(),
+        };
+        Self { question: format!("{} which {}: {}?", verb, noun, listed), options }
+    }
+
+    /// The option `answer` picks: by position ("2", "the second one", "last")
+    /// or by name ("Notepad"). `None` when it picks none or several.
+    pub fn resolve(&self, answer: &str) -> Option<&ClarificationOption> {
+        let folded = text_match::fold(answer);
+        let words: Vec<&str> = folded
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty() && *w != "the")
+            .collect();
+
+        let named: Vec<&str> = words.iter().copied().filter(|w| !FILLER.contains(w)).collect();
+        if named.is_empty() || named == ["last"] || named.iter().all(|w| ORDINALS.iter().any(|o| o.contains(w))) {
+            let position = match words.as_slice() {
+                ["last"] | ["last", "one"] => self.options.len().checked_sub(1),
+                [word] | [word, "one"] => ORDINALS.iter().position(|o| o.contains(word)),
+                _ => None,
+            };
+            if let Some(option) = position.and_then(|p| self.options.get(p)) {
+                return Some(option);
+            }
+        }
+
+        // Every word of the answer must be part of the option's name
+        let answer = named.join(" ");
+        let mut scored: Vec<(f64, &ClarificationOption)> = self
+            .options
+            .iter()
+            .filter_map(|option| Some((text_match::match_label(&option.label, &answer)?.score, option)))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        match scored.as_slice() {
+            [(_, only)] => Some(only),
+            [(best, option), (next, _), ..] if best > next => Some(option),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Clarification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.question)
+    }
+}
+
+impl std::error::Error for Clarification {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_question_names_each_option() {
+        let windows = Clarification::which("Close", "window", vec![
+            (2, "Google Chrome".to_string(), (400, 10)),
+            (7, "Notepad".to_string(), (900, 10)),
+        ]);
+        assert_eq!(windows.question, "Close which window: Google Chrome or Notepad?");
+
+        let buttons = Clarification::which("Click", "button", vec![
+            (3, "Apply".to_string(), (60, 284)),
+            (5, "Apply".to_string(), (400, 224)),
+            (6, "Cancel".to_string(), (480, 224)),
+        ]);
+        assert_eq!(buttons.question, "Click which button: Apply at (60, 284), Apply at (400, 224) or Cancel?");
+    }
+
+    #[test]
+    fn test_answers_pick_by_position_or_name() {
+        let windows = Clarification::which("Close", "window", vec![
+            (2, "Google Chrome".to_string(), (400, 10)),
+            (7, "Notepad".to_string(), (900, 10)),
+            (9, "Notepad".to_string(), (900, 400)),
+        ]);
+        let picked = |answer: &str| windows.resolve(answer).map(|o| o.element);
+
+        assert_eq!(picked("1"), Some(2));
+        assert_eq!(picked("the second one"), Some(7));
+        assert_eq!(picked("last"), Some(9));
+        assert_eq!(picked("chrome"), Some(2));
+        assert_eq!(picked("The Chrome window"), Some(2));
+        assert_eq!(picked("notepad at 900, 400"), Some(9));
+        assert_eq!(picked("notepad"), None, "two options are named Notepad");
+        assert_eq!(picked("firefox"), None);
+        assert_eq!(picked("4"), None);
+    }
+}
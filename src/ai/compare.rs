@@ -0,0 +1,355 @@
+// Element-level comparison of two analyses, for UI regression checks
+// Elements are paired between a "before" and an "after" screen (two builds
+// of the same dialog, say): by type and label first, wherever they moved;
+// then elements that stayed put, which is how a relabeled or restyled
+// element is still recognized; then, for unlabeled elements, by appearance
+// fingerprint; then by overlap or nearby position. Pairs
+// that differ by more than the tolerances are reported as moved, resized,
+// relabeled or restyled, and what is left over as added or removed.
+
+use serde::{Deserialize, Serialize};
+
+use super::fingerprint::ElementFingerprint;
+use crate::core::config::CompareConfig;
+use crate::core::{ElementBounds, ScreenAnalysis, ScreenElement};
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+use crate::utils::text;
+
+/// Overlap above which an element has stayed put
+const UNMOVED_IOU: f64 = 0.9;
+/// Overlap above which two elements are the same one when nothing else
+/// says so
+const POSITION_IOU: f64 = 0.3;
+/// Center distance, in pixels, within which two small elements that no
+/// longer overlap are still the same one
+const POSITION_DISTANCE: f64 = 24.0;
+
+/// How a paired element differs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ElementChange {
+    /// Top-left corner moved by (`dx`, `dy`)
+    Moved { dx: i32, dy: i32 },
+    Resized { dw: i32, dh: i32 },
+    /// Recognized text differs; `None` where there was none
+    Relabeled { before: Option<String>, after: Option<String> },
+    /// Looks different: bits of the appearance hash that differ (out of 64)
+    Restyled { hash_distance: u32 },
+}
+
+/// An element present on both screens that changed
+#[derive(Debug, Clone)]
+pub struct ChangedElement {
+    pub before: ScreenElement,
+    pub after: ScreenElement,
+    pub changes: Vec<ElementChange>,
+}
+
+/// What differs between two screens
+#[derive(Debug, Clone, Default)]
+pub struct ScreenComparison {
+    /// Paired elements within every tolerance
+    pub unchanged: usize,
+    pub changed: Vec<ChangedElement>,
+    /// Only on the after screen
+    pub added: Vec<ScreenElement>,
+    /// Only on the before screen
+    pub removed: Vec<ScreenElement>,
+}
+
+impl ScreenComparison {
+    pub fn is_identical(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// One line per difference, for logs and the CLI
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.removed.iter().map(|e| format!("removed {}", describe(e))).collect();
+        lines.extend(self.added.iter().map(|e| format!("added {}", describe(e))));
+        for changed in &self.changed {
+            let changes: Vec<String> = changed.changes.iter().map(|change| match change {
+                ElementChange::Moved { dx, dy } => format!("moved {:+},{:+}", dx, dy),
+                ElementChange::Resized { dw, dh } => format!("resized {:+}x{:+}", dw, dh),
+                ElementChange::Relabeled { after, .. } => format!("relabeled \"{}\"", after.as_deref().unwrap_or("")),
+                ElementChange::Restyled { hash_distance } => format!("restyled ({}/64)", hash_distance),
+            }).collect();
+            lines.push(format!("{} {}", describe(&changed.before), changes.join(", ")));
+        }
+        lines
+    }
+}
+
+fn describe(element: &ScreenElement) -> String {
+    let b = &element.bounds;
+    match &element.text {
+        Some(label) => format!("{} \"{}\" at ({}, {})", element.element_type, label, b.x, b.y),
+        None => format!("{} at ({}, {}) {}x{}", element.element_type, b.x, b.y, b.width, b.height),
+    }
+}
+
+/// Compare two analyses by type, label and position
+pub fn compare_screens(before: &ScreenAnalysis, after: &ScreenAnalysis, thresholds: &CompareConfig) -> ScreenComparison {
+    compare(before, after, None, thresholds)
+}
+
+/// Compare two analyses of the frames they were made from; elements are
+/// also paired and checked by appearance
+pub fn compare_frames(
+    before: (&ScreenAnalysis, &Image),
+    after: (&ScreenAnalysis, &Image),
+    thresholds: &CompareConfig,
+) -> ScreenComparison {
+    compare(before.0, after.0, Some((before.1, after.1)), thresholds)
+}
+
+fn compare(
+    before: &ScreenAnalysis,
+    after: &ScreenAnalysis,
+    frames: Option<(&Image, &Image)>,
+    thresholds: &CompareConfig,
+) -> ScreenComparison {
+    let hashes = |elements: &[ScreenElement], frame: Option<&Image>| -> Vec<Option<u64>> {
+        elements.iter().map(|e| frame.and_then(|f| ElementFingerprint::capture(f, e)).map(|f| f.hash)).collect()
+    };
+    let before_hashes = hashes(&before.elements, frames.map(|f| f.0));
+    let after_hashes = hashes(&after.elements, frames.map(|f| f.1));
+    let (width, height) = after.screen_size;
+    let max_shift = thresholds.max_shift * f64::from(width).hypot(f64::from(height)).max(1.0);
+
+    let mut costs: Vec<(f64, usize, usize)> = Vec::new();
+    for (b, old) in before.elements.iter().enumerate() {
+        for (a, new) in after.elements.iter().enumerate() {
+            if old.element_type != new.element_type {
+                continue;
+            }
+            let hash_distance = before_hashes[b].zip(after_hashes[a]).map(|(x, y)| (x ^ y).count_ones());
+            if let Some(cost) = pair_cost(old, new, hash_distance, max_shift, thresholds) {
+                costs.push((cost, b, a));
+            }
+        }
+    }
+    costs.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let mut before_taken = vec![false; before.elements.len()];
+    let mut after_taken = vec![false; after.elements.len()];
+    let mut comparison = ScreenComparison::default();
+    for (_, b, a) in costs {
+        if before_taken[b] || after_taken[a] {
+            continue;
+        }
+        before_taken[b] = true;
+        after_taken[a] = true;
+        let (old, new) = (&before.elements[b], &after.elements[a]);
+        let hash_distance = before_hashes[b].zip(after_hashes[a]).map(|(x, y)| (x ^ y).count_ones());
+        let changes = differences(old, new, hash_distance, thresholds);
+        if changes.is_empty() {
+            comparison.unchanged += 1;
+        } else {
+            comparison.changed.push(ChangedElement { before: old.clone(), after: new.clone(), changes });
+        }
+    }
+    comparison.removed = before.elements.iter().zip(&before_taken).filter(|(_, t)| !**t).map(|(e, _)| e.clone()).collect();
+    comparison.added = after.elements.iter().zip(&after_taken).filter(|(_, t)| !**t).map(|(e, _)| e.clone()).collect();
+    comparison
+}
+
+fn label(element: &ScreenElement) -> Option<String> {
+    element.text.as_deref().map(|t| text::fold_case(t.trim())).filter(|t| !t.is_empty())
+}
+
+/// How unlike two elements of the same type are, or `None` when they can't
+/// be the same element. Same label < same place < same look < overlapping.
+fn pair_cost(old: &ScreenElement, new: &ScreenElement, hash_distance: Option<u32>, max_shift: f64, thresholds: &CompareConfig) -> Option<f64> {
+    let ((ox, oy), (nx, ny)) = (old.bounds.center(), new.bounds.center());
+    let distance = f64::from(ox - nx).hypot(f64::from(oy - ny));
+    let (old_label, new_label) = (label(old), label(new));
+    if distance <= max_shift && old_label.is_some() && old_label == new_label {
+        return Some(distance / max_shift);
+    }
+    let iou = Rectangle::from(&old.bounds).iou(&Rectangle::from(&new.bounds));
+    if iou >= UNMOVED_IOU {
+        return Some(1.0 + (1.0 - iou));
+    }
+    let looks_same = hash_distance.is_some_and(|d| d <= thresholds.max_hash_distance);
+    if distance <= max_shift && looks_same && (old_label.is_none() || new_label.is_none()) {
+        return Some(2.0 + distance / max_shift);
+    }
+    if iou >= POSITION_IOU {
+        return Some(3.0 + (1.0 - iou));
+    }
+    (distance <= POSITION_DISTANCE).then(|| 4.0 + distance / POSITION_DISTANCE)
+}
+
+fn differences(old: &ScreenElement, new: &ScreenElement, hash_distance: Option<u32>, thresholds: &CompareConfig) -> Vec<ElementChange> {
+    let (o, n) = (&old.bounds, &new.bounds);
+    let tolerance = thresholds.tolerance_px;
+    let mut changes = Vec::new();
+    let (dx, dy) = (n.x - o.x, n.y - o.y);
+    if dx.abs() > tolerance || dy.abs() > tolerance {
+        changes.push(ElementChange::Moved { dx, dy });
+    }
+    let (dw, dh) = (n.width - o.width, n.height - o.height);
+    if dw.abs() > tolerance || dh.abs() > tolerance {
+        changes.push(ElementChange::Resized { dw, dh });
+    }
+    if label(old) != label(new) {
+        changes.push(ElementChange::Relabeled { before: old.text.clone(), after: new.text.clone() });
+    }
+    if let Some(hash_distance) = hash_distance.filter(|d| *d > thresholds.max_hash_distance) {
+        changes.push(ElementChange::Restyled { hash_distance });
+    }
+    changes
+}
+
+const REMOVED: [u8; 3] = [220, 40, 40];
+const ADDED: [u8; 3] = [40, 180, 60];
+const CHANGED: [u8; 3] = [240, 150, 0];
+const PREVIOUSLY: [u8; 3] = [150, 150, 150];
+
+/// `after` with the differences outlined: removed elements in red where
+/// they were, added ones in green, changed ones in orange, with the old
+/// place of moved or resized ones in grey
+pub fn annotate(after: &Image, comparison: &ScreenComparison) -> Image {
+    let mut image = to_rgb(after);
+    for changed in &comparison.changed {
+        if changed.before.bounds != changed.after.bounds {
+            outline(&mut image, &changed.before.bounds, PREVIOUSLY, 1);
+        }
+    }
+    for element in &comparison.removed {
+        outline(&mut image, &element.bounds, REMOVED, 2);
+    }
+    for element in &comparison.added {
+        outline(&mut image, &element.bounds, ADDED, 2);
+    }
+    for changed in &comparison.changed {
+        outline(&mut image, &changed.after.bounds, CHANGED, 2);
+    }
+    image
+}
+
+/// Copy of `image` with three channels, for drawing in color
+pub(crate) fn to_rgb(image: &Image) -> Image {
+    if image.channels == 3 {
+        return image.clone();
+    }
+    let mut rgb = Image::new(image.width, image.height, 3);
+    for (index, pixel) in image.data.chunks(image.channels.max(1)).enumerate() {
+        let gray = pixel[0];
+        rgb.data[index * 3..index * 3 + 3].copy_from_slice(pixel.get(..3).unwrap_or(&[gray, gray, gray]));
+    }
+    rgb
+}
+
+/// Draw the border of `bounds`, `thickness` pixels wide, clipped to the image
+pub(crate) fn outline(image: &mut Image, bounds: &ElementBounds, color: [u8; 3], thickness: i32) {
+    let (right, bottom) = (bounds.x + bounds.width - 1, bounds.y + bounds.height - 1);
+    for y in bounds.y..=bottom {
+        for x in bounds.x..=right {
+            let edge = x - bounds.x < thickness || right - x < thickness || y - bounds.y < thickness || bottom - y < thickness;
+            if edge && x >= 0 && y >= 0 && (x as usize) < image.width && (y as usize) < image.height {
+                image.set_pixel(x as usize, y as usize, &color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(element_type: &str, x: i32, y: i32, text: Option<&str>) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(x, y, 80, 30),
+            shape: None,
+            confidence: 0.9,
+            text: text.map(str::to_string),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn analysis(elements: Vec<ScreenElement>) -> ScreenAnalysis {
+        ScreenAnalysis { elements, confidence: 0.9, processing_time_ms: 0, screen_size: (800, 600), context: Default::default() }
+    }
+
+    #[test]
+    fn test_reports_moved_relabeled_added_and_removed() {
+        let before = analysis(vec![
+            element("button", 100, 500, Some("Save")),
+            element("button", 200, 500, Some("Cancel")),
+            element("textfield", 100, 100, None),
+            element("checkbox", 100, 200, Some("Remember me")),
+        ]);
+        let mut wider = element("textfield", 100, 100, None);
+        wider.bounds.width = 160;
+        let after = analysis(vec![
+            element("button", 200, 500, Some("Discard")),
+            // Save swapped places with Cancel's old spot and beyond
+            element("button", 520, 500, Some("Save")),
+            wider,
+            element("link", 100, 300, Some("Help")),
+        ]);
+
+        let comparison = compare_screens(&before, &after, &CompareConfig::default());
+        assert_eq!(comparison.unchanged, 0);
+        let changes: Vec<(&str, &[ElementChange])> = comparison
+            .changed
+            .iter()
+            .map(|c| (c.before.text.as_deref().unwrap_or("-"), c.changes.as_slice()))
+            .collect();
+        assert!(changes.contains(&("Save", &[ElementChange::Moved { dx: 420, dy: 0 }][..])), "{:?}", changes);
+        assert!(changes.contains(&("Cancel", &[ElementChange::Relabeled { before: Some("Cancel".to_string()), after: Some("Discard".to_string()) }][..])));
+        assert!(changes.contains(&("-", &[ElementChange::Resized { dw: 80, dh: 0 }][..])));
+        assert_eq!(comparison.removed[0].text.as_deref(), Some("Remember me"));
+        assert_eq!(comparison.added[0].text.as_deref(), Some("Help"));
+        assert_eq!(comparison.summary().len(), 5);
+
+        // Within tolerance and the same label: nothing to report
+        let mut nudged = before.clone();
+        nudged.elements[0].bounds.x += 2;
+        let same = compare_screens(&before, &nudged, &CompareConfig::default());
+        assert!(same.is_identical() && same.unchanged == 4);
+    }
+
+    /// A light frame with a dark-bordered 80x30 box at each of `boxes`,
+    /// with a glyph on the left or, for `true`, the right
+    fn frame(boxes: &[(i32, i32, bool)]) -> Image {
+        let mut image = Image::from_rgb_data(800, 600, vec![200; 800 * 600 * 3]);
+        for &(x, y, glyph_right) in boxes {
+            for dy in 0..30 {
+                for dx in 0..80 {
+                    let border = dx < 2 || dy < 2 || dx >= 78 || dy >= 28;
+                    let glyph_x = if glyph_right { 55..70 } else { 10..25 };
+                    let value = if border || (glyph_x.contains(&dx) && (8..22).contains(&dy)) { 20 } else { 240 };
+                    image.set_pixel((x + dx) as usize, (y + dy) as usize, &[value, value, value]);
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_frames_pair_unlabeled_elements_by_appearance() {
+        let before = analysis(vec![element("icon", 100, 100, None), element("icon", 400, 100, None)]);
+        let before_frame = frame(&[(100, 100, false), (400, 100, true)]);
+        // The icons moved apart, and the first one's glyph flipped sides
+        let after = analysis(vec![element("icon", 600, 400, None), element("icon", 100, 100, None)]);
+        let after_frame = frame(&[(600, 400, true), (100, 100, true)]);
+
+        let comparison = compare_frames((&before, &before_frame), (&after, &after_frame), &CompareConfig::default());
+        assert!(comparison.added.is_empty() && comparison.removed.is_empty());
+        let moved = comparison.changed.iter().find(|c| c.before.bounds.x == 400).unwrap();
+        assert_eq!(moved.changes, [ElementChange::Moved { dx: 200, dy: 300 }]);
+        let restyled = comparison.changed.iter().find(|c| c.before.bounds.x == 100).unwrap();
+        assert!(matches!(restyled.changes.as_slice(), [ElementChange::Restyled { .. }]), "{:?}", restyled.changes);
+
+        let diff = annotate(&after_frame, &comparison);
+        assert_eq!(diff.get_pixel(600, 400), Some(&CHANGED[..]));
+        assert_eq!(diff.get_pixel(400, 100), Some(&PREVIOUSLY[..]));
+        assert_eq!(diff.get_pixel(300, 300), Some(&[200, 200, 200][..]));
+    }
+}
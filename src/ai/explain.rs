@@ -0,0 +1,174 @@
+// Why nothing matched
+// "Nothing to click" alone leaves the user guessing whether Luna saw the
+// button at all. When a command finds no target, the elements that came
+// closest are scored the way the planner looks at them - by text, by type
+// and by where they are - and the best near miss is pointed out ("the text
+// 'Sumbit' was found - did you mean that?").
+
+use serde::Serialize;
+use std::fmt;
+
+use super::text_match;
+use crate::core::ScreenAnalysis;
+
+/// Candidates reported with a failure
+const MAX_CANDIDATES: usize = 5;
+/// Text closeness from which a near miss is worth pointing out
+const SUGGESTION_CLOSENESS: f64 = 0.6;
+/// Share of each component in a candidate's total
+const TEXT_WEIGHT: f64 = 0.6;
+const TYPE_WEIGHT: f64 = 0.2;
+const SPATIAL_WEIGHT: f64 = 0.2;
+
+/// One element the planner considered, scored by component in 0.0..=1.0
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CandidateScore {
+    /// Index of the element in the analysis
+    pub element: usize,
+    pub element_type: String,
+    pub text: Option<String>,
+    /// `(x, y, width, height)`
+    pub bounds: (i32, i32, i32, i32),
+    /// How close its text or label comes to the command's words
+    pub text_score: f64,
+    /// 1.0 when the command names its type, 0.0 when it names another type
+    pub type_score: f64,
+    /// How visible it is; 0.0 outside the region or scope searched
+    pub spatial_score: f64,
+    pub total: f64,
+}
+
+/// A command that found no target, with what came closest
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NoMatch {
+    pub command: String,
+    /// What was looked for and not found
+    pub reason: String,
+    /// Closest elements, best first
+    pub candidates: Vec<CandidateScore>,
+    /// A near miss worth pointing out
+    pub suggestion: Option<String>,
+}
+
+/// Explain why `command` found nothing in `analysis`, where only the
+/// elements at `in_scope` were searched
+pub fn explain(command: &str, reason: String, analysis: &ScreenAnalysis, in_scope: &[usize]) -> NoMatch {
+    let command_lower = command.to_lowercase();
+    let names_type = |element_type: &str| command_lower.split(|c: char| !c.is_alphanumeric()).any(|word| word == element_type);
+    let named_type = analysis.elements.iter().any(|e| names_type(&e.element_type));
+
+    let mut candidates: Vec<CandidateScore> = analysis
+        .elements
+        .iter()
+        .enumerate()
+        .map(|(index, element)| {
+            let text_score = [element.text.as_deref(), element.label()]
+                .into_iter()
+                .flatten()
+                .map(|name| text_match::closeness(command, name))
+                .fold(0.0, f64::max);
+            let type_score = match (names_type(&element.element_type), named_type) {
+                (true, _) => 1.0,
+                (false, true) => 0.0,
+                (false, false) => 0.5,
+            };
+            let spatial_score = if in_scope.contains(&index) { 1.0 - element.occlusion() } else { 0.0 };
+            let b = &element.bounds;
+            CandidateScore {
+                element: index,
+                element_type: element.element_type.clone(),
+                text: element.text.clone(),
+                bounds: (b.x, b.y, b.width, b.height),
+                text_score,
+                type_score,
+                spatial_score,
+                total: TEXT_WEIGHT * text_score + TYPE_WEIGHT * type_score + SPATIAL_WEIGHT * spatial_score,
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.total.total_cmp(&a.total));
+    candidates.truncate(MAX_CANDIDATES);
+
+    let suggestion = match candidates
+        .iter()
+        .filter(|c| c.text.is_some() && c.text_score >= SUGGESTION_CLOSENESS)
+        .max_by(|a, b| a.text_score.total_cmp(&b.text_score))
+    {
+        Some(near) if near.spatial_score > 0.0 => {
+            Some(format!("the text '{}' was found - did you mean that?", near.text.as_deref().unwrap_or_default()))
+        }
+        Some(near) => Some(format!(
+            "the text '{}' was found outside the part of the screen searched",
+            near.text.as_deref().unwrap_or_default()
+        )),
+        None if analysis.elements.is_empty() => Some("nothing was detected on screen".to_string()),
+        None => None,
+    };
+    NoMatch { command: command.to_string(), reason, candidates, suggestion }
+}
+
+impl fmt::Display for NoMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "; {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CandidateScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at ({}, {})", self.element_type, self.bounds.0, self.bounds.1)?;
+        if let Some(text) = &self.text {
+            write!(f, " '{}'", text)?;
+        }
+        write!(
+            f,
+            ": {:.2} (text {:.2}, type {:.2}, spatial {:.2})",
+            self.total, self.text_score, self.type_score, self.spatial_score
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ElementBounds, ScreenElement};
+
+    fn element(element_type: &str, x: i32, text: &str) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(x, 10, 80, 30),
+            shape: None,
+            confidence: 0.8,
+            text: Some(text.to_string()),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_near_misses_are_scored_and_pointed_out() {
+        let analysis = ScreenAnalysis {
+            elements: vec![element("label", 10, "Subscribe"), element("button", 200, "Cancel"), element("label", 400, "Sumbit")],
+            confidence: 0.8,
+            processing_time_ms: 0,
+            screen_size: (800, 600),
+            context: Default::default(),
+        };
+        let explained = explain("click the submit button", "nothing to click".to_string(), &analysis, &[0, 1, 2]);
+        let best = &explained.candidates[0];
+        assert_eq!((best.element, best.type_score, best.spatial_score), (2, 0.0, 1.0));
+        assert!(best.text_score > 0.9, "{}", best);
+        assert_eq!(explained.candidates.iter().find(|c| c.element == 1).unwrap().type_score, 1.0);
+        assert_eq!(explained.to_string(), "nothing to click; the text 'Sumbit' was found - did you mean that?");
+
+        // Outside the region searched
+        let explained = explain("click submit", "nothing to click".to_string(), &analysis, &[1]);
+        assert_eq!(explained.suggestion.as_deref(), Some("the text 'Sumbit' was found outside the part of the screen searched"));
+        let explained = explain("click xyzzy", "nothing to click".to_string(), &analysis, &[0, 1, 2]);
+        assert_eq!(explained.suggestion, None);
+    }
+}
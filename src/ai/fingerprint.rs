@@ -0,0 +1,323 @@
+// Appearance fingerprints for re-locating elements after layout changes
+// A fingerprint is a 64-bit difference hash of the element's crop plus its text,
+// type and size, so "the same Save button" can be found again after a window
+// moves or resizes without running full screen analysis.
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{ElementBounds, ScreenElement};
+use crate::utils::image_processing::Image;
+use crate::utils::text;
+
+/// Hashes further apart than this (out of 64 bits) are different appearances
+const MAX_HASH_DISTANCE: u32 = 8;
+/// Mean brightness may differ this much before a window is rejected
+const MAX_MEAN_DIFFERENCE: f64 = 40.0;
+/// Windows with less than this fraction of the fingerprint's contrast are rejected
+const MIN_CONTRAST_RATIO: f64 = 0.5;
+/// Hashes with fewer set (or unset) bits than this describe flat regions and
+/// would match any blank area of the screen
+const MIN_HASH_DETAIL: u32 = 6;
+/// Added to the hash distance when matching analysed elements
+const TEXT_MISMATCH_PENALTY: u32 = 16;
+const TYPE_MISMATCH_PENALTY: u32 = 8;
+const MAX_MATCH_SCORE: u32 = 20;
+
+/// Appearance of one element, stable across moves and window resizes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElementFingerprint {
+    pub element_type: String,
+    pub text: Option<String>,
+    pub width: i32,
+    pub height: i32,
+    /// Difference hash of the crop: bit r*8+c is set when cell (r, c) is darker than (r, c+1)
+    pub hash: u64,
+    /// Mean brightness of the crop
+    pub mean_luma: f64,
+    /// Brightest minus darkest hash cell
+    pub contrast: f64,
+    /// Where the element was when fingerprinted, used to break ties
+    pub last_x: i32,
+    pub last_y: i32,
+}
+
+impl ElementFingerprint {
+    /// Fingerprint `element` as it appears in `frame`
+    pub fn capture(frame: &Image, element: &ScreenElement) -> Option<Self> {
+        let bounds = &element.bounds;
+        let luma = IntegralImage::new(frame);
+        let (hash, mean_luma, contrast) = luma.dhash(bounds.x, bounds.y, bounds.width, bounds.height)?;
+        Some(Self {
+            element_type: element.element_type.clone(),
+            text: element.text.clone(),
+            width: bounds.width,
+            height: bounds.height,
+            hash,
+            mean_luma,
+            contrast,
+            last_x: bounds.x,
+            last_y: bounds.y,
+        })
+    }
+
+    /// Whether the hash carries enough detail to search the raw frame with
+    pub fn is_distinctive(&self) -> bool {
+        let bits = self.hash.count_ones();
+        (MIN_HASH_DETAIL..=64 - MIN_HASH_DETAIL).contains(&bits)
+    }
+
+    /// Whether `other` fingerprints the same element: same type and text, and
+    /// a hash close enough that only hover or focus styling differs
+    pub fn is_same_element(&self, other: &ElementFingerprint) -> bool {
+        let text = |f: &ElementFingerprint| f.text.as_ref().map(|t| text::fold_case(t.trim()));
+        self.element_type == other.element_type
+            && text(self) == text(other)
+            && (self.hash ^ other.hash).count_ones() <= MAX_HASH_DISTANCE
+    }
+
+    fn distance_from_last(&self, x: i32, y: i32) -> i64 {
+        let (dx, dy) = ((x - self.last_x) as i64, (y - self.last_y) as i64);
+        dx * dx + dy * dy
+    }
+}
+
+/// Where a fingerprint was found and how closely it matched
+#[derive(Debug, Clone, PartialEq)]
+pub struct FingerprintMatch {
+    pub bounds: ElementBounds,
+    /// Bits that differ from the fingerprint's hash
+    pub hash_distance: u32,
+}
+
+impl FingerprintMatch {
+    /// 1.0 for an identical hash, falling to 0.0 at 64 differing bits
+    pub fn confidence(&self) -> f32 {
+        1.0 - self.hash_distance as f32 / 64.0
+    }
+}
+
+/// Search the raw frame for the fingerprint at its original size.
+///
+/// A coarse scan is refined around the best candidate; among equally good
+/// matches the one nearest the element's last position wins. Returns `None`
+/// for flat fingerprints (see `is_distinctive`) or when nothing is close.
+pub fn locate(frame: &Image, fingerprint: &ElementFingerprint) -> Option<FingerprintMatch> {
+    if !fingerprint.is_distinctive() {
+        return None;
+    }
+    let (width, height) = (fingerprint.width, fingerprint.height);
+    if width < 9 || height < 8 || width > frame.width as i32 || height > frame.height as i32 {
+        return None;
+    }
+
+    let luma = IntegralImage::new(frame);
+    let score = |x: i32, y: i32| -> Option<(u32, i64)> {
+        let (hash, mean, contrast) = luma.dhash(x, y, width, height)?;
+        if (mean - fingerprint.mean_luma).abs() > MAX_MEAN_DIFFERENCE
+            || contrast < fingerprint.contrast * MIN_CONTRAST_RATIO
+        {
+            return None;
+        }
+        Some(((hash ^ fingerprint.hash).count_ones(), fingerprint.distance_from_last(x, y)))
+    };
+    let best_in = |xs: &mut dyn Iterator<Item = i32>, ys: Vec<i32>| {
+        xs.flat_map(|x| ys.iter().map(move |&y| (x, y)))
+            .filter_map(|(x, y)| score(x, y).map(|s| (s, x, y)))
+            .min()
+    };
+
+    // Coarse pass at a stride proportional to the element, then a fine pass around the winner
+    let stride = (width.min(height) / 4).max(1);
+    let max_x = frame.width as i32 - width;
+    let max_y = frame.height as i32 - height;
+    let coarse_ys: Vec<i32> = (0..=max_y).step_by(stride as usize).collect();
+    let ((coarse, _), cx, cy) = best_in(&mut (0..=max_x).step_by(stride as usize), coarse_ys)?;
+    if coarse > MAX_HASH_DISTANCE * 2 {
+        return None;
+    }
+
+    let fine_ys: Vec<i32> = ((cy - stride).max(0)..=(cy + stride).min(max_y)).collect();
+    let ((distance, _), x, y) = best_in(&mut ((cx - stride).max(0)..=(cx + stride).min(max_x)), fine_ys)?;
+    (distance <= MAX_HASH_DISTANCE).then(|| FingerprintMatch {
+        bounds: ElementBounds::new(x, y, width, height),
+        hash_distance: distance,
+    })
+}
+
+/// Pick the analysed element that best matches the fingerprint.
+///
+/// Elements are compared by hash (size-independent, so resized controls
+/// still match), text and type.
+pub fn best_match<'a>(
+    frame: &Image,
+    elements: &'a [ScreenElement],
+    fingerprint: &ElementFingerprint,
+) -> Option<(&'a ScreenElement, FingerprintMatch)> {
+    let luma = IntegralImage::new(frame);
+    let wanted_text = fingerprint.text.as_ref().map(|t| text::fold_case(t.trim()));
+
+    elements
+        .iter()
+        .filter_map(|element| {
+            let b = &element.bounds;
+            let (hash, _, _) = luma.dhash(b.x, b.y, b.width, b.height)?;
+            let distance = (hash ^ fingerprint.hash).count_ones();
+            let mut score = distance;
+            if wanted_text.is_some() && element.text.as_ref().map(|t| text::fold_case(t.trim())) != wanted_text {
+                score += TEXT_MISMATCH_PENALTY;
+            }
+            if element.element_type != fingerprint.element_type {
+                score += TYPE_MISMATCH_PENALTY;
+            }
+            let found = FingerprintMatch { bounds: b.clone(), hash_distance: distance };
+            Some(((score, fingerprint.distance_from_last(b.x, b.y)), element, found))
+        })
+        .filter(|((score, _), _, _)| *score <= MAX_MATCH_SCORE)
+        .min_by_key(|(key, _, _)| *key)
+        .map(|(_, element, found)| (element, found))
+}
+
+/// Summed-area table over brightness, so any cell average is four lookups
+struct IntegralImage {
+    width: usize,
+    height: usize,
+    sums: Vec<u64>,
+}
+
+impl IntegralImage {
+    fn new(frame: &Image) -> Self {
+        let gray = if frame.channels == 1 { None } else { Some(frame.to_grayscale()) };
+        let gray = gray.as_ref().unwrap_or(frame);
+        let (width, height) = (gray.width, gray.height);
+
+        let mut sums = vec![0u64; (width + 1) * (height + 1)];
+        for y in 0..height {
+            let mut row = 0u64;
+            for x in 0..width {
+                row += gray.data[y * width + x] as u64;
+                sums[(y + 1) * (width + 1) + x + 1] = sums[y * (width + 1) + x + 1] + row;
+            }
+        }
+        Self { width, height, sums }
+    }
+
+    /// Sum over [x0, x1) x [y0, y1)
+    fn sum(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> u64 {
+        let stride = self.width + 1;
+        self.sums[y1 * stride + x1] + self.sums[y0 * stride + x0]
+            - self.sums[y0 * stride + x1]
+            - self.sums[y1 * stride + x0]
+    }
+
+    /// Difference hash over a 9x8 grid of cells, plus the mean brightness and
+    /// the spread between the brightest and darkest cell
+    fn dhash(&self, x: i32, y: i32, width: i32, height: i32) -> Option<(u64, f64, f64)> {
+        if x < 0 || y < 0 || width < 9 || height < 8 {
+            return None;
+        }
+        let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+        if x + width > self.width || y + height > self.height {
+            return None;
+        }
+
+        let mut cells = [[0f64; 9]; 8];
+        for (row, cell_row) in cells.iter_mut().enumerate() {
+            let (y0, y1) = (y + row * height / 8, y + (row + 1) * height / 8);
+            for (col, cell) in cell_row.iter_mut().enumerate() {
+                let (x0, x1) = (x + col * width / 9, x + (col + 1) * width / 9);
+                *cell = self.sum(x0, y0, x1, y1) as f64 / ((x1 - x0) * (y1 - y0)) as f64;
+            }
+        }
+
+        let mut hash = 0u64;
+        for (row, cell_row) in cells.iter().enumerate() {
+            for col in 0..8 {
+                if cell_row[col] < cell_row[col + 1] {
+                    hash |= 1 << (row * 8 + col);
+                }
+            }
+        }
+        let mean = self.sum(x, y, x + width, y + height) as f64 / (width * height) as f64;
+        let (min, max) = cells
+            .iter()
+            .flatten()
+            .fold((f64::MAX, f64::MIN), |(min, max), &cell| (min.min(cell), max.max(cell)));
+        Some((hash, mean, max - min))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Mid-grey frame with a "button": dark border, light face and a dark glyph on the left
+    fn frame_with_button(x: usize, y: usize, frame_width: usize) -> Image {
+        let mut image = Image::new(frame_width, 300, 3);
+        image.data.fill(128);
+        for dy in 0..24 {
+            for dx in 0..60 {
+                let border = dx < 2 || dy < 2 || dx >= 58 || dy >= 22;
+                let glyph = (8..20).contains(&dx) && (6..18).contains(&dy);
+                let value = if border || glyph { 30 } else { 230 };
+                image.set_pixel(x + dx, y + dy, &[value, value, value]);
+            }
+        }
+        image
+    }
+
+    fn button(x: i32, y: i32) -> ScreenElement {
+        ScreenElement {
+            element_type: "button".to_string(),
+            bounds: ElementBounds::new(x, y, 60, 24),
+            shape: None,
+            confidence: 0.9,
+            text: Some("Save".to_string()),
+            attributes: HashMap::new(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_locates_moved_element() {
+        let before = frame_with_button(20, 30, 400);
+        let fingerprint = ElementFingerprint::capture(&before, &button(20, 30)).unwrap();
+        assert!(fingerprint.is_distinctive());
+
+        // Window widened and the button moved
+        let after = frame_with_button(233, 171, 500);
+        let found = locate(&after, &fingerprint).unwrap();
+        assert_eq!(found.bounds, ElementBounds::new(233, 171, 60, 24));
+        assert_eq!(found.hash_distance, 0);
+
+        // Gone entirely
+        let mut blank = Image::new(400, 300, 3);
+        blank.data.fill(128);
+        assert!(locate(&blank, &fingerprint).is_none());
+    }
+
+    #[test]
+    fn test_flat_fingerprint_is_not_searched() {
+        let mut flat = Image::new(100, 100, 3);
+        flat.data.fill(200);
+        let fingerprint = ElementFingerprint::capture(&flat, &button(10, 10)).unwrap();
+        assert!(!fingerprint.is_distinctive());
+        assert!(locate(&flat, &fingerprint).is_none());
+    }
+
+    #[test]
+    fn test_best_match_prefers_same_text() {
+        let before = frame_with_button(20, 30, 400);
+        let fingerprint = ElementFingerprint::capture(&before, &button(20, 30)).unwrap();
+
+        let after = frame_with_button(150, 100, 400);
+        let mut cancel = button(150, 100);
+        cancel.text = Some("Cancel".to_string());
+        let elements = vec![cancel, button(150, 100)];
+
+        let (element, found) = best_match(&after, &elements, &fingerprint).unwrap();
+        assert_eq!(element.text.as_deref(), Some("Save"));
+        assert!(found.confidence() > 0.99);
+    }
+}
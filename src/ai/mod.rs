@@ -7,18 +7,69 @@
 use anyhow::Result;
 use image::{DynamicImage, RgbImage};
 use std::collections::HashMap;
-use log::{debug, info};
+use std::path::PathBuf;
+use log::{debug, info, warn};
 
-use crate::core::{ScreenAnalysis, ScreenElement, LunaAction, ElementBounds};
+use crate::core::capabilities::Capabilities;
+use crate::core::config::{EscalationConfig, InputConfig, ModalTargets, PartialVisionConfig, RawOutputsConfig, VisionConfig};
+use crate::core::{ScreenAnalysis, ScreenElement, LunaAction, LunaError, ElementBounds, ExecuteOptions};
+use crate::core::{selection, ShotTarget, COLOR_ATTRIBUTE, SHAPE_ATTRIBUTE, Z_ORDER_ATTRIBUTE};
+use crate::input::keys;
+use crate::utils::geometry::{Point, Polygon, Rectangle};
+use crate::utils::image_processing::Image;
+use crate::utils::locale::{self, Date, Locale};
+use crate::utils::quantity::{self, ScrollUnits};
+use crate::utils::text;
+use crate::vision::{color, hierarchy, modal, occlusion, refine};
+use crate::vision::ui_detection::ControlDetector;
+use clarification::Clarification;
+
+pub mod clarification;
+pub mod compare;
+pub mod explain;
+pub mod fingerprint;
+pub mod raw;
+pub mod remote;
+pub mod suggest;
+pub mod text_match;
+pub mod training;
 
 /// Lightweight AI coordinator for screen analysis and action planning
 pub struct AICoordinator {
-    /// Confidence threshold for element detection
-    confidence_threshold: f32,
+    /// Confidence thresholds for detected elements
+    thresholds: ConfidenceThresholds,
     /// Maximum number of elements to detect
     max_elements: usize,
+    /// Every candidate of the last analysis, before thresholding, so changed
+    /// thresholds can re-filter it without recapturing
+    last_candidates: Option<Vec<ElementDetection>>,
+    /// Model-specific extras the inference server sent with the last screen analysis
+    last_model_output: Option<serde_json::Value>,
     /// Processing statistics
     stats: ProcessingStats,
+    /// Element detector built from the current edge/size settings
+    detector: VisionProcessor,
+    /// Rebuilt detector waiting to be swapped in before the next analysis
+    pending_detector: Option<VisionProcessor>,
+    /// Inference server tried before the local detector
+    remote: Option<remote::RemoteDetector>,
+    /// What can execute here; plans needing unavailable input are rejected
+    capabilities: Option<Capabilities>,
+    /// Asked instead of the remote and local detectors when set
+    detector_override: Option<Box<dyn ElementDetector + Send>>,
+    /// How typed dates and amounts are written
+    locale: Locale,
+    /// Whether an open modal dialog confines target search
+    modal_targets: ModalTargets,
+    /// How far "a page" and other scroll distances go
+    scroll_units: ScrollUnits,
+    /// Zoom levels "set zoom to 150%" steps through
+    zoom_steps: Vec<u32>,
+}
+
+/// Source of element detections for a screen image
+pub trait ElementDetector {
+    fn detect_elements(&mut self, image: &DynamicImage) -> Result<Vec<ElementDetection>>;
 }
 
 /// Lightweight computer vision model for UI element detection
@@ -31,11 +82,61 @@ pub struct VisionProcessor {
     classification_rules: HashMap<String, ClassificationRule>,
 }
 
+/// Outcome of `AICoordinator::reconfigure`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconfigureReport {
+    /// Settings that took effect immediately
+    pub applied: Vec<&'static str>,
+    /// Settings that required rebuilding the detector; active from the next analysis
+    pub reloaded: Vec<&'static str>,
+}
+
+impl ReconfigureReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.reloaded.is_empty()
+    }
+}
+
+/// Minimum confidence an element needs to appear in an analysis
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConfidenceThresholds {
+    pub detection: f32,
+    /// Used instead of `detection` for elements carrying recognized text
+    pub text: Option<f32>,
+}
+
+impl ConfidenceThresholds {
+    pub fn keeps(&self, confidence: f32, has_text: bool) -> bool {
+        let threshold = if has_text { self.text.unwrap_or(self.detection) } else { self.detection };
+        confidence >= threshold
+    }
+}
+
+impl From<&VisionConfig> for ConfidenceThresholds {
+    fn from(config: &VisionConfig) -> Self {
+        Self { detection: config.confidence_threshold, text: config.text_confidence_threshold }
+    }
+}
+
+/// Element whose text matched a command, from `AICoordinator::rank_text_targets`
+#[derive(Debug, Clone)]
+pub struct RankedTarget<'a> {
+    pub element: &'a ScreenElement,
+    /// Text similarity in 0.0..=1.0
+    pub score: f64,
+    /// Fraction of the command's words the label accounts for; breaks score ties
+    pub coverage: f64,
+    /// Which label words matched which command words, and how
+    pub reasoning: String,
+}
+
 /// Element detection result
 #[derive(Debug, Clone)]
 pub struct ElementDetection {
     pub element_type: String,
     pub bounds: ElementBounds,
+    /// Exact outline when not an axis-aligned box
+    pub shape: Option<Polygon>,
     pub confidence: f32,
     pub text: Option<String>,
     pub attributes: HashMap<String, String>,
@@ -58,16 +159,141 @@ pub struct ProcessingStats {
     pub elements_detected: u64,
     pub total_processing_time_ms: u64,
     pub average_processing_time_ms: f64,
+    /// Analyses answered by the remote inference server
+    pub remote_requests: u64,
+    /// Analyses that fell back to local detection because the server was unavailable
+    pub remote_fallbacks: u64,
 }
 
 impl AICoordinator {
     /// Create new AI coordinator
     pub fn new() -> Self {
+        Self::from_config(&VisionConfig::default())
+    }
+
+    /// Create an AI coordinator using the thresholds and detector settings in `config`
+    pub fn from_config(config: &VisionConfig) -> Self {
         Self {
-            confidence_threshold: 0.6,
-            max_elements: 50,
+            thresholds: ConfidenceThresholds::from(config),
+            max_elements: config.max_elements,
+            last_candidates: None,
+            last_model_output: None,
             stats: ProcessingStats::default(),
+            detector: VisionProcessor::with_settings(config.edge_threshold, config.min_element_size),
+            pending_detector: None,
+            remote: None,
+            capabilities: None,
+            detector_override: None,
+            locale: Locale::default(),
+            modal_targets: config.modal_targets,
+            scroll_units: ScrollUnits::default(),
+            zoom_steps: InputConfig::default().zoom_steps,
+        }
+    }
+
+    /// Consult this capability report when planning
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = Some(capabilities);
+    }
+
+    /// Write typed dates and amounts for `locale`
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.locale = locale;
+    }
+
+    /// Convert scroll distances and zoom levels with `config`'s sizes
+    pub fn set_quantities(&mut self, config: &InputConfig) {
+        self.scroll_units = ScrollUnits {
+            default_notches: config.scroll_amount,
+            notch_pixels: config.notch_pixels,
+            page_pixels: config.page_pixels,
+        };
+        self.zoom_steps = config.zoom_steps.clone();
+    }
+
+    /// Reject plans containing actions that cannot execute on this machine
+    pub fn ensure_executable(&self, actions: &[LunaAction]) -> Result<()> {
+        let Some(capabilities) = &self.capabilities else {
+            return Ok(());
+        };
+        match actions.iter().find_map(|action| capabilities.blocker(action)) {
+            Some(reason) => Err(LunaError::Input(format!("cannot plan this command: {}", reason)).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Use a remote inference server for element detection, or `None` for local only
+    pub fn set_remote_backend(&mut self, remote: Option<remote::RemoteDetector>) {
+        if let Some(remote) = &remote {
+            info!("Using remote inference at {}", remote.endpoint());
+        }
+        self.remote = remote;
+    }
+
+    /// Detect with `detector` instead of the remote and local detectors, e.g.
+    /// a simulated desktop that knows its own layout; `None` restores them
+    pub fn set_detector(&mut self, detector: Option<Box<dyn ElementDetector + Send>>) {
+        self.detector_override = detector;
+    }
+
+    /// Endpoint of the inference server and whether it is being used (not
+    /// backing off after a failure); `None` when detection is local only
+    pub fn remote_status(&self) -> Option<(&str, bool)> {
+        self.remote.as_ref().map(|remote| (remote.endpoint(), remote.is_available()))
+    }
+
+    /// Apply changed settings without restarting.
+    ///
+    /// Thresholds and limits apply immediately. Edge and size settings change
+    /// the detector itself, so a new one is built and swapped in before the
+    /// next analysis; an analysis already running is unaffected.
+    pub fn reconfigure(&mut self, changes: &PartialVisionConfig) -> ReconfigureReport {
+        let mut report = ReconfigureReport::default();
+
+        if let Some(value) = changes.confidence_threshold.filter(|v| *v != self.thresholds.detection) {
+            self.thresholds.detection = value;
+            report.applied.push("confidence_threshold");
+        }
+        if let Some(value) = changes.text_confidence_threshold.filter(|v| Some(*v) != self.thresholds.text) {
+            self.thresholds.text = Some(value);
+            report.applied.push("text_confidence_threshold");
+        }
+        if let Some(value) = changes.max_elements.filter(|v| *v != self.max_elements) {
+            self.max_elements = value;
+            report.applied.push("max_elements");
+        }
+
+        let current = self.pending_detector.as_ref().unwrap_or(&self.detector);
+        let mut edge_threshold = current.edge_threshold;
+        let mut min_element_size = current.min_element_size;
+        if let Some(value) = changes.edge_threshold.filter(|v| *v != edge_threshold) {
+            edge_threshold = value;
+            report.reloaded.push("edge_threshold");
+        }
+        if let Some(value) = changes.min_element_size.filter(|v| *v != min_element_size) {
+            min_element_size = value;
+            report.reloaded.push("min_element_size");
+        }
+        if !report.reloaded.is_empty() {
+            self.pending_detector = Some(VisionProcessor::with_settings(edge_threshold, min_element_size));
+        }
+
+        if !report.is_empty() {
+            info!("AI reconfigured: applied {:?}, detector reload for {:?}", report.applied, report.reloaded);
         }
+        report
+    }
+
+    /// Build a fresh detector with the current settings, swapped in before
+    /// the next analysis; for when the watchdog found the old one hung
+    pub fn reload_detector(&mut self) {
+        let current = self.pending_detector.as_ref().unwrap_or(&self.detector);
+        self.pending_detector = Some(VisionProcessor::with_settings(current.edge_threshold, current.min_element_size));
+    }
+
+    /// Whether a rebuilt detector is waiting for the next analysis
+    pub fn has_pending_reload(&self) -> bool {
+        self.pending_detector.is_some()
     }
 
     /// Analyze screen image and detect UI elements
@@ -76,23 +302,109 @@ impl AICoordinator {
         
         debug!("Starting screen analysis {}x{}", image.width(), image.height());
         
-        // Use lightweight computer vision processor
-        let mut vision = VisionProcessor::new();
-        let elements = vision.detect_elements(image)?;
+        if let Some(detector) = self.pending_detector.take() {
+            debug!("Switching to reconfigured element detector");
+            self.detector = detector;
+        }
+
+        if let Some(detector) = self.detector_override.as_mut() {
+            let elements = detector.detect_elements(image)?;
+            self.last_model_output = None;
+            return self.finish_analysis(image, elements, start_time);
+        }
+
+        // Prefer the inference server; fall back to the lightweight local processor
+        let remote_elements = match self.remote.as_mut() {
+            Some(remote) if remote.is_available() => match remote.detect_elements(image) {
+                Ok(elements) => Some(elements),
+                Err(e) => {
+                    warn!("Remote inference failed, using local detection: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        self.last_model_output = match (&remote_elements, &self.remote) {
+            (Some(_), Some(remote)) => remote.last_raw().cloned(),
+            _ => None,
+        };
+        let elements = match remote_elements {
+            Some(elements) => {
+                self.stats.remote_requests += 1;
+                elements
+            }
+            None => {
+                if self.remote.is_some() {
+                    self.stats.remote_fallbacks += 1;
+                }
+                self.detector.detect_elements(image)?
+            }
+        };
+        self.finish_analysis(image, elements, start_time)
+    }
+
+    /// Analyze `image` with `detector` in place of the configured remote and
+    /// local detectors, e.g. recorded model output when replaying a frame
+    pub fn analyze_with(&mut self, image: &DynamicImage, detector: &mut dyn ElementDetector) -> Result<ScreenAnalysis> {
+        let start_time = std::time::Instant::now();
+        let elements = detector.detect_elements(image)?;
+        self.finish_analysis(image, elements, start_time)
+    }
+
+    /// Analyze again with more effort after planning found no target: the
+    /// local detector with lower edge and size limits, and confidence
+    /// thresholds scaled down, as set in `effort`. The remote detector is not
+    /// asked again; it already gave its best answer for this frame.
+    pub fn analyze_thorough(&mut self, image: &DynamicImage, effort: &EscalationConfig) -> Result<ScreenAnalysis> {
+        let current = self.pending_detector.as_ref().unwrap_or(&self.detector);
+        let min_element_size = (current.min_element_size as f32 * effort.sensitivity_scale).round().max(1.0) as u32;
+        let mut detector = VisionProcessor::with_settings(current.edge_threshold * effort.sensitivity_scale, min_element_size);
+
+        let saved = self.thresholds;
+        self.thresholds = ConfidenceThresholds {
+            detection: saved.detection * effort.threshold_scale,
+            text: saved.text.map(|t| t * effort.threshold_scale),
+        };
+        let analysis = self.analyze_with(image, &mut detector);
+        self.thresholds = saved;
+        analysis
+    }
+
+    /// Merge control detections, threshold, and build the analysis
+    fn finish_analysis(
+        &mut self,
+        image: &DynamicImage,
+        mut elements: Vec<ElementDetection>,
+        start_time: std::time::Instant,
+    ) -> Result<ScreenAnalysis> {
+        let rgb = image.to_rgb8();
+        let frame = Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw());
+
+        // Boxes are often a few pixels off; pull each side onto the element's edge
+        for element in elements.iter_mut().filter(|e| e.shape.is_none()) {
+            let snapped = refine::snap_to_edges(&frame, &Rectangle::from(&element.bounds), refine::SNAP_RADIUS);
+            element.bounds = ElementBounds::from(&snapped);
+        }
+
+        // Checkboxes, toggles, sliders etc. get dedicated detectors that also read their state
+        let controls = detect_controls(&frame)?;
+        elements.retain(|e| !controls.iter().any(|c| overlap_ratio(&c.bounds, &e.bounds) > 0.5));
+        elements.extend(controls);
+
+        // Solid colored shapes no detector reported ("the red circle"), and
+        // every element's main color as a cue for commands that name one
+        let shapes = color_shapes(&frame, &elements);
+        elements.extend(shapes);
+        for element in &mut elements {
+            if let Some(name) = color::dominant_color(&frame, &Rectangle::from(&element.bounds)) {
+                element.attributes.entry(COLOR_ATTRIBUTE.to_string()).or_insert_with(|| name.to_string());
+            }
+        }
+        elements.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal));
         
         // Filter by confidence threshold
-        let filtered_elements: Vec<ScreenElement> = elements
-            .into_iter()
-            .filter(|e| e.confidence >= self.confidence_threshold)
-            .take(self.max_elements)
-            .map(|e| ScreenElement {
-                element_type: e.element_type,
-                bounds: e.bounds,
-                confidence: e.confidence,
-                text: e.text,
-                attributes: e.attributes,
-            })
-            .collect();
+        let filtered_elements = self.filter_candidates(&elements);
+        self.last_candidates = Some(elements);
 
         let processing_time = start_time.elapsed();
         let processing_time_ms = processing_time.as_millis() as u64;
@@ -107,126 +419,1486 @@ impl AICoordinator {
         info!("Screen analysis complete: {} elements detected in {}ms", 
               filtered_elements.len(), processing_time_ms);
 
-        let confidence = self.calculate_overall_confidence(&filtered_elements);
+        Ok(self.assemble_analysis(filtered_elements, image, processing_time_ms))
+    }
+
+    pub fn thresholds(&self) -> ConfidenceThresholds {
+        self.thresholds
+    }
+
+    /// Change the confidence thresholds; takes effect from the next analysis
+    /// or `refilter`
+    pub fn set_thresholds(&mut self, thresholds: ConfidenceThresholds) {
+        self.thresholds = thresholds;
+    }
+
+    /// The last analysis again under the current thresholds, without
+    /// re-running detection. `image` must be the frame that analysis saw.
+    /// `None` before the first analysis.
+    pub fn refilter(&self, image: &DynamicImage) -> Option<ScreenAnalysis> {
+        let candidates = self.last_candidates.as_ref()?;
+        let started = std::time::Instant::now();
+        let elements = self.filter_candidates(candidates);
+        Some(self.assemble_analysis(elements, image, started.elapsed().as_millis() as u64))
+    }
+
+    /// Every candidate of the last analysis and the inference server's extras,
+    /// capped by `limits`. `None` before the first analysis.
+    pub fn raw_outputs(&self, limits: &RawOutputsConfig) -> Option<raw::RawOutputs> {
+        let candidates = self.last_candidates.as_ref()?;
+        Some(raw::RawOutputs::collect(candidates, &self.thresholds, self.last_model_output.as_ref(), limits))
+    }
+
+    /// How many elements of the last analysis each detection threshold in
+    /// `levels` would keep (text threshold unchanged)
+    pub fn threshold_sweep(&self, levels: &[f32]) -> Vec<(f32, usize)> {
+        let candidates = self.last_candidates.as_deref().unwrap_or_default();
+        levels
+            .iter()
+            .map(|&level| {
+                let thresholds = ConfidenceThresholds { detection: level, ..self.thresholds };
+                let kept = candidates.iter().filter(|e| thresholds.keeps(e.confidence, e.text.is_some())).count();
+                (level, kept.min(self.max_elements))
+            })
+            .collect()
+    }
+
+    /// Candidates that pass the thresholds, capped at `max_elements`
+    fn filter_candidates(&self, candidates: &[ElementDetection]) -> Vec<ScreenElement> {
+        candidates
+            .iter()
+            .filter(|e| self.thresholds.keeps(e.confidence, e.text.is_some()))
+            .take(self.max_elements)
+            .map(|e| ScreenElement {
+                element_type: e.element_type.clone(),
+                bounds: e.bounds.clone(),
+                shape: e.shape.clone(),
+                confidence: e.confidence,
+                text: e.text.clone(),
+                attributes: e.attributes.clone(),
+                parent: None,
+                children: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Add separator panels and link the containment hierarchy
+    fn assemble_analysis(&self, mut elements: Vec<ScreenElement>, image: &DynamicImage, processing_time_ms: u64) -> ScreenAnalysis {
+        let confidence = self.calculate_overall_confidence(&elements);
+        let panels = separator_panels(image, &elements);
+        elements.extend(panels);
+
+        let mut analysis = ScreenAnalysis {
+            elements,
+            confidence,
+            processing_time_ms,
+            screen_size: (image.width(), image.height()),
+            context: Default::default(),
+        };
+        analysis.link_hierarchy();
+        analysis.link_labels();
+        analysis.mark_occlusion();
+        analysis.mark_visibility();
+        let modal = find_modal(image, &analysis);
+        analysis.mark_modal(modal);
+        analysis
+    }
+
+    /// Plan actions based on user command and screen analysis
+    pub fn plan_actions(&self, command: &str, analysis: &ScreenAnalysis) -> Result<Vec<LunaAction>> {
+        self.plan_actions_with_options(command, analysis, &ExecuteOptions::default())
+    }
+
+    /// Plan actions, honoring per-command options such as a region constraint
+    pub fn plan_actions_with_options(
+        &self,
+        command: &str,
+        analysis: &ScreenAnalysis,
+        options: &ExecuteOptions,
+    ) -> Result<Vec<LunaAction>> {
+        debug!("Planning actions for command: '{}'", command);
+
+        if let Some(wait) = parse_wait(command) {
+            let actions = vec![wait];
+            self.ensure_executable(&actions)?;
+            return Ok(actions);
+        }
+        // "After clicking Submit, wait for the spinner to disappear"
+        if let Some((first, wait)) = split_wait_clause(command) {
+            let mut actions = self.plan_actions_with_options(&first, analysis, options)?;
+            actions.push(wait);
+            self.ensure_executable(&actions)?;
+            return Ok(actions);
+        }
+
+        // "While holding shift, click report3" presses the keys around the plan
+        if let Some((keys, held)) = split_hold(command) {
+            let inner = match held {
+                Held::While(inner) => self.plan_actions_with_options(&inner, analysis, options)?,
+                Held::For(milliseconds) => vec![LunaAction::Wait { milliseconds }],
+            };
+            let actions = hold_around(&keys, inner);
+            self.ensure_executable(&actions)?;
+            return Ok(actions);
+        }
+
+        // "In the settings dialog, click Apply" only searches that container's subtree;
+        // a container that isn't detected ("in the toolbar") doesn't narrow anything
+        let (command, scope) = match split_scope(command) {
+            Some((name, rest)) => {
+                let Some(container) = find_container(&name, analysis) else {
+                    debug!("No '{}' window or panel on screen; planning '{}' without a scope", name, rest);
+                    return self.plan_actions_with_options(&rest, analysis, options);
+                };
+                debug!("Scoped to {} at ({}, {})", analysis.elements[container].element_type,
+                       analysis.elements[container].bounds.x, analysis.elements[container].bounds.y);
+                (rest, Some(analysis.subtree(container)[1..].to_vec()))
+            }
+            None => {
+                // An open modal dialog blocks everything behind it, so look there first
+                if let Some(modal) = analysis.active_modal().filter(|_| self.modal_targets != ModalTargets::Ignore) {
+                    let inside = analysis.subtree(modal)[1..].to_vec();
+                    let behind = self.names_only_background(command, analysis, &inside);
+                    if self.modal_targets == ModalTargets::Require {
+                        if behind {
+                            let reason = format!("what '{}' names is behind an open dialog", command);
+                            return Err(LunaError::NoMatchingElement(Box::new(explain::explain(command, reason, analysis, &inside))).into());
+                        }
+                        return self.plan_in_scope(command, analysis, options, Some(inside));
+                    }
+                    if behind {
+                        debug!("'{}' names nothing in the modal dialog; searching the whole screen", command);
+                    } else {
+                        match self.plan_in_scope(command, analysis, options, Some(inside)) {
+                            Err(e) if e.downcast_ref::<LunaError>().is_some_and(|e| e.kind() == "not_found") => {
+                                debug!("Nothing in the modal dialog fits '{}'; searching the whole screen", command);
+                            }
+                            planned => return planned,
+                        }
+                    }
+                }
+                (command.to_string(), None)
+            }
+        };
+        self.plan_in_scope(&command, analysis, options, scope)
+    }
+
+    /// Whether `command` names elements on screen and all of them are
+    /// outside `inside` (a modal dialog's elements)
+    fn names_only_background(&self, command: &str, analysis: &ScreenAnalysis, inside: &[usize]) -> bool {
+        let ranked = self.rank_text_targets(&command.to_lowercase(), &analysis.elements);
+        let index = |target: &RankedTarget| analysis.elements.iter().position(|e| std::ptr::eq(e, target.element));
+        !ranked.is_empty() && ranked.iter().all(|target| index(target).is_none_or(|i| !inside.contains(&i)))
+    }
+
+    /// Plan a command among the elements of `scope` (all of them when `None`)
+    fn plan_in_scope(&self, command: &str, analysis: &ScreenAnalysis, options: &ExecuteOptions, scope: Option<Vec<usize>>) -> Result<Vec<LunaAction>> {
+        let command_lower = command.to_lowercase();
+        let mut actions = Vec::new();
+
+        // Only visible elements in scope and centered inside the constraint region are candidates
+        let candidate_indices: Vec<usize> = analysis.elements
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| scope.as_ref().is_none_or(|scope| scope.contains(index)))
+            .filter(|(_, e)| e.occlusion() < 1.0)
+            .filter(|(_, e)| {
+                let (cx, cy) = e.click_point();
+                options.region_constraint.as_ref().is_none_or(|region| region.contains_point(cx, cy))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let candidates: Vec<ScreenElement> = candidate_indices.iter().map(|&i| analysis.elements[i].clone()).collect();
+
+        // Simple command parsing and action planning
+        if let Some(selection) = parse_selection(&command_lower) {
+            let actions = self.plan_selection(selection, analysis, &candidate_indices)?;
+            self.ensure_executable(&actions)?;
+            return Ok(actions);
+        } else if let Some(control_actions) = self.plan_control_actions(&command_lower, &candidates)? {
+            actions = control_actions;
+        } else if let Some(named) = command_lower.strip_prefix("close ") {
+            actions = self.plan_close_actions(named, analysis, &candidate_indices)?;
+        } else if let Some((ShotSubject::Window, target)) = parse_screenshot(command) {
+            actions = plan_window_screenshot(analysis, &candidate_indices, target)?;
+        } else if let Some(actions) = self.plan_color_click(&command_lower, analysis, &candidate_indices)? {
+            return Ok(actions);
+        } else if let Some(inner) = strip_at_caret(command) {
+            // Not `NotFound`: analyzing harder finds elements, not the caret
+            let caret = analysis.context.caret
+                .ok_or_else(|| LunaError::Vision("no text cursor on screen to insert at".to_string()))?;
+            info!("Inserting at the text cursor ({}, {})", caret.x, caret.y);
+            if let Some(path) = parse_paste_image(inner) {
+                actions.push(LunaAction::PasteImage { path });
+            } else {
+                let lower = text::lowercase_aligned(inner);
+                let verb = ["type ", "enter "].iter().find(|verb| lower.starts_with(**verb))
+                    .ok_or_else(|| LunaError::InvalidArgument(format!("say what to type or paste at the cursor (not '{}')", command)))?;
+                let text = inner[verb.len()..].trim().trim_matches('"');
+                let text = localized_text(text, &self.locale).unwrap_or_else(|| text.to_string());
+                actions.push(LunaAction::Type { text });
+            }
+        } else if let Some((path, field)) = split_paste_target(command) {
+            let target = self.rank_text_targets(&field, &candidates).into_iter().find(|t| FIELD_TYPES.contains(&t.element.element_type.as_str()))
+                .ok_or_else(|| {
+                    let reason = format!("no '{}' field to paste into", field);
+                    LunaError::NoMatchingElement(Box::new(explain::explain(&field, reason, analysis, &candidate_indices)))
+                })?;
+            let position = candidates.iter().position(|c| std::ptr::eq(c, target.element)).unwrap_or_default();
+            actions.extend(focus_field(analysis, candidate_indices[position])?);
+            actions.push(LunaAction::PasteImage { path });
+        } else if command_lower.contains("click") {
+            let ranked = self.rank_text_targets(&command_lower, &candidates);
+            let standing = |t: &RankedTarget| (t.score, t.coverage, t.element.visibility(), t.element.occlusion());
+            let tied: Vec<&RankedTarget> = ranked.iter().take_while(|t| standing(t) == standing(&ranked[0])).collect();
+            if tied.len() > 1 {
+                let noun = match tied[0].element.element_type.as_str() {
+                    kind if tied.iter().all(|t| t.element.element_type == kind) => kind,
+                    _ => "one",
+                };
+                let options = tied
+                    .iter()
+                    .map(|t| {
+                        let position = candidates.iter().position(|c| std::ptr::eq(c, t.element)).unwrap_or_default();
+                        let text = t.element.text.clone().unwrap_or_default();
+                        (candidate_indices[position], text, t.element.click_point())
+                    })
+                    .collect();
+                return Err(Clarification::which("Click", noun, options).into());
+            }
+            let element = self.find_clickable_element(&command_lower, &candidates).ok_or_else(|| {
+                let reason = format!("nothing to click for '{}'", command);
+                LunaError::NoMatchingElement(Box::new(explain::explain(command, reason, analysis, &candidate_indices)))
+            })?;
+            let position = candidates.iter().position(|c| std::ptr::eq(c, element)).unwrap_or_default();
+            let (reveal, (x, y)) = reveal_before_click(analysis, candidate_indices[position], &self.scroll_units);
+            actions.extend(reveal);
+            actions.extend(raise_before_click(analysis, candidate_indices[position], (x, y))?);
+            actions.push(LunaAction::Click { x, y });
+        } else if let Some((text, field, named)) = split_type_target(command) {
+            let text = localized_text(&text, &self.locale).unwrap_or(text);
+            match self.rank_text_targets(&field, &candidates).into_iter().find(|t| FIELD_TYPES.contains(&t.element.element_type.as_str())) {
+                Some(target) => {
+                    info!("Typing into {} at ({}, {}): {}", target.element.element_type, target.element.bounds.x, target.element.bounds.y, target.reasoning);
+                    let position = candidates.iter().position(|c| std::ptr::eq(c, target.element)).unwrap_or_default();
+                    actions.extend(focus_field(analysis, candidate_indices[position])?);
+                    actions.push(LunaAction::Type { text });
+                }
+                None if named => {
+                    let reason = format!("no '{}' field to type into", field);
+                    return Err(LunaError::NoMatchingElement(Box::new(explain::explain(&field, reason, analysis, &candidate_indices))).into());
+                }
+                None => actions.extend(self.extract_text_from_command(command).map(|text| LunaAction::Type { text })),
+            }
+        } else if command_lower.contains("type") || command_lower.contains("enter") {
+            if let Some(text) = self.extract_text_from_command(command) {
+                let text = localized_text(&text, &self.locale).unwrap_or(text);
+                actions.push(LunaAction::Type { text });
+            }
+        } else if command_lower.contains("scroll") {
+            let scroll = command_lower
+                .split_once("scroll")
+                .and_then(|(_, rest)| quantity::parse_scroll(rest))
+                .unwrap_or(quantity::Scroll { up: command_lower.contains("up"), amount: quantity::ScrollAmount::Default });
+            actions.push(LunaAction::Scroll {
+                direction: if scroll.up { "up" } else { "down" }.to_string(),
+                amount: self.scroll_units.notches(scroll.amount),
+            });
+        }
+
+        debug!("Planned {} actions", actions.len());
+        self.ensure_executable(&actions)?;
+        Ok(actions)
+    }
+
+    /// Plan "click the red circle": elements whose color (and shape or type,
+    /// when named) fit the command. `None` when the command names no color or
+    /// nothing on screen has it, so it can still match by text ("click Red Hat").
+    fn plan_color_click(&self, command: &str, analysis: &ScreenAnalysis, candidates: &[usize]) -> Result<Option<Vec<LunaAction>>> {
+        if !command.contains("click") {
+            return Ok(None);
+        }
+        let words: Vec<&str> = command.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+        let Some(wanted) = words.iter().find_map(|w| color::COLOR_NAMES.iter().find(|name| **name == *w || (*w == "grey" && **name == "gray"))) else {
+            return Ok(None);
+        };
+        let shape = words.iter().find_map(|w| COLOR_SHAPE_WORDS.iter().find(|(word, _)| word == w).map(|(_, shape)| *shape));
+        let kind = words.iter().copied().find(|w| COLOR_TARGET_TYPES.contains(w));
+
+        let matching: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let element = &analysis.elements[i];
+                element.attributes.get(COLOR_ATTRIBUTE).is_some_and(|c| c == wanted)
+                    && shape.is_none_or(|s| element.attributes.get(SHAPE_ATTRIBUTE).is_some_and(|a| a == s))
+                    && kind.is_none_or(|k| element.element_type == k)
+            })
+            .collect();
+        let noun = format!("{} {}", wanted, shape.or(kind).unwrap_or("one"));
+        match matching.as_slice() {
+            [] => Ok(None),
+            [index] => {
+                let (x, y) = analysis.elements[*index].click_point();
+                info!("Target {} at ({}, {}) by color", noun, x, y);
+                let mut actions = raise_before_click(analysis, *index, (x, y))?;
+                actions.push(LunaAction::Click { x, y });
+                self.ensure_executable(&actions)?;
+                Ok(Some(actions))
+            }
+            several => {
+                let options = several
+                    .iter()
+                    .map(|&i| {
+                        let element = &analysis.elements[i];
+                        (i, element.text.clone().unwrap_or_else(|| noun.clone()), element.click_point())
+                    })
+                    .collect();
+                Err(Clarification::which("Click", &noun, options).into())
+            }
+        }
+    }
+
+    /// Plan "select report1 through report5" as a click on whichever end comes
+    /// first on screen and a shift-click on the other, and "select a, b and
+    /// c" or "ctrl-click a, b and c" as clicks that add each item in turn
+    fn plan_selection(&self, selection: Selection, analysis: &ScreenAnalysis, candidates: &[usize]) -> Result<Vec<LunaAction>> {
+        let elements: Vec<ScreenElement> = candidates.iter().map(|&i| analysis.elements[i].clone()).collect();
+        let resolve = |name: &str| -> Result<&ScreenElement> {
+            if ["these", "those", "them"].iter().any(|w| name.split_whitespace().next() == Some(w)) {
+                return Err(LunaError::InvalidArgument(
+                    format!("say which items to select, e.g. \"ctrl-click report1, report3 and report5\" (not '{}')", name)).into());
+            }
+            let ranked = self.rank_text_targets(name, &elements);
+            match ranked.as_slice() {
+                [] => Err(LunaError::NotFound(format!("nothing to select for '{}'", name)).into()),
+                [best, next, ..] if (best.score, best.coverage) == (next.score, next.coverage) => Err(LunaError::InvalidArgument(format!(
+                    "'{}' fits both '{}' and '{}'; name the item exactly", name,
+                    best.element.text.as_deref().unwrap_or_default(), next.element.text.as_deref().unwrap_or_default())).into()),
+                [best, ..] => Ok(best.element),
+            }
+        };
+        let toggle = || vec![selection::TOGGLE_MODIFIER.to_string()];
+
+        let actions = match selection {
+            Selection::Range(first, last) => {
+                let (first, last) = (resolve(&first)?, resolve(&last)?);
+                // Shift selects whatever lies between in layout order, so start at the earlier end
+                let order = hierarchy::reading_order(&[Rectangle::from(&first.bounds), Rectangle::from(&last.bounds)]);
+                let (anchor, end) = if order[0] == 0 { (first, last) } else { (last, first) };
+                info!("Selecting from '{}' to '{}'", anchor.text.as_deref().unwrap_or_default(), end.text.as_deref().unwrap_or_default());
+                let ((ax, ay), (ex, ey)) = (anchor.click_point(), end.click_point());
+                vec![
+                    LunaAction::Click { x: ax, y: ay },
+                    LunaAction::ModifierClick { x: ex, y: ey, modifiers: vec![selection::RANGE_MODIFIER.to_string()] },
+                ]
+            }
+            Selection::Items { names, add } => names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let (x, y) = resolve(name)?.click_point();
+                    Ok(if i == 0 && !add { LunaAction::Click { x, y } } else { LunaAction::ModifierClick { x, y, modifiers: toggle() } })
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        Ok(actions)
+    }
+
+    /// Plan `command` against the element the user picked in answer to a
+    /// `Clarification` asked about `analysis`
+    pub fn plan_choice(&self, command: &str, analysis: &ScreenAnalysis, element: usize) -> Result<Vec<LunaAction>> {
+        let chosen = analysis.elements.get(element)
+            .ok_or_else(|| LunaError::InvalidArgument(format!("no element {} in the analysis", element)))?;
+        if let Some((first, wait)) = split_wait_clause(command) {
+            let mut actions = self.plan_choice(&first, analysis, element)?;
+            actions.push(wait);
+            self.ensure_executable(&actions)?;
+            return Ok(actions);
+        }
+        let command = split_scope(command).map_or_else(|| command.to_string(), |(_, rest)| rest);
+        let actions = if command.trim().to_lowercase().starts_with("close ") {
+            vec![close_button(analysis, element)?]
+        } else if let Some((ShotSubject::Window, target)) = parse_screenshot(&command) {
+            vec![LunaAction::Screenshot { region: Some(chosen.bounds.clone()), target }]
+        } else {
+            let (x, y) = chosen.click_point();
+            vec![LunaAction::Click { x, y }]
+        };
+        self.ensure_executable(&actions)?;
+        Ok(actions)
+    }
+
+    /// "close it", "close the notepad window": click the close button of the
+    /// window named, or of the only one on screen. Asks which when several fit.
+    fn plan_close_actions(&self, named: &str, analysis: &ScreenAnalysis, candidates: &[usize]) -> Result<Vec<LunaAction>> {
+        let windows: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&i| WINDOW_TYPES.contains(&analysis.elements[i].element_type.as_str()))
+            .collect();
+        let name: Vec<&str> = named.split_whitespace().filter(|w| !CLOSE_FILLER.contains(w)).collect();
+
+        let fitting: Vec<usize> = if name.is_empty() {
+            windows
+        } else {
+            let name = name.join(" ");
+            let scored: Vec<(usize, f64)> = windows
+                .iter()
+                // Every named word must be in the title: "close chrome" fits "Google Chrome"
+                .filter_map(|&i| Some((i, text_match::match_label(container_title(analysis, i)?, &name)?.score)))
+                .collect();
+            let best = scored.iter().map(|(_, score)| *score).fold(0.0, f64::max);
+            scored.into_iter().filter(|(_, score)| *score == best).map(|(i, _)| i).collect()
+        };
+
+        match fitting.as_slice() {
+            [] => Err(LunaError::NotFound(format!("no window matching '{}' to close", named.trim())).into()),
+            [window] => Ok(vec![close_button(analysis, *window)?]),
+            _ => {
+                let options = fitting
+                    .iter()
+                    .map(|&i| {
+                        let element = &analysis.elements[i];
+                        let title = container_title(analysis, i).map_or_else(|| format!("untitled {}", element.element_type), str::to_string);
+                        (i, title, (element.bounds.x, element.bounds.y))
+                    })
+                    .collect();
+                Err(Clarification::which("Close", "window", options).into())
+            }
+        }
+    }
+
+    /// Plan commands that need no screen state: "click at 800,420", "press ctrl+s",
+    /// "type hello", "wait for the spinner to disappear". Returns `None` when the command must go through analysis.
+    pub fn plan_direct_actions(&self, command: &str) -> Option<Vec<LunaAction>> {
+        let trimmed = command.trim();
+        let lower = text::lowercase_aligned(trimmed);
+
+        // "press ctrl+a, then type hello": keystrokes all the way through
+        if let Some((first, rest)) = split_keyboard_steps(trimmed) {
+            if let (Some(mut actions), Some(more)) = (self.plan_direct_actions(first), self.plan_direct_actions(rest)) {
+                actions.extend(more);
+                return Some(actions);
+            }
+        }
+
+        if let Some(wait) = parse_wait(trimmed) {
+            return Some(vec![wait]);
+        }
+        if let Some((first, wait)) = split_wait_clause(trimmed) {
+            let mut actions = self.plan_direct_actions(&first)?;
+            actions.push(wait);
+            return Some(actions);
+        }
+        if let Some((keys, held)) = split_hold(trimmed) {
+            let inner = match held {
+                Held::While(inner) => self.plan_direct_actions(&inner)?,
+                Held::For(milliseconds) => vec![LunaAction::Wait { milliseconds }],
+            };
+            return Some(hold_around(&keys, inner));
+        }
+
+        if let Some(rest) = lower.strip_prefix("click") {
+            let rest = rest.trim_start();
+            let rest = rest.strip_prefix("at").unwrap_or(rest);
+            let (x, y) = parse_coordinates(rest)?;
+            return Some(vec![LunaAction::Click { x, y }]);
+        }
+
+        for verb in ["press", "hit", "key"] {
+            if let Some(rest) = lower.strip_prefix(verb) {
+                if !rest.starts_with(char::is_whitespace) {
+                    continue;
+                }
+                let chord = keys::parse_chord(rest.trim()).ok()?;
+                let mut keys: Vec<String> = chord.modifiers.iter().map(|m| m.to_string()).collect();
+                keys.push(chord.key.to_string());
+                return Some(vec![LunaAction::KeyCombo { keys }]);
+            }
+        }
+
+        if let Some(percent) = ZOOM_PREFIXES.iter().find_map(|prefix| lower.strip_prefix(prefix)).and_then(quantity::parse_percent) {
+            return self.plan_zoom(percent);
+        }
+
+        if let Some((subject, target)) = parse_screenshot(trimmed) {
+            let region = match subject {
+                ShotSubject::Screen => None,
+                ShotSubject::Region(region) => Some(region),
+                ShotSubject::Window => return None,
+            };
+            return Some(vec![LunaAction::Screenshot { region, target }]);
+        }
+        // Pasting into a field or typing at the caret needs the screen
+        if strip_at_caret(trimmed).is_some() || split_paste_target(trimmed).is_some() {
+            return None;
+        }
+        if let Some(path) = parse_paste_image(trimmed) {
+            return Some(vec![LunaAction::PasteImage { path }]);
+        }
+
+        // "type today's date", "type 1,234.56 formatted for German locale"
+        if lower.starts_with("type ") {
+            if let Some(text) = localized_text(&trimmed["type ".len()..], &self.locale) {
+                return Some(vec![LunaAction::Type { text }]);
+            }
+        }
+
+        // Quoted text is typed as is; unquoted text only when no field is
+        // named ("type hello in the search box" needs the screen)
+        if lower.starts_with("type ") {
+            let rest = trimmed[5..].trim();
+            if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') && !rest[1..rest.len() - 1].contains('"') {
+                return Some(vec![LunaAction::Type { text: rest[1..rest.len() - 1].to_string() }]);
+            }
+            let rest_lower = &lower[lower.len() - rest.len()..];
+            let names_field = split_type_target(trimmed).is_some() || rest_lower.starts_with("into ") || rest_lower.starts_with("in ");
+            if !rest.is_empty() && !rest.contains('"') && !names_field {
+                return Some(vec![LunaAction::Type { text: rest.to_string() }]);
+            }
+        }
+
+        None
+    }
+
+    /// Reset zoom to 100%, then zoom in or out to the level nearest `percent`
+    fn plan_zoom(&self, percent: f64) -> Option<Vec<LunaAction>> {
+        let presses = quantity::zoom_presses(percent, &self.zoom_steps)?;
+        let chord = |key: &str| LunaAction::KeyCombo { keys: vec!["ctrl".to_string(), key.to_string()] };
+        let step = chord(if presses > 0 { "=" } else { "-" });
+        Some(std::iter::once(chord("0")).chain(std::iter::repeat_n(step, presses.unsigned_abs() as usize)).collect())
+    }
+
+    /// Get processing statistics
+    pub fn get_stats(&self) -> &ProcessingStats {
+        &self.stats
+    }
+
+    /// Calculate overall confidence from detected elements
+    fn calculate_overall_confidence(&self, elements: &[ScreenElement]) -> f32 {
+        if elements.is_empty() {
+            return 0.0;
+        }
+        
+        let total_confidence: f32 = elements.iter().map(|e| e.confidence).sum();
+        total_confidence / elements.len() as f32
+    }
+
+    /// Elements whose text matches the command, best first, with why each matched
+    pub fn rank_text_targets<'a>(&self, command: &str, elements: &'a [ScreenElement]) -> Vec<RankedTarget<'a>> {
+        let mut ranked: Vec<RankedTarget> = elements
+            .iter()
+            .filter_map(|element| {
+                let found = match_name(command, element)?;
+                Some(RankedTarget { element, score: found.score, coverage: found.coverage, reasoning: found.reasoning })
+            })
+            .collect();
+        // A label that matched through its control is not a target of its own
+        let labels: Vec<String> = ranked.iter().filter_map(|t| t.element.label()).map(str::to_string).collect();
+        ranked.retain(|t| t.element.element_type != "label" || t.element.text.as_ref().is_none_or(|text| !labels.contains(text)));
+        ranked.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.coverage.total_cmp(&a.coverage))
+                .then(b.element.reliability().total_cmp(&a.element.reliability()))
+                .then(b.element.visibility().total_cmp(&a.element.visibility()))
+                .then(a.element.occlusion().total_cmp(&b.element.occlusion()))
+                .then(b.element.confidence.total_cmp(&a.element.confidence))
+        });
+        ranked
+    }
+
+    /// Find the best clickable element for a command
+    fn find_clickable_element<'a>(&self, command: &str, elements: &'a [ScreenElement]) -> Option<&'a ScreenElement> {
+        // An element named by the command beats any type preference
+        if let Some(target) = self.rank_text_targets(command, elements).into_iter().next() {
+            info!("Target {} at ({}, {}): {}", target.element.element_type, target.element.bounds.x, target.element.bounds.y, target.reasoning);
+            return Some(target.element);
+        }
+
+        // Look for specific element types mentioned in command
+        let button_keywords = ["button", "click", "press"];
+        let link_keywords = ["link", "navigate", "go to"];
+        
+        // First, try to find elements by type preference
+        for keyword in &button_keywords {
+            if command.contains(keyword) {
+                if let Some(button) = elements.iter().find(|e| e.element_type == "button") {
+                    return Some(button);
+                }
+            }
+        }
+        
+        for keyword in &link_keywords {
+            if command.contains(keyword) {
+                if let Some(link) = elements.iter().find(|e| e.element_type == "link") {
+                    return Some(link);
+                }
+            }
+        }
+
+        // Fall back to first clickable element
+        elements.iter()
+            .find(|e| matches!(e.element_type.as_str(), "button" | "link" | "icon"))
+    }
+
+    /// Plan commands aimed at stateful controls: "check remember me", "turn off wifi",
+    /// "set volume slider to 30%", "open the settings tab", "open the country dropdown".
+    ///
+    /// The control's current state is reported first, and nothing is clicked when
+    /// it is already in the requested state. `None` when the command is not
+    /// about a control, or names a tab or dropdown and none is on screen.
+    fn plan_control_actions(&self, command: &str, elements: &[ScreenElement]) -> Result<Option<Vec<LunaAction>>> {
+        const ON_PREFIXES: [&str; 5] = ["check ", "tick ", "enable ", "turn on ", "switch on "];
+        const OFF_PREFIXES: [&str; 5] = ["uncheck ", "untick ", "disable ", "turn off ", "switch off "];
+
+        let desired = if ON_PREFIXES.iter().any(|p| command.starts_with(p)) {
+            Some(Some(true))
+        } else if OFF_PREFIXES.iter().any(|p| command.starts_with(p)) {
+            Some(Some(false))
+        } else if command.starts_with("toggle ") {
+            Some(None)
+        } else {
+            None
+        };
+
+        if let Some(desired) = desired {
+            let control = require_control(command, elements, &["checkbox", "toggle", "radio"])?;
+            let current = control_state(control);
+            info!("{} is currently {}", control.element_type, if current { "on" } else { "off" });
+            if desired == Some(current) {
+                return Ok(Some(Vec::new()));
+            }
+            let (x, y) = control.click_point();
+            return Ok(Some(vec![LunaAction::Click { x, y }]));
+        }
+
+        // Only an explicit slider: "type 50% off in the coupon field" is text
+        let percent = command
+            .split_whitespace()
+            .find_map(|word| word.strip_suffix('%')?.parse::<f64>().ok());
+        if let Some(percent) = percent.filter(|_| command.contains("slider") || command.contains("volume")) {
+            let slider = require_control(command, elements, &["slider"])?;
+            if let Some(value) = slider.attributes.get("value") {
+                info!("slider is currently at {}%", (value.parse::<f64>().unwrap_or(0.0) * 100.0).round());
+            }
+            let fraction = (percent / 100.0).clamp(0.0, 1.0);
+            let x = slider.bounds.x + (fraction * (slider.bounds.width - 1) as f64).round() as i32;
+            let (_, y) = slider.bounds.center();
+            return Ok(Some(vec![LunaAction::Click { x, y }]));
+        }
+
+        // Without a detected tab or dropdown, "click the settings tab" can still match by text
+        if command.contains(" tab") {
+            let Some(tab) = find_control(command, elements, &["tab"])? else { return Ok(None) };
+            if tab.attributes.get("selected").is_some_and(|s| s == "true") {
+                info!("tab is already selected");
+                return Ok(Some(Vec::new()));
+            }
+            let (x, y) = tab.click_point();
+            return Ok(Some(vec![LunaAction::Click { x, y }]));
+        }
+
+        if command.contains("dropdown") {
+            let Some(dropdown) = find_control(command, elements, &["dropdown"])? else { return Ok(None) };
+            let (x, y) = dropdown.click_point();
+            return Ok(Some(vec![LunaAction::Click { x, y }]));
+        }
+
+        Ok(None)
+    }
+
+    /// Extract text to type from command
+    fn extract_text_from_command(&self, command: &str) -> Option<String> {
+        // Simple text extraction - look for quoted text or text after "type"
+        if let Some(start) = command.find('"') {
+            if let Some(end) = command[start + 1..].find('"') {
+                return Some(command[start + 1..start + 1 + end].to_string());
+            }
+        }
+
+        // Look for text after "type" keyword
+        if let Some(type_pos) = text::lowercase_aligned(command).find("type") {
+            let after_type = &command[type_pos + 4..].trim();
+            if !after_type.is_empty() {
+                return Some(after_type.to_string());
+            }
+        }
+
+        None
+    }
+}
+
+/// Run the control detector and convert its results to element detections
+fn detect_controls(frame: &Image) -> Result<Vec<ElementDetection>> {
+    let controls = ControlDetector::new().detect(frame)?;
+
+    Ok(controls
+        .into_iter()
+        .map(|control| ElementDetection {
+            element_type: control.element_type.to_string().to_lowercase(),
+            bounds: ElementBounds::from(&control.bounds),
+            shape: control.shape,
+            confidence: control.confidence as f32,
+            text: None,
+            attributes: control.properties,
+        })
+        .collect())
+}
+
+/// Detected colored shapes are this sure of themselves
+const COLOR_SHAPE_CONFIDENCE: f32 = 0.65;
+/// Colored shapes smaller than this on either side are text strokes or icons' details
+const MIN_COLOR_SHAPE_SIDE: f64 = 6.0;
+/// Largest fraction of the frame a colored shape may cover; bigger patches are backgrounds
+const MAX_COLOR_SHAPE_FRACTION: f64 = 0.05;
+/// Shape words in commands, and the shape they mean
+const COLOR_SHAPE_WORDS: [(&str, &str); 6] = [
+    ("circle", "circle"), ("dot", "circle"), ("round", "circle"),
+    ("square", "square"), ("rectangle", "rectangle"), ("bar", "rectangle"),
+];
+/// Element types a color can be combined with ("the red button")
+const COLOR_TARGET_TYPES: [&str; 6] = ["button", "icon", "link", "tab", "checkbox", "toggle"];
+
+/// Solid patches of one hue shaped like a circle, square or rectangle that
+/// don't overlap an existing detection, as `shape` elements
+fn color_shapes(frame: &Image, existing: &[ElementDetection]) -> Vec<ElementDetection> {
+    let max_area = (frame.width * frame.height) as f64 * MAX_COLOR_SHAPE_FRACTION;
+    color::named_color_regions(frame)
+        .into_iter()
+        .filter(|region| region.bounds.width.min(region.bounds.height) >= MIN_COLOR_SHAPE_SIDE && region.bounds.area() <= max_area)
+        .filter_map(|region| {
+            let shape = region.shape()?;
+            let bounds = ElementBounds::from(&region.bounds);
+            if existing.iter().any(|e| overlap_ratio(&e.bounds, &bounds) > 0.5) {
+                return None;
+            }
+            let mut attributes = HashMap::new();
+            attributes.insert(COLOR_ATTRIBUTE.to_string(), region.color.to_string());
+            attributes.insert(SHAPE_ATTRIBUTE.to_string(), shape.to_string());
+            Some(ElementDetection {
+                element_type: "shape".to_string(),
+                bounds,
+                shape: None,
+                confidence: COLOR_SHAPE_CONFIDENCE,
+                text: None,
+                attributes,
+            })
+        })
+        .collect()
+}
+
+/// Element types "type ... into <field>" can target
+pub(crate) const FIELD_TYPES: [&str; 4] = ["textfield", "textbox", "input", "combobox"];
+/// Element types that group other elements and can be named as a scope
+const CONTAINER_TYPES: [&str; 3] = ["window", "dialog", "panel"];
+/// Text this close to a container's top edge is its title
+const TITLE_BAR_HEIGHT: i32 = 40;
+/// Time for a raised window to come to the front before clicking into it
+const RAISE_SETTLE_MS: u64 = 150;
+/// Visibility below which a click target is scrolled into view first
+const MIN_CLICK_VISIBILITY: f64 = 0.6;
+/// Time for scrolled content to settle before clicking into it
+const REVEAL_SETTLE_MS: u64 = 250;
+/// Containers that "close" applies to
+const WINDOW_TYPES: [&str; 2] = ["window", "dialog"];
+/// Words in "close it" / "close the window" that don't name a window
+const CLOSE_FILLER: [&str; 7] = ["it", "this", "that", "the", "window", "dialog", "app"];
+/// Title-bar text of a close button
+const CLOSE_LABELS: [&str; 4] = ["x", "×", "✕", "close"];
+/// How long a planned semantic wait gives its condition
+const WAIT_TIMEOUT_MS: u64 = 10_000;
+/// How long the screen must be still to count as idle
+const IDLE_QUIET_MS: u64 = 500;
+/// Endings of "wait for X to disappear" / "wait until X is gone"
+const GONE_SUFFIXES: [&str; 9] = [
+    " to disappear", " to go away", " to vanish", " to be gone", " to close",
+    " disappears", " goes away", " is gone", " closes",
+];
+/// Endings of "wait for X to appear" / "wait until X shows up"
+const APPEAR_SUFFIXES: [&str; 7] = [" to appear", " to show up", " to be visible", " appears", " shows up", " is visible", " is shown"];
+/// "wait until the screen is idle" and friends
+const IDLE_PHRASES: [&str; 6] = [
+    "idle", "the screen is idle", "the screen settles", "the screen to settle", "the screen to be idle", "things settle",
+];
+/// Commands that set a zoom level ("set zoom to 150%")
+const ZOOM_PREFIXES: [&str; 4] = ["set zoom to ", "set the zoom to ", "zoom to ", "zoom "];
+/// Verbs of commands that only use the keyboard
+const KEYBOARD_VERBS: [&str; 4] = ["press ", "hit ", "key ", "type "];
+/// Separators between the steps of "press ctrl+a, then type hello"
+const STEP_SEPARATORS: [&str; 5] = [", then ", " and then ", " then ", " and ", ", "];
+/// Gerunds that open "after clicking Submit, wait ..." and the verb they plan as
+const AFTER_VERBS: [(&str, &str); 5] = [("clicking", "click"), ("pressing", "press"), ("typing", "type"), ("closing", "close"), ("scrolling", "scroll")];
+
+/// Panels inside windows and dialogs, split along visual separators
+fn separator_panels(image: &DynamicImage, elements: &[ScreenElement]) -> Vec<ScreenElement> {
+    let containers: Vec<&ScreenElement> = elements
+        .iter()
+        .filter(|e| CONTAINER_TYPES.contains(&e.element_type.as_str()))
+        .collect();
+    if containers.is_empty() {
+        return Vec::new();
+    }
+    let rgb = image.to_rgb8();
+    let frame = Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw());
+
+    let mut panels = Vec::new();
+    for container in containers {
+        let region = Rectangle::from(&container.bounds);
+        let separators = hierarchy::find_separators(&frame, &region);
+        if separators.is_empty() {
+            continue;
+        }
+        for cell in hierarchy::split_panels(&region, &separators) {
+            let bounds = ElementBounds::from(&cell);
+            // Empty cells are background, not panels
+            let occupied = elements.iter().any(|e| {
+                let (x, y) = e.bounds.center();
+                e.bounds.width < bounds.width && bounds.contains_point(x, y)
+            });
+            if occupied {
+                panels.push(ScreenElement {
+                    element_type: "panel".to_string(),
+                    bounds,
+                    shape: None,
+                    confidence: container.confidence,
+                    text: None,
+                    attributes: HashMap::from([("source".to_string(), "separator".to_string())]),
+                    parent: None,
+                    children: Vec::new(),
+                });
+            }
+        }
+    }
+    panels
+}
+
+/// The container that looks like an open modal dialog: one holding other
+/// elements, centered, and set off by a border or a dimmed background
+fn find_modal(image: &DynamicImage, analysis: &ScreenAnalysis) -> Option<usize> {
+    let containers: Vec<usize> = (0..analysis.elements.len())
+        .filter(|&i| !analysis.elements[i].children.is_empty())
+        .filter(|&i| analysis.elements[i].attributes.get("source").is_none_or(|source| source != "separator"))
+        .collect();
+    if containers.is_empty() {
+        return None;
+    }
+    let rgb = image.to_rgb8();
+    let frame = Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw());
+    let bounds: Vec<Rectangle> = containers.iter().map(|&i| Rectangle::from(&analysis.elements[i].bounds)).collect();
+    modal::find_modal(&frame, &bounds).map(|found| containers[found])
+}
+
+/// Split "in the settings dialog, click apply" or "click apply in the settings dialog"
+/// into the container name and the rest of the command
+fn split_scope(command: &str) -> Option<(String, String)> {
+    static PATTERNS: std::sync::OnceLock<[regex::Regex; 2]> = std::sync::OnceLock::new();
+    let [leading, trailing] = PATTERNS.get_or_init(|| {
+        const KIND: &str = r"\s+(?:dialog|window|panel|pane|section|sidebar|toolbar|form)";
+        [
+            regex::Regex::new(&format!(r"(?i)^\s*(?:in|inside|within|on)\s+(?:the\s+)?(?P<scope>.+?){}\s*,\s*(?P<rest>.+)$", KIND)).unwrap(),
+            regex::Regex::new(&format!(r"(?i)^(?P<rest>.+)\s+(?:in|inside|within)\s+(?:the\s+)?(?P<scope>.+?){}\s*$", KIND)).unwrap(),
+        ]
+    });
+    [leading, trailing].iter().find_map(|pattern| {
+        let captures = pattern.captures(command.trim())?;
+        Some((captures["scope"].to_string(), captures["rest"].trim().to_string()))
+    })
+}
+
+/// Container whose title best matches `name`
+fn find_container(name: &str, analysis: &ScreenAnalysis) -> Option<usize> {
+    analysis.elements
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| CONTAINER_TYPES.contains(&e.element_type.as_str()))
+        .filter_map(|(index, _)| {
+            let title = container_title(analysis, index)?;
+            Some((index, text_match::match_label(name, title)?.score))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+}
+
+/// Where to click element `index`, and the actions that bring it into view
+/// first. A target mostly scrolled out of its list or off the screen is
+/// scrolled in, with the pointer over the list so the wheel reaches it, and
+/// clicked where it should then be; when it isn't there, the stale-frame
+/// guard re-plans. A partly clipped target is clicked in the middle of what
+/// shows of it, since its center may be over another widget.
+fn reveal_before_click(analysis: &ScreenAnalysis, index: usize, units: &ScrollUnits) -> (Vec<LunaAction>, (i32, i32)) {
+    let element = &analysis.elements[index];
+    let point = element.click_point();
+    let (bounds, Some(clip)) = (Rectangle::from(&element.bounds), analysis.clip_area(index)) else {
+        return (Vec::new(), point);
+    };
+    let shown = clip.intersection(&bounds).filter(|r| r.area() > 0.0);
+    let pixel = |p: Point| (p.x.round() as i32, p.y.round() as i32);
+    let inside = |r: &Rectangle, (x, y): (i32, i32)| r.contains_point(&Point::new(x as f64, y as f64));
+    if element.visibility() >= MIN_CLICK_VISIBILITY || bounds.height >= clip.height {
+        return match shown {
+            Some(shown) if !inside(&shown, point) => (Vec::new(), pixel(shown.center())),
+            _ => (Vec::new(), point),
+        };
+    }
+
+    // How far the content has to move up (scrolling down) or down (up)
+    let (top, bottom) = (bounds.y, bounds.y + bounds.height);
+    let (clip_top, clip_bottom) = (clip.y, clip.y + clip.height);
+    let shift = if bottom > clip_bottom {
+        (bottom - clip_bottom).min(top - clip_top)
+    } else if top < clip_top {
+        -(clip_top - top).min(clip_bottom - bottom)
+    } else {
+        0.0
+    };
+    if shift.abs() < 1.0 {
+        // Cut off at the side: there is no sideways scroll, so click what shows
+        return (Vec::new(), shown.map_or(point, |shown| pixel(shown.center())));
+    }
+    let notch = f64::from(units.notch_pixels.max(1));
+    let notches = (shift.abs() / notch).ceil();
+    let x = (point.0 as f64).clamp(clip.x + 1.0, clip.x + clip.width - 1.0);
+    let y = (point.1 as f64 - notches * notch * shift.signum()).clamp(clip_top + 1.0, clip_bottom - 1.0);
+    let (hover_x, hover_y) = pixel(shown.unwrap_or(clip).center());
+    info!("Scrolling {} {} notch(es) to bring the target into view", if shift > 0.0 { "down" } else { "up" }, notches);
+    let actions = vec![
+        LunaAction::Hover { x: hover_x, y: hover_y },
+        LunaAction::Scroll { direction: if shift > 0.0 { "down" } else { "up" }.to_string(), amount: notches as i32 },
+        LunaAction::Wait { milliseconds: REVEAL_SETTLE_MS },
+    ];
+    (actions, pixel(Point::new(x, y)))
+}
+
+/// Click on the visible part of the title bar of the window `element` is in
+/// when the click at `point` would land on a window in front of it
+fn raise_before_click(analysis: &ScreenAnalysis, element: usize, point: (i32, i32)) -> Result<Vec<LunaAction>> {
+    let covering: Vec<Rectangle> = analysis.covering_windows(element)
+        .into_iter()
+        .map(|i| Rectangle::from(&analysis.elements[i].bounds))
+        .collect();
+    if !occlusion::is_covered(&Point::new(point.0 as f64, point.1 as f64), &covering) {
+        return Ok(Vec::new());
+    }
+
+    let window = analysis.root(element);
+    let bounds = &analysis.elements[window].bounds;
+    let title_bar = Rectangle::new(bounds.x as f64, bounds.y as f64, bounds.width as f64, TITLE_BAR_HEIGHT.min(bounds.height) as f64);
+    let name = container_title(analysis, window).unwrap_or(&analysis.elements[window].element_type);
+    let grab = occlusion::visible_point(&title_bar, &covering)
+        .ok_or_else(|| LunaError::NotFound(format!("target is hidden behind another window and '{}' has no visible title bar to raise it by", name)))?;
+    info!("Raising '{}' first: the target is behind another window", name);
+    Ok(vec![
+        LunaAction::Click { x: grab.x.round() as i32, y: grab.y.round() as i32 },
+        LunaAction::Wait { milliseconds: RAISE_SETTLE_MS },
+    ])
+}
+
+/// Click on the close control in a window's title bar. Alt+F4 is blocked by
+/// the input safety checker, so the button is the only way to close.
+fn close_button(analysis: &ScreenAnalysis, window: usize) -> Result<LunaAction> {
+    let container = &analysis.elements[window];
+    let button = container.children
+        .iter()
+        .map(|&child| &analysis.elements[child])
+        .filter(|child| child.bounds.y - container.bounds.y < TITLE_BAR_HEIGHT)
+        .find(|child| child.text.as_deref().is_some_and(|text| CLOSE_LABELS.contains(&text.trim().to_lowercase().as_str())))
+        .ok_or_else(|| LunaError::NotFound(format!("no close button on '{}'",
+            container_title(analysis, window).unwrap_or(&container.element_type))))?;
+    let (x, y) = button.click_point();
+    Ok(LunaAction::Click { x, y })
+}
+
+/// A container's own text, or else its topmost child text inside the title bar
+fn container_title(analysis: &ScreenAnalysis, index: usize) -> Option<&str> {
+    let container = &analysis.elements[index];
+    if let Some(text) = &container.text {
+        return Some(text);
+    }
+    container.children
+        .iter()
+        .map(|&child| &analysis.elements[child])
+        .filter(|child| child.bounds.y - container.bounds.y < TITLE_BAR_HEIGHT)
+        .filter_map(|child| Some((child.bounds.y, child.text.as_deref()?)))
+        .filter(|(_, text)| !CLOSE_LABELS.contains(&text.trim().to_lowercase().as_str()))
+        .min_by_key(|(y, _)| *y)
+        .map(|(_, text)| text)
+}
+
+/// Intersection area over the smaller element's area
+fn overlap_ratio(a: &ElementBounds, b: &ElementBounds) -> f64 {
+    let (a, b) = (Rectangle::from(a), Rectangle::from(b));
+    match a.intersection(&b) {
+        Some(inter) => inter.area() / a.area().min(b.area()).max(1.0),
+        None => 0.0,
+    }
+}
+
+/// The control of one of `types` whose text best matches the command, or the
+/// only one on screen. `None` when there is none; `NotFound` when there are
+/// several and the command names none of them.
+fn find_control<'a>(command: &str, elements: &'a [ScreenElement], types: &[&str]) -> Result<Option<&'a ScreenElement>> {
+    let controls: Vec<&ScreenElement> = elements
+        .iter()
+        .filter(|e| types.contains(&e.element_type.as_str()))
+        .collect();
+
+    let named = controls
+        .iter()
+        .filter_map(|e| Some((*e, match_name(command, e)?)))
+        .max_by(|a, b| a.1.score.total_cmp(&b.1.score));
+    if let Some((control, found)) = named {
+        debug!("Control {}: {}", control.element_type, found.reasoning);
+        return Ok(Some(control));
+    }
+    match controls.as_slice() {
+        [] => Ok(None),
+        [only] => Ok(Some(*only)),
+        _ => Err(LunaError::NotFound(format!("{} {}s on screen and '{}' names none of them", controls.len(), types[0], command)).into()),
+    }
+}
+
+/// `find_control` for commands that only make sense on a control
+fn require_control<'a>(command: &str, elements: &'a [ScreenElement], types: &[&str]) -> Result<&'a ScreenElement> {
+    find_control(command, elements, types)?
+        .ok_or_else(|| LunaError::NotFound(format!("no {} on screen for '{}'", types.join(" or "), command)).into())
+}
+
+/// Best match of the command against an element's own text or the label
+/// that names it
+fn match_name(command: &str, element: &ScreenElement) -> Option<text_match::TextMatch> {
+    [element.text.as_deref(), element.label()]
+        .into_iter()
+        .flatten()
+        .filter_map(|name| text_match::match_label(command, name))
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+}
+
+/// Split "type bob@example.com into Email" or "enter 42 in the quantity
+/// field" into the text to type, the words naming the field, and whether the
+/// field was named with "into" (rather than "in", which can be part of the text)
+fn split_type_target(command: &str) -> Option<(String, String, bool)> {
+    let lower = text::lowercase_aligned(command);
+    let start = ["type ", "enter "].iter().find(|verb| lower.starts_with(**verb))?.len();
+    let (at, separator) = [" into ", " in "]
+        .into_iter()
+        .find_map(|separator| lower.rfind(separator).filter(|&at| at >= start).map(|at| (at, separator)))?;
+    let typed = command[start..at].trim().trim_matches('"');
+    let field = command[at + separator.len()..].trim();
+    (!typed.is_empty() && !field.is_empty()).then(|| (typed.to_string(), field.to_string(), separator == " into "))
+}
+
+/// Endings that ask for text to go wherever the caret is
+const AT_CARET_SUFFIXES: [&str; 4] = [" at the cursor", " at cursor", " at the caret", " at the text cursor"];
+
+/// "type hello at the cursor" without its ending; `None` without one
+fn strip_at_caret(command: &str) -> Option<&str> {
+    let command = command.trim_end();
+    let lower = text::lowercase_aligned(command);
+    AT_CARET_SUFFIXES
+        .iter()
+        .find(|suffix| lower.ends_with(**suffix))
+        .map(|suffix| command[..command.len() - suffix.len()].trim_end())
+}
+
+/// Whether planning `command` can use where the caret is: it types or pastes
+pub fn needs_caret(command: &str) -> bool {
+    let lower = text::lowercase_aligned(command.trim_start());
+    ["type ", "enter ", "paste "].iter().any(|verb| lower.starts_with(verb))
+}
+
+/// The file in "paste image C:\shots\plot.png"
+fn parse_paste_image(command: &str) -> Option<PathBuf> {
+    let command = command.trim();
+    let lower = text::lowercase_aligned(command);
+    let verb = ["paste image ", "paste the image "].into_iter().find(|verb| lower.starts_with(verb))?;
+    let path = command[verb.len()..].trim().trim_matches('"');
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Split "paste image plot.png into Notes" into the image and the field
+fn split_paste_target(command: &str) -> Option<(PathBuf, String)> {
+    let lower = text::lowercase_aligned(command);
+    let at = lower.rfind(" into ")?;
+    let field = command[at + " into ".len()..].trim();
+    let path = parse_paste_image(&command[..at])?;
+    (!field.is_empty()).then(|| (path, field.to_string()))
+}
+
+/// Click into the field at `index`, raising its window first; nothing when
+/// the caret is already in it, since a click would move the caret
+fn focus_field(analysis: &ScreenAnalysis, index: usize) -> Result<Vec<LunaAction>> {
+    let field = &analysis.elements[index];
+    if analysis.context.caret.is_some_and(|caret| caret.is_inside(&field.bounds)) {
+        info!("The text cursor is already in the {}; not clicking it", field.element_type);
+        return Ok(Vec::new());
+    }
+    let (x, y) = field.click_point();
+    let mut actions = raise_before_click(analysis, index, (x, y))?;
+    actions.push(LunaAction::Click { x, y });
+    Ok(actions)
+}
+
+/// Words that name a date relative to today, and the offset in days
+const DATE_PHRASES: [(&str, i64); 6] = [
+    ("today's date", 0), ("todays date", 0), ("the date", 0), ("the current date", 0),
+    ("tomorrow's date", 1), ("yesterday's date", -1),
+];
+/// Words that end an explicit locale ("for German locale", "in de-DE format")
+const LOCALE_NOUNS: [&str; 4] = [" locale", " format", " formatting", " style"];
+
+/// What to type for "today's date" or "1,234.56 formatted for German
+/// locale": dates in `locale` unless another is named, amounts only when
+/// formatting is asked for. `None` when `value` is neither.
+fn localized_text(value: &str, locale: &Locale) -> Option<String> {
+    let value = value.trim().trim_end_matches(['.', '!']);
+    let lower = text::lowercase_aligned(value);
+    let (subject, locale, explicit) = explicit_locale(&lower)
+        .map(|(at, named)| (lower[..at].trim(), named, true))
+        .or_else(|| lower.strip_suffix(" formatted").map(|subject| (subject.trim(), *locale, true)))
+        .unwrap_or((lower.as_str(), *locale, false));
+
+    if let Some((_, offset)) = DATE_PHRASES.iter().find(|(phrase, _)| *phrase == subject) {
+        return Some(locale.format_date(Date::today().add_days(*offset)));
+    }
+    let (amount, decimals) = locale::parse_amount(subject).filter(|_| explicit)?;
+    Some(locale.format_number(amount, decimals))
+}
+
+/// Where a trailing "formatted for German locale", "in de-DE format" or
+/// "for the French locale" starts, and the locale it names
+fn explicit_locale(lower: &str) -> Option<(usize, Locale)> {
+    [" formatted for ", " formatted in ", " formatted as ", " for ", " in ", " as "]
+        .iter()
+        .filter_map(|connector| Some((lower.rfind(connector)?, connector.len())))
+        .min_by_key(|(at, _)| *at)
+        .and_then(|(at, len)| {
+            let named = lower[at + len..].trim();
+            let named = named.strip_prefix("the ").unwrap_or(named);
+            let named = LOCALE_NOUNS.iter().find_map(|noun| named.strip_suffix(noun)).unwrap_or(named);
+            Some((at, Locale::parse(named)?))
+        })
+}
+
+/// Whether a checkbox/radio is checked or a toggle is on
+fn control_state(element: &ScreenElement) -> bool {
+    element.attributes.get("checked").is_some_and(|v| v == "true")
+        || element.attributes.get("state").is_some_and(|v| v == "on")
+}
+
+/// What a screenshot command captures
+#[derive(Debug, Clone, PartialEq)]
+enum ShotSubject {
+    Screen,
+    Region(ElementBounds),
+    /// The frontmost window, found by analysis
+    Window,
+}
+
+/// Parse "take a screenshot", "screenshot region 0 0 400 300 to shot.png" or
+/// "screenshot this window to clipboard". The target defaults to the
+/// clipboard; a file name keeps its case.
+fn parse_screenshot(command: &str) -> Option<(ShotSubject, ShotTarget)> {
+    let command = command.trim();
+    let lower = text::lowercase_aligned(command);
+    let start = ["take a screenshot", "take screenshot", "screenshot"]
+        .iter()
+        .find_map(|verb| lower.strip_prefix(verb).filter(|rest| rest.is_empty() || rest.starts_with(' ')))
+        .map(|rest| command.len() - rest.len())?;
+
+    let rest = &lower[start..];
+    let (subject, target) = match rest.rfind(" to ") {
+        Some(at) => {
+            let named = command[start + at + 4..].trim();
+            let target = match named.to_lowercase().as_str() {
+                "clipboard" | "the clipboard" => ShotTarget::Clipboard,
+                _ => ShotTarget::File(PathBuf::from(named.trim_matches('"'))),
+            };
+            (rest[..at].trim(), target)
+        }
+        None => (rest.trim(), ShotTarget::Clipboard),
+    };
+    let subject = subject.strip_prefix("of ").unwrap_or(subject).trim();
+    let subject = match subject {
+        "" | "screen" | "the screen" => ShotSubject::Screen,
+        "window" | "this window" | "the window" | "the current window" | "the active window" => ShotSubject::Window,
+        _ => {
+            let numbers = subject.strip_prefix("region")?;
+            let numbers: Vec<i32> = numbers
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|n| !n.is_empty())
+                .map(|n| n.parse().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let [x, y, width, height] = numbers[..] else {
+                return None;
+            };
+            ShotSubject::Region(ElementBounds { x, y, width, height })
+        }
+    };
+    Some((subject, target))
+}
+
+/// Items a selection command names
+#[derive(Debug, PartialEq)]
+enum Selection {
+    /// "select report1 through report5": both ends and everything between
+    Range(String, String),
+    /// "select a, b and c"; `add` keeps what is already selected, as
+    /// "ctrl-click" does
+    Items { names: Vec<String>, add: bool },
+}
+
+/// Parse a (lowercase) multi-selection command. A plain "select X" of a
+/// single item is no multi-selection and is left to the other planners.
+fn parse_selection(command: &str) -> Option<Selection> {
+    const TOGGLE_VERBS: [&str; 8] = [
+        "ctrl-click ", "ctrl+click ", "ctrl click ", "control-click ", "cmd-click ", "cmd+click ", "command-click ", "ctrl-select ",
+    ];
+    const RANGE_VERBS: [&str; 3] = ["shift-click ", "shift+click ", "shift click "];
+    const FILLER: [&str; 9] = ["the ", "files ", "file ", "items ", "item ", "rows ", "entries ", "from ", "all of "];
 
-        Ok(ScreenAnalysis {
-            elements: filtered_elements,
-            confidence,
-            processing_time_ms,
-            screen_size: (image.width(), image.height()),
-        })
+    let command = command.trim().trim_end_matches('.');
+    let (mut rest, add, range_only) = if let Some(rest) = TOGGLE_VERBS.iter().find_map(|v| command.strip_prefix(v)) {
+        (rest, true, false)
+    } else if let Some(rest) = RANGE_VERBS.iter().find_map(|v| command.strip_prefix(v)) {
+        (rest, false, true)
+    } else {
+        (command.strip_prefix("select ")?, false, false)
+    };
+    while let Some(stripped) = FILLER.iter().find_map(|f| rest.strip_prefix(f)) {
+        rest = stripped;
     }
+    let clean = |name: &str| {
+        let name = name.trim();
+        name.strip_prefix("the ").unwrap_or(name).trim_matches(['"', '\'']).trim().to_string()
+    };
 
-    /// Plan actions based on user command and screen analysis
-    pub fn plan_actions(&self, command: &str, analysis: &ScreenAnalysis) -> Result<Vec<LunaAction>> {
-        debug!("Planning actions for command: '{}'", command);
-        
-        let command_lower = command.to_lowercase();
-        let mut actions = Vec::new();
+    if let Some((first, last)) = [" through ", " thru ", " to "].iter().find_map(|separator| rest.split_once(separator)) {
+        let (first, last) = (clean(first), clean(last));
+        return (!first.is_empty() && !last.is_empty()).then_some(Selection::Range(first, last));
+    }
+    if range_only {
+        return None;
+    }
+    let names: Vec<String> = rest
+        .split(", and ")
+        .flat_map(|part| part.split(", "))
+        .flat_map(|part| part.split(" and "))
+        .map(clean)
+        .filter(|name| !name.is_empty())
+        .collect();
+    // "select report1" alone is a plain click; "ctrl-click report1" still adds it
+    (names.len() > 1 || (add && names.len() == 1)).then_some(Selection::Items { names, add })
+}
 
-        // Simple command parsing and action planning
-        if command_lower.contains("click") {
-            if let Some(element) = self.find_clickable_element(&command_lower, &analysis.elements) {
-                let center_x = element.bounds.x + element.bounds.width / 2;
-                let center_y = element.bounds.y + element.bounds.height / 2;
-                
-                actions.push(LunaAction::Click { 
-                    x: center_x, 
-                    y: center_y 
-                });
-            }
-        } else if command_lower.contains("type") || command_lower.contains("enter") {
-            if let Some(text) = self.extract_text_from_command(&command) {
-                actions.push(LunaAction::Type { text });
-            }
-        } else if command_lower.contains("scroll") {
-            let direction = if command_lower.contains("up") { "up" }
-                          else if command_lower.contains("down") { "down" }
-                          else { "down" };
-            
-            actions.push(LunaAction::Scroll { 
-                direction: direction.to_string(),
-                amount: 3 
-            });
-        }
+/// Parse "wait for the spinner to disappear", "wait for Saved to appear",
+/// "wait until the screen is idle" or "wait 2 seconds". What is waited for
+/// is a `find` query, so an element type works as well as its text.
+fn parse_wait(command: &str) -> Option<LunaAction> {
+    let command = command.trim().trim_end_matches(['.', '!']);
+    let lower = text::lowercase_aligned(command);
+    let start = ["wait for ", "wait until ", "wait till ", "wait "]
+        .iter()
+        .find_map(|verb| lower.strip_prefix(verb))
+        .map(|rest| command.len() - rest.len())?;
+    let rest = &lower[start..];
 
-        debug!("Planned {} actions", actions.len());
-        Ok(actions)
+    if IDLE_PHRASES.contains(&rest) {
+        return Some(LunaAction::WaitForScreenIdle { quiet_ms: IDLE_QUIET_MS, timeout_ms: WAIT_TIMEOUT_MS });
+    }
+    if let Some(milliseconds) = quantity::parse_duration_ms(rest) {
+        return Some(LunaAction::Wait { milliseconds });
+    }
+    let subject = |suffix: &str| {
+        let text = command[start..command.len() - suffix.len()].trim();
+        let text = ["the ", "a ", "an "].iter().find_map(|a| text.strip_prefix(a)).unwrap_or(text);
+        let text = text.trim_matches(['"', '\'']).trim();
+        (!text.is_empty()).then(|| text.to_string())
+    };
+    if let Some(suffix) = GONE_SUFFIXES.iter().find(|s| rest.ends_with(*s)) {
+        return Some(LunaAction::WaitForTextGone { text: subject(suffix)?, timeout_ms: WAIT_TIMEOUT_MS });
     }
+    let suffix = APPEAR_SUFFIXES.iter().find(|s| rest.ends_with(*s)).copied().unwrap_or("");
+    // A bare "wait until X" has no condition to check
+    if suffix.is_empty() && !lower.starts_with("wait for ") {
+        return None;
+    }
+    Some(LunaAction::WaitForElement { query: subject(suffix)?, timeout_ms: WAIT_TIMEOUT_MS })
+}
 
-    /// Get processing statistics
-    pub fn get_stats(&self) -> &ProcessingStats {
-        &self.stats
+/// Split "after clicking Submit, wait for the spinner to disappear" or
+/// "click Submit, then wait until idle" into the command to plan first and
+/// the wait that follows it
+fn split_wait_clause(command: &str) -> Option<(String, LunaAction)> {
+    let command = command.trim();
+    let lower = text::lowercase_aligned(command);
+    if let Some(rest) = lower.strip_prefix("after ") {
+        let (at, _) = rest.match_indices(',').find(|(at, _)| parse_wait(&rest[at + 1..]).is_some())?;
+        let offset = command.len() - rest.len();
+        let first = command[offset..offset + at].trim();
+        let first = AFTER_VERBS
+            .iter()
+            .find_map(|(gerund, verb)| {
+                let tail = first.get(gerund.len()..)?;
+                (first[..gerund.len()].eq_ignore_ascii_case(gerund) && tail.starts_with(' ')).then(|| format!("{}{}", verb, tail))
+            })
+            .unwrap_or_else(|| first.to_string());
+        return Some((first, parse_wait(&command[offset + at + 1..])?));
     }
+    for separator in [", then ", " and then ", " then ", ", and ", " and ", ", "] {
+        let Some(at) = lower.rfind(&format!("{}wait ", separator)) else {
+            continue;
+        };
+        let wait = parse_wait(&command[at + separator.len()..])?;
+        let first = command[..at].trim();
+        return (!first.is_empty()).then(|| (first.to_string(), wait));
+    }
+    None
+}
 
-    /// Calculate overall confidence from detected elements
-    fn calculate_overall_confidence(&self, elements: &[ScreenElement]) -> f32 {
-        if elements.is_empty() {
-            return 0.0;
-        }
-        
-        let total_confidence: f32 = elements.iter().map(|e| e.confidence).sum();
-        total_confidence / elements.len() as f32
+/// Split "press ctrl+a, then type hello" before the first later step that
+/// starts with a keyboard verb; separators inside quotes don't count
+fn split_keyboard_steps(command: &str) -> Option<(&str, &str)> {
+    let lower = text::lowercase_aligned(command);
+    STEP_SEPARATORS
+        .iter()
+        .flat_map(|separator| lower.match_indices(separator).map(move |(at, _)| (at, at + separator.len())))
+        .filter(|&(at, next)| {
+            command[..at].matches('"').count().is_multiple_of(2) && KEYBOARD_VERBS.iter().any(|verb| lower[next..].starts_with(verb))
+        })
+        .min()
+        .map(|(at, next)| (command[..at].trim(), command[next..].trim()))
+        .filter(|(first, _)| KEYBOARD_VERBS.iter().any(|verb| text::lowercase_aligned(first).starts_with(verb)))
+}
+
+/// What happens while keys are held down
+#[derive(Debug, PartialEq)]
+enum Held {
+    /// A command, planned as usual
+    While(String),
+    /// Nothing, for this many milliseconds
+    For(u64),
+}
+
+/// Split "while holding shift, click report3", "hold ctrl while clicking
+/// report1", "hold alt and press tab", "click report3 while holding shift"
+/// and "hold w for 2 seconds" into the keys to hold and what to do meanwhile
+fn split_hold(command: &str) -> Option<(Vec<String>, Held)> {
+    let command = command.trim().trim_end_matches(['.', '!']);
+    let lower = text::lowercase_aligned(command);
+
+    if let Some(rest) = lower.strip_prefix("while holding ") {
+        let offset = command.len() - rest.len();
+        let (keys, split) = [rest.find(','), rest.find(' ')]
+            .into_iter()
+            .flatten()
+            .find_map(|split| Some((parse_held_keys(&rest[..split])?, split)))?;
+        let inner = command[offset + split..].trim_start_matches([',', ' ']);
+        return (!inner.is_empty()).then(|| (keys, Held::While(inner.to_string())));
+    }
+    if let Some(at) = lower.find(" while holding ") {
+        let keys = parse_held_keys(&lower[at + " while holding ".len()..])?;
+        return Some((keys, Held::While(command[..at].trim().to_string())));
     }
 
-    /// Find the best clickable element for a command
-    fn find_clickable_element<'a>(&self, command: &str, elements: &'a [ScreenElement]) -> Option<&'a ScreenElement> {
-        // Look for specific element types mentioned in command
-        let button_keywords = ["button", "click", "press"];
-        let link_keywords = ["link", "navigate", "go to"];
-        
-        // First, try to find elements by type preference
-        for keyword in &button_keywords {
-            if command.contains(keyword) {
-                if let Some(button) = elements.iter().find(|e| e.element_type == "button") {
-                    return Some(button);
-                }
-            }
+    let rest = lower.strip_prefix("hold down ").or_else(|| lower.strip_prefix("hold "))?;
+    let offset = command.len() - rest.len();
+    if let Some(at) = rest.rfind(" for ") {
+        if let (Some(keys), Some(milliseconds)) = (parse_held_keys(&rest[..at]), quantity::parse_duration_ms(&rest[at + " for ".len()..])) {
+            return Some((keys, Held::For(milliseconds)));
         }
-        
-        for keyword in &link_keywords {
-            if command.contains(keyword) {
-                if let Some(link) = elements.iter().find(|e| e.element_type == "link") {
-                    return Some(link);
-                }
-            }
+    }
+    if let Some(at) = rest.find(" while ") {
+        let inner = command[offset + at + " while ".len()..].trim();
+        let inner = AFTER_VERBS
+            .iter()
+            .find_map(|(gerund, verb)| {
+                let tail = inner.get(gerund.len()..)?;
+                (inner[..gerund.len()].eq_ignore_ascii_case(gerund) && tail.starts_with(' ')).then(|| format!("{}{}", verb, tail))
+            })
+            .unwrap_or_else(|| inner.to_string());
+        return Some((parse_held_keys(&rest[..at])?, Held::While(inner)));
+    }
+    // "hold ctrl and shift and click" holds both keys: split where the
+    // words after "and" stop naming keys
+    for (at, separator) in rest.match_indices(", then ").chain(rest.match_indices(" and ")).chain(rest.match_indices(", ")) {
+        let inner = command[offset + at + separator.len()..].trim();
+        if inner.split_whitespace().next().is_none_or(|word| keys::canonical_key_name(word).is_some()) {
+            continue;
         }
-
-        // Look for text matches
-        for element in elements {
-            if let Some(text) = &element.text {
-                let text_lower = text.to_lowercase();
-                for word in command.split_whitespace() {
-                    if text_lower.contains(word) && word.len() > 2 {
-                        return Some(element);
-                    }
-                }
-            }
+        if let Some(keys) = parse_held_keys(&rest[..at]) {
+            return Some((keys, Held::While(inner.to_string())));
         }
-
-        // Fall back to first clickable element
-        elements.iter()
-            .find(|e| matches!(e.element_type.as_str(), "button" | "link" | "icon"))
     }
+    None
+}
 
-    /// Extract text to type from command
-    fn extract_text_from_command(&self, command: &str) -> Option<String> {
-        // Simple text extraction - look for quoted text or text after "type"
-        if let Some(start) = command.find('"') {
-            if let Some(end) = command[start + 1..].find('"') {
-                return Some(command[start + 1..start + 1 + end].to_string());
-            }
-        }
+/// Keys named in "shift", "the ctrl key", "ctrl+shift" or "ctrl and shift",
+/// by their canonical names
+fn parse_held_keys(text: &str) -> Option<Vec<String>> {
+    let text = text.trim();
+    let text = text.strip_prefix("the ").unwrap_or(text);
+    let text = text.strip_suffix(" keys").or_else(|| text.strip_suffix(" key")).unwrap_or(text);
+    text.split(['+', ','])
+        .flat_map(|part| part.split(" and "))
+        .map(|name| keys::canonical_key_name(name.trim()).map(str::to_string))
+        .collect::<Option<Vec<_>>>()
+        .filter(|keys| !keys.is_empty())
+}
 
-        // Look for text after "type" keyword
-        if let Some(type_pos) = command.to_lowercase().find("type") {
-            let after_type = &command[type_pos + 4..].trim();
-            if !after_type.is_empty() {
-                return Some(after_type.to_string());
-            }
+/// `actions` with `keys` pressed before them and released, in reverse,
+/// after them
+fn hold_around(keys: &[String], actions: Vec<LunaAction>) -> Vec<LunaAction> {
+    let press = keys.iter().map(|key| LunaAction::KeyDown { key: key.clone() });
+    let release = keys.iter().rev().map(|key| LunaAction::KeyUp { key: key.clone() });
+    press.chain(actions).chain(release).collect()
+}
+
+/// Screenshot of the frontmost window or dialog, by `z_order`; asks which
+/// one when several are on screen and their stacking is unknown
+fn plan_window_screenshot(analysis: &ScreenAnalysis, candidates: &[usize], target: ShotTarget) -> Result<Vec<LunaAction>> {
+    let z_order = |i: usize| analysis.elements[i].attributes.get(Z_ORDER_ATTRIBUTE)?.parse::<u32>().ok();
+    let windows: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&i| WINDOW_TYPES.contains(&analysis.elements[i].element_type.as_str()))
+        .collect();
+    let front = windows.iter().copied().filter_map(|i| Some((z_order(i)?, i))).min().map(|(_, i)| i);
+    let window = match (front, windows.as_slice()) {
+        (Some(window), _) | (None, &[window]) => window,
+        (None, []) => return Err(LunaError::NotFound("no window on screen to take a screenshot of".to_string()).into()),
+        (None, _) => {
+            let options = windows
+                .iter()
+                .map(|&i| {
+                    let element = &analysis.elements[i];
+                    let title = container_title(analysis, i).map_or_else(|| format!("untitled {}", element.element_type), str::to_string);
+                    (i, title, (element.bounds.x, element.bounds.y))
+                })
+                .collect();
+            return Err(Clarification::which("Screenshot", "window", options).into());
         }
+    };
+    Ok(vec![LunaAction::Screenshot { region: Some(analysis.elements[window].bounds.clone()), target }])
+}
 
-        None
+/// Parse "800,420", "800, 420", "(800 420)" or "x=800 y=420" into a coordinate pair
+fn parse_coordinates(text: &str) -> Option<(i32, i32)> {
+    let cleaned: String = text
+        .chars()
+        .map(|c| if c.is_ascii_digit() || c == '-' { c } else { ' ' })
+        .collect();
+    let numbers: Vec<i32> = cleaned
+        .split_whitespace()
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    // Anything besides the two numbers and separators means this is not a literal command
+    let leftover = text.replace(|c: char| c.is_ascii_digit() || "-,()xy=: ".contains(c), "");
+    match numbers.as_slice() {
+        [x, y] if leftover.is_empty() => Some((*x, *y)),
+        _ => None,
     }
 }
 
@@ -269,6 +1941,15 @@ impl VisionProcessor {
         }
     }
 
+    /// Create a vision processor with custom edge sensitivity and minimum element size
+    pub fn with_settings(edge_threshold: f32, min_element_size: u32) -> Self {
+        Self {
+            edge_threshold,
+            min_element_size,
+            ..Self::new()
+        }
+    }
+
     /// Detect UI elements in image using lightweight computer vision
     pub fn detect_elements(&mut self, image: &DynamicImage) -> Result<Vec<ElementDetection>> {
         let mut elements = Vec::new();
@@ -436,6 +2117,7 @@ impl VisionProcessor {
                 return Some(ElementDetection {
                     element_type: element_type.clone(),
                     bounds: rect.clone(),
+                    shape: None,
                     confidence,
                     text: None, // TODO: Implement simple OCR
                     attributes: self.extract_attributes(rect, element_type),
@@ -448,6 +2130,7 @@ impl VisionProcessor {
             Some(ElementDetection {
                 element_type: "element".to_string(),
                 bounds: rect.clone(),
+                shape: None,
                 confidence: 0.3,
                 text: None,
                 attributes: HashMap::new(),
@@ -506,7 +2189,7 @@ impl VisionProcessor {
         }
         
         // Penalize extreme sizes
-        if area < 100 || area > 50000 {
+        if !(100..=50000).contains(&area) {
             confidence -= 0.2;
         }
         
@@ -537,6 +2220,12 @@ impl VisionProcessor {
     }
 }
 
+impl ElementDetector for VisionProcessor {
+    fn detect_elements(&mut self, image: &DynamicImage) -> Result<Vec<ElementDetection>> {
+        VisionProcessor::detect_elements(self, image)
+    }
+}
+
 impl Default for AICoordinator {
     fn default() -> Self {
         Self::new()
@@ -550,3 +2239,744 @@ impl Default for VisionProcessor {
 }
 
 // Re-export for backward compatibility
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ClickStrategy;
+
+    fn element(element_type: &str, x: i32, y: i32) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(x, y, 80, 30),
+            shape: None,
+            confidence: 0.9,
+            text: None,
+            attributes: HashMap::new(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn analysis(elements: Vec<ScreenElement>) -> ScreenAnalysis {
+        ScreenAnalysis {
+            elements,
+            confidence: 0.9,
+            processing_time_ms: 0,
+            screen_size: (1920, 1080),
+            context: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_plan_click_unconstrained() {
+        let coordinator = AICoordinator::new();
+        let analysis = analysis(vec![element("button", 10, 10), element("button", 500, 500)]);
+
+        let actions = coordinator.plan_actions("click the button", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 50, y: 25 }]));
+    }
+
+    #[test]
+    fn test_click_lands_inside_an_irregular_mask() {
+        let coordinator = AICoordinator::new();
+        // An L-shaped button whose bounding-box center is a transparent corner
+        let mut button = labeled("button", 100, "Launch");
+        button.bounds = ElementBounds::new(100, 100, 60, 60);
+        button.shape = Some(Polygon::new(vec![
+            Point::new(100.0, 100.0), Point::new(120.0, 100.0), Point::new(120.0, 140.0),
+            Point::new(160.0, 140.0), Point::new(160.0, 160.0), Point::new(100.0, 160.0),
+        ]));
+        assert!(!button.shape.as_ref().unwrap().contains_point(&Point::new(130.0, 130.0)));
+
+        let actions = coordinator.plan_actions("click Launch", &analysis(vec![button.clone()])).unwrap();
+        let [LunaAction::Click { x, y }] = actions[..] else {
+            panic!("unexpected plan {:?}", actions);
+        };
+        assert!(button.shape.as_ref().unwrap().signed_distance(&Point::new(x as f64, y as f64)) >= 5.0, "({}, {})", x, y);
+        assert_eq!(button.click_target().1, ClickStrategy::Mask);
+
+        // A sliver too thin to click inside falls back to the bounds
+        button.shape = Some(Polygon::new(vec![Point::new(100.0, 100.0), Point::new(160.0, 100.0), Point::new(160.0, 101.0)]));
+        assert_eq!(button.click_target(), ((130, 130), ClickStrategy::Bounds));
+    }
+
+    #[test]
+    fn test_plan_click_respects_region_constraint() {
+        let coordinator = AICoordinator::new();
+        let analysis = analysis(vec![element("button", 10, 10), element("button", 500, 500)]);
+        let options = ExecuteOptions::default().with_region(ElementBounds::new(400, 400, 300, 300));
+
+        let actions = coordinator.plan_actions_with_options("click the button", &analysis, &options).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 540, y: 515 }]));
+
+        // Nothing inside the region means nothing to click
+        let empty_region = ExecuteOptions::default().with_region(ElementBounds::new(1000, 0, 100, 100));
+        let err = coordinator.plan_actions_with_options("click the button", &analysis, &empty_region).unwrap_err();
+        let no_match = err.downcast_ref::<LunaError>().and_then(LunaError::no_match).expect("an explained failure");
+        assert!(no_match.candidates.iter().all(|c| c.spatial_score == 0.0), "{:?}", no_match);
+    }
+
+    #[test]
+    fn test_direct_click_commands() {
+        let coordinator = AICoordinator::new();
+        for command in ["click at 800,420", "Click 800, 420", "click at (800 420)", "click x=800 y=420"] {
+            let actions = coordinator.plan_direct_actions(command).unwrap();
+            assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 800, y: 420 }]), "{}", command);
+        }
+
+        assert!(coordinator.plan_direct_actions("click the save button").is_none());
+        assert!(coordinator.plan_direct_actions("click at 800").is_none());
+        assert!(coordinator.plan_direct_actions("click item 3 at 800,420").is_none());
+    }
+
+    #[test]
+    fn test_direct_keyboard_commands() {
+        let coordinator = AICoordinator::new();
+
+        let actions = coordinator.plan_direct_actions("press Ctrl+S").unwrap();
+        match actions.as_slice() {
+            [LunaAction::KeyCombo { keys }] => assert_eq!(keys, &["ctrl", "s"]),
+            other => panic!("unexpected plan {:?}", other),
+        }
+        assert!(coordinator.plan_direct_actions("hit enter").is_some());
+        assert!(coordinator.plan_direct_actions("press the big red button").is_none());
+        assert!(coordinator.plan_direct_actions("pressure gauge").is_none());
+
+        let actions = coordinator.plan_direct_actions(r#"type "Hello World""#).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Type { text }] if text == "Hello World"));
+        assert!(coordinator.plan_direct_actions("type into the search box").is_none());
+
+        // Unquoted text needs no screen unless a field is named
+        let actions = coordinator.plan_direct_actions("type hello world").unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Type { text }] if text == "hello world"));
+        assert!(coordinator.plan_direct_actions("type hello into Email").is_none());
+        assert!(coordinator.plan_direct_actions("type I live in Paris").is_none());
+
+        // Keystroke sequences stay off the screen; a step that needs it sends all of it there
+        let actions = coordinator.plan_direct_actions("press ctrl+a, then type new title and press enter").unwrap();
+        assert!(
+            matches!(actions.as_slice(), [LunaAction::KeyCombo { .. }, LunaAction::Type { text }, LunaAction::KeyCombo { keys }]
+                if text == "new title" && keys == &["enter"]),
+            "{:?}",
+            actions
+        );
+        let actions = coordinator.plan_direct_actions(r#"type "salt and pepper" and press tab"#).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Type { text }, LunaAction::KeyCombo { .. }] if text == "salt and pepper"));
+        assert!(coordinator.plan_direct_actions("click Save and press enter").is_none());
+        assert!(coordinator.plan_direct_actions("press tab and type hello into Email").is_none());
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        let items = |names: &[&str], add| Some(Selection::Items { names: names.iter().map(|n| n.to_string()).collect(), add });
+        assert_eq!(parse_selection("select files report1 through report5"), Some(Selection::Range("report1".into(), "report5".into())));
+        assert_eq!(parse_selection("shift-click from the first row to \"last row\""), Some(Selection::Range("first row".into(), "last row".into())));
+        assert_eq!(parse_selection("select a.txt, b.txt and c.txt"), items(&["a.txt", "b.txt", "c.txt"], false));
+        assert_eq!(parse_selection("ctrl-click the inbox"), items(&["inbox"], true));
+        assert_eq!(parse_selection("select the settings tab"), None);
+        assert_eq!(parse_selection("shift-click save"), None);
+    }
+
+    #[test]
+    fn test_wait_commands() {
+        let coordinator = AICoordinator::new();
+        let plan = |command: &str| coordinator.plan_direct_actions(command).unwrap();
+
+        assert!(matches!(plan("wait for the spinner to disappear").as_slice(),
+            [LunaAction::WaitForTextGone { text, timeout_ms: WAIT_TIMEOUT_MS }] if text == "spinner"));
+        assert!(matches!(plan("wait until \"Loading…\" is gone").as_slice(),
+            [LunaAction::WaitForTextGone { text, .. }] if text == "Loading…"));
+        assert!(matches!(plan("Wait for Saved to appear").as_slice(),
+            [LunaAction::WaitForElement { query, .. }] if query == "Saved"));
+        assert!(matches!(plan("wait for button:OK").as_slice(),
+            [LunaAction::WaitForElement { query, .. }] if query == "button:OK"));
+        // Lowercasing "İ" changes its length; slicing the original must still line up
+        assert!(matches!(plan("wait for İSTANBUL to appear").as_slice(),
+            [LunaAction::WaitForElement { query, .. }] if query == "İSTANBUL"));
+        assert!(matches!(plan("wait until the screen is idle").as_slice(),
+            [LunaAction::WaitForScreenIdle { quiet_ms: IDLE_QUIET_MS, .. }]));
+        assert!(matches!(plan("wait 1.5 seconds").as_slice(), [LunaAction::Wait { milliseconds: 1500 }]));
+        assert!(coordinator.plan_direct_actions("wait until tomorrow").is_none());
+
+        // A wait after a click that needs the screen is planned with it
+        assert!(coordinator.plan_direct_actions("after clicking Submit, wait for the spinner to disappear").is_none());
+        assert!(matches!(plan("click at 10,20, then wait until idle").as_slice(),
+            [LunaAction::Click { x: 10, y: 20 }, LunaAction::WaitForScreenIdle { .. }]));
+        let form = analysis(vec![labeled("button", 100, "Submit"), labeled("button", 300, "Cancel")]);
+        let actions = coordinator.plan_actions("After clicking Submit, wait for the spinner to disappear", &form).unwrap();
+        assert!(matches!(actions.as_slice(),
+            [LunaAction::Click { x: 140, y: 25 }, LunaAction::WaitForTextGone { text, .. }] if text == "spinner"), "{:?}", actions);
+    }
+
+    #[test]
+    fn test_quantities_become_action_parameters() {
+        let mut coordinator = AICoordinator::new();
+        coordinator.set_quantities(&InputConfig { page_pixels: 800, ..InputConfig::default() });
+        let plan = |command: &str| coordinator.plan_direct_actions(command).unwrap();
+        assert!(matches!(plan("wait two minutes").as_slice(), [LunaAction::Wait { milliseconds: 120_000 }]));
+
+        let scroll = |command: &str| match coordinator.plan_actions(command, &analysis(Vec::new())).unwrap().as_slice() {
+            [LunaAction::Scroll { direction, amount }] => (direction.clone(), *amount),
+            other => panic!("{}: {:?}", command, other),
+        };
+        assert_eq!(scroll("scroll down half a page"), ("down".to_string(), 10));
+        assert_eq!(scroll("scroll up 2 pages"), ("up".to_string(), 40));
+        assert_eq!(scroll("scroll to the bottom"), ("down".to_string(), quantity::SCROLL_TO_END_NOTCHES));
+        assert_eq!(scroll("scroll up"), ("up".to_string(), 3));
+
+        let chords = |command: &str| -> Vec<String> {
+            plan(command)
+                .iter()
+                .map(|action| match action {
+                    LunaAction::KeyCombo { keys } => keys.join("+"),
+                    other => panic!("{}: {:?}", command, other),
+                })
+                .collect()
+        };
+        assert_eq!(chords("set zoom to 150%"), ["ctrl+0", "ctrl+=", "ctrl+=", "ctrl+="]);
+        assert_eq!(chords("zoom to 80 percent"), ["ctrl+0", "ctrl+-", "ctrl+-"]);
+    }
+
+    #[test]
+    fn test_hold_commands() {
+        let held = |keys: &[&str], then: Held| Some((keys.iter().map(|k| k.to_string()).collect::<Vec<_>>(), then));
+        let inner = |command: &str| Held::While(command.to_string());
+        assert_eq!(split_hold("While holding Shift, click report3"), held(&["shift"], inner("click report3")));
+        assert_eq!(split_hold("hold ctrl while clicking report1"), held(&["ctrl"], inner("click report1")));
+        assert_eq!(split_hold("hold ctrl and shift and press tab"), held(&["ctrl", "shift"], inner("press tab")));
+        assert_eq!(split_hold("click Save while holding the alt key"), held(&["alt"], inner("click Save")));
+        assert_eq!(split_hold("hold down W for 2 seconds"), held(&["w"], Held::For(2000)));
+        assert_eq!(split_hold("hold music"), None);
+        assert_eq!(split_hold("click Hold"), None);
+
+        let coordinator = AICoordinator::new();
+        let actions = coordinator.plan_direct_actions("while holding ctrl+shift click at 10,20").unwrap();
+        assert!(matches!(actions.as_slice(), [
+            LunaAction::KeyDown { key: a }, LunaAction::KeyDown { key: b }, LunaAction::Click { x: 10, y: 20 },
+            LunaAction::KeyUp { key: c }, LunaAction::KeyUp { key: d },
+        ] if (a, b, c, d) == (&"ctrl".into(), &"shift".into(), &"shift".into(), &"ctrl".into())), "{:?}", actions);
+
+        let form = analysis(vec![labeled("button", 100, "Submit"), labeled("button", 300, "Cancel")]);
+        let actions = coordinator.plan_actions("hold shift while clicking Cancel", &form).unwrap();
+        assert!(matches!(actions.as_slice(),
+            [LunaAction::KeyDown { .. }, LunaAction::Click { x: 340, y: 25 }, LunaAction::KeyUp { .. }]), "{:?}", actions);
+    }
+
+    #[test]
+    fn test_typed_dates_and_amounts_follow_the_locale() {
+        let mut coordinator = AICoordinator::new();
+        coordinator.set_locale(Locale::parse("de-DE").unwrap());
+        let typed = |coordinator: &AICoordinator, command: &str| match coordinator.plan_direct_actions(command).as_deref() {
+            Some([LunaAction::Type { text }]) => text.clone(),
+            other => panic!("unexpected plan {:?}", other),
+        };
+
+        let today = Date::today();
+        assert_eq!(typed(&coordinator, "type today's date"), format!("{:02}.{:02}.{}", today.day, today.month, today.year));
+        assert_eq!(typed(&coordinator, "type today's date in ISO format"), format!("{}-{:02}-{:02}", today.year, today.month, today.day));
+        assert_eq!(typed(&coordinator, "type 1,234.56 formatted for German locale"), "1.234,56");
+        assert_eq!(typed(&coordinator, "type 1234.5 formatted"), "1.234,5");
+        assert_eq!(typed(&coordinator, "type 1.234,56 in en-US format"), "1,234.56");
+
+        // Without an explicit request, amounts are typed as written
+        assert_eq!(typed(&coordinator, "type 1234.5"), "1234.5");
+        assert_eq!(typed(&coordinator, "type \"1234.5\""), "1234.5");
+    }
+
+    #[test]
+    fn test_screenshot_and_paste_image_commands() {
+        let coordinator = AICoordinator::new();
+        let plan = |command: &str| coordinator.plan_direct_actions(command).unwrap();
+
+        assert!(matches!(plan("Take a screenshot").as_slice(), [LunaAction::Screenshot { region: None, target: ShotTarget::Clipboard }]));
+        let actions = plan("screenshot region 10, 20, 300, 200 to Shots/Before.png");
+        let [LunaAction::Screenshot { region: Some(region), target: ShotTarget::File(path) }] = actions.as_slice() else {
+            panic!("unexpected plan {:?}", actions);
+        };
+        assert_eq!(region, &ElementBounds::new(10, 20, 300, 200));
+        assert_eq!(path, &PathBuf::from("Shots/Before.png"));
+        assert!(matches!(plan(r#"paste image "C:\Diagrams\Flow.PNG""#).as_slice(),
+            [LunaAction::PasteImage { path }] if path == &PathBuf::from("C:\\Diagrams\\Flow.PNG")));
+        assert!(coordinator.plan_direct_actions("screenshot region 10 20").is_none());
+        assert!(coordinator.plan_direct_actions("screenshots folder").is_none());
+
+        // "This window" is the frontmost one, which takes analysis to find
+        assert!(coordinator.plan_direct_actions("screenshot this window").is_none());
+        let window = |x, z: u32| {
+            let mut window = ScreenElement { bounds: ElementBounds::new(x, 0, 400, 300), ..element("window", x, 0) };
+            window.attributes.insert(Z_ORDER_ATTRIBUTE.to_string(), z.to_string());
+            window
+        };
+        let windows = analysis(vec![window(0, 1), window(500, 0)]);
+        let actions = coordinator.plan_actions("screenshot this window to front.png", &windows).unwrap();
+        assert!(matches!(actions.as_slice(),
+            [LunaAction::Screenshot { region: Some(ElementBounds { x: 500, .. }), target: ShotTarget::File(_) }]), "{:?}", actions);
+
+        let unstacked = analysis(vec![element("window", 0, 0), element("window", 500, 0)]);
+        let err = coordinator.plan_actions("screenshot the window", &unstacked).unwrap_err();
+        assert!(err.downcast_ref::<Clarification>().is_some(), "{}", err);
+    }
+
+    #[test]
+    fn test_plans_refused_when_input_unavailable() {
+        use crate::core::capabilities::CapabilityStatus;
+
+        let mut coordinator = AICoordinator::new();
+        let mut capabilities = Capabilities::probe(&Default::default());
+        capabilities.mouse_input = CapabilityStatus::Available;
+        capabilities.keyboard_input = CapabilityStatus::Unavailable("no uinput".to_string());
+        coordinator.set_capabilities(capabilities);
+
+        let analysis = analysis(vec![element("button", 10, 10)]);
+        assert!(coordinator.plan_actions("click the button", &analysis).is_ok());
+        let err = coordinator.plan_actions(r#"type "hello""#, &analysis).unwrap_err();
+        assert!(err.to_string().contains("keyboard input is unavailable"), "{}", err);
+    }
+
+    fn labeled(element_type: &str, x: i32, text: &str) -> ScreenElement {
+        let mut element = element(element_type, x, 10);
+        element.text = Some(text.to_string());
+        element
+    }
+
+    #[test]
+    fn test_click_ranks_targets_by_text_similarity() {
+        let coordinator = AICoordinator::new();
+        let analysis = analysis(vec![labeled("button", 10, "Cancel"), labeled("button", 200, "OK"), labeled("button", 400, "Okay then")]);
+
+        let ranked = coordinator.rank_text_targets("click okay", &analysis.elements);
+        assert_eq!(ranked[0].element.text.as_deref(), Some("OK"));
+        assert!(ranked[0].reasoning.contains("abbreviation"));
+
+        let actions = coordinator.plan_actions("click the okay button", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 240, y: 25 }]));
+
+        // Misspelled command still finds the OCR'd label
+        let actions = coordinator.plan_actions("click cancle", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 50, y: 25 }]));
+    }
+
+    #[test]
+    fn test_unlabeled_fields_are_found_by_the_label_beside_them() {
+        let at = |element: ScreenElement, x, y, width, height| ScreenElement { bounds: ElementBounds::new(x, y, width, height), ..element };
+        let mut analysis = analysis(vec![
+            at(labeled("label", 0, "Name"), 20, 106, 60, 20),
+            at(element("textfield", 0, 0), 100, 100, 300, 32),
+            at(labeled("label", 0, "Email"), 20, 156, 60, 20),
+            at(element("textfield", 0, 0), 100, 150, 300, 32),
+            at(labeled("button", 0, "Save"), 100, 260, 100, 36),
+        ]);
+        analysis.link_labels();
+        assert_eq!(analysis.elements[3].label(), Some("Email"));
+        let coordinator = AICoordinator::new();
+
+        let actions = coordinator.plan_actions("type bob@example.com into Email", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 250, y: 166 }, LunaAction::Type { text }] if text == "bob@example.com"), "{:?}", actions);
+        let actions = coordinator.plan_actions(r#"enter "Bob" in the name field"#, &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 250, y: 116 }, LunaAction::Type { text }] if text == "Bob"), "{:?}", actions);
+
+        // The field, not its label, is the target; without a field "in" is just text
+        let actions = coordinator.plan_actions("click email", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 250, y: 166 }]), "{:?}", actions);
+        let err = coordinator.plan_actions("type hello into Phone", &analysis).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::NoMatchingElement(_))), "{}", err);
+        let actions = coordinator.plan_actions("type I live in Paris", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Type { text }] if text == "I live in Paris"), "{:?}", actions);
+    }
+
+    #[test]
+    fn test_the_caret_decides_where_text_and_images_go() {
+        use crate::vision::caret::{Caret, CaretSource};
+        let at = |element: ScreenElement, x, y, width, height| ScreenElement { bounds: ElementBounds::new(x, y, width, height), ..element };
+        let mut analysis = analysis(vec![
+            at(labeled("label", 0, "Name"), 20, 106, 60, 20),
+            at(element("textfield", 0, 0), 100, 100, 300, 32),
+            at(labeled("label", 0, "Notes"), 20, 156, 60, 20),
+            at(element("textbox", 0, 0), 100, 150, 300, 200),
+        ]);
+        analysis.link_labels();
+        let coordinator = AICoordinator::new();
+
+        let err = coordinator.plan_actions("type hello at the cursor", &analysis).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::Vision(_))), "{}", err);
+
+        // The caret sits in Notes: typing there keeps it, typing elsewhere clicks
+        analysis.context.caret = Some(Caret { x: 180, y: 200, height: 16, source: CaretSource::Blink });
+        let actions = coordinator.plan_actions("type hello into Notes", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Type { text }] if text == "hello"), "{:?}", actions);
+        let actions = coordinator.plan_actions("type Bob into Name", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 250, y: 116 }, LunaAction::Type { .. }]), "{:?}", actions);
+        let actions = coordinator.plan_actions(r#"type "see below" at the cursor"#, &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Type { text }] if text == "see below"), "{:?}", actions);
+
+        let actions = coordinator.plan_actions("paste image C:\\Shots\\plot.png at the caret", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::PasteImage { path }] if path == &PathBuf::from("C:\\Shots\\plot.png")), "{:?}", actions);
+        let actions = coordinator.plan_actions("paste image plot.png into Name", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 250, y: 116 }, LunaAction::PasteImage { .. }]), "{:?}", actions);
+        let actions = coordinator.plan_actions("paste image plot.png into Notes", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::PasteImage { path }] if path == &PathBuf::from("plot.png")), "{:?}", actions);
+
+        // Neither can be planned without the screen
+        assert!(coordinator.plan_direct_actions(r#"type "x" at the cursor"#).is_none());
+        assert!(coordinator.plan_direct_actions("paste image plot.png into Notes").is_none());
+        assert!(needs_caret("Enter 42 in quantity") && !needs_caret("click Save"));
+    }
+
+    #[test]
+    fn test_equally_named_targets_prefer_reliable_history() {
+        let mut flaky = labeled("button", 10, "Save");
+        flaky.attributes.insert(crate::core::RELIABILITY_ATTRIBUTE.to_string(), "0.20".to_string());
+        let mut proven = labeled("button", 200, "Save");
+        proven.attributes.insert(crate::core::RELIABILITY_ATTRIBUTE.to_string(), "0.90".to_string());
+        let unknown = labeled("button", 400, "Save");
+
+        let analysis = analysis(vec![flaky, unknown, proven]);
+        let ranked = AICoordinator::new().rank_text_targets("click save", &analysis.elements);
+        let order: Vec<i32> = ranked.iter().map(|t| t.element.bounds.x).collect();
+        assert_eq!(order, vec![200, 400, 10]);
+    }
+
+    #[test]
+    fn test_click_lands_inside_element_shape() {
+        use crate::utils::geometry::Point;
+
+        // An L-shaped mask whose bounding-box center (40, 40) is outside the shape
+        let mut element = labeled("button", 0, "Deploy");
+        element.bounds = ElementBounds::new(0, 0, 80, 80);
+        element.shape = Some(Polygon::new(vec![
+            Point::new(0.0, 0.0), Point::new(80.0, 0.0), Point::new(80.0, 20.0),
+            Point::new(20.0, 20.0), Point::new(20.0, 80.0), Point::new(0.0, 80.0),
+        ]));
+        let shape = element.shape.clone().unwrap();
+
+        let actions = AICoordinator::new().plan_actions("click deploy", &analysis(vec![element])).unwrap();
+        let [LunaAction::Click { x, y }] = actions.as_slice() else {
+            panic!("expected one click, got {:?}", actions);
+        };
+        assert!(shape.contains_point(&Point::new(*x as f64, *y as f64)), "({}, {}) is outside the shape", x, y);
+    }
+
+    #[test]
+    fn test_scope_phrase_restricts_search_to_container() {
+        let coordinator = AICoordinator::new();
+        let window = |x| ScreenElement { bounds: ElementBounds::new(x, 0, 400, 300), ..element("window", x, 0) };
+        let title = |x, text| ScreenElement { bounds: ElementBounds::new(x + 10, 5, 200, 20), ..labeled("label", 0, text) };
+        let apply = |x| ScreenElement { bounds: ElementBounds::new(x + 300, 250, 80, 30), ..labeled("button", 0, "Apply") };
+        let mut analysis = analysis(vec![
+            window(0), title(0, "Display"), apply(0),
+            window(500), title(500, "Settings"), apply(500),
+        ]);
+        analysis.link_hierarchy();
+        assert_eq!(analysis.elements[5].parent, Some(3));
+        assert_eq!(analysis.subtree(3), [3, 4, 5]);
+
+        for command in ["In the settings dialog, click Apply", "click apply in the Settings window"] {
+            let actions = coordinator.plan_actions(command, &analysis).unwrap();
+            assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 840, y: 265 }]), "{}", command);
+        }
+        // Without a network panel both Apply buttons fit, so the planner asks
+        let unscoped = coordinator.plan_actions("in the network panel, click apply", &analysis).unwrap_err();
+        assert!(unscoped.downcast_ref::<Clarification>().is_some(), "{}", unscoped);
+    }
+
+    #[test]
+    fn test_undetected_scope_falls_back_to_whole_screen() {
+        let coordinator = AICoordinator::new();
+        let analysis = analysis(vec![labeled("button", 40, "Save"), labeled("button", 200, "Open")]);
+        for command in ["in the toolbar, click save", "click save in the toolbar"] {
+            let actions = coordinator.plan_actions(command, &analysis).unwrap();
+            assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 80, y: 25 }]), "{}: {:?}", command, actions);
+        }
+    }
+
+    #[test]
+    fn test_open_modal_dialog_comes_first() {
+        let at = |x, y, element: ScreenElement| ScreenElement { bounds: ElementBounds::new(x, y, 80, 30), ..element };
+        let mut analysis = analysis(vec![
+            labeled("button", 0, "Save"),
+            labeled("button", 100, "OK"),
+            ScreenElement { bounds: ElementBounds::new(300, 200, 300, 150), ..element("dialog", 0, 0) },
+            at(320, 300, labeled("button", 0, "OK")),
+            at(450, 300, labeled("button", 0, "Cancel")),
+        ]);
+        analysis.link_hierarchy();
+        analysis.mark_modal(Some(2));
+        assert_eq!(analysis.active_modal(), Some(2));
+        assert_eq!(analysis.elements[3].attributes.get(crate::core::MODAL_ATTRIBUTE).map(String::as_str), Some("true"));
+
+        // The dialog's OK wins over the one behind it; Save is only behind it
+        let prefer = AICoordinator::new();
+        assert!(matches!(prefer.plan_actions("click OK", &analysis).unwrap().as_slice(), [LunaAction::Click { x: 360, y: 315 }]));
+        assert!(matches!(prefer.plan_actions("click Save", &analysis).unwrap().as_slice(), [LunaAction::Click { x: 40, y: 25 }]));
+        let require = AICoordinator::from_config(&VisionConfig { modal_targets: ModalTargets::Require, ..VisionConfig::default() });
+        let blocked = require.plan_actions("click Save", &analysis).unwrap_err();
+        assert_eq!(blocked.downcast_ref::<LunaError>().map(LunaError::kind), Some("not_found"));
+
+        // Without the dialog both OK buttons are candidates
+        analysis.mark_modal(None);
+        assert!(prefer.plan_actions("click OK", &analysis).is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_close_asks_which_window() {
+        let coordinator = AICoordinator::new();
+        let window = |x| ScreenElement { bounds: ElementBounds::new(x, 0, 400, 300), ..element("window", x, 0) };
+        let title = |x, text| ScreenElement { bounds: ElementBounds::new(x + 10, 5, 200, 20), ..labeled("label", 0, text) };
+        let close = |x| ScreenElement { bounds: ElementBounds::new(x + 370, 5, 20, 20), ..labeled("button", 0, "×") };
+        let mut analysis = analysis(vec![
+            window(0), title(0, "Google Chrome"), close(0),
+            window(500), title(500, "Notepad"), close(500),
+        ]);
+        analysis.link_hierarchy();
+
+        let error = coordinator.plan_actions("close it", &analysis).unwrap_err();
+        let clarification = error.downcast_ref::<Clarification>().expect("a question");
+        assert_eq!(clarification.question, "Close which window: Google Chrome or Notepad?");
+
+        let chosen = clarification.resolve("notepad").unwrap().element;
+        let actions = coordinator.plan_choice("close it", &analysis, chosen).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 880, y: 15 }]));
+
+        // Naming the window needs no question
+        let actions = coordinator.plan_actions("close the chrome window", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 380, y: 15 }]));
+    }
+
+    #[test]
+    fn test_target_behind_another_window_is_raised_first() {
+        let coordinator = AICoordinator::new();
+        let window = |x, y, z: &str| {
+            let mut window = ScreenElement { bounds: ElementBounds::new(x, y, 400, 300), ..element("window", x, y) };
+            window.attributes.insert(crate::core::Z_ORDER_ATTRIBUTE.to_string(), z.to_string());
+            window
+        };
+        let button = |x, y, text| ScreenElement { bounds: ElementBounds::new(x, y, 80, 30), ..labeled("button", 0, text) };
+        let mut analysis = analysis(vec![
+            window(0, 0, "1"), button(300, 250, "Export"), button(20, 250, "Print"),
+            window(200, 100, "0"), button(220, 350, "Cancel"),
+        ]);
+        analysis.link_hierarchy();
+        analysis.mark_occlusion();
+        assert_eq!(analysis.elements[1].occlusion(), 1.0);
+        assert_eq!(analysis.elements[2].occlusion(), 0.0);
+        assert_eq!(analysis.elements[3].occlusion(), 0.0);
+
+        // Only part of the rear window shows; its title bar is grabbed to raise it
+        analysis.elements.push(button(180, 90, "Export"));
+        analysis.link_hierarchy();
+        analysis.mark_occlusion();
+        let actions = coordinator.plan_actions("click export", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [
+            LunaAction::Click { y: 2..=40, .. },
+            LunaAction::Wait { .. },
+            LunaAction::Click { x: 220, y: 105 },
+        ]), "{:?}", actions);
+
+        // Visible targets are clicked directly
+        let actions = coordinator.plan_actions("click print", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 60, y: 265 }]), "{:?}", actions);
+    }
+
+    #[test]
+    fn test_clipped_target_is_scrolled_into_view_first() {
+        let coordinator = AICoordinator::new();
+        let item = |y, text| ScreenElement { bounds: ElementBounds::new(110, y, 280, 30), ..labeled("listitem", 0, text) };
+        let mut analysis = analysis(vec![
+            ScreenElement { bounds: ElementBounds::new(0, 0, 800, 600), ..element("window", 0, 0) },
+            ScreenElement { bounds: ElementBounds::new(100, 100, 300, 200), ..element("list", 0, 0) },
+            item(110, "Inbox"),
+            item(276, "Archive"),
+            item(290, "Settings"),
+            item(295, "Save"),
+            labeled("button", 500, "Save"),
+        ]);
+        analysis.elements[6].bounds.y = 400;
+        analysis.link_hierarchy();
+        analysis.mark_visibility();
+        assert!(!analysis.elements[2].attributes.contains_key(crate::core::VISIBLE_ATTRIBUTE));
+        assert_eq!(analysis.elements[3].visibility(), 0.8);
+        assert_eq!(analysis.elements[4].visibility(), 0.33);
+
+        // Mostly visible: clicked where it is
+        let actions = coordinator.plan_actions("click archive", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 250, y: 291 }]), "{:?}", actions);
+
+        // A third shows: scroll the list under the pointer one notch, click where it lands
+        let actions = coordinator.plan_actions("click settings", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [
+            LunaAction::Hover { x: 250, y: 295 },
+            LunaAction::Scroll { direction, amount: 1 },
+            LunaAction::Wait { .. },
+            LunaAction::Click { x: 250, y: 265 },
+        ] if direction == "down"), "{:?}", actions);
+
+        // Of two equally named targets the visible one wins without a question
+        let actions = coordinator.plan_actions("click save", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 540, y: 415 }]), "{:?}", actions);
+    }
+
+    fn control(element_type: &str, x: i32, attributes: &[(&str, &str)]) -> ScreenElement {
+        let mut element = element(element_type, x, 10);
+        element.attributes = attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        element
+    }
+
+    #[test]
+    fn test_click_by_color_and_shape() {
+        struct Nothing;
+        impl ElementDetector for Nothing {
+            fn detect_elements(&mut self, _image: &DynamicImage) -> Result<Vec<ElementDetection>> {
+                Ok(Vec::new())
+            }
+        }
+
+        // Red and blue discs and a red square on a light background
+        let mut frame = RgbImage::from_pixel(300, 120, image::Rgb([245, 245, 245]));
+        for (x, y, pixel) in frame.enumerate_pixels_mut() {
+            let disc = |cx: f64| (x as f64 - cx).powi(2) + (y as f64 - 60.0).powi(2) <= 16.0 * 16.0;
+            if disc(50.0) {
+                *pixel = image::Rgb([210, 40, 40]);
+            } else if disc(150.0) {
+                *pixel = image::Rgb([40, 90, 220]);
+            } else if (230..262).contains(&x) && (44..76).contains(&y) {
+                *pixel = image::Rgb([200, 30, 30]);
+            }
+        }
+        let mut coordinator = AICoordinator::new();
+        let analysis = coordinator.analyze_with(&DynamicImage::ImageRgb8(frame), &mut Nothing).unwrap();
+
+        let click = |command: &str| coordinator.plan_actions(command, &analysis).map(|actions| format!("{:?}", actions));
+        assert_eq!(click("click the red circle").unwrap(), "[Click { x: 50, y: 60 }]");
+        assert_eq!(click("click the blue dot").unwrap(), "[Click { x: 150, y: 60 }]");
+        assert_eq!(click("click the red square").unwrap(), "[Click { x: 246, y: 60 }]");
+
+        let question = click("click the red one").unwrap_err().downcast::<Clarification>().unwrap();
+        assert_eq!(question.options.len(), 2);
+        // Nothing green on screen: matched by text like any other click, which finds nothing either
+        let err = click("click the green circle").unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::NoMatchingElement(_))), "{}", err);
+    }
+
+    #[test]
+    fn test_thorough_analysis_finds_faint_elements() {
+        // A low-contrast button the default edge threshold does not see
+        let mut frame = RgbImage::from_pixel(400, 200, image::Rgb([200, 200, 200]));
+        for (x, y, pixel) in frame.enumerate_pixels_mut() {
+            if (100..220).contains(&x) && (80..120).contains(&y) {
+                *pixel = image::Rgb([206, 206, 206]);
+            }
+        }
+        let image = DynamicImage::ImageRgb8(frame);
+        let mut coordinator = AICoordinator::new();
+        let thresholds = coordinator.thresholds();
+
+        let fast = coordinator.analyze_screen(&image).unwrap();
+        let thorough = coordinator.analyze_thorough(&image, &EscalationConfig::default()).unwrap();
+        assert!(thorough.elements.len() > fast.elements.len(), "{} -> {}", fast.elements.len(), thorough.elements.len());
+        assert_eq!(coordinator.thresholds(), thresholds, "thresholds are restored");
+    }
+
+    #[test]
+    fn test_toggle_already_in_requested_state() {
+        let coordinator = AICoordinator::new();
+        let analysis = analysis(vec![control("toggle", 10, &[("state", "on")])]);
+
+        let actions = coordinator.plan_actions("turn on wifi", &analysis).unwrap();
+        assert!(actions.is_empty());
+
+        let actions = coordinator.plan_actions("turn off wifi", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 50, y: 25 }]));
+    }
+
+    #[test]
+    fn test_checkbox_and_slider_plans() {
+        let coordinator = AICoordinator::new();
+        let analysis = analysis(vec![
+            control("checkbox", 10, &[("checked", "false")]),
+            control("slider", 200, &[("value", "0.10")]),
+        ]);
+
+        let actions = coordinator.plan_actions("check remember me", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 50, .. }]));
+
+        // 50% of an 80px track starting at x=200
+        let actions = coordinator.plan_actions("set the volume slider to 50%", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 240, y: 25 }]), "{:?}", actions);
+    }
+
+    #[test]
+    fn test_unnamed_control_among_several_is_not_guessed() {
+        let coordinator = AICoordinator::new();
+        let mut analysis = analysis(vec![
+            control("checkbox", 10, &[("checked", "false")]),
+            control("checkbox", 200, &[("checked", "false")]),
+            labeled("textfield", 400, "Coupon"),
+            control("slider", 600, &[("value", "0.10")]),
+        ]);
+
+        let err = coordinator.plan_actions("check remember me", &analysis).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::NotFound(_))), "{}", err);
+        analysis.elements[1].text = Some("Remember me".to_string());
+        let actions = coordinator.plan_actions("check remember me", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 240, .. }]), "{:?}", actions);
+
+        // A percentage in typed text doesn't make it a slider command
+        let actions = coordinator.plan_actions("type 50% off in the coupon field", &analysis).unwrap();
+        assert!(matches!(actions.as_slice(), [LunaAction::Click { x: 440, .. }, LunaAction::Type { text }] if text == "50% off"), "{:?}", actions);
+    }
+
+    #[test]
+    fn test_reconfigure_reports_live_and_reloaded_settings() {
+        let mut coordinator = AICoordinator::new();
+
+        let report = coordinator.reconfigure(&PartialVisionConfig {
+            confidence_threshold: Some(0.8),
+            max_elements: Some(50), // unchanged
+            edge_threshold: Some(45.0),
+            ..PartialVisionConfig::default()
+        });
+        assert_eq!(report.applied, vec!["confidence_threshold"]);
+        assert_eq!(report.reloaded, vec!["edge_threshold"]);
+        assert!(coordinator.has_pending_reload());
+
+        let image = DynamicImage::new_rgb8(64, 64);
+        coordinator.analyze_screen(&image).unwrap();
+        assert!(!coordinator.has_pending_reload());
+        assert_eq!(coordinator.detector.edge_threshold, 45.0);
+
+        let full = PartialVisionConfig::from(&VisionConfig { confidence_threshold: 0.8, edge_threshold: 45.0, ..VisionConfig::default() });
+        assert!(coordinator.reconfigure(&full).is_empty());
+    }
+
+    #[test]
+    fn test_refilter_applies_new_thresholds_without_detection() {
+        let mut coordinator = AICoordinator::new();
+        let image = DynamicImage::new_rgb8(400, 100);
+        assert!(coordinator.refilter(&image).is_none());
+
+        let detection = |x: i32, confidence: f32, text: Option<&str>| ElementDetection {
+            element_type: "button".to_string(),
+            bounds: ElementBounds::new(x, 10, 80, 30),
+            shape: None,
+            confidence,
+            text: text.map(String::from),
+            attributes: HashMap::new(),
+        };
+        coordinator.last_candidates = Some(vec![
+            detection(0, 0.9, None),
+            detection(100, 0.5, None),
+            detection(200, 0.4, Some("Save")),
+            detection(300, 0.2, None),
+        ]);
+        assert_eq!(coordinator.refilter(&image).unwrap().elements.len(), 1);
+
+        coordinator.set_thresholds(ConfidenceThresholds { detection: 0.6, text: Some(0.3) });
+        let refiltered = coordinator.refilter(&image).unwrap();
+        let texts: Vec<_> = refiltered.elements.iter().map(|e| e.text.as_deref()).collect();
+        assert_eq!(texts, [None, Some("Save")]);
+
+        let sweep = coordinator.threshold_sweep(&[0.1, 0.45, 0.95]);
+        assert_eq!(sweep, [(0.1, 4), (0.45, 3), (0.95, 1)]);
+    }
+}
@@ -0,0 +1,111 @@
+// Raw detector output for research tooling
+// An analysis only carries the elements that passed the confidence
+// thresholds. Tooling built on top (threshold studies, detector comparisons,
+// dataset mining) needs what was thrown away too: every candidate with its
+// confidence, and whatever extra an inference server reports beyond boxes
+// (proposal logits, text-image similarities, per-character OCR confidences,
+// mask scores) in the optional `raw` field of its `/v1/detect` response.
+// Both are capped so a chatty model cannot blow up a result.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{ConfidenceThresholds, ElementDetection};
+use crate::core::config::RawOutputsConfig;
+
+/// Unfiltered output of the last analysis
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RawOutputs {
+    /// Every candidate, most confident first, up to `max_candidates`
+    pub candidates: Vec<RawCandidate>,
+    /// Candidates left out by the `max_candidates` cap
+    pub candidates_omitted: usize,
+    /// The inference server's `raw` payload, as sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<Value>,
+    /// Serialized size of a payload dropped for exceeding `max_bytes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_omitted_bytes: Option<usize>,
+}
+
+/// One detection before thresholding
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RawCandidate {
+    pub element_type: String,
+    /// `(x, y, width, height)`
+    pub bounds: (i32, i32, i32, i32),
+    pub confidence: f32,
+    pub text: Option<String>,
+    /// Whether it passed the thresholds (the `max_elements` cap aside)
+    pub kept: bool,
+}
+
+impl RawOutputs {
+    /// `candidates` sorted most confident first, as `finish_analysis` leaves them
+    pub fn collect(
+        candidates: &[ElementDetection],
+        thresholds: &ConfidenceThresholds,
+        model: Option<&Value>,
+        limits: &RawOutputsConfig,
+    ) -> Self {
+        let kept = candidates.len().min(limits.max_candidates);
+        let size = model.map(|value| serde_json::to_vec(value).map_or(usize::MAX, |bytes| bytes.len()));
+        let (model, model_omitted_bytes) = match size {
+            Some(size) if size > limits.max_bytes => (None, Some(size)),
+            _ => (model.cloned(), None),
+        };
+        Self {
+            candidates: candidates[..kept]
+                .iter()
+                .map(|c| RawCandidate {
+                    element_type: c.element_type.clone(),
+                    bounds: (c.bounds.x, c.bounds.y, c.bounds.width, c.bounds.height),
+                    confidence: c.confidence,
+                    text: c.text.clone(),
+                    kept: thresholds.keeps(c.confidence, c.text.is_some()),
+                })
+                .collect(),
+            candidates_omitted: candidates.len() - kept,
+            model,
+            model_omitted_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ElementBounds;
+
+    fn candidate(confidence: f32) -> ElementDetection {
+        ElementDetection {
+            element_type: "button".to_string(),
+            bounds: ElementBounds::new(10, 20, 80, 24),
+            shape: None,
+            confidence,
+            text: None,
+            attributes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_candidates_and_payload_are_capped() {
+        let candidates = vec![candidate(0.9), candidate(0.5), candidate(0.2)];
+        let thresholds = ConfidenceThresholds { detection: 0.4, text: None };
+        let limits = RawOutputsConfig { include: true, max_candidates: 2, max_bytes: 64 };
+
+        let small = serde_json::json!({ "logits": [1.5, -0.25] });
+        let raw = RawOutputs::collect(&candidates, &thresholds, Some(&small), &limits);
+        assert_eq!(raw.candidates.iter().map(|c| c.kept).collect::<Vec<_>>(), vec![true, true]);
+        assert_eq!(raw.candidates[0].bounds, (10, 20, 80, 24));
+        assert_eq!(raw.candidates_omitted, 1);
+        assert_eq!(raw.model, Some(small));
+
+        let thresholds = ConfidenceThresholds { detection: 0.6, ..thresholds };
+        let large = serde_json::json!({ "similarity": vec![0.5; 100] });
+        let raw = RawOutputs::collect(&candidates, &thresholds, Some(&large), &limits);
+        assert!(!raw.candidates[1].kept);
+        assert!(raw.model.is_none());
+        assert!(raw.model_omitted_bytes.unwrap() > 64);
+    }
+}
@@ -0,0 +1,616 @@
+/*!
+ * Luna Remote Inference - Offload element detection to an inference server
+ *
+ * The protocol is plain HTTP/1.1 so a server can be written in any language:
+ *
+ * - `POST /v1/detect` with a `image/png` body (or raw RGB as
+ *   `application/x-luna-rgb` plus `X-Luna-Width`/`X-Luna-Height` headers)
+ *   returns a JSON `DetectResponse`.
+ * - `GET /v1/health` returns a JSON `HealthResponse`.
+ *
+ * `InferenceServer` is a reference implementation backed by any local
+ * `ElementDetector`. Only `http://` endpoints are supported. A server that
+ * listens beyond loopback requires `Authorization: Bearer <token>` on every
+ * request.
+ */
+
+use anyhow::Result;
+use image::DynamicImage;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use super::{ElementDetection, ElementDetector};
+use crate::core::config::{RemoteInferenceConfig, MIN_API_TOKEN_LEN};
+use crate::core::confirmation::constant_time_eq;
+use crate::core::{ElementBounds, LunaError};
+use crate::utils::geometry::{Point, Polygon};
+
+/// Version sent in `X-Luna-Protocol` and echoed by servers
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const RAW_RGB_CONTENT_TYPE: &str = "application/x-luna-rgb";
+/// Upper bound on request and response sizes (a 4K RGB frame is ~25 MB)
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+/// One detected element on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteElement {
+    pub element_type: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub confidence: f32,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    /// Outline as [x, y] vertices, e.g. a segmentation mask; absent for plain boxes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape: Option<Vec<[f64; 2]>>,
+}
+
+/// Body of a `/v1/detect` response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectResponse {
+    pub protocol: u32,
+    pub elements: Vec<RemoteElement>,
+    #[serde(default)]
+    pub processing_time_ms: u64,
+    /// Model-specific extras (logits, similarities, mask scores), passed
+    /// through untouched when `raw_outputs.include` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<serde_json::Value>,
+}
+
+/// Body of a `/v1/health` response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub protocol: u32,
+    pub status: String,
+}
+
+impl From<&ElementDetection> for RemoteElement {
+    fn from(detection: &ElementDetection) -> Self {
+        Self {
+            element_type: detection.element_type.clone(),
+            x: detection.bounds.x,
+            y: detection.bounds.y,
+            width: detection.bounds.width,
+            height: detection.bounds.height,
+            confidence: detection.confidence,
+            text: detection.text.clone(),
+            attributes: detection.attributes.clone(),
+            shape: detection.shape.as_ref().map(|shape| shape.points.iter().map(|p| [p.x, p.y]).collect()),
+        }
+    }
+}
+
+impl From<RemoteElement> for ElementDetection {
+    fn from(element: RemoteElement) -> Self {
+        Self {
+            element_type: element.element_type,
+            bounds: ElementBounds::new(element.x, element.y, element.width, element.height),
+            shape: element
+                .shape
+                .filter(|points| points.len() >= 3)
+                .map(|points| Polygon::new(points.into_iter().map(|[x, y]| Point::new(x, y)).collect())),
+            confidence: element.confidence,
+            text: element.text,
+            attributes: element.attributes,
+        }
+    }
+}
+
+/// Element detector that calls a remote inference server.
+///
+/// After a connection failure the server is skipped for `retry_after_secs`
+/// so every frame doesn't pay the connect timeout.
+pub struct RemoteDetector {
+    config: RemoteInferenceConfig,
+    host: String,
+    port: u16,
+    base_path: String,
+    unavailable_until: Option<Instant>,
+    /// `raw` of the last successful response
+    last_raw: Option<serde_json::Value>,
+}
+
+impl RemoteDetector {
+    pub fn new(config: RemoteInferenceConfig) -> Result<Self> {
+        let (host, port, base_path) = parse_endpoint(&config.endpoint)?;
+        Ok(Self { config, host, port, base_path, unavailable_until: None, last_raw: None })
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.config.endpoint
+    }
+
+    /// Model-specific extras of the last successful detection, if the server sent any
+    pub fn last_raw(&self) -> Option<&serde_json::Value> {
+        self.last_raw.as_ref()
+    }
+
+    /// False while backing off after a failed request
+    pub fn is_available(&self) -> bool {
+        self.unavailable_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Ask the server whether it is up and speaks our protocol
+    pub fn health_check(&mut self) -> Result<HealthResponse> {
+        let response = self.request("GET", "/v1/health", &[], &[])?;
+        let health: HealthResponse = serde_json::from_slice(&response)?;
+        if health.protocol != PROTOCOL_VERSION {
+            return Err(LunaError::AI(format!("server speaks protocol {}, expected {}", health.protocol, PROTOCOL_VERSION)).into());
+        }
+        Ok(health)
+    }
+
+    fn request(&mut self, method: &str, path: &str, headers: &[(&str, String)], body: &[u8]) -> Result<Vec<u8>> {
+        if !self.is_available() {
+            return Err(LunaError::AI(format!("inference server {} is backing off", self.config.endpoint)).into());
+        }
+
+        let path = format!("{}{}", self.base_path, path);
+        let mut headers = headers.to_vec();
+        if let Some(token) = &self.config.token {
+            headers.push(("Authorization", format!("Bearer {}", token)));
+        }
+        let timeouts = HttpTimeouts {
+            connect: Duration::from_millis(self.config.connect_timeout_ms),
+            read: Duration::from_millis(self.config.timeout_ms),
+        };
+        match http_request(&self.host, self.port, method, &path, &headers, body, timeouts) {
+            Ok(response) if response.status == 200 => {
+                self.unavailable_until = None;
+                Ok(response.body)
+            }
+            Ok(response) => Err(LunaError::AI(format!(
+                "inference server returned {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            ))
+            .into()),
+            Err(e) => {
+                warn!("Inference server {} unreachable: {}", self.config.endpoint, e);
+                self.unavailable_until = Some(Instant::now() + Duration::from_secs(self.config.retry_after_secs));
+                Err(e)
+            }
+        }
+    }
+}
+
+impl ElementDetector for RemoteDetector {
+    fn detect_elements(&mut self, image: &DynamicImage) -> Result<Vec<ElementDetection>> {
+        let rgb = image.to_rgb8();
+        let mut headers = vec![("X-Luna-Protocol", PROTOCOL_VERSION.to_string())];
+
+        let body = if self.config.compress {
+            headers.push(("Content-Type", "image/png".to_string()));
+            let mut png = std::io::Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(rgb).write_to(&mut png, image::ImageOutputFormat::Png)?;
+            png.into_inner()
+        } else {
+            headers.push(("Content-Type", RAW_RGB_CONTENT_TYPE.to_string()));
+            headers.push(("X-Luna-Width", rgb.width().to_string()));
+            headers.push(("X-Luna-Height", rgb.height().to_string()));
+            rgb.into_raw()
+        };
+
+        let start = Instant::now();
+        self.last_raw = None;
+        let response = self.request("POST", "/v1/detect", &headers, &body)?;
+        let response: DetectResponse = serde_json::from_slice(&response)?;
+        debug!(
+            "Remote inference: {} elements, {} byte request, {}ms round trip ({}ms on server)",
+            response.elements.len(),
+            body.len(),
+            start.elapsed().as_millis(),
+            response.processing_time_ms
+        );
+
+        self.last_raw = response.raw;
+        Ok(response.elements.into_iter().map(ElementDetection::from).collect())
+    }
+}
+
+/// Reference inference server wrapping a local detector
+pub struct InferenceServer {
+    listener: TcpListener,
+    /// Bearer token every request must carry
+    token: Option<String>,
+}
+
+impl InferenceServer {
+    /// Listen on `addr`. Anything that can connect may use the detector, so
+    /// addresses other than loopback are refused unless clients must present
+    /// `token`.
+    pub fn bind(addr: impl ToSocketAddrs, token: Option<String>) -> Result<Self> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        if let Some(token) = token.as_ref().filter(|token| token.len() < MIN_API_TOKEN_LEN) {
+            return Err(LunaError::Config(format!(
+                "inference server token must be at least {} characters, got {}", MIN_API_TOKEN_LEN, token.len())).into());
+        }
+        if token.is_none() {
+            if let Some(open) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+                return Err(LunaError::PermissionDenied(format!(
+                    "refusing to serve detection on {} without a token; bind to 127.0.0.1 or set one", open)).into());
+            }
+        }
+        Ok(Self { listener: TcpListener::bind(&addrs[..])?, token })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests until the listener fails
+    pub fn serve(&self, detector: &mut dyn ElementDetector) -> Result<()> {
+        info!("Inference server listening on {}", self.local_addr()?);
+        loop {
+            if let Err(e) = self.serve_one(detector) {
+                warn!("Inference request failed: {}", e);
+            }
+        }
+    }
+
+    /// Accept and answer a single connection
+    pub fn serve_one(&self, detector: &mut dyn ElementDetector) -> Result<()> {
+        let (mut stream, peer) = self.listener.accept()?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+        let request = read_message(&mut stream)?;
+        let (method, path) = request.route();
+        debug!("Inference request from {}: {} {}", peer, method, path);
+
+        let authorized = self.token.as_ref().is_none_or(|token| {
+            request
+                .header("authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .is_some_and(|sent| constant_time_eq(sent.trim().as_bytes(), token.as_bytes()))
+        });
+        let (status, body) = match (method, path) {
+            _ if !authorized => (401, b"missing or wrong bearer token".to_vec()),
+            ("GET", "/v1/health") => {
+                let health = HealthResponse { protocol: PROTOCOL_VERSION, status: "ok".to_string() };
+                (200, serde_json::to_vec(&health)?)
+            }
+            ("POST", "/v1/detect") => match handle_detect(&request, detector) {
+                Ok(response) => (200, serde_json::to_vec(&response)?),
+                Err(e) => (400, e.to_string().into_bytes()),
+            },
+            _ => (404, b"not found".to_vec()),
+        };
+
+        write_response(&mut stream, status, "application/json", &body)
+    }
+}
+
+fn handle_detect(request: &HttpMessage, detector: &mut dyn ElementDetector) -> Result<DetectResponse> {
+    let start = Instant::now();
+    let content_type = request.header("content-type").unwrap_or("image/png");
+
+    let image = if content_type == RAW_RGB_CONTENT_TYPE {
+        let dimension = |name: &str| -> Result<u32> {
+            request
+                .header(name)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("missing or invalid {} header", name))
+        };
+        let (width, height) = (dimension("x-luna-width")?, dimension("x-luna-height")?);
+        image::RgbImage::from_raw(width, height, request.body.clone())
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(|| anyhow::anyhow!("raw body does not match {}x{}", width, height))?
+    } else {
+        image::load_from_memory(&request.body)?
+    };
+
+    let elements = detector.detect_elements(&image)?;
+    Ok(DetectResponse {
+        protocol: PROTOCOL_VERSION,
+        elements: elements.iter().map(RemoteElement::from).collect(),
+        processing_time_ms: start.elapsed().as_millis() as u64,
+        raw: None,
+    })
+}
+
+/// Split `http://host:port/prefix` into its parts
+pub(crate) fn parse_endpoint(endpoint: &str) -> Result<(String, u16, String)> {
+    let rest = endpoint
+        .strip_prefix("http://")
+        .ok_or_else(|| LunaError::Config(format!("endpoint must start with http://: {}", endpoint)))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], rest[index..].trim_end_matches('/')),
+        None => (rest, ""),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| LunaError::Config(format!("invalid port in endpoint: {}", endpoint)))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(LunaError::Config(format!("missing host in endpoint: {}", endpoint)).into());
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HttpTimeouts {
+    pub connect: Duration,
+    /// Per read or write on the connection
+    pub read: Duration,
+}
+
+/// Minimal HTTP/1.1 client: one request per connection, `Content-Length` bodies
+pub(crate) fn http_request(
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    timeouts: HttpTimeouts,
+) -> Result<HttpResponse> {
+    let (connect_timeout, timeout) = (timeouts.connect, timeouts.read);
+
+    let mut last_error = None;
+    let mut stream = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, connect_timeout) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let mut stream = match (stream, last_error) {
+        (Some(stream), _) => stream,
+        (None, Some(e)) => return Err(e.into()),
+        (None, None) => return Err(LunaError::AI(format!("{} did not resolve", host)).into()),
+    };
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n", method, path, host, port, body.len());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let response = read_message(&mut stream)?;
+    let status = response
+        .start_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| LunaError::AI(format!("malformed status line: {}", response.start_line)))?;
+    Ok(HttpResponse { status, body: response.body })
+}
+
+/// Request or response: start line, lower-cased headers and body
+pub(crate) struct HttpMessage {
+    pub start_line: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Method and path of a request
+    pub fn route(&self) -> (&str, &str) {
+        let mut parts = self.start_line.split_whitespace();
+        (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+    }
+}
+
+/// Write a complete response and let the connection close
+pub(crate) fn write_response(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Not Found",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+pub(crate) fn read_message(stream: &mut TcpStream) -> Result<HttpMessage> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    let header_end = loop {
+        if let Some(index) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break index;
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Err(LunaError::AI("connection closed before headers were complete".to_string()).into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if buffer.len() > MAX_BODY_BYTES {
+            return Err(LunaError::AI("HTTP headers too large".to_string()).into());
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let mut body = buffer[header_end + 4..].to_vec();
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok());
+
+    match content_length {
+        Some(length) if length > MAX_BODY_BYTES => {
+            return Err(LunaError::AI(format!("HTTP body of {} bytes is too large", length)).into());
+        }
+        Some(length) => {
+            while body.len() < length {
+                let read = stream.read(&mut chunk)?;
+                if read == 0 {
+                    return Err(LunaError::AI("connection closed mid-body".to_string()).into());
+                }
+                body.extend_from_slice(&chunk[..read]);
+            }
+            body.truncate(length);
+        }
+        None => {
+            stream.take(MAX_BODY_BYTES as u64).read_to_end(&mut body)?;
+        }
+    }
+
+    Ok(HttpMessage { start_line, headers, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AICoordinator;
+
+    /// Detector that reports one fixed element and the image size it received
+    struct FixedDetector;
+
+    impl ElementDetector for FixedDetector {
+        fn detect_elements(&mut self, image: &DynamicImage) -> Result<Vec<ElementDetection>> {
+            let mut attributes = HashMap::new();
+            attributes.insert("seen".to_string(), format!("{}x{}", image.width(), image.height()));
+            Ok(vec![ElementDetection {
+                element_type: "button".to_string(),
+                bounds: ElementBounds::new(10, 20, 80, 30),
+                shape: None,
+                confidence: 0.9,
+                text: Some("OK".to_string()),
+                attributes,
+            }])
+        }
+    }
+
+    fn config_for(addr: SocketAddr, compress: bool) -> RemoteInferenceConfig {
+        RemoteInferenceConfig {
+            enabled: true,
+            endpoint: format!("http://{}", addr),
+            compress,
+            ..RemoteInferenceConfig::default()
+        }
+    }
+
+    /// Address nothing is listening on
+    fn dead_address() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn serve_requests(count: usize) -> SocketAddr {
+        let server = InferenceServer::bind("127.0.0.1:0", None).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for _ in 0..count {
+                server.serve_one(&mut FixedDetector).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(parse_endpoint("http://gpu-box:8700").unwrap(), ("gpu-box".to_string(), 8700, String::new()));
+        assert_eq!(parse_endpoint("http://gpu-box/luna/").unwrap(), ("gpu-box".to_string(), 80, "/luna".to_string()));
+        assert!(parse_endpoint("https://gpu-box").is_err());
+        assert!(parse_endpoint("http://:80").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_compressed_and_raw() {
+        let addr = serve_requests(3);
+        let image = DynamicImage::new_rgb8(64, 48);
+
+        let mut remote = RemoteDetector::new(config_for(addr, true)).unwrap();
+        assert_eq!(remote.health_check().unwrap().status, "ok");
+        let elements = remote.detect_elements(&image).unwrap();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].bounds, ElementBounds::new(10, 20, 80, 30));
+        assert_eq!(elements[0].attributes["seen"], "64x48");
+
+        let mut raw = RemoteDetector::new(config_for(addr, false)).unwrap();
+        assert_eq!(raw.detect_elements(&image).unwrap()[0].text.as_deref(), Some("OK"));
+    }
+
+    #[test]
+    fn test_open_addresses_need_a_token() {
+        let denied = InferenceServer::bind("0.0.0.0:0", None).err().expect("no token");
+        assert!(matches!(denied.downcast_ref::<LunaError>(), Some(LunaError::PermissionDenied(_))));
+        assert!(InferenceServer::bind("0.0.0.0:0", Some("short".to_string())).is_err());
+
+        let token = "0123456789abcdef".to_string();
+        let server = InferenceServer::bind("127.0.0.1:0", Some(token.clone())).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for _ in 0..2 {
+                server.serve_one(&mut FixedDetector).unwrap();
+            }
+        });
+
+        let image = DynamicImage::new_rgb8(8, 8);
+        let mut anonymous = RemoteDetector::new(config_for(addr, true)).unwrap();
+        let refused = anonymous.detect_elements(&image).unwrap_err();
+        assert!(refused.to_string().contains("401"), "{}", refused);
+
+        let mut remote = RemoteDetector::new(RemoteInferenceConfig { token: Some(token), ..config_for(addr, true) }).unwrap();
+        assert_eq!(remote.detect_elements(&image).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unreachable_server_backs_off() {
+        let mut remote = RemoteDetector::new(config_for(dead_address(), true)).unwrap();
+        assert!(remote.is_available());
+        assert!(remote.detect_elements(&DynamicImage::new_rgb8(8, 8)).is_err());
+        assert!(!remote.is_available());
+    }
+
+    #[test]
+    fn test_coordinator_falls_back_to_local_detection() {
+        let mut coordinator = AICoordinator::new();
+        coordinator.set_remote_backend(Some(RemoteDetector::new(config_for(dead_address(), true)).unwrap()));
+
+        let analysis = coordinator.analyze_screen(&DynamicImage::new_rgb8(64, 64)).unwrap();
+        assert_eq!(analysis.screen_size, (64, 64));
+        assert_eq!(coordinator.get_stats().remote_fallbacks, 1);
+        assert_eq!(coordinator.get_stats().remote_requests, 0);
+    }
+}
@@ -0,0 +1,220 @@
+// Command suggestions drawn from what is on screen
+// Instead of a fixed list of examples, suggestions name what the user can
+// act on right now: the dialog in front ("Close the 'Update' dialog") and
+// the most prominent named controls ("Click 'Compose'"), ranked by how big,
+// visible and confidently detected they are. Commands recently run in the
+// same window are mixed in. Every suggestion is phrased so the planner
+// resolves it to exactly one target.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::text_match::fold;
+use super::{close_button, container_title, WINDOW_TYPES};
+use crate::core::{ScreenAnalysis, ScreenElement};
+
+/// Clickable element types and how much a control of that type is worth suggesting
+const CLICK_WEIGHTS: [(&str, f64); 8] = [
+    ("button", 1.0),
+    ("tab", 0.8),
+    ("link", 0.7),
+    ("icon", 0.6),
+    ("checkbox", 0.6),
+    ("toggle", 0.6),
+    ("menuitem", 0.6),
+    ("listitem", 0.5),
+];
+/// Side length (pixels) from which an element counts as fully prominent
+const PROMINENT_SIZE: f64 = 80.0;
+/// Below this an element is too hidden or too faint to suggest
+const MIN_PROMINENCE: f64 = 0.05;
+/// Longest label worth putting in a suggestion
+const MAX_LABEL_CHARS: usize = 40;
+/// Score of closing the dialog in front, above any click
+const DIALOG_SCORE: f64 = 2.0;
+
+/// Where a suggestion came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionSource {
+    /// An element of the current screen
+    Screen,
+    /// A command recently run in the same window
+    History,
+}
+
+/// A command worth offering for the current screen
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub command: String,
+    pub source: SuggestionSource,
+    /// Ranking among suggestions from the same source; higher first
+    pub score: f64,
+}
+
+/// Suggestions for `analysis`, best first. With a modal dialog open only
+/// the dialog and what is inside it are suggested.
+pub fn from_screen(analysis: &ScreenAnalysis) -> Vec<Suggestion> {
+    let modal = analysis.active_modal();
+    let scope: Vec<usize> = match modal {
+        Some(modal) => analysis.subtree(modal),
+        None => (0..analysis.elements.len()).collect(),
+    };
+
+    let mut suggestions = Vec::new();
+    if let Some(command) = close_suggestion(analysis, modal) {
+        suggestions.push(Suggestion { command, source: SuggestionSource::Screen, score: DIALOG_SCORE });
+    }
+
+    let clickable: Vec<(&ScreenElement, &str, f64)> = scope
+        .iter()
+        .map(|&i| &analysis.elements[i])
+        .filter_map(|e| Some((e, name(e)?, type_weight(e)?)))
+        .collect();
+    // A label shared by several controls would need a clarification to click
+    let mut seen = HashSet::new();
+    let repeated: HashSet<String> = clickable.iter().map(|(_, name, _)| fold(name)).filter(|n| !seen.insert(n.clone())).collect();
+
+    let mut clicks: Vec<Suggestion> = clickable
+        .into_iter()
+        .filter(|(_, name, _)| !repeated.contains(&fold(name)))
+        .map(|(element, name, weight)| Suggestion {
+            command: format!("Click '{name}'"),
+            source: SuggestionSource::Screen,
+            score: prominence(element) * weight,
+        })
+        .filter(|s| s.score >= MIN_PROMINENCE)
+        .collect();
+    clicks.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.extend(clicks);
+    suggestions
+}
+
+/// `n` suggestions: the best of `screen`, with about a third of the slots
+/// kept for `history` (most recent first). Slots one source can't fill go
+/// to the other, and a command is offered once.
+pub fn merge(screen: Vec<Suggestion>, history: &[String], n: usize) -> Vec<Suggestion> {
+    let mut seen = HashSet::new();
+    let history: Vec<Suggestion> = history
+        .iter()
+        .enumerate()
+        .map(|(rank, command)| Suggestion {
+            command: command.clone(),
+            source: SuggestionSource::History,
+            score: 1.0 / (rank + 1) as f64,
+        })
+        .collect();
+    let screen: Vec<Suggestion> = screen.into_iter().filter(|s| seen.insert(fold(&s.command))).collect();
+    let history: Vec<Suggestion> = history.into_iter().filter(|s| seen.insert(fold(&s.command))).collect();
+
+    let history_slots = (n / 3).max(usize::from(n > 1)).min(history.len());
+    let screen_slots = (n - history_slots).min(screen.len());
+    let history_slots = (n - screen_slots).min(history.len());
+    screen.into_iter().take(screen_slots).chain(history.into_iter().take(history_slots)).collect()
+}
+
+/// "Close the 'Title' dialog" for the modal, or else the one titled dialog
+/// on screen, when it has a close button to click
+fn close_suggestion(analysis: &ScreenAnalysis, modal: Option<usize>) -> Option<String> {
+    let dialog = match modal {
+        Some(modal) => WINDOW_TYPES.contains(&analysis.elements[modal].element_type.as_str()).then_some(modal)?,
+        None => {
+            let dialogs: Vec<usize> = (0..analysis.elements.len()).filter(|&i| analysis.elements[i].element_type == "dialog").collect();
+            match dialogs.as_slice() {
+                [dialog] => *dialog,
+                _ => return None,
+            }
+        }
+    };
+    let title = container_title(analysis, dialog).map(str::trim).filter(|t| usable(t))?;
+    close_button(analysis, dialog).ok()?;
+    Some(format!("Close the '{title}' dialog"))
+}
+
+/// The text a click suggestion names the element by
+fn name(element: &ScreenElement) -> Option<&str> {
+    element.text.as_deref().or(element.label()).map(str::trim).filter(|t| usable(t))
+}
+
+fn usable(text: &str) -> bool {
+    text.chars().any(char::is_alphanumeric) && text.chars().count() <= MAX_LABEL_CHARS && !text.contains('\'')
+}
+
+fn type_weight(element: &ScreenElement) -> Option<f64> {
+    CLICK_WEIGHTS.iter().find(|(t, _)| *t == element.element_type).map(|(_, weight)| *weight)
+}
+
+/// How much an element stands out: size, visibility and detection confidence
+fn prominence(element: &ScreenElement) -> f64 {
+    let area = (element.bounds.width.max(0) as f64) * (element.bounds.height.max(0) as f64);
+    let size = (area.sqrt() / PROMINENT_SIZE).min(1.0);
+    size * element.visibility() * (1.0 - element.occlusion()) * element.confidence as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AICoordinator;
+    use crate::core::ElementBounds;
+
+    fn element(element_type: &str, text: &str, bounds: (i32, i32, i32, i32)) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(bounds.0, bounds.1, bounds.2, bounds.3),
+            shape: None,
+            confidence: 0.9,
+            text: (!text.is_empty()).then(|| text.to_string()),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn analysis(elements: Vec<ScreenElement>) -> ScreenAnalysis {
+        let mut analysis =
+            ScreenAnalysis { elements, confidence: 0.9, processing_time_ms: 0, screen_size: (1280, 800), context: Default::default() };
+        analysis.link_hierarchy();
+        analysis.mark_visibility();
+        analysis
+    }
+
+    fn mail_screen() -> ScreenAnalysis {
+        analysis(vec![
+            element("button", "Compose", (20, 80, 140, 48)),
+            element("link", "Inbox", (20, 160, 100, 24)),
+            element("icon", "", (1200, 20, 24, 24)),
+            element("button", "Archive", (400, 80, 80, 32)),
+            element("button", "Archive", (400, 400, 80, 32)),
+            element("dialog", "", (440, 250, 400, 300)),
+            element("text", "Update available", (460, 255, 200, 20)),
+            element("button", "×", (810, 255, 20, 20)),
+            element("button", "Restart now", (640, 500, 120, 32)),
+        ])
+    }
+
+    #[test]
+    fn test_screen_suggestions_rank_dialog_and_prominent_controls() {
+        let commands: Vec<String> = from_screen(&mail_screen()).into_iter().map(|s| s.command).collect();
+        assert_eq!(commands[0], "Close the 'Update available' dialog");
+        assert_eq!(commands[1], "Click 'Compose'");
+        assert!(commands.contains(&"Click 'Inbox'".to_string()));
+        // Unnamed and repeated controls can't be clicked by name
+        assert!(!commands.iter().any(|c| c.contains("Archive")));
+
+        let history = vec!["Click 'Inbox'".to_string(), "press ctrl+r".to_string()];
+        let merged = merge(from_screen(&mail_screen()), &history, 4);
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged.iter().filter(|s| s.source == SuggestionSource::History).count(), 1);
+        assert_eq!(merged.last().unwrap().command, "press ctrl+r");
+    }
+
+    #[test]
+    fn test_every_screen_suggestion_plans() {
+        let coordinator = AICoordinator::new();
+        let screen = mail_screen();
+        for suggestion in from_screen(&screen) {
+            let actions = coordinator.plan_actions(&suggestion.command, &screen).unwrap();
+            assert!(!actions.is_empty(), "{} planned nothing", suggestion.command);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// Fuzzy matching of command words against element labels
+// Both sides are folded (case, diacritics), abbreviations are expanded ("Pwd" ->
+// "password") and words are stemmed before comparing with Jaro-Winkler and
+// normalized Levenshtein similarity, so OCR noise and inflections ("saving" vs
+// "Save") still rank the intended element first.
+
+use crate::utils::text;
+
+/// Word similarity below this does not count as a match
+const MIN_WORD_SIMILARITY: f64 = 0.85;
+/// Words shorter than this only match exactly (after folding and expansion)
+const MIN_FUZZY_WORD_LEN: usize = 4;
+/// Label score below this does not make an element a text target
+pub const MIN_LABEL_SCORE: f64 = 0.6;
+
+/// Words that say how to act rather than what to act on
+const STOPWORDS: &[&str] = &[
+    "a", "an", "the", "to", "on", "in", "of", "for", "and", "at", "with", "my", "this", "that", "please",
+    "click", "press", "tap", "hit", "select", "choose", "button", "link", "icon",
+];
+
+/// Abbreviation -> canonical word
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("ok", "okay"),
+    ("pwd", "password"),
+    ("passwd", "password"),
+    ("pw", "password"),
+    ("usr", "user"),
+    ("acct", "account"),
+    ("addr", "address"),
+    ("btn", "button"),
+    ("cfg", "configuration"),
+    ("config", "configuration"),
+    ("prefs", "preferences"),
+    ("pref", "preferences"),
+    ("msg", "message"),
+    ("info", "information"),
+    ("del", "delete"),
+    ("num", "number"),
+    ("qty", "quantity"),
+    ("amt", "amount"),
+    ("desc", "description"),
+    ("dir", "directory"),
+    ("img", "image"),
+    ("nav", "navigation"),
+    ("prev", "previous"),
+    ("nxt", "next"),
+    ("app", "application"),
+    ("tel", "telephone"),
+];
+
+/// How a label word was matched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordMatch {
+    Exact,
+    /// Equal once case and diacritics are folded
+    Folded,
+    Abbreviation,
+    /// Equal after stemming ("saving" / "save")
+    Stem,
+    /// Similar spelling, with the similarity in 0.0..=1.0
+    Fuzzy(f64),
+}
+
+impl WordMatch {
+    fn similarity(self) -> f64 {
+        match self {
+            WordMatch::Exact | WordMatch::Folded | WordMatch::Abbreviation => 1.0,
+            WordMatch::Stem => 0.95,
+            WordMatch::Fuzzy(similarity) => similarity,
+        }
+    }
+}
+
+impl std::fmt::Display for WordMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WordMatch::Exact => write!(f, "exact"),
+            WordMatch::Folded => write!(f, "folded"),
+            WordMatch::Abbreviation => write!(f, "abbreviation"),
+            WordMatch::Stem => write!(f, "stem"),
+            WordMatch::Fuzzy(similarity) => write!(f, "fuzzy {:.2}", similarity),
+        }
+    }
+}
+
+/// Score of a label against a command, with a human-readable explanation
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextMatch {
+    /// Mean similarity of the label's words to their best command word
+    pub score: f64,
+    /// Fraction of the command's words matched by some label word; prefers
+    /// "Don't Save" over "Save" for "click don't save" when both score 1.0
+    pub coverage: f64,
+    pub reasoning: String,
+}
+
+/// A word in its raw, folded, canonical and stemmed forms
+struct Word {
+    raw: String,
+    folded: String,
+    canonical: String,
+    stem: String,
+}
+
+impl Word {
+    fn new(raw: &str) -> Self {
+        let folded = fold(raw);
+        let canonical = expand_abbreviation(&folded).to_string();
+        let stem = stem(&canonical);
+        Self { raw: raw.to_string(), folded, canonical, stem }
+    }
+
+    fn compare(&self, other: &Word) -> Option<WordMatch> {
+        if self.raw == other.raw {
+            return Some(WordMatch::Exact);
+        }
+        if self.folded == other.folded {
+            return Some(WordMatch::Folded);
+        }
+        if self.canonical == other.canonical {
+            return Some(WordMatch::Abbreviation);
+        }
+        if self.stem == other.stem {
+            return Some(WordMatch::Stem);
+        }
+        if self.canonical.chars().count().min(other.canonical.chars().count()) < MIN_FUZZY_WORD_LEN {
+            return None;
+        }
+        let similarity = jaro_winkler(&self.canonical, &other.canonical)
+            .max(levenshtein_similarity(&self.canonical, &other.canonical));
+        (similarity >= MIN_WORD_SIMILARITY).then_some(WordMatch::Fuzzy(similarity))
+    }
+}
+
+/// Content words of `text`, without stopwords
+fn words(text: &str) -> Vec<Word> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|raw| !raw.is_empty())
+        .map(Word::new)
+        .filter(|word| !STOPWORDS.contains(&word.folded.as_str()))
+        .collect()
+}
+
+/// Score how well `label` is described by `command`, or `None` below `MIN_LABEL_SCORE`
+pub fn match_label(command: &str, label: &str) -> Option<TextMatch> {
+    let label_words = words(label);
+    let command_words = words(command);
+    if label_words.is_empty() || command_words.is_empty() {
+        return None;
+    }
+
+    let mut total = 0.0;
+    let mut evidence = Vec::new();
+    let mut covered = vec![false; command_words.len()];
+    for label_word in &label_words {
+        let best = command_words
+            .iter()
+            .enumerate()
+            .filter_map(|(index, command_word)| Some((index, command_word, label_word.compare(command_word)?)))
+            .max_by(|a, b| a.2.similarity().total_cmp(&b.2.similarity()));
+        if let Some((index, command_word, how)) = best {
+            total += how.similarity();
+            covered[index] = true;
+            evidence.push(format!("'{}' ~ '{}' ({})", label_word.raw, command_word.raw, how));
+        }
+    }
+
+    let score = total / label_words.len() as f64;
+    if score < MIN_LABEL_SCORE {
+        return None;
+    }
+    Some(TextMatch {
+        score,
+        coverage: covered.iter().filter(|c| **c).count() as f64 / command_words.len() as f64,
+        reasoning: format!("label '{}' scored {:.2}: {}", label.trim(), score, evidence.join(", ")),
+    })
+}
+
+/// How close `label` comes to naming what `command` names, even when it is
+/// too far off to match: for each command word its best similarity to a
+/// label word, averaged, in 0.0..=1.0
+pub fn closeness(command: &str, label: &str) -> f64 {
+    let label_words = words(label);
+    let command_words = words(command);
+    if label_words.is_empty() || command_words.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = command_words
+        .iter()
+        .map(|command_word| {
+            label_words
+                .iter()
+                .map(|label_word| match label_word.compare(command_word) {
+                    Some(how) => how.similarity(),
+                    None => jaro_winkler(&label_word.canonical, &command_word.canonical)
+                        .max(levenshtein_similarity(&label_word.canonical, &command_word.canonical)),
+                })
+                .fold(0.0, f64::max)
+        })
+        .sum();
+    total / command_words.len() as f64
+}
+
+/// Lowercase and strip diacritics from Latin letters
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    // Accents OCR returns decomposed are dropped like precomposed ones
+    for c in text::fold_case(text).chars().filter(|&c| !text::is_extend(c)) {
+        match c {
+            'æ' => folded.push_str("ae"),
+            'œ' => folded.push_str("oe"),
+            _ => folded.push(fold_char(c)),
+        }
+    }
+    folded
+}
+
+fn fold_char(c: char) -> char {
+    match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ł' | 'ľ' | 'ĺ' | 'ļ' => 'l',
+        'ñ' | 'ń' | 'ň' | 'ņ' => 'n',
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ř' | 'ŗ' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' => 't',
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        _ => c,
+    }
+}
+
+fn expand_abbreviation(word: &str) -> &str {
+    ABBREVIATIONS
+        .iter()
+        .find(|(short, _)| *short == word)
+        .map_or(word, |(_, full)| full)
+}
+
+/// Light suffix stripping: plurals, -ing, -ed, -ly and a trailing silent e
+pub fn stem(word: &str) -> String {
+    let len = word.chars().count();
+    let mut stem = if len > 4 && word.ends_with("ies") {
+        format!("{}y", &word[..word.len() - 3])
+    } else if len > 5 && word.ends_with("ing") {
+        undouble(&word[..word.len() - 3])
+    } else if len > 4 && word.ends_with("ed") {
+        undouble(&word[..word.len() - 2])
+    } else if len > 4 && ["ly", "ches", "shes", "xes", "sses"].iter().any(|s| word.ends_with(s)) {
+        // -ly, or -es after a sibilant
+        word[..word.len() - 2].to_string()
+    } else if len > 3 && word.ends_with('s') && !word.ends_with("ss") {
+        word[..word.len() - 1].to_string()
+    } else {
+        word.to_string()
+    };
+    if stem.chars().count() > 3 && stem.ends_with('e') {
+        stem.pop();
+    }
+    stem
+}
+
+/// "runn" -> "run", so "running" and "run" share a stem
+fn undouble(stem: &str) -> String {
+    let chars: Vec<char> = stem.chars().collect();
+    match chars.as_slice() {
+        [.., a, b] if a == b && !"aeiouls".contains(*a) => chars[..chars.len() - 1].iter().collect(),
+        _ => stem.to_string(),
+    }
+}
+
+/// Edit distance normalized to 0.0..=1.0, where 1.0 means identical
+pub fn levenshtein_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+/// Jaro-Winkler similarity with the standard 0.1 prefix scale
+pub fn jaro_winkler(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let window = (a.len().max(b.len()) / 2).saturating_sub(1);
+    let mut a_matched = vec![false; a.len()];
+    let mut b_matched = vec![false; b.len()];
+    let mut matches = 0usize;
+    for (i, ca) in a.iter().enumerate() {
+        let start = i.saturating_sub(window);
+        let end = (i + window + 1).min(b.len());
+        for j in start..end {
+            if !b_matched[j] && b[j] == *ca {
+                a_matched[i] = true;
+                b_matched[j] = true;
+                matches += 1;
+                break;
+            }
+        }
+    }
+    if matches == 0 {
+        return 0.0;
+    }
+
+    let a_seq = a.iter().zip(&a_matched).filter(|(_, m)| **m).map(|(c, _)| c);
+    let b_seq = b.iter().zip(&b_matched).filter(|(_, m)| **m).map(|(c, _)| c);
+    let transpositions = a_seq.zip(b_seq).filter(|(x, y)| x != y).count() / 2;
+
+    let m = matches as f64;
+    let jaro = (m / a.len() as f64 + m / b.len() as f64 + (m - transpositions as f64) / m) / 3.0;
+    let prefix = a.iter().zip(&b).take(4).take_while(|(x, y)| x == y).count();
+    jaro + prefix as f64 * 0.1 * (1.0 - jaro)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity_measures() {
+        assert!((jaro_winkler("martha", "marhta") - 0.961).abs() < 0.001);
+        assert_eq!(jaro_winkler("abc", "xyz"), 0.0);
+        assert!((levenshtein_similarity("kitten", "sitting") - (1.0 - 3.0 / 7.0)).abs() < 1e-9);
+        assert_eq!(levenshtein_similarity("", ""), 1.0);
+    }
+
+    #[test]
+    fn test_normalization() {
+        assert_eq!(fold("Café Ångström"), "cafe angstrom");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(stem("saving"), stem("save"));
+        assert_eq!(stem("running"), "run");
+        assert_eq!(stem("settings"), "setting");
+        assert_eq!(stem("entries"), "entry");
+    }
+
+    #[test]
+    fn test_match_label() {
+        let okay = match_label("click okay", "OK").unwrap();
+        assert_eq!(okay.score, 1.0);
+        assert!(okay.reasoning.contains("abbreviation"), "{}", okay.reasoning);
+
+        assert!(match_label("enter the password", "Pwd:").is_some());
+        assert!(match_label("open preferences", "Préférences").is_some());
+        assert!(match_label("click saving", "Save").unwrap().reasoning.contains("stem"));
+
+        // OCR dropped a letter
+        let fuzzy = match_label("click submit", "Subrnit").unwrap();
+        assert!(fuzzy.score < 1.0 && fuzzy.reasoning.contains("fuzzy"));
+
+        assert!(match_label("click cancel", "Submit").is_none());
+        assert!(match_label("click on", "OK").is_none());
+        // Half the label is not enough
+        assert!(match_label("click save", "Save as template copy").is_none());
+
+        // Both labels fully match; the one accounting for more of the command wins ties
+        let save = match_label("click don't save", "Save").unwrap();
+        let dont_save = match_label("click don't save", "Don't Save").unwrap();
+        assert_eq!(save.score, dont_save.score);
+        assert!(dont_save.coverage > save.coverage);
+    }
+}
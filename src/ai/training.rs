@@ -0,0 +1,367 @@
+// Retraining data export from user corrections
+// Writes labeled crops plus context windows as JSONL and COCO for offline detector training
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::core::scratch::ScratchRegistry;
+use crate::core::{ElementBounds, ScreenElement};
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+const LABELS_FILE: &str = "labels.jsonl";
+const COCO_FILE: &str = "annotations.coco.json";
+
+/// A user correction: what LUNA picked versus what it should have picked
+#[derive(Debug, Clone)]
+pub struct Correction {
+    /// Command that produced the wrong result
+    pub command: String,
+    /// Element LUNA chose, if any
+    pub predicted: Option<ScreenElement>,
+    /// Where the intended element actually is
+    pub correct_bounds: ElementBounds,
+    /// Correct element type (e.g. "button", "textfield")
+    pub correct_label: String,
+}
+
+/// One exported training sample, stored as a line of `labels.jsonl`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportRecord {
+    /// Content hash of crop and label; also the file stem of the images
+    pub id: String,
+    pub label: String,
+    pub predicted_label: Option<String>,
+    /// Command text after privacy redaction
+    pub command: String,
+    /// Corrected box in full-frame coordinates [x, y, width, height]
+    pub bbox: [i32; 4],
+    /// Corrected box relative to the context image
+    pub context_bbox: [i32; 4],
+    pub context_size: (u32, u32),
+    pub frame_size: (u32, u32),
+    pub crop_file: String,
+    pub context_file: String,
+    pub timestamp: u64,
+}
+
+/// Writes correction samples into a training-data directory
+pub struct TrainingExporter {
+    dir: PathBuf,
+    context_margin: i32,
+    redactor: Redactor,
+    seen_ids: Option<HashSet<String>>,
+    /// Images are registered here until their label line is written
+    scratch: ScratchRegistry,
+}
+
+impl TrainingExporter {
+    /// Exporter writing into `dir`, redacting built-in PII patterns plus `extra_patterns`
+    pub fn new(dir: impl Into<PathBuf>, context_margin: i32, redact: bool, extra_patterns: &[String]) -> Result<Self> {
+        let redactor = if redact { Redactor::new(extra_patterns)? } else { Redactor::default() };
+
+        Ok(Self {
+            dir: dir.into(),
+            context_margin: context_margin.max(0),
+            redactor,
+            seen_ids: None,
+            scratch: ScratchRegistry::default(),
+        })
+    }
+
+    /// Journal sample images in `scratch`, so images without a label line are removed
+    pub fn with_scratch(mut self, scratch: ScratchRegistry) -> Self {
+        self.scratch = scratch;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Export one correction against the frame it was made on.
+    ///
+    /// Returns `None` if an identical sample was already exported.
+    pub fn export(&mut self, frame: &Image, correction: &Correction) -> Result<Option<ExportRecord>> {
+        let frame_rect = ElementBounds::new(0, 0, frame.width as i32, frame.height as i32);
+        let target = clip(&correction.correct_bounds, &frame_rect)
+            .ok_or_else(|| anyhow::anyhow!("correction lies outside the {}x{} frame", frame.width, frame.height))?;
+
+        let crop = frame.crop(&Rectangle::from(&target));
+        let id = sample_id(&crop, &correction.correct_label);
+        if self.seen_ids()?.contains(&id) {
+            return Ok(None);
+        }
+
+        let context_rect = clip(
+            &ElementBounds::new(
+                target.x - self.context_margin,
+                target.y - self.context_margin,
+                target.width + 2 * self.context_margin,
+                target.height + 2 * self.context_margin,
+            ),
+            &frame_rect,
+        )
+        .unwrap_or_else(|| target.clone());
+        let context = frame.crop(&Rectangle::from(&context_rect));
+
+        fs::create_dir_all(self.dir.join("crops"))?;
+        fs::create_dir_all(self.dir.join("context"))?;
+        let crop_file = format!("crops/{}.png", id);
+        let context_file = format!("context/{}.png", id);
+        let scope = self.scratch.scope("training sample");
+        let crop_path = scope.register(self.dir.join(&crop_file))?;
+        let context_path = scope.register(self.dir.join(&context_file))?;
+        crate::core::to_dynamic_image(&crop)?.save(&crop_path)?;
+        crate::core::to_dynamic_image(&context)?.save(&context_path)?;
+
+        let record = ExportRecord {
+            id: id.clone(),
+            label: correction.correct_label.clone(),
+            predicted_label: correction.predicted.as_ref().map(|e| e.element_type.clone()),
+            command: self.redact(&correction.command),
+            bbox: [target.x, target.y, target.width, target.height],
+            context_bbox: [target.x - context_rect.x, target.y - context_rect.y, target.width, target.height],
+            context_size: (context.width as u32, context.height as u32),
+            frame_size: (frame.width as u32, frame.height as u32),
+            crop_file,
+            context_file,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        };
+
+        let mut labels = OpenOptions::new().create(true).append(true).open(self.dir.join(LABELS_FILE))?;
+        writeln!(labels, "{}", serde_json::to_string(&record)?)?;
+        scope.keep(&crop_path);
+        scope.keep(&context_path);
+        self.seen_ids()?.insert(id);
+
+        Ok(Some(record))
+    }
+
+    /// All exported samples
+    pub fn records(&self) -> Result<Vec<ExportRecord>> {
+        let path = self.dir.join(LABELS_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(Into::into))
+            .collect()
+    }
+
+    /// Write a COCO detection file over the context images and return its path
+    pub fn write_coco(&self) -> Result<PathBuf> {
+        let records = self.records()?;
+
+        let mut categories: Vec<String> = records.iter().map(|r| r.label.clone()).collect();
+        categories.sort();
+        categories.dedup();
+
+        let images: Vec<serde_json::Value> = records
+            .iter()
+            .enumerate()
+            .map(|(i, r)| serde_json::json!({
+                "id": i + 1,
+                "file_name": r.context_file,
+                "width": r.context_size.0,
+                "height": r.context_size.1,
+            }))
+            .collect();
+
+        let annotations: Vec<serde_json::Value> = records
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let category = categories.iter().position(|c| *c == r.label).unwrap_or(0) + 1;
+                serde_json::json!({
+                    "id": i + 1,
+                    "image_id": i + 1,
+                    "category_id": category,
+                    "bbox": r.context_bbox,
+                    "area": r.context_bbox[2] * r.context_bbox[3],
+                    "iscrowd": 0,
+                })
+            })
+            .collect();
+
+        let categories: Vec<serde_json::Value> = categories
+            .iter()
+            .enumerate()
+            .map(|(i, name)| serde_json::json!({ "id": i + 1, "name": name }))
+            .collect();
+
+        let coco = serde_json::json!({
+            "images": images,
+            "annotations": annotations,
+            "categories": categories,
+        });
+
+        let path = self.dir.join(COCO_FILE);
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&path, serde_json::to_string_pretty(&coco)?)?;
+        Ok(path)
+    }
+
+    /// Replace privacy-sensitive spans with a placeholder
+    pub fn redact(&self, text: &str) -> String {
+        self.redactor.redact(text)
+    }
+
+    fn seen_ids(&mut self) -> Result<&mut HashSet<String>> {
+        if self.seen_ids.is_none() {
+            let ids = self.records()?.into_iter().map(|r| r.id).collect();
+            self.seen_ids = Some(ids);
+        }
+        Ok(self.seen_ids.get_or_insert_with(HashSet::new))
+    }
+}
+
+/// Emails, quoted text (usually what the user asked to type) and long digit runs
+const BUILTIN_REDACTIONS: [&str; 3] = [
+    r"[\w.+-]+@[\w-]+\.[\w.-]+",
+    r#""[^"]*""#,
+    r"\d[\d \-]{5,}\d",
+];
+
+/// Replaces privacy-sensitive spans of text with a placeholder; the default
+/// redacts nothing
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// The built-in PII patterns plus `extra_patterns` (`training.redact_patterns`)
+    pub fn new(extra_patterns: &[String]) -> Result<Self> {
+        let patterns = BUILTIN_REDACTIONS
+            .iter()
+            .copied()
+            .map(str::to_string)
+            .chain(extra_patterns.iter().cloned())
+            .map(|pattern| Regex::new(&pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.patterns
+            .iter()
+            .fold(text.to_string(), |acc, re| re.replace_all(&acc, "<redacted>").into_owned())
+    }
+}
+
+/// Intersection of two boxes, or `None` if they do not overlap
+fn clip(bounds: &ElementBounds, frame: &ElementBounds) -> Option<ElementBounds> {
+    let x1 = bounds.x.max(frame.x);
+    let y1 = bounds.y.max(frame.y);
+    let x2 = (bounds.x + bounds.width).min(frame.x + frame.width);
+    let y2 = (bounds.y + bounds.height).min(frame.y + frame.height);
+    if x2 <= x1 || y2 <= y1 {
+        None
+    } else {
+        Some(ElementBounds::new(x1, y1, x2 - x1, y2 - y1))
+    }
+}
+
+/// FNV-1a over crop pixels and label
+fn sample_id(crop: &Image, label: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in crop.data.iter().chain(label.as_bytes()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Image {
+        let mut image = Image::new(200, 100, 3);
+        for y in 0..100 {
+            for x in 0..200 {
+                image.set_pixel(x, y, &[x as u8, y as u8, 128]);
+            }
+        }
+        image
+    }
+
+    fn correction(x: i32, label: &str) -> Correction {
+        Correction {
+            command: "click submit for alice@example.com".to_string(),
+            predicted: None,
+            correct_bounds: ElementBounds::new(x, 20, 40, 20),
+            correct_label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_export_writes_crop_context_and_label() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut exporter = TrainingExporter::new(temp.path(), 10, true, &[]).unwrap();
+
+        let record = exporter.export(&frame(), &correction(50, "button")).unwrap().unwrap();
+        assert_eq!(record.bbox, [50, 20, 40, 20]);
+        assert_eq!(record.context_bbox, [10, 10, 40, 20]);
+        assert_eq!(record.context_size, (60, 40));
+        assert!(temp.path().join(&record.crop_file).exists());
+        assert!(temp.path().join(&record.context_file).exists());
+        assert_eq!(record.command, "click submit for <redacted>");
+        assert_eq!(exporter.records().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_corrections_are_skipped() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut exporter = TrainingExporter::new(temp.path(), 10, true, &[]).unwrap();
+
+        assert!(exporter.export(&frame(), &correction(50, "button")).unwrap().is_some());
+        assert!(exporter.export(&frame(), &correction(50, "button")).unwrap().is_none());
+        // Same crop with a different label is a distinct sample
+        assert!(exporter.export(&frame(), &correction(50, "link")).unwrap().is_some());
+
+        // Dedupe survives a restart
+        let mut reopened = TrainingExporter::new(temp.path(), 10, true, &[]).unwrap();
+        assert!(reopened.export(&frame(), &correction(50, "link")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_correction_outside_frame_is_rejected() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut exporter = TrainingExporter::new(temp.path(), 10, true, &[]).unwrap();
+        assert!(exporter.export(&frame(), &correction(500, "button")).is_err());
+    }
+
+    #[test]
+    fn test_redaction() {
+        let exporter = TrainingExporter::new("unused", 0, true, &["(?i)acme".to_string()]).unwrap();
+        assert_eq!(exporter.redact(r#"type "hunter2" into Acme"#), "type <redacted> into <redacted>");
+        assert_eq!(exporter.redact("call 555-123-4567"), "call <redacted>");
+
+        let plain = TrainingExporter::new("unused", 0, false, &[]).unwrap();
+        assert_eq!(plain.redact("bob@example.com"), "bob@example.com");
+    }
+
+    #[test]
+    fn test_write_coco() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut exporter = TrainingExporter::new(temp.path(), 10, true, &[]).unwrap();
+        exporter.export(&frame(), &correction(50, "button")).unwrap();
+        exporter.export(&frame(), &correction(100, "textfield")).unwrap();
+
+        let path = exporter.write_coco().unwrap();
+        let coco: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(coco["images"].as_array().unwrap().len(), 2);
+        assert_eq!(coco["annotations"].as_array().unwrap().len(), 2);
+        assert_eq!(coco["categories"][0]["name"], "button");
+    }
+}
@@ -0,0 +1,193 @@
+// Headless screen analysis for benchmarks, datasets and CI.
+//
+//   luna-analyze [--preset fast|balanced|thorough] [--config FILE] [--repeat N] [--json] IMAGE...
+//   luna-analyze --capture [--preset ...] [--config FILE] [--repeat N] [--json]
+//
+// Runs only the vision/AI pipeline: no command planning, no input, no
+// overlay, no storage. Each image (or the captured screen) is loaded once and
+// analyzed `--repeat` times, so load and analysis time are measured apart
+// and the later runs show warm timings. Remote inference is used when the
+// config enables it, as `luna` would.
+//
+// With --json every image gives one luna.analyze/v1 document per line, with
+// `source`, `preset` and `timings` filled in; an image that fails gives a
+// luna.error/v1 document and the rest still run. Exit status: 0 success,
+// 1 when any image failed, 2 usage error.
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+use luna::ai::remote::RemoteDetector;
+use luna::ai::AICoordinator;
+use luna::core::config::AnalysisPreset;
+use luna::core::schema::{self, AnalysisRecord, AnalysisTimings, ErrorRecord};
+use luna::vision::screen_capture::{CaptureConfig, ScreenCapture};
+use luna::LunaConfig;
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+struct Flags {
+    images: Vec<PathBuf>,
+    capture: bool,
+    preset: AnalysisPreset,
+    config: Option<PathBuf>,
+    repeat: usize,
+    json: bool,
+}
+
+fn parse_flags(args: &[String]) -> Result<Flags, String> {
+    let mut flags = Flags { images: Vec::new(), capture: false, preset: AnalysisPreset::default(), config: None, repeat: 1, json: false };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => flags.json = true,
+            "--capture" => flags.capture = true,
+            "--preset" => {
+                let value = args.next().ok_or("--preset needs fast, balanced or thorough")?;
+                flags.preset = AnalysisPreset::from_name(value).ok_or_else(|| format!("unknown preset '{}'", value))?;
+            }
+            "--config" => flags.config = Some(PathBuf::from(args.next().ok_or("--config needs a path")?)),
+            "--repeat" => {
+                let value = args.next().ok_or("--repeat needs a number of runs")?;
+                flags.repeat = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid run count '{}'", value))?;
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ => flags.images.push(PathBuf::from(arg)),
+        }
+    }
+    match (flags.capture, flags.images.is_empty()) {
+        (true, false) => Err("give image paths or --capture, not both".to_string()),
+        (false, true) => Err("nothing to analyze: give image paths or --capture".to_string()),
+        _ => Ok(flags),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flags = match parse_flags(&args) {
+        Ok(flags) => flags,
+        Err(message) => std::process::exit(usage_error(&message, args.iter().any(|a| a == "--json"))),
+    };
+    std::process::exit(match run(&flags) {
+        Ok(code) => code,
+        Err(error) => {
+            report_error(&error, None, flags.json);
+            EXIT_FAILURE
+        }
+    });
+}
+
+fn run(flags: &Flags) -> anyhow::Result<i32> {
+    let mut config = match &flags.config {
+        Some(path) => LunaConfig::from_file(path)?,
+        None => LunaConfig::default(),
+    };
+    config.validate()?;
+    config.apply_logging()?;
+    flags.preset.apply(&mut config);
+
+    let mut coordinator = AICoordinator::from_config(&config.vision);
+    if config.remote_inference.enabled {
+        coordinator.set_remote_backend(Some(RemoteDetector::new(config.remote_inference.clone())?));
+    }
+
+    let mut code = EXIT_OK;
+    if flags.capture {
+        let mut capture = ScreenCapture::new(CaptureConfig::default());
+        analyze(&mut coordinator, flags, "screen", || luna::core::to_dynamic_image(&capture.capture_screen()?))?;
+    } else {
+        for path in &flags.images {
+            let source = path.display().to_string();
+            if let Err(error) = analyze(&mut coordinator, flags, &source, || Ok(image::open(path)?)) {
+                report_error(&error, Some(&source), flags.json);
+                code = EXIT_FAILURE;
+            }
+        }
+    }
+    Ok(code)
+}
+
+/// Load one frame, analyze it `flags.repeat` times and print the last analysis
+fn analyze(
+    coordinator: &mut AICoordinator,
+    flags: &Flags,
+    source: &str,
+    load: impl FnOnce() -> anyhow::Result<image::DynamicImage>,
+) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let frame = load()?;
+    let load_ms = elapsed_ms(started);
+
+    let mut analysis_ms = Vec::with_capacity(flags.repeat);
+    let mut analysis = None;
+    for _ in 0..flags.repeat {
+        let started = Instant::now();
+        analysis = Some(coordinator.analyze_screen(&frame)?);
+        analysis_ms.push(elapsed_ms(started));
+    }
+    let Some(analysis) = analysis else { return Ok(()) };
+
+    if flags.json {
+        let record = AnalysisRecord {
+            source: Some(source.to_string()),
+            preset: Some(flags.preset.name().to_string()),
+            timings: Some(AnalysisTimings { load_ms, analysis_ms }),
+            ..AnalysisRecord::from(&analysis)
+        };
+        print_json(&schema::to_document(&record));
+    } else {
+        let mut sorted = analysis_ms.clone();
+        sorted.sort_by(f64::total_cmp);
+        println!(
+            "{}: {} element(s), {}x{}, load {:.1}ms, analysis {:.1}ms median over {} run(s) ({:.1}-{:.1}ms)",
+            source,
+            analysis.elements.len(),
+            analysis.screen_size.0,
+            analysis.screen_size.1,
+            load_ms,
+            sorted[sorted.len() / 2],
+            sorted.len(),
+            sorted[0],
+            sorted[sorted.len() - 1],
+        );
+    }
+    Ok(())
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Print `error`, prefixed with the image it happened on
+fn report_error(error: &anyhow::Error, source: Option<&str>, json: bool) {
+    let message = match source {
+        Some(source) => format!("{}: {:#}", source, error),
+        None => format!("{:#}", error),
+    };
+    if json {
+        print_json(&schema::to_document(&ErrorRecord { error: message, ..ErrorRecord::from(error) }));
+    } else {
+        eprintln!("error: {}", message);
+    }
+}
+
+fn usage_error(message: &str, json: bool) -> i32 {
+    if json {
+        let record = ErrorRecord { kind: "usage".to_string(), error: message.to_string(), explanation: None, clarification: None, environment: None };
+        print_json(&schema::to_document(&record));
+    } else {
+        eprintln!("error: {}", message);
+        eprintln!("usage: luna-analyze [--preset fast|balanced|thorough] [--config FILE] [--repeat N] [--json] IMAGE...");
+        eprintln!("       luna-analyze --capture [--preset fast|balanced|thorough] [--config FILE] [--repeat N] [--json]");
+    }
+    EXIT_USAGE
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("error: failed to encode JSON output: {}", e),
+    }
+}
@@ -0,0 +1,1058 @@
+// One-shot commands for driving LUNA from shells and other languages.
+//
+//   luna do "click save" [--dry-run] [--full] [--region X,Y,W,H] [--speed demo|fast|N]
+//           [--restore | --no-restore] [--expect-env env.json] [--json]
+//   luna find "button:submit" [--json]
+//   luna shot [--region X,Y,W,H] [--out shot.png] [--json]
+//   luna watch rules.toml [--once] [--json]
+//   luna remember "deploy button" [--at X,Y] [--json]
+//   luna anchors [--json]
+//   luna forget "deploy button" [--json]
+//   luna compare before.png after.png [--out diff.png] [--json]
+//   luna diagnose [--out report.zip] [--only PARTS | --skip PARTS] [--last N] [--yes] [--json]
+//   luna env save|check env.json [--json]
+//
+// With --json every command prints exactly one JSON document per result on
+// stdout, tagged with a versioned "schema" field (luna.do/v1, luna.find/v1,
+// luna.shot/v1, luna.watch/v1, luna.remember/v1, luna.anchors/v1,
+// luna.forget/v1, luna.compare/v1, luna.diagnose/v1, luna.env/v1, luna.error/v1). The shared documents are defined in
+// core::schema, which states the compatibility rules. Exit status: 0 success, 1 failure (or nothing
+// found), 2 usage error.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use luna::ai::compare::{ChangedElement, ElementChange};
+use luna::core::anchors::Anchor;
+use luna::core::diagnose::{BundlePart, DiagnoseOptions, Manifest};
+use luna::core::environment::{Difference, EnvironmentSnapshot};
+use luna::core::explore::UiMap;
+use luna::core::config::SpeedPreset;
+use luna::core::query::ElementQuery;
+use luna::core::frames::{FrameDiagnosis, FrameMetrics};
+use luna::core::schema::{self, ActionRecord, CommandRecord, ElementRecord, ErrorRecord, FindRecord};
+use luna::core::{CancelToken, CommandSource, ElementBounds};
+use luna::{ExecuteOptions, Luna, LunaError};
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+/// Flags shared by all one-shot commands
+struct Flags {
+    positional: Vec<String>,
+    json: bool,
+    dry_run: bool,
+    full: bool,
+    once: bool,
+    region: Option<ElementBounds>,
+    out: Option<PathBuf>,
+    at: Option<(i32, i32)>,
+    speed: Option<f64>,
+    /// Put the cursor and focus back afterwards; the config decides when unset
+    restore: Option<bool>,
+    seed_anchors: bool,
+    /// Bundle parts to include, or to leave out (`diagnose`)
+    only: Option<Vec<BundlePart>>,
+    skip: Vec<BundlePart>,
+    /// How many recent commands to include
+    last: Option<usize>,
+    /// Accept the defaults instead of asking
+    yes: bool,
+    /// Environment snapshot the desktop must match before anything runs
+    expect_env: Option<PathBuf>,
+}
+
+fn parse_flags(args: &[String]) -> Result<Flags, String> {
+    let mut flags = Flags {
+        positional: Vec::new(),
+        json: false,
+        dry_run: false,
+        full: false,
+        once: false,
+        region: None,
+        out: None,
+        at: None,
+        speed: None,
+        restore: None,
+        seed_anchors: false,
+        only: None,
+        skip: Vec::new(),
+        last: None,
+        expect_env: None,
+        yes: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => flags.json = true,
+            "--dry-run" => flags.dry_run = true,
+            "--full" => flags.full = true,
+            "--once" => flags.once = true,
+            "--restore" => flags.restore = Some(true),
+            "--no-restore" => flags.restore = Some(false),
+            "--seed-anchors" => flags.seed_anchors = true,
+            "--yes" => flags.yes = true,
+            "--only" => flags.only = Some(parse_parts(args.next().ok_or("--only needs a list of parts")?)?),
+            "--skip" => flags.skip = parse_parts(args.next().ok_or("--skip needs a list of parts")?)?,
+            "--last" => {
+                let value = args.next().ok_or("--last needs a number of commands")?;
+                flags.last = Some(value.parse().map_err(|_| format!("invalid count '{}'", value))?);
+            }
+            "--region" => {
+                let value = args.next().ok_or("--region needs X,Y,W,H")?;
+                flags.region = Some(parse_region(value)?);
+            }
+            "--out" => flags.out = Some(PathBuf::from(args.next().ok_or("--out needs a path")?)),
+            "--expect-env" => flags.expect_env = Some(PathBuf::from(args.next().ok_or("--expect-env needs a snapshot file")?)),
+            "--speed" => {
+                let value = args.next().ok_or("--speed needs demo, normal, fast or a multiplier")?;
+                let multiplier = SpeedPreset::from_name(value).map(SpeedPreset::multiplier).or_else(|| value.parse().ok());
+                flags.speed = Some(multiplier.ok_or_else(|| format!("invalid speed '{}'", value))?);
+            }
+            "--at" => {
+                let value = args.next().ok_or("--at needs X,Y")?;
+                flags.at = Some(parse_point(value)?);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ => flags.positional.push(arg.clone()),
+        }
+    }
+    Ok(flags)
+}
+
+fn parse_region(value: &str) -> Result<ElementBounds, String> {
+    let parts: Vec<i32> = value
+        .split(',')
+        .map(|part| part.trim().parse().map_err(|_| format!("invalid region '{}'", value)))
+        .collect::<Result<_, _>>()?;
+    match parts.as_slice() {
+        &[x, y, width, height] if width > 0 && height > 0 => Ok(ElementBounds::new(x, y, width, height)),
+        _ => Err(format!("region must be X,Y,W,H with positive size, got '{}'", value)),
+    }
+}
+
+fn parse_parts(value: &str) -> Result<Vec<BundlePart>, String> {
+    value
+        .split(',')
+        .map(|name| {
+            BundlePart::from_name(name).ok_or_else(|| {
+                let known: Vec<&str> = BundlePart::ALL.iter().map(BundlePart::name).collect();
+                format!("unknown part '{}' (expected: {})", name.trim(), known.join(", "))
+            })
+        })
+        .collect()
+}
+
+fn parse_point(value: &str) -> Result<(i32, i32), String> {
+    match value.split_once(',').map(|(x, y)| (x.trim().parse(), y.trim().parse())) {
+        Some((Ok(x), Ok(y))) => Ok((x, y)),
+        _ => Err(format!("point must be X,Y, got '{}'", value)),
+    }
+}
+
+/// Run a one-shot command, returning the process exit status
+pub fn run(luna: &mut Luna, command: &str, args: &[String]) -> i32 {
+    let flags = match parse_flags(args) {
+        Ok(flags) => flags,
+        Err(message) => return usage_error(&message, args.iter().any(|a| a == "--json")),
+    };
+    let json = flags.json;
+
+    let result = match command {
+        "do" => run_do(luna, &flags),
+        "find" => run_find(luna, &flags),
+        "shot" => run_shot(luna, &flags),
+        "watch" => run_watch(luna, &flags),
+        "remember" => run_remember(luna, &flags),
+        "anchors" => run_anchors(luna, &flags),
+        "forget" => run_forget(luna, &flags),
+        "compare" => run_compare(luna, &flags),
+        "explore" => run_explore(luna, &flags),
+        "diagnose" => run_diagnose(luna, &flags),
+        "env" => run_env(luna, &flags),
+        other => return usage_error(&format!("unknown command '{}'", other), json),
+    };
+    match result {
+        Ok(code) => code,
+        Err(CliError::Usage(message)) => usage_error(&message, json),
+        Err(CliError::Failed(error)) => {
+            report_error(&error, json);
+            EXIT_FAILURE
+        }
+    }
+}
+
+enum CliError {
+    Usage(String),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for CliError {
+    fn from(error: anyhow::Error) -> Self {
+        CliError::Failed(error)
+    }
+}
+
+type CliResult = Result<i32, CliError>;
+
+fn single_argument<'a>(flags: &'a Flags, what: &str) -> Result<&'a str, CliError> {
+    match flags.positional.as_slice() {
+        [value] => Ok(value),
+        _ => Err(CliError::Usage(format!("expected exactly one {}", what))),
+    }
+}
+
+fn run_do(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let command = single_argument(flags, "command, e.g. luna do \"click save\"")?;
+    if let Some(path) = &flags.expect_env {
+        luna.verify_environment(&EnvironmentSnapshot::load(path)?)?;
+    }
+    let options = ExecuteOptions {
+        region_constraint: flags.region.clone(),
+        force_full_pipeline: flags.full,
+        dry_run: flags.dry_run,
+        speed: flags.speed,
+        restore: flags.restore,
+        ..ExecuteOptions::default()
+    };
+    let result = luna.execute_command(command, &options)?;
+
+    if flags.json {
+        print_json(&schema::to_document(&CommandRecord::new(command, &result)));
+    } else {
+        let verb = if result.dry_run { "Planned" } else { "Executed" };
+        println!("{} {} action(s) in {}ms", verb, result.actions.len(), result.processing_time_ms);
+        if let Some(escalation) = &result.escalation {
+            println!("  {}", escalation);
+        }
+        for (index, (action, retries)) in result.actions.iter().zip(result.retries.iter().chain(std::iter::repeat(&0))).enumerate() {
+            match retries {
+                0 => println!("  {:?}", action),
+                n => println!("  {:?} (retried {}x)", action, n),
+            }
+            // A dry run is for reviewing where clicks would land
+            if result.dry_run {
+                if let Some(element) = result.provenance.get(index).and_then(|p| p.element.as_ref()) {
+                    println!("    on {}", element);
+                }
+            }
+        }
+        for step in &result.restoration {
+            println!("  {}", step);
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+fn run_find(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let query = single_argument(flags, "query, e.g. luna find \"button:submit\"")?;
+    let elements = luna.find(query)?;
+
+    if flags.json {
+        print_json(&schema::to_document(&FindRecord {
+            query: ElementQuery::parse(query).to_string(),
+            elements: elements.iter().map(ElementRecord::from).collect(),
+        }));
+    } else {
+        for element in &elements {
+            println!(
+                "{} at ({}, {}) {}x{} confidence {:.2}{}",
+                element.element_type,
+                element.bounds.x,
+                element.bounds.y,
+                element.bounds.width,
+                element.bounds.height,
+                element.confidence,
+                element.text.as_ref().map(|t| format!(" \"{}\"", t)).unwrap_or_default()
+            );
+        }
+    }
+    Ok(if elements.is_empty() { EXIT_FAILURE } else { EXIT_OK })
+}
+
+#[derive(Serialize)]
+struct CompareOutput {
+    schema: &'static str,
+    identical: bool,
+    unchanged: usize,
+    changed: Vec<ChangedOutput>,
+    added: Vec<ElementRecord>,
+    removed: Vec<ElementRecord>,
+    diff: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChangedOutput {
+    before: ElementRecord,
+    after: ElementRecord,
+    changes: Vec<ElementChange>,
+}
+
+impl From<&ChangedElement> for ChangedOutput {
+    fn from(changed: &ChangedElement) -> Self {
+        Self {
+            before: ElementRecord::from(&changed.before),
+            after: ElementRecord::from(&changed.after),
+            changes: changed.changes.clone(),
+        }
+    }
+}
+
+/// Exits 1 when the screens differ, so nightly UI checks can fail on it
+fn run_compare(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let (before, after) = match flags.positional.as_slice() {
+        [before, after] => (Path::new(before), Path::new(after)),
+        _ => return Err(CliError::Usage("expected two screenshots, e.g. luna compare before.png after.png".to_string())),
+    };
+    let (comparison, diff) = luna.compare_image_files(before, after)?;
+    if let Some(path) = &flags.out {
+        save_png(&diff, path)?;
+    }
+
+    if flags.json {
+        print_json(&CompareOutput {
+            schema: "luna.compare/v1",
+            identical: comparison.is_identical(),
+            unchanged: comparison.unchanged,
+            changed: comparison.changed.iter().map(ChangedOutput::from).collect(),
+            added: comparison.added.iter().map(ElementRecord::from).collect(),
+            removed: comparison.removed.iter().map(ElementRecord::from).collect(),
+            diff: flags.out.as_ref().map(|p| p.display().to_string()),
+        });
+    } else {
+        for line in comparison.summary() {
+            println!("  {}", line);
+        }
+        println!(
+            "{} unchanged, {} changed, {} added, {} removed",
+            comparison.unchanged,
+            comparison.changed.len(),
+            comparison.added.len(),
+            comparison.removed.len()
+        );
+        if let Some(path) = &flags.out {
+            println!("Saved diff to {}", path.display());
+        }
+    }
+    Ok(if comparison.is_identical() { EXIT_OK } else { EXIT_FAILURE })
+}
+
+#[derive(Serialize)]
+struct ExploreOutput<'a> {
+    schema: &'static str,
+    #[serde(flatten)]
+    map: &'a UiMap,
+    /// The map saved as JSON next to the frames, with `--out`
+    saved: Option<String>,
+}
+
+/// With `--out DIR`, the annotated frames and `map.json` are written there
+fn run_explore(luna: &mut Luna, flags: &Flags) -> CliResult {
+    if !flags.positional.is_empty() {
+        return Err(CliError::Usage("explore takes no positional arguments; bring the app to the front first".to_string()));
+    }
+    let map = luna.explore(flags.out.as_deref(), flags.seed_anchors, &CancelToken::new())?;
+    let saved = match &flags.out {
+        Some(dir) => {
+            let path = dir.join("map.json");
+            std::fs::write(&path, serde_json::to_string_pretty(&map).map_err(anyhow::Error::from)?)
+                .map_err(anyhow::Error::from)?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    if flags.json {
+        print_json(&ExploreOutput {
+            schema: "luna.explore/v1",
+            map: &map,
+            saved: saved.as_ref().map(|p| p.display().to_string()),
+        });
+    } else {
+        for element in &map.elements {
+            let (x, y, width, height) = element.bounds;
+            println!(
+                "  {:?} {} at ({}, {}) {}x{}{}",
+                element.role,
+                element.element_type,
+                x,
+                y,
+                width,
+                height,
+                element.name.as_ref().map(|n| format!(" \"{}\"", n)).unwrap_or_default()
+            );
+        }
+        println!(
+            "Mapped {} element(s) of {} in {} frame(s), {}ms{}",
+            map.elements.len(),
+            map.app.as_deref().unwrap_or("the screen"),
+            map.frames.len(),
+            map.elapsed_ms,
+            if map.complete { "" } else { " (stopped early)" }
+        );
+        if !map.anchors.is_empty() {
+            println!("Remembered {} anchor(s)", map.anchors.len());
+        }
+        if let Some(path) = &saved {
+            println!("Saved the map to {}", path.display());
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+#[derive(Serialize)]
+struct EnvOutput<'a> {
+    schema: &'static str,
+    path: String,
+    /// The desktop as it is now
+    snapshot: &'a EnvironmentSnapshot,
+    /// `check`: what differs from the saved snapshot; always empty for `save`
+    differences: &'a [Difference],
+}
+
+/// `save` records the desktop; `check` compares it with a saved snapshot,
+/// failing when anything differs
+fn run_env(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let (action, path) = match flags.positional.as_slice() {
+        [action, path] if action == "save" || action == "check" => (action.as_str(), Path::new(path)),
+        _ => return Err(CliError::Usage("expected save or check and a file, e.g. luna env save env.json".to_string())),
+    };
+    let expected = if action == "check" { Some(EnvironmentSnapshot::load(path)?) } else { None };
+    let snapshot = luna.environment_snapshot()?;
+    let mismatch = expected.and_then(|expected| expected.compare(&snapshot));
+    if action == "save" {
+        snapshot.save(path)?;
+    }
+    let differences = mismatch.as_ref().map_or(&[][..], |m| m.differences.as_slice());
+
+    if flags.json {
+        print_json(&EnvOutput { schema: "luna.env/v1", path: path.display().to_string(), snapshot: &snapshot, differences });
+    } else {
+        match (&mismatch, action) {
+            (Some(mismatch), _) => {
+                println!("Environment differs from {}: {}", path.display(), mismatch);
+                for difference in differences {
+                    println!("  {}: expected {}, found {}", difference.setting, difference.expected, difference.found);
+                }
+            }
+            (None, "save") => println!("Saved the environment to {}", path.display()),
+            (None, _) => println!("Environment matches {}", path.display()),
+        }
+    }
+    Ok(if mismatch.is_some() { EXIT_FAILURE } else { EXIT_OK })
+}
+
+#[derive(Serialize)]
+struct DiagnoseOutput {
+    schema: &'static str,
+    path: String,
+    size_bytes: u64,
+    manifest: Manifest,
+}
+
+/// Without `--only`, `--skip`, `--yes` or `--json`, and with a terminal to
+/// ask on, each part is offered in turn
+fn run_diagnose(luna: &mut Luna, flags: &Flags) -> CliResult {
+    if !flags.positional.is_empty() {
+        return Err(CliError::Usage("diagnose takes no positional arguments; use --out FILE".to_string()));
+    }
+    let mut options = DiagnoseOptions::default();
+    if let Some(only) = &flags.only {
+        options.parts = only.clone();
+    }
+    options.parts.retain(|part| !flags.skip.contains(part));
+    if let Some(last) = flags.last {
+        options.transcripts = last;
+    }
+    let interactive = flags.only.is_none() && flags.skip.is_empty() && !flags.yes && !flags.json;
+    if interactive && std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        options.parts = choose_parts().map_err(anyhow::Error::from)?;
+    }
+    if options.parts.is_empty() {
+        return Err(CliError::Usage("nothing to include in the bundle".to_string()));
+    }
+
+    let path = flags.out.clone().unwrap_or_else(|| {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        PathBuf::from(format!("luna-diagnose-{}.zip", stamp))
+    });
+    let bundle = luna.diagnose(&options)?;
+    let size_bytes = bundle.write_zip(&path)?;
+
+    if flags.json {
+        print_json(&DiagnoseOutput { schema: "luna.diagnose/v1", path: path.display().to_string(), size_bytes, manifest: bundle.manifest() });
+    } else {
+        let included: Vec<&str> = bundle.included.iter().map(BundlePart::name).collect();
+        println!("Wrote {} ({} KB): {}", path.display(), size_bytes.div_ceil(1024), included.join(", "));
+        for omission in &bundle.omitted {
+            println!("  left out {}: {}", omission.part.name(), omission.reason);
+        }
+        println!("Review what it holds before attaching it to a report");
+    }
+    Ok(EXIT_OK)
+}
+
+/// Ask for each part, defaulting to yes
+fn choose_parts() -> std::io::Result<Vec<BundlePart>> {
+    use std::io::Write;
+    let mut parts = Vec::new();
+    for part in BundlePart::ALL {
+        print!("Include {} ({})? [Y/n] ", part.name(), part.description());
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "n" | "no") {
+            parts.push(part);
+        }
+    }
+    Ok(parts)
+}
+
+#[derive(Serialize)]
+struct RegionOutput {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Serialize)]
+struct ShotOutput {
+    schema: &'static str,
+    path: String,
+    width: usize,
+    height: usize,
+    region: Option<RegionOutput>,
+}
+
+fn run_shot(luna: &mut Luna, flags: &Flags) -> CliResult {
+    if !flags.positional.is_empty() {
+        return Err(CliError::Usage("shot takes no positional arguments; use --out PATH".to_string()));
+    }
+    let path = flags.out.clone().unwrap_or_else(|| {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        PathBuf::from(format!("luna-shot-{}.png", stamp))
+    });
+
+    let shot = luna.screenshot(flags.region.as_ref())?;
+    save_png(&shot, &path)?;
+
+    if flags.json {
+        print_json(&ShotOutput {
+            schema: "luna.shot/v1",
+            path: path.display().to_string(),
+            width: shot.width,
+            height: shot.height,
+            region: flags.region.as_ref().map(|r| RegionOutput { x: r.x, y: r.y, width: r.width, height: r.height }),
+        });
+    } else {
+        println!("Saved {}x{} screenshot to {}", shot.width, shot.height, path.display());
+    }
+    Ok(EXIT_OK)
+}
+
+pub(crate) fn save_png(shot: &luna::utils::image_processing::Image, path: &Path) -> anyhow::Result<()> {
+    let (width, height) = (shot.width as u32, shot.height as u32);
+    let data = shot.data.clone();
+    let image = match shot.channels {
+        1 => image::GrayImage::from_raw(width, height, data).map(image::DynamicImage::ImageLuma8),
+        3 => image::RgbImage::from_raw(width, height, data).map(image::DynamicImage::ImageRgb8),
+        4 => image::RgbaImage::from_raw(width, height, data).map(image::DynamicImage::ImageRgba8),
+        _ => None,
+    }
+    .ok_or_else(|| LunaError::ScreenCapture(format!("cannot encode {}-channel screenshot", shot.channels)))?;
+    image.save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AnchorOutput {
+    name: String,
+    #[serde(rename = "type")]
+    element_type: String,
+    app: Option<String>,
+    /// Where the element was last seen
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    stale: bool,
+}
+
+impl From<&Anchor> for AnchorOutput {
+    fn from(anchor: &Anchor) -> Self {
+        let fingerprint = &anchor.fingerprint;
+        Self {
+            name: anchor.name.clone(),
+            element_type: fingerprint.element_type.clone(),
+            app: anchor.app.clone(),
+            x: fingerprint.last_x,
+            y: fingerprint.last_y,
+            width: fingerprint.width,
+            height: fingerprint.height,
+            stale: anchor.is_stale(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RememberOutput {
+    schema: &'static str,
+    anchor: AnchorOutput,
+}
+
+fn run_remember(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let name = single_argument(flags, "anchor name, e.g. luna remember \"deploy button\"")?;
+    let anchor = luna.remember(name, flags.at)?;
+
+    if flags.json {
+        print_json(&RememberOutput { schema: "luna.remember/v1", anchor: AnchorOutput::from(&anchor) });
+    } else {
+        let fingerprint = &anchor.fingerprint;
+        println!(
+            "Remembered {} at ({}, {}) as \"{}\"",
+            fingerprint.element_type, fingerprint.last_x, fingerprint.last_y, anchor.name
+        );
+    }
+    Ok(EXIT_OK)
+}
+
+#[derive(Serialize)]
+struct AnchorsOutput {
+    schema: &'static str,
+    anchors: Vec<AnchorOutput>,
+}
+
+fn run_anchors(luna: &mut Luna, flags: &Flags) -> CliResult {
+    if !flags.positional.is_empty() {
+        return Err(CliError::Usage("anchors takes no positional arguments".to_string()));
+    }
+    let anchors: Vec<AnchorOutput> = luna.anchors().list().map(AnchorOutput::from).collect();
+
+    if flags.json {
+        print_json(&AnchorsOutput { schema: "luna.anchors/v1", anchors });
+    } else {
+        for anchor in &anchors {
+            println!(
+                "\"{}\": {} at ({}, {}){}{}",
+                anchor.name,
+                anchor.element_type,
+                anchor.x,
+                anchor.y,
+                anchor.app.as_ref().map(|app| format!(" in '{}'", app)).unwrap_or_default(),
+                if anchor.stale { " [stale]" } else { "" }
+            );
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+#[derive(Serialize)]
+struct ForgetOutput<'a> {
+    schema: &'static str,
+    name: &'a str,
+    removed: bool,
+}
+
+fn run_forget(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let name = single_argument(flags, "anchor name")?;
+    let removed = luna.forget(name)?;
+
+    if flags.json {
+        print_json(&ForgetOutput { schema: "luna.forget/v1", name, removed });
+    } else if removed {
+        println!("Forgot \"{}\"", name);
+    } else {
+        println!("No anchor named \"{}\"", name);
+    }
+    Ok(if removed { EXIT_OK } else { EXIT_FAILURE })
+}
+
+/// One rule from a watch file: when `when` matches an element, run `command`
+#[derive(Debug, Clone, PartialEq)]
+struct WatchRule {
+    name: String,
+    when: ElementQuery,
+    command: String,
+    cooldown: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct WatchFile {
+    interval: Duration,
+    rules: Vec<WatchRule>,
+}
+
+/// Parse a watch file. This is the subset of TOML rules files need:
+///
+/// ```toml
+/// interval_ms = 1000          # how often to analyze the screen
+///
+/// [[rule]]
+/// name = "dismiss updater"
+/// when = "button:later"       # element query, as for `luna find`
+/// do = "click later"          # command, as for `luna do`
+/// cooldown_ms = 5000          # minimum time between runs of this rule
+/// ```
+fn parse_watch_file(source: &str) -> Result<WatchFile, String> {
+    let mut interval_ms = 1000;
+    let mut tables: Vec<HashMap<String, TomlValue>> = Vec::new();
+
+    for (index, raw) in source.lines().enumerate() {
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at = |message: String| format!("line {}: {}", index + 1, message);
+
+        if line == "[[rule]]" {
+            tables.push(HashMap::new());
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(at(format!("unsupported table '{}' (only [[rule]])", line)));
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| at("expected key = value".to_string()))?;
+        let (key, value) = (key.trim().to_string(), parse_toml_value(value.trim()).map_err(at)?);
+
+        match tables.last_mut() {
+            Some(table) => {
+                table.insert(key, value);
+            }
+            None if key == "interval_ms" => {
+                interval_ms = value.as_int().ok_or_else(|| at("interval_ms must be an integer".to_string()))?;
+            }
+            None => return Err(at(format!("unknown top-level key '{}'", key))),
+        }
+    }
+
+    let rules = tables
+        .into_iter()
+        .enumerate()
+        .map(|(index, table)| {
+            let string = |key: &str| match table.get(key) {
+                Some(TomlValue::String(value)) => Ok(value.clone()),
+                _ => Err(format!("rule {} needs a string '{}'", index + 1, key)),
+            };
+            let cooldown_ms = match table.get("cooldown_ms") {
+                None => 5000,
+                Some(value) => value.as_int().ok_or_else(|| format!("rule {}: cooldown_ms must be an integer", index + 1))?,
+            };
+            Ok(WatchRule {
+                name: string("name").unwrap_or_else(|_| format!("rule {}", index + 1)),
+                when: ElementQuery::parse(&string("when")?),
+                command: string("do")?,
+                cooldown: Duration::from_millis(cooldown_ms),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if rules.is_empty() {
+        return Err("no [[rule]] entries".to_string());
+    }
+    Ok(WatchFile { interval: Duration::from_millis(interval_ms), rules })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TomlValue {
+    String(String),
+    Integer(u64),
+    Bool(bool),
+}
+
+impl TomlValue {
+    fn as_int(&self) -> Option<u64> {
+        match self {
+            TomlValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+fn parse_toml_value(value: &str) -> Result<TomlValue, String> {
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut out = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(c @ ('"' | '\\')) => out.push(c),
+                other => return Err(format!("unsupported escape '\\{}'", other.unwrap_or(' '))),
+            }
+        }
+        return Ok(TomlValue::String(out));
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return Ok(TomlValue::String(inner.to_string()));
+    }
+    match value {
+        "true" => Ok(TomlValue::Bool(true)),
+        "false" => Ok(TomlValue::Bool(false)),
+        _ => value
+            .replace('_', "")
+            .parse()
+            .map(TomlValue::Integer)
+            .map_err(|_| format!("unsupported value '{}'", value)),
+    }
+}
+
+/// Drop a trailing `# comment` that is not inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('#', None) => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[derive(Serialize)]
+struct WatchOutput<'a> {
+    schema: &'static str,
+    rule: &'a str,
+    command: &'a str,
+    element: ElementRecord,
+    ok: bool,
+    actions: Vec<ActionRecord>,
+    error: Option<String>,
+    /// Capture/analysis counters; drops mean analysis is slower than capture
+    frames: FrameMetrics,
+}
+
+fn run_watch(luna: &mut Luna, flags: &Flags) -> CliResult {
+    let path = single_argument(flags, "rules file, e.g. luna watch rules.toml")?;
+    let source = std::fs::read_to_string(path).map_err(|e| CliError::Failed(e.into()))?;
+    let watch = parse_watch_file(&source).map_err(|e| CliError::Usage(format!("{}: {}", path, e)))?;
+    if !flags.json {
+        println!("Watching {} rule(s) every {}ms", watch.rules.len(), watch.interval.as_millis());
+    }
+
+    // Frames are captured on their own thread; while rules run or analysis
+    // lags, intermediate frames are dropped rather than queued
+    let mut last_run: HashMap<usize, Instant> = HashMap::new();
+    let mut warned_slow = false;
+    luna.watch_screen(watch.interval, &CancelToken::new(), |luna, analysis, frames| {
+        if !flags.json && !warned_slow && frames.diagnosis() == FrameDiagnosis::SlowAnalysis {
+            warned_slow = true;
+            println!(
+                "Analysis ({:.0}ms) is slower than the {}ms interval; {} frame(s) dropped so far, analyzing only the latest",
+                frames.analysis_ms,
+                watch.interval.as_millis(),
+                frames.dropped
+            );
+        }
+
+        for (index, rule) in watch.rules.iter().enumerate() {
+            if last_run.get(&index).is_some_and(|at| at.elapsed() < rule.cooldown) {
+                continue;
+            }
+            let Some(element) = analysis.elements.iter().find(|e| rule.when.matches(e)) else {
+                continue;
+            };
+            last_run.insert(index, Instant::now());
+
+            let options = ExecuteOptions {
+                source: CommandSource::Watcher,
+                dry_run: flags.dry_run,
+                ..ExecuteOptions::default()
+            };
+            let result = luna.execute_command(&rule.command, &options);
+            if flags.json {
+                print_json(&WatchOutput {
+                    schema: "luna.watch/v1",
+                    rule: &rule.name,
+                    command: &rule.command,
+                    element: ElementRecord::from(element),
+                    ok: result.is_ok(),
+                    actions: result
+                        .as_ref()
+                        .map(|r| r.actions.iter().map(ActionRecord::from).collect())
+                        .unwrap_or_default(),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                    frames: frames.clone(),
+                });
+            } else {
+                match &result {
+                    Ok(r) => println!("[{}] ran '{}': {} action(s)", rule.name, rule.command, r.actions.len()),
+                    Err(e) => println!("[{}] '{}' failed: {}", rule.name, rule.command, e),
+                }
+            }
+        }
+
+        !flags.once
+    })?;
+    Ok(EXIT_OK)
+}
+
+fn report_error(error: &anyhow::Error, json: bool) {
+    if json {
+        print_json(&schema::to_document(&ErrorRecord::from(error)));
+    } else {
+        eprintln!("error: {}", error);
+        if let Some(no_match) = error.downcast_ref::<LunaError>().and_then(LunaError::no_match) {
+            for candidate in &no_match.candidates {
+                eprintln!("  considered {}", candidate);
+            }
+        }
+        if let Some(mismatch) = error.downcast_ref::<LunaError>().and_then(LunaError::environment_mismatch) {
+            for difference in &mismatch.differences {
+                eprintln!("  {}: expected {}, found {}", difference.setting, difference.expected, difference.found);
+            }
+        }
+    }
+}
+
+fn usage_error(message: &str, json: bool) -> i32 {
+    if json {
+        let record = ErrorRecord { kind: "usage".to_string(), error: message.to_string(), explanation: None, clarification: None, environment: None };
+        print_json(&schema::to_document(&record));
+    } else {
+        eprintln!("error: {}", message);
+        eprintln!("usage: luna do \"COMMAND\" [--dry-run] [--full] [--region X,Y,W,H] [--speed demo|fast|N] [--expect-env FILE] [--json]");
+        eprintln!("       luna find \"QUERY\" [--json]");
+        eprintln!("       luna shot [--region X,Y,W,H] [--out PATH] [--json]");
+        eprintln!("       luna watch RULES.toml [--once] [--dry-run] [--json]");
+        eprintln!("       luna remember \"NAME\" [--at X,Y] [--json]");
+        eprintln!("       luna anchors [--json]");
+        eprintln!("       luna forget \"NAME\" [--json]");
+        eprintln!("       luna compare BEFORE.png AFTER.png [--out DIFF.png] [--json]");
+        eprintln!("       luna explore [--out DIR] [--seed-anchors] [--json]");
+        eprintln!("       luna diagnose [--out FILE.zip] [--only PARTS | --skip PARTS] [--last N] [--yes] [--json]");
+        eprintln!("       luna env save|check FILE.json [--json]");
+    }
+    EXIT_USAGE
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("error: failed to encode JSON output: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use luna::LunaConfig;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let flags = parse_flags(&args(&["click save", "--dry-run", "--json", "--region", "0,0,800,600"])).unwrap();
+        assert_eq!(flags.positional, ["click save"]);
+        assert!(flags.dry_run && flags.json && !flags.full);
+        assert_eq!(flags.region, Some(ElementBounds::new(0, 0, 800, 600)));
+
+        assert!(parse_flags(&args(&["--region", "0,0,0,5"])).is_err());
+        assert_eq!(parse_flags(&args(&["deploy", "--at", "812, 433"])).unwrap().at, Some((812, 433)));
+        assert!(parse_flags(&args(&["--at", "812"])).is_err());
+        assert_eq!(parse_flags(&args(&["--speed", "demo"])).unwrap().speed, Some(0.25));
+        assert_eq!(parse_flags(&args(&["--speed", "2"])).unwrap().speed, Some(2.0));
+        assert!(parse_flags(&args(&["--speed", "ludicrous"])).is_err());
+        let only = parse_flags(&args(&["--only", "config, frames"])).unwrap().only;
+        assert_eq!(only, Some(vec![BundlePart::Config, BundlePart::Frames]));
+        assert!(parse_flags(&args(&["--skip", "passwords"])).is_err());
+        assert!(parse_flags(&args(&["--bogus"])).is_err());
+        assert_eq!(parse_flags(&args(&["--expect-env", "env.json"])).unwrap().expect_env, Some(PathBuf::from("env.json")));
+    }
+
+    #[test]
+    fn test_parse_watch_file() {
+        let source = r#"
+            interval_ms = 250  # fast
+
+            [[rule]]
+            name = "dismiss # updater"
+            when = "button:later"
+            do = "click later"
+
+            [[rule]]
+            when = 'dialog'
+            do = "press escape"
+            cooldown_ms = 1_000
+        "#;
+        let watch = parse_watch_file(source).unwrap();
+        assert_eq!(watch.interval, Duration::from_millis(250));
+        assert_eq!(watch.rules[0].name, "dismiss # updater");
+        assert_eq!(watch.rules[0].when, ElementQuery::parse("button:later"));
+        assert_eq!(watch.rules[0].cooldown, Duration::from_secs(5));
+        assert_eq!(watch.rules[1].name, "rule 2");
+        assert_eq!(watch.rules[1].cooldown, Duration::from_secs(1));
+
+        assert!(parse_watch_file("[[rule]]\nname = \"no action\"\nwhen = \"x\"").is_err());
+        assert!(parse_watch_file("interval_ms = 5").is_err());
+        assert!(parse_watch_file("[settings]").is_err());
+    }
+
+    #[test]
+    fn test_do_dry_run_json_schema() {
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let code = run(&mut luna, "do", &args(&["click at 10,20", "--dry-run", "--json"]));
+        assert_eq!(code, EXIT_OK);
+        assert_eq!(luna.get_stats().actions_executed, 0);
+
+        let output = schema::to_document(&CommandRecord {
+            command: "click at 10,20".to_string(),
+            dry_run: true,
+            pipeline_skipped: true,
+            processing_time_ms: 0,
+            actions: vec![ActionRecord::Click { x: 10, y: 20 }],
+            retries: Vec::new(),
+            escalation: None,
+            raw_outputs: None,
+            command_id: "1-0".to_string(),
+            provenance: Vec::new(),
+            restoration: Vec::new(),
+            typing: Vec::new(),
+            metadata: Default::default(),
+        });
+        assert_eq!(output["schema"], "luna.do/v1");
+        assert_eq!(output["actions"][0], serde_json::json!({"type": "click", "x": 10, "y": 20}));
+        assert!(output.get("escalation").is_none(), "only present when a thorough pass ran");
+    }
+
+    #[test]
+    fn test_anchor_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        let mut luna = Luna::new(config).unwrap();
+
+        // Nothing is detected on the blank corner of the synthetic screen
+        assert_eq!(run(&mut luna, "remember", &args(&["deploy button", "--at", "5,5", "--json"])), EXIT_FAILURE);
+        assert_eq!(luna.anchors().list().count(), 0);
+        assert_eq!(run(&mut luna, "anchors", &args(&["--json"])), EXIT_OK);
+        assert_eq!(run(&mut luna, "forget", &args(&["deploy button"])), EXIT_FAILURE);
+        assert_eq!(run(&mut luna, "remember", &[]), EXIT_USAGE);
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        assert_eq!(run(&mut luna, "do", &[]), EXIT_USAGE);
+        assert_eq!(run(&mut luna, "shot", &args(&["extra"])), EXIT_USAGE);
+        assert_eq!(run(&mut luna, "do", &args(&["format c:", "--json"])), EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,400 @@
+/*!
+ * Luna Accessibility - Detected elements as an accessibility tree
+ *
+ * Legacy apps that draw their own controls expose nothing to screen
+ * readers. Luna sees those controls anyway, so an analysis can be handed
+ * to assistive technology as the tree it would have gotten from the app:
+ * detection types become roles, recognized text (or the label paired with
+ * a field) becomes names, and the containment hierarchy becomes the tree.
+ *
+ * Roles follow AccessKit's names and carry the matching UI Automation
+ * control type, so a provider host on either side maps nodes one to one.
+ * Children are in reading order, rows top to bottom and each row left to
+ * right. Node ids are derived from what a node is, not where, so they stay
+ * put across analyses while the element does, even as it moves or is typed
+ * into. Invoking a node means clicking its click point, which a host does
+ * through the command API (`click at X,Y`) so the safety rules still apply.
+ */
+
+use serde::Serialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use super::{ScreenAnalysis, ScreenElement, MODAL_ATTRIBUTE};
+
+/// Id of the node standing for the whole screen
+pub const ROOT_ID: u64 = 0;
+/// Below this visible fraction a node is reported offscreen
+const MIN_ONSCREEN: f64 = 0.5;
+
+/// What a node is, by AccessKit's role names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessibleRole {
+    Window,
+    Dialog,
+    Group,
+    Button,
+    Link,
+    CheckBox,
+    RadioButton,
+    Switch,
+    TextInput,
+    ComboBox,
+    Slider,
+    Tab,
+    MenuItem,
+    ListItem,
+    Image,
+    Label,
+    Unknown,
+}
+
+impl AccessibleRole {
+    /// Role for a detected element type
+    pub fn for_element_type(element_type: &str) -> Self {
+        match element_type {
+            "window" => AccessibleRole::Window,
+            "dialog" => AccessibleRole::Dialog,
+            "panel" | "container" | "toolbar" | "group" => AccessibleRole::Group,
+            // Icons found on screen are clickable; a picture would be "image"
+            "button" | "icon" => AccessibleRole::Button,
+            "link" => AccessibleRole::Link,
+            "checkbox" => AccessibleRole::CheckBox,
+            "radio" => AccessibleRole::RadioButton,
+            "toggle" => AccessibleRole::Switch,
+            "textfield" | "textbox" | "input" => AccessibleRole::TextInput,
+            "combobox" | "dropdown" => AccessibleRole::ComboBox,
+            "slider" => AccessibleRole::Slider,
+            "tab" => AccessibleRole::Tab,
+            "menuitem" => AccessibleRole::MenuItem,
+            "listitem" => AccessibleRole::ListItem,
+            "image" => AccessibleRole::Image,
+            "text" | "label" => AccessibleRole::Label,
+            _ => AccessibleRole::Unknown,
+        }
+    }
+
+    /// UI Automation control type id (`UIA_ButtonControlTypeId` and so on)
+    pub fn uia_control_type(&self) -> u32 {
+        match self {
+            AccessibleRole::Window | AccessibleRole::Dialog => 50032,
+            AccessibleRole::Group => 50026,
+            AccessibleRole::Button | AccessibleRole::Switch => 50000,
+            AccessibleRole::Link => 50005,
+            AccessibleRole::CheckBox => 50002,
+            AccessibleRole::RadioButton => 50013,
+            AccessibleRole::TextInput => 50004,
+            AccessibleRole::ComboBox => 50003,
+            AccessibleRole::Slider => 50015,
+            AccessibleRole::Tab => 50019,
+            AccessibleRole::MenuItem => 50011,
+            AccessibleRole::ListItem => 50007,
+            AccessibleRole::Image => 50006,
+            AccessibleRole::Label => 50020,
+            AccessibleRole::Unknown => 50025,
+        }
+    }
+
+    /// What activating a node of this role does; `None` for what can't be activated
+    fn default_action(&self) -> Option<DefaultAction> {
+        match self {
+            AccessibleRole::TextInput | AccessibleRole::ComboBox => Some(DefaultAction::Focus),
+            AccessibleRole::Window | AccessibleRole::Dialog | AccessibleRole::Group => None,
+            AccessibleRole::Image | AccessibleRole::Label | AccessibleRole::Unknown => None,
+            _ => Some(DefaultAction::Click),
+        }
+    }
+}
+
+/// What a host does when assistive technology activates a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultAction {
+    Click,
+    /// Click into it, so typing goes there
+    Focus,
+}
+
+/// One node of the tree
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessibleNode {
+    pub id: u64,
+    pub role: AccessibleRole,
+    pub uia_control_type: u32,
+    /// Recognized text, or for a field the label paired with it
+    pub name: Option<String>,
+    /// Text in a field, or a slider's position
+    pub value: Option<String>,
+    /// `(x, y, width, height)` in screen pixels
+    pub bounds: (i32, i32, i32, i32),
+    pub parent: Option<u64>,
+    /// In reading order
+    pub children: Vec<u64>,
+    /// For checkboxes, radio buttons and switches
+    pub checked: Option<bool>,
+    pub selected: bool,
+    /// Mostly scrolled away or clipped
+    pub offscreen: bool,
+    /// A modal dialog; what is outside it can't be used until it closes
+    pub modal: bool,
+    pub default_action: Option<DefaultAction>,
+    /// Where to click to activate it
+    pub click_point: Option<(i32, i32)>,
+    /// Detector confidence
+    pub confidence: f32,
+    /// Index of the element in the analysis; `None` for the root
+    #[serde(skip)]
+    pub element: Option<usize>,
+}
+
+/// An analysis as an accessibility tree
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessibilityTree {
+    /// The root (`ROOT_ID`, the screen) first, then every element in reading order
+    pub nodes: Vec<AccessibleNode>,
+    pub screen_size: (u32, u32),
+}
+
+impl AccessibilityTree {
+    pub fn from_analysis(analysis: &ScreenAnalysis) -> Self {
+        // Alike nodes (two unnamed icons) are told apart top to bottom
+        let keys: Vec<u64> = analysis.elements.iter().map(stable_id).collect();
+        let mut by_key: Vec<usize> = (0..keys.len()).collect();
+        by_key.sort_by_key(|&i| {
+            let (x, y) = analysis.elements[i].bounds.center();
+            (keys[i], y, x)
+        });
+        let mut ids = vec![ROOT_ID; keys.len()];
+        let mut used = vec![ROOT_ID];
+        for index in by_key {
+            let mut id = keys[index];
+            while used.contains(&id) {
+                id = id.wrapping_add(1);
+            }
+            used.push(id);
+            ids[index] = id;
+        }
+
+        let (width, height) = analysis.screen_size;
+        let mut nodes = vec![AccessibleNode {
+            id: ROOT_ID,
+            role: AccessibleRole::Window,
+            uia_control_type: AccessibleRole::Window.uia_control_type(),
+            name: Some("Screen".to_string()),
+            value: None,
+            bounds: (0, 0, width as i32, height as i32),
+            parent: None,
+            children: Vec::new(),
+            checked: None,
+            selected: false,
+            offscreen: false,
+            modal: false,
+            default_action: None,
+            click_point: None,
+            confidence: 1.0,
+            element: None,
+        }];
+        let top: Vec<usize> = (0..analysis.elements.len()).filter(|&i| analysis.elements[i].parent.is_none()).collect();
+        let mut pending = vec![(None, reading_order(analysis, top))];
+        while let Some((parent, indices)) = pending.pop() {
+            let parent_node = parent.map_or(0, |p: usize| nodes.iter().position(|n| n.element == Some(p)).expect("parents come first"));
+            nodes[parent_node].children = indices.iter().map(|&i| ids[i]).collect();
+            for &index in &indices {
+                let element = &analysis.elements[index];
+                nodes.push(node(element, index, ids[index], nodes[parent_node].id));
+                if !element.children.is_empty() {
+                    pending.push((Some(index), reading_order(analysis, element.children.clone())));
+                }
+            }
+        }
+        // Depth-first, so walking `nodes` is reading the screen
+        let order = depth_first(&nodes);
+        let mut nodes: Vec<Option<AccessibleNode>> = nodes.into_iter().map(Some).collect();
+        let nodes = order.into_iter().filter_map(|i| nodes[i].take()).collect();
+        Self { nodes, screen_size: analysis.screen_size }
+    }
+
+    pub fn node(&self, id: u64) -> Option<&AccessibleNode> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    /// The node read after `id`
+    pub fn next(&self, id: u64) -> Option<&AccessibleNode> {
+        let at = self.nodes.iter().position(|n| n.id == id)?;
+        self.nodes.get(at + 1)
+    }
+
+    /// The node read before `id`; the root has none
+    pub fn previous(&self, id: u64) -> Option<&AccessibleNode> {
+        let at = self.nodes.iter().position(|n| n.id == id)?;
+        at.checked_sub(1).and_then(|at| self.nodes.get(at))
+    }
+}
+
+fn node(element: &ScreenElement, index: usize, id: u64, parent: u64) -> AccessibleNode {
+    let role = AccessibleRole::for_element_type(&element.element_type);
+    let text = element.text.as_deref().map(str::trim).filter(|t| !t.is_empty()).map(str::to_string);
+    let label = element.label().map(str::to_string);
+    // A field's own text is what was typed into it; its label names it
+    let (name, value) = match role {
+        AccessibleRole::TextInput | AccessibleRole::ComboBox => (label, text),
+        AccessibleRole::Slider => (label.or(text), element.attributes.get("value").cloned()),
+        _ => (text.or(label), None),
+    };
+    let checked = matches!(role, AccessibleRole::CheckBox | AccessibleRole::RadioButton | AccessibleRole::Switch).then(|| {
+        element.attributes.get("checked").is_some_and(|v| v == "true") || element.attributes.get("state").is_some_and(|v| v == "on")
+    });
+    let b = &element.bounds;
+    let default_action = role.default_action();
+    AccessibleNode {
+        id,
+        role,
+        uia_control_type: role.uia_control_type(),
+        name,
+        value,
+        bounds: (b.x, b.y, b.width, b.height),
+        parent: Some(parent),
+        children: Vec::new(),
+        checked,
+        selected: element.attributes.get("selected").is_some_and(|v| v == "true"),
+        offscreen: element.visibility() < MIN_ONSCREEN,
+        modal: element.attributes.contains_key(MODAL_ATTRIBUTE),
+        default_action,
+        click_point: default_action.map(|_| element.click_point()),
+        confidence: element.confidence,
+        element: Some(index),
+    }
+}
+
+/// `indices` in reading order: rows top to bottom, each row left to right.
+/// An element starts a new row when its center is below the bottom of the
+/// row's first element.
+fn reading_order(analysis: &ScreenAnalysis, mut indices: Vec<usize>) -> Vec<usize> {
+    let bounds = |i: usize| &analysis.elements[i].bounds;
+    indices.sort_by_key(|&i| (bounds(i).y * 2 + bounds(i).height, bounds(i).x));
+    let mut rows: Vec<Vec<usize>> = Vec::new();
+    for index in indices {
+        let center = bounds(index).y * 2 + bounds(index).height;
+        match rows.last_mut() {
+            Some(row) if center < bounds(row[0]).y * 2 + bounds(row[0]).height * 2 => row.push(index),
+            _ => rows.push(vec![index]),
+        }
+    }
+    rows.into_iter()
+        .flat_map(|mut row| {
+            row.sort_by_key(|&i| bounds(i).x);
+            row
+        })
+        .collect()
+}
+
+/// Positions of `nodes` visited depth-first from the root, children in order
+fn depth_first(nodes: &[AccessibleNode]) -> Vec<usize> {
+    let position = |id: u64| nodes.iter().position(|n| n.id == id);
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut stack = vec![0];
+    while let Some(at) = stack.pop() {
+        order.push(at);
+        stack.extend(nodes[at].children.iter().rev().filter_map(|&id| position(id)));
+    }
+    order
+}
+
+/// Id from the element's type, label and, unless it is a field, its text
+fn stable_id(element: &ScreenElement) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    element.element_type.hash(&mut hasher);
+    element.label().hash(&mut hasher);
+    let role = AccessibleRole::for_element_type(&element.element_type);
+    if !matches!(role, AccessibleRole::TextInput | AccessibleRole::ComboBox) {
+        element.text.as_deref().map(str::trim).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ElementBounds, LABEL_ATTRIBUTE};
+
+    fn element(element_type: &str, text: &str, bounds: (i32, i32, i32, i32)) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(bounds.0, bounds.1, bounds.2, bounds.3),
+            shape: None,
+            confidence: 0.9,
+            text: (!text.is_empty()).then(|| text.to_string()),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn analysis(elements: Vec<ScreenElement>) -> ScreenAnalysis {
+        let mut analysis =
+            ScreenAnalysis { elements, confidence: 0.9, processing_time_ms: 0, screen_size: (1280, 800), context: Default::default() };
+        analysis.link_hierarchy();
+        analysis.mark_visibility();
+        analysis
+    }
+
+    fn login_form() -> Vec<ScreenElement> {
+        let mut email = element("textfield", "ada@example.com", (200, 100, 300, 30));
+        email.attributes.insert(LABEL_ATTRIBUTE.to_string(), "Email".to_string());
+        let mut remember = element("checkbox", "Remember me", (100, 150, 120, 20));
+        remember.attributes.insert("checked".to_string(), "true".to_string());
+        vec![
+            element("button", "Sign in", (300, 200, 100, 32)),
+            element("text", "Email", (100, 104, 80, 20)),
+            element("dialog", "", (50, 50, 500, 250)),
+            email,
+            element("button", "Cancel", (150, 201, 100, 30)),
+            remember,
+            element("icon", "", (1200, 760, 24, 24)),
+        ]
+    }
+
+    #[test]
+    fn test_tree_names_roles_and_reads_in_order() {
+        let tree = AccessibilityTree::from_analysis(&analysis(login_form()));
+        let read: Vec<(AccessibleRole, Option<&str>)> = tree.nodes.iter().map(|n| (n.role, n.name.as_deref())).collect();
+        assert_eq!(
+            read,
+            [
+                (AccessibleRole::Window, Some("Screen")),
+                (AccessibleRole::Dialog, None),
+                (AccessibleRole::Label, Some("Email")),
+                (AccessibleRole::TextInput, Some("Email")),
+                (AccessibleRole::CheckBox, Some("Remember me")),
+                (AccessibleRole::Button, Some("Cancel")),
+                (AccessibleRole::Button, Some("Sign in")),
+                (AccessibleRole::Button, None),
+            ]
+        );
+        let field = &tree.nodes[3];
+        assert_eq!((field.value.as_deref(), field.default_action, field.uia_control_type), (Some("ada@example.com"), Some(DefaultAction::Focus), 50004));
+        assert_eq!(tree.nodes[4].checked, Some(true));
+        assert_eq!(field.parent, Some(tree.nodes[1].id));
+        assert_eq!(tree.nodes[0].children, [tree.nodes[1].id, tree.nodes[7].id]);
+        assert_eq!(tree.next(field.id).unwrap().role, AccessibleRole::CheckBox);
+        assert_eq!(tree.previous(tree.nodes[1].id).unwrap().id, ROOT_ID);
+        assert_eq!(tree.nodes[6].click_point, Some((350, 216)));
+    }
+
+    #[test]
+    fn test_ids_survive_small_moves_and_other_changes() {
+        let before = AccessibilityTree::from_analysis(&analysis(login_form()));
+        let mut elements = login_form();
+        elements[0].bounds.x += 40;
+        elements[3].text = Some("ada@example.org".to_string());
+        elements.push(element("text", "Wrong password", (100, 260, 200, 20)));
+        let after = AccessibilityTree::from_analysis(&analysis(elements));
+        let id = |tree: &AccessibilityTree, name: &str| tree.nodes.iter().find(|n| n.name.as_deref() == Some(name)).unwrap().id;
+        assert_eq!(id(&before, "Sign in"), id(&after, "Sign in"));
+        assert_eq!(id(&before, "Remember me"), id(&after, "Remember me"));
+        let field = |tree: &AccessibilityTree| tree.nodes.iter().find(|n| n.role == AccessibleRole::TextInput).unwrap().id;
+        assert_eq!(field(&before), field(&after));
+        assert_ne!(id(&after, "Cancel"), id(&after, "Sign in"));
+        assert_eq!(after.nodes.len(), before.nodes.len() + 1);
+    }
+}
@@ -0,0 +1,218 @@
+/*!
+ * Luna Anchors - Named, persistent element locations taught by the user
+ *
+ * `luna remember "deploy button"` fingerprints the element under the cursor
+ * and stores it with the app it belongs to. Click commands that name an
+ * anchor outright ("click the deploy button", or quoted) re-locate it by
+ * fingerprint instead of running the planner. Anchors that can no longer be
+ * found are marked stale so the user knows to teach them again.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ai::fingerprint::ElementFingerprint;
+
+/// File name of the anchor store under the storage root
+pub const ANCHOR_FILE: &str = "anchors.json";
+/// Consecutive failed look-ups after which an anchor is reported as stale
+const STALE_AFTER_MISSES: u32 = 2;
+
+/// A remembered element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Anchor {
+    pub name: String,
+    pub fingerprint: ElementFingerprint,
+    /// Title of the window the element was in, when known
+    pub app: Option<String>,
+    /// Unix seconds
+    pub created_at: u64,
+    /// Unix seconds of the last successful look-up
+    pub last_seen_at: Option<u64>,
+    /// Failed look-ups since the last success
+    pub misses: u32,
+}
+
+impl Anchor {
+    pub fn new(name: &str, fingerprint: ElementFingerprint, app: Option<String>) -> Self {
+        Self {
+            name: normalize_name(name),
+            fingerprint,
+            app,
+            created_at: unix_now(),
+            last_seen_at: None,
+            misses: 0,
+        }
+    }
+
+    /// The UI probably changed and the anchor needs to be taught again
+    pub fn is_stale(&self) -> bool {
+        self.misses >= STALE_AFTER_MISSES
+    }
+}
+
+/// Anchors persisted as one JSON file
+pub struct AnchorStore {
+    path: PathBuf,
+    anchors: BTreeMap<String, Anchor>,
+}
+
+impl AnchorStore {
+    /// Load the store at `path`; a missing file is an empty store
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let anchors = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str::<Vec<Anchor>>(&contents)?
+                .into_iter()
+                .map(|anchor| (anchor.name.clone(), anchor))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, anchors })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Anchor> {
+        self.anchors.get(&normalize_name(name))
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = &Anchor> {
+        self.anchors.values()
+    }
+
+    /// Add or replace an anchor and save
+    pub fn insert(&mut self, anchor: Anchor) -> Result<()> {
+        self.anchors.insert(anchor.name.clone(), anchor);
+        self.save()
+    }
+
+    /// Remove an anchor and save; returns whether it existed
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        let removed = self.anchors.remove(&normalize_name(name)).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Anchor a click command names outright: in quotes ("click \"deploy\" now")
+    /// or as its whole target ("click the deploy button" -> "deploy button").
+    /// Commands that merely resemble a name are left to the planner.
+    pub fn find_in_command(&self, command: &str) -> Option<&Anchor> {
+        let quoted = command.split('"').skip(1).step_by(2);
+        let mut words = command.split_whitespace().skip(1).peekable();
+        while words.next_if(|word| ["on", "the"].contains(&word.to_lowercase().as_str())).is_some() {}
+        let target = words.collect::<Vec<_>>().join(" ");
+
+        quoted
+            .chain(std::iter::once(target.as_str()))
+            .find_map(|name| self.anchors.get(&normalize_name(name)))
+    }
+
+    /// Record a successful look-up, refreshing the fingerprint when the element moved
+    pub fn mark_found(&mut self, name: &str, fingerprint: Option<ElementFingerprint>) -> Result<()> {
+        if let Some(anchor) = self.anchors.get_mut(&normalize_name(name)) {
+            anchor.misses = 0;
+            anchor.last_seen_at = Some(unix_now());
+            if let Some(fingerprint) = fingerprint {
+                anchor.fingerprint = fingerprint;
+            }
+        }
+        self.save()
+    }
+
+    /// Record a failed look-up; returns the updated anchor
+    pub fn mark_missed(&mut self, name: &str) -> Result<Option<Anchor>> {
+        let anchor = self.anchors.get_mut(&normalize_name(name)).map(|anchor| {
+            anchor.misses += 1;
+            anchor.clone()
+        });
+        self.save()?;
+        Ok(anchor)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let anchors: Vec<&Anchor> = self.anchors.values().collect();
+        // Write then rename so a crash never leaves a truncated store
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(&anchors)?)?;
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(x: i32) -> ElementFingerprint {
+        ElementFingerprint {
+            element_type: "button".to_string(),
+            text: None,
+            width: 80,
+            height: 30,
+            hash: 0x0f0f_0f0f_0f0f_0f0f,
+            mean_luma: 120.0,
+            contrast: 60.0,
+            last_x: x,
+            last_y: 10,
+        }
+    }
+
+    #[test]
+    fn test_store_persists_and_resolves_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ANCHOR_FILE);
+
+        let mut store = AnchorStore::open(&path).unwrap();
+        store.insert(Anchor::new("Deploy  Button", fingerprint(10), Some("CI - Firefox".to_string()))).unwrap();
+        store.insert(Anchor::new("search box", fingerprint(300), None)).unwrap();
+
+        let store = AnchorStore::open(&path).unwrap();
+        assert_eq!(store.list().count(), 2);
+        assert_eq!(store.get("deploy button").unwrap().app.as_deref(), Some("CI - Firefox"));
+        assert_eq!(store.find_in_command("click the deploy button").unwrap().name, "deploy button");
+        assert_eq!(store.find_in_command("click on Deploy Button").unwrap().name, "deploy button");
+        assert_eq!(store.find_in_command("click \"search box\" in the toolbar").unwrap().name, "search box");
+        assert!(store.find_in_command("click the save button").is_none());
+        // Close wording is not enough to take the command away from the planner
+        assert!(store.find_in_command("click the deploy buttons").is_none());
+        assert!(store.find_in_command("click the search box twice").is_none());
+    }
+
+    #[test]
+    fn test_misses_mark_anchor_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = AnchorStore::open(dir.path().join(ANCHOR_FILE)).unwrap();
+        store.insert(Anchor::new("deploy", fingerprint(10), None)).unwrap();
+
+        assert!(!store.mark_missed("deploy").unwrap().unwrap().is_stale());
+        assert!(store.mark_missed("deploy").unwrap().unwrap().is_stale());
+
+        store.mark_found("deploy", Some(fingerprint(400))).unwrap();
+        let anchor = store.get("deploy").unwrap();
+        assert!(!anchor.is_stale() && anchor.last_seen_at.is_some());
+        assert_eq!(anchor.fingerprint.last_x, 400);
+
+        assert!(store.remove("deploy").unwrap());
+        assert!(!store.remove("deploy").unwrap());
+    }
+}
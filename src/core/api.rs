@@ -0,0 +1,606 @@
+/*!
+ * Luna API - Authenticated command endpoints for remote clients
+ *
+ * `ApiServer` lets other processes drive a `LunaHandle` over plain HTTP.
+ * Every request carries `Authorization: Bearer <token>`; the token names one
+ * of the clients in `ApiConfig`, and that client's permission decides what it
+ * may do:
+ *
+ * - `analyze_only`: `POST /v1/analyze`, `GET /v1/accessibility` and dry runs
+ *   of `POST /v1/command`.
+ * - `execute_low_risk`: also commands whose actions are all at most low risk;
+ *   anything riskier is refused before the first action runs.
+ * - `execute_all`: any command the safety rules allow.
+ *
+ * `GET /v1/accessibility` answers with the screen as an accessibility tree
+ * (see `accessibility`), for a screen reader bridge to publish; activating a
+ * node is a `click at X,Y` command, so it needs more than `analyze_only`.
+ *
+ * `GET /v1/debug` reports whether commands are paused and where, and
+ * `POST /v1/debug` pauses, steps, resumes, sets breakpoints or changes the
+ * playback speed (see `debugger`); changing anything needs more than
+ * `analyze_only`, since stepping runs actions. Under supervision, `approve`
+ * and `reject` answer the low-confidence action a command is holding. Each connection is answered
+ * on its own thread, so these reach a command that is paused mid-run.
+ *
+ * `GET /v1/review` lists the doubtful detections queued for review and the
+ * thresholds learned per app; `POST /v1/review` (`{"id": 3, "verdict":
+ * "confirm"}`, `"reject"`, or `"relabel"` with a `label`) answers one (see
+ * `review`). Verdicts change how apps are analyzed, so answering needs more
+ * than `analyze_only` too.
+ *
+ * Each client has its own `requests_per_minute` budget. Commands run with
+ * `CommandSource::Api` and the client's name, so confirmation requests and
+ * provenance transcripts name who asked, and every request - refused ones
+ * included - is appended to an audit log when one is set. Requests without
+ * a valid token are limited per peer address to `UNAUTHORIZED_PER_MINUTE`;
+ * past that they are answered 429 and not logged, so whoever can reach the
+ * port can't grow the log without bound.
+ */
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::accessibility::AccessibilityTree;
+use super::config::{ApiClient, ApiConfig, ApiPermission};
+use super::confirmation::constant_time_eq;
+use super::debugger::Breakpoint;
+use super::handle::LunaHandle;
+use super::provenance::ActionProvenance;
+use super::restore::RestoreStep;
+use super::review::Verdict;
+use super::{CommandSource, ExecuteOptions, LunaError, ScreenElement};
+use crate::ai::remote::{read_message, write_response};
+use crate::input::RateLimiter;
+
+/// File name of the API audit log in the transcript store
+pub const AUDIT_FILE: &str = "api_audit.jsonl";
+/// Requests with a missing or unknown token answered (and audited) per peer
+/// address each minute
+pub const UNAUTHORIZED_PER_MINUTE: usize = 10;
+
+/// Body of `POST /v1/command`
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandRequest {
+    pub command: String,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Put the cursor and focus back afterwards; the config decides when unset
+    #[serde(default)]
+    pub restore: Option<bool>,
+}
+
+/// Body of `POST /v1/debug`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum DebugRequest {
+    Pause,
+    Resume,
+    Step,
+    Approve,
+    Reject,
+    AddBreakpoint { breakpoint: Breakpoint },
+    RemoveBreakpoint { breakpoint: Breakpoint },
+    ClearBreakpoints,
+    SetSpeed { multiplier: f64 },
+}
+
+/// Body of `POST /v1/review`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReviewRequest {
+    pub id: u64,
+    #[serde(flatten)]
+    pub verdict: Verdict,
+}
+
+/// Answer to an executed or dry-run command
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandResponse {
+    pub command_id: String,
+    pub dry_run: bool,
+    pub provenance: Vec<ActionProvenance>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub restoration: Vec<RestoreStep>,
+}
+
+/// One detected element in the answer to `POST /v1/analyze`
+#[derive(Debug, Clone, Serialize)]
+pub struct ElementSummary {
+    #[serde(rename = "type")]
+    pub element_type: String,
+    /// `(x, y, width, height)`
+    pub bounds: (i32, i32, i32, i32),
+    pub confidence: f32,
+    pub text: Option<String>,
+}
+
+impl From<&ScreenElement> for ElementSummary {
+    fn from(element: &ScreenElement) -> Self {
+        let b = &element.bounds;
+        Self {
+            element_type: element.element_type.clone(),
+            bounds: (b.x, b.y, b.width, b.height),
+            confidence: element.confidence,
+            text: element.text.clone(),
+        }
+    }
+}
+
+/// One request's line in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix seconds
+    pub at: u64,
+    /// Client the token belongs to; `None` when it was missing or unknown
+    pub client: Option<String>,
+    pub route: String,
+    pub command: Option<String>,
+    pub dry_run: bool,
+    pub status: u16,
+    /// Transcript ID of the command, when it was planned
+    pub command_id: Option<String>,
+}
+
+/// What the server answered, before it is written out and audited
+struct Reply {
+    status: u16,
+    body: serde_json::Value,
+    command: Option<String>,
+    dry_run: bool,
+    command_id: Option<String>,
+}
+
+impl Reply {
+    fn error(status: u16, kind: &str, message: impl std::fmt::Display) -> Self {
+        let body = serde_json::json!({ "error": kind, "message": message.to_string() });
+        Self { status, body, command: None, dry_run: false, command_id: None }
+    }
+}
+
+/// Command API server for one Luna instance
+pub struct ApiServer {
+    listener: TcpListener,
+    handle: LunaHandle,
+    clients: Vec<ApiClient>,
+    limiters: Mutex<HashMap<String, RateLimiter>>,
+    /// Requests without a valid token, keyed by peer address
+    unauthorized: Mutex<RateLimiter>,
+    audit: Option<PathBuf>,
+}
+
+impl ApiServer {
+    pub fn bind(addr: impl ToSocketAddrs, handle: LunaHandle, config: ApiConfig) -> Result<Self> {
+        let limiters = config
+            .clients
+            .iter()
+            .filter(|client| client.requests_per_minute > 0)
+            .map(|client| (client.name.clone(), RateLimiter::new(client.requests_per_minute, usize::MAX)))
+            .collect();
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            handle,
+            clients: config.clients,
+            limiters: Mutex::new(limiters),
+            unauthorized: Mutex::new(RateLimiter::new(UNAUTHORIZED_PER_MINUTE, usize::MAX)),
+            audit: None,
+        })
+    }
+
+    /// Append an `AuditRecord` for every request to `path`
+    pub fn with_audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit = Some(path.into());
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests on a background thread, each connection on a thread
+    /// of its own
+    pub fn spawn(self) -> Result<std::thread::JoinHandle<()>> {
+        info!("Command API listening on {}", self.local_addr()?);
+        let server = Arc::new(self);
+        Ok(std::thread::Builder::new().name("luna-api".to_string()).spawn(move || loop {
+            let connection = match server.listener.accept() {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("API connection failed: {}", e);
+                    continue;
+                }
+            };
+            let server = Arc::clone(&server);
+            let answered = std::thread::Builder::new().name("luna-api-request".to_string()).spawn(move || {
+                if let Err(e) = server.answer(connection) {
+                    warn!("API request failed: {}", e);
+                }
+            });
+            if let Err(e) = answered {
+                warn!("Could not start an API request thread: {}", e);
+            }
+        })?)
+    }
+
+    /// Accept and answer a single connection. Commands run one at a time, in
+    /// the order the worker receives them.
+    pub fn serve_one(&self) -> Result<()> {
+        self.answer(self.listener.accept()?)
+    }
+
+    fn answer(&self, (mut stream, peer): (std::net::TcpStream, SocketAddr)) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let request = read_message(&mut stream)?;
+        let (method, path) = request.route();
+        debug!("API request from {}: {} {}", peer, method, path);
+
+        let client = request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| {
+                let token = token.trim().as_bytes();
+                self.clients.iter().find(|client| constant_time_eq(client.token.as_bytes(), token))
+            });
+        if client.is_none() && !self.within_unauthorized_limit(&peer) {
+            debug!("Too many unauthorized requests from {}; not audited", peer.ip());
+            let reply = Reply::error(429, "rate_limited", "too many requests without a valid token");
+            return write_response(&mut stream, reply.status, "application/json", &serde_json::to_vec(&reply.body)?);
+        }
+        let reply = match client {
+            None => Reply::error(401, "unauthorized", "missing or unknown bearer token"),
+            Some(client) if !self.within_rate_limit(client) => Reply::error(
+                429, "rate_limited", format!("more than {} requests per minute", client.requests_per_minute)),
+            Some(client) => match (method, path) {
+                ("POST", "/v1/analyze") => self.analyze(),
+                ("GET", "/v1/accessibility") => self.accessibility(),
+                ("POST", "/v1/command") => match serde_json::from_slice::<CommandRequest>(&request.body) {
+                    Ok(command) => self.command(client, command),
+                    Err(e) => Reply::error(400, "invalid_argument", e),
+                },
+                ("GET", "/v1/debug") => self.debug_state(),
+                ("POST", "/v1/debug") => match serde_json::from_slice::<DebugRequest>(&request.body) {
+                    Ok(debug) => self.debug(client, debug),
+                    Err(e) => Reply::error(400, "invalid_argument", e),
+                },
+                ("GET", "/v1/review") => self.review_state(),
+                ("POST", "/v1/review") => match serde_json::from_slice::<ReviewRequest>(&request.body) {
+                    Ok(review) => self.review(client, review),
+                    Err(e) => Reply::error(400, "invalid_argument", e),
+                },
+                _ => Reply::error(404, "not_found", format!("no route {} {}", method, path)),
+            },
+        };
+
+        self.audit(client, path, &reply);
+        write_response(&mut stream, reply.status, "application/json", &serde_json::to_vec(&reply.body)?)
+    }
+
+    fn within_unauthorized_limit(&self, peer: &SocketAddr) -> bool {
+        let mut limiter = self.unauthorized.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        limiter.check_rate_limit(&peer.ip().to_string())
+    }
+
+    fn within_rate_limit(&self, client: &ApiClient) -> bool {
+        let mut limiters = self.limiters.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        limiters.get_mut(&client.name).is_none_or(|limiter| limiter.check_rate_limit(&client.name))
+    }
+
+    fn analyze(&self) -> Reply {
+        match self.handle.analyze_current_screen().wait() {
+            Ok(analysis) => {
+                let elements: Vec<ElementSummary> = analysis.elements.iter().map(ElementSummary::from).collect();
+                let body = serde_json::json!({ "elements": elements, "screen_size": analysis.screen_size });
+                Reply { status: 200, body, command: None, dry_run: false, command_id: None }
+            }
+            Err(e) => error_reply(&e),
+        }
+    }
+
+    fn accessibility(&self) -> Reply {
+        let tree = self.handle.analyze_current_screen().wait().map(|analysis| AccessibilityTree::from_analysis(&analysis));
+        match tree.map(|tree| serde_json::to_value(&tree)) {
+            Ok(Ok(body)) => Reply { status: 200, body, command: None, dry_run: false, command_id: None },
+            Ok(Err(e)) => Reply::error(500, "luna", e),
+            Err(e) => error_reply(&e),
+        }
+    }
+
+    fn command(&self, client: &ApiClient, request: CommandRequest) -> Reply {
+        let reply = |reply: Reply| Reply { command: Some(request.command.clone()), dry_run: request.dry_run, ..reply };
+        if !request.dry_run && client.permission == ApiPermission::AnalyzeOnly {
+            return reply(Reply::error(403, "permission_denied", format!("client '{}' may only analyze and dry-run", client.name)));
+        }
+
+        let options = ExecuteOptions {
+            source: CommandSource::Api,
+            dry_run: request.dry_run,
+            max_risk: client.permission.max_risk(),
+            client: Some(client.name.clone()),
+            restore: request.restore,
+            ..ExecuteOptions::default()
+        };
+        info!("API client '{}' sent '{}'", client.name, request.command);
+        match self.handle.execute_command(&request.command, options).wait() {
+            Ok(result) => {
+                let response = CommandResponse {
+                    command_id: result.command_id,
+                    dry_run: result.dry_run,
+                    provenance: result.provenance,
+                    restoration: result.restoration,
+                };
+                let command_id = Some(response.command_id.clone());
+                match serde_json::to_value(&response) {
+                    Ok(body) => reply(Reply { status: 200, body, command: None, dry_run: false, command_id }),
+                    Err(e) => reply(Reply::error(500, "luna", e)),
+                }
+            }
+            Err(e) => reply(error_reply(&e)),
+        }
+    }
+
+    fn debug_state(&self) -> Reply {
+        match serde_json::to_value(self.handle.step_control().state()) {
+            Ok(mut body) => {
+                body["speed"] = serde_json::json!(self.handle.speed());
+                Reply { status: 200, body, command: None, dry_run: false, command_id: None }
+            }
+            Err(e) => Reply::error(500, "luna", e),
+        }
+    }
+
+    fn debug(&self, client: &ApiClient, request: DebugRequest) -> Reply {
+        if client.permission == ApiPermission::AnalyzeOnly {
+            return Reply::error(403, "permission_denied", format!("client '{}' may only analyze and dry-run", client.name));
+        }
+        info!("API client '{}' sent debugger request {:?}", client.name, request);
+        let control = self.handle.step_control();
+        match request {
+            DebugRequest::Pause => control.pause(),
+            DebugRequest::Resume => control.resume(),
+            DebugRequest::Step => control.step(),
+            DebugRequest::Approve | DebugRequest::Reject => {
+                let answered = if matches!(request, DebugRequest::Approve) { control.approve() } else { control.reject() };
+                if !answered {
+                    return Reply::error(409, "not_waiting", "no action is waiting for approval");
+                }
+            }
+            DebugRequest::AddBreakpoint { breakpoint } => control.add_breakpoint(breakpoint),
+            DebugRequest::RemoveBreakpoint { breakpoint } => {
+                control.remove_breakpoint(&breakpoint);
+            }
+            DebugRequest::ClearBreakpoints => control.clear_breakpoints(),
+            DebugRequest::SetSpeed { multiplier } => {
+                if let Err(e) = self.handle.set_speed(multiplier) {
+                    return error_reply(&e);
+                }
+            }
+        }
+        self.debug_state()
+    }
+
+    fn review_state(&self) -> Reply {
+        let state = self.handle.review_queue().wait().and_then(|items| {
+            let thresholds: HashMap<String, f32> = self.handle.learned_app_thresholds().wait()?.into_iter().collect();
+            Ok(serde_json::json!({ "items": items, "thresholds": thresholds }))
+        });
+        match state {
+            Ok(body) => Reply { status: 200, body, command: None, dry_run: false, command_id: None },
+            Err(e) => error_reply(&e),
+        }
+    }
+
+    fn review(&self, client: &ApiClient, request: ReviewRequest) -> Reply {
+        if client.permission == ApiPermission::AnalyzeOnly {
+            return Reply::error(403, "permission_denied", format!("client '{}' may only analyze and dry-run", client.name));
+        }
+        info!("API client '{}' reviewed #{}: {:?}", client.name, request.id, request.verdict);
+        match self.handle.resolve_review(request.id, request.verdict).wait().and_then(|outcome| Ok(serde_json::to_value(outcome)?)) {
+            Ok(body) => Reply { status: 200, body, command: None, dry_run: false, command_id: None },
+            Err(e) => error_reply(&e),
+        }
+    }
+
+    fn audit(&self, client: Option<&ApiClient>, route: &str, reply: &Reply) {
+        let Some(path) = &self.audit else {
+            return;
+        };
+        let record = AuditRecord {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            client: client.map(|client| client.name.clone()),
+            route: route.to_string(),
+            command: reply.command.clone(),
+            dry_run: reply.dry_run,
+            status: reply.status,
+            command_id: reply.command_id.clone(),
+        };
+        if let Err(e) = append_audit(path, &record) {
+            warn!("Could not write the API audit log: {}", e);
+        }
+    }
+}
+
+/// Status code and body for a failed request
+fn error_reply(error: &anyhow::Error) -> Reply {
+    let kind = error.downcast_ref::<LunaError>().map_or("luna", LunaError::kind);
+    let status = match kind {
+        "unsafe" | "permission_denied" => 403,
+        "not_found" => 404,
+        "invalid_argument" | "needs_clarification" => 400,
+        "deferred" | "timeout" => 503,
+        _ => 500,
+    };
+    let mut reply = Reply::error(status, kind, error);
+    // A command that found no target says what came closest
+    if let Some(no_match) = error.downcast_ref::<LunaError>().and_then(LunaError::no_match) {
+        reply.body["explanation"] = serde_json::json!(no_match);
+    }
+    reply
+}
+
+fn append_audit(path: &Path, record: &AuditRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Requests in the audit log at `path`, oldest first
+pub fn read_audit(path: &Path) -> Result<Vec<AuditRecord>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => contents.lines().map(|line| Ok(serde_json::from_str(line)?)).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::remote::{http_request, HttpTimeouts};
+    use crate::core::LunaConfig;
+
+    const READER: &str = "reader-token-0123456789";
+    const RUNNER: &str = "runner-token-0123456789";
+
+    fn api_client(name: &str, token: &str, permission: ApiPermission, requests_per_minute: usize) -> ApiClient {
+        ApiClient { name: name.to_string(), token: token.to_string(), permission, requests_per_minute }
+    }
+
+    /// A server for `clients` in front of a fresh Luna
+    fn api_server(clients: Vec<ApiClient>) -> (LunaHandle, ApiServer) {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+        let server = ApiServer::bind("127.0.0.1:0", handle.clone(), ApiConfig { clients }).unwrap();
+        (handle, server)
+    }
+
+    fn post(server: &ApiServer, path: &str, token: &str, body: &str) -> (u16, serde_json::Value) {
+        let port = server.local_addr().unwrap().port();
+        let (path, body) = (path.to_string(), body.as_bytes().to_vec());
+        let authorization = format!("Bearer {}", token);
+        let client = std::thread::spawn(move || {
+            let timeouts = HttpTimeouts { connect: Duration::from_secs(1), read: Duration::from_secs(30) };
+            let headers = [("Authorization", authorization), ("Content-Type", "application/json".to_string())];
+            http_request("127.0.0.1", port, "POST", &path, &headers, &body, timeouts).unwrap()
+        });
+        server.serve_one().unwrap();
+        let response = client.join().unwrap();
+        (response.status, serde_json::from_slice(&response.body).unwrap())
+    }
+
+    #[test]
+    fn test_clients_are_held_to_their_permissions_and_audited() {
+        let (handle, server) = api_server(vec![
+            api_client("dashboard", READER, ApiPermission::AnalyzeOnly, 2),
+            api_client("ci", RUNNER, ApiPermission::ExecuteLowRisk, 0),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let audit = dir.path().join(AUDIT_FILE);
+        let server = server.with_audit_log(&audit);
+
+        assert_eq!(post(&server, "/v1/command", "wrong-token-0123456789", r#"{"command": "press enter"}"#).0, 401);
+
+        // Analyze-only clients may plan but not execute
+        let (status, body) = post(&server, "/v1/command", READER, r#"{"command": "press enter", "dry_run": true}"#);
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["provenance"][0]["safety"]["risk"], "low");
+        let (status, body) = post(&server, "/v1/command", READER, r#"{"command": "press enter"}"#);
+        assert_eq!((status, body["error"].as_str()), (403, Some("permission_denied")));
+
+        // Two requests a minute, the refused one included
+        assert_eq!(post(&server, "/v1/command", READER, r#"{"command": "press enter", "dry_run": true}"#).0, 429);
+
+        // A low-risk client is refused a high-risk action before anything runs
+        let (status, body) = post(&server, "/v1/command", RUNNER, r#"{"command": "type admin", "dry_run": true}"#);
+        assert_eq!((status, body["error"].as_str()), (403, Some("permission_denied")), "{}", body);
+        assert_eq!(post(&server, "/v1/command", RUNNER, r#"{"command": 7}"#).0, 400);
+
+        let records = read_audit(&audit).unwrap();
+        let summary: Vec<(Option<&str>, u16, bool)> =
+            records.iter().map(|r| (r.client.as_deref(), r.status, r.command_id.is_some())).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (None, 401, false),
+                (Some("dashboard"), 200, true),
+                (Some("dashboard"), 403, false),
+                (Some("dashboard"), 429, false),
+                (Some("ci"), 403, false),
+                (Some("ci"), 400, false),
+            ]
+        );
+        assert_eq!(records[4].command.as_deref(), Some("type admin"));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_unauthorized_requests_are_throttled_before_the_audit_log() {
+        let (handle, server) = api_server(vec![api_client("ci", RUNNER, ApiPermission::ExecuteLowRisk, 0)]);
+        let dir = tempfile::tempdir().unwrap();
+        let audit = dir.path().join(AUDIT_FILE);
+        let server = server.with_audit_log(&audit);
+
+        for _ in 0..UNAUTHORIZED_PER_MINUTE {
+            assert_eq!(post(&server, "/v1/analyze", "guess-0123456789", "").0, 401);
+        }
+        let (status, body) = post(&server, "/v1/analyze", "guess-0123456789", "");
+        assert_eq!((status, body["error"].as_str()), (429, Some("rate_limited")));
+        assert_eq!(read_audit(&audit).unwrap().len(), UNAUTHORIZED_PER_MINUTE);
+        // Clients with a valid token are not held back
+        assert_eq!(post(&server, "/v1/command", RUNNER, r#"{"command": 7}"#).0, 400);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_review_verdicts_need_more_than_analyze_only() {
+        let (handle, server) = api_server(vec![
+            api_client("dashboard", READER, ApiPermission::AnalyzeOnly, 0),
+            api_client("labeler", RUNNER, ApiPermission::ExecuteLowRisk, 0),
+        ]);
+
+        assert_eq!(post(&server, "/v1/review", READER, r#"{"id": 1, "verdict": "confirm"}"#).0, 403);
+        assert_eq!(post(&server, "/v1/review", RUNNER, r#"{"id": 1, "verdict": "maybe"}"#).0, 400);
+        let (status, body) = post(&server, "/v1/review", RUNNER, r#"{"id": 4000000000, "verdict": "relabel", "label": "link"}"#);
+        assert_eq!((status, body["error"].as_str()), (404, Some("not_found")));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_debugger_requests_reach_a_paused_command() {
+        let (handle, server) = api_server(vec![
+            api_client("dashboard", READER, ApiPermission::AnalyzeOnly, 0),
+            api_client("debugger", RUNNER, ApiPermission::ExecuteAll, 0),
+        ]);
+        let port = server.local_addr().unwrap().port();
+        server.spawn().unwrap();
+        let request = move |method: &str, path: &str, token: &str, body: &str| {
+            let timeouts = HttpTimeouts { connect: Duration::from_secs(1), read: Duration::from_secs(30) };
+            let headers = [("Authorization", format!("Bearer {}", token))];
+            let response = http_request("127.0.0.1", port, method, path, &headers, body.as_bytes(), timeouts).unwrap();
+            (response.status, serde_json::from_slice::<serde_json::Value>(&response.body).unwrap())
+        };
+
+        assert_eq!(request("POST", "/v1/debug", READER, r#"{"op": "pause"}"#).0, 403);
+        let (status, state) = request("POST", "/v1/debug", RUNNER, r#"{"op": "add_breakpoint", "breakpoint": {"on": "action", "kind": "keys"}}"#);
+        assert_eq!((status, &state["breakpoints"][0]["kind"]), (200, &serde_json::json!("keys")));
+
+        // The command stops at the breakpoint while other requests are still answered
+        let command = std::thread::spawn(move || request("POST", "/v1/command", RUNNER, r#"{"command": "press enter"}"#));
+        assert_eq!(handle.step_control().wait_until_paused(Duration::from_secs(10)).map(|at| at.index), Some(0));
+        let (_, state) = request("GET", "/v1/debug", READER, "");
+        assert_eq!((state["paused"].as_bool(), state["waiting"]["breakpoint"]["kind"].as_str()), (Some(true), Some("keys")));
+
+        request("POST", "/v1/debug", RUNNER, r#"{"op": "clear_breakpoints"}"#);
+        request("POST", "/v1/debug", RUNNER, r#"{"op": "resume"}"#);
+        let (status, body) = command.join().unwrap();
+        assert_eq!(status, 200, "{}", body);
+        handle.shutdown();
+    }
+}
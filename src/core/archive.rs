@@ -0,0 +1,433 @@
+/*!
+ * Luna Archive - Compressed screenshot sequences with random access
+ *
+ * Consecutive screenshots mostly repeat each other, so storing each as a PNG
+ * wastes most of the disk it takes. An archive keeps a keyframe every
+ * `keyframe_interval` frames (and whenever the frame size changes) and in
+ * between only the XOR against the previous frame, which is zero wherever
+ * nothing moved; every record is zstd-compressed. A JSON-lines index next to
+ * the data file gives each frame's offset, capture time and command ID, so a
+ * reader decodes from the nearest keyframe instead of from the start. The
+ * records carry the same fields, and a missing or short index is rebuilt by
+ * scanning them.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::config::ArchiveConfig;
+use super::LunaError;
+use crate::utils::image_processing::Image;
+
+/// Archive of analyzed frames in the recordings store
+pub const FRAMES_FILE: &str = "frames.lfa";
+/// Extension of archive data files
+pub const EXTENSION: &str = "lfa";
+
+const MAGIC: &[u8; 8] = b"LUNAARC1";
+/// kind, channels, command ID length, width, height, capture time, payload length
+const RECORD_HEADER: u64 = 1 + 1 + 2 + 4 + 4 + 8 + 4;
+
+/// One frame as listed in the index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// Start of the record in the data file
+    pub offset: u64,
+    /// Record length, header included
+    pub bytes: u64,
+    pub keyframe: bool,
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub at_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_id: Option<String>,
+}
+
+impl ArchiveEntry {
+    pub fn captured_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.at_ms)
+    }
+
+    /// Size of the decoded frame
+    pub fn raw_bytes(&self) -> u64 {
+        (self.width * self.height * self.channels) as u64
+    }
+}
+
+/// Index file kept next to `path`
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+/// Appends frames to an archive, creating it if needed
+pub struct ArchiveWriter {
+    path: PathBuf,
+    data: File,
+    index: File,
+    offset: u64,
+    previous: Option<Image>,
+    since_keyframe: usize,
+    keyframe_interval: usize,
+    level: i32,
+}
+
+impl ArchiveWriter {
+    /// Open `path` for appending. The first frame written after opening is
+    /// always a keyframe, so an existing archive is never decoded.
+    pub fn open(path: impl Into<PathBuf>, config: &ArchiveConfig) -> Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Bring the index up to date first, in case the last append was cut short
+        let offset = if path.exists() {
+            ArchiveReader::open(&path)?.end()
+        } else {
+            std::fs::write(&path, MAGIC)?;
+            std::fs::write(index_path(&path), "")?;
+            MAGIC.len() as u64
+        };
+        let data = OpenOptions::new().write(true).open(&path)?;
+        data.set_len(offset)?;
+        let index = OpenOptions::new().append(true).open(index_path(&path))?;
+        Ok(Self {
+            path,
+            data,
+            index,
+            offset,
+            previous: None,
+            since_keyframe: 0,
+            keyframe_interval: config.keyframe_interval.max(1),
+            level: config.compression_level,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a frame; returns its index entry
+    pub fn append(&mut self, frame: &Image, at: SystemTime, command_id: Option<&str>) -> Result<ArchiveEntry> {
+        let keyframe = self.since_keyframe >= self.keyframe_interval
+            || !self.previous.as_ref().is_some_and(|previous| same_shape(previous, frame));
+        let compressed = if keyframe {
+            zstd::bulk::compress(&frame.data, self.level)?
+        } else {
+            let previous = self.previous.as_ref().expect("a delta follows a frame");
+            let delta: Vec<u8> = frame.data.iter().zip(&previous.data).map(|(a, b)| a ^ b).collect();
+            zstd::bulk::compress(&delta, self.level)?
+        };
+        let id = command_id.unwrap_or_default().as_bytes();
+        let at_ms = at.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+
+        let mut record = Vec::with_capacity(RECORD_HEADER as usize + id.len() + compressed.len());
+        record.push(keyframe as u8);
+        record.push(frame.channels as u8);
+        record.extend_from_slice(&(id.len() as u16).to_le_bytes());
+        record.extend_from_slice(&(frame.width as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.height as u32).to_le_bytes());
+        record.extend_from_slice(&at_ms.to_le_bytes());
+        record.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        record.extend_from_slice(id);
+        record.extend_from_slice(&compressed);
+        self.data.seek(SeekFrom::Start(self.offset))?;
+        self.data.write_all(&record)?;
+
+        let entry = ArchiveEntry {
+            offset: self.offset,
+            bytes: record.len() as u64,
+            keyframe,
+            width: frame.width,
+            height: frame.height,
+            channels: frame.channels,
+            at_ms,
+            command_id: command_id.map(str::to_string),
+        };
+        writeln!(self.index, "{}", serde_json::to_string(&entry)?)?;
+        self.offset += entry.bytes;
+        self.since_keyframe = if keyframe { 1 } else { self.since_keyframe + 1 };
+        self.previous = Some(frame.clone());
+        Ok(entry)
+    }
+}
+
+fn same_shape(a: &Image, b: &Image) -> bool {
+    (a.width, a.height, a.channels) == (b.width, b.height, b.channels)
+}
+
+/// Random access to the frames of an archive
+pub struct ArchiveReader {
+    data: File,
+    entries: Vec<ArchiveEntry>,
+    /// Last decoded frame, so reading forward applies one delta at a time
+    cached: Option<(usize, Image)>,
+}
+
+impl ArchiveReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut data = File::open(path)
+            .map_err(|e| LunaError::NotFound(format!("frame archive {}: {}", path.display(), e)))?;
+        let mut magic = [0u8; 8];
+        data.read_exact(&mut magic).ok();
+        if &magic != MAGIC {
+            return Err(LunaError::InvalidArgument(format!("{} is not a frame archive", path.display())).into());
+        }
+        let length = data.metadata()?.len();
+        let entries = match read_index(&index_path(path)) {
+            Some(entries) if entries.last().map_or(MAGIC.len() as u64, |e| e.offset + e.bytes) == length => entries,
+            _ => {
+                let entries = scan(&mut data, length)?;
+                let lines: String = entries.iter().map(|e| serde_json::to_string(e).map(|line| line + "\n")).collect::<Result<_, _>>()?;
+                // Readers work without it; the next one just rescans
+                std::fs::write(index_path(path), lines).ok();
+                entries
+            }
+        };
+        Ok(Self { data, entries, cached: None })
+    }
+
+    /// Frames, oldest first
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// End of the last complete record
+    fn end(&self) -> u64 {
+        self.entries.last().map_or(MAGIC.len() as u64, |e| e.offset + e.bytes)
+    }
+
+    /// Bytes the frames take decoded and as stored
+    pub fn sizes(&self) -> (u64, u64) {
+        let raw = self.entries.iter().map(ArchiveEntry::raw_bytes).sum();
+        (raw, self.end())
+    }
+
+    /// Latest frame captured at or before `time`
+    pub fn index_at(&self, time: SystemTime) -> Option<usize> {
+        self.entries.iter().rposition(|entry| entry.captured_at() <= time)
+    }
+
+    /// Latest frame archived for a command
+    pub fn index_of_command(&self, command_id: &str) -> Option<usize> {
+        self.entries.iter().rposition(|entry| entry.command_id.as_deref() == Some(command_id))
+    }
+
+    /// Decode frame `index`, starting from its keyframe or the cached frame
+    pub fn frame(&mut self, index: usize) -> Result<Image> {
+        if index >= self.entries.len() {
+            return Err(LunaError::NotFound(format!("archived frame {} (archive has {})", index, self.entries.len())).into());
+        }
+        let keyframe = self.entries[..=index].iter().rposition(|entry| entry.keyframe)
+            .ok_or_else(|| LunaError::InvalidArgument(format!("archived frame {} has no keyframe before it", index)))?;
+        let (start, mut frame) = match self.cached.take() {
+            Some((cached, frame)) if (keyframe..=index).contains(&cached) => (cached, frame),
+            _ => (keyframe, self.decode(keyframe, None)?),
+        };
+        for next in start + 1..=index {
+            frame = self.decode(next, Some(frame))?;
+        }
+        self.cached = Some((index, frame.clone()));
+        Ok(frame)
+    }
+
+    pub fn frame_at(&mut self, time: SystemTime) -> Result<Option<Image>> {
+        self.index_at(time).map(|index| self.frame(index)).transpose()
+    }
+
+    pub fn frame_for_command(&mut self, command_id: &str) -> Result<Option<Image>> {
+        self.index_of_command(command_id).map(|index| self.frame(index)).transpose()
+    }
+
+    /// Record `index` on its own (keyframe) or applied to `previous` (delta)
+    fn decode(&mut self, index: usize, previous: Option<Image>) -> Result<Image> {
+        let entry = &self.entries[index];
+        let id_len = entry.command_id.as_ref().map_or(0, String::len) as u64;
+        let payload_at = entry.offset + RECORD_HEADER + id_len;
+        let mut compressed = vec![0u8; (entry.offset + entry.bytes - payload_at) as usize];
+        self.data.seek(SeekFrom::Start(payload_at))?;
+        self.data.read_exact(&mut compressed)?;
+        let raw = zstd::bulk::decompress(&compressed, entry.raw_bytes() as usize)?;
+        if raw.len() as u64 != entry.raw_bytes() {
+            return Err(LunaError::InvalidArgument(format!("archived frame {} decodes to {} bytes, expected {}", index, raw.len(), entry.raw_bytes())).into());
+        }
+        let data = match previous {
+            Some(previous) if !entry.keyframe => previous.data.iter().zip(&raw).map(|(a, b)| a ^ b).collect(),
+            _ => raw,
+        };
+        Ok(Image { width: entry.width, height: entry.height, channels: entry.channels, data })
+    }
+}
+
+fn read_index(path: &Path) -> Option<Vec<ArchiveEntry>> {
+    let file = File::open(path).ok()?;
+    BufReader::new(file)
+        .lines()
+        .map(|line| line.ok().and_then(|line| serde_json::from_str(&line).ok()))
+        .collect()
+}
+
+/// Entries for every complete record in the data file; a record cut short
+/// by a crash ends the scan
+fn scan(data: &mut File, length: u64) -> Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = MAGIC.len() as u64;
+    let mut header = [0u8; RECORD_HEADER as usize];
+    while offset + RECORD_HEADER <= length {
+        data.seek(SeekFrom::Start(offset))?;
+        data.read_exact(&mut header)?;
+        let u16_at = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]) as u64;
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().expect("4 bytes")) as u64;
+        let id_len = u16_at(2);
+        let bytes = RECORD_HEADER + id_len + u32_at(20);
+        if offset + bytes > length {
+            break;
+        }
+        let mut id = vec![0u8; id_len as usize];
+        data.read_exact(&mut id)?;
+        entries.push(ArchiveEntry {
+            offset,
+            bytes,
+            keyframe: header[0] == 1,
+            width: u32_at(4) as usize,
+            height: u32_at(8) as usize,
+            channels: header[1] as usize,
+            at_ms: u64::from_le_bytes(header[12..20].try_into().expect("8 bytes")),
+            command_id: (!id.is_empty()).then(|| String::from_utf8_lossy(&id).into_owned()),
+        });
+        offset += bytes;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::sandbox::SandboxScene;
+    use crate::core::{ExecuteOptions, Luna, LunaConfig};
+
+    /// A 64x48 frame with a 4x4 block at `x`
+    fn frame(x: usize) -> Image {
+        let mut image = Image::from_rgb_data(64, 48, vec![200; 64 * 48 * 3]);
+        for y in 10..14 {
+            for dx in 0..4 {
+                image.set_pixel(x + dx, y, &[0, 0, 255]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_deltas_decode_from_the_nearest_keyframe() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FRAMES_FILE);
+        let config = ArchiveConfig { keyframe_interval: 3, ..ArchiveConfig::default() };
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut writer = ArchiveWriter::open(&path, &config).unwrap();
+        for i in 0..7 {
+            let id = format!("cmd-{}", i);
+            writer.append(&frame(i * 5), start + Duration::from_secs(i as u64), Some(&id)).unwrap();
+        }
+        // A size change forces a keyframe
+        writer.append(&Image::new(8, 8, 1), start + Duration::from_secs(7), None).unwrap();
+        drop(writer);
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        let keyframes: Vec<bool> = reader.entries().iter().map(|e| e.keyframe).collect();
+        assert_eq!(keyframes, [true, false, false, true, false, false, true, true]);
+        let (raw, stored) = reader.sizes();
+        assert!(stored * 20 < raw, "{} stored for {} raw", stored, raw);
+
+        // Backwards, forwards and by key
+        assert_eq!(reader.frame(5).unwrap().data, frame(25).data);
+        assert_eq!(reader.frame(1).unwrap().data, frame(5).data);
+        assert_eq!(reader.frame(2).unwrap().data, frame(10).data);
+        assert_eq!(reader.frame_for_command("cmd-4").unwrap().unwrap().data, frame(20).data);
+        assert_eq!(reader.frame_at(start + Duration::from_millis(6500)).unwrap().unwrap().data, frame(30).data);
+        assert!(reader.frame_at(start - Duration::from_secs(1)).unwrap().is_none());
+        assert_eq!(reader.frame(7).unwrap().channels, 1);
+        assert!(reader.frame(8).is_err());
+    }
+
+    #[test]
+    fn test_appends_after_reopening_and_survives_a_torn_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FRAMES_FILE);
+        let config = ArchiveConfig::default();
+        let mut writer = ArchiveWriter::open(&path, &config).unwrap();
+        writer.append(&frame(0), SystemTime::now(), Some("a")).unwrap();
+        writer.append(&frame(4), SystemTime::now(), Some("b")).unwrap();
+        drop(writer);
+
+        // A crash mid-append: half a record and no index line
+        let length = std::fs::metadata(&path).unwrap().len();
+        let mut data = OpenOptions::new().append(true).open(&path).unwrap();
+        data.write_all(&[0u8; 9]).unwrap();
+        std::fs::remove_file(index_path(&path)).unwrap();
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.entries()[1].command_id.as_deref(), Some("b"));
+        assert_eq!(reader.frame(1).unwrap().data, frame(4).data);
+        assert!(index_path(&path).exists());
+
+        // Reopening drops the torn bytes and starts with a keyframe
+        let mut writer = ArchiveWriter::open(&path, &config).unwrap();
+        let entry = writer.append(&frame(8), SystemTime::now(), Some("c")).unwrap();
+        assert_eq!(entry.offset, length);
+        assert!(entry.keyframe);
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.frame(2).unwrap().data, frame(8).data);
+
+        let not_archive = dir.path().join("shot.png");
+        std::fs::write(&not_archive, b"\x89PNG").unwrap();
+        assert!(ArchiveReader::open(&not_archive).is_err());
+    }
+
+    #[test]
+    fn test_commands_archive_the_frame_they_were_planned_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        let mut luna = Luna::new(config.clone()).unwrap();
+        luna.enter_sandbox(SandboxScene::tutorial());
+        luna.execute_command("click Save", &ExecuteOptions::default()).unwrap();
+        assert!(!luna.frame_archive_path().unwrap().exists(), "archiving is off by default");
+
+        config.archive.enabled = true;
+        luna.update_config(config).unwrap();
+        let first = luna.execute_command("click Save", &ExecuteOptions::default()).unwrap();
+        let second = luna.execute_command("click Cancel", &ExecuteOptions { dry_run: true, ..Default::default() }).unwrap();
+        // Literal coordinates need no frame
+        luna.execute_command("click at 5, 5", &ExecuteOptions::default()).unwrap();
+
+        let mut reader = luna.archived_frames().unwrap();
+        let ids: Vec<_> = reader.entries().iter().map(|e| e.command_id.clone().unwrap()).collect();
+        assert_eq!(ids, [first.command_id.clone(), second.command_id]);
+        assert!(!reader.entries()[1].keyframe, "the same screen again is a delta");
+        let frame = reader.frame_for_command(&first.command_id).unwrap().unwrap();
+        let inspected = luna.inspector().timeline().filter(|shown| shown.command == "click Save").last().unwrap();
+        assert_eq!(frame.data, inspected.frame.data);
+
+        luna.inspect_archived(0).unwrap();
+        let shown = luna.inspector().current().unwrap();
+        assert!(shown.command.starts_with("archived #0"), "{}", shown.command);
+        assert!(!shown.analysis.elements.is_empty());
+        assert!(luna.inspect_archived(2).is_err());
+    }
+}
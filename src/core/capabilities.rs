@@ -0,0 +1,200 @@
+/*!
+ * Luna Capabilities - What works on this machine, probed at startup
+ *
+ * Each subsystem reports Available, Degraded (works with caveats, e.g. a
+ * placeholder backend) or Unavailable. The planner consults the report so it
+ * never plans an action whose input path cannot execute.
+ */
+
+use serde::Serialize;
+
+use super::config::LunaConfig;
+use super::LunaAction;
+
+/// How well one capability works
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "lowercase")]
+pub enum CapabilityStatus {
+    Available,
+    /// Works, with the given caveat
+    Degraded(String),
+    /// Cannot be used, for the given reason
+    Unavailable(String),
+}
+
+impl CapabilityStatus {
+    /// Available or degraded
+    pub fn is_usable(&self) -> bool {
+        !matches!(self, CapabilityStatus::Unavailable(_))
+    }
+}
+
+impl std::fmt::Display for CapabilityStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CapabilityStatus::Available => write!(f, "available"),
+            CapabilityStatus::Degraded(reason) => write!(f, "degraded ({})", reason),
+            CapabilityStatus::Unavailable(reason) => write!(f, "unavailable ({})", reason),
+        }
+    }
+}
+
+/// A detection model or backend and whether it is loaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelCapability {
+    pub name: String,
+    #[serde(flatten)]
+    pub status: CapabilityStatus,
+}
+
+/// Structured report of what this Luna instance can do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// Name of the screen capture backend
+    pub capture_backend: String,
+    pub capture: CapabilityStatus,
+    pub mouse_input: CapabilityStatus,
+    pub keyboard_input: CapabilityStatus,
+    pub models: Vec<ModelCapability>,
+    pub voice: CapabilityStatus,
+    pub overlay: CapabilityStatus,
+}
+
+impl Capabilities {
+    /// Probe the subsystems for this platform and configuration
+    pub fn probe(config: &LunaConfig) -> Self {
+        let (capture_backend, capture, (mouse_input, keyboard_input)) = if config.vnc.enabled {
+            let pending = CapabilityStatus::Degraded(format!(
+                "VNC server {}:{} is connected to on first use", config.vnc.host, config.vnc.port));
+            ("vnc", pending.clone(), (pending.clone(), pending))
+        } else {
+            (
+                capture_backend(),
+                CapabilityStatus::Degraded("placeholder backend returns a synthetic test pattern".to_string()),
+                probe_input(),
+            )
+        };
+        Self {
+            capture_backend: capture_backend.to_string(),
+            capture,
+            mouse_input,
+            keyboard_input,
+            models: probe_models(config),
+            voice: CapabilityStatus::Unavailable("no speech input in this build".to_string()),
+            overlay: CapabilityStatus::Degraded("highlights are computed but there is no window renderer".to_string()),
+        }
+    }
+
+    /// Capability an action needs in order to execute
+    pub fn required_for(&self, action: &LunaAction) -> Option<(&'static str, &CapabilityStatus)> {
+        match action {
+            LunaAction::Click { .. } | LunaAction::ModifierClick { .. } | LunaAction::Scroll { .. } | LunaAction::Hover { .. } => {
+                Some(("mouse input", &self.mouse_input))
+            }
+            LunaAction::Type { .. }
+            | LunaAction::KeyCombo { .. }
+            | LunaAction::KeyDown { .. }
+            | LunaAction::KeyUp { .. }
+            | LunaAction::PasteImage { .. } => {
+                Some(("keyboard input", &self.keyboard_input))
+            }
+            LunaAction::Screenshot { .. }
+            | LunaAction::WaitForElement { .. }
+            | LunaAction::WaitForTextGone { .. }
+            | LunaAction::WaitForScreenIdle { .. } => Some(("screen capture", &self.capture)),
+            LunaAction::Wait { .. } => None,
+        }
+    }
+
+    /// Why `action` cannot execute here, if it cannot
+    pub fn blocker(&self, action: &LunaAction) -> Option<String> {
+        match self.required_for(action)? {
+            (name, CapabilityStatus::Unavailable(reason)) => Some(format!("{} is unavailable: {}", name, reason)),
+            _ => None,
+        }
+    }
+
+    /// Multi-line summary for logs and the REPL
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!("capture ({}): {}", self.capture_backend, self.capture),
+            format!("mouse input: {}", self.mouse_input),
+            format!("keyboard input: {}", self.keyboard_input),
+        ];
+        lines.extend(self.models.iter().map(|m| format!("model {}: {}", m.name, m.status)));
+        lines.push(format!("voice: {}", self.voice));
+        lines.push(format!("overlay: {}", self.overlay));
+        lines.join("\n")
+    }
+}
+
+fn capture_backend() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "gdi"
+    } else if cfg!(target_os = "macos") {
+        "core-graphics"
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        "wayland"
+    } else if std::env::var_os("DISPLAY").is_some() {
+        "x11"
+    } else {
+        "headless"
+    }
+}
+
+fn probe_input() -> (CapabilityStatus, CapabilityStatus) {
+    // Wayland compositors ignore synthetic X11 events; only a uinput device reaches them
+    let uinput_writable = || {
+        std::fs::OpenOptions::new().write(true).open("/dev/uinput").is_ok()
+    };
+    let status = match capture_backend() {
+        "wayland" if !uinput_writable() => CapabilityStatus::Unavailable(
+            "Wayland session without write access to /dev/uinput".to_string(),
+        ),
+        "headless" => CapabilityStatus::Degraded("no display session; input is logged, not injected".to_string()),
+        _ => CapabilityStatus::Degraded("input is logged, not injected".to_string()),
+    };
+    (status.clone(), status)
+}
+
+fn probe_models(config: &LunaConfig) -> Vec<ModelCapability> {
+    let mut models = vec![
+        ModelCapability { name: "edge-detector".to_string(), status: CapabilityStatus::Available },
+        ModelCapability { name: "control-detector".to_string(), status: CapabilityStatus::Available },
+    ];
+    if config.remote_inference.enabled {
+        models.push(ModelCapability {
+            name: format!("remote-inference ({})", config.remote_inference.endpoint),
+            status: CapabilityStatus::Degraded("availability is checked per request; falls back to local".to_string()),
+        });
+    }
+    models
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_reports_every_subsystem() {
+        let capabilities = Capabilities::probe(&LunaConfig::default());
+        assert!(capabilities.capture.is_usable());
+        assert!(!capabilities.voice.is_usable());
+        assert_eq!(capabilities.models.len(), 2);
+        assert!(capabilities.summary().contains("keyboard input"));
+
+        let json = serde_json::to_value(&capabilities).unwrap();
+        assert_eq!(json["voice"]["status"], "unavailable");
+        assert_eq!(json["models"][0]["status"], "available");
+    }
+
+    #[test]
+    fn test_blocker_for_unavailable_input() {
+        let mut capabilities = Capabilities::probe(&LunaConfig::default());
+        capabilities.keyboard_input = CapabilityStatus::Unavailable("no keyboard".to_string());
+
+        assert!(capabilities.blocker(&LunaAction::Type { text: "hi".to_string() }).unwrap().contains("no keyboard"));
+        assert!(capabilities.blocker(&LunaAction::Click { x: 1, y: 1 }).is_none());
+        assert!(capabilities.blocker(&LunaAction::Wait { milliseconds: 10 }).is_none());
+    }
+}
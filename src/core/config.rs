@@ -3,86 +3,1498 @@
  */
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use crate::input::RiskLevel;
+use crate::utils::locale::Locale;
+
 /// Luna configuration structure
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LunaConfig {
+    /// Safety system settings
+    pub safety: SafetyConfig,
+    /// Vision processing settings
+    pub vision: VisionConfig,
+    /// Input system settings
+    pub input: InputConfig,
+    /// Logging settings
+    pub logging: LoggingConfig,
+    /// Disk quotas for transcripts, recordings and caches
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Sandbox limits for user scripts (`scripting` feature)
+    #[serde(default)]
+    pub scripting: ScriptingConfig,
+    /// Export of user corrections as training data
+    #[serde(default)]
+    pub training: TrainingConfig,
+    /// Element detection on a remote inference server
+    #[serde(default)]
+    pub remote_inference: RemoteInferenceConfig,
+    /// Deferring automation during presentations and do-not-disturb
+    #[serde(default)]
+    pub disruption: DisruptionConfig,
+    /// What commands may do depending on what triggered them
+    #[serde(default)]
+    pub contexts: ContextsConfig,
+    /// Per-command CPU, memory and GPU profiling
+    #[serde(default)]
+    pub resources: ResourceConfig,
+    /// Re-checking click targets against the analyzed frame before acting
+    #[serde(default)]
+    pub stale_frame: StaleFrameConfig,
+    /// Following scrolled content instead of analyzing it again
+    #[serde(default)]
+    pub scroll_tracking: ScrollTrackingConfig,
+    /// Analyzing the screen ahead of the next command while idle
+    #[serde(default)]
+    pub pre_analysis: PreAnalysisConfig,
+    /// Coordination with other LUNA processes over who injects input
+    #[serde(default)]
+    pub instance: InstanceConfig,
+    /// Recent analyses kept for the "what did Luna see" inspector
+    #[serde(default)]
+    pub inspector: InspectorConfig,
+    /// Out-of-band approval of high-risk actions in unattended runs
+    #[serde(default)]
+    pub confirmation: ConfirmationConfig,
+    /// Holding low-confidence steps for approval while the rest run unattended
+    #[serde(default)]
+    pub supervision: SupervisionConfig,
+    /// How fast actions are performed
+    #[serde(default)]
+    pub speed: SpeedConfig,
+    /// Pausing while the workstation is locked or on the secure desktop
+    #[serde(default)]
+    pub session: SessionConfig,
+    /// Noticing displays being plugged in, unplugged or resized
+    #[serde(default)]
+    pub displays: DisplaysConfig,
+    /// Re-analyzing with more effort when a command finds no target
+    #[serde(default)]
+    pub escalation: EscalationConfig,
+    /// Unfiltered detector output attached to command results
+    #[serde(default)]
+    pub raw_outputs: RawOutputsConfig,
+    /// Verifying clicks and keeping per-element success statistics
+    #[serde(default)]
+    pub element_stats: ElementStatsConfig,
+    /// Liveness, readiness and metrics endpoints for orchestration
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// Re-reading confirmation dialogs before clicks in destructive commands
+    #[serde(default)]
+    pub destructive_check: DestructiveCheckConfig,
+    /// Reading typed text back from the field and retyping what was lost
+    #[serde(default)]
+    pub typing_check: TypingCheckConfig,
+    /// Captured frames shared with other processes through a channel file
+    #[serde(default)]
+    pub frame_channel: FrameChannelConfig,
+    /// How failed actions are retried, by action type
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Evidence behind executed actions, kept in the transcript store
+    #[serde(default)]
+    pub transcript: TranscriptConfig,
+    /// Clients of the command API and what each may do
+    #[serde(default)]
+    pub api: ApiConfig,
+    /// OCR languages and where their language packs come from
+    #[serde(default)]
+    pub ocr: OcrConfig,
+    /// Queueing doubtful detections for the user to confirm or correct
+    #[serde(default)]
+    pub review: ReviewConfig,
+    /// Compressed archive of analyzed frames
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    /// Finding the text caret for commands that type or paste
+    #[serde(default)]
+    pub caret: CaretConfig,
+    /// Capturing from and sending input to a VNC server instead of this desktop
+    #[serde(default)]
+    pub vnc: VncConfig,
+    /// Tolerances for comparing two screens (see `ai::compare`)
+    #[serde(default)]
+    pub compare: CompareConfig,
+    /// Hovering and scrolling through an app to map its UI (see `core::explore`)
+    #[serde(default)]
+    pub explore: ExploreConfig,
+    /// Noticing hung subsystems and re-initializing them (see `core::watchdog`)
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    /// Latency objectives for actions, with alerts when missed (see `core::slo`)
+    #[serde(default)]
+    pub slo: SloConfig,
+}
+
+/// Outcome of applying a configuration with `Luna::update_config`. An update
+/// is all or nothing: when anything is rejected, nothing was applied.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigReport {
+    /// Changed settings that took effect, as dotted keys
+    pub applied: Vec<String>,
+    pub rejected: Vec<RejectedKey>,
+}
+
+impl ConfigReport {
+    pub fn is_applied(&self) -> bool {
+        self.rejected.is_empty()
+    }
+}
+
+/// A changed setting that was not applied
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RejectedKey {
+    pub key: String,
+    /// What was wrong with it, or that it was rolled back because another key failed
+    pub reason: String,
+}
+
+/// Safety system configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyConfig {
+    /// Enable safety system
+    pub enabled: bool,
+    /// Confidence threshold for dangerous patterns
+    pub threat_threshold: f32,
+    /// Maximum actions per command
+    pub max_actions_per_command: usize,
+    /// Action delay in milliseconds
+    pub action_delay_ms: u64,
+    /// Blocked applications
+    pub blocked_apps: Vec<String>,
+    /// Regexes refusing commands and typed text, on top of the built-in ones
+    #[serde(default)]
+    pub blocked_patterns: Vec<String>,
+}
+
+/// Vision processing configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VisionConfig {
+    /// Confidence threshold for element detection
+    pub confidence_threshold: f32,
+    /// Confidence threshold for elements carrying recognized text; `None`
+    /// applies `confidence_threshold` to them too
+    #[serde(default)]
+    pub text_confidence_threshold: Option<f32>,
+    /// Maximum elements to detect
+    pub max_elements: usize,
+    /// Edge detection sensitivity
+    pub edge_threshold: f32,
+    /// Minimum element size
+    pub min_element_size: u32,
+    /// Screenshot quality (0-100)
+    pub screenshot_quality: u8,
+    /// Whether targets must be inside a modal dialog while one is open
+    #[serde(default)]
+    pub modal_targets: ModalTargets,
+}
+
+/// How an open modal dialog limits where commands look for targets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModalTargets {
+    /// Search the dialog first and the rest of the screen only when it has
+    /// no match
+    #[default]
+    Prefer,
+    /// Only search the dialog; everything behind it is blocked
+    Require,
+    /// Search the whole screen as if no dialog were open
+    Ignore,
+}
+
+/// Partial update to `VisionConfig`; `None` fields are left unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartialVisionConfig {
+    pub confidence_threshold: Option<f32>,
+    pub text_confidence_threshold: Option<f32>,
+    pub max_elements: Option<usize>,
+    pub edge_threshold: Option<f32>,
+    pub min_element_size: Option<u32>,
+    pub screenshot_quality: Option<u8>,
+}
+
+impl PartialVisionConfig {
+    /// Apply the set fields to `config`
+    pub fn apply_to(&self, config: &mut VisionConfig) {
+        if let Some(value) = self.confidence_threshold {
+            config.confidence_threshold = value;
+        }
+        if let Some(value) = self.text_confidence_threshold {
+            config.text_confidence_threshold = Some(value);
+        }
+        if let Some(value) = self.max_elements {
+            config.max_elements = value;
+        }
+        if let Some(value) = self.edge_threshold {
+            config.edge_threshold = value;
+        }
+        if let Some(value) = self.min_element_size {
+            config.min_element_size = value;
+        }
+        if let Some(value) = self.screenshot_quality {
+            config.screenshot_quality = value;
+        }
+    }
+}
+
+impl From<&VisionConfig> for PartialVisionConfig {
+    fn from(config: &VisionConfig) -> Self {
+        Self {
+            confidence_threshold: Some(config.confidence_threshold),
+            text_confidence_threshold: config.text_confidence_threshold,
+            max_elements: Some(config.max_elements),
+            edge_threshold: Some(config.edge_threshold),
+            min_element_size: Some(config.min_element_size),
+            screenshot_quality: Some(config.screenshot_quality),
+        }
+    }
+}
+
+/// Input system configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Click delay in milliseconds
+    pub click_delay_ms: u64,
+    /// Type delay between characters in milliseconds
+    pub type_delay_ms: u64,
+    /// Wheel notches a scroll without an amount moves
+    pub scroll_amount: i32,
+    /// Pixels one wheel notch scrolls, for "scroll down 300 pixels"
+    pub notch_pixels: u32,
+    /// Pixels in a page, for "scroll half a page"
+    pub page_pixels: u32,
+    /// Zoom levels (percent) zoom-in and zoom-out step through; "set zoom
+    /// to 150%" resets to 100 and steps to the nearest one
+    pub zoom_steps: Vec<u32>,
+    /// Enable input validation
+    pub validate_coordinates: bool,
+    /// Locale typed dates and amounts are written for ("de-DE", "fr"); the
+    /// system locale when unset
+    pub locale: Option<String>,
+    /// Put the cursor and the focused window back after each command;
+    /// `ExecuteOptions::restore` overrides it per command
+    pub restore_after_command: bool,
+}
+
+/// Logging configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Log level (error, warn, info, debug, trace)
+    pub level: String,
+    /// Enable file logging
+    pub log_to_file: bool,
+    /// Log directory
+    pub log_dir: Option<PathBuf>,
+    /// Maximum log file size in MB
+    pub max_file_size_mb: u64,
+    /// Maximum number of log files to keep
+    pub max_files: u32,
+}
+
+/// Storage quota configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Storage root (defaults to the user data directory)
+    pub root_dir: Option<PathBuf>,
+    /// Per-store quotas in MB (0 = unlimited)
+    pub transcripts_mb: u64,
+    pub recordings_mb: u64,
+    pub model_cache_mb: u64,
+    pub embeddings_mb: u64,
+    pub history_mb: u64,
+    pub training_data_mb: u64,
+    /// Usage ratio at which a quota warning is emitted
+    pub warn_ratio: f64,
+    /// Cleanup deletes LRU files until usage drops to this ratio of the quota
+    pub clean_target_ratio: f64,
+    /// Clean over-quota stores automatically after commands
+    pub auto_clean: bool,
+    /// Minimum seconds between automatic quota checks
+    pub check_interval_secs: u64,
+}
+
+impl StorageConfig {
+    /// Quota in MB for a store
+    pub fn quota_mb(&self, kind: super::storage::StoreKind) -> u64 {
+        use super::storage::StoreKind;
+        match kind {
+            StoreKind::Transcripts => self.transcripts_mb,
+            StoreKind::Recordings => self.recordings_mb,
+            StoreKind::ModelCache => self.model_cache_mb,
+            StoreKind::Embeddings => self.embeddings_mb,
+            StoreKind::History => self.history_mb,
+            StoreKind::TrainingData => self.training_data_mb,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            root_dir: None,
+            transcripts_mb: 200,
+            recordings_mb: 2048,
+            model_cache_mb: 4096,
+            embeddings_mb: 512,
+            history_mb: 100,
+            training_data_mb: 1024,
+            warn_ratio: 0.9,
+            clean_target_ratio: 0.8,
+            auto_clean: true,
+            check_interval_secs: 300,
+        }
+    }
+}
+
+/// Scripting sandbox configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptingConfig {
+    /// Maximum interpreter operations per run
+    pub max_operations: u64,
+    /// Maximum wall-clock time per run, including waits
+    pub max_runtime_ms: u64,
+    /// Maximum function call nesting
+    pub max_call_depth: usize,
+    /// Maximum string, array and map sizes
+    pub max_collection_size: usize,
+}
+
+impl Default for ScriptingConfig {
+    fn default() -> Self {
+        Self {
+            max_operations: 1_000_000,
+            max_runtime_ms: 30_000,
+            max_call_depth: 32,
+            max_collection_size: 10_000,
+        }
+    }
+}
+
+/// Training data export configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    /// Export corrections reported through the feedback API
+    pub export_corrections: bool,
+    /// Pixels of surrounding screen saved around each corrected element
+    pub context_margin: i32,
+    /// Redact emails, quoted text and long digit runs from exported commands
+    pub redact: bool,
+    /// Additional regex patterns to redact
+    pub redact_patterns: Vec<String>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            export_corrections: false,
+            context_margin: 64,
+            redact: true,
+            redact_patterns: Vec::new(),
+        }
+    }
+}
+
+/// Remote inference server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteInferenceConfig {
+    /// Send frames to the inference server instead of detecting locally
+    pub enabled: bool,
+    /// Server base URL (http only), e.g. "http://gpu-box:8700"
+    pub endpoint: String,
+    pub connect_timeout_ms: u64,
+    /// Read/write timeout for one detection request
+    pub timeout_ms: u64,
+    /// PNG-compress frames; raw RGB is larger but cheaper to encode
+    pub compress: bool,
+    /// Seconds to use local detection after the server fails before trying it again
+    pub retry_after_secs: u64,
+    /// Sent as `Authorization: Bearer <token>`; servers off loopback require one
+    pub token: Option<String>,
+}
+
+impl Default for RemoteInferenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://127.0.0.1:8700".to_string(),
+            connect_timeout_ms: 500,
+            timeout_ms: 3000,
+            compress: true,
+            retry_after_secs: 30,
+            token: None,
+        }
+    }
+}
+
+/// Resource profiling configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceConfig {
+    pub enabled: bool,
+    /// How often peak memory is sampled while a command runs (0 = phase boundaries only)
+    pub sample_interval_ms: u64,
+    /// Query `nvidia-smi` at the start and end of each command
+    pub sample_gpu: bool,
+    /// Number of recent commands whose usage is kept
+    pub history_len: usize,
+}
+
+impl Default for ResourceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_interval_ms: 25,
+            sample_gpu: false,
+            history_len: 100,
+        }
+    }
+}
+
+/// What to do when the screen changed between analysis and an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleFramePolicy {
+    /// Wait for the screen to settle, then capture, analyze and plan again
+    Replan,
+    /// Fail the command with `LunaError::StaleFrame`
+    Abort,
+}
+
+/// Stale-frame guard configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StaleFrameConfig {
+    pub enabled: bool,
+    /// Pixels around a click target that are compared, in each direction
+    pub margin_px: u32,
+    /// Per-channel difference below which a pixel counts as unchanged
+    pub pixel_tolerance: u8,
+    /// Fraction of changed pixels (0.0 - 1.0) that makes the frame stale
+    pub threshold: f64,
+    pub on_stale: StaleFramePolicy,
+    /// Re-plans allowed per command before aborting
+    pub max_replans: u32,
+    /// Pause before re-capturing, so animations can finish
+    pub settle_ms: u64,
+}
+
+impl Default for StaleFrameConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            margin_px: 24,
+            pixel_tolerance: 24,
+            threshold: 0.25,
+            on_stale: StaleFramePolicy::Replan,
+            max_replans: 1,
+            settle_ms: 200,
+        }
+    }
+}
+
+/// Following a scroll that brought a click target into view (see
+/// `vision::scroll`). The shift of the scrolled area is measured, the
+/// analyzed elements are moved by it and a sample of them is checked at
+/// the new place; only when that fails is the screen analyzed again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrollTrackingConfig {
+    pub enabled: bool,
+    /// Profile correlation (0.0 - 1.0) a measured shift needs
+    pub min_correlation: f64,
+    /// Elements checked at their new place
+    pub samples: usize,
+    /// Fraction of an element's pixels (0.0 - 1.0) that must match at its new place
+    pub min_match: f64,
+}
+
+impl Default for ScrollTrackingConfig {
+    fn default() -> Self {
+        Self { enabled: true, min_correlation: 0.9, samples: 4, min_match: 0.9 }
+    }
+}
+
+/// Idle-time pre-analysis. While no command is running, the screen is
+/// analyzed in the background and the result kept warm; a command that
+/// arrives finds the same window and an unchanged frame skips straight to
+/// planning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreAnalysisConfig {
+    pub enabled: bool,
+    /// Time without commands before the screen is analyzed
+    pub idle_ms: u64,
+    /// A warm analysis older than this is not used
+    pub max_age_ms: u64,
+}
+
+impl Default for PreAnalysisConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_ms: 1500,
+            max_age_ms: 30_000,
+        }
+    }
+}
+
+/// Inspector configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InspectorConfig {
+    /// Analyses (frame, elements, planned actions) kept on the timeline; 0 disables
+    pub history: usize,
+}
+
+impl Default for InspectorConfig {
+    fn default() -> Self {
+        Self { history: 8 }
+    }
+}
+
+/// Channel that approves a high-risk action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmerKind {
+    /// y/N question on the terminal
+    Prompt,
+    /// Code from an authenticator app
+    Totp,
+    /// HTTP callback, e.g. relaying to a phone push service
+    Webhook,
+}
+
+/// Out-of-band confirmation configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfirmationConfig {
+    pub enabled: bool,
+    /// Also confirm interactive commands, not just scheduled and watcher ones
+    pub include_interactive: bool,
+    /// Confirmer per risk level; actions at unlisted levels run unconfirmed
+    pub levels: BTreeMap<RiskLevel, ConfirmerKind>,
+    /// Seconds to wait for an answer; no answer denies the action
+    pub timeout_secs: u64,
+    /// Base32 secret shared with the authenticator app
+    pub totp_secret: Option<String>,
+    /// File polled for the TOTP code when there is no terminal (daemon mode)
+    pub totp_code_file: Option<PathBuf>,
+    /// `http://` URL that is POSTed each request and answers approve or deny
+    pub webhook_url: Option<String>,
+    /// Shared secret the webhook signs its answers with (HMAC-SHA256);
+    /// unsigned answers are refused
+    pub webhook_secret: Option<String>,
+}
+
+impl Default for ConfirmationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            include_interactive: false,
+            levels: BTreeMap::from([(RiskLevel::High, ConfirmerKind::Prompt)]),
+            timeout_secs: 120,
+            totp_secret: None,
+            totp_code_file: None,
+            webhook_url: None,
+            webhook_secret: None,
+        }
+    }
+}
+
+/// Supervised execution: actions on confidently detected targets run on
+/// their own, the others wait for approval (see `core::debugger`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SupervisionConfig {
+    pub enabled: bool,
+    /// Detection confidence at or above which a target is acted on without
+    /// asking. Actions without a detected target (keys, typing, explicit
+    /// coordinates) never wait.
+    pub auto_above: f32,
+}
+
+impl Default for SupervisionConfig {
+    fn default() -> Self {
+        Self { enabled: false, auto_above: 0.9 }
+    }
+}
+
+impl SupervisionConfig {
+    /// Threshold for `StepControl::supervise`
+    pub fn threshold(&self) -> Option<f32> {
+        self.enabled.then_some(self.auto_above)
+    }
+}
+
+/// Named execution speeds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeedPreset {
+    /// Slow enough for someone watching to follow every step
+    Demo,
+    #[default]
+    Normal,
+    /// Minimal pauses, for unattended runs
+    Fast,
+}
+
+impl SpeedPreset {
+    /// Speed relative to normal; delays are divided by it
+    pub fn multiplier(self) -> f64 {
+        match self {
+            SpeedPreset::Demo => 0.25,
+            SpeedPreset::Normal => 1.0,
+            SpeedPreset::Fast => 4.0,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "demo" => Some(SpeedPreset::Demo),
+            "normal" => Some(SpeedPreset::Normal),
+            "fast" => Some(SpeedPreset::Fast),
+            _ => None,
+        }
+    }
+}
+
+/// Execution speed configuration. Every pause below, plus
+/// `safety.action_delay_ms` and `input.type_delay_ms`, is divided by the multiplier.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedConfig {
+    pub preset: SpeedPreset,
+    /// Overrides the preset's multiplier; 2.0 runs twice as fast, 0.5 half as fast
+    pub multiplier: Option<f64>,
+    /// Time the cursor takes to glide to a click target at normal speed; 0 jumps
+    pub move_duration_ms: u64,
+    /// Pause before a command's first action at normal speed, so a viewer can follow
+    pub countdown_ms: u64,
+}
+
+impl SpeedConfig {
+    /// Effective multiplier: the explicit one, else the preset's
+    pub fn multiplier(&self) -> f64 {
+        self.multiplier.unwrap_or_else(|| self.preset.multiplier())
+    }
+}
+
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        Self {
+            preset: SpeedPreset::Normal,
+            multiplier: None,
+            move_duration_ms: 120,
+            countdown_ms: 0,
+        }
+    }
+}
+
+/// Slowest and fastest allowed speed multipliers
+pub const SPEED_MULTIPLIER_RANGE: std::ops::RangeInclusive<f64> = 0.05..=20.0;
+
+/// Failure-driven re-analysis. When planning finds no target, the frame is
+/// analyzed again with a more sensitive detector and lower thresholds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EscalationConfig {
+    pub enabled: bool,
+    /// Skip escalation once the command has already taken this long
+    pub budget_ms: u64,
+    /// Confidence thresholds are multiplied by this for the thorough pass
+    pub threshold_scale: f32,
+    /// Edge threshold and minimum element size are multiplied by this
+    pub sensitivity_scale: f32,
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            budget_ms: 3000,
+            threshold_scale: 0.6,
+            sensitivity_scale: 0.6,
+        }
+    }
+}
+
+/// Named analysis efforts for headless runs (`luna-analyze --preset`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisPreset {
+    /// Coarser edges and larger minimum sizes: fewer candidates, sooner
+    Fast,
+    /// The vision settings as configured
+    #[default]
+    Balanced,
+    /// What an escalated analysis uses: lower limits and thresholds, scaled
+    /// by the `escalation` settings, and room for twice as many elements
+    Thorough,
+}
+
+impl AnalysisPreset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "fast" => Some(AnalysisPreset::Fast),
+            "balanced" => Some(AnalysisPreset::Balanced),
+            "thorough" => Some(AnalysisPreset::Thorough),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AnalysisPreset::Fast => "fast",
+            AnalysisPreset::Balanced => "balanced",
+            AnalysisPreset::Thorough => "thorough",
+        }
+    }
+
+    /// Adjust `config.vision` for this effort
+    pub fn apply(self, config: &mut LunaConfig) {
+        let vision = &mut config.vision;
+        match self {
+            AnalysisPreset::Fast => {
+                vision.edge_threshold *= 1.5;
+                vision.min_element_size = vision.min_element_size.saturating_mul(3) / 2;
+            }
+            AnalysisPreset::Balanced => {}
+            AnalysisPreset::Thorough => {
+                let effort = &config.escalation;
+                vision.edge_threshold *= effort.sensitivity_scale;
+                vision.min_element_size = ((vision.min_element_size as f32 * effort.sensitivity_scale).round() as u32).max(1);
+                vision.confidence_threshold *= effort.threshold_scale;
+                vision.text_confidence_threshold = vision.text_confidence_threshold.map(|t| t * effort.threshold_scale);
+                vision.max_elements = vision.max_elements.saturating_mul(2);
+            }
+        }
+    }
+}
+
+/// Health endpoint configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// A worker that takes longer than this to answer a readiness probe is not ready
+    pub probe_timeout_ms: u64,
+    /// Not ready while more requests than this are queued or running
+    pub max_queue_depth: usize,
+    /// Serve Prometheus metrics at `/metrics` on the same port
+    pub metrics: bool,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_timeout_ms: 2000,
+            max_queue_depth: 16,
+            metrics: true,
+        }
+    }
+}
+
+/// OCR language configuration (see `core::language_packs`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OcrConfig {
+    /// Languages recognized when no app profile names others, as pack codes ("en", "de")
+    pub languages: Vec<String>,
+    /// Languages per app, keyed by text in the focused window's title
+    /// (`"Outlook" = ["en", "de"]`)
+    pub app_languages: BTreeMap<String, Vec<String>>,
+    /// Catalog of available packs (`http://host:port/path/catalog.json`);
+    /// `None` uses installed packs only
+    pub catalog_url: Option<String>,
+    /// Hex SHA-256 of the catalog. It comes over plain HTTP and vouches for
+    /// every pack, so nothing is installed from it unless this is set
+    pub catalog_sha256: Option<String>,
+    /// Download a missing pack the first time its language is needed
+    pub auto_download: bool,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self {
+            languages: vec!["en".to_string()],
+            app_languages: BTreeMap::new(),
+            catalog_url: None,
+            catalog_sha256: None,
+            auto_download: true,
+        }
+    }
+}
+
+/// Review queue for doubtful detections (see `core::review`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReviewConfig {
+    /// Queue doubtful click targets and the options of clarifying questions
+    pub enabled: bool,
+    /// Click targets detected with less confidence than this are queued
+    pub confidence_below: f32,
+    /// Pending items kept; the oldest are dropped first
+    pub max_items: usize,
+    /// Analyze each app with the detection threshold learned from its verdicts
+    pub adapt_thresholds: bool,
+    /// Verdicts an app needs before its learned threshold is used
+    pub min_verdicts: usize,
+}
+
+impl Default for ReviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            confidence_below: 0.75,
+            max_items: 200,
+            adapt_thresholds: true,
+            min_verdicts: 10,
+        }
+    }
+}
+
+/// Frame archive (see `core::archive`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Append each analyzed frame, tagged with its command ID, to the archive
+    /// in the recordings store
+    pub enabled: bool,
+    /// Frames from one keyframe to the next; longer runs compress better,
+    /// shorter ones seek faster
+    pub keyframe_interval: usize,
+    /// zstd level, 1 (fast) to 22 (small)
+    pub compression_level: i32,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            keyframe_interval: 30,
+            compression_level: 3,
+        }
+    }
+}
+
+/// Text caret location (see `vision::caret`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaretConfig {
+    /// Look for the caret before planning a command that types or pastes
+    pub enabled: bool,
+    /// When the platform can't say, watch for the caret blinking
+    pub blink_fallback: bool,
+    /// Time between the frames compared, about half a blink period
+    pub blink_ms: u64,
+    /// Frame pairs compared before giving up
+    pub blink_samples: usize,
+}
+
+impl Default for CaretConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            blink_fallback: true,
+            blink_ms: 550,
+            blink_samples: 2,
+        }
+    }
+}
+
+/// Remote desktop over VNC (see `core::vnc`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VncConfig {
+    /// Take frames from and send input to the server instead of this desktop
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// Let other viewers stay connected
+    pub shared: bool,
+    pub connect_timeout_ms: u64,
+    /// Read/write timeout for one frame or input event
+    pub timeout_ms: u64,
+    /// File holding the VNC password, for servers that ask for one;
+    /// `LUNA_VNC_PASSWORD` takes precedence. The password itself never goes here
+    pub password_file: Option<PathBuf>,
+}
+
+impl Default for VncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 5900,
+            shared: true,
+            connect_timeout_ms: 2000,
+            timeout_ms: 5000,
+            password_file: None,
+        }
+    }
+}
+
+/// What counts as a difference between two screens (see `ai::compare`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompareConfig {
+    /// Pixels an element may move or grow before it is reported
+    pub tolerance_px: i32,
+    /// Furthest a labeled element is followed, as a fraction of the screen diagonal
+    pub max_shift: f64,
+    /// Appearance hash bits (out of 64) that may differ before an element is restyled
+    pub max_hash_distance: u32,
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        Self {
+            tolerance_px: 3,
+            max_shift: 0.5,
+            max_hash_distance: 10,
+        }
+    }
+}
+
+/// Mapping an app's UI by hovering and scrolling (see `core::explore`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExploreConfig {
+    /// Exploration stops after this long, with what it has found so far
+    pub budget_secs: u64,
+    /// Pause after each hover or scroll for tooltips and lists to settle
+    pub settle_ms: u64,
+    /// Hover icons and buttons without text to read their tooltips
+    pub hover_unlabeled: bool,
+    /// Wheel notches per step through a scrolled list
+    pub scroll_step: i32,
+    /// Steps into one list before moving on; lists end sooner when a step shows nothing new
+    pub max_scroll_steps: u32,
+}
+
+impl Default for ExploreConfig {
+    fn default() -> Self {
+        Self {
+            budget_secs: 60,
+            settle_ms: 400,
+            hover_unlabeled: true,
+            scroll_step: 3,
+            max_scroll_steps: 20,
+        }
+    }
+}
+
+/// Stall thresholds and degraded mode for hung subsystems (see `core::watchdog`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// How often the watchdog looks at the subsystems
+    pub check_interval_ms: u64,
+    /// Time a screen capture may run without a heartbeat before it counts as hung
+    pub capture_stall_ms: u64,
+    /// Same for one model inference, local or remote
+    pub inference_stall_ms: u64,
+    /// Same for injecting one input action
+    pub input_stall_ms: u64,
+    /// Stalls of one subsystem, without `recover_after` healthy runs in
+    /// between, that put it in degraded mode
+    pub degrade_after: u32,
+    /// Healthy runs in a row that take a subsystem out of degraded mode
+    pub recover_after: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_ms: 500,
+            capture_stall_ms: 5000,
+            inference_stall_ms: 30000,
+            input_stall_ms: 5000,
+            degrade_after: 3,
+            recover_after: 5,
+        }
+    }
+}
+
+/// Latency objectives for actions (see `core::slo`); none by default
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SloConfig {
+    pub objectives: Vec<SloObjective>,
+}
+
+/// "95% of clicks complete within 500ms of the command being received"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SloObjective {
+    /// Names the objective in metrics, `/slo` and alerts
+    pub name: String,
+    /// Action type covered, as in `luna_input_actions_total` (click, type,
+    /// keys, scroll, ...), or "*" for every action
+    pub action: String,
+    /// Time from the command being received to the action completing
+    pub latency_ms: u64,
+    /// Fraction of actions that must complete within `latency_ms`
+    pub target: f64,
+    /// Rolling window compliance is measured over
+    pub window_secs: u64,
+    /// Fewer actions in the window than this are not judged
+    pub min_samples: usize,
+}
+
+impl Default for SloObjective {
+    fn default() -> Self {
+        Self { name: String::new(), action: "click".to_string(), latency_ms: 500, target: 0.95, window_secs: 300, min_samples: 20 }
+    }
+}
+
+/// Frame channel file for other processes (see `vision::frame_channel`); read at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameChannelConfig {
+    pub enabled: bool,
+    /// Channel file; defaults to `luna-<pid>.frames` in the runtime or temp directory
+    pub path: Option<PathBuf>,
+    /// Frames kept, so a slow reader still finds a whole one
+    pub slots: u32,
+    /// Largest frame in bytes; bigger frames are not published
+    pub max_frame_bytes: u64,
+}
+
+impl Default for FrameChannelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            slots: 3,
+            // One 4K RGBA frame
+            max_frame_bytes: 3840 * 2160 * 4,
+        }
+    }
+}
+
+/// Double-check for commands with a destructive verb (delete, remove, ...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DestructiveCheckConfig {
+    /// Re-analyze the screen before each click of such a command
+    pub enabled: bool,
+    /// Regex the clicked dialog's text (or the target's own, outside a
+    /// dialog) must match before the click goes ahead
+    pub dialog_pattern: String,
+    /// Pause before re-capturing, so a dialog can finish opening
+    pub settle_ms: u64,
+}
+
+impl Default for DestructiveCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dialog_pattern: r"(?i)\b(delete|remove|uninstall|format|discard|erase|wipe|permanently|cannot be undone)\b".to_string(),
+            settle_ms: 150,
+        }
+    }
+}
+
+/// Reading fields back after typing into them (see `core::typing`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LunaConfig {
-    /// Safety system settings
-    pub safety: SafetyConfig,
-    /// Vision processing settings
-    pub vision: VisionConfig,
-    /// Input system settings
-    pub input: InputConfig,
-    /// Logging settings
-    pub logging: LoggingConfig,
+#[serde(default)]
+pub struct TypingCheckConfig {
+    /// Read the field after text is typed into one the command clicked.
+    /// Off by default: OCR that drops a character the same way twice is
+    /// taken for a lost keystroke, and the correction then adds one.
+    pub enabled: bool,
+    /// Times missing characters are typed again before giving up
+    pub max_corrections: u32,
+    /// Pause before reading, so a slow app can finish drawing the text
+    pub settle_ms: u64,
 }
 
-/// Safety system configuration
+impl Default for TypingCheckConfig {
+    fn default() -> Self {
+        Self { enabled: false, max_corrections: 2, settle_ms: 150 }
+    }
+}
+
+/// Retries of one action type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Tries in total, the first included; 1 never retries
+    pub max_attempts: u32,
+    /// Pause before the first retry
+    pub backoff_ms: u64,
+    /// Factor the pause grows by after each retry
+    pub backoff_multiplier: f64,
+    /// Before retrying, check that the failed attempt left the screen
+    /// unchanged; an attempt that did something is not repeated
+    pub verify_before_retry: bool,
+}
+
+impl RetryPolicy {
+    /// One attempt, no retries
+    pub fn once() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// Pause before retry number `retry` (1-based)
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        let ms = self.backoff_ms as f64 * self.backoff_multiplier.powi(retry.saturating_sub(1) as i32);
+        std::time::Duration::from_millis(ms as u64)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff_ms: 200,
+            backoff_multiplier: 2.0,
+            verify_before_retry: true,
+        }
+    }
+}
+
+/// Retry policies by action type. Only transient failures (input backend
+/// errors, rate limiting, capture errors) are retried; safety blocks and
+/// invalid actions never are. Typing, key combinations and pastes are not
+/// idempotent: they are retried only with `verify_before_retry`, so text is
+/// never typed twice.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub click: RetryPolicy,
+    #[serde(rename = "type")]
+    pub type_text: RetryPolicy,
+    pub keys: RetryPolicy,
+    pub scroll: RetryPolicy,
+    pub screenshot: RetryPolicy,
+    pub paste: RetryPolicy,
+}
+
+impl RetryConfig {
+    /// Policy for an action type as named in metrics: click, type, keys,
+    /// scroll, screenshot or paste; waits are never retried
+    pub fn policy(&self, kind: &str) -> RetryPolicy {
+        match kind {
+            "click" => self.click.clone(),
+            "type" => self.type_text.clone(),
+            "keys" => self.keys.clone(),
+            "scroll" => self.scroll.clone(),
+            "screenshot" => self.screenshot.clone(),
+            "paste" => self.paste.clone(),
+            _ => RetryPolicy::once(),
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            click: RetryPolicy::default(),
+            type_text: RetryPolicy::once(),
+            keys: RetryPolicy::once(),
+            scroll: RetryPolicy { max_attempts: 2, ..RetryPolicy::default() },
+            screenshot: RetryPolicy { max_attempts: 2, verify_before_retry: false, ..RetryPolicy::default() },
+            paste: RetryPolicy::once(),
+        }
+    }
+}
+
+/// What a command API client may do, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiPermission {
+    /// Analyze the screen and plan commands as dry runs
+    AnalyzeOnly,
+    /// Also execute commands whose actions are all at most low risk
+    ExecuteLowRisk,
+    ExecuteAll,
+}
+
+impl ApiPermission {
+    /// Riskiest action the client may execute; `None` when it may execute nothing
+    pub fn max_risk(self) -> Option<RiskLevel> {
+        match self {
+            ApiPermission::AnalyzeOnly => None,
+            ApiPermission::ExecuteLowRisk => Some(RiskLevel::Low),
+            ApiPermission::ExecuteAll => Some(RiskLevel::Critical),
+        }
+    }
+}
+
+/// A client of the command API, identified by its bearer token
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SafetyConfig {
-    /// Enable safety system
+pub struct ApiClient {
+    /// Recorded as the origin of everything the client runs
+    pub name: String,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: String,
+    pub permission: ApiPermission,
+    /// Requests allowed per minute (0 = unlimited)
+    #[serde(default)]
+    pub requests_per_minute: usize,
+}
+
+/// Shortest bearer token accepted for an API client
+pub const MIN_API_TOKEN_LEN: usize = 16;
+
+/// Command API (see `core::api`). Requests without a known token are refused,
+/// so an empty client list serves nobody.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub clients: Vec<ApiClient>,
+}
+
+/// Provenance transcripts: one line per executed command with the evidence
+/// chain behind each of its actions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscriptConfig {
     pub enabled: bool,
-    /// Confidence threshold for dangerous patterns
-    pub threat_threshold: f32,
-    /// Maximum actions per command
-    pub max_actions_per_command: usize,
-    /// Action delay in milliseconds
-    pub action_delay_ms: u64,
-    /// Blocked applications
-    pub blocked_apps: Vec<String>,
 }
 
-/// Vision processing configuration
+/// Per-element click outcome tracking
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VisionConfig {
-    /// Confidence threshold for element detection
-    pub confidence_threshold: f32,
-    /// Maximum elements to detect
+#[serde(default)]
+pub struct ElementStatsConfig {
+    /// Check each click on a detected element for an effect and record it
+    pub enabled: bool,
+    /// A click with no visible change by then counts as failed
+    pub verify_timeout_ms: u64,
+    /// Fraction of the screen (0.0 - 1.0) that must change to count as an effect
+    pub min_changed: f64,
+    /// Elements remembered; the least recently seen are dropped first
     pub max_elements: usize,
-    /// Edge detection sensitivity
-    pub edge_threshold: f32,
-    /// Minimum element size
-    pub min_element_size: u32,
-    /// Screenshot quality (0-100)
-    pub screenshot_quality: u8,
 }
 
-/// Input system configuration
+impl Default for ElementStatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            verify_timeout_ms: 1000,
+            min_changed: 0.001,
+            max_elements: 1000,
+        }
+    }
+}
+
+/// Raw detector output for research tooling
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct InputConfig {
-    /// Click delay in milliseconds
-    pub click_delay_ms: u64,
-    /// Type delay between characters in milliseconds
-    pub type_delay_ms: u64,
-    /// Scroll amount per action
-    pub scroll_amount: i32,
-    /// Enable input validation
-    pub validate_coordinates: bool,
+#[serde(default)]
+pub struct RawOutputsConfig {
+    /// Attach every candidate and the inference server's raw payload to results
+    pub include: bool,
+    /// Candidates kept, most confident first
+    pub max_candidates: usize,
+    /// Server payloads larger than this (serialized) are dropped
+    pub max_bytes: usize,
 }
 
-/// Logging configuration
+impl Default for RawOutputsConfig {
+    fn default() -> Self {
+        Self {
+            include: false,
+            max_candidates: 1000,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Screen lock and secure desktop handling
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LoggingConfig {
-    /// Log level (error, warn, info, debug, trace)
-    pub level: String,
-    /// Enable file logging
-    pub log_to_file: bool,
-    /// Log directory
-    pub log_dir: Option<PathBuf>,
-    /// Maximum log file size in MB
-    pub max_file_size_mb: u64,
-    /// Maximum number of log files to keep
-    pub max_files: u32,
+#[serde(default)]
+pub struct SessionConfig {
+    /// Hold commands and watchers while the session is locked or a UAC prompt is up
+    pub pause_when_locked: bool,
+    /// How often the lock state is checked while paused (and reused in between)
+    pub poll_ms: u64,
+    /// Give up on a paused command after this long; 0 waits indefinitely
+    pub max_pause_ms: u64,
+    /// Pause after unlock before re-validating, while the desktop redraws
+    pub resume_settle_ms: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            pause_when_locked: true,
+            poll_ms: 1000,
+            max_pause_ms: 600_000,
+            resume_settle_ms: 500,
+        }
+    }
+}
+
+/// Display layout watching (see `core::displays`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaysConfig {
+    /// Check the layout before each command and action
+    pub watch: bool,
+    /// How long a checked layout is trusted before asking again
+    pub poll_ms: u64,
+}
+
+impl Default for DisplaysConfig {
+    fn default() -> Self {
+        Self { watch: true, poll_ms: 1000 }
+    }
 }
 
-impl Default for LunaConfig {
+/// Multi-instance coordination configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstanceConfig {
+    /// Hold a machine-wide lease before injecting input
+    pub enabled: bool,
+    /// Name shown to other instances, e.g. "gui" or "daemon"
+    pub label: String,
+    /// Directory of the lease file (default: the user's runtime directory)
+    pub lease_dir: Option<PathBuf>,
+    /// Hand the lease over when another instance requests it
+    pub yield_on_request: bool,
+}
+
+impl Default for InstanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            label: "luna".to_string(),
+            lease_dir: None,
+            yield_on_request: true,
+        }
+    }
+}
+
+/// What to do with a command while the user is presenting or in do-not-disturb
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisruptionPolicy {
+    /// Run anyway
+    Allow,
+    /// Reject with `LunaError::Deferred` so the caller can retry later
+    Defer,
+    /// Reject as unsafe
+    Refuse,
+}
+
+/// Disruption rule configuration, with a policy per command source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisruptionConfig {
+    pub enabled: bool,
+    /// Commands typed or sent by the user
+    pub interactive: DisruptionPolicy,
+    /// Commands run on a timer or schedule
+    pub scheduled: DisruptionPolicy,
+    /// Commands triggered by a screen or event watcher
+    pub watcher: DisruptionPolicy,
+    /// Commands sent by command API clients
+    pub api: DisruptionPolicy,
+    /// Treat OS do-not-disturb / focus assist as a disruption-sensitive state
+    pub respect_do_not_disturb: bool,
+    /// Executables that indicate a presentation, each optionally followed by
+    /// the flag that starts its slideshow ("soffice --show")
+    pub presentation_apps: Vec<String>,
+    /// Seconds to reuse a focus probe before checking again
+    pub probe_interval_secs: u64,
+}
+
+impl Default for DisruptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interactive: DisruptionPolicy::Allow,
+            scheduled: DisruptionPolicy::Defer,
+            watcher: DisruptionPolicy::Refuse,
+            api: DisruptionPolicy::Defer,
+            respect_do_not_disturb: true,
+            presentation_apps: ["powerpnt", "keynote", "soffice --show", "pdfpc", "pympress", "obs", "obs64"]
+                .iter()
+                .map(|app| app.to_string())
+                .collect(),
+            probe_interval_secs: 5,
+        }
+    }
+}
+
+/// Limits on the actions of commands from one source
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextPolicy {
+    /// Refuse actions riskier than this; `None` allows any risk
+    pub max_risk: Option<RiskLevel>,
+    /// Action types that may run (`LunaAction::KINDS`, e.g. "click",
+    /// "type", "keys"); `None` allows all
+    pub allowed_actions: Option<Vec<String>>,
+    /// Refuse commands with a destructive verb (delete, remove, ...)
+    pub refuse_destructive: bool,
+    /// Confirmer per risk level for this source instead of
+    /// `confirmation.levels`; applies even with confirmation disabled
+    pub confirm: Option<BTreeMap<RiskLevel, ConfirmerKind>>,
+}
+
+/// Execution context policies, one per command source. Unattended sources
+/// get less than a user at the keyboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextsConfig {
+    pub interactive: ContextPolicy,
+    pub scheduled: ContextPolicy,
+    pub watcher: ContextPolicy,
+    /// Commands from command API clients
+    pub api: ContextPolicy,
+}
+
+impl ContextsConfig {
+    pub fn policy(&self, source: super::CommandSource) -> &ContextPolicy {
+        use super::CommandSource;
+        match source {
+            CommandSource::Interactive => &self.interactive,
+            CommandSource::Scheduled => &self.scheduled,
+            CommandSource::Watcher => &self.watcher,
+            CommandSource::Api => &self.api,
+        }
+    }
+}
+
+impl Default for ContextsConfig {
     fn default() -> Self {
+        let kinds = |kinds: &[&str]| Some(kinds.iter().map(|kind| kind.to_string()).collect());
         Self {
-            safety: SafetyConfig::default(),
-            vision: VisionConfig::default(),
-            input: InputConfig::default(),
-            logging: LoggingConfig::default(),
+            interactive: ContextPolicy::default(),
+            scheduled: ContextPolicy {
+                max_risk: Some(RiskLevel::High),
+                ..ContextPolicy::default()
+            },
+            // A watcher reacts to whatever appears on screen, so it may do least
+            watcher: ContextPolicy {
+                max_risk: Some(RiskLevel::Medium),
+                allowed_actions: kinds(&[
+                    "click", "type", "keys", "scroll", "wait", "screenshot",
+                    "wait_for_element", "wait_for_text_gone", "wait_for_screen_idle", "hover",
+                ]),
+                refuse_destructive: true,
+                confirm: None,
+            },
+            api: ContextPolicy {
+                max_risk: Some(RiskLevel::High),
+                ..ContextPolicy::default()
+            },
         }
     }
 }
@@ -99,6 +1511,7 @@ impl Default for SafetyConfig {
                 "powershell.exe".to_string(),
                 "regedit.exe".to_string(),
             ],
+            blocked_patterns: Vec::new(),
         }
     }
 }
@@ -107,10 +1520,12 @@ impl Default for VisionConfig {
     fn default() -> Self {
         Self {
             confidence_threshold: 0.6,
+            text_confidence_threshold: None,
             max_elements: 50,
             edge_threshold: 30.0,
             min_element_size: 20,
             screenshot_quality: 85,
+            modal_targets: ModalTargets::default(),
         }
     }
 }
@@ -121,7 +1536,13 @@ impl Default for InputConfig {
             click_delay_ms: 50,
             type_delay_ms: 10,
             scroll_amount: 3,
+            notch_pixels: 40,
+            page_pixels: 600,
+            // Chromium's zoom levels
+            zoom_steps: vec![25, 33, 50, 67, 75, 80, 90, 100, 110, 125, 150, 175, 200, 250, 300, 400, 500],
             validate_coordinates: true,
+            locale: None,
+            restore_after_command: false,
         }
     }
 }
@@ -188,6 +1609,23 @@ impl LunaConfig {
         self.save_to_file(&config_path)
     }
 
+    /// Dotted paths of the settings that differ in `other`, e.g. "vision.edge_threshold"
+    pub fn changed_keys(&self, other: &LunaConfig) -> anyhow::Result<Vec<String>> {
+        let mut changed = Vec::new();
+        diff_values("", &serde_json::to_value(self)?, &serde_json::to_value(other)?, &mut changed);
+        Ok(changed)
+    }
+
+    /// This configuration with the single setting `key` (see `changed_keys`) taken from `other`
+    pub fn with_key_from(&self, other: &LunaConfig, key: &str) -> anyhow::Result<LunaConfig> {
+        let path: Vec<&str> = key.split('.').collect();
+        let mut merged = serde_json::to_value(self)?;
+        let other = serde_json::to_value(other)?;
+        let value = path.iter().try_fold(&other, |value, segment| value.get(segment));
+        set_path(&mut merged, &path, value.cloned());
+        Ok(serde_json::from_value(merged)?)
+    }
+
     /// Validate configuration values
     pub fn validate(&self) -> anyhow::Result<()> {
         // Validate safety config
@@ -199,19 +1637,193 @@ impl LunaConfig {
             return Err(anyhow::anyhow!("Max actions per command must be greater than 0"));
         }
 
+        for pattern in &self.safety.blocked_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(anyhow::anyhow!("Invalid safety blocked pattern '{}': {}", pattern, e));
+            }
+        }
+
         // Validate vision config
         if self.vision.confidence_threshold < 0.0 || self.vision.confidence_threshold > 1.0 {
             return Err(anyhow::anyhow!("Vision confidence threshold must be between 0.0 and 1.0"));
         }
 
+        if self.vision.text_confidence_threshold.is_some_and(|t| !(0.0..=1.0).contains(&t)) {
+            return Err(anyhow::anyhow!("Vision text confidence threshold must be between 0.0 and 1.0"));
+        }
+
         if self.vision.max_elements == 0 {
             return Err(anyhow::anyhow!("Max elements must be greater than 0"));
         }
 
+        if let Some(locale) = self.input.locale.as_deref().filter(|l| Locale::parse(l).is_none()) {
+            return Err(anyhow::anyhow!("Unknown input locale '{}'", locale));
+        }
+
+        if self.input.notch_pixels == 0 || self.input.page_pixels == 0 {
+            return Err(anyhow::anyhow!("Input notch and page sizes must be greater than 0"));
+        }
+
+        if !self.input.zoom_steps.contains(&100) {
+            return Err(anyhow::anyhow!("Input zoom steps must include 100"));
+        }
+
+        if let Some(url) = self.ocr.catalog_url.as_deref().filter(|url| !url.starts_with("http://")) {
+            return Err(anyhow::anyhow!("OCR catalog URL must start with http:// ('{}')", url));
+        }
+
+        if let Some(digest) = self.ocr.catalog_sha256.as_deref().filter(|d| d.len() != 64 || !d.chars().all(|c| c.is_ascii_hexdigit())) {
+            return Err(anyhow::anyhow!("OCR catalog SHA-256 must be 64 hex digits ('{}')", digest));
+        }
+
+        if !(0.0..=1.0).contains(&self.review.confidence_below) {
+            return Err(anyhow::anyhow!("Review confidence bound must be between 0.0 and 1.0"));
+        }
+
+        if self.archive.keyframe_interval == 0 {
+            return Err(anyhow::anyhow!("Archive keyframe interval must be at least 1"));
+        }
+
+        if !(1..=22).contains(&self.archive.compression_level) {
+            return Err(anyhow::anyhow!("Archive compression level must be between 1 and 22"));
+        }
+
+        if self.caret.blink_fallback && self.caret.blink_ms == 0 {
+            return Err(anyhow::anyhow!("Caret blink interval must be greater than 0"));
+        }
+
+        if self.vnc.enabled && (self.vnc.host.trim().is_empty() || self.vnc.connect_timeout_ms == 0 || self.vnc.timeout_ms == 0) {
+            return Err(anyhow::anyhow!("VNC needs a host and timeouts greater than 0"));
+        }
+
+        if self.compare.tolerance_px < 0 || !(self.compare.max_shift > 0.0 && self.compare.max_shift <= 1.0) {
+            return Err(anyhow::anyhow!("Compare tolerance must not be negative and max_shift must be in (0, 1]"));
+        }
+
+        let watchdog = &self.watchdog;
+        let stall_thresholds = [watchdog.capture_stall_ms, watchdog.inference_stall_ms, watchdog.input_stall_ms];
+        if watchdog.enabled && (watchdog.check_interval_ms == 0 || stall_thresholds.contains(&0) || watchdog.degrade_after == 0) {
+            return Err(anyhow::anyhow!("Watchdog check interval, stall thresholds and degrade_after must be greater than 0"));
+        }
+
+        if self.explore.budget_secs == 0 || self.explore.scroll_step <= 0 {
+            return Err(anyhow::anyhow!("Explore budget and scroll step must be greater than 0"));
+        }
+
         if self.vision.screenshot_quality > 100 {
             return Err(anyhow::anyhow!("Screenshot quality must be between 0 and 100"));
         }
 
+        // Validate storage config
+        if !(0.0..=1.0).contains(&self.storage.warn_ratio) || self.storage.warn_ratio == 0.0 {
+            return Err(anyhow::anyhow!("Storage warn ratio must be in (0.0, 1.0]"));
+        }
+
+        if !(0.0..=1.0).contains(&self.storage.clean_target_ratio) {
+            return Err(anyhow::anyhow!("Storage clean target ratio must be between 0.0 and 1.0"));
+        }
+
+        if self.confirmation.enabled {
+            let uses = |kind| self.confirmation.levels.values().any(|k| *k == kind);
+            if uses(ConfirmerKind::Totp) && self.confirmation.totp_secret.is_none() {
+                return Err(anyhow::anyhow!("TOTP confirmation requires confirmation.totp_secret"));
+            }
+            if uses(ConfirmerKind::Webhook) && self.confirmation.webhook_url.is_none() {
+                return Err(anyhow::anyhow!("Webhook confirmation requires confirmation.webhook_url"));
+            }
+            if uses(ConfirmerKind::Webhook) && self.confirmation.webhook_secret.as_deref().is_none_or(str::is_empty) {
+                return Err(anyhow::anyhow!("Webhook confirmation requires confirmation.webhook_secret to verify answers"));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.supervision.auto_above) {
+            return Err(anyhow::anyhow!("Supervision auto_above must be between 0.0 and 1.0"));
+        }
+
+        if self.speed.multiplier.is_some_and(|m| !SPEED_MULTIPLIER_RANGE.contains(&m)) {
+            return Err(anyhow::anyhow!("Speed multiplier must be between {} and {}",
+                SPEED_MULTIPLIER_RANGE.start(), SPEED_MULTIPLIER_RANGE.end()));
+        }
+
+        let scales = [self.escalation.threshold_scale, self.escalation.sensitivity_scale];
+        if scales.iter().any(|scale| !(*scale > 0.0 && *scale <= 1.0)) {
+            return Err(anyhow::anyhow!("Escalation scales must be in (0.0, 1.0]"));
+        }
+
+        if !(0.0..=1.0).contains(&self.element_stats.min_changed) {
+            return Err(anyhow::anyhow!("Element stats min_changed must be between 0.0 and 1.0"));
+        }
+
+        if self.raw_outputs.include && (self.raw_outputs.max_candidates == 0 || self.raw_outputs.max_bytes == 0) {
+            return Err(anyhow::anyhow!("Raw output limits must be greater than 0"));
+        }
+
+        if self.frame_channel.enabled && (self.frame_channel.slots == 0 || self.frame_channel.max_frame_bytes == 0) {
+            return Err(anyhow::anyhow!("Frame channel needs at least one slot and a nonzero frame size"));
+        }
+
+        let retry = &self.retry;
+        for (kind, policy) in [("click", &retry.click), ("type", &retry.type_text), ("keys", &retry.keys),
+                               ("scroll", &retry.scroll), ("screenshot", &retry.screenshot), ("paste", &retry.paste)] {
+            if policy.max_attempts == 0 || policy.backoff_multiplier < 1.0 {
+                return Err(anyhow::anyhow!("Retry policy for {} needs at least one attempt and a backoff multiplier of at least 1.0", kind));
+            }
+        }
+        for (kind, policy) in [("type", &retry.type_text), ("keys", &retry.keys), ("paste", &retry.paste)] {
+            if policy.max_attempts > 1 && !policy.verify_before_retry {
+                return Err(anyhow::anyhow!("Retrying {} actions requires verify_before_retry, or input could be repeated", kind));
+            }
+        }
+
+        for (index, objective) in self.slo.objectives.iter().enumerate() {
+            if objective.name.is_empty() || self.slo.objectives[..index].iter().any(|o| o.name == objective.name) {
+                return Err(anyhow::anyhow!("SLO objectives need unique, non-empty names (got '{}')", objective.name));
+            }
+            if !(objective.target > 0.0 && objective.target <= 1.0) || objective.latency_ms == 0 || objective.window_secs == 0 {
+                return Err(anyhow::anyhow!("SLO '{}' needs a target in (0, 1], a latency and a window", objective.name));
+            }
+        }
+
+        if let Err(e) = regex::Regex::new(&self.destructive_check.dialog_pattern) {
+            return Err(anyhow::anyhow!("Invalid destructive check dialog pattern: {}", e));
+        }
+
+        if self.session.pause_when_locked && self.session.poll_ms == 0 {
+            return Err(anyhow::anyhow!("Session poll interval must be greater than 0"));
+        }
+
+        if !(0.0..=1.0).contains(&self.stale_frame.threshold) {
+            return Err(anyhow::anyhow!("Stale frame threshold must be between 0.0 and 1.0"));
+        }
+
+        let tracking = &self.scroll_tracking;
+        if !(0.0..=1.0).contains(&tracking.min_correlation) || !(0.0..=1.0).contains(&tracking.min_match) {
+            return Err(anyhow::anyhow!("Scroll tracking correlation and match must be between 0.0 and 1.0"));
+        }
+        if tracking.enabled && tracking.samples == 0 {
+            return Err(anyhow::anyhow!("Scroll tracking needs at least one sample"));
+        }
+
+        for (index, client) in self.api.clients.iter().enumerate() {
+            if client.name.trim().is_empty() || client.token.len() < MIN_API_TOKEN_LEN {
+                return Err(anyhow::anyhow!("API client {} needs a name and a token of at least {} characters", index, MIN_API_TOKEN_LEN));
+            }
+            if self.api.clients[..index].iter().any(|c| c.name == client.name || c.token == client.token) {
+                return Err(anyhow::anyhow!("API client '{}' shares its name or token with another client", client.name));
+            }
+        }
+
+        if self.pre_analysis.enabled && (self.pre_analysis.idle_ms == 0 || self.pre_analysis.max_age_ms == 0) {
+            return Err(anyhow::anyhow!("Pre-analysis idle time and maximum age must be greater than 0"));
+        }
+
+        for (source, policy) in super::CommandSource::ALL.iter().map(|source| (source, self.contexts.policy(*source))) {
+            let unknown = policy.allowed_actions.iter().flatten().find(|kind| !super::LunaAction::KINDS.contains(&kind.as_str()));
+            if let Some(kind) = unknown {
+                return Err(anyhow::anyhow!("Unknown action type '{}' allowed for {} commands", kind, source.name()));
+            }
+        }
+
         // Validate logging config
         let valid_levels = ["error", "warn", "info", "debug", "trace"];
         if !valid_levels.contains(&self.logging.level.as_str()) {
@@ -233,4 +1845,42 @@ impl LunaConfig {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Collect the dotted paths under `prefix` where `a` and `b` differ; objects
+/// are compared key by key, anything else as a whole
+fn diff_values(prefix: &str, a: &serde_json::Value, b: &serde_json::Value, changed: &mut Vec<String>) {
+    use serde_json::Value;
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                let missing = Value::Null;
+                diff_values(&path, a.get(key).unwrap_or(&missing), b.get(key).unwrap_or(&missing), changed);
+            }
+        }
+        (a, b) if a != b => changed.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+/// Set the value at `path`, creating objects along the way; `None` removes it
+fn set_path(target: &mut serde_json::Value, path: &[&str], value: Option<serde_json::Value>) {
+    let (Some((last, parents)), serde_json::Value::Object(_)) = (path.split_last(), &target) else {
+        return;
+    };
+    let mut object = target;
+    for segment in parents {
+        let serde_json::Value::Object(map) = object else {
+            return;
+        };
+        object = map.entry(segment.to_string()).or_insert_with(|| serde_json::Value::Object(Default::default()));
+    }
+    if let serde_json::Value::Object(map) = object {
+        match value {
+            Some(value) => map.insert(last.to_string(), value),
+            None => map.remove(*last),
+        };
+    }
+}
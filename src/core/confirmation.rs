@@ -0,0 +1,685 @@
+/*!
+ * Luna Confirmation - Out-of-band approval of high-risk actions
+ *
+ * Unattended runs (schedules, watchers, daemon mode) have nobody watching the
+ * screen. Actions at or above a configured risk level are held until someone
+ * approves them through a separate channel: a TOTP code from an authenticator
+ * app, a webhook that relays the request to a phone, or a local y/N prompt.
+ * Webhook answers travel over plain HTTP, so each must carry an HMAC of the
+ * request ID and the decision under `confirmation.webhook_secret`; anything
+ * else on the path could otherwise approve an action.
+ * No answer within the timeout denies the action, and every decision is
+ * appended to an audit log recording who approved or denied what. A source's
+ * context policy (`config.contexts`) can demand confirmation on its own, and
+ * its refusals are audited too; each entry names the rule behind it.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::config::{ConfirmationConfig, ConfirmerKind, ContextsConfig};
+use super::{CommandSource, LunaError};
+use crate::ai::remote::{http_request, parse_endpoint, HttpTimeouts};
+use crate::utils::digest::{hex, sha1, sha256};
+use crate::input::RiskLevel;
+
+/// File name of the audit log under the storage root
+pub const AUDIT_FILE: &str = "confirmations.jsonl";
+/// Time step of the last accepted TOTP code, next to the audit log, so a
+/// code can't be used again after a restart
+pub const TOTP_STATE_FILE: &str = "totp_last_step";
+/// Seconds per TOTP step (RFC 6238 default)
+const TOTP_STEP_SECS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// How often the TOTP code file is checked for an answer
+const CODE_FILE_POLL: Duration = Duration::from_millis(250);
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// An action waiting for approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmationRequest {
+    pub id: String,
+    pub command: String,
+    /// Human-readable description of the action
+    pub action: String,
+    pub risk: RiskLevel,
+    /// Where the command came from, e.g. "scheduled"
+    pub source: String,
+    /// Who sent it, e.g. the command API client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// Setting that required the entry: "confirmation.levels" or the
+    /// source's context policy, e.g. "contexts.watcher"
+    #[serde(default)]
+    pub rule: String,
+    /// Unix seconds
+    pub requested_at: u64,
+}
+
+impl ConfirmationRequest {
+    fn new(command: &str, action: &str, risk: RiskLevel, source: CommandSource, client: Option<&str>, rule: String) -> Self {
+        Self {
+            id: format!("{}-{}", unix_now(), NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)),
+            command: command.to_string(),
+            action: action.to_string(),
+            risk,
+            source: source.name().to_string(),
+            client: client.map(str::to_string),
+            rule,
+            requested_at: unix_now(),
+        }
+    }
+}
+
+/// What the confirmer decided
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ConfirmationOutcome {
+    Approved { by: String },
+    Denied { by: String, reason: String },
+    /// Nobody answered in time; treated as a denial
+    TimedOut,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    #[serde(flatten)]
+    pub request: ConfirmationRequest,
+    pub confirmer: String,
+    #[serde(flatten)]
+    pub outcome: ConfirmationOutcome,
+    /// Unix seconds
+    pub decided_at: u64,
+}
+
+/// A channel that can approve or deny a request
+pub trait Confirmer: Send {
+    fn name(&self) -> &'static str;
+    /// Ask for approval, giving up after `timeout`
+    fn confirm(&mut self, request: &ConfirmationRequest, timeout: Duration) -> Result<ConfirmationOutcome>;
+}
+
+/// Holds high-risk actions until a confirmer approves them
+pub struct ConfirmationGate {
+    config: ConfirmationConfig,
+    confirmers: HashMap<ConfirmerKind, Box<dyn Confirmer>>,
+    contexts: ContextsConfig,
+    audit_path: PathBuf,
+}
+
+impl ConfirmationGate {
+    /// Gate with the confirmers `config` describes, auditing to `audit_path`
+    pub fn new(config: &ConfirmationConfig, audit_path: impl Into<PathBuf>) -> Self {
+        let audit_path = audit_path.into();
+        let mut confirmers: HashMap<ConfirmerKind, Box<dyn Confirmer>> = HashMap::new();
+        confirmers.insert(ConfirmerKind::Prompt, Box::new(PromptConfirmer));
+        if let Some(secret) = &config.totp_secret {
+            let state = audit_path.with_file_name(TOTP_STATE_FILE);
+            match TotpConfirmer::new(secret, config.totp_code_file.clone()).map(|totp| totp.remember_in(state)) {
+                Ok(totp) => {
+                    confirmers.insert(ConfirmerKind::Totp, Box::new(totp));
+                }
+                Err(e) => log::warn!("TOTP confirmation unavailable: {}", e),
+            }
+        }
+        match (&config.webhook_url, &config.webhook_secret) {
+            (Some(url), Some(secret)) => {
+                confirmers.insert(ConfirmerKind::Webhook, Box::new(WebhookConfirmer::new(url, secret)));
+            }
+            (Some(_), None) => log::warn!("Webhook confirmation unavailable: confirmation.webhook_secret is not set"),
+            _ => {}
+        }
+        Self { config: config.clone(), confirmers, contexts: ContextsConfig::default(), audit_path }
+    }
+
+    /// Also apply the confirmation each context policy demands
+    pub fn with_contexts(mut self, contexts: &ContextsConfig) -> Self {
+        self.contexts = contexts.clone();
+        self
+    }
+
+    /// Replace the confirmer used for `kind`
+    pub fn set_confirmer(&mut self, kind: ConfirmerKind, confirmer: Box<dyn Confirmer>) {
+        self.confirmers.insert(kind, confirmer);
+    }
+
+    pub fn audit_path(&self) -> &Path {
+        &self.audit_path
+    }
+
+    /// Confirmer an action at `risk` from `source` needs: the one configured
+    /// for the highest level at or below `risk`
+    pub fn required(&self, risk: RiskLevel, source: CommandSource) -> Option<ConfirmerKind> {
+        self.requirement(risk, source).map(|(kind, _)| kind)
+    }
+
+    /// Confirmer needed and the rule that asks for it. A context policy's
+    /// levels replace the configured ones for its source.
+    fn requirement(&self, risk: RiskLevel, source: CommandSource) -> Option<(ConfirmerKind, String)> {
+        if let Some(levels) = &self.contexts.policy(source).confirm {
+            let kind = levels.range(..=risk).next_back().map(|(_, kind)| *kind)?;
+            return Some((kind, format!("contexts.{}", source.name())));
+        }
+        if !self.config.enabled || (source == CommandSource::Interactive && !self.config.include_interactive) {
+            return None;
+        }
+        let kind = self.config.levels.range(..=risk).next_back().map(|(_, kind)| *kind)?;
+        Some((kind, "confirmation.levels".to_string()))
+    }
+
+    /// Wait for approval when `risk` requires it. Denials, timeouts and
+    /// confirmer failures all refuse the action; an approval returns the ID
+    /// of its audit record.
+    pub fn confirm(
+        &mut self,
+        command: &str,
+        action: &str,
+        risk: RiskLevel,
+        source: CommandSource,
+        client: Option<&str>,
+    ) -> Result<Option<String>> {
+        let Some((kind, rule)) = self.requirement(risk, source) else {
+            return Ok(None);
+        };
+        let request = ConfirmationRequest::new(command, action, risk, source, client, rule);
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+
+        let (confirmer, outcome) = match self.confirmers.get_mut(&kind) {
+            Some(confirmer) => {
+                log::info!("Waiting up to {}s for {} approval of {}", timeout.as_secs(), confirmer.name(), action);
+                let outcome = confirmer.confirm(&request, timeout).unwrap_or_else(|e| ConfirmationOutcome::Denied {
+                    by: confirmer.name().to_string(),
+                    reason: format!("confirmer failed: {}", e),
+                });
+                (confirmer.name().to_string(), outcome)
+            }
+            None => (
+                format!("{:?}", kind).to_lowercase(),
+                ConfirmationOutcome::Denied { by: "luna".to_string(), reason: "confirmer is not configured".to_string() },
+            ),
+        };
+
+        let id = request.id.clone();
+        let record = AuditRecord { request, confirmer, outcome: outcome.clone(), decided_at: unix_now() };
+        self.append_audit(&record)?;
+
+        match outcome {
+            ConfirmationOutcome::Approved { by } => {
+                log::info!("{} approved by {}", action, by);
+                Ok(Some(id))
+            }
+            ConfirmationOutcome::Denied { by, reason } => {
+                Err(LunaError::PermissionDenied(format!("{} denied by {}: {}", action, by, reason)).into())
+            }
+            ConfirmationOutcome::TimedOut => Err(LunaError::PermissionDenied(format!(
+                "{} was not confirmed within {}s", action, timeout.as_secs())).into()),
+        }
+    }
+
+    /// Audit an action (or, without one, a whole command) refused by the
+    /// context policy of its source
+    pub fn record_refusal(
+        &self,
+        command: &str,
+        action: Option<&str>,
+        risk: Option<RiskLevel>,
+        source: CommandSource,
+        client: Option<&str>,
+        reason: &str,
+    ) -> Result<()> {
+        let request = ConfirmationRequest::new(
+            command,
+            action.unwrap_or(command),
+            risk.unwrap_or(RiskLevel::Safe),
+            source,
+            client,
+            format!("contexts.{}", source.name()),
+        );
+        let outcome = ConfirmationOutcome::Denied { by: "policy".to_string(), reason: reason.to_string() };
+        self.append_audit(&AuditRecord { request, confirmer: "policy".to_string(), outcome, decided_at: unix_now() })
+    }
+
+    /// Past decisions, oldest first
+    pub fn audit_log(&self) -> Result<Vec<AuditRecord>> {
+        match std::fs::read_to_string(&self.audit_path) {
+            Ok(contents) => contents.lines().map(|line| Ok(serde_json::from_str(line)?)).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append_audit(&self, record: &AuditRecord) -> Result<()> {
+        if let Some(parent) = self.audit_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.audit_path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+}
+
+/// y/N question on the terminal
+pub struct PromptConfirmer;
+
+impl Confirmer for PromptConfirmer {
+    fn name(&self) -> &'static str {
+        "prompt"
+    }
+
+    fn confirm(&mut self, request: &ConfirmationRequest, timeout: Duration) -> Result<ConfirmationOutcome> {
+        let question = format!("Allow {} ({:?} risk) for '{}'? [y/N] ", request.action, request.risk, request.command);
+        let Some(answer) = ask(&question, timeout) else {
+            return Ok(ConfirmationOutcome::TimedOut);
+        };
+        let by = format!("local:{}", local_user());
+        Ok(if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            ConfirmationOutcome::Approved { by }
+        } else {
+            ConfirmationOutcome::Denied { by, reason: "declined at prompt".to_string() }
+        })
+    }
+}
+
+/// Code from an authenticator app, typed at the terminal or written to a file
+pub struct TotpConfirmer {
+    secret: Vec<u8>,
+    /// Polled instead of the terminal when set (daemon mode)
+    code_file: Option<PathBuf>,
+    /// Time step of the last accepted code; a code is only good once
+    last_step: Option<u64>,
+    /// Where `last_step` is kept across restarts and config reloads
+    state_file: Option<PathBuf>,
+}
+
+impl TotpConfirmer {
+    /// `secret` is the base32 string shown when enrolling the authenticator
+    pub fn new(secret: &str, code_file: Option<PathBuf>) -> Result<Self> {
+        let secret = base32_decode(secret)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| LunaError::Config("TOTP secret is not valid base32".to_string()))?;
+        Ok(Self { secret, code_file, last_step: None, state_file: None })
+    }
+
+    /// Keep the last accepted step in `path`, starting from the one saved there
+    pub fn remember_in(mut self, path: PathBuf) -> Self {
+        self.last_step = std::fs::read_to_string(&path).ok().and_then(|saved| saved.trim().parse().ok());
+        self.state_file = Some(path);
+        self
+    }
+
+    /// Mark `step` used. It is saved before the approval counts, so a code
+    /// whose use can't be recorded isn't accepted.
+    fn accept(&mut self, step: u64) -> Result<()> {
+        if let Some(path) = &self.state_file {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, step.to_string())?;
+        }
+        self.last_step = Some(step);
+        Ok(())
+    }
+
+    /// Step the code matches, allowing one step of clock drift either way
+    fn verify(&self, code: &str, unix_secs: u64) -> Option<u64> {
+        let code: u32 = code.trim().parse().ok()?;
+        let step = unix_secs / TOTP_STEP_SECS;
+        [step.saturating_sub(1), step, step + 1]
+            .into_iter()
+            .filter(|s| self.last_step.is_none_or(|last| *s > last))
+            .find(|s| totp(&self.secret, *s) == code)
+    }
+
+    fn read_code(&self, request: &ConfirmationRequest, timeout: Duration) -> Option<String> {
+        let Some(path) = &self.code_file else {
+            return ask(&format!("TOTP code to allow {} for '{}': ", request.action, request.command), timeout);
+        };
+        log::warn!("Write the TOTP code for request {} to {}", request.id, path.display());
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Ok(code) = std::fs::read_to_string(path) {
+                // Consume the code so it cannot answer the next request too
+                let _ = std::fs::remove_file(path);
+                return Some(code);
+            }
+            std::thread::sleep(CODE_FILE_POLL);
+        }
+        None
+    }
+}
+
+impl Confirmer for TotpConfirmer {
+    fn name(&self) -> &'static str {
+        "totp"
+    }
+
+    fn confirm(&mut self, request: &ConfirmationRequest, timeout: Duration) -> Result<ConfirmationOutcome> {
+        let Some(code) = self.read_code(request, timeout) else {
+            return Ok(ConfirmationOutcome::TimedOut);
+        };
+        Ok(match self.verify(&code, unix_now()) {
+            Some(step) => {
+                self.accept(step)?;
+                ConfirmationOutcome::Approved { by: "totp".to_string() }
+            }
+            None => ConfirmationOutcome::Denied { by: "totp".to_string(), reason: "invalid or reused code".to_string() },
+        })
+    }
+}
+
+/// POSTs the request as JSON and waits for
+/// `{"approved": bool, "by": .., "reason": .., "signature": ..}`, where
+/// `signature` is the hex HMAC-SHA256 under the shared secret of
+/// `"<request id>:<approved|denied>:<by>"` (`by` empty when absent)
+pub struct WebhookConfirmer {
+    url: String,
+    secret: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct WebhookAnswer {
+    approved: bool,
+    by: Option<String>,
+    reason: Option<String>,
+    signature: Option<String>,
+}
+
+impl WebhookConfirmer {
+    pub fn new(url: &str, secret: &str) -> Self {
+        Self { url: url.to_string(), secret: secret.as_bytes().to_vec() }
+    }
+
+    /// Signature the hook must send with `answer` to request `id`
+    fn signature(&self, id: &str, approved: bool, by: Option<&str>) -> String {
+        let decision = if approved { "approved" } else { "denied" };
+        hex(&hmac_sha256(&self.secret, format!("{}:{}:{}", id, decision, by.unwrap_or("")).as_bytes()))
+    }
+}
+
+impl Confirmer for WebhookConfirmer {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn confirm(&mut self, request: &ConfirmationRequest, timeout: Duration) -> Result<ConfirmationOutcome> {
+        let (host, port, path) = parse_endpoint(&self.url)?;
+        let path = if path.is_empty() { "/".to_string() } else { path };
+        let body = serde_json::to_vec(request)?;
+        let headers = [("Content-Type", "application/json".to_string())];
+        // The hook holds the connection open until someone answers on the phone
+        let timeouts = HttpTimeouts { connect: timeout.min(Duration::from_secs(10)), read: timeout };
+        let response = match http_request(&host, port, "POST", &path, &headers, &body, timeouts) {
+            Ok(response) => response,
+            Err(e) if is_timeout(&e) => return Ok(ConfirmationOutcome::TimedOut),
+            Err(e) => return Err(e),
+        };
+        if response.status != 200 {
+            return Err(LunaError::System(format!("webhook answered HTTP {}", response.status)).into());
+        }
+        let answer: WebhookAnswer = serde_json::from_slice(&response.body)?;
+        let expected = self.signature(&request.id, answer.approved, answer.by.as_deref());
+        if !answer.signature.is_some_and(|signature| constant_time_eq(signature.to_lowercase().as_bytes(), expected.as_bytes())) {
+            return Err(LunaError::PermissionDenied("webhook answer is not signed with confirmation.webhook_secret".to_string()).into());
+        }
+        let by = answer.by.unwrap_or_else(|| "webhook".to_string());
+        Ok(if answer.approved {
+            ConfirmationOutcome::Approved { by }
+        } else {
+            ConfirmationOutcome::Denied { by, reason: answer.reason.unwrap_or_else(|| "denied".to_string()) }
+        })
+    }
+}
+
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock)
+    })
+}
+
+/// Print `question` and wait up to `timeout` for a line on stdin
+fn ask(question: &str, timeout: Duration) -> Option<String> {
+    eprint!("{}", question);
+    let _ = std::io::stderr().flush();
+    let (tx, rx) = mpsc::channel();
+    // stdin cannot be read with a timeout; an unanswered reader is left behind
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line).is_ok_and(|read| read > 0) {
+            let _ = tx.send(line);
+        }
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+fn local_user() -> String {
+    std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// RFC 6238 code for time step `step`
+fn totp(secret: &[u8], step: u64) -> u32 {
+    let mac = hmac_sha1(secret, &step.to_be_bytes());
+    // RFC 4226 dynamic truncation
+    let offset = (mac[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([mac[offset] & 0x7f, mac[offset + 1], mac[offset + 2], mac[offset + 3]]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| !matches!(c, b' ' | b'-' | b'=')) {
+        let value = ALPHABET.iter().position(|a| *a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    hmac(sha1, key, message)
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    hmac(sha256, key, message)
+}
+
+/// RFC 2104 HMAC over a hash with a 64-byte block
+fn hmac<const N: usize>(hash: fn(&[u8]) -> [u8; N], key: &[u8], message: &[u8]) -> [u8; N] {
+    const BLOCK: usize = 64;
+    let mut block_key = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block_key[..N].copy_from_slice(&hash(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block_key.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block_key.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&hash(&inner));
+    hash(&outer)
+}
+
+/// Compare without stopping at the first difference, so timing doesn't reveal how much of a signature was right
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    struct Scripted(Option<ConfirmationOutcome>);
+
+    impl Confirmer for Scripted {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn confirm(&mut self, _: &ConfirmationRequest, _: Duration) -> Result<ConfirmationOutcome> {
+            self.0.clone().ok_or_else(|| anyhow::anyhow!("phone unreachable"))
+        }
+    }
+
+    #[test]
+    fn test_totp_matches_rfc_vectors() {
+        // RFC 6238 appendix B, SHA-1 secret; the RFC lists 8 digits, we use the last 6
+        let secret = b"12345678901234567890";
+        assert_eq!(totp(secret, 59 / TOTP_STEP_SECS), 287082);
+        assert_eq!(totp(secret, 1111111109 / TOTP_STEP_SECS), 81804);
+        assert_eq!(base32_decode("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(), secret);
+
+        let mut confirmer = TotpConfirmer::new("GEZD GNBV GY3T QOJQ GEZD GNBV GY3T QOJQ", None).unwrap();
+        assert_eq!(confirmer.verify("287082", 59), Some(1));
+        assert_eq!(confirmer.verify("287082", 89), Some(1), "one step of drift is allowed");
+        assert_eq!(confirmer.verify("287082", 200), None);
+        confirmer.last_step = Some(1);
+        assert_eq!(confirmer.verify("287082", 59), None, "codes cannot be replayed");
+
+        // Not even by a new confirmer after a restart
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join(TOTP_STATE_FILE);
+        let mut confirmer = TotpConfirmer::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", None).unwrap().remember_in(state.clone());
+        confirmer.accept(1).unwrap();
+        let restarted = TotpConfirmer::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", None).unwrap().remember_in(state);
+        assert_eq!(restarted.verify("287082", 59), None);
+    }
+
+    #[test]
+    fn test_webhook_answers_must_be_signed() {
+        use crate::ai::remote::{read_message, write_response};
+        use std::net::TcpListener;
+
+        // Answers each request with what `answer` makes of it
+        let hook = |answer: fn(&WebhookConfirmer, &ConfirmationRequest) -> serde_json::Value| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/approve", listener.local_addr().unwrap());
+            std::thread::spawn(move || {
+                let signer = WebhookConfirmer::new("", "s3cret");
+                let (mut stream, _) = listener.accept().unwrap();
+                let request: ConfirmationRequest = serde_json::from_slice(&read_message(&mut stream).unwrap().body).unwrap();
+                write_response(&mut stream, 200, "application/json", answer(&signer, &request).to_string().as_bytes()).unwrap();
+            });
+            WebhookConfirmer::new(&url, "s3cret")
+        };
+        let request = ConfirmationRequest::new("deploy", "type 'admin'", RiskLevel::High, CommandSource::Scheduled, None, String::new());
+        let timeout = Duration::from_secs(5);
+
+        let mut unsigned = hook(|_, _| serde_json::json!({ "approved": true, "by": "mallory" }));
+        assert!(unsigned.confirm(&request, timeout).is_err());
+        let mut wrong_decision = hook(|signer, request| {
+            serde_json::json!({ "approved": true, "by": "ops", "signature": signer.signature(&request.id, false, Some("ops")) })
+        });
+        assert!(wrong_decision.confirm(&request, timeout).is_err(), "a signed denial can't be turned into an approval");
+        let mut signed = hook(|signer, request| {
+            serde_json::json!({ "approved": true, "by": "ops", "signature": signer.signature(&request.id, true, Some("ops")) })
+        });
+        assert_eq!(signed.confirm(&request, timeout).unwrap(), ConfirmationOutcome::Approved { by: "ops".to_string() });
+    }
+
+    #[test]
+    fn test_gate_audits_every_decision() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ConfirmationConfig {
+            enabled: true,
+            levels: BTreeMap::from([(RiskLevel::High, ConfirmerKind::Webhook)]),
+            ..ConfirmationConfig::default()
+        };
+        let mut gate = ConfirmationGate::new(&config, dir.path().join(AUDIT_FILE));
+        assert_eq!(gate.required(RiskLevel::Critical, CommandSource::Scheduled), Some(ConfirmerKind::Webhook));
+        assert_eq!(gate.required(RiskLevel::Medium, CommandSource::Scheduled), None);
+        assert_eq!(gate.required(RiskLevel::High, CommandSource::Interactive), None);
+
+        // Not configured, so denied rather than waved through
+        assert!(gate.confirm("deploy", "type 'admin'", RiskLevel::High, CommandSource::Watcher, None).is_err());
+
+        let approved = ConfirmationOutcome::Approved { by: "alice".to_string() };
+        gate.set_confirmer(ConfirmerKind::Webhook, Box::new(Scripted(Some(approved))));
+        gate.confirm("deploy", "type 'admin'", RiskLevel::High, CommandSource::Scheduled, None).unwrap();
+        gate.confirm("deploy", "click (1, 2)", RiskLevel::Safe, CommandSource::Scheduled, None).unwrap();
+
+        gate.set_confirmer(ConfirmerKind::Webhook, Box::new(Scripted(Some(ConfirmationOutcome::TimedOut))));
+        let error = gate.confirm("deploy", "type 'admin'", RiskLevel::High, CommandSource::Scheduled, None).unwrap_err();
+        assert!(matches!(error.downcast_ref::<LunaError>(), Some(LunaError::PermissionDenied(_))));
+
+        gate.set_confirmer(ConfirmerKind::Webhook, Box::new(Scripted(None)));
+        assert!(gate.confirm("deploy", "type 'admin'", RiskLevel::High, CommandSource::Scheduled, None).is_err());
+
+        let log = gate.audit_log().unwrap();
+        let outcomes: Vec<_> = log.iter().map(|r| r.outcome.clone()).collect();
+        assert_eq!(log.len(), 4, "the safe action needs no approval and is not audited");
+        assert!(matches!(&outcomes[0], ConfirmationOutcome::Denied { reason, .. } if reason.contains("not configured")));
+        assert_eq!(outcomes[1], ConfirmationOutcome::Approved { by: "alice".to_string() });
+        assert_eq!(outcomes[2], ConfirmationOutcome::TimedOut);
+        assert!(matches!(&outcomes[3], ConfirmationOutcome::Denied { reason, .. } if reason.contains("phone unreachable")));
+        assert_eq!((log[1].request.source.as_str(), log[1].request.risk), ("scheduled", RiskLevel::High));
+        assert_eq!(log[1].request.rule, "confirmation.levels");
+    }
+
+    #[test]
+    fn test_context_policies_demand_confirmation_and_audit_refusals() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut contexts = ContextsConfig::default();
+        contexts.api.confirm = Some(BTreeMap::from([(RiskLevel::Medium, ConfirmerKind::Totp)]));
+        let mut gate = ConfirmationGate::new(&ConfirmationConfig::default(), dir.path().join(AUDIT_FILE)).with_contexts(&contexts);
+        // Confirmation is off, but the API context asks for it anyway
+        assert_eq!(gate.required(RiskLevel::High, CommandSource::Api), Some(ConfirmerKind::Totp));
+        assert_eq!(gate.required(RiskLevel::Low, CommandSource::Api), None);
+        assert_eq!(gate.required(RiskLevel::High, CommandSource::Scheduled), None);
+
+        gate.set_confirmer(ConfirmerKind::Totp, Box::new(Scripted(Some(ConfirmationOutcome::Approved { by: "totp".to_string() }))));
+        gate.confirm("fill form", "type 'x'", RiskLevel::Medium, CommandSource::Api, Some("ci")).unwrap();
+        gate.record_refusal("paste chart", Some("PasteImage"), None, CommandSource::Watcher, None, "watcher commands may not run paste_image actions").unwrap();
+
+        let log = gate.audit_log().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[0].request.rule.as_str(), log[0].request.client.as_deref()), ("contexts.api", Some("ci")));
+        assert_eq!((log[1].request.rule.as_str(), log[1].request.source.as_str()), ("contexts.watcher", "watcher"));
+        assert!(matches!(&log[1].outcome, ConfirmationOutcome::Denied { by, .. } if by == "policy"));
+    }
+
+    #[test]
+    fn test_watcher_commands_are_held_to_their_context() {
+        use crate::core::sandbox::SandboxScene;
+        use crate::core::{ExecuteOptions, Luna, LunaConfig};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        config.disruption.enabled = false;
+        let mut luna = Luna::new(config).unwrap();
+        luna.enter_sandbox(SandboxScene::tutorial());
+
+        let watcher = ExecuteOptions { source: CommandSource::Watcher, dry_run: true, ..ExecuteOptions::default() };
+        assert!(luna.execute_command("click Save", &watcher).is_ok());
+        for (command, reason) in [("type admin", "watcher commands may run at most Medium"), ("delete the draft", "watcher commands may not delete")] {
+            let error = luna.execute_command(command, &watcher).unwrap_err();
+            assert!(matches!(error.downcast_ref::<LunaError>(), Some(LunaError::PermissionDenied(_))));
+            assert!(error.to_string().contains("[context]") && error.to_string().contains(reason), "{}", error);
+        }
+        // The same typing is fine from the keyboard
+        assert!(luna.execute_command("type admin", &ExecuteOptions { dry_run: true, ..ExecuteOptions::default() }).is_ok());
+
+        let log = luna.confirmation_log().unwrap();
+        let audited: Vec<_> = log.iter().map(|r| (r.request.command.as_str(), r.request.rule.as_str(), r.request.risk)).collect();
+        assert_eq!(audited, [("type admin", "contexts.watcher", RiskLevel::High), ("delete the draft", "contexts.watcher", RiskLevel::Safe)]);
+    }
+}
@@ -0,0 +1,377 @@
+/*!
+ * Luna Debugger - Pausing, single-stepping and breakpoints for action sequences
+ *
+ * A `StepControl` is shared between the Luna instance that runs commands
+ * and whoever debugs them: the REPL, a `LunaHandle` owner or the command
+ * API. Before each action the run loop checks it: when paused, or when a
+ * breakpoint matches the action's kind or its target element, the action
+ * waits until `resume` (run freely again) or `step` (run this one action,
+ * then pause before the next). Cancelling the command ends the wait.
+ * Playback rate is the speed multiplier (`SpeedControl`), which a paused
+ * command picks up when it continues.
+ *
+ * Supervision (`supervise`) holds only the uncertain steps: an action whose
+ * target was detected with less confidence than the threshold waits for
+ * `approve` or `reject`, and everything else runs on unattended. Approving
+ * lets just that action run without pausing the rest, and `resume`
+ * approves it too; a rejection fails the command.
+ */
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::handle::CancelToken;
+use super::provenance::ElementEvidence;
+use super::query::ElementQuery;
+use super::LunaError;
+
+/// How often a paused action checks whether its command was cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Kinds an `Action` breakpoint can name, as in `luna_input_actions_total`
+pub const ACTION_KINDS: [&str; 10] =
+    ["click", "type", "keys", "scroll", "wait", "screenshot", "paste", "wait_for_element", "wait_for_text_gone", "wait_idle"];
+
+/// Condition that pauses a command before an action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "on", rename_all = "snake_case")]
+pub enum Breakpoint {
+    /// Every action of this kind (one of `ACTION_KINDS`)
+    Action { kind: String },
+    /// Actions whose target element matches this query (see `query`)
+    Target { query: String },
+}
+
+impl Breakpoint {
+    /// An action kind ("click") or, for anything else, a target query
+    /// ("button:save")
+    pub fn parse(spec: &str) -> Self {
+        let spec = spec.trim();
+        match ACTION_KINDS.iter().find(|kind| kind.eq_ignore_ascii_case(spec)) {
+            Some(kind) => Breakpoint::Action { kind: kind.to_string() },
+            None => Breakpoint::Target { query: spec.to_string() },
+        }
+    }
+
+    /// Whether an action of `kind` aimed at `target` stops here
+    pub fn matches(&self, kind: &str, target: Option<&ElementEvidence>) -> bool {
+        match self {
+            Breakpoint::Action { kind: wanted } => wanted == kind,
+            Breakpoint::Target { query } => target.is_some_and(|element| {
+                ElementQuery::parse(query).matches_parts(&element.element_type, element.text.as_deref())
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Action { kind } => write!(f, "before every {} action", kind),
+            Breakpoint::Target { query } => write!(f, "before actions on '{}'", query),
+        }
+    }
+}
+
+/// The action a command is paused before
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PausedAt {
+    pub command: String,
+    /// Position in the command's actions
+    pub index: usize,
+    /// The action, as planned
+    pub action: String,
+    /// The breakpoint that stopped it; `None` when paused by hand or stepping
+    pub breakpoint: Option<Breakpoint>,
+    /// Detection confidence of the target, when supervision held it for
+    /// approval; `None` for a debugger pause
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+}
+
+/// What the debugger is doing, for a debugger panel to show
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DebugState {
+    /// Actions wait before running
+    pub paused: bool,
+    pub breakpoints: Vec<Breakpoint>,
+    /// Actions on targets detected with less confidence wait for approval
+    pub supervised_below: Option<f32>,
+    /// The action a command is waiting before right now
+    pub waiting: Option<PausedAt>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: DebugState,
+    /// Actions allowed to run while paused
+    steps: u32,
+    /// Answer for the action held for approval: `true` approves it
+    verdict: Option<bool>,
+    /// Bumped by `resume`, which also releases held actions
+    resumes: u64,
+}
+
+/// Shared pause, step and breakpoint controls; clones control the same commands
+#[derive(Clone, Default)]
+pub struct StepControl(Arc<(Mutex<Shared>, Condvar)>);
+
+impl StepControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.0.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn change(&self, f: impl FnOnce(&mut Shared)) {
+        f(&mut self.lock());
+        self.0.1.notify_all();
+    }
+
+    /// Hold every command before its next action
+    pub fn pause(&self) {
+        self.change(|shared| shared.state.paused = true);
+    }
+
+    /// Run freely until the next breakpoint
+    pub fn resume(&self) {
+        self.change(|shared| {
+            shared.state.paused = false;
+            shared.steps = 0;
+            shared.resumes += 1;
+        });
+    }
+
+    /// Let one action run and pause before the one after it
+    pub fn step(&self) {
+        self.change(|shared| {
+            shared.state.paused = true;
+            shared.steps += 1;
+        });
+    }
+
+    pub fn add_breakpoint(&self, breakpoint: Breakpoint) {
+        self.change(|shared| {
+            if !shared.state.breakpoints.contains(&breakpoint) {
+                shared.state.breakpoints.push(breakpoint);
+            }
+        });
+    }
+
+    /// Returns whether the breakpoint was set
+    pub fn remove_breakpoint(&self, breakpoint: &Breakpoint) -> bool {
+        let mut shared = self.lock();
+        let before = shared.state.breakpoints.len();
+        shared.state.breakpoints.retain(|b| b != breakpoint);
+        before != shared.state.breakpoints.len()
+    }
+
+    pub fn clear_breakpoints(&self) {
+        self.change(|shared| shared.state.breakpoints.clear());
+    }
+
+    /// Hold actions on targets detected with less than `below` confidence
+    /// for approval; `None` runs every action unattended
+    pub fn supervise(&self, below: Option<f32>) {
+        self.change(|shared| shared.state.supervised_below = below);
+    }
+
+    /// Let the action held for approval run; returns whether one was held
+    pub fn approve(&self) -> bool {
+        self.answer(true)
+    }
+
+    /// Fail the command whose action is held for approval; returns whether one was held
+    pub fn reject(&self) -> bool {
+        self.answer(false)
+    }
+
+    fn answer(&self, approved: bool) -> bool {
+        let mut shared = self.lock();
+        let held = shared.state.waiting.as_ref().is_some_and(|w| w.confidence.is_some());
+        if held {
+            shared.verdict = Some(approved);
+            self.0.1.notify_all();
+        }
+        held
+    }
+
+    pub fn state(&self) -> DebugState {
+        self.lock().state.clone()
+    }
+
+    /// Called before each action: wait while paused, stopped at a
+    /// breakpoint or held for approval. Returns where it waited, if it did.
+    pub(crate) fn gate(
+        &self,
+        at: impl FnOnce() -> PausedAt,
+        kind: &str,
+        target: Option<&ElementEvidence>,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Option<PausedAt>, LunaError> {
+        let (lock, condvar) = &*self.0;
+        let mut shared = lock.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let hit = shared.state.breakpoints.iter().find(|b| b.matches(kind, target)).cloned();
+        let debugging = shared.state.paused || hit.is_some();
+        let uncertain = target.map(|t| t.confidence).filter(|&c| shared.state.supervised_below.is_some_and(|below| c < below));
+        if !debugging && uncertain.is_none() {
+            return Ok(None);
+        }
+        let paused = if debugging {
+            shared.state.paused = true;
+            PausedAt { breakpoint: hit, ..at() }
+        } else {
+            PausedAt { confidence: uncertain, ..at() }
+        };
+        match (&paused.breakpoint, paused.confidence) {
+            (Some(breakpoint), _) => info!("Paused '{}' at action {}: {}", paused.command, paused.index, breakpoint),
+            (None, Some(confidence)) => info!(
+                "Holding action {} of '{}' for approval: target is {:.0}% confident",
+                paused.index, paused.command, confidence * 100.0),
+            (None, None) => info!("Paused '{}' at action {}", paused.command, paused.index),
+        }
+        shared.state.waiting = Some(paused.clone());
+        shared.verdict = None;
+        let resumes = shared.resumes;
+        condvar.notify_all();
+        loop {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                shared.state.waiting = None;
+                return Err(LunaError::Cancelled(format!("{} (paused before action {})", paused.command, paused.index)));
+            }
+            if debugging && !shared.state.paused {
+                break;
+            }
+            if shared.steps > 0 {
+                shared.steps -= 1;
+                break;
+            }
+            if !debugging {
+                match shared.verdict.take() {
+                    Some(true) => break,
+                    Some(false) => {
+                        shared.state.waiting = None;
+                        return Err(LunaError::PermissionDenied(format!(
+                            "action {} of '{}' ({}) was rejected", paused.index, paused.command, paused.action)));
+                    }
+                    None if shared.resumes != resumes => break,
+                    None => {}
+                }
+            }
+            shared = condvar.wait_timeout(shared, CANCEL_POLL).unwrap_or_else(std::sync::PoisonError::into_inner).0;
+        }
+        shared.state.waiting = None;
+        Ok(Some(paused))
+    }
+
+    /// Block until a command is waiting before an action, or `timeout` passes
+    pub fn wait_until_paused(&self, timeout: Duration) -> Option<PausedAt> {
+        let (lock, condvar) = &*self.0;
+        let shared = lock.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (shared, _) = condvar
+            .wait_timeout_while(shared, timeout, |shared| shared.state.waiting.is_none())
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        shared.state.waiting.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evidence(element_type: &str, text: &str) -> ElementEvidence {
+        ElementEvidence {
+            detection: Some(0),
+            element_type: element_type.to_string(),
+            bounds: (0, 0, 80, 30),
+            confidence: 0.9,
+            text: Some(text.to_string()),
+            reliability: None,
+            click_strategy: None,
+        }
+    }
+
+    #[test]
+    fn test_breakpoints_match_kinds_and_targets() {
+        assert_eq!(Breakpoint::parse("Click"), Breakpoint::Action { kind: "click".to_string() });
+        let save = Breakpoint::parse("button:save");
+        assert!(save.matches("click", Some(&evidence("button", "Save as"))));
+        assert!(!save.matches("click", Some(&evidence("link", "Save"))));
+        assert!(!save.matches("type", None));
+        assert!(Breakpoint::parse("type").matches("type", None));
+    }
+
+    #[test]
+    fn test_paused_actions_wait_for_step_or_resume() {
+        let control = StepControl::new();
+        let at = |index| move || PausedAt { command: "macro".to_string(), index, action: "Wait".to_string(), breakpoint: None, confidence: None };
+        assert_eq!(control.gate(at(0), "wait", None, None).unwrap(), None);
+
+        control.add_breakpoint(Breakpoint::parse("keys"));
+        let runner = {
+            let control = control.clone();
+            std::thread::spawn(move || {
+                let stops: Vec<Option<PausedAt>> =
+                    ["keys", "wait", "wait"].iter().enumerate().map(|(i, kind)| control.gate(at(i), kind, None, None).unwrap()).collect();
+                stops
+            })
+        };
+        // Stopped at the breakpoint; a step runs it and pauses before the next
+        let first = control.wait_until_paused(Duration::from_secs(5)).unwrap();
+        assert_eq!((first.index, first.breakpoint.clone()), (0, Some(Breakpoint::parse("keys"))));
+        control.step();
+        while control.state().waiting.as_ref().is_none_or(|w| w.index != 1) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        control.resume();
+        let stops = runner.join().unwrap();
+        assert_eq!(stops.iter().map(|s| s.as_ref().map(|s| s.index)).collect::<Vec<_>>(), [Some(0), Some(1), None]);
+
+        // Cancelling ends the wait
+        control.pause();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(control.gate(at(0), "wait", None, Some(&cancel)), Err(LunaError::Cancelled(_))));
+        assert_eq!(control.state().waiting, None);
+    }
+
+    #[test]
+    fn test_supervision_holds_only_uncertain_targets() {
+        let control = StepControl::new();
+        control.supervise(Some(0.8));
+        let at = |index| {
+            move || PausedAt { command: "macro".to_string(), index, action: "Click".to_string(), breakpoint: None, confidence: None }
+        };
+        let sure = ElementEvidence { confidence: 0.95, ..evidence("button", "Save") };
+        let unsure = ElementEvidence { confidence: 0.6, ..evidence("button", "Sove") };
+        assert_eq!(control.gate(at(0), "click", Some(&sure), None).unwrap(), None);
+        assert_eq!(control.gate(at(0), "keys", None, None).unwrap(), None);
+        assert!(!control.approve());
+
+        let runner = {
+            let (control, unsure, sure) = (control.clone(), unsure.clone(), sure.clone());
+            std::thread::spawn(move || {
+                let held = control.gate(at(0), "click", Some(&unsure), None);
+                let unattended = control.gate(at(1), "click", Some(&sure), None);
+                (held, unattended, control.gate(at(2), "click", Some(&unsure), None))
+            })
+        };
+        let held = control.wait_until_paused(Duration::from_secs(5)).unwrap();
+        assert_eq!((held.index, held.confidence), (0, Some(0.6)));
+        assert!(!control.state().paused);
+        assert!(control.approve());
+        while control.state().waiting.as_ref().is_none_or(|w| w.index != 2) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(control.reject());
+        let (held, unattended, rejected) = runner.join().unwrap();
+        assert_eq!(held.unwrap().map(|p| p.index), Some(0));
+        assert_eq!(unattended.unwrap(), None);
+        assert!(matches!(rejected, Err(LunaError::PermissionDenied(_))));
+        assert_eq!(control.state().waiting, None);
+    }
+}
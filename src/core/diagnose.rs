@@ -0,0 +1,318 @@
+/*!
+ * Luna Diagnose - A bug report in one file
+ *
+ * `luna diagnose` gathers what is usually asked for after an issue is
+ * filed into a single ZIP file: the configuration, the capability report,
+ * the last commands' transcripts, recent frames, log files, model and
+ * language pack versions and a metrics snapshot. Each part can be left out.
+ *
+ * Nothing leaves as it was recorded. Secrets are stripped from the
+ * configuration, transcripts and logs go through the redaction training
+ * export uses (`training.redact_patterns` included), and frames are
+ * pixelated so the layout stays visible but text can't be read. The
+ * bundle's `manifest.json` says what was included and what was not.
+ */
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::provenance::TranscriptRecord;
+use crate::ai::training::Redactor;
+use crate::utils::image_processing::Image;
+use crate::utils::zip::ZipWriter;
+
+/// Name of the bundle's table of contents
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Side (pixels) of the blocks frames are reduced to
+const PIXEL_BLOCK: usize = 8;
+/// Bytes kept from the end of each log file
+const MAX_LOG_BYTES: u64 = 1 << 20;
+/// Log files included, newest first
+const MAX_LOG_FILES: usize = 5;
+/// Config keys whose values are never included, matched within the key name
+const SECRET_KEYS: [&str; 5] = ["secret", "token", "password", "api_key", "webhook"];
+const STRIPPED: &str = "<stripped>";
+
+/// What a bundle can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BundlePart {
+    Config,
+    Capabilities,
+    Transcripts,
+    Frames,
+    Logs,
+    Models,
+    Metrics,
+}
+
+impl BundlePart {
+    pub const ALL: [BundlePart; 7] = [
+        BundlePart::Config,
+        BundlePart::Capabilities,
+        BundlePart::Transcripts,
+        BundlePart::Frames,
+        BundlePart::Logs,
+        BundlePart::Models,
+        BundlePart::Metrics,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BundlePart::Config => "config",
+            BundlePart::Capabilities => "capabilities",
+            BundlePart::Transcripts => "transcripts",
+            BundlePart::Frames => "frames",
+            BundlePart::Logs => "logs",
+            BundlePart::Models => "models",
+            BundlePart::Metrics => "metrics",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|part| part.name() == name.trim().to_lowercase())
+    }
+
+    /// What the part holds, for the checklist
+    pub fn description(&self) -> &'static str {
+        match self {
+            BundlePart::Config => "configuration, secrets stripped",
+            BundlePart::Capabilities => "capture, input and detector availability",
+            BundlePart::Transcripts => "recent commands and their actions, redacted",
+            BundlePart::Frames => "recent analyzed frames, pixelated",
+            BundlePart::Logs => "log files, redacted",
+            BundlePart::Models => "Luna, model and language pack versions",
+            BundlePart::Metrics => "metrics snapshot",
+        }
+    }
+}
+
+/// What to put in a bundle
+#[derive(Debug, Clone)]
+pub struct DiagnoseOptions {
+    pub parts: Vec<BundlePart>,
+    /// Most recent transcript records included
+    pub transcripts: usize,
+    /// Most recent frames included
+    pub frames: usize,
+}
+
+impl Default for DiagnoseOptions {
+    fn default() -> Self {
+        Self { parts: BundlePart::ALL.to_vec(), transcripts: 20, frames: 5 }
+    }
+}
+
+/// A part asked for but left out, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Omission {
+    pub part: BundlePart,
+    pub reason: String,
+}
+
+/// Table of contents written into the bundle
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    pub luna_version: &'static str,
+    /// Unix seconds
+    pub created_at: u64,
+    pub included: Vec<BundlePart>,
+    pub omitted: Vec<Omission>,
+    pub files: Vec<String>,
+}
+
+/// The files of a bundle, before they are written
+#[derive(Debug, Default)]
+pub struct DiagnosticBundle {
+    pub files: Vec<(String, Vec<u8>)>,
+    pub included: Vec<BundlePart>,
+    pub omitted: Vec<Omission>,
+}
+
+impl DiagnosticBundle {
+    pub fn add(&mut self, name: impl Into<String>, bytes: Vec<u8>) {
+        self.files.push((name.into(), bytes));
+    }
+
+    pub fn manifest(&self) -> Manifest {
+        Manifest {
+            luna_version: env!("CARGO_PKG_VERSION"),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            included: self.included.clone(),
+            omitted: self.omitted.clone(),
+            files: self.files.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+
+    /// Write the bundle as a ZIP file, manifest first; returns its size
+    pub fn write_zip(&self, path: &Path) -> Result<u64> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut zip = ZipWriter::new(fs::File::create(path)?);
+        zip.add(MANIFEST_FILE, &serde_json::to_vec_pretty(&self.manifest())?)?;
+        for (name, bytes) in &self.files {
+            zip.add(name, bytes)?;
+        }
+        Ok(zip.finish()?.metadata()?.len())
+    }
+}
+
+/// Replace the values of secret-looking keys, at any depth, with a placeholder
+pub fn strip_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_KEYS.iter().any(|secret| key.contains(secret)) && !value.is_null() {
+                    *value = Value::String(STRIPPED.to_string());
+                } else {
+                    strip_secrets(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_secrets),
+        _ => {}
+    }
+}
+
+/// `record` with its command, planned actions and recognized text redacted
+pub fn redact_transcript(record: &TranscriptRecord, redactor: &Redactor) -> TranscriptRecord {
+    let mut record = record.clone();
+    record.command = redactor.redact(&record.command);
+    for action in &mut record.actions {
+        action.action = redactor.redact(&action.action);
+        if let Some(element) = &mut action.element {
+            element.text = element.text.as_deref().map(|text| redactor.redact(text));
+        }
+    }
+    record
+}
+
+/// `image` with each `PIXEL_BLOCK`-sized block filled with its mean color
+pub fn pixelate(image: &Image) -> Image {
+    let mut out = Image::new(image.width, image.height, image.channels);
+    for by in (0..image.height).step_by(PIXEL_BLOCK) {
+        for bx in (0..image.width).step_by(PIXEL_BLOCK) {
+            let (x1, y1) = ((bx + PIXEL_BLOCK).min(image.width), (by + PIXEL_BLOCK).min(image.height));
+            let mut sums = vec![0u64; image.channels];
+            for y in by..y1 {
+                for x in bx..x1 {
+                    if let Some(pixel) = image.get_pixel(x, y) {
+                        sums.iter_mut().zip(pixel).for_each(|(sum, &c)| *sum += c as u64);
+                    }
+                }
+            }
+            let count = ((x1 - bx) * (y1 - by)) as u64;
+            let mean: Vec<u8> = sums.iter().map(|sum| (sum / count) as u8).collect();
+            for y in by..y1 {
+                for x in bx..x1 {
+                    out.set_pixel(x, y, &mean);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Files in the log directory, newest first
+pub fn log_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<(SystemTime, PathBuf)> = fs::read_dir(dir)?
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((metadata.modified().unwrap_or(UNIX_EPOCH), entry.path()))
+        })
+        .collect();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    Ok(files.into_iter().take(MAX_LOG_FILES).map(|(_, path)| path).collect())
+}
+
+/// The end of a log file, from its first whole line, redacted
+pub fn log_tail(path: &Path, redactor: &Redactor) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let start = file.metadata()?.len().saturating_sub(MAX_LOG_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let mut text = String::from_utf8_lossy(&bytes).into_owned();
+    if start > 0 {
+        text = text.split_once('\n').map(|(_, rest)| rest.to_string()).unwrap_or_default();
+    }
+    Ok(text.lines().map(|line| redactor.redact(line) + "\n").collect::<String>().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::provenance::{ActionProvenance, ElementEvidence, PlannedBy, SafetyVerdict};
+    use crate::LunaConfig;
+
+    #[test]
+    fn test_secrets_are_stripped_and_transcripts_redacted() {
+        let mut config = LunaConfig::default();
+        config.confirmation.totp_secret = Some("JBSWY3DPEHPK3PXP".to_string());
+        config.confirmation.webhook_url = Some("https://hooks.example.com/T000/secret".to_string());
+        let mut value = serde_json::to_value(&config).unwrap();
+        strip_secrets(&mut value);
+        let text = value.to_string();
+        assert!(!text.contains("JBSWY3DPEHPK3PXP") && !text.contains("hooks.example.com"));
+        assert_eq!(value["confirmation"]["totp_secret"], STRIPPED);
+        // Other settings are kept
+        assert_eq!(value["logging"]["level"], config.logging.level.as_str());
+
+        let record = TranscriptRecord {
+            command_id: "1-0".to_string(),
+            command: "type \"hunter2\" into password".to_string(),
+            client: None,
+            executed_at: 0,
+            actions: vec![ActionProvenance {
+                index: 0,
+                action: "Type { text: \"hunter2\" }".to_string(),
+                planned_by: PlannedBy::Literal,
+                element: Some(ElementEvidence {
+                    detection: Some(0),
+                    element_type: "text".to_string(),
+                    bounds: (0, 0, 10, 10),
+                    confidence: 0.9,
+                    text: Some("mail me at ada@example.com".to_string()),
+                    reliability: None,
+                    click_strategy: None,
+                }),
+                safety: SafetyVerdict::default(),
+            }],
+        };
+        let redacted = redact_transcript(&record, &Redactor::new(&[]).unwrap());
+        let text = serde_json::to_string(&redacted).unwrap();
+        assert!(!text.contains("hunter2") && !text.contains("ada@example.com"));
+        assert_eq!(redacted.command, "type <redacted> into password");
+    }
+
+    #[test]
+    fn test_bundle_zip_lists_parts_and_hides_frame_detail() {
+        let mut frame = Image::new(16, 16, 3);
+        frame.set_pixel(0, 0, &[255, 255, 255]);
+        let pixelated = pixelate(&frame);
+        assert_eq!(pixelated.get_pixel(7, 7), Some(&[3u8, 3, 3][..]));
+        assert_eq!(pixelated.get_pixel(8, 8), Some(&[0u8, 0, 0][..]));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut bundle = DiagnosticBundle::default();
+        bundle.add("metrics.prom", b"luna_commands_total 3\n".to_vec());
+        bundle.included.push(BundlePart::Metrics);
+        bundle.omitted.push(Omission { part: BundlePart::Logs, reason: "logging.log_dir is not set".to_string() });
+        let path = dir.path().join("report.zip");
+        let size = bundle.write_zip(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(size, bytes.len() as u64);
+        assert_eq!(&bytes[30..30 + MANIFEST_FILE.len()], MANIFEST_FILE.as_bytes());
+        let manifest = serde_json::to_string(&bundle.manifest()).unwrap();
+        assert!(manifest.contains("\"included\":[\"metrics\"]") && manifest.contains("log_dir is not set"));
+        assert_eq!(BundlePart::from_name("Frames"), Some(BundlePart::Frames));
+    }
+}
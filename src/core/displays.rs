@@ -0,0 +1,265 @@
+/*!
+ * Luna Displays - Monitor hotplug and resolution changes
+ *
+ * Coordinates from an analysis only hold for the display layout it was made
+ * under: plugging in a projector, unplugging a monitor or changing a
+ * resolution moves everything. The layout is polled like the session state
+ * (`xrandr --listmonitors` on X11, `System.Windows.Forms.Screen` on Windows;
+ * WM_DISPLAYCHANGE and RandR notifications need an event loop this crate
+ * does not run). When it changes, Luna drops what depends on screen geometry
+ * (the idle analysis, the analyzed frame and tracked element IDs), emits
+ * `DisplayChanged`, and stops a running command before an action aimed at a
+ * display that was removed or resized.
+ */
+
+use log::debug;
+use std::time::{Duration, Instant};
+
+use super::focus::command_output;
+use crate::vision::screen_capture::DisplayInfo;
+
+/// Source of the current display layout
+pub trait DisplayProbe {
+    /// Connected displays; `None` when the platform can't say
+    fn displays(&self) -> Option<Vec<DisplayInfo>>;
+}
+
+/// Asks the OS; elsewhere the layout is unknown and never changes
+pub struct SystemDisplayProbe;
+
+impl DisplayProbe for SystemDisplayProbe {
+    fn displays(&self) -> Option<Vec<DisplayInfo>> {
+        let displays = if cfg!(target_os = "windows") {
+            parse_windows(&command_output("powershell", &["-NoProfile", "-Command", WINDOWS_SCREENS])?)
+        } else if cfg!(target_os = "linux") {
+            parse_xrandr(&command_output("xrandr", &["--listmonitors"])?)
+        } else {
+            return None;
+        };
+        (!displays.is_empty()).then_some(displays)
+    }
+}
+
+/// One line per screen: "NAME X Y WIDTH HEIGHT PRIMARY"
+const WINDOWS_SCREENS: &str = "Add-Type -AssemblyName System.Windows.Forms; \
+    [System.Windows.Forms.Screen]::AllScreens | ForEach-Object { \
+    '{0} {1} {2} {3} {4} {5}' -f $_.DeviceName, $_.Bounds.X, $_.Bounds.Y, $_.Bounds.Width, $_.Bounds.Height, $_.Primary }";
+
+/// How the layout changed between two polls
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayChange {
+    pub before: Vec<DisplayInfo>,
+    pub after: Vec<DisplayInfo>,
+}
+
+impl DisplayChange {
+    /// Displays that are gone or no longer have the same position and size
+    pub fn removed(&self) -> Vec<DisplayInfo> {
+        self.before.iter().filter(|d| !self.after.iter().any(|a| same_geometry(a, d))).cloned().collect()
+    }
+
+    /// Displays that are new or now have a different position or size
+    pub fn added(&self) -> Vec<DisplayInfo> {
+        self.after.iter().filter(|a| !self.before.iter().any(|d| same_geometry(a, d))).cloned().collect()
+    }
+}
+
+fn same_geometry(a: &DisplayInfo, b: &DisplayInfo) -> bool {
+    (&a.name, a.x, a.y, a.width, a.height) == (&b.name, b.x, b.y, b.width, b.height)
+}
+
+/// The display showing (`x`, `y`)
+pub fn display_at(displays: &[DisplayInfo], x: i32, y: i32) -> Option<&DisplayInfo> {
+    displays.iter().find(|d| d.contains_point(x, y))
+}
+
+/// The display in `before` that (`x`, `y`) was on, when `after` no longer
+/// has it with the same position and size
+pub fn lost_display<'a>(before: &'a [DisplayInfo], after: &[DisplayInfo], x: i32, y: i32) -> Option<&'a DisplayInfo> {
+    display_at(before, x, y).filter(|display| !after.iter().any(|a| same_geometry(a, display)))
+}
+
+/// Caches the layout between polls and reports when it changed
+pub struct DisplayMonitor {
+    probe: Box<dyn DisplayProbe + Send>,
+    interval: Duration,
+    checked: Option<Instant>,
+    known: Option<Vec<DisplayInfo>>,
+}
+
+impl DisplayMonitor {
+    pub fn new(probe: Box<dyn DisplayProbe + Send>, interval: Duration) -> Self {
+        Self { probe, interval, checked: None, known: None }
+    }
+
+    /// Current layout, as of the last probe
+    pub fn displays(&self) -> Option<&[DisplayInfo]> {
+        self.known.as_deref()
+    }
+
+    /// Probe again once the interval has passed; the change, if the layout
+    /// differs from the last one seen. The first layout seen is no change.
+    pub fn poll(&mut self) -> Option<DisplayChange> {
+        if self.checked.is_some_and(|at| at.elapsed() < self.interval) {
+            return None;
+        }
+        self.checked = Some(Instant::now());
+        let current = self.probe.displays()?;
+        let previous = self.known.replace(current.clone())?;
+        if previous == current {
+            return None;
+        }
+        debug!("Display layout changed: {} -> {} displays", previous.len(), current.len());
+        Some(DisplayChange { before: previous, after: current })
+    }
+}
+
+/// `xrandr --listmonitors`: " 0: +*eDP-1 1920/344x1080/193+0+0  eDP-1"
+fn parse_xrandr(output: &str) -> Vec<DisplayInfo> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Monitors:"))
+        .skip(1)
+        .enumerate()
+        .filter_map(|(id, line)| {
+            let mut fields = line.split_whitespace().skip(1);
+            let flags = fields.next()?;
+            let geometry = fields.next()?;
+            let (width, rest) = geometry.split_once('/')?;
+            let (_, rest) = rest.split_once('x')?;
+            let (height, rest) = rest.split_once('/')?;
+            let mut offsets = rest.splitn(3, '+').skip(1);
+            Some(DisplayInfo {
+                id: id as u32,
+                name: flags.trim_start_matches(['+', '*']).to_string(),
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+                x: offsets.next()?.parse().ok()?,
+                y: offsets.next()?.parse().ok()?,
+                is_primary: flags.contains('*'),
+            })
+        })
+        .collect()
+}
+
+/// The screen script's "\\.\DISPLAY1 0 0 1920 1080 True" lines
+fn parse_windows(output: &str) -> Vec<DisplayInfo> {
+    output
+        .lines()
+        .enumerate()
+        .filter_map(|(id, line)| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, x, y, width, height, primary] = fields.as_slice() else {
+                return None;
+            };
+            Some(DisplayInfo {
+                id: id as u32,
+                name: name.to_string(),
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+                is_primary: primary.eq_ignore_ascii_case("true"),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::sandbox::SandboxScene;
+    use crate::core::{ExecuteOptions, Luna, LunaConfig, LunaError, LunaEvent};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn display(name: &str, x: i32, width: u32) -> DisplayInfo {
+        DisplayInfo { id: 0, name: name.to_string(), width, height: 1080, x, y: 0, is_primary: x == 0 }
+    }
+
+    /// Reports whatever layout the test last set
+    #[derive(Clone)]
+    struct Layout(Arc<Mutex<Vec<DisplayInfo>>>);
+
+    impl DisplayProbe for Layout {
+        fn displays(&self) -> Option<Vec<DisplayInfo>> {
+            Some(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[test]
+    fn test_platform_outputs() {
+        let xrandr = "Monitors: 2\n 0: +*eDP-1 1920/344x1080/193+0+0  eDP-1\n 1: +HDMI-1 1280/531x720/299+1920+-40  HDMI-1\n";
+        let displays = parse_xrandr(xrandr);
+        assert_eq!(displays.len(), 2);
+        assert_eq!((displays[0].name.as_str(), displays[0].is_primary, displays[0].width), ("eDP-1", true, 1920));
+        assert_eq!((displays[1].x, displays[1].y, displays[1].height, displays[1].is_primary), (1920, -40, 720, false));
+
+        let windows = parse_windows("\\\\.\\DISPLAY1 0 0 2560 1440 True\r\n\\\\.\\DISPLAY2 -1920 0 1920 1080 False\r\n");
+        assert_eq!(display_at(&windows, -5, 10).map(|d| d.name.as_str()), Some("\\\\.\\DISPLAY2"));
+        assert!(windows[0].is_primary && display_at(&windows, 0, 2000).is_none());
+    }
+
+    #[test]
+    fn test_only_changes_are_reported() {
+        let layout = Layout(Arc::new(Mutex::new(vec![display("eDP-1", 0, 1920)])));
+        let mut monitor = DisplayMonitor::new(Box::new(layout.clone()), Duration::ZERO);
+        assert_eq!(monitor.poll(), None, "the first layout is the baseline");
+        assert_eq!(monitor.poll(), None);
+
+        layout.0.lock().unwrap().push(display("HDMI-1", 1920, 1280));
+        let change = monitor.poll().unwrap();
+        assert_eq!((change.removed().len(), change.added()[0].name.as_str()), (0, "HDMI-1"));
+        assert_eq!(monitor.displays().unwrap().len(), 2);
+
+        // A resolution change removes the old mode and adds the new one
+        layout.0.lock().unwrap()[1].width = 1920;
+        let change = monitor.poll().unwrap();
+        assert_eq!(change.removed()[0].width, 1280);
+        assert_eq!(change.added()[0].width, 1920);
+    }
+
+    /// Two displays, then only the first from probe number `after` on
+    struct Unplugs {
+        probes: AtomicUsize,
+        after: usize,
+    }
+
+    impl DisplayProbe for Unplugs {
+        fn displays(&self) -> Option<Vec<DisplayInfo>> {
+            let mut displays = vec![display("eDP-1", 0, 400), display("HDMI-1", 400, 880)];
+            if self.probes.fetch_add(1, Ordering::SeqCst) >= self.after {
+                displays.pop();
+            }
+            Some(displays)
+        }
+    }
+
+    #[test]
+    fn test_actions_on_a_removed_display_are_aborted() {
+        let mut config = LunaConfig::default();
+        config.displays.poll_ms = 0;
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        luna.subscribe_to_events(move |event| {
+            if let LunaEvent::DisplayChanged { removed, .. } = event {
+                seen.lock().unwrap().push(removed.len());
+            }
+        });
+
+        // Probed when the command starts and before each of its three
+        // actions; HDMI-1 is unplugged between the key press and the click
+        luna.set_display_probe(Box::new(Unplugs { probes: AtomicUsize::new(0), after: 2 }));
+        let err = luna.execute_command("while holding shift, click at 600, 20", &ExecuteOptions::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::StaleFrame(m)) if m.contains("HDMI-1")), "{}", err);
+        assert_eq!(*events.lock().unwrap(), [1]);
+        assert_eq!(luna.displays().unwrap().len(), 1);
+
+        // Clicks on a display that stays are unaffected
+        luna.set_display_probe(Box::new(Unplugs { probes: AtomicUsize::new(0), after: 2 }));
+        luna.execute_command("while holding shift, click at 100, 20", &ExecuteOptions::default()).unwrap();
+        assert!(sandbox.scene().log.iter().any(|line| line.contains("(100, 20)")), "{:?}", sandbox.scene().log);
+    }
+}
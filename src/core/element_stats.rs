@@ -0,0 +1,221 @@
+/*!
+ * Luna Element Stats - How clicking each element has gone before
+ *
+ * Every verified click is recorded against the target's appearance
+ * fingerprint: whether the screen reacted within the verification timeout,
+ * and how long that took. Elements with a good record are preferred when a
+ * command names several equally well ("click Save" with two Save buttons),
+ * and the numbers are there for diagnostics: a control that keeps failing
+ * verification is a detection or timing problem worth looking at.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ai::fingerprint::ElementFingerprint;
+use crate::utils::text;
+
+/// File name of the history under the storage root
+pub const ELEMENT_STATS_FILE: &str = "element_stats.json";
+
+/// Outcomes of clicking one element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElementStats {
+    pub fingerprint: ElementFingerprint,
+    /// Clicks after which the screen changed within the verification timeout
+    pub successes: u32,
+    /// Clicks after which nothing changed
+    pub failures: u32,
+    /// Time from click to the first change, summed over successes
+    pub total_effect_ms: u64,
+    /// Unix seconds the element was last clicked or seen in a planned frame
+    pub last_seen_at: u64,
+}
+
+impl ElementStats {
+    pub fn clicks(&self) -> u32 {
+        self.successes + self.failures
+    }
+
+    /// Fraction of clicks that had an effect; `None` before the first click
+    pub fn success_rate(&self) -> Option<f64> {
+        (self.clicks() > 0).then(|| self.successes as f64 / self.clicks() as f64)
+    }
+
+    /// Mean time from click to effect; `None` before the first success
+    pub fn average_effect_ms(&self) -> Option<u64> {
+        (self.successes > 0).then(|| self.total_effect_ms / self.successes as u64)
+    }
+
+    /// Success rate pulled toward 0.5 while there are few clicks, so one
+    /// lucky click doesn't outrank a long good record
+    pub fn reliability(&self) -> f64 {
+        (self.successes as f64 + 1.0) / (self.clicks() as f64 + 2.0)
+    }
+}
+
+/// Per-element history persisted as one JSON file
+pub struct ElementHistory {
+    path: PathBuf,
+    entries: Vec<ElementStats>,
+    /// Least recently seen elements are dropped beyond this many
+    max_entries: usize,
+}
+
+impl ElementHistory {
+    /// Load the history at `path`; a missing file is an empty history
+    pub fn open(path: impl Into<PathBuf>, max_entries: usize) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, entries, max_entries })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stats of the element `fingerprint` describes
+    pub fn get(&self, fingerprint: &ElementFingerprint) -> Option<&ElementStats> {
+        self.entries.iter().find(|e| e.fingerprint.is_same_element(fingerprint))
+    }
+
+    /// Most recently seen first
+    pub fn list(&self) -> impl Iterator<Item = &ElementStats> {
+        let mut entries: Vec<&ElementStats> = self.entries.iter().collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.last_seen_at));
+        entries.into_iter()
+    }
+
+    /// Whether any entry has this type and text, a cheap check before
+    /// fingerprinting an element
+    pub fn knows(&self, element_type: &str, text: Option<&str>) -> bool {
+        let text = text.map(|t| text::fold_case(t.trim()));
+        self.entries.iter().any(|e| {
+            e.fingerprint.element_type == element_type && e.fingerprint.text.as_ref().map(|t| text::fold_case(t.trim())) == text
+        })
+    }
+
+    /// Note that the element was on screen; saved with the next outcome
+    pub fn mark_seen(&mut self, fingerprint: &ElementFingerprint) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.fingerprint.is_same_element(fingerprint)) {
+            entry.last_seen_at = unix_now();
+        }
+    }
+
+    /// Record a click on the element and save: `Some(ms)` when the screen
+    /// changed that long after it, `None` when it didn't
+    pub fn record(&mut self, fingerprint: &ElementFingerprint, effect_ms: Option<u64>) -> Result<&ElementStats> {
+        let index = match self.entries.iter().position(|e| e.fingerprint.is_same_element(fingerprint)) {
+            Some(index) => index,
+            None => {
+                self.entries.push(ElementStats {
+                    fingerprint: fingerprint.clone(),
+                    successes: 0,
+                    failures: 0,
+                    total_effect_ms: 0,
+                    last_seen_at: 0,
+                });
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[index];
+        match effect_ms {
+            Some(ms) => {
+                entry.successes += 1;
+                entry.total_effect_ms += ms;
+            }
+            None => entry.failures += 1,
+        }
+        // Keep the latest appearance so gradual restyling doesn't drift out of range
+        entry.fingerprint = fingerprint.clone();
+        entry.last_seen_at = unix_now();
+        let recorded = entry.fingerprint.clone();
+
+        if self.entries.len() > self.max_entries {
+            self.entries.sort_by_key(|e| std::cmp::Reverse(e.last_seen_at));
+            self.entries.truncate(self.max_entries);
+        }
+        self.save()?;
+        Ok(self.get(&recorded).expect("just recorded"))
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write then rename so a crash never leaves a truncated history
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(&self.entries)?)?;
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(text: &str, hash: u64) -> ElementFingerprint {
+        ElementFingerprint {
+            element_type: "button".to_string(),
+            text: Some(text.to_string()),
+            width: 80,
+            height: 30,
+            hash,
+            mean_luma: 120.0,
+            contrast: 60.0,
+            last_x: 10,
+            last_y: 10,
+        }
+    }
+
+    #[test]
+    fn test_outcomes_accumulate_per_element_and_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ELEMENT_STATS_FILE);
+        let save = fingerprint("Save", 0x0f0f_0f0f_0f0f_0f0f);
+
+        let mut history = ElementHistory::open(&path, 10).unwrap();
+        history.record(&save, Some(120)).unwrap();
+        // Hover styling flips a few hash bits; still the same button
+        history.record(&fingerprint(" save ", 0x0f0f_0f0f_0f0f_0f0e), Some(80)).unwrap();
+        history.record(&save, None).unwrap();
+        history.record(&fingerprint("Cancel", 0x0f0f_0f0f_0f0f_0f0f), None).unwrap();
+
+        let history = ElementHistory::open(&path, 10).unwrap();
+        let stats = history.get(&save).unwrap();
+        assert_eq!((stats.successes, stats.failures), (2, 1));
+        assert_eq!(stats.average_effect_ms(), Some(100));
+        assert!((stats.success_rate().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.reliability(), 0.6);
+        assert!(stats.last_seen_at > 0);
+
+        assert!(history.get(&fingerprint("Save", !0x0f0f_0f0f_0f0f_0f0f)).is_none(), "different appearance");
+        assert!(history.knows("button", Some("cancel")));
+        assert!(!history.knows("link", Some("cancel")));
+    }
+
+    #[test]
+    fn test_least_recently_seen_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = ElementHistory::open(dir.path().join(ELEMENT_STATS_FILE), 2).unwrap();
+        history.record(&fingerprint("One", 0x0f0f), None).unwrap();
+        history.entries[0].last_seen_at = 1;
+        history.record(&fingerprint("Two", 0x0f0f), None).unwrap();
+        history.record(&fingerprint("Three", 0x0f0f), None).unwrap();
+
+        let texts: Vec<&str> = history.list().filter_map(|e| e.fingerprint.text.as_deref()).collect();
+        assert_eq!(texts.len(), 2);
+        assert!(!texts.contains(&"One"));
+    }
+}
@@ -0,0 +1,339 @@
+/*!
+ * Luna Environment - Snapshots of the desktop a run expects
+ *
+ * A script recorded against a 1080p light desktop that runs on a 4K dark
+ * one doesn't fail where the difference is; it fails later, on an element
+ * that "isn't there". `EnvironmentSnapshot` records what detection depends
+ * on (the display layout, scaling, how light the screen is, the app in
+ * front and the settings that change what is found) so a later run can
+ * compare against it before its first action and stop with a report of
+ * what differs.
+ *
+ * The theme is judged from the captured screen rather than the OS setting:
+ * a dark app on a light desktop looks dark to the detectors, and that is
+ * what matters. Scaling comes from the OS where it can be read (the applied
+ * DPI on Windows, `Xft.dpi` on X11). Settings the platform can't report are
+ * left out of a snapshot, and only what the expected snapshot recorded is
+ * checked.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use super::focus::command_output;
+use super::LunaConfig;
+use crate::utils::image_processing::Image;
+use crate::vision::screen_capture::DisplayInfo;
+
+/// Settings that change what detection finds, as dotted config keys
+pub const RELEVANT_CONFIG: [&str; 6] = [
+    "vision.confidence_threshold",
+    "vision.text_confidence_threshold",
+    "vision.max_elements",
+    "ocr.languages",
+    "input.locale",
+    "speed.preset",
+];
+/// Mean luma (0-255) below which a screen counts as dark
+const DARK_BELOW: f64 = 110.0;
+/// Pixels skipped in each direction when measuring luma
+const LUMA_STRIDE: usize = 8;
+
+/// How light the screen is overall
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Theme of a captured screen, from its mean luma
+    pub fn of_frame(frame: &Image) -> Option<Self> {
+        if frame.channels == 0 {
+            return None;
+        }
+        let (mut sum, mut count) = (0u64, 0u64);
+        for y in (0..frame.height).step_by(LUMA_STRIDE) {
+            for x in (0..frame.width).step_by(LUMA_STRIDE) {
+                let pixel = frame.get_pixel(x, y)?;
+                let luma = match pixel {
+                    [r, g, b, ..] => (299 * *r as u64 + 587 * *g as u64 + 114 * *b as u64) / 1000,
+                    [gray, ..] => *gray as u64,
+                    [] => return None,
+                };
+                sum += luma;
+                count += 1;
+            }
+        }
+        (count > 0).then(|| if (sum as f64 / count as f64) < DARK_BELOW { Theme::Dark } else { Theme::Light })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+}
+
+/// One display of the layout
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplaySnapshot {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+impl From<&DisplayInfo> for DisplaySnapshot {
+    fn from(display: &DisplayInfo) -> Self {
+        Self {
+            name: display.name.clone(),
+            x: display.x,
+            y: display.y,
+            width: display.width,
+            height: display.height,
+            primary: display.is_primary,
+        }
+    }
+}
+
+/// The desktop a run was recorded or last checked against
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentSnapshot {
+    pub luna_version: String,
+    /// Unix seconds
+    pub captured_at: u64,
+    /// Left to right, then top to bottom
+    pub displays: Vec<DisplaySnapshot>,
+    /// Display scaling in percent (100 = 96 DPI)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale_percent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<Theme>,
+    /// Application of the focused window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foreground_app: Option<String>,
+    /// Values of `RELEVANT_CONFIG`
+    #[serde(default)]
+    pub config: BTreeMap<String, Value>,
+}
+
+impl EnvironmentSnapshot {
+    /// Snapshot of `displays`, the captured `frame` and `config`, with
+    /// scaling and the foreground app read from the OS
+    pub fn capture(displays: &[DisplayInfo], frame: &Image, config: &LunaConfig) -> Self {
+        let mut displays: Vec<DisplaySnapshot> = displays.iter().map(DisplaySnapshot::from).collect();
+        if displays.is_empty() {
+            // The platform can't list displays; the captured screen is the layout
+            displays.push(DisplaySnapshot {
+                name: "screen".to_string(),
+                x: 0,
+                y: 0,
+                width: frame.width as u32,
+                height: frame.height as u32,
+                primary: true,
+            });
+        }
+        displays.sort_by_key(|d| (d.x, d.y));
+        let settings = serde_json::to_value(config).unwrap_or(Value::Null);
+        let config = RELEVANT_CONFIG
+            .iter()
+            .map(|key| {
+                let value = key.split('.').try_fold(&settings, |value, segment| value.get(segment));
+                (key.to_string(), value.cloned().unwrap_or(Value::Null))
+            })
+            .collect();
+        Self {
+            luna_version: env!("CARGO_PKG_VERSION").to_string(),
+            captured_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            displays,
+            scale_percent: system_scale_percent(),
+            theme: Theme::of_frame(frame),
+            foreground_app: super::focus::active_window_title().map(|title| app_name(&title)),
+            config,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("cannot read environment snapshot {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// How `found` differs from this expected snapshot; `None` when it
+    /// matches everything this one recorded
+    pub fn compare(&self, found: &EnvironmentSnapshot) -> Option<EnvironmentMismatch> {
+        let mut mismatch = EnvironmentMismatch::default();
+        let geometry = |displays: &[DisplaySnapshot]| -> Vec<(i32, i32, u32, u32, bool)> {
+            displays.iter().map(|d| (d.x, d.y, d.width, d.height, d.primary)).collect()
+        };
+        if !self.displays.is_empty() && geometry(&self.displays) != geometry(&found.displays) {
+            mismatch.push("displays", layout(&self.displays), layout(&found.displays));
+        }
+        if let Some(expected) = self.scale_percent.filter(|expected| found.scale_percent != Some(*expected)) {
+            let scaling = |scale: Option<u32>| scale.map_or("unknown scaling".to_string(), |s| format!("{}% scaling", s));
+            mismatch.push("scale", scaling(Some(expected)), scaling(found.scale_percent));
+        }
+        if let Some(expected) = self.theme.filter(|expected| found.theme != Some(*expected)) {
+            let theme = |theme: Option<Theme>| format!("{} theme", theme.map_or("unknown", |t| t.as_str()));
+            mismatch.push("theme", theme(Some(expected)), theme(found.theme));
+        }
+        if let Some(expected) = self.foreground_app.as_ref().filter(|expected| found.foreground_app.as_ref() != Some(*expected)) {
+            let found = found.foreground_app.as_deref().unwrap_or("no known app");
+            mismatch.push("foreground_app", format!("{} in front", expected), format!("{} in front", found));
+        }
+        for (key, expected) in &self.config {
+            let found = found.config.get(key).unwrap_or(&Value::Null);
+            if found != expected {
+                mismatch.push(&format!("config.{}", key), format!("{} = {}", key, expected), format!("{} = {}", key, found));
+            }
+        }
+        (!mismatch.differences.is_empty()).then_some(mismatch)
+    }
+}
+
+/// One setting that differs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Difference {
+    /// "displays", "scale", "theme", "foreground_app" or "config.KEY"
+    pub setting: String,
+    pub expected: String,
+    pub found: String,
+}
+
+/// Everything that differs from the expected environment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentMismatch {
+    pub differences: Vec<Difference>,
+}
+
+impl EnvironmentMismatch {
+    fn push(&mut self, setting: &str, expected: String, found: String) {
+        self.differences.push(Difference { setting: setting.to_string(), expected, found });
+    }
+}
+
+impl fmt::Display for EnvironmentMismatch {
+    /// "expected 1080p light theme, found 4K dark theme"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected: Vec<&str> = self.differences.iter().map(|d| d.expected.as_str()).collect();
+        let found: Vec<&str> = self.differences.iter().map(|d| d.found.as_str()).collect();
+        write!(f, "expected {}, found {}", expected.join(" "), found.join(" "))
+    }
+}
+
+/// Common name of a resolution ("1080p", "4K"), or "WIDTHxHEIGHT"
+pub fn resolution_name(width: u32, height: u32) -> String {
+    match (width, height) {
+        (1280, 720) => "720p".to_string(),
+        (1920, 1080) => "1080p".to_string(),
+        (2560, 1440) => "1440p".to_string(),
+        (3840, 2160) => "4K".to_string(),
+        (5120, 2880) => "5K".to_string(),
+        _ => format!("{}x{}", width, height),
+    }
+}
+
+/// "1080p" for one display, "1080p + 4K" for several
+fn layout(displays: &[DisplaySnapshot]) -> String {
+    match displays {
+        [] => "no displays".to_string(),
+        _ => displays.iter().map(|d| resolution_name(d.width, d.height)).collect::<Vec<_>>().join(" + "),
+    }
+}
+
+/// Application part of a window title: "report.docx - Word" -> "Word"
+fn app_name(title: &str) -> String {
+    let app = title.rsplit(" - ").next().unwrap_or(title);
+    app.rsplit(" — ").next().unwrap_or(app).trim().to_string()
+}
+
+/// Display scaling in percent, where the OS reports it
+fn system_scale_percent() -> Option<u32> {
+    let dpi: f64 = if cfg!(target_os = "windows") {
+        command_output("powershell", &["-NoProfile", "-Command", WINDOWS_DPI])?.trim().parse().ok()?
+    } else if cfg!(target_os = "linux") {
+        parse_xrdb_dpi(&command_output("xrdb", &["-query"])?)?
+    } else {
+        return None;
+    };
+    (dpi > 0.0).then(|| (dpi / 96.0 * 100.0).round() as u32)
+}
+
+/// Applied DPI of the signed-in user's desktop
+const WINDOWS_DPI: &str = "(Get-ItemProperty 'HKCU:\\Control Panel\\Desktop\\WindowMetrics' -Name AppliedDPI).AppliedDPI";
+
+/// `Xft.dpi:\t144` from `xrdb -query`
+fn parse_xrdb_dpi(output: &str) -> Option<f64> {
+    output.lines().find_map(|line| line.strip_prefix("Xft.dpi:")).and_then(|dpi| dpi.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(width: u32, height: u32, theme: Theme) -> EnvironmentSnapshot {
+        EnvironmentSnapshot {
+            luna_version: "0.1.0".to_string(),
+            captured_at: 0,
+            displays: vec![DisplaySnapshot { name: "eDP-1".to_string(), x: 0, y: 0, width, height, primary: true }],
+            scale_percent: Some(100),
+            theme: Some(theme),
+            foreground_app: Some("Word".to_string()),
+            config: BTreeMap::from([("ocr.languages".to_string(), serde_json::json!(["eng"]))]),
+        }
+    }
+
+    #[test]
+    fn test_mismatches_are_reported_by_setting() {
+        let expected = snapshot(1920, 1080, Theme::Light);
+        assert_eq!(expected.compare(&EnvironmentSnapshot { captured_at: 99, ..expected.clone() }), None);
+
+        let renamed = vec![DisplaySnapshot { name: "DP-2".to_string(), ..expected.displays[0].clone() }];
+        assert_eq!(expected.compare(&EnvironmentSnapshot { displays: renamed, ..expected.clone() }), None, "display names don't matter");
+
+        let mismatch = expected.compare(&snapshot(3840, 2160, Theme::Dark)).unwrap();
+        assert_eq!(mismatch.to_string(), "expected 1080p light theme, found 4K dark theme");
+        let mut found = snapshot(1920, 1080, Theme::Light);
+        found.scale_percent = None;
+        found.foreground_app = Some("Excel".to_string());
+        found.config.insert("ocr.languages".to_string(), serde_json::json!(["deu"]));
+        let settings: Vec<String> = expected.compare(&found).unwrap().differences.into_iter().map(|d| d.setting).collect();
+        assert_eq!(settings, ["scale", "foreground_app", "config.ocr.languages"]);
+
+        // What the expected snapshot didn't record isn't checked
+        let lenient = EnvironmentSnapshot { scale_percent: None, theme: None, foreground_app: None, config: BTreeMap::new(), ..expected };
+        assert_eq!(lenient.compare(&found), None);
+    }
+
+    #[test]
+    fn test_theme_from_frame_and_scale_from_xrdb() {
+        let mut frame = Image::new(64, 48, 3);
+        assert_eq!(Theme::of_frame(&frame), Some(Theme::Dark));
+        frame.data.fill(235);
+        assert_eq!(Theme::of_frame(&frame), Some(Theme::Light));
+        assert_eq!(parse_xrdb_dpi("Xcursor.size:\t24\nXft.dpi:\t144\n"), Some(144.0));
+        assert_eq!(parse_xrdb_dpi("Xcursor.size:\t24\n"), None);
+        assert_eq!(app_name("report.docx - Word"), "Word");
+        assert_eq!(app_name("Calculator"), "Calculator");
+    }
+}
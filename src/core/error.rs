@@ -4,6 +4,9 @@
 
 use std::fmt;
 
+use super::environment::EnvironmentMismatch;
+use crate::ai::explain::NoMatch;
+
 /// Luna-specific error types
 #[derive(Debug)]
 pub enum LunaError {
@@ -29,8 +32,22 @@ pub enum LunaError {
     Timeout(String),
     /// Resource not found
     NotFound(String),
+    /// A command's target is not on screen; says what came closest
+    NoMatchingElement(Box<NoMatch>),
     /// Permission denied
     PermissionDenied(String),
+    /// User script failed to compile or run
+    Script(String),
+    /// Request was cancelled by the caller
+    Cancelled(String),
+    /// Command postponed because running it now would be disruptive
+    Deferred(String),
+    /// Screen changed between analysis and execution
+    StaleFrame(String),
+    /// Command is ambiguous; the question waits for `Luna::answer_clarification`
+    NeedsClarification(String),
+    /// The desktop differs from the environment snapshot a run expects
+    EnvironmentMismatch(Box<EnvironmentMismatch>),
 }
 
 impl fmt::Display for LunaError {
@@ -47,7 +64,52 @@ impl fmt::Display for LunaError {
             LunaError::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
             LunaError::Timeout(msg) => write!(f, "Operation timeout: {}", msg),
             LunaError::NotFound(msg) => write!(f, "Resource not found: {}", msg),
+            LunaError::NoMatchingElement(no_match) => write!(f, "Resource not found: {}", no_match),
             LunaError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            LunaError::Script(msg) => write!(f, "Script error: {}", msg),
+            LunaError::Cancelled(msg) => write!(f, "Cancelled: {}", msg),
+            LunaError::Deferred(msg) => write!(f, "Deferred: {}", msg),
+            LunaError::StaleFrame(msg) => write!(f, "Stale frame: {}", msg),
+            LunaError::NeedsClarification(question) => write!(f, "Clarification needed: {}", question),
+            LunaError::EnvironmentMismatch(mismatch) => write!(f, "Environment mismatch: {}", mismatch),
+        }
+    }
+}
+
+impl LunaError {
+    /// Short machine-readable name, as used in JSON output and test corpora
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LunaError::Config(_) => "config",
+            LunaError::UnsafeCommand(_) | LunaError::UnsafeAction(_) => "unsafe",
+            LunaError::InvalidArgument(_) => "invalid_argument",
+            LunaError::NotFound(_) | LunaError::NoMatchingElement(_) => "not_found",
+            LunaError::PermissionDenied(_) => "permission_denied",
+            LunaError::Timeout(_) => "timeout",
+            LunaError::Cancelled(_) => "cancelled",
+            LunaError::Deferred(_) => "deferred",
+            LunaError::StaleFrame(_) => "stale_frame",
+            LunaError::NeedsClarification(_) => "needs_clarification",
+            LunaError::EnvironmentMismatch(_) => "environment_mismatch",
+            _ => "luna",
+        }
+    }
+}
+
+impl LunaError {
+    /// What came closest, when a command found no target
+    pub fn no_match(&self) -> Option<&NoMatch> {
+        match self {
+            LunaError::NoMatchingElement(no_match) => Some(no_match),
+            _ => None,
+        }
+    }
+
+    /// What differed, when the desktop was not the one a run expects
+    pub fn environment_mismatch(&self) -> Option<&EnvironmentMismatch> {
+        match self {
+            LunaError::EnvironmentMismatch(mismatch) => Some(mismatch),
+            _ => None,
         }
     }
 }
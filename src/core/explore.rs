@@ -0,0 +1,403 @@
+/*!
+ * Luna Explore - Mapping an application's UI without changing it
+ *
+ * `luna explore` inventories the window in front: its menus, toolbars and
+ * labelled controls, including what only shows on demand. Icons without
+ * text are hovered to read their tooltips, and clipped lists are scrolled
+ * through a step at a time and then scrolled back. Nothing is clicked or
+ * typed. Exploration is time-boxed (`explore.budget_secs`); a map cut
+ * short says so.
+ *
+ * The `UiMap` lists each element once, with the frame it was first seen
+ * in, and the app's vocabulary: the names commands can use for it. Named
+ * elements visible without scrolling can be remembered as anchors (see
+ * `core::anchors`).
+ */
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::spy::query_for;
+use super::{ElementBounds, ScreenAnalysis, ScreenElement};
+use crate::ai::compare::{outline, to_rgb};
+use crate::ai::text_match::fold;
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// Element types that are controls in their own right
+const CONTROL_TYPES: [&str; 13] = [
+    "button", "icon", "link", "tab", "checkbox", "toggle", "textfield", "textbox", "input", "combobox", "dropdown",
+    "slider", "listitem",
+];
+/// Element types that make up a toolbar
+const TOOL_TYPES: [&str; 2] = ["button", "icon"];
+/// Buttons and icons side by side in one container that make a toolbar
+const MIN_TOOLBAR_ITEMS: usize = 3;
+/// Pixels around a hovered element in which new text is its tooltip
+const TOOLTIP_REACH: f64 = 48.0;
+/// Overlap above which text is where it already was, not a tooltip
+const SAME_PLACE_IOU: f64 = 0.5;
+
+const MENU_COLOR: [u8; 3] = [70, 110, 230];
+const TOOLBAR_COLOR: [u8; 3] = [240, 150, 0];
+const CONTROL_COLOR: [u8; 3] = [40, 180, 60];
+
+/// What part of the UI an element belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UiRole {
+    Menu,
+    Toolbar,
+    Control,
+}
+
+/// Where an element's name came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameSource {
+    Text,
+    /// The label next to it (see `ScreenAnalysis::link_labels`)
+    Label,
+    /// The tooltip shown while hovering it
+    Tooltip,
+}
+
+/// One element of the map
+#[derive(Debug, Clone, Serialize)]
+pub struct MappedElement {
+    pub element_type: String,
+    pub role: UiRole,
+    pub name: Option<String>,
+    pub named_by: Option<NameSource>,
+    /// `(x, y, width, height)` in the frame it was first seen in
+    pub bounds: (i32, i32, i32, i32),
+    /// Query selecting it (see `spy::query_for`)
+    pub query: String,
+    /// Index into `UiMap::frames` of the frame it was first seen in
+    pub frame: usize,
+    /// Index in that frame's analysis
+    #[serde(skip)]
+    pub index: usize,
+}
+
+impl MappedElement {
+    pub fn center(&self) -> (i32, i32) {
+        let (x, y, width, height) = self.bounds;
+        (x + width / 2, y + height / 2)
+    }
+}
+
+/// One analyzed view of the app
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MappedFrame {
+    /// `(x, y, width, height)` of the area scrolled; `None` for the app as found
+    pub scrolled_area: Option<(i32, i32, i32, i32)>,
+    /// Wheel notches scrolled down in that area
+    pub scrolled: i32,
+    /// Elements first seen in this frame
+    pub found: usize,
+    /// Screenshot with those elements outlined, when frames are saved
+    pub image: Option<PathBuf>,
+}
+
+/// Inventory of an app's UI
+#[derive(Debug, Clone, Serialize)]
+pub struct UiMap {
+    /// Title of the window explored, when known
+    pub app: Option<String>,
+    pub elapsed_ms: u64,
+    /// False when the time budget or a cancel ended exploration early
+    pub complete: bool,
+    pub frames: Vec<MappedFrame>,
+    pub elements: Vec<MappedElement>,
+    /// Distinct element names, sorted
+    pub vocabulary: Vec<String>,
+    /// Anchors remembered from the map
+    pub anchors: Vec<String>,
+}
+
+impl UiMap {
+    pub fn new(app: Option<String>) -> Self {
+        Self {
+            app,
+            elapsed_ms: 0,
+            complete: false,
+            frames: Vec::new(),
+            elements: Vec::new(),
+            vocabulary: Vec::new(),
+            anchors: Vec::new(),
+        }
+    }
+
+    /// Add `frame` and the elements of its `analysis` not mapped yet;
+    /// returns their indices in `elements`. Unnamed elements are only taken
+    /// from the first frame, since nothing tells them apart once scrolled.
+    pub fn record(&mut self, analysis: &ScreenAnalysis, mut frame: MappedFrame) -> Vec<usize> {
+        let frame_index = self.frames.len();
+        let mut found = Vec::new();
+        for index in 0..analysis.elements.len() {
+            let Some(role) = role(analysis, index) else {
+                continue;
+            };
+            let element = &analysis.elements[index];
+            let named = name(element);
+            match &named {
+                Some((name, _)) if self.position(&element.element_type, name).is_some() => continue,
+                None if frame_index > 0 => continue,
+                _ => {}
+            }
+            found.push(self.elements.len());
+            self.elements.push(MappedElement {
+                element_type: element.element_type.clone(),
+                role,
+                named_by: named.as_ref().map(|(_, source)| *source),
+                name: named.map(|(name, _)| name),
+                bounds: (element.bounds.x, element.bounds.y, element.bounds.width, element.bounds.height),
+                query: query_for(element),
+                frame: frame_index,
+                index,
+            });
+        }
+        frame.found = found.len();
+        self.frames.push(frame);
+        found
+    }
+
+    /// Unnamed elements of the first frame, which may have tooltips
+    pub fn unnamed(&self) -> Vec<usize> {
+        (0..self.elements.len())
+            .filter(|&i| self.elements[i].frame == 0 && self.elements[i].name.is_none())
+            .collect()
+    }
+
+    /// Name unnamed `element` after the tooltip it showed
+    pub fn name_by_tooltip(&mut self, element: usize, tooltip: &str) {
+        if let Some(mapped) = self.elements.get_mut(element).filter(|e| e.name.is_none()) {
+            mapped.name = Some(tooltip.to_string());
+            mapped.named_by = Some(NameSource::Tooltip);
+        }
+    }
+
+    /// Save `image`, the last recorded frame, into `dir` with the `found`
+    /// elements outlined in their role's color
+    pub fn save_frame(&mut self, dir: &Path, image: &Image, found: &[usize]) -> Result<()> {
+        let Some(index) = self.frames.len().checked_sub(1) else {
+            return Ok(());
+        };
+        let mut annotated = to_rgb(image);
+        for mapped in found.iter().filter_map(|&i| self.elements.get(i)) {
+            let (x, y, width, height) = mapped.bounds;
+            let color = match mapped.role {
+                UiRole::Menu => MENU_COLOR,
+                UiRole::Toolbar => TOOLBAR_COLOR,
+                UiRole::Control => CONTROL_COLOR,
+            };
+            outline(&mut annotated, &ElementBounds::new(x, y, width, height), color, 2);
+        }
+        let path = dir.join(format!("frame-{:02}.png", index));
+        std::fs::write(&path, super::encode_png(&annotated)?)?;
+        self.frames[index].image = Some(path);
+        Ok(())
+    }
+
+    /// Close the map after `elapsed`, collecting its vocabulary
+    pub fn finish(&mut self, elapsed: Duration, complete: bool) {
+        self.elapsed_ms = elapsed.as_millis() as u64;
+        self.complete = complete;
+        let mut vocabulary: Vec<String> = Vec::new();
+        for name in self.elements.iter().filter_map(|e| e.name.as_deref()) {
+            if !vocabulary.iter().any(|known| fold(known) == fold(name)) {
+                vocabulary.push(name.to_string());
+            }
+        }
+        vocabulary.sort_by_key(|name| fold(name));
+        self.vocabulary = vocabulary;
+    }
+
+    fn position(&self, element_type: &str, name: &str) -> Option<usize> {
+        let name = fold(name);
+        self.elements
+            .iter()
+            .position(|e| e.element_type == element_type && e.name.as_deref().is_some_and(|n| fold(n) == name))
+    }
+}
+
+/// What part of the UI element `index` is; `None` for what isn't worth
+/// mapping (windows, panels, plain text and unnamed menu parts)
+pub fn role(analysis: &ScreenAnalysis, index: usize) -> Option<UiRole> {
+    let element = analysis.elements.get(index)?;
+    if in_menu(analysis, index) {
+        return name(element).map(|_| UiRole::Menu);
+    }
+    if in_toolbar(analysis, index) {
+        return Some(UiRole::Toolbar);
+    }
+    CONTROL_TYPES.contains(&element.element_type.as_str()).then_some(UiRole::Control)
+}
+
+/// New text near `target` in the `hovered` analysis that wasn't in `before`
+pub fn tooltip(before: &ScreenAnalysis, hovered: &ScreenAnalysis, target: &ElementBounds) -> Option<String> {
+    let target = Rectangle::from(target);
+    let reach = target.expand(TOOLTIP_REACH);
+    let text = |e: &ScreenElement| e.text.as_deref().map(str::trim).filter(|t| !t.is_empty()).map(str::to_string);
+    hovered
+        .elements
+        .iter()
+        .filter_map(|e| Some((Rectangle::from(&e.bounds), text(e)?)))
+        .filter(|(bounds, _)| bounds.intersects(&reach) && bounds.iou(&target) < SAME_PLACE_IOU)
+        .filter(|(bounds, tip)| {
+            !before
+                .elements
+                .iter()
+                .any(|old| text(old).as_ref() == Some(tip) && Rectangle::from(&old.bounds).iou(bounds) > SAME_PLACE_IOU)
+        })
+        .min_by(|a, b| a.0.center().distance_to(&target.center()).total_cmp(&b.0.center().distance_to(&target.center())))
+        .map(|(_, tip)| tip)
+}
+
+/// Areas with content cut off at the bottom, which scrolling down reveals
+pub fn scroll_areas(analysis: &ScreenAnalysis) -> Vec<Rectangle> {
+    let mut areas: Vec<Rectangle> = Vec::new();
+    for (index, element) in analysis.elements.iter().enumerate() {
+        if element.visibility() >= 1.0 {
+            continue;
+        }
+        let Some(clip) = analysis.clip_area(index) else {
+            continue;
+        };
+        let cut_below = f64::from(element.bounds.y + element.bounds.height) > clip.y + clip.height;
+        if cut_below && !areas.iter().any(|area| area.iou(&clip) > 0.9) {
+            areas.push(clip);
+        }
+    }
+    areas
+}
+
+fn name(element: &ScreenElement) -> Option<(String, NameSource)> {
+    match element.text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        Some(text) => Some((text.to_string(), NameSource::Text)),
+        None => element.label().map(|label| (label.to_string(), NameSource::Label)),
+    }
+}
+
+fn in_menu(analysis: &ScreenAnalysis, index: usize) -> bool {
+    let mut current = Some(index);
+    while let Some(i) = current {
+        if analysis.elements[i].element_type == "menu" {
+            return true;
+        }
+        current = analysis.elements[i].parent;
+    }
+    false
+}
+
+/// Whether element `index` is a button or icon in a row of them
+fn in_toolbar(analysis: &ScreenAnalysis, index: usize) -> bool {
+    let element = &analysis.elements[index];
+    if !TOOL_TYPES.contains(&element.element_type.as_str()) {
+        return false;
+    }
+    let middle = |e: &ScreenElement| e.bounds.y * 2 + e.bounds.height;
+    let row = analysis
+        .elements
+        .iter()
+        .filter(|e| e.parent == element.parent && TOOL_TYPES.contains(&e.element_type.as_str()))
+        .filter(|e| (middle(e) - middle(element)).abs() <= element.bounds.height)
+        .count();
+    row >= MIN_TOOLBAR_ITEMS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(element_type: &str, text: Option<&str>, bounds: ElementBounds) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds,
+            shape: None,
+            confidence: 0.9,
+            text: text.map(String::from),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn analysis(elements: Vec<ScreenElement>) -> ScreenAnalysis {
+        let mut analysis =
+            ScreenAnalysis { elements, confidence: 0.9, processing_time_ms: 0, screen_size: (800, 600), context: Default::default() };
+        analysis.link_hierarchy();
+        analysis.mark_visibility();
+        analysis
+    }
+
+    /// A window with a menu bar, an icon toolbar and a list of four items,
+    /// of which the last two hang out of the list's bottom edge
+    fn app(items: [&str; 4], first_item_y: i32) -> ScreenAnalysis {
+        let mut elements = vec![
+            element("window", Some("Editor"), ElementBounds::new(0, 0, 600, 400)),
+            element("menu", None, ElementBounds::new(0, 30, 600, 24)),
+            element("text", Some("File"), ElementBounds::new(10, 34, 40, 16)),
+            element("text", Some("Edit"), ElementBounds::new(60, 34, 40, 16)),
+            element("icon", None, ElementBounds::new(10, 60, 24, 24)),
+            element("icon", None, ElementBounds::new(40, 60, 24, 24)),
+            element("icon", None, ElementBounds::new(70, 60, 24, 24)),
+            element("list", None, ElementBounds::new(10, 100, 200, 100)),
+        ];
+        for (row, item) in items.iter().enumerate() {
+            elements.push(element("listitem", Some(item), ElementBounds::new(12, first_item_y + row as i32 * 40, 196, 30)));
+        }
+        analysis(elements)
+    }
+
+    #[test]
+    fn test_map_names_roles_and_only_adds_new_elements_when_scrolled() {
+        let first = app(["Alpha", "Beta", "Gamma", "Delta"], 104);
+        let mut map = UiMap::new(Some("Editor".to_string()));
+        let found = map.record(&first, MappedFrame::default());
+        let roles: Vec<(Option<&str>, UiRole)> = found.iter().map(|&i| (map.elements[i].name.as_deref(), map.elements[i].role)).collect();
+        assert_eq!(&roles[..5], &[
+            (Some("File"), UiRole::Menu),
+            (Some("Edit"), UiRole::Menu),
+            (None, UiRole::Toolbar),
+            (None, UiRole::Toolbar),
+            (None, UiRole::Toolbar),
+        ]);
+        assert_eq!(roles.len(), 9, "and the four list items as controls");
+        assert_eq!(map.unnamed(), vec![2, 3, 4]);
+
+        let areas = scroll_areas(&first);
+        assert_eq!(areas.len(), 1);
+        assert_eq!((areas[0].y, areas[0].height), (100.0, 100.0), "the list, not the window");
+
+        // Scrolled by two rows: Gamma and Delta are seen again, Epsilon and Zeta are new
+        let scrolled = app(["Gamma", "Delta", "Epsilon", "Zeta"], 104);
+        let found = map.record(&scrolled, MappedFrame { scrolled: 3, ..MappedFrame::default() });
+        let names: Vec<_> = found.iter().map(|&i| map.elements[i].name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["Epsilon", "Zeta"]);
+        assert_eq!(map.frames[1].found, 2);
+        assert!(found.iter().all(|&i| map.elements[i].frame == 1));
+
+        map.name_by_tooltip(2, "Bold");
+        map.finish(Duration::from_secs(2), true);
+        assert_eq!(map.elements[2].named_by, Some(NameSource::Tooltip));
+        assert_eq!(map.vocabulary, ["Alpha", "Beta", "Bold", "Delta", "Edit", "Epsilon", "File", "Gamma", "Zeta"]);
+    }
+
+    #[test]
+    fn test_tooltip_is_new_text_next_to_the_hovered_element() {
+        let before = app(["Alpha", "Beta", "Gamma", "Delta"], 104);
+        let icon = before.elements[4].bounds.clone();
+        let mut elements = before.elements.clone();
+        // A status line far away changes too, but isn't near the icon
+        elements.push(element("text", Some("Ready"), ElementBounds::new(400, 380, 60, 16)));
+        elements.push(element("label", Some("Bold (Ctrl+B)"), ElementBounds::new(20, 88, 90, 18)));
+        let hovered = analysis(elements);
+
+        assert_eq!(tooltip(&before, &hovered, &icon).as_deref(), Some("Bold (Ctrl+B)"));
+        assert_eq!(tooltip(&before, &before, &icon), None, "nothing new appeared");
+    }
+}
@@ -0,0 +1,358 @@
+/*!
+ * Luna Focus - Do-not-disturb and presentation awareness
+ *
+ * Detects when the user is presenting or has silenced notifications so
+ * automation that would pop windows or move the cursor can be deferred or
+ * refused, depending on where the command came from.
+ */
+
+use log::debug;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// What the user is currently doing that automation could disrupt
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FocusState {
+    /// Presentation or streaming app found running, if any
+    pub presentation_app: Option<String>,
+    /// OS do-not-disturb / focus assist is on
+    pub do_not_disturb: bool,
+}
+
+impl FocusState {
+    /// Human-readable reason automation would be disruptive, if any
+    pub fn disruption_reason(&self, respect_do_not_disturb: bool) -> Option<String> {
+        if let Some(app) = &self.presentation_app {
+            return Some(format!("presentation in progress ({})", app));
+        }
+        if respect_do_not_disturb && self.do_not_disturb {
+            return Some("do-not-disturb is on".to_string());
+        }
+        None
+    }
+}
+
+/// Source of the current focus state
+pub trait FocusProbe {
+    fn probe(&self) -> FocusState;
+}
+
+/// Probes the running processes and OS do-not-disturb setting.
+///
+/// A presentation app entry is an executable name (`pdfpc`), optionally
+/// followed by the flag that starts its slideshow (`soffice --show`). An
+/// entry with a flag matches only that executable running with that flag,
+/// and other processes' arguments are never looked at. Windows lists no
+/// arguments, so only plain entries match there. Do-not-disturb is read
+/// from GNOME's notification banners setting on Linux and the Focus
+/// assertions file on macOS; Windows focus assist has no public query API
+/// and reads as off.
+pub struct SystemFocusProbe {
+    presentation_apps: Vec<PresentationApp>,
+}
+
+impl SystemFocusProbe {
+    pub fn new(presentation_apps: &[String]) -> Self {
+        Self {
+            presentation_apps: presentation_apps.iter().filter_map(|a| PresentationApp::parse(a)).collect(),
+        }
+    }
+
+    fn find_presentation_app(&self) -> Option<String> {
+        find_presentation_app(&self.presentation_apps, &running_processes())
+    }
+}
+
+/// One `disruption.presentation_apps` entry
+#[derive(Debug, Clone, PartialEq)]
+struct PresentationApp {
+    name: String,
+    flag: Option<String>,
+}
+
+impl PresentationApp {
+    /// "soffice --show" -> executable "soffice" started with "--show"
+    fn parse(entry: &str) -> Option<Self> {
+        let mut parts = entry.split_whitespace();
+        let name = normalize_process_name(parts.next()?);
+        let flag = parts.next().map(str::to_lowercase);
+        Some(Self { name, flag })
+    }
+
+    fn matches(&self, process: &RunningProcess) -> bool {
+        process.name == self.name
+            && self
+                .flag
+                .as_ref()
+                .is_none_or(|flag| process.args.iter().any(|arg| arg.to_lowercase() == *flag))
+    }
+}
+
+/// Name of the first running process that is a presentation app
+fn find_presentation_app(apps: &[PresentationApp], processes: &[RunningProcess]) -> Option<String> {
+    processes
+        .iter()
+        .find(|process| apps.iter().any(|app| app.matches(process)))
+        .map(|process| process.name.clone())
+}
+
+impl FocusProbe for SystemFocusProbe {
+    fn probe(&self) -> FocusState {
+        FocusState {
+            presentation_app: self.find_presentation_app(),
+            do_not_disturb: do_not_disturb_enabled(),
+        }
+    }
+}
+
+/// Caches probe results so every command doesn't rescan the process list
+pub struct FocusMonitor {
+    probe: Box<dyn FocusProbe + Send>,
+    interval: Duration,
+    cached: Option<(Instant, FocusState)>,
+}
+
+impl FocusMonitor {
+    pub fn new(probe: Box<dyn FocusProbe + Send>, interval: Duration) -> Self {
+        Self { probe, interval, cached: None }
+    }
+
+    /// Current focus state, probing again once the cached value is older than the interval
+    pub fn state(&mut self) -> FocusState {
+        if let Some((at, state)) = &self.cached {
+            if at.elapsed() < self.interval {
+                return state.clone();
+            }
+        }
+        let state = self.probe.probe();
+        debug!("Focus state: {:?}", state);
+        self.cached = Some((Instant::now(), state.clone()));
+        state
+    }
+}
+
+/// Title of the focused window, used as app context (GetForegroundWindow)
+#[cfg(target_os = "windows")]
+pub fn active_window_title() -> Option<String> {
+    crate::utils::win32::foreground_window_title()
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// Title of the focused window, used as app context (`xdotool` on X11)
+#[cfg(not(target_os = "windows"))]
+pub fn active_window_title() -> Option<String> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    command_output("xdotool", &["getactivewindow", "getwindowname"])
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// Lower-cased file stem: "C:\\Program Files\\POWERPNT.EXE" -> "powerpnt"
+fn normalize_process_name(name: &str) -> String {
+    let name = name.trim();
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let base = base.to_lowercase();
+    // LibreOffice runs as soffice.bin behind its launcher script
+    [".exe", ".bin"]
+        .iter()
+        .find_map(|suffix| base.strip_suffix(suffix))
+        .unwrap_or(&base)
+        .to_string()
+}
+
+/// A running process: normalized executable name and its arguments
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RunningProcess {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// Everything running. Windows' `tasklist` gives names without arguments.
+pub(crate) fn running_processes() -> Vec<RunningProcess> {
+    let mut processes = Vec::new();
+
+    if cfg!(target_os = "linux") {
+        if let Ok(entries) = std::fs::read_dir("/proc") {
+            for entry in entries.flatten() {
+                let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else {
+                    continue;
+                };
+                let mut argv = cmdline
+                    .split(|b| *b == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned());
+                // comm names the script rather than its interpreter
+                let comm = std::fs::read_to_string(entry.path().join("comm")).ok();
+                let argv0 = argv.next();
+                let Some(name) = comm.or(argv0) else {
+                    continue;
+                };
+                let args = argv.collect();
+                processes.push(RunningProcess { name: normalize_process_name(&name), args });
+            }
+        }
+    } else if cfg!(target_os = "windows") {
+        if let Some(output) = command_output("tasklist", &["/fo", "csv", "/nh"]) {
+            processes.extend(
+                output
+                    .lines()
+                    .filter_map(|line| line.split(',').next())
+                    .map(|name| RunningProcess {
+                        name: normalize_process_name(name.trim_matches('"')),
+                        args: Vec::new(),
+                    }),
+            );
+        }
+    } else if let Some(output) = command_output("ps", &["-axo", "args="]) {
+        processes.extend(output.lines().filter_map(|line| {
+            let mut argv = line.split_whitespace();
+            let name = normalize_process_name(argv.next()?);
+            Some(RunningProcess { name, args: argv.map(str::to_string).collect() })
+        }));
+    }
+
+    processes
+}
+
+fn do_not_disturb_enabled() -> bool {
+    if cfg!(target_os = "linux") {
+        // GNOME hides banners while do-not-disturb is on
+        command_output("gsettings", &["get", "org.gnome.desktop.notifications", "show-banners"])
+            .is_some_and(|value| value.trim() == "false")
+    } else if cfg!(target_os = "macos") {
+        dirs::home_dir()
+            .map(|home| home.join("Library/DoNotDisturb/DB/Assertions.json"))
+            .is_some_and(|path| macos_focus_active(&path))
+    } else {
+        false
+    }
+}
+
+/// A Focus mode is active when the assertions file holds any records
+fn macos_focus_active(path: &Path) -> bool {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return false;
+    };
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(&contents) else {
+        return false;
+    };
+    json["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|entry| entry["storeAssertionRecords"].as_array().is_some_and(|records| !records.is_empty()))
+}
+
+pub(crate) fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct CountingProbe(Arc<AtomicUsize>);
+
+    impl FocusProbe for CountingProbe {
+        fn probe(&self) -> FocusState {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            FocusState { presentation_app: None, do_not_disturb: true }
+        }
+    }
+
+    #[test]
+    fn test_normalize_process_name() {
+        assert_eq!(normalize_process_name("C:\\Program Files\\Office\\POWERPNT.EXE"), "powerpnt");
+        assert_eq!(normalize_process_name("/usr/bin/obs"), "obs");
+        assert_eq!(normalize_process_name("/usr/lib/libreoffice/program/soffice.bin"), "soffice");
+    }
+
+    fn process(name: &str, args: &[&str]) -> RunningProcess {
+        RunningProcess { name: name.to_string(), args: args.iter().map(|a| a.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_presentation_apps_match_executable_and_flag() {
+        let apps: Vec<_> = ["soffice --show", "obs"].iter().filter_map(|a| PresentationApp::parse(a)).collect();
+
+        // Arguments of unrelated processes don't count
+        let unrelated = [process("vim", &["obs", "--show"]), process("grep", &["-r", "soffice"])];
+        assert_eq!(find_presentation_app(&apps, &unrelated), None);
+        // Impress open for editing isn't a slideshow
+        assert_eq!(find_presentation_app(&apps, &[process("soffice", &["--impress", "talk.odp"])]), None);
+
+        assert_eq!(
+            find_presentation_app(&apps, &[process("soffice", &["--impress", "--show", "talk.odp"])]),
+            Some("soffice".to_string())
+        );
+        assert_eq!(find_presentation_app(&apps, &[process("obs", &[])]), Some("obs".to_string()));
+    }
+
+    #[test]
+    fn test_disruption_reason() {
+        let presenting = FocusState { presentation_app: Some("keynote".to_string()), do_not_disturb: false };
+        assert!(presenting.disruption_reason(true).unwrap().contains("keynote"));
+
+        let quiet = FocusState { presentation_app: None, do_not_disturb: true };
+        assert!(quiet.disruption_reason(true).is_some());
+        assert!(quiet.disruption_reason(false).is_none());
+        assert!(FocusState::default().disruption_reason(true).is_none());
+    }
+
+    #[test]
+    fn test_monitor_caches_within_interval() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut monitor = FocusMonitor::new(Box::new(CountingProbe(calls.clone())), Duration::from_secs(60));
+        assert!(monitor.state().do_not_disturb);
+        assert!(monitor.state().do_not_disturb);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut uncached = FocusMonitor::new(Box::new(CountingProbe(calls.clone())), Duration::ZERO);
+        uncached.state();
+        uncached.state();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_macos_assertions_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("Assertions.json");
+        std::fs::write(&path, r#"{"data":[{"storeAssertionRecords":[{"assertionDetails":{}}]}]}"#).unwrap();
+        assert!(macos_focus_active(&path));
+        std::fs::write(&path, r#"{"data":[{"storeAssertionRecords":[]}]}"#).unwrap();
+        assert!(!macos_focus_active(&path));
+        assert!(!macos_focus_active(&temp.path().join("missing.json")));
+    }
+
+    struct Presenting;
+
+    impl FocusProbe for Presenting {
+        fn probe(&self) -> FocusState {
+            FocusState { presentation_app: Some("pdfpc".to_string()), do_not_disturb: false }
+        }
+    }
+
+    #[test]
+    fn test_scheduled_commands_deferred_while_presenting() {
+        use crate::core::{CommandSource, ExecuteOptions, Luna, LunaConfig, LunaError};
+
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        luna.set_focus_probe(Box::new(Presenting));
+
+        let scheduled = ExecuteOptions { source: CommandSource::Scheduled, ..ExecuteOptions::default() };
+        let err = luna.execute_command("press enter", &scheduled).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::Deferred(_))));
+
+        let watcher = ExecuteOptions { source: CommandSource::Watcher, ..ExecuteOptions::default() };
+        let err = luna.execute_command("press enter", &watcher).unwrap_err();
+        assert!(err.to_string().contains("[disruption]"));
+
+        assert!(luna.execute_command("press enter", &ExecuteOptions::default()).is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+/*!
+ * Luna Frames - Latest-frame-wins scheduling for continuous analysis
+ *
+ * Capture runs on its own thread and hands frames to the analyzer through a
+ * single slot. A frame that arrives while the previous one is still waiting
+ * replaces it and is counted as dropped, so at most one analysis is ever
+ * pending and memory stays flat however slow the model is. The metrics tell
+ * "slow model" (drops, with analysis time above the capture interval) apart
+ * from anything else.
+ */
+
+use serde::Serialize;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in the moving averages
+const SMOOTHING: f64 = 0.2;
+
+/// Counters for one continuous-analysis run
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FrameMetrics {
+    pub captured: u64,
+    pub analyzed: u64,
+    /// Frames replaced by a newer one before analysis picked them up
+    pub dropped: u64,
+    /// Moving average of the time between captures
+    pub capture_interval_ms: f64,
+    /// Moving average of the time one analysis takes
+    pub analysis_ms: f64,
+    /// Age of the most recent frame when analysis started
+    pub last_frame_age_ms: u64,
+}
+
+/// Why frames are (or aren't) being dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameDiagnosis {
+    /// Not enough frames yet to say
+    Warmup,
+    /// Every captured frame is analyzed
+    KeepingUp,
+    /// Analysis takes longer than the capture interval; only the latest frame is analyzed
+    SlowAnalysis,
+}
+
+impl FrameMetrics {
+    /// Fraction of captured frames that were dropped
+    pub fn drop_rate(&self) -> f64 {
+        if self.captured == 0 { 0.0 } else { self.dropped as f64 / self.captured as f64 }
+    }
+
+    pub fn diagnosis(&self) -> FrameDiagnosis {
+        if self.analyzed < 2 {
+            FrameDiagnosis::Warmup
+        } else if self.dropped > 0 && self.analysis_ms > self.capture_interval_ms {
+            FrameDiagnosis::SlowAnalysis
+        } else {
+            FrameDiagnosis::KeepingUp
+        }
+    }
+}
+
+struct SlotState<T> {
+    frame: Option<(T, Instant)>,
+    last_offer: Option<Instant>,
+    closed: bool,
+    metrics: FrameMetrics,
+}
+
+/// Single-frame mailbox between a capture thread and the analyzer
+pub struct FrameSlot<T> {
+    state: Mutex<SlotState<T>>,
+    ready: Condvar,
+}
+
+impl<T> FrameSlot<T> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(SlotState { frame: None, last_offer: None, closed: false, metrics: FrameMetrics::default() }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Hand over a new frame, replacing (and counting as dropped) any frame
+    /// still waiting. Returns false once the slot is closed.
+    pub fn offer(&self, frame: T) -> bool {
+        let mut state = self.lock();
+        if state.closed {
+            return false;
+        }
+        let now = Instant::now();
+        if let Some(previous) = state.last_offer.replace(now) {
+            let interval = now.duration_since(previous).as_secs_f64() * 1000.0;
+            state.metrics.capture_interval_ms = smooth(state.metrics.capture_interval_ms, interval, state.metrics.captured);
+        }
+        state.metrics.captured += 1;
+        if state.frame.replace((frame, now)).is_some() {
+            state.metrics.dropped += 1;
+        }
+        self.ready.notify_one();
+        true
+    }
+
+    /// Latest frame, waiting up to `timeout` for one. `None` on timeout or
+    /// when the slot is closed and empty.
+    pub fn take(&self, timeout: Duration) -> Option<T> {
+        let state = self.lock();
+        let (mut state, _) = self
+            .ready
+            .wait_timeout_while(state, timeout, |state| state.frame.is_none() && !state.closed)
+            .unwrap_or_else(|e| e.into_inner());
+        let (frame, offered_at) = state.frame.take()?;
+        state.metrics.last_frame_age_ms = offered_at.elapsed().as_millis() as u64;
+        Some(frame)
+    }
+
+    /// Record how long analysing the last taken frame took
+    pub fn record_analysis(&self, duration: Duration) {
+        let mut state = self.lock();
+        let metrics = &mut state.metrics;
+        metrics.analysis_ms = smooth(metrics.analysis_ms, duration.as_secs_f64() * 1000.0, metrics.analyzed);
+        metrics.analyzed += 1;
+    }
+
+    /// Stop accepting frames and wake any waiting `take`
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.ready.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    pub fn metrics(&self) -> FrameMetrics {
+        self.lock().metrics.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SlotState<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Default for FrameSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exponential moving average; the first sample is taken as is
+fn smooth(average: f64, sample: f64, samples_so_far: u64) -> f64 {
+    if samples_so_far == 0 { sample } else { average + SMOOTHING * (sample - average) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_latest_frame_wins_and_drops_are_counted() {
+        let slot = FrameSlot::new();
+        for frame in 1..=3 {
+            assert!(slot.offer(frame));
+        }
+        assert_eq!(slot.take(Duration::ZERO), Some(3));
+        assert_eq!(slot.take(Duration::ZERO), None);
+
+        let metrics = slot.metrics();
+        assert_eq!((metrics.captured, metrics.dropped), (3, 2));
+        assert!((metrics.drop_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        slot.close();
+        assert!(!slot.offer(4));
+        assert_eq!(slot.take(Duration::from_secs(5)), None, "closed slot must not block");
+    }
+
+    #[test]
+    fn test_slow_analysis_is_diagnosed() {
+        let slot = Arc::new(FrameSlot::new());
+        let producer = {
+            let slot = Arc::clone(&slot);
+            std::thread::spawn(move || {
+                let mut frame = 0;
+                while slot.offer(frame) {
+                    frame += 1;
+                    std::thread::sleep(Duration::from_millis(2));
+                }
+            })
+        };
+
+        for _ in 0..4 {
+            assert!(slot.take(Duration::from_secs(5)).is_some());
+            std::thread::sleep(Duration::from_millis(20));
+            slot.record_analysis(Duration::from_millis(20));
+        }
+        slot.close();
+        producer.join().unwrap();
+
+        let metrics = slot.metrics();
+        assert_eq!(metrics.analyzed, 4);
+        assert!(metrics.dropped > 0);
+        assert_eq!(metrics.diagnosis(), FrameDiagnosis::SlowAnalysis);
+    }
+}
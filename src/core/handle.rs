@@ -0,0 +1,609 @@
+/*!
+ * Luna Handle - Cloneable, thread-safe facade over a Luna instance
+ *
+ * A dedicated worker thread owns the `Luna` coordinator and processes
+ * requests one at a time. Handles only hold a channel to that worker, so
+ * they are `Send + Sync` and can be shared with web servers or thread pools.
+ * Every request returns a `Pending` reply, which is a plain `Future` (usable
+ * from any async runtime) that can also be waited on synchronously.
+ */
+
+use anyhow::Result;
+use log::{debug, info};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::capabilities::Capabilities;
+use super::debugger::StepControl;
+use super::instance::InputOwnership;
+use super::metrics::MetricsCollector;
+use super::config::{ConfigReport, PartialVisionConfig, SPEED_MULTIPLIER_RANGE};
+use super::review::{ReviewItem, ReviewOutcome, Verdict};
+use super::spy::SpyReport;
+use super::scratch::ReclaimedSpace;
+use super::storage::StoreStatus;
+use crate::ai::clarification::Clarification;
+use crate::ai::fingerprint::ElementFingerprint;
+use crate::ai::{ConfidenceThresholds, ReconfigureReport};
+use super::{CommandResult, ExecuteOptions, Luna, LunaConfig, LunaError, LunaEvent, ProcessingStats, ScreenAnalysis, ScreenElement};
+
+/// Shared flag used to cancel a queued or running request
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Shared speed multiplier, read before every action so a change applies to
+/// a command that is already running
+#[derive(Debug, Clone)]
+pub struct SpeedControl(Arc<AtomicU64>);
+
+impl SpeedControl {
+    pub fn new(multiplier: f64) -> Self {
+        Self(Arc::new(AtomicU64::new(multiplier.to_bits())))
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::SeqCst))
+    }
+
+    /// Change the multiplier; 2.0 runs twice as fast as normal
+    pub fn set(&self, multiplier: f64) -> Result<()> {
+        if !SPEED_MULTIPLIER_RANGE.contains(&multiplier) {
+            return Err(LunaError::InvalidArgument(
+                format!("speed multiplier {} is outside {:?}", multiplier, SPEED_MULTIPLIER_RANGE)).into());
+        }
+        info!("Speed multiplier set to {}", multiplier);
+        self.0.store(multiplier.to_bits(), Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Luna) + Send>;
+
+enum Message {
+    Run(Job),
+    Shutdown,
+}
+
+/// Cloneable handle to a Luna instance running on its own worker thread
+#[derive(Clone)]
+pub struct LunaHandle {
+    sender: mpsc::Sender<Message>,
+    worker: Arc<Mutex<Option<JoinHandle<()>>>>,
+    speed: SpeedControl,
+    debugger: StepControl,
+    metrics: MetricsCollector,
+    /// Requests queued or running
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Counts a request as in flight until its job has run or been dropped
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl LunaHandle {
+    /// Start a worker thread and build the Luna instance on it
+    pub fn spawn(config: LunaConfig) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(SpeedControl, StepControl, MetricsCollector)>>();
+
+        let worker = std::thread::Builder::new()
+            .name("luna-worker".to_string())
+            .spawn(move || {
+                let mut luna = match Luna::new(config) {
+                    Ok(luna) => {
+                        let _ = ready_tx.send(Ok((luna.speed_control(), luna.step_control(), luna.metrics())));
+                        luna
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                run_worker(&mut luna, receiver);
+            })?;
+
+        let (speed, debugger, metrics) = match ready_rx.recv() {
+            Ok(Ok(ready)) => ready,
+            Ok(Err(e)) => {
+                let _ = worker.join();
+                return Err(e);
+            }
+            Err(_) => return Err(LunaError::System("Luna worker exited during startup".to_string()).into()),
+        };
+
+        Ok(Self {
+            sender,
+            worker: Arc::new(Mutex::new(Some(worker))),
+            speed,
+            debugger,
+            metrics,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Run a closure against the Luna instance on the worker thread.
+    ///
+    /// The closure is skipped if the request is cancelled before it starts.
+    pub fn call<T, F>(&self, f: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Luna, &CancelToken) -> Result<T> + Send + 'static,
+    {
+        let (pending, slot) = Pending::new();
+        let token = pending.token.clone();
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let in_flight = InFlight(self.in_flight.clone());
+
+        let job: Job = Box::new(move |luna| {
+            let _in_flight = in_flight;
+            let result = if token.is_cancelled() {
+                Err(LunaError::Cancelled("request cancelled before it started".to_string()).into())
+            } else {
+                f(luna, &token)
+            };
+            slot.complete(result);
+        });
+
+        // If the worker is gone the job is dropped here, which fails the reply
+        let _ = self.sender.send(Message::Run(job));
+        pending
+    }
+
+    /// Process a command; cancelling the reply stops execution between actions
+    pub fn execute_command(&self, command: &str, options: ExecuteOptions) -> Pending<CommandResult> {
+        let command = command.to_string();
+        self.call(move |luna, token| {
+            let options = ExecuteOptions { cancel: Some(token.clone()), ..options };
+            luna.execute_command(&command, &options)
+        })
+    }
+
+    /// Answer the question a command was failed with (`LunaError::NeedsClarification`)
+    pub fn answer_clarification(&self, answer: &str, options: ExecuteOptions) -> Pending<CommandResult> {
+        let answer = answer.to_string();
+        self.call(move |luna, token| {
+            let options = ExecuteOptions { cancel: Some(token.clone()), ..options };
+            luna.answer_clarification(&answer, &options)
+        })
+    }
+
+    pub fn pending_clarification(&self) -> Pending<Option<Clarification>> {
+        self.call(|luna, _| Ok(luna.pending_clarification().cloned()))
+    }
+
+    pub fn analyze_current_screen(&self) -> Pending<ScreenAnalysis> {
+        self.call(|luna, _| luna.analyze_current_screen())
+    }
+
+    /// Call `callback` with every event from now on. It runs on the worker
+    /// thread, so it should hand events off rather than block.
+    pub fn subscribe_to_events<F>(&self, callback: F) -> Pending<()>
+    where
+        F: Fn(LunaEvent) + Send + Sync + 'static,
+    {
+        self.call(move |luna, _| {
+            luna.subscribe_to_events(callback);
+            Ok(())
+        })
+    }
+
+    pub fn get_stats(&self) -> Pending<ProcessingStats> {
+        self.call(|luna, _| Ok(luna.get_stats()))
+    }
+
+    pub fn get_config(&self) -> Pending<LunaConfig> {
+        self.call(|luna, _| Ok(luna.get_config().clone()))
+    }
+
+    pub fn update_config(&self, config: LunaConfig) -> Pending<ConfigReport> {
+        self.call(move |luna, _| luna.update_config(config))
+    }
+
+    pub fn reconfigure_vision(&self, changes: PartialVisionConfig) -> Pending<ReconfigureReport> {
+        self.call(move |luna, _| luna.reconfigure_vision(&changes))
+    }
+
+    /// Change the speed multiplier immediately, even mid-command
+    pub fn set_speed(&self, multiplier: f64) -> Result<()> {
+        self.speed.set(multiplier)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.get()
+    }
+
+    /// Pause, step and breakpoints for running commands; works while the
+    /// worker is busy, including while it is paused
+    pub fn step_control(&self) -> &StepControl {
+        &self.debugger
+    }
+
+    /// The instance's metrics, readable without waiting for the worker
+    pub fn metrics(&self) -> &MetricsCollector {
+        &self.metrics
+    }
+
+    /// Change confidence thresholds and re-filter the last analysis
+    pub fn set_runtime_thresholds(&self, thresholds: ConfidenceThresholds) -> Pending<Option<ScreenAnalysis>> {
+        self.call(move |luna, _| luna.set_runtime_thresholds(thresholds))
+    }
+
+    /// Report the element under `cursor` (see `Luna::spy_at`)
+    pub fn spy_at(&self, cursor: (i32, i32)) -> Pending<SpyReport> {
+        self.call(move |luna, _| luna.spy_at(cursor))
+    }
+
+    pub fn find_again(&self, fingerprint: ElementFingerprint) -> Pending<Option<ScreenElement>> {
+        self.call(move |luna, _| luna.find_again(&fingerprint))
+    }
+
+    pub fn capabilities(&self) -> Pending<Capabilities> {
+        self.call(|luna, _| Ok(luna.capabilities().clone()))
+    }
+
+    pub fn input_ownership(&self) -> Pending<InputOwnership> {
+        self.call(|luna, _| Ok(luna.input_ownership()))
+    }
+
+    pub fn request_input_ownership(&self) -> Pending<InputOwnership> {
+        self.call(|luna, _| luna.request_input_ownership())
+    }
+
+    pub fn release_input_ownership(&self) -> Pending<()> {
+        self.call(|luna, _| luna.release_input_ownership())
+    }
+
+    pub fn storage_status(&self) -> Pending<Vec<StoreStatus>> {
+        self.call(|luna, _| luna.storage_status())
+    }
+
+    pub fn reclaimed_space(&self) -> Pending<ReclaimedSpace> {
+        self.call(|luna, _| Ok(luna.reclaimed_space()))
+    }
+
+    /// Detections waiting for review, oldest first
+    pub fn review_queue(&self) -> Pending<Vec<ReviewItem>> {
+        self.call(|luna, _| Ok(luna.review_queue().to_vec()))
+    }
+
+    pub fn resolve_review(&self, id: u64, verdict: Verdict) -> Pending<ReviewOutcome> {
+        self.call(move |luna, _| luna.resolve_review(id, verdict))
+    }
+
+    pub fn learned_app_thresholds(&self) -> Pending<Vec<(String, f32)>> {
+        self.call(|luna, _| Ok(luna.learned_app_thresholds()))
+    }
+
+    /// Finish queued requests, then stop the worker thread.
+    ///
+    /// Requests made through any clone after shutdown fail immediately.
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(Message::Shutdown);
+        let worker = self
+            .worker
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        if let Some(worker) = worker {
+            let _ = worker.join();
+        }
+    }
+
+    /// Requests waiting for the worker, plus the one it is running
+    pub fn queue_depth(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.worker
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|worker| !worker.is_finished())
+    }
+}
+
+fn run_worker(luna: &mut Luna, receiver: mpsc::Receiver<Message>) {
+    info!("Luna worker started");
+    loop {
+        // With pre-analysis on, an idle worker analyzes the screen for the next command
+        let pre_analysis = &luna.get_config().pre_analysis;
+        let message = if pre_analysis.enabled {
+            match receiver.recv_timeout(Duration::from_millis(pre_analysis.idle_ms)) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Err(e) = luna.pre_analyze() {
+                        debug!("Idle pre-analysis failed: {}", e);
+                    }
+                    continue;
+                }
+                message => message.ok(),
+            }
+        } else {
+            receiver.recv().ok()
+        };
+        let Some(message) = message else {
+            break;
+        };
+        match message {
+            Message::Run(job) => job(luna),
+            Message::Shutdown => break,
+        }
+    }
+    // Anything still queued after shutdown is dropped, which fails its reply
+    debug!("Luna worker stopping");
+}
+
+struct SlotState<T> {
+    result: Option<Result<T>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+/// Worker side of a `Pending` reply
+struct Slot<T> {
+    shared: Arc<(Mutex<SlotState<T>>, Condvar)>,
+}
+
+impl<T> Slot<T> {
+    fn complete(mut self, result: Result<T>) {
+        self.fill(Some(result));
+    }
+
+    fn fill(&mut self, result: Option<Result<T>>) {
+        let (lock, condvar) = &*self.shared;
+        let mut state = lock.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if state.finished {
+            return;
+        }
+        state.result = result;
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        condvar.notify_all();
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    /// A dropped slot means the worker shut down before running the request
+    fn drop(&mut self) {
+        self.fill(None);
+    }
+}
+
+/// Reply to a request made through `LunaHandle`.
+///
+/// Await it, or call `wait` from synchronous code. Dropping it cancels the
+/// request.
+pub struct Pending<T> {
+    shared: Arc<(Mutex<SlotState<T>>, Condvar)>,
+    token: CancelToken,
+}
+
+impl<T> Pending<T> {
+    fn new() -> (Self, Slot<T>) {
+        let shared = Arc::new((
+            Mutex::new(SlotState { result: None, finished: false, waker: None }),
+            Condvar::new(),
+        ));
+        let pending = Self { shared: shared.clone(), token: CancelToken::new() };
+        (pending, Slot { shared })
+    }
+
+    /// Cancel the request; a running command stops before its next action
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// Token that cancels this request, for handing to another thread
+    pub fn cancel_token(&self) -> CancelToken {
+        self.token.clone()
+    }
+
+    /// Block until the worker has answered
+    pub fn wait(self) -> Result<T> {
+        let (lock, condvar) = &*self.shared;
+        let mut state = lock.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        while !state.finished {
+            state = condvar.wait(state).unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+        take_result(&mut state)
+    }
+
+    /// Block until the worker has answered or `timeout` has passed; `None`
+    /// (and the request cancelled) on timeout
+    pub fn wait_timeout(self, timeout: Duration) -> Option<Result<T>> {
+        let (lock, condvar) = &*self.shared;
+        let state = lock.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (mut state, _) = condvar
+            .wait_timeout_while(state, timeout, |state| !state.finished)
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        state.finished.then(|| take_result(&mut state))
+    }
+}
+
+impl<T> Future for Pending<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if state.finished {
+            Poll::Ready(take_result(&mut state))
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Pending<T> {
+    fn drop(&mut self) {
+        let finished = self.shared.0.lock().map(|state| state.finished).unwrap_or(true);
+        if !finished {
+            self.token.cancel();
+        }
+    }
+}
+
+fn take_result<T>(state: &mut SlotState<T>) -> Result<T> {
+    state
+        .result
+        .take()
+        .unwrap_or_else(|| Err(LunaError::System("Luna worker has shut down".to_string()).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    struct ThreadWaker(std::thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor so the tests don't need an async runtime
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn test_handle_is_send_sync() {
+        assert_send_sync::<LunaHandle>();
+        assert_send_sync::<CancelToken>();
+    }
+
+    #[test]
+    fn test_shared_across_threads() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let handle = handle.clone();
+                std::thread::spawn(move || {
+                    let command = format!("click at {},{}", 10 * i, 20);
+                    handle.execute_command(&command, ExecuteOptions::default()).wait().unwrap()
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert!(worker.join().unwrap().pipeline_skipped);
+        }
+
+        let stats = block_on(handle.get_stats()).unwrap();
+        assert_eq!(stats.commands_processed, 4);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_events_reach_subscribers_off_the_worker() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+        let (sender, events) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        handle.subscribe_to_events(move |event| {
+            let _ = sender.lock().unwrap().send(event);
+        }).wait().unwrap();
+
+        handle.execute_command("press tab", ExecuteOptions::default()).wait().unwrap();
+        let executed = events.try_iter().find_map(|event| match event {
+            LunaEvent::ActionExecuted { action, success } => Some((format!("{:?}", action), success)),
+            _ => None,
+        });
+        assert_eq!(executed, Some(("KeyCombo { keys: [\"tab\"] }".to_string(), true)));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_config_update_is_all_or_nothing() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+
+        let mut config = LunaConfig::default();
+        config.vision.edge_threshold = 0.2;
+        config.speed.preset = crate::core::config::SpeedPreset::Fast;
+        let report = handle.update_config(config.clone()).wait().unwrap();
+        assert_eq!(report.applied, vec!["speed.preset", "vision.edge_threshold"]);
+
+        let mut bad = config.clone();
+        bad.vision.min_element_size = 4;
+        bad.safety.threat_threshold = 2.0;
+        let err = handle.update_config(bad).wait().unwrap_err().to_string();
+        assert!(err.contains("safety.threat_threshold"), "{}", err);
+        assert!(!err.contains("min_element_size"), "valid keys are only rolled back: {}", err);
+
+        let current = handle.get_config().wait().unwrap();
+        assert_eq!(current.vision.min_element_size, config.vision.min_element_size);
+        assert_eq!(current.safety.threat_threshold, config.safety.threat_threshold);
+        assert_eq!(handle.speed(), crate::core::config::SpeedPreset::Fast.multiplier());
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_cancelled_request_is_skipped() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+
+        // Occupy the worker so the next request is still queued when cancelled
+        let busy = handle.call(|_, _| {
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(())
+        });
+        let queued = handle.execute_command("press enter", ExecuteOptions::default());
+        queued.cancel();
+
+        busy.wait().unwrap();
+        let err = queued.wait().unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::Cancelled(_))));
+        assert_eq!(handle.get_stats().wait().unwrap().commands_processed, 0);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_shutdown_drains_queue_then_rejects() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+        let queued = handle.execute_command("press tab", ExecuteOptions::default());
+        let other = handle.clone();
+
+        handle.shutdown();
+        assert!(!handle.is_running());
+        assert_eq!(queued.wait().unwrap().actions.len(), 1);
+        assert!(other.get_stats().wait().is_err());
+    }
+}
@@ -0,0 +1,254 @@
+/*!
+ * Luna Health - Liveness and readiness endpoints for orchestration
+ *
+ * A daemon running in a container or VM needs probes its orchestrator can
+ * poll. `HealthServer` answers them over plain HTTP next to a `LunaHandle`:
+ *
+ * - `GET /healthz` (liveness): 200 while the worker thread is running, even
+ *   when it is busy with a long command; 503 once it has died, so the
+ *   process gets restarted.
+ * - `GET /readyz` (readiness): 200 when the worker answers within
+ *   `probe_timeout_ms` and every subsystem check passes (capture works, a
+ *   detector is loaded, input may be injected, the session is unlocked, no
+ *   subsystem is degraded by repeated stalls (see `watchdog`), the queue is
+ *   short); 503 listing the failing checks otherwise, so no new work is
+ *   routed here until they clear.
+ * - `GET /metrics`: Prometheus text exposition of the instance's
+ *   `MetricsCollector` and the queue depth, when `health.metrics` is set.
+ * - `GET /slo`: each latency objective's compliance over its window and
+ *   whether it is violated (see `slo`), as JSON. Like metrics, it never
+ *   waits for the worker.
+ *
+ * Both probes return a JSON `HealthReport`. Each connection is answered on
+ * its own thread, so a client that connects and sends nothing can't hold up
+ * the probes behind it.
+ */
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::config::HealthConfig;
+use super::handle::LunaHandle;
+use crate::ai::remote::{read_message, write_response};
+
+/// One subsystem's state
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl HealthCheck {
+    pub fn new(name: &'static str, ok: bool, detail: impl Into<String>) -> Self {
+        Self { name, ok, detail: detail.into() }
+    }
+}
+
+/// Body of `/healthz` and `/readyz`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    /// "ok" or "unavailable"
+    pub status: &'static str,
+    pub queue_depth: usize,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<HealthCheck>, queue_depth: usize) -> Self {
+        let status = if checks.iter().all(|c| c.ok) { "ok" } else { "unavailable" };
+        Self { status, queue_depth, checks }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Probe server for one Luna instance
+pub struct HealthServer {
+    listener: TcpListener,
+    handle: LunaHandle,
+    config: HealthConfig,
+}
+
+impl HealthServer {
+    pub fn bind(addr: impl ToSocketAddrs, handle: LunaHandle, config: HealthConfig) -> Result<Self> {
+        Ok(Self { listener: TcpListener::bind(addr)?, handle, config })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve probes on a background thread, each connection on a thread of
+    /// its own
+    pub fn spawn(self) -> Result<std::thread::JoinHandle<()>> {
+        info!("Health endpoints listening on {}", self.local_addr()?);
+        let server = Arc::new(self);
+        Ok(std::thread::Builder::new().name("luna-health".to_string()).spawn(move || loop {
+            let connection = match server.listener.accept() {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Health connection failed: {}", e);
+                    continue;
+                }
+            };
+            let server = Arc::clone(&server);
+            let answered = std::thread::Builder::new().name("luna-health-request".to_string()).spawn(move || {
+                if let Err(e) = server.answer(connection) {
+                    warn!("Health request failed: {}", e);
+                }
+            });
+            if let Err(e) = answered {
+                warn!("Could not start a health request thread: {}", e);
+            }
+        })?)
+    }
+
+    /// Accept and answer a single connection
+    pub fn serve_one(&self) -> Result<()> {
+        self.answer(self.listener.accept()?)
+    }
+
+    fn answer(&self, (mut stream, peer): (TcpStream, SocketAddr)) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let request = read_message(&mut stream)?;
+        let (method, path) = request.route();
+        debug!("Health request from {}: {} {}", peer, method, path);
+
+        let report = |report: HealthReport| -> Result<(u16, &str, Vec<u8>)> {
+            let status = if report.is_ok() { 200 } else { 503 };
+            Ok((status, "application/json", serde_json::to_vec(&report)?))
+        };
+        let (status, content_type, body) = match (method, path) {
+            ("GET", "/healthz") => report(self.liveness())?,
+            ("GET", "/readyz") => report(self.readiness())?,
+            ("GET", "/metrics") if self.config.metrics => (200, "text/plain; version=0.0.4", self.metrics().into_bytes()),
+            ("GET", "/slo") => (200, "application/json", serde_json::to_vec(&serde_json::json!({ "objectives": self.handle.metrics().slo_statuses() }))?),
+            _ => (404, "text/plain", b"not found".to_vec()),
+        };
+        write_response(&mut stream, status, content_type, &body)
+    }
+
+    pub fn liveness(&self) -> HealthReport {
+        let alive = self.handle.is_running();
+        let detail = if alive { "running" } else { "worker thread has exited" };
+        HealthReport::new(vec![HealthCheck::new("worker", alive, detail)], self.handle.queue_depth())
+    }
+
+    pub fn readiness(&self) -> HealthReport {
+        let depth = self.handle.queue_depth();
+        let mut checks = match self.ask(|luna| Ok(luna.readiness())) {
+            Some(checks) => checks,
+            None => vec![HealthCheck::new("worker", false, format!("no answer within {}ms", self.config.probe_timeout_ms))],
+        };
+        checks.push(HealthCheck::new(
+            "queue",
+            depth <= self.config.max_queue_depth,
+            format!("{} of at most {} requests", depth, self.config.max_queue_depth),
+        ));
+        HealthReport::new(checks, depth)
+    }
+
+    /// Collected metrics plus the queue depth; never waits for the worker
+    fn metrics(&self) -> String {
+        format!(
+            "{}# HELP luna_queue_depth Requests queued or running\n# TYPE luna_queue_depth gauge\nluna_queue_depth {}\n",
+            self.handle.metrics().render(),
+            self.handle.queue_depth()
+        )
+    }
+
+    /// Run `f` on the worker, giving up after the probe timeout
+    fn ask<T: Send + 'static>(&self, f: impl FnOnce(&mut super::Luna) -> Result<T> + Send + 'static) -> Option<T> {
+        let timeout = Duration::from_millis(self.config.probe_timeout_ms);
+        self.handle.call(move |luna, _| f(luna)).wait_timeout(timeout)?.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::remote::{http_request, HttpTimeouts};
+    use crate::core::LunaConfig;
+
+    fn get(addr: SocketAddr, server: &HealthServer, path: &str) -> (u16, String) {
+        let path = path.to_string();
+        let client = std::thread::spawn(move || {
+            let timeouts = HttpTimeouts { connect: Duration::from_secs(1), read: Duration::from_secs(5) };
+            http_request("127.0.0.1", addr.port(), "GET", &path, &[], &[], timeouts).unwrap()
+        });
+        server.serve_one().unwrap();
+        let response = client.join().unwrap();
+        (response.status, String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn test_liveness_readiness_and_metrics() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+        let config = HealthConfig { probe_timeout_ms: 100, max_queue_depth: 0, ..HealthConfig::default() };
+        let server = HealthServer::bind("127.0.0.1:0", handle.clone(), config).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let (status, body) = get(addr, &server, "/healthz");
+        assert_eq!(status, 200, "{}", body);
+
+        // Whether an idle instance is ready depends on this machine's input and session
+        let (_, body) = get(addr, &server, "/readyz");
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let names: Vec<&str> = report["checks"].as_array().unwrap().iter().map(|c| c["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["capture", "models", "input", "session", "watchdog", "queue"]);
+        assert_eq!(report["queue_depth"], 0);
+
+        // A busy worker is alive but not ready
+        let busy = handle.call(|_, _| {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(())
+        });
+        assert_eq!(get(addr, &server, "/healthz").0, 200);
+        let (status, body) = get(addr, &server, "/readyz");
+        assert_eq!(status, 503);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let failing: Vec<&str> = report["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|c| c["ok"] == false)
+            .map(|c| c["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(failing, vec!["worker", "queue"], "{}", body);
+
+        // Metrics don't wait for the busy worker
+        let (status, body) = get(addr, &server, "/metrics");
+        assert_eq!(status, 200);
+        assert!(body.contains("# TYPE luna_safety_blocks_total counter\nluna_safety_blocks_total 0\n"), "{}", body);
+        assert!(!body.contains("luna_queue_depth 0\n"), "{}", body);
+        let (status, body) = get(addr, &server, "/slo");
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["objectives"], serde_json::json!([]));
+        busy.wait().unwrap();
+
+        handle.shutdown();
+        assert_eq!(get(addr, &server, "/healthz").0, 503);
+        assert_eq!(get(addr, &server, "/nope").0, 404);
+    }
+
+    #[test]
+    fn test_silent_client_does_not_block_probes() {
+        let handle = LunaHandle::spawn(LunaConfig::default()).unwrap();
+        let server = HealthServer::bind("127.0.0.1:0", handle.clone(), HealthConfig::default()).unwrap();
+        let port = server.local_addr().unwrap().port();
+        server.spawn().unwrap();
+
+        let _silent = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let timeouts = HttpTimeouts { connect: Duration::from_secs(1), read: Duration::from_secs(2) };
+        let response = http_request("127.0.0.1", port, "GET", "/healthz", &[], &[], timeouts).unwrap();
+        assert_eq!(response.status, 200);
+        handle.shutdown();
+    }
+}
@@ -0,0 +1,216 @@
+/*!
+ * Luna Hooks - Extension points around analysis and execution
+ *
+ * A `PipelineHook` registered with `Luna::add_hook` is called before and
+ * after each screen analysis and before and after each executed action. It
+ * can edit the analysis (add, drop or re-label elements before planning
+ * sees them), veto an action, fail the command, and attach key/value
+ * metadata that comes back in the command result and the `luna.do`
+ * document. This is where site-specific policy goes without patching the
+ * pipeline itself.
+ *
+ * Hooks run in registration order on the thread running the command, like
+ * confirmers: a hook that has to ask a service blocks the command until it
+ * answers. Action hooks only run for actions that are executed, so a dry run
+ * calls the analysis hooks alone.
+ */
+
+use anyhow::Result;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+
+use super::{ExecuteOptions, LunaAction, LunaError, ScreenAnalysis};
+
+/// What a hook sees of the running command
+pub struct HookContext<'a> {
+    pub command: &'a str,
+    pub options: &'a ExecuteOptions,
+    /// Returned as `CommandResult::metadata`; shared by all hooks
+    pub metadata: &'a mut BTreeMap<String, String>,
+}
+
+/// Whether an action may run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookVerdict {
+    Allow,
+    /// Stop the command before the action, for this reason
+    Veto(String),
+}
+
+/// Callbacks around the analysis and execution stages; every one defaults
+/// to doing nothing. An `Err` from an analysis callback fails the command.
+pub trait PipelineHook: Send {
+    /// Names the hook in logs and refusals, and for `Luna::remove_hook`
+    fn name(&self) -> &str;
+
+    fn before_analysis(&mut self, _context: &mut HookContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// Runs on the thorough re-analysis too, when there is one
+    fn after_analysis(&mut self, _context: &mut HookContext, _analysis: &mut ScreenAnalysis) -> Result<()> {
+        Ok(())
+    }
+
+    fn before_action(&mut self, _context: &mut HookContext, _action: &LunaAction) -> HookVerdict {
+        HookVerdict::Allow
+    }
+
+    fn after_action(&mut self, _context: &mut HookContext, _action: &LunaAction, _succeeded: bool) {}
+}
+
+/// Registered hooks, in the order they run
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Box<dyn PipelineHook>>,
+}
+
+impl Hooks {
+    pub fn add(&mut self, hook: Box<dyn PipelineHook>) {
+        debug!("Registered pipeline hook '{}'", hook.name());
+        self.hooks.push(hook);
+    }
+
+    /// Unregister every hook called `name`; false when there was none
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|hook| hook.name() != name);
+        self.hooks.len() < before
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.hooks.iter().map(|hook| hook.name()).collect()
+    }
+
+    pub(crate) fn before_analysis(&mut self, context: &mut HookContext) -> Result<()> {
+        for hook in &mut self.hooks {
+            hook.before_analysis(context).map_err(|e| failed(hook.as_ref(), "before analysis", e))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn after_analysis(&mut self, context: &mut HookContext, analysis: &mut ScreenAnalysis) -> Result<()> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        for hook in &mut self.hooks {
+            hook.after_analysis(context, analysis).map_err(|e| failed(hook.as_ref(), "after analysis", e))?;
+        }
+        // Parent and child indices may point at moved or removed elements
+        analysis.link_hierarchy();
+        Ok(())
+    }
+
+    /// The first veto, as the error that stops the command
+    pub(crate) fn before_action(&mut self, context: &mut HookContext, action: &LunaAction) -> Result<()> {
+        for hook in &mut self.hooks {
+            if let HookVerdict::Veto(reason) = hook.before_action(context, action) {
+                warn!("Hook '{}' vetoed {:?}: {}", hook.name(), action, reason);
+                return Err(LunaError::UnsafeAction(format!("vetoed by hook '{}': {}", hook.name(), reason)).into());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn after_action(&mut self, context: &mut HookContext, action: &LunaAction, succeeded: bool) {
+        for hook in &mut self.hooks {
+            hook.after_action(context, action, succeeded);
+        }
+    }
+}
+
+/// A hook's error, named after the hook; Luna's own errors keep their kind
+fn failed(hook: &dyn PipelineHook, stage: &str, error: anyhow::Error) -> anyhow::Error {
+    match error.downcast::<LunaError>() {
+        Ok(error) => error.into(),
+        Err(error) => LunaError::System(format!("hook '{}' failed {}: {}", hook.name(), stage, error)).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::sandbox::SandboxScene;
+    use crate::core::{ElementBounds, Luna, LunaConfig, ScreenElement};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Hides the Save button, vetoes clicks left of x = 150 and counts calls
+    struct Policy(Arc<Mutex<Vec<String>>>);
+
+    impl PipelineHook for Policy {
+        fn name(&self) -> &str {
+            "policy"
+        }
+
+        fn before_analysis(&mut self, context: &mut HookContext) -> Result<()> {
+            self.0.lock().unwrap().push(format!("analyze {}", context.command));
+            if context.command.contains("Delete") {
+                anyhow::bail!("deleting is not allowed here");
+            }
+            Ok(())
+        }
+
+        fn after_analysis(&mut self, context: &mut HookContext, analysis: &mut ScreenAnalysis) -> Result<()> {
+            analysis.elements.retain(|e| e.text.as_deref() != Some("Save"));
+            analysis.elements.push(ScreenElement {
+                element_type: "button".to_string(),
+                bounds: ElementBounds::new(400, 400, 80, 30),
+                shape: None,
+                confidence: 0.99,
+                text: Some("Archive".to_string()),
+                attributes: HashMap::new(),
+                parent: None,
+                children: Vec::new(),
+            });
+            context.metadata.insert("policy".to_string(), "v2".to_string());
+            Ok(())
+        }
+
+        fn before_action(&mut self, _context: &mut HookContext, action: &LunaAction) -> HookVerdict {
+            match action.click_point() {
+                Some((x, _)) if x < 150 => HookVerdict::Veto("clicks in the left margin are reserved".to_string()),
+                _ => HookVerdict::Allow,
+            }
+        }
+
+        fn after_action(&mut self, context: &mut HookContext, _action: &LunaAction, succeeded: bool) {
+            self.0.lock().unwrap().push(format!("acted {}", succeeded));
+            context.metadata.insert("acted".to_string(), succeeded.to_string());
+        }
+    }
+
+    #[test]
+    fn test_hooks_edit_the_analysis_veto_actions_and_attach_metadata() {
+        let mut config = LunaConfig::default();
+        config.escalation.enabled = false;
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        luna.add_hook(Box::new(Policy(calls.clone())));
+        assert_eq!(luna.hook_names(), ["policy"]);
+        let options = ExecuteOptions::default();
+
+        // The hook's own element is a target; the one it removed isn't
+        let result = luna.execute_command("click Archive", &options).unwrap();
+        assert!(matches!(result.actions.as_slice(), [LunaAction::Click { x: 440, y: 415 }]), "{:?}", result.actions);
+        let expected: BTreeMap<String, String> = [("acted", "true"), ("policy", "v2")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(result.metadata, expected);
+        luna.execute_command("click Save", &options).ok();
+        assert_eq!(sandbox.scene().widget("Save").unwrap().clicks, 0);
+
+        let err = luna.execute_command("click at 20, 20", &options).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::UnsafeAction(m)) if m.contains("hook 'policy'")), "{}", err);
+        let err = luna.execute_command("click Delete", &options).unwrap_err();
+        assert!(err.to_string().contains("deleting is not allowed"), "{}", err);
+
+        // Literal commands skip analysis; dry runs skip the action hooks
+        let dry = luna.execute_command("click Archive", &ExecuteOptions { dry_run: true, ..Default::default() }).unwrap();
+        assert!(!dry.metadata.contains_key("acted"));
+        assert_eq!(calls.lock().unwrap().iter().filter(|c| c.starts_with("analyze")).count(), 4);
+
+        assert!(luna.remove_hook("policy"));
+        assert!(!luna.remove_hook("policy"));
+        luna.execute_command("click at 20, 20", &options).unwrap();
+    }
+}
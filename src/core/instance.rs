@@ -0,0 +1,411 @@
+/*!
+ * Luna Instance - Machine-wide ownership of the input subsystem
+ *
+ * Only one LUNA process may inject input at a time. The owner holds an
+ * exclusive OS lock (flock / LockFileEx) on a lease file in the runtime
+ * directory for as long as it owns input, and writes who it is into the
+ * file; other instances run analysis-only (they can capture, analyze and
+ * plan but not execute). Taking the lock is a single atomic step, and the
+ * OS drops it when the owner exits or dies, so ownership can't be claimed
+ * twice and a dead owner's lease never outlives it, whatever its PID is
+ * reused for. A secondary instance asks for ownership by writing a request
+ * file, which the owner honours at its next command when
+ * `instance.yield_on_request` is set.
+ *
+ * Instances in the same process share the lock.
+ */
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::config::InstanceConfig;
+use super::error::LunaError;
+
+pub const LEASE_FILE: &str = "input.lease";
+pub const REQUEST_FILE: &str = "input.request";
+
+/// Locked lease file and its in-process holders, per lease path, so the
+/// lock outlives all but the last
+static HOLDERS: Mutex<Option<HashMap<PathBuf, (File, usize)>>> = Mutex::new(None);
+
+/// Who holds (or is asking for) the input lease
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseHolder {
+    pub pid: u32,
+    pub label: String,
+    /// Unix seconds
+    pub since: u64,
+}
+
+impl std::fmt::Display for LeaseHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {} ({})", self.pid, self.label)
+    }
+}
+
+/// Whether this instance may inject input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputOwnership {
+    /// This process holds the lease
+    Owner,
+    /// Another instance holds the lease; this one only analyzes and plans
+    AnalysisOnly { owner: LeaseHolder },
+    /// Nobody holds the lease; this instance takes it at its next command
+    Free,
+    /// Coordination is disabled in the config
+    Unmanaged,
+}
+
+impl InputOwnership {
+    pub fn can_inject(&self) -> bool {
+        !matches!(self, InputOwnership::AnalysisOnly { .. })
+    }
+}
+
+impl std::fmt::Display for InputOwnership {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputOwnership::Owner => write!(f, "this instance owns input"),
+            InputOwnership::AnalysisOnly { owner } => write!(f, "analysis-only; input is owned by {}", owner),
+            InputOwnership::Free => write!(f, "input is free; taken at the next command"),
+            InputOwnership::Unmanaged => write!(f, "input coordination is disabled"),
+        }
+    }
+}
+
+/// This instance's view of the machine-wide input lease
+pub struct InputLease {
+    dir: Option<PathBuf>,
+    label: String,
+    yield_on_request: bool,
+    held: bool,
+}
+
+impl InputLease {
+    pub fn new(config: &InstanceConfig) -> Self {
+        let dir = config.enabled.then(|| {
+            config.lease_dir.clone().unwrap_or_else(|| {
+                dirs::runtime_dir().unwrap_or_else(std::env::temp_dir).join("luna")
+            })
+        });
+        Self { dir, label: config.label.clone(), yield_on_request: config.yield_on_request, held: false }
+    }
+
+    /// Take the lease if no other process holds it
+    pub fn try_acquire(&mut self) -> Result<InputOwnership> {
+        let Some(dir) = self.dir.clone() else {
+            return Ok(InputOwnership::Unmanaged);
+        };
+        if self.held {
+            return Ok(InputOwnership::Owner);
+        }
+        std::fs::create_dir_all(&dir)?;
+        let lease_path = dir.join(LEASE_FILE);
+        if self.join_holders(&lease_path) {
+            return Ok(InputOwnership::Owner);
+        }
+
+        // Someone else asked first; let them have it
+        if let Some(requester) = read_holder(&dir.join(REQUEST_FILE)) {
+            if requester.pid != std::process::id() && process_alive(requester.pid) {
+                return Ok(InputOwnership::AnalysisOnly { owner: locked_by_other(&lease_path)?.unwrap_or(requester) });
+            }
+        }
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&lease_path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(InputOwnership::AnalysisOnly { owner: current_holder(&lease_path) }),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        if let Some(previous) = read_holder(&lease_path) {
+            info!("Taking over input lease from exited {}", previous);
+        }
+        let holder = LeaseHolder { pid: std::process::id(), label: self.label.clone(), since: unix_now() };
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        // Our own request has been granted
+        if read_holder(&dir.join(REQUEST_FILE)).is_some_and(|r| r.pid == holder.pid) {
+            remove_if_exists(&dir.join(REQUEST_FILE))?;
+        }
+        info!("Acquired input lease at {}", lease_path.display());
+        self.held = true;
+        HOLDERS.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashMap::new).insert(lease_path, (file, 1));
+        Ok(InputOwnership::Owner)
+    }
+
+    /// Current ownership, without trying to acquire
+    pub fn ownership(&self) -> InputOwnership {
+        let Some(dir) = &self.dir else {
+            return InputOwnership::Unmanaged;
+        };
+        if self.held {
+            return InputOwnership::Owner;
+        }
+        match locked_by_other(&dir.join(LEASE_FILE)) {
+            Ok(Some(owner)) => InputOwnership::AnalysisOnly { owner },
+            // Free, or held only by other instances in this process
+            Ok(None) => InputOwnership::Free,
+            Err(e) => {
+                debug!("Could not check the input lease: {}", e);
+                InputOwnership::Free
+            }
+        }
+    }
+
+    /// Instance waiting for the lease, if any
+    pub fn pending_request(&self) -> Option<LeaseHolder> {
+        let request = read_holder(&self.dir.as_ref()?.join(REQUEST_FILE))?;
+        (request.pid != std::process::id() && process_alive(request.pid)).then_some(request)
+    }
+
+    /// Ask the current owner to hand over the lease
+    pub fn request(&self) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)?;
+        let holder = LeaseHolder { pid: std::process::id(), label: self.label.clone(), since: unix_now() };
+        std::fs::write(dir.join(REQUEST_FILE), serde_json::to_string(&holder)?)?;
+        debug!("Requested input ownership");
+        Ok(())
+    }
+
+    /// Give up the lease (once every in-process holder has released it)
+    pub fn release(&mut self) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        if !std::mem::take(&mut self.held) {
+            return Ok(());
+        }
+        let lease_path = dir.join(LEASE_FILE);
+        let mut holders = HOLDERS.lock().unwrap_or_else(|e| e.into_inner());
+        let holders = holders.get_or_insert_with(HashMap::new);
+        let Some((_, count)) = holders.get_mut(&lease_path) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count == 0 {
+            let (file, _) = holders.remove(&lease_path).expect("entry just found");
+            // The file stays: removing it could let two processes lock different files
+            file.set_len(0)?;
+            drop(file);
+            info!("Released input lease");
+        }
+        Ok(())
+    }
+
+    /// Check before injecting input: yields to a pending request when configured
+    /// to, and picks the lease up again if it has become free.
+    pub fn ensure_owner(&mut self) -> Result<()> {
+        if self.dir.is_none() {
+            return Ok(());
+        }
+        if self.held && self.yield_on_request {
+            if let Some(requester) = self.pending_request() {
+                info!("Handing input ownership to {}", requester);
+                self.release()?;
+                return Err(LunaError::PermissionDenied(format!(
+                    "input ownership was handed to {}; this instance is now analysis-only", requester)).into());
+            }
+        }
+        if self.held && matches!(self.ownership(), InputOwnership::Owner) {
+            return Ok(());
+        }
+        self.held = false;
+        match self.try_acquire()? {
+            InputOwnership::AnalysisOnly { owner } => Err(LunaError::PermissionDenied(format!(
+                "input is owned by {}; this instance is analysis-only (request ownership to take over)", owner)).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Share a lock another instance in this process already holds
+    fn join_holders(&mut self, lease_path: &Path) -> bool {
+        let mut holders = HOLDERS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((_, count)) = holders.get_or_insert_with(HashMap::new).get_mut(lease_path) else {
+            return false;
+        };
+        *count += 1;
+        self.held = true;
+        true
+    }
+}
+
+impl Drop for InputLease {
+    fn drop(&mut self) {
+        if let Err(e) = self.release() {
+            warn!("Failed to release input lease: {}", e);
+        }
+    }
+}
+
+fn read_holder(path: &Path) -> Option<LeaseHolder> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+/// Holder of the lease at `path` if another process has it locked. Probing
+/// takes the lock for a moment when it is free.
+fn locked_by_other(path: &Path) -> Result<Option<LeaseHolder>> {
+    let in_process = HOLDERS.lock().unwrap_or_else(|e| e.into_inner()).as_ref().is_some_and(|h| h.contains_key(path));
+    if in_process {
+        return Ok(None);
+    }
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match file.try_lock_shared() {
+        Ok(()) => Ok(None),
+        Err(TryLockError::WouldBlock) => Ok(Some(current_holder(path))),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Who wrote the locked lease file; it can be empty for a moment while the
+/// owner writes it
+fn current_holder(path: &Path) -> LeaseHolder {
+    read_holder(path).unwrap_or_else(|| LeaseHolder { pid: 0, label: "another instance".to_string(), since: 0 })
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn process_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else if cfg!(target_os = "windows") {
+        std::process::Command::new("tasklist")
+            .args(["/fi", &format!("PID eq {}", pid), "/fo", "csv", "/nh"])
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+    } else {
+        std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A live process that is not this one
+    fn other_process() -> std::process::Child {
+        std::process::Command::new("sleep").arg("30").spawn().unwrap()
+    }
+
+    fn lease(dir: &Path) -> InputLease {
+        InputLease::new(&InstanceConfig { lease_dir: Some(dir.to_path_buf()), ..InstanceConfig::default() })
+    }
+
+    fn write_holder(dir: &Path, file: &str, pid: u32) {
+        let holder = LeaseHolder { pid, label: "daemon".to_string(), since: 0 };
+        std::fs::write(dir.join(file), serde_json::to_string(&holder).unwrap()).unwrap();
+    }
+
+    /// Lock the lease the way another process would, with its holder written in
+    fn hold_elsewhere(dir: &Path, pid: u32) -> File {
+        write_holder(dir, LEASE_FILE, pid);
+        let file = OpenOptions::new().read(true).write(true).open(dir.join(LEASE_FILE)).unwrap();
+        file.try_lock().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_acquire_release_and_same_process_sharing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut first = lease(dir.path());
+        let mut second = lease(dir.path());
+        assert_eq!(first.try_acquire().unwrap(), InputOwnership::Owner);
+        assert_eq!(second.try_acquire().unwrap(), InputOwnership::Owner);
+
+        first.release().unwrap();
+        assert_eq!(first.ownership(), InputOwnership::Free, "held only within this process");
+        assert!(read_holder(&dir.path().join(LEASE_FILE)).is_some(), "still held by the second instance");
+        drop(second);
+        assert!(read_holder(&dir.path().join(LEASE_FILE)).is_none());
+        assert_eq!(first.try_acquire().unwrap(), InputOwnership::Owner);
+
+        let disabled = InputLease::new(&InstanceConfig { enabled: false, ..InstanceConfig::default() });
+        assert_eq!(disabled.ownership(), InputOwnership::Unmanaged);
+    }
+
+    #[test]
+    fn test_secondary_is_analysis_only_until_the_lock_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let daemon = hold_elsewhere(dir.path(), 4242);
+
+        let mut gui = lease(dir.path());
+        let ownership = gui.try_acquire().unwrap();
+        assert!(matches!(&ownership, InputOwnership::AnalysisOnly { owner } if owner.pid == 4242));
+        assert!(!ownership.can_inject());
+        assert_eq!(gui.ownership(), ownership);
+        let err = gui.ensure_owner().unwrap_err();
+        assert!(err.to_string().contains("analysis-only"), "{}", err);
+
+        // The owner exits and the OS drops its lock
+        drop(daemon);
+        gui.ensure_owner().unwrap();
+        assert_eq!(gui.ownership(), InputOwnership::Owner);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unlocked_lease_is_free_even_if_its_pid_is_alive() {
+        let dir = tempfile::tempdir().unwrap();
+        // Left by an owner that crashed; its PID now belongs to something else
+        let mut reused = other_process();
+        write_holder(dir.path(), LEASE_FILE, reused.id());
+
+        let mut luna = lease(dir.path());
+        assert_eq!(luna.ownership(), InputOwnership::Free);
+        assert_eq!(luna.try_acquire().unwrap(), InputOwnership::Owner);
+        assert_eq!(read_holder(&dir.path().join(LEASE_FILE)).unwrap().pid, std::process::id());
+        reused.kill().unwrap();
+        reused.wait().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owner_yields_to_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut owner = lease(dir.path());
+        assert_eq!(owner.try_acquire().unwrap(), InputOwnership::Owner);
+
+        let mut requester = other_process();
+        write_holder(dir.path(), REQUEST_FILE, requester.id());
+        assert_eq!(owner.pending_request().unwrap().pid, requester.id());
+
+        assert!(owner.ensure_owner().is_err());
+        assert!(read_holder(&dir.path().join(LEASE_FILE)).is_none());
+        // The requester gets first pick; we stay analysis-only until it takes over
+        assert!(!owner.try_acquire().unwrap().can_inject());
+
+        requester.kill().unwrap();
+        requester.wait().unwrap();
+        assert_eq!(owner.try_acquire().unwrap(), InputOwnership::Owner);
+    }
+}
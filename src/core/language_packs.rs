@@ -0,0 +1,407 @@
+/*!
+ * Luna Language Packs - OCR language data, downloaded on demand
+ *
+ * Recognizing text in a language needs that language's data. Packs live in
+ * the model cache under `ocr/<language>/`, next to a `pack.json` recording
+ * the catalog entry they came from. The catalog (`ocr.catalog_url`, JSON
+ * over HTTP) lists what can be installed with each pack's size and SHA-256;
+ * a download that doesn't match is discarded before anything is replaced.
+ * Plain HTTP can be tampered with on the way, and a catalog that was would
+ * vouch for whatever it lists, so packs are only installed from a catalog
+ * whose own SHA-256 matches `ocr.catalog_sha256`.
+ * Which languages are needed depends on the app in front
+ * (`ocr.app_languages`). Pinned packs are kept through storage cleanup.
+ */
+
+use anyhow::Result;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::config::OcrConfig;
+use super::scratch::ScratchRegistry;
+use super::storage::{StorageManager, StoreKind};
+use super::LunaError;
+use crate::ai::remote::{http_request, parse_endpoint, HttpTimeouts};
+use crate::utils::digest::{hex, sha256};
+
+/// Directory of the packs inside the model cache
+pub const PACK_DIR: &str = "ocr";
+/// Manifest of an installed pack, inside its directory
+const PACK_MANIFEST: &str = "pack.json";
+/// Downloads are large; allow a slow connection per read
+const DOWNLOAD_TIMEOUTS: HttpTimeouts = HttpTimeouts { connect: Duration::from_secs(5), read: Duration::from_secs(60) };
+
+/// A pack as listed in the catalog
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackEntry {
+    /// Pack code, as used in `ocr.languages` ("en", "de", "ja")
+    pub language: String,
+    /// Display name ("German")
+    pub name: String,
+    pub version: String,
+    /// Where the pack's data is downloaded from (`http://`)
+    pub url: String,
+    /// Hex SHA-256 of the data
+    pub sha256: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Deserialize)]
+struct Catalog {
+    packs: Vec<PackEntry>,
+}
+
+/// One language: installed, available from the catalog, or both
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackListing {
+    pub language: String,
+    pub name: String,
+    /// Installed version
+    pub installed: Option<String>,
+    /// Version in the catalog
+    pub available: Option<String>,
+    pub size_bytes: u64,
+    pub pinned: bool,
+}
+
+impl PackListing {
+    pub fn update_available(&self) -> bool {
+        matches!((&self.installed, &self.available), (Some(installed), Some(available)) if installed != available)
+    }
+}
+
+impl std::fmt::Display for PackListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<4} {:<16}", self.language, self.name)?;
+        match (&self.installed, &self.available) {
+            (Some(installed), _) if self.update_available() => {
+                write!(f, " installed {} (update {} available)", installed, self.available.as_deref().unwrap_or_default())?
+            }
+            (Some(installed), _) => write!(f, " installed {}", installed)?,
+            (None, Some(available)) => write!(f, " available {}", available)?,
+            (None, None) => {}
+        }
+        write!(f, ", {}", super::storage::format_bytes(self.size_bytes))?;
+        if self.pinned {
+            write!(f, ", pinned")?;
+        }
+        Ok(())
+    }
+}
+
+/// Installs, lists and pins language packs in the model cache
+pub struct LanguagePacks<'a> {
+    storage: &'a StorageManager,
+    catalog_url: Option<&'a str>,
+    /// Hex SHA-256 the catalog must have before anything is installed from it
+    catalog_sha256: Option<&'a str>,
+    /// Downloads are staged here until they are swapped in
+    scratch: ScratchRegistry,
+}
+
+impl<'a> LanguagePacks<'a> {
+    pub fn new(storage: &'a StorageManager, catalog_url: Option<&'a str>) -> Self {
+        Self { storage, catalog_url, catalog_sha256: None, scratch: ScratchRegistry::default() }
+    }
+
+    /// Trust the catalog only when its SHA-256 is `sha256`
+    pub fn with_catalog_digest(mut self, sha256: Option<&'a str>) -> Self {
+        self.catalog_sha256 = sha256;
+        self
+    }
+
+    /// Journal staged downloads in `scratch`, so an interrupted install is removed
+    pub fn with_scratch(mut self, scratch: ScratchRegistry) -> Self {
+        self.scratch = scratch;
+        self
+    }
+
+    fn dir(&self) -> Result<PathBuf> {
+        let dir = self.storage.store_path(StoreKind::ModelCache)?.join(PACK_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// A pack's directory relative to the model cache, as pinned
+    fn store_path(language: &str) -> PathBuf {
+        Path::new(PACK_DIR).join(language)
+    }
+
+    /// Installed packs, by language
+    pub fn installed(&self) -> Result<Vec<PackEntry>> {
+        let mut packs = Vec::new();
+        for entry in fs::read_dir(self.dir()?)? {
+            let manifest = entry?.path().join(PACK_MANIFEST);
+            match fs::read(&manifest).map_err(anyhow::Error::from).and_then(|bytes| Ok(serde_json::from_slice(&bytes)?)) {
+                Ok(pack) => packs.push(pack),
+                Err(e) => warn!("Skipping language pack at {}: {}", manifest.display(), e),
+            }
+        }
+        packs.sort_by(|a: &PackEntry, b| a.language.cmp(&b.language));
+        Ok(packs)
+    }
+
+    /// Packs the catalog offers. A catalog that doesn't match a pinned
+    /// digest is an error; an unpinned one can be listed but not installed from.
+    pub fn catalog(&self) -> Result<Vec<PackEntry>> {
+        let url = self.catalog_url.ok_or_else(|| LunaError::Config("no OCR language pack catalog configured (ocr.catalog_url)".to_string()))?;
+        let data = download(url)?;
+        if let Some(expected) = self.catalog_sha256 {
+            let digest = hex(&sha256(&data));
+            if !digest.eq_ignore_ascii_case(expected) {
+                return Err(LunaError::System(format!(
+                    "language pack catalog failed verification: SHA-256 {}, expected {} (ocr.catalog_sha256)",
+                    digest, expected
+                ))
+                .into());
+            }
+        }
+        let catalog: Catalog = serde_json::from_slice(&data)?;
+        Ok(catalog.packs)
+    }
+
+    /// Every installed or available pack. Lists installed packs alone when
+    /// the catalog can't be reached.
+    pub fn list(&self) -> Result<Vec<PackListing>> {
+        let pinned = self.storage.pinned(StoreKind::ModelCache)?;
+        let available = match self.catalog_url.map(|_| self.catalog()) {
+            Some(Ok(packs)) => packs,
+            Some(Err(e)) => {
+                warn!("Language pack catalog unavailable: {}", e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let installed = self.installed()?;
+        let mut languages: Vec<&str> = installed.iter().chain(&available).map(|p| p.language.as_str()).collect();
+        languages.sort_unstable();
+        languages.dedup();
+        Ok(languages
+            .into_iter()
+            .map(|language| {
+                let find = |packs: &'_ [PackEntry]| packs.iter().find(|p| p.language == language).cloned();
+                let (have, offered) = (find(&installed), find(&available));
+                let entry = have.as_ref().or(offered.as_ref()).expect("listed from one of them");
+                PackListing {
+                    language: language.to_string(),
+                    name: entry.name.clone(),
+                    installed: have.as_ref().map(|p| p.version.clone()),
+                    available: offered.as_ref().map(|p| p.version.clone()),
+                    size_bytes: entry.size_bytes,
+                    pinned: pinned.contains(&Self::store_path(language)),
+                }
+            })
+            .collect())
+    }
+
+    /// Download `language`'s pack from the catalog, replacing any installed
+    /// version once the download is verified
+    pub fn install(&self, language: &str) -> Result<PackEntry> {
+        if self.catalog_sha256.is_none() {
+            return Err(LunaError::Config(
+                "language packs are only installed from a pinned catalog; set ocr.catalog_sha256 to the catalog's SHA-256".to_string(),
+            )
+            .into());
+        }
+        let entry = self
+            .catalog()?
+            .into_iter()
+            .find(|p| p.language == language)
+            .ok_or_else(|| LunaError::NotFound(format!("no '{}' language pack in the catalog", language)))?;
+        let data = download(&entry.url)?;
+        let digest = hex(&sha256(&data));
+        if data.len() as u64 != entry.size_bytes || !digest.eq_ignore_ascii_case(&entry.sha256) {
+            return Err(LunaError::System(format!(
+                "'{}' language pack failed verification: {} bytes with SHA-256 {}, expected {} bytes with {}",
+                language,
+                data.len(),
+                digest,
+                entry.size_bytes,
+                entry.sha256
+            ))
+            .into());
+        }
+
+        // Stage next to the final directory so the swap is a rename
+        let dir = self.dir()?;
+        let scope = self.scratch.scope("language pack download");
+        let staging = scope.register(dir.join(format!(".{}.partial", language)))?;
+        let _ = fs::remove_dir_all(&staging);
+        fs::create_dir_all(&staging)?;
+        fs::write(staging.join(pack_file_name(&entry.url, language)?), &data)?;
+        fs::write(staging.join(PACK_MANIFEST), serde_json::to_vec_pretty(&entry)?)?;
+        let target = dir.join(language);
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::rename(&staging, &target)?;
+        scope.keep(&staging);
+        info!("Installed {} language pack {} ({} bytes)", entry.name, entry.version, entry.size_bytes);
+        Ok(entry)
+    }
+
+    /// Install whichever of `languages` are missing; returns the packs installed now
+    pub fn ensure(&self, languages: &[String]) -> Result<Vec<PackEntry>> {
+        let installed = self.installed()?;
+        languages
+            .iter()
+            .filter(|language| !installed.iter().any(|p| &p.language == *language))
+            .map(|language| self.install(language))
+            .collect()
+    }
+
+    /// Returns whether the pack was installed
+    pub fn remove(&self, language: &str) -> Result<bool> {
+        let dir = self.dir()?.join(language);
+        if !dir.join(PACK_MANIFEST).exists() {
+            return Ok(false);
+        }
+        fs::remove_dir_all(dir)?;
+        self.storage.unpin(StoreKind::ModelCache, Self::store_path(language))?;
+        Ok(true)
+    }
+
+    /// Keep an installed pack through storage cleanup, or stop keeping it
+    pub fn set_pinned(&self, language: &str, pinned: bool) -> Result<()> {
+        if !pinned {
+            self.storage.unpin(StoreKind::ModelCache, Self::store_path(language))?;
+            return Ok(());
+        }
+        if !self.installed()?.iter().any(|p| p.language == language) {
+            return Err(LunaError::NotFound(format!("'{}' language pack is not installed", language)).into());
+        }
+        self.storage.pin(StoreKind::ModelCache, Self::store_path(language))
+    }
+}
+
+/// OCR languages for the app whose window title is `window_title`: those of
+/// the first `ocr.app_languages` key the title contains, else `ocr.languages`
+pub fn languages_for(config: &OcrConfig, window_title: Option<&str>) -> Vec<String> {
+    let title = window_title.unwrap_or_default().to_lowercase();
+    config
+        .app_languages
+        .iter()
+        .find(|(app, _)| !title.is_empty() && title.contains(&app.to_lowercase()))
+        .map_or(&config.languages, |(_, languages)| languages)
+        .clone()
+}
+
+/// Name the pack's data is saved under: the last segment of its URL, or the
+/// language when the URL ends in `/`. Anything that could leave the pack's
+/// directory is refused.
+fn pack_file_name<'u>(url: &'u str, language: &'u str) -> Result<&'u str> {
+    let name = url.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or(language);
+    if name == "." || name == ".." || name.contains(['/', '\\', ':', '\0']) {
+        return Err(LunaError::InvalidArgument(format!("language pack URL '{}' has no usable file name", url)).into());
+    }
+    Ok(name)
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let (host, port, path) = parse_endpoint(url)?;
+    let response = http_request(&host, port, "GET", &path, &[], &[], DOWNLOAD_TIMEOUTS)?;
+    if response.status != 200 {
+        return Err(LunaError::NotFound(format!("{} answered HTTP {}", url, response.status)).into());
+    }
+    Ok(response.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::remote::{read_message, write_response};
+    use crate::core::config::StorageConfig;
+    use std::collections::BTreeMap;
+    use std::net::TcpListener;
+
+    /// Serve `files(base_url)` by path for `requests` connections; returns the base URL
+    fn serve(files: impl FnOnce(&str) -> Vec<(String, Vec<u8>)>, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let files = files(&base);
+        std::thread::spawn(move || {
+            for _ in 0..requests {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = read_message(&mut stream).unwrap();
+                match files.iter().find(|(path, _)| path == request.route().1) {
+                    Some((_, body)) => write_response(&mut stream, 200, "application/octet-stream", body).unwrap(),
+                    None => write_response(&mut stream, 404, "text/plain", b"").unwrap(),
+                }
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn test_install_verifies_and_pins_survive_cleanup() {
+        let german = b"german pack data".to_vec();
+        let mut catalog = Vec::new();
+        let base = serve(
+            |base| {
+                let entry = |language: &str, name: &str, sha256: String| PackEntry {
+                    language: language.to_string(),
+                    name: name.to_string(),
+                    version: "1".to_string(),
+                    url: format!("{}/packs/{}.pack", base, language),
+                    sha256,
+                    size_bytes: german.len() as u64,
+                };
+                // The French entry's checksum doesn't match its data
+                catalog = serde_json::to_vec(&serde_json::json!({ "packs": [entry("de", "German", hex(&sha256(&german))), entry("fr", "French", "00".repeat(32))] })).unwrap();
+                vec![
+                    ("/catalog.json".to_string(), catalog.clone()),
+                    ("/packs/de.pack".to_string(), german.clone()),
+                    ("/packs/fr.pack".to_string(), german.clone()),
+                ]
+            },
+            6,
+        );
+
+        let temp = tempfile::tempdir().unwrap();
+        let storage = StorageManager::new(temp.path(), StorageConfig { model_cache_mb: 1, ..StorageConfig::default() });
+        let url = format!("{}/catalog.json", base);
+
+        // An unpinned catalog is never installed from, and a pinned one must match
+        assert!(LanguagePacks::new(&storage, Some(&url)).install("de").unwrap_err().to_string().contains("ocr.catalog_sha256"));
+        let wrong = "11".repeat(32);
+        let tampered = LanguagePacks::new(&storage, Some(&url)).with_catalog_digest(Some(&wrong));
+        assert!(tampered.install("de").unwrap_err().to_string().contains("catalog failed verification"));
+
+        let digest = hex(&sha256(&catalog));
+        let packs = LanguagePacks::new(&storage, Some(&url)).with_catalog_digest(Some(&digest));
+        assert_eq!(packs.ensure(&["de".to_string()]).unwrap().len(), 1);
+        assert!(packs.install("fr").unwrap_err().to_string().contains("verification"));
+        let listed = packs.list().unwrap();
+        assert_eq!(listed.iter().map(|p| (p.language.as_str(), p.installed.is_some())).collect::<Vec<_>>(), [("de", true), ("fr", false)]);
+
+        packs.set_pinned("de", true).unwrap();
+        assert!(packs.set_pinned("fr", true).is_err());
+        storage.purge(StoreKind::ModelCache).unwrap();
+        assert_eq!(packs.installed().unwrap().len(), 1);
+        assert!(packs.remove("de").unwrap());
+        assert!(storage.pinned(StoreKind::ModelCache).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pack_file_names_stay_in_the_pack_directory() {
+        assert_eq!(pack_file_name("http://host/packs/de.pack", "de").unwrap(), "de.pack");
+        assert_eq!(pack_file_name("http://host/packs/", "de").unwrap(), "de");
+        for url in ["http://host/packs/..", "http://host/..\\..\\evil", "http://host/C:evil", "http://host/."] {
+            assert!(pack_file_name(url, "de").is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_app_profiles_choose_languages() {
+        let config = OcrConfig {
+            app_languages: BTreeMap::from([("Outlook".to_string(), vec!["en".to_string(), "de".to_string()])]),
+            ..OcrConfig::default()
+        };
+        assert_eq!(languages_for(&config, Some("Inbox - Microsoft OUTLOOK")), ["en", "de"]);
+        assert_eq!(languages_for(&config, Some("Terminal")), ["en"]);
+        assert_eq!(languages_for(&config, None), ["en"]);
+    }
+}
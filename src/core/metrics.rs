@@ -0,0 +1,288 @@
+/*!
+ * Luna Metrics - Counters and latency histograms in the Prometheus format
+ *
+ * `MetricsCollector` is shared by the Luna instance that feeds it and
+ * whoever exports it: the `/metrics` endpoint of `health::HealthServer`
+ * (pull) or `push` to a Prometheus push gateway. Scrapes never wait for the
+ * worker thread, so a long command doesn't make metrics disappear.
+ *
+ * Naming follows the Prometheus conventions: every metric starts with
+ * `luna_`, names are snake_case, durations are in seconds with a `_seconds`
+ * suffix, counters end in `_total`, and dimensions are labels with a small,
+ * fixed set of values (pipeline stage, action type, outcome) - never
+ * commands, text or coordinates. `METRICS` lists every metric exported.
+ */
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::config::SloObjective;
+use super::slo::{SloStatus, SloTracker};
+use super::ProcessingStats;
+use crate::ai::remote::{http_request, parse_endpoint, HttpTimeouts};
+
+/// Upper bounds of the latency histogram buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Every exported metric: name, type and help text
+pub const METRICS: &[(&str, &str, &str)] = &[
+    ("luna_commands_total", "counter", "Commands finished, by outcome (ok, error)"),
+    ("luna_command_duration_seconds", "histogram", "Time from receiving a command to its result"),
+    ("luna_stage_duration_seconds", "histogram", "Time spent in each pipeline stage (safety, capture, analysis, planning, execution, ...)"),
+    ("luna_input_actions_total", "counter", "Input actions executed, by type (click, type, keys, scroll, wait, wait_for_element, ...) and outcome"),
+    ("luna_action_retries_total", "counter", "Retries of failed input actions, by type"),
+    ("luna_click_strategy_total", "counter", "Planned clicks on elements, by how the point was chosen (mask, bounds)"),
+    ("luna_safety_blocks_total", "counter", "Commands and actions blocked by the safety system"),
+    ("luna_pipeline_skips_total", "counter", "Literal commands that skipped capture and analysis"),
+    ("luna_fingerprint_lookups_total", "counter", "Element re-locations, by result (hit: by appearance, fallback: needed analysis, miss)"),
+    ("luna_escalations_total", "counter", "Thorough re-analyses after the first found no target"),
+    ("luna_pre_analysis_total", "counter", "Commands that found an idle-time analysis, by whether it was still fresh"),
+    ("luna_stale_frames_total", "counter", "Targets that changed between analysis and execution"),
+    ("luna_scroll_tracking_total", "counter", "Scrolls followed by moving the analyzed elements, by result (verified, unverified, unmeasured)"),
+    ("luna_frames_dropped_total", "counter", "Frames skipped by continuous analysis because a newer one arrived"),
+    ("luna_subsystem_init_seconds", "histogram", "Time to set up the core and each subsystem on first use (core, capture, input, focus, session)"),
+    ("luna_watchdog_stalls_total", "counter", "Operations found hung past their stall threshold, by subsystem (capture, inference, input)"),
+    ("luna_watchdog_degraded_total", "counter", "Subsystems put in degraded mode after repeated stalls, by subsystem"),
+    ("luna_slo_compliance_ratio", "gauge", "Fraction of an objective's actions within its latency over its window, by objective"),
+    ("luna_slo_target_ratio", "gauge", "Fraction each latency objective requires, by objective"),
+    ("luna_slo_violated", "gauge", "1 while a latency objective is violated over its window, by objective"),
+];
+
+/// Label set, sorted by name
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<(&'static str, Labels), u64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations at or below each of `LATENCY_BUCKETS`, cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Shared, cheaply cloneable metric store
+#[derive(Clone)]
+pub struct MetricsCollector {
+    registry: Arc<Mutex<Registry>>,
+    /// Counters Luna already keeps in its processing statistics
+    stats: Arc<Mutex<ProcessingStats>>,
+    /// Latency objectives and their recent actions
+    slo: Arc<Mutex<SloTracker>>,
+}
+
+impl MetricsCollector {
+    pub fn new(stats: Arc<Mutex<ProcessingStats>>) -> Self {
+        Self { registry: Arc::default(), stats, slo: Arc::default() }
+    }
+
+    /// Track `objectives` from now on (see `SloTracker::set_objectives`)
+    pub fn set_slo_objectives(&self, objectives: &[SloObjective]) {
+        self.slo.lock().unwrap_or_else(std::sync::PoisonError::into_inner).set_objectives(objectives);
+    }
+
+    /// Record an action against the latency objectives; returns those that
+    /// became violated or recovered (see `SloTracker::record`)
+    pub fn record_slo(&self, kind: &str, latency: Option<Duration>) -> Vec<SloStatus> {
+        self.slo.lock().unwrap_or_else(std::sync::PoisonError::into_inner).record(kind, latency, Instant::now())
+    }
+
+    /// Every latency objective's compliance over its window
+    pub fn slo_statuses(&self) -> Vec<SloStatus> {
+        self.slo.lock().unwrap_or_else(std::sync::PoisonError::into_inner).statuses(Instant::now())
+    }
+
+    pub fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        let mut registry = self.registry.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        *registry.counters.entry((name, sorted(labels))).or_insert(0) += 1;
+    }
+
+    pub fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut registry = self.registry.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let histogram = registry.histograms.entry((name, sorted(labels))).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Current value of a collector-owned counter
+    pub fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+        let registry = self.registry.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        registry.counters.get(&(name, sorted(labels))).copied().unwrap_or(0)
+    }
+
+    /// Times the stages of one command; see `StageTimer`
+    pub fn stage_timer(&self) -> StageTimer {
+        StageTimer { metrics: self.clone(), started: Instant::now(), stage: None, succeeded: false }
+    }
+
+    /// Everything in the Prometheus text exposition format, in `METRICS` order
+    pub fn render(&self) -> String {
+        let stats = self.stats.lock().unwrap_or_else(std::sync::PoisonError::into_inner).clone();
+        let from_stats: [(&str, u64); 4] = [
+            ("luna_safety_blocks_total", stats.safety_blocks),
+            ("luna_pipeline_skips_total", stats.pipeline_skips),
+            ("luna_stale_frames_total", stats.stale_frames),
+            ("luna_frames_dropped_total", stats.frames_dropped),
+        ];
+        let slos = self.slo_statuses();
+        let registry = self.registry.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let mut out = String::new();
+        for (name, kind, help) in METRICS {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            if let Some((_, value)) = from_stats.iter().find(|(n, _)| n == name) {
+                let _ = writeln!(out, "{} {}", name, value);
+                continue;
+            }
+            for slo in &slos {
+                let value = match *name {
+                    "luna_slo_compliance_ratio" => slo.compliance,
+                    "luna_slo_target_ratio" => Some(slo.target),
+                    "luna_slo_violated" => Some(if slo.violated { 1.0 } else { 0.0 }),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    let _ = writeln!(out, "{}{} {}", name, format_labels(&vec![("objective", slo.name.clone())], None), value);
+                }
+            }
+            for ((_, labels), value) in registry.counters.iter().filter(|((n, _), _)| n == name) {
+                let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+            }
+            for ((_, labels), histogram) in registry.histograms.iter().filter(|((n, _), _)| n == name) {
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                    let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some(&bound.to_string())), count);
+                }
+                let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some("+Inf")), histogram.count);
+                let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum);
+                let _ = writeln!(out, "{}_count{} {}", name, format_labels(labels, None), histogram.count);
+            }
+        }
+        out
+    }
+
+    /// Replace this instance's metrics on a push gateway
+    /// (`http://host:port`) under `job`
+    pub fn push(&self, gateway: &str, job: &str) -> Result<()> {
+        let (host, port, base_path) = parse_endpoint(gateway)?;
+        let path = format!("{}/metrics/job/{}", base_path, job);
+        let timeouts = HttpTimeouts { connect: Duration::from_secs(2), read: Duration::from_secs(5) };
+        let headers = [("Content-Type", "text/plain; version=0.0.4".to_string())];
+        let response = http_request(&host, port, "PUT", &path, &headers, self.render().as_bytes(), timeouts)?;
+        if !(200..300).contains(&response.status) {
+            anyhow::bail!("push gateway returned {}: {}", response.status, String::from_utf8_lossy(&response.body));
+        }
+        Ok(())
+    }
+}
+
+/// Records how long each stage of a command takes and, when dropped, the
+/// command's total duration and outcome. Failed commands are counted too:
+/// anything not marked `succeeded` is an error.
+pub struct StageTimer {
+    metrics: MetricsCollector,
+    started: Instant,
+    stage: Option<(&'static str, Instant)>,
+    succeeded: bool,
+}
+
+impl StageTimer {
+    /// Close the current stage and start timing `name`
+    pub fn enter(&mut self, name: &'static str) {
+        self.close_stage();
+        self.stage = Some((name, Instant::now()));
+    }
+
+    pub fn succeeded(&mut self) {
+        self.succeeded = true;
+    }
+
+    fn close_stage(&mut self) {
+        if let Some((stage, since)) = self.stage.take() {
+            self.metrics.observe("luna_stage_duration_seconds", &[("stage", stage)], since.elapsed());
+        }
+    }
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        self.close_stage();
+        self.metrics.observe("luna_command_duration_seconds", &[], self.started.elapsed());
+        let outcome = if self.succeeded { "ok" } else { "error" };
+        self.metrics.increment("luna_commands_total", &[("outcome", outcome)]);
+    }
+}
+
+fn sorted(labels: &[(&'static str, &str)]) -> Labels {
+    let mut labels: Labels = labels.iter().map(|(name, value)| (*name, value.to_string())).collect();
+    labels.sort();
+    labels
+}
+
+/// `{a="1",le="0.5"}`, or nothing without labels
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() { String::new() } else { format!("{{{}}}", pairs.join(",")) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_histograms_and_stats() {
+        let stats = Arc::new(Mutex::new(ProcessingStats { safety_blocks: 2, ..ProcessingStats::default() }));
+        let metrics = MetricsCollector::new(stats);
+        metrics.increment("luna_input_actions_total", &[("type", "click"), ("outcome", "ok")]);
+        metrics.increment("luna_input_actions_total", &[("outcome", "ok"), ("type", "click")]);
+        metrics.observe("luna_stage_duration_seconds", &[("stage", "analysis")], Duration::from_millis(30));
+        metrics.observe("luna_stage_duration_seconds", &[("stage", "analysis")], Duration::from_secs(20));
+
+        let text = metrics.render();
+        assert!(text.contains("luna_safety_blocks_total 2\n"), "{}", text);
+        assert!(text.contains("luna_input_actions_total{outcome=\"ok\",type=\"click\"} 2\n"), "{}", text);
+        assert!(text.contains("luna_stage_duration_seconds_bucket{stage=\"analysis\",le=\"0.025\"} 0\n"));
+        assert!(text.contains("luna_stage_duration_seconds_bucket{stage=\"analysis\",le=\"0.05\"} 1\n"));
+        assert!(text.contains("luna_stage_duration_seconds_bucket{stage=\"analysis\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("luna_stage_duration_seconds_count{stage=\"analysis\"} 2\n"));
+        assert!(text.contains("# TYPE luna_command_duration_seconds histogram\n"));
+    }
+
+    #[test]
+    fn test_stage_timer_counts_failed_commands() {
+        let metrics = MetricsCollector::new(Arc::default());
+        {
+            let mut timer = metrics.stage_timer();
+            timer.enter("safety");
+            timer.enter("planning");
+        }
+        {
+            let mut timer = metrics.stage_timer();
+            timer.enter("safety");
+            timer.succeeded();
+        }
+        assert_eq!(metrics.counter("luna_commands_total", &[("outcome", "error")]), 1);
+        assert_eq!(metrics.counter("luna_commands_total", &[("outcome", "ok")]), 1);
+        let text = metrics.render();
+        assert!(text.contains("luna_stage_duration_seconds_count{stage=\"safety\"} 2\n"), "{}", text);
+        assert!(text.contains("luna_command_duration_seconds_count 2\n"), "{}", text);
+    }
+}
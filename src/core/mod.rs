@@ -5,24 +5,115 @@
  */
 
 use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use log::{info, debug, warn, error};
 
-use crate::ai::AICoordinator;
+use crate::ai::training::{Correction, ExportRecord, TrainingExporter};
+use crate::ai::fingerprint::{self, ElementFingerprint, FingerprintMatch};
+use crate::ai::remote::RemoteDetector;
+use crate::ai::clarification::Clarification;
+use crate::ai::raw::RawOutputs;
+use crate::ai::suggest;
+use crate::ai::{AICoordinator, ConfidenceThresholds, ElementDetector, ReconfigureReport};
 use crate::input::{
-    ActionType, BasicSafetyChecker, InputAction, InputController, MouseButton, ScrollDirection,
-    Target,
+    ActionType, BasicSafetyChecker, InputAction, InputController, MouseButton, Pacing,
+    RiskLevel, SafetyChecker, ScrollDirection, Target,
 };
-use crate::utils::image_processing::Image;
-use crate::vision::screen_capture::{CaptureConfig, ScreenCapture};
+use crate::overlay::inspector::{Inspector, InspectorFrame};
+use crate::utils::geometry::{self, Polygon, Rectangle};
+use crate::utils::image_processing::{self, Image};
+use crate::utils::locale::Locale;
+use crate::vision::frame_channel;
+use crate::vision::screen_capture::{CaptureConfig, DisplayInfo, ScreenCapture};
+use crate::vision::color::{self, Rgb};
 
+pub mod accessibility;
+pub mod anchors;
+pub mod api;
+pub mod archive;
+pub mod capabilities;
+pub mod confirmation;
+pub mod debugger;
+pub mod diagnose;
+pub mod displays;
+pub mod element_stats;
+pub mod environment;
+pub mod explore;
+pub mod frames;
+pub mod instance;
+pub mod language_packs;
+pub mod metrics;
+pub mod provenance;
 pub mod config;
 pub mod error;
+pub mod focus;
+pub mod hooks;
+pub mod health;
+pub mod query;
+pub mod replay;
+pub mod resources;
+pub mod restore;
+pub mod review;
+pub mod handle;
 pub mod safety;
+pub mod schema;
+pub mod scratch;
+pub mod sandbox;
+pub mod selection;
+pub mod session;
+pub mod slo;
+pub mod spy;
+pub mod storage;
+pub mod tracker;
+pub mod typing;
+pub mod vnc;
+pub mod watchdog;
 
 pub use error::LunaError;
 pub use config::LunaConfig;
+pub use handle::{CancelToken, LunaHandle, SpeedControl};
+
+/// Attribute holding a top-level window's stacking order, 0 = frontmost
+pub const Z_ORDER_ATTRIBUTE: &str = "z_order";
+/// Attribute holding the fraction of an element hidden by windows in front of it
+pub const OCCLUDED_ATTRIBUTE: &str = "occluded";
+/// Attribute holding the fraction of an element inside the screen and the
+/// container that clips it, when part of it is scrolled or cut off
+pub const VISIBLE_ATTRIBUTE: &str = "visible";
+/// Attribute marking an open modal dialog and everything inside it ("true")
+pub const MODAL_ATTRIBUTE: &str = "modal";
+/// Attribute holding the name of an element's main color ("red", "gray", ...)
+pub const COLOR_ATTRIBUTE: &str = "color";
+/// Attribute holding the outline of a detected colored shape ("circle", "square", "rectangle")
+pub const SHAPE_ATTRIBUTE: &str = "shape";
+/// Attribute holding how reliably clicking the element has worked before (0.0 - 1.0)
+pub const RELIABILITY_ATTRIBUTE: &str = "reliability";
+/// Attribute holding the text of the label that names a control, e.g. the
+/// "Email" beside an empty text field
+pub const LABEL_ATTRIBUTE: &str = "label";
+
+/// Element types whose content ends at their edge, like a scrolled list
+const CLIPPING_TYPES: [&str; 5] = ["window", "dialog", "panel", "list", "menu"];
+/// Share of an element a container must hold to be the one clipping it
+const MIN_CLIPPED_OVERLAP: f64 = 0.1;
+
+/// Element types named by a label beside or above them rather than their own text
+const LABELLED_TYPES: [&str; 7] = ["textfield", "textbox", "input", "combobox", "dropdown", "slider", "toggle"];
+
+/// How close (px) the click point found inside a shape is to the deepest one
+const MASK_POLE_PRECISION: f64 = 0.5;
+/// Pixels a click must stay inside a shape's edge to count as inside it
+const MIN_MASK_CLEARANCE: f64 = 1.0;
+
+/// How often semantic waits capture the screen to check their condition
+const WAIT_POLL_MS: u64 = 100;
+/// How often `wait_for_screen_idle` compares frames
+const IDLE_POLL_MS: u64 = 50;
+/// Commands remembered for `suggest_commands`
+const SUGGESTION_HISTORY_LEN: usize = 20;
 
 /// Screen analysis result
 #[derive(Debug, Clone)]
@@ -31,6 +122,188 @@ pub struct ScreenAnalysis {
     pub confidence: f32,
     pub processing_time_ms: u64,
     pub screen_size: (u32, u32),
+    pub context: AnalysisContext,
+}
+
+/// What is known about the screen beyond its elements
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisContext {
+    /// Where typed text would go, when the command needed to know
+    pub caret: Option<crate::vision::caret::Caret>,
+}
+
+impl ScreenAnalysis {
+    /// Set each element's `parent` and `children` from bounds containment
+    pub fn link_hierarchy(&mut self) {
+        let bounds: Vec<Rectangle> = self.elements.iter().map(|e| Rectangle::from(&e.bounds)).collect();
+        let parents = crate::vision::hierarchy::containment_parents(&bounds);
+        for element in &mut self.elements {
+            element.children.clear();
+        }
+        for (index, parent) in parents.into_iter().enumerate() {
+            self.elements[index].parent = parent;
+            if let Some(parent) = parent {
+                self.elements[parent].children.push(index);
+            }
+        }
+    }
+
+    /// The top-level element `index` is nested in (itself when it has no parent)
+    pub fn root(&self, index: usize) -> usize {
+        let mut current = index;
+        while let Some(parent) = self.elements.get(current).and_then(|e| e.parent) {
+            current = parent;
+        }
+        current
+    }
+
+    /// Top-level windows stacked in front of the one `index` is in, by
+    /// `z_order`. Empty when either window's stacking order is unknown.
+    pub fn covering_windows(&self, index: usize) -> Vec<usize> {
+        let root = self.root(index);
+        let z_order = |i: usize| self.elements[i].attributes.get(Z_ORDER_ATTRIBUTE)?.parse::<u32>().ok();
+        let Some(depth) = z_order(root) else {
+            return Vec::new();
+        };
+        (0..self.elements.len())
+            .filter(|&i| i != root && self.elements[i].parent.is_none())
+            .filter(|&i| z_order(i).is_some_and(|z| z < depth))
+            .collect()
+    }
+
+    /// Record in each element's `occluded` attribute how much of it windows
+    /// in front hide. Elements with nothing in front are left unmarked.
+    pub fn mark_occlusion(&mut self) {
+        for index in 0..self.elements.len() {
+            let covering: Vec<Rectangle> = self.covering_windows(index)
+                .into_iter()
+                .map(|i| Rectangle::from(&self.elements[i].bounds))
+                .collect();
+            let element = &mut self.elements[index];
+            element.attributes.remove(OCCLUDED_ATTRIBUTE);
+            let hidden = crate::vision::occlusion::covered_fraction(&Rectangle::from(&element.bounds), &covering);
+            if hidden > 0.0 {
+                element.attributes.insert(OCCLUDED_ATTRIBUTE.to_string(), format!("{:.2}", hidden));
+            }
+        }
+    }
+
+    /// Where element `index` can be seen: the screen, cut down to the
+    /// smallest window, panel or list holding part of it, then to that
+    /// container's own clip, and so on outwards. Containers in another
+    /// top-level window only cover it (see `mark_occlusion`) and don't count.
+    /// `None` when that leaves nothing.
+    pub fn clip_area(&self, index: usize) -> Option<Rectangle> {
+        let (width, height) = self.screen_size;
+        let mut area = (width > 0 && height > 0).then(|| Rectangle::new(0.0, 0.0, width as f64, height as f64));
+        let mut current = Rectangle::from(&self.elements.get(index)?.bounds);
+        let root = self.root(index);
+        loop {
+            let overlap = |r: &Rectangle| r.intersection(&current).map_or(0.0, |o| o.area());
+            let container = (0..self.elements.len())
+                .filter(|&i| CLIPPING_TYPES.contains(&self.elements[i].element_type.as_str()))
+                .filter(|&i| root == index || self.root(i) == root)
+                .map(|i| Rectangle::from(&self.elements[i].bounds))
+                .filter(|r| r.area() > current.area() && overlap(r) >= current.area() * MIN_CLIPPED_OVERLAP)
+                .min_by(|a, b| a.area().total_cmp(&b.area()));
+            let Some(container) = container else {
+                break;
+            };
+            area = Some(match area {
+                Some(area) => area.intersection(&container)?,
+                None => container,
+            });
+            current = container;
+        }
+        area.or_else(|| Some(Rectangle::from(&self.elements[index].bounds)))
+    }
+
+    /// Record in each element's `visible` attribute how much of it lies in
+    /// its `clip_area`. Fully visible elements are left unmarked.
+    pub fn mark_visibility(&mut self) {
+        for index in 0..self.elements.len() {
+            let bounds = Rectangle::from(&self.elements[index].bounds);
+            let inside = self.clip_area(index).and_then(|clip| clip.intersection(&bounds)).map_or(0.0, |r| r.area());
+            let element = &mut self.elements[index];
+            element.attributes.remove(VISIBLE_ATTRIBUTE);
+            if bounds.area() > 0.0 && inside < bounds.area() {
+                element.attributes.insert(VISIBLE_ATTRIBUTE.to_string(), format!("{:.2}", inside / bounds.area()));
+            }
+        }
+    }
+
+    /// Move the elements shown in `area` up `dy` pixels, as scrolling its
+    /// content does, and mark their visibility again. Returns the indices
+    /// of the elements moved.
+    pub fn scroll_content(&mut self, area: &Rectangle, dy: i32) -> Vec<usize> {
+        let moved: Vec<usize> = (0..self.elements.len())
+            .filter(|&i| {
+                let bounds = Rectangle::from(&self.elements[i].bounds);
+                let overlap = bounds.intersection(area).map_or(0.0, |r| r.area());
+                bounds.area() < area.area() && overlap >= bounds.area() * MIN_CLIPPED_OVERLAP
+            })
+            .collect();
+        for &index in &moved {
+            let element = &mut self.elements[index];
+            element.bounds.y -= dy;
+            if let Some(shape) = element.shape.as_mut() {
+                for point in &mut shape.points {
+                    point.y -= dy as f64;
+                }
+            }
+        }
+        self.mark_visibility();
+        moved
+    }
+
+    /// Tag `modal` and every element nested in it with the `modal`
+    /// attribute, clearing it everywhere else
+    pub fn mark_modal(&mut self, modal: Option<usize>) {
+        for element in &mut self.elements {
+            element.attributes.remove(MODAL_ATTRIBUTE);
+        }
+        for index in modal.map(|modal| self.subtree(modal)).unwrap_or_default() {
+            self.elements[index].attributes.insert(MODAL_ATTRIBUTE.to_string(), "true".to_string());
+        }
+    }
+
+    /// The open modal dialog: the outermost element tagged `modal`
+    pub fn active_modal(&self) -> Option<usize> {
+        let modal = |i: usize| self.elements[i].attributes.contains_key(MODAL_ATTRIBUTE);
+        (0..self.elements.len()).find(|&i| modal(i) && self.elements[i].parent.is_none_or(|parent| !modal(parent)))
+    }
+
+    /// Give each control without a `label` attribute the text of the label
+    /// it pairs with (see `vision::labels::pair_labels`)
+    pub fn link_labels(&mut self) {
+        let labels: Vec<usize> = (0..self.elements.len())
+            .filter(|&i| self.elements[i].element_type == "label" && self.elements[i].text.as_deref().is_some_and(|t| !t.trim().is_empty()))
+            .collect();
+        let controls: Vec<usize> = (0..self.elements.len())
+            .filter(|&i| LABELLED_TYPES.contains(&self.elements[i].element_type.as_str()))
+            .filter(|&i| !self.elements[i].attributes.contains_key(LABEL_ATTRIBUTE))
+            .collect();
+        let bounds = |indices: &[usize]| -> Vec<Rectangle> {
+            indices.iter().map(|&i| Rectangle::from(&self.elements[i].bounds)).collect()
+        };
+        let pairs = crate::vision::labels::pair_labels(&bounds(&labels), &bounds(&controls));
+        for (control, label) in controls.into_iter().zip(pairs) {
+            if let Some(text) = label.and_then(|l| self.elements[labels[l]].text.clone()) {
+                self.elements[control].attributes.insert(LABEL_ATTRIBUTE.to_string(), text);
+            }
+        }
+    }
+
+    /// `index` followed by every element nested inside it
+    pub fn subtree(&self, index: usize) -> Vec<usize> {
+        let mut indices = vec![index];
+        let mut next = 0;
+        while let Some(&current) = indices.get(next) {
+            indices.extend(self.elements.get(current).map_or(&[][..], |e| &e.children[..]));
+            next += 1;
+        }
+        indices
+    }
 }
 
 /// Detected screen element
@@ -38,13 +311,83 @@ pub struct ScreenAnalysis {
 pub struct ScreenElement {
     pub element_type: String,
     pub bounds: ElementBounds,
+    /// Exact outline when not an axis-aligned box; clicks land inside it
+    pub shape: Option<Polygon>,
     pub confidence: f32,
     pub text: Option<String>,
     pub attributes: std::collections::HashMap<String, String>,
+    /// Index in `ScreenAnalysis::elements` of the smallest element containing this one
+    pub parent: Option<usize>,
+    /// Indices of the elements whose parent is this one
+    pub children: Vec<usize>,
+}
+
+impl ScreenElement {
+    /// Where a click on this element lands (see `click_target`)
+    pub fn click_point(&self) -> (i32, i32) {
+        self.click_target().0
+    }
+
+    /// Where a click on this element lands and how that was decided. With a
+    /// shape (a segmentation mask's outline, a rotated box) it is the point
+    /// deepest inside it, so round and irregular controls aren't clicked on
+    /// a transparent corner; the rounded pixel is checked against the shape
+    /// before it is used. Without a usable shape it is the bounds' center.
+    pub fn click_target(&self) -> ((i32, i32), ClickStrategy) {
+        let inside_mask = self.shape.as_ref().and_then(|shape| {
+            let pole = shape.pole_of_inaccessibility(MASK_POLE_PRECISION)?;
+            let pixel = (pole.x.round() as i32, pole.y.round() as i32);
+            let clearance = shape.signed_distance(&geometry::Point::new(pixel.0 as f64, pixel.1 as f64));
+            (clearance >= MIN_MASK_CLEARANCE).then_some(pixel)
+        });
+        match inside_mask {
+            Some(point) => (point, ClickStrategy::Mask),
+            None => (self.bounds.center(), ClickStrategy::Bounds),
+        }
+    }
+
+    /// Text of the label naming this control, from the detector or `ScreenAnalysis::link_labels`
+    pub fn label(&self) -> Option<&str> {
+        self.attributes.get(LABEL_ATTRIBUTE).map(String::as_str)
+    }
+
+    /// Fraction hidden behind windows in front, as set by `ScreenAnalysis::mark_occlusion`
+    pub fn occlusion(&self) -> f64 {
+        self.attributes.get(OCCLUDED_ATTRIBUTE).and_then(|v| v.parse().ok()).unwrap_or(0.0)
+    }
+
+    /// Fraction inside the screen and its clipping container, as set by
+    /// `ScreenAnalysis::mark_visibility`
+    pub fn visibility(&self) -> f64 {
+        self.attributes.get(VISIBLE_ATTRIBUTE).and_then(|v| v.parse().ok()).unwrap_or(1.0)
+    }
+
+    /// Click reliability from the element's history; 0.5 when it has none
+    pub fn reliability(&self) -> f64 {
+        self.attributes.get(RELIABILITY_ATTRIBUTE).and_then(|v| v.parse().ok()).unwrap_or(0.5)
+    }
+}
+
+/// How `ScreenElement::click_target` chose a click point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClickStrategy {
+    /// Deepest point inside the element's shape
+    Mask,
+    /// Center of the bounding box: no shape, or none with room to click in
+    Bounds,
+}
+
+impl ClickStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClickStrategy::Mask => "mask",
+            ClickStrategy::Bounds => "bounds",
+        }
+    }
 }
 
 /// Element bounds rectangle
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ElementBounds {
     pub x: i32,
     pub y: i32,
@@ -52,6 +395,160 @@ pub struct ElementBounds {
     pub height: i32,
 }
 
+impl ElementBounds {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Center point, rounded toward the top-left
+    pub fn center(&self) -> (i32, i32) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+}
+
+impl From<&Rectangle> for ElementBounds {
+    fn from(rect: &Rectangle) -> Self {
+        Self {
+            x: rect.x.round() as i32,
+            y: rect.y.round() as i32,
+            width: rect.width.round() as i32,
+            height: rect.height.round() as i32,
+        }
+    }
+}
+
+impl From<&ElementBounds> for Rectangle {
+    fn from(bounds: &ElementBounds) -> Self {
+        Rectangle::new(bounds.x as f64, bounds.y as f64, bounds.width as f64, bounds.height as f64)
+    }
+}
+
+/// Per-command execution options
+#[derive(Debug, Clone, Default)]
+pub struct ExecuteOptions {
+    /// Only consider elements whose center lies inside this region
+    pub region_constraint: Option<ElementBounds>,
+    /// Always capture and analyze, even for literal-coordinate and keyboard commands
+    pub force_full_pipeline: bool,
+    /// Stop before the next action once this is cancelled
+    pub cancel: Option<CancelToken>,
+    /// Where the command came from; selects the disruption policy
+    pub source: CommandSource,
+    /// Plan and validate the actions but do not execute them
+    pub dry_run: bool,
+    /// Speed multiplier for this command instead of the current one
+    pub speed: Option<f64>,
+    /// Refuse the command when a planned action is riskier than this
+    pub max_risk: Option<crate::input::RiskLevel>,
+    /// Who sent the command, e.g. an API client's name; recorded in the
+    /// confirmation audit and the transcript
+    pub client: Option<String>,
+    /// Put the cursor and focus back afterwards, instead of
+    /// `input.restore_after_command`
+    pub restore: Option<bool>,
+}
+
+/// Origin of a command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommandSource {
+    /// Typed or sent directly by the user
+    #[default]
+    Interactive,
+    /// Run from a schedule or timer
+    Scheduled,
+    /// Triggered by a screen or event watcher
+    Watcher,
+    /// Sent by a client of the command API
+    Api,
+}
+
+impl CommandSource {
+    pub const ALL: [CommandSource; 4] =
+        [CommandSource::Interactive, CommandSource::Scheduled, CommandSource::Watcher, CommandSource::Api];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandSource::Interactive => "interactive",
+            CommandSource::Scheduled => "scheduled",
+            CommandSource::Watcher => "watcher",
+            CommandSource::Api => "api",
+        }
+    }
+}
+
+impl ExecuteOptions {
+    pub fn with_region(mut self, region: ElementBounds) -> Self {
+        self.region_constraint = Some(region);
+        self
+    }
+}
+
+/// Outcome of one processed command
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    /// Actions that were executed, in order
+    pub actions: Vec<LunaAction>,
+    /// Retries each executed action needed under its `retry` policy,
+    /// parallel to `actions`; empty for a dry run
+    pub retries: Vec<u32>,
+    /// Capture and analysis were skipped because the command was fully literal
+    pub pipeline_skipped: bool,
+    /// Actions were planned and validated but not executed
+    pub dry_run: bool,
+    pub processing_time_ms: u64,
+    /// CPU, memory and GPU used by the command, when profiling is enabled
+    pub resources: Option<resources::ResourceUsage>,
+    /// Set when the first analysis found no target and a thorough one ran
+    pub escalation: Option<Escalation>,
+    /// Every detection candidate and the inference server's extras, when
+    /// `raw_outputs.include` is set and the screen was analyzed
+    pub raw_outputs: Option<RawOutputs>,
+    /// Identifies the command in the provenance transcript
+    pub command_id: String,
+    /// Evidence behind each action, parallel to `actions`
+    pub provenance: Vec<provenance::ActionProvenance>,
+    /// How the cursor and focus were put back, when restoring was asked for
+    pub restoration: Vec<restore::RestoreStep>,
+    /// What each field typed into read as afterwards (see `typing`)
+    pub typing: Vec<typing::TypingReport>,
+    /// Attached by pipeline hooks
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// A command re-analyzed with more effort because the first analysis found no target
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Escalation {
+    /// Why the first plan failed
+    pub reason: String,
+    /// Elements found by the first and the thorough analysis
+    pub elements_before: usize,
+    pub elements_after: usize,
+    /// Time the thorough analysis and re-planning took
+    pub elapsed_ms: u64,
+}
+
+impl std::fmt::Display for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "re-analyzed thoroughly ({}): {} -> {} elements in {}ms",
+            self.reason, self.elements_before, self.elements_after, self.elapsed_ms)
+    }
+}
+
+/// Resource usage of one past command
+#[derive(Debug, Clone)]
+pub struct CommandResources {
+    pub command: String,
+    pub usage: resources::ResourceUsage,
+}
+
 /// Action to be executed by Luna
 #[derive(Debug, Clone)]
 pub enum LunaAction {
@@ -65,6 +562,70 @@ pub enum LunaAction {
     Scroll { direction: String, amount: i32 },
     /// Wait for specified time
     Wait { milliseconds: u64 },
+    /// Capture the screen, or `region` of it, as PNG
+    Screenshot { region: Option<ElementBounds>, target: ShotTarget },
+    /// Put the image at `path` on the clipboard and paste it
+    PasteImage { path: PathBuf },
+    /// Wait until an element matching `query` (as in `Luna::find`) is on screen
+    WaitForElement { query: String, timeout_ms: u64 },
+    /// Wait until nothing matches `text` any more; matched like a `find`
+    /// query, so an element type ("spinner") works too
+    WaitForTextGone { text: String, timeout_ms: u64 },
+    /// Wait until the screen has not changed for `quiet_ms`
+    WaitForScreenIdle { quiet_ms: u64, timeout_ms: u64 },
+    /// Click while holding `modifiers` ("shift", "ctrl", "cmd"), as when
+    /// extending a selection
+    ModifierClick { x: i32, y: i32, modifiers: Vec<String> },
+    /// Press and hold `key` until a matching `KeyUp`, as in "hold shift
+    /// while dragging". Keys still held when a command ends are released.
+    KeyDown { key: String },
+    KeyUp { key: String },
+    /// Move the pointer to (x, y) without clicking, e.g. so a scroll goes to
+    /// the list under it
+    Hover { x: i32, y: i32 },
+}
+
+impl LunaAction {
+    /// Every `kind`, as named in the `luna.do` schema
+    pub const KINDS: [&'static str; 14] = [
+        "click", "type", "keys", "scroll", "wait", "screenshot", "paste_image", "wait_for_element",
+        "wait_for_text_gone", "wait_for_screen_idle", "modifier_click", "key_down", "key_up", "hover",
+    ];
+
+    /// Type of action, as named in the `luna.do` schema
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LunaAction::Click { .. } => "click",
+            LunaAction::Type { .. } => "type",
+            LunaAction::KeyCombo { .. } => "keys",
+            LunaAction::Scroll { .. } => "scroll",
+            LunaAction::Wait { .. } => "wait",
+            LunaAction::Screenshot { .. } => "screenshot",
+            LunaAction::PasteImage { .. } => "paste_image",
+            LunaAction::WaitForElement { .. } => "wait_for_element",
+            LunaAction::WaitForTextGone { .. } => "wait_for_text_gone",
+            LunaAction::WaitForScreenIdle { .. } => "wait_for_screen_idle",
+            LunaAction::ModifierClick { .. } => "modifier_click",
+            LunaAction::KeyDown { .. } => "key_down",
+            LunaAction::KeyUp { .. } => "key_up",
+            LunaAction::Hover { .. } => "hover",
+        }
+    }
+
+    /// Where the action clicks, if it is a click
+    pub fn click_point(&self) -> Option<(i32, i32)> {
+        match self {
+            LunaAction::Click { x, y } | LunaAction::ModifierClick { x, y, .. } => Some((*x, *y)),
+            _ => None,
+        }
+    }
+}
+
+/// Where a `LunaAction::Screenshot` goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShotTarget {
+    Clipboard,
+    File(PathBuf),
 }
 
 /// Luna event for coordination
@@ -72,24 +633,54 @@ pub enum LunaAction {
 pub enum LunaEvent {
     /// Command received from user
     CommandReceived { command: String },
+    /// `update_config` finished, applied or rolled back
+    ConfigApplied { report: config::ConfigReport },
     /// Screen analysis completed
     AnalysisComplete { analysis: ScreenAnalysis },
+    /// Elements added, removed, moved or re-read since the previous
+    /// analysis, under stable IDs (see `tracker`); not sent when nothing changed
+    ElementsChanged { delta: tracker::ElementDelta },
     /// Actions planned
     ActionsPlanned { actions: Vec<LunaAction> },
     /// Action executed
     ActionExecuted { action: LunaAction, success: bool },
+    /// User corrected the element LUNA chose
+    CorrectionReported { command: String, label: String, exported: bool },
+    /// The screen around an action's target changed since it was analyzed
+    StaleFrame { action: LunaAction, changed_fraction: f64 },
+    /// A store is close to (or over) its disk quota
+    StorageQuotaWarning { store: storage::StoreKind, used_bytes: u64, quota_bytes: u64 },
+    /// A command was ambiguous; answer with `Luna::answer_clarification`
+    ClarificationNeeded { command: String, clarification: Clarification },
+    /// A doubtful detection was queued for review (see `Luna::review_queue`)
+    ReviewQueued { id: u64, reason: review::ReviewReason, pending: usize },
+    /// The session was locked or unlocked, or the secure desktop came up;
+    /// commands and watchers pause while it is not active
+    SystemState { state: session::SessionState },
+    /// A display was plugged in, unplugged or resized; analyses from before
+    /// no longer apply
+    DisplayChanged { displays: Vec<DisplayInfo>, removed: Vec<DisplayInfo>, added: Vec<DisplayInfo> },
+    /// A subsystem hung, came back, or went into or out of degraded mode
+    /// (see `watchdog`)
+    Watchdog { event: watchdog::WatchdogEvent },
+    /// A latency objective became violated over its window, or recovered
+    /// (see `slo`)
+    Slo { status: slo::SloStatus },
     /// Error occurred
     Error { error: String },
 }
 
+/// Callback invoked for every emitted `LunaEvent`
+type EventCallback = Box<dyn Fn(LunaEvent) + Send + Sync>;
+
 /// Main Luna coordinator
 pub struct Luna {
     /// AI coordinator for screen analysis
     ai_coordinator: AICoordinator,
-    /// Screen capture system
-    screen_capture: ScreenCapture,
-    /// Input system for executing actions
-    input_system: InputController,
+    /// Screen capture system, built on first use (see `ensure_capture`)
+    screen_capture: Option<ScreenCapture>,
+    /// Input system for executing actions, built on first use (see `ensure_input`)
+    input_system: Option<InputController>,
     /// Safety system for validating commands
     safety_system: Arc<safety::SafetySystem>,
     /// Configuration
@@ -97,7 +688,114 @@ pub struct Luna {
     /// Processing statistics
     stats: Arc<Mutex<ProcessingStats>>,
     /// Event subscribers
-    event_subscribers: Arc<Mutex<Vec<Box<dyn Fn(LunaEvent) + Send + Sync>>>>,
+    event_subscribers: Arc<Mutex<Vec<EventCallback>>>,
+    /// Disk quota enforcement for transcripts, recordings and caches
+    storage: storage::StorageManager,
+    /// Temporary files of running operations, removed when they don't finish
+    scratch: scratch::ScratchRegistry,
+    /// When quotas were last checked automatically
+    last_storage_check: Option<Instant>,
+    /// Most recent captured frame, kept for correction export
+    last_frame: Option<Image>,
+    /// The next click target's region as it looked once the previous
+    /// action settled; the stale-frame guard compares against it rather than
+    /// the analyzed frame, so a plan's own effects don't count as stale
+    stale_reference: Option<(Rectangle, Image)>,
+    /// Analysis made while idle, for the next command to start from
+    warm: Option<WarmAnalysis>,
+    /// Lazily created exporter for correction samples
+    training_exporter: Option<TrainingExporter>,
+    /// Lazily opened frame archive in the recordings store
+    archive: Option<archive::ArchiveWriter>,
+    /// Frame analyzed for the current command, archived once it has an ID
+    unarchived: Option<Image>,
+    /// Presentation and do-not-disturb detection for the disruption rule,
+    /// built on first use
+    focus_monitor: Option<focus::FocusMonitor>,
+    /// Reads and restores the cursor and focused window around commands
+    desktop: Box<dyn restore::DesktopProbe + Send>,
+    /// Screen lock and secure desktop detection, built on first use
+    session_monitor: Option<session::SessionMonitor>,
+    /// Display layout watching, built on first use
+    display_monitor: Option<displays::DisplayMonitor>,
+    /// Remote desktop standing in for this one when `vnc.enabled`, built on first use
+    vnc: Option<vnc::VncClient>,
+    /// Resource usage of recent commands, oldest first
+    resource_history: std::collections::VecDeque<CommandResources>,
+    /// What works on this machine, probed at startup
+    capabilities: capabilities::Capabilities,
+    /// Elements the user taught by name
+    anchors: anchors::AnchorStore,
+    /// This process's claim on the machine-wide input lease
+    input_lease: instance::InputLease,
+    /// Recent analyses for the inspector panel
+    inspector: Inspector,
+    /// Out-of-band approval of high-risk actions
+    confirmations: confirmation::ConfirmationGate,
+    /// Question asked about the last command, waiting for an answer
+    pending_clarification: Option<PendingClarification>,
+    /// Answered question and the chosen element, while its command re-runs
+    answered: Option<(PendingClarification, usize)>,
+    /// Current speed multiplier, adjustable while running
+    speed: SpeedControl,
+    /// Pause, single-step and breakpoints before each action
+    debugger: debugger::StepControl,
+    /// Thorough re-analysis done for the command being planned
+    escalation: Option<Escalation>,
+    /// Click outcomes per element fingerprint
+    element_history: element_stats::ElementHistory,
+    /// Doubtful detections waiting for the user, and past verdicts
+    review: review::ReviewQueue,
+    /// Fingerprints of the elements the planned clicks land on, by click point
+    click_targets: Vec<((i32, i32), ElementFingerprint)>,
+    /// Evidence behind the planned actions, parallel to them
+    provenance: Vec<provenance::ActionProvenance>,
+    /// Callbacks around analysis and execution, and what they attached to
+    /// the running command
+    hooks: hooks::Hooks,
+    hook_metadata: BTreeMap<String, String>,
+    /// Counters and latency histograms for the metrics endpoint
+    metrics: metrics::MetricsCollector,
+    /// How long the core and each subsystem took to come up, in that order
+    startup: Vec<(&'static str, Duration)>,
+    /// Stable IDs for detected elements across analyses
+    tracker: tracker::ElementTracker,
+    /// The last full-screen analysis, for suggestions
+    latest_analysis: Option<ScreenAnalysis>,
+    /// Commands that ran and the window each ran in, oldest first
+    recent_commands: std::collections::VecDeque<(String, Option<String>)>,
+    /// Window the last suggestions were made for
+    suggested_for: Option<Option<String>>,
+    /// Busy state of capture, inference and input, for the watchdog
+    heartbeats: watchdog::Heartbeats,
+    /// Thread reporting hung subsystems; `None` when disabled
+    watchdog: Option<watchdog::Watchdog>,
+}
+
+/// Parts of a new configuration that can fail to build, built before any is applied
+struct StagedConfig {
+    remote: Option<RemoteDetector>,
+    storage: storage::StorageManager,
+    anchors: anchors::AnchorStore,
+    element_history: element_stats::ElementHistory,
+    review: review::ReviewQueue,
+}
+
+/// A screen analyzed ahead of the command that will need it
+struct WarmAnalysis {
+    frame: Image,
+    analysis: ScreenAnalysis,
+    window: Option<String>,
+    at: Instant,
+}
+
+/// An ambiguous command with the analysis its question was asked about
+struct PendingClarification {
+    command: String,
+    analysis: ScreenAnalysis,
+    /// Frame the analysis was made on, for exporting the answer as a label
+    frame: Option<Image>,
+    clarification: Clarification,
 }
 
 /// Processing statistics
@@ -106,32 +804,370 @@ pub struct ProcessingStats {
     pub commands_processed: u64,
     pub actions_executed: u64,
     pub safety_blocks: u64,
+    /// Commands that took the direct path without capture or analysis
+    pub pipeline_skips: u64,
+    /// Actions whose target region changed between analysis and execution
+    pub stale_frames: u64,
+    /// Frames skipped by continuous analysis because a newer one arrived
+    pub frames_dropped: u64,
     pub total_processing_time_ms: u64,
     pub average_processing_time_ms: f64,
+    /// CPU time spent in profiled commands
+    pub total_cpu_time_ms: u64,
+    /// Highest resident memory seen during any command
+    pub peak_rss_bytes: u64,
+    /// Highest GPU memory seen during any command
+    pub peak_gpu_memory_bytes: u64,
+    /// CPU time per pipeline phase, summed over all commands
+    pub phase_cpu_time_ms: std::collections::HashMap<&'static str, u64>,
 }
 
 impl Luna {
     /// Create a new Luna instance with the given configuration
+    ///
+    /// Only what every use needs is set up here. Screen capture, input and
+    /// the session and focus monitors come up on first use, so a program
+    /// that only analyzes image files never touches the screen or the input
+    /// devices; `ensure_capture` and `ensure_input` bring them up ahead of
+    /// time.
     pub fn new(config: LunaConfig) -> Result<Self> {
-        Ok(Self {
-            ai_coordinator: AICoordinator::new(),
-            screen_capture: ScreenCapture::new(CaptureConfig::default()),
-            input_system: InputController::new(Box::new(BasicSafetyChecker::new())),
+        let started = Instant::now();
+        let capabilities = capabilities::Capabilities::probe(&config);
+        debug!("Capabilities:\n{}", capabilities.summary());
+        let storage = storage::StorageManager::from_config(&config.storage)?;
+        let scratch = scratch::ScratchRegistry::new(storage.root());
+        match scratch.sweep() {
+            Ok(swept) if swept.files > 0 => {
+                info!("Removed {} temporary file(s) ({} bytes) left by earlier runs", swept.files, swept.bytes)
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Could not sweep temporary files left by earlier runs: {}", e),
+        }
+        let input_lease = acquire_input_lease(&config);
+        let stats = Arc::new(Mutex::new(ProcessingStats::default()));
+        let mut luna = Self {
+            input_lease,
+            inspector: Inspector::new(config.inspector.history),
+            anchors: anchors::AnchorStore::open(storage.root().join(anchors::ANCHOR_FILE))?,
+            element_history: element_stats::ElementHistory::open(
+                storage.root().join(element_stats::ELEMENT_STATS_FILE), config.element_stats.max_elements)?,
+            review: review::ReviewQueue::open(storage.root().join(review::REVIEW_DIR), config.review.max_items)?
+                .with_scratch(scratch.clone()),
+            click_targets: Vec::new(),
+            provenance: Vec::new(),
+            hooks: hooks::Hooks::default(),
+            hook_metadata: BTreeMap::new(),
+            confirmations: confirmation::ConfirmationGate::new(
+                &config.confirmation, storage.root().join(confirmation::AUDIT_FILE)).with_contexts(&config.contexts),
+            pending_clarification: None,
+            escalation: None,
+            answered: None,
+            speed: SpeedControl::new(config.speed.multiplier()),
+            debugger: {
+                let debugger = debugger::StepControl::new();
+                debugger.supervise(config.supervision.threshold());
+                debugger
+            },
+            ai_coordinator: build_ai_coordinator(&config, &capabilities)?,
+            screen_capture: None,
+            input_system: None,
             safety_system: Arc::new(safety::SafetySystem::new(&config)),
+            storage,
+            scratch,
+            focus_monitor: None,
+            desktop: Box::new(restore::SystemDesktop),
+            session_monitor: None,
+            display_monitor: None,
+            vnc: None,
+            resource_history: std::collections::VecDeque::new(),
+            capabilities,
+            metrics: {
+                let metrics = metrics::MetricsCollector::new(stats.clone());
+                metrics.set_slo_objectives(&config.slo.objectives);
+                metrics
+            },
             config,
-            stats: Arc::new(Mutex::new(ProcessingStats::default())),
+            stats,
             event_subscribers: Arc::new(Mutex::new(Vec::new())),
+            last_storage_check: None,
+            last_frame: None,
+            stale_reference: None,
+            warm: None,
+            training_exporter: None,
+            archive: None,
+            unarchived: None,
+            startup: Vec::new(),
+            tracker: tracker::ElementTracker::new(),
+            latest_analysis: None,
+            recent_commands: std::collections::VecDeque::new(),
+            suggested_for: None,
+            heartbeats: watchdog::Heartbeats::default(),
+            watchdog: None,
+        };
+        luna.watchdog = luna.start_watchdog();
+        luna.record_startup("core", started);
+        Ok(luna)
+    }
+
+    /// Screen capture, set up now if this is its first use
+    pub fn ensure_capture(&mut self) -> Result<&mut ScreenCapture> {
+        if self.screen_capture.is_none() {
+            let started = Instant::now();
+            let mut capture = build_screen_capture(&self.config)?;
+            if let Some(client) = self.vnc_client() {
+                capture.set_source(Some(client.frame_source()));
+            }
+            self.screen_capture = Some(capture);
+            self.record_startup("capture", started);
+        }
+        Ok(self.screen_capture.as_mut().expect("capture was just set up"))
+    }
+
+    /// The input system, set up now if this is its first use
+    pub fn ensure_input(&mut self) -> &mut InputController {
+        if self.input_system.is_none() {
+            let started = Instant::now();
+            let mut input = InputController::new(Box::new(BasicSafetyChecker::new()));
+            if let Some(client) = self.vnc_client() {
+                input.set_sink(Some(client.input_sink()));
+            }
+            self.input_system = Some(input);
+            self.record_startup("input", started);
+        }
+        self.input_system.as_mut().expect("input was just set up")
+    }
+
+    /// The VNC server frames and input go to, when `vnc.enabled`; the
+    /// connection opens on first capture or input
+    fn vnc_client(&mut self) -> Option<vnc::VncClient> {
+        if !self.config.vnc.enabled {
+            return None;
+        }
+        let config = &self.config.vnc;
+        Some(self.vnc.get_or_insert_with(|| vnc::VncClient::new(config.clone())).clone())
+    }
+
+    fn ensure_focus_monitor(&mut self) -> &mut focus::FocusMonitor {
+        if self.focus_monitor.is_none() {
+            let started = Instant::now();
+            self.focus_monitor = Some(build_focus_monitor(&self.config));
+            self.record_startup("focus", started);
+        }
+        self.focus_monitor.as_mut().expect("focus monitor was just set up")
+    }
+
+    fn ensure_session_monitor(&mut self) -> &mut session::SessionMonitor {
+        if self.session_monitor.is_none() {
+            let started = Instant::now();
+            self.session_monitor = Some(build_session_monitor(&self.config));
+            self.record_startup("session", started);
+        }
+        self.session_monitor.as_mut().expect("session monitor was just set up")
+    }
+
+    fn record_startup(&mut self, subsystem: &'static str, started: Instant) {
+        let took = started.elapsed();
+        debug!("Initialized {} in {:.1}ms", subsystem, took.as_secs_f64() * 1000.0);
+        self.metrics.observe("luna_subsystem_init_seconds", &[("subsystem", subsystem)], took);
+        self.startup.push((subsystem, took));
+    }
+
+    /// How long the core and each subsystem brought up so far took to
+    /// initialize, in the order they came up
+    pub fn startup_times(&self) -> &[(&'static str, Duration)] {
+        &self.startup
+    }
+
+    /// Capture the screen, setting up capture on first use
+    fn capture_screen(&mut self) -> Result<Image> {
+        self.recover_stalled(watchdog::Subsystem::Capture);
+        let _busy = self.heartbeats.busy(watchdog::Subsystem::Capture);
+        Ok(self.ensure_capture()?.capture_screen()?)
+    }
+
+    /// Capture only `rect` of the screen, under the watchdog like `capture_screen`
+    fn capture_area(&mut self, rect: &Rectangle) -> Result<Image> {
+        self.recover_stalled(watchdog::Subsystem::Capture);
+        let _busy = self.heartbeats.busy(watchdog::Subsystem::Capture);
+        Ok(self.ensure_capture()?.capture_area(rect)?)
+    }
+
+    /// Run the detectors on `image` under the watchdog
+    fn detect(&mut self, image: &image::DynamicImage) -> Result<ScreenAnalysis> {
+        self.recover_stalled(watchdog::Subsystem::Inference);
+        let _busy = self.heartbeats.busy(watchdog::Subsystem::Inference);
+        self.ai_coordinator.analyze_screen(image)
+    }
+
+    /// Re-initialize `subsystem` if the watchdog found it hung since its last use
+    fn recover_stalled(&mut self, subsystem: watchdog::Subsystem) {
+        if !self.heartbeats.take_reset(subsystem) {
+            return;
+        }
+        warn!("Re-initializing {} after it stalled", subsystem.as_str());
+        match subsystem {
+            watchdog::Subsystem::Capture => self.screen_capture = None,
+            watchdog::Subsystem::Inference => self.ai_coordinator.reload_detector(),
+            watchdog::Subsystem::Input => self.input_system = None,
+        }
+    }
+
+    /// Watchdog over the heartbeats with the current settings, reporting what
+    /// it finds in the log, the metrics and `LunaEvent::Watchdog`
+    fn start_watchdog(&self) -> Option<watchdog::Watchdog> {
+        let subscribers = Arc::clone(&self.event_subscribers);
+        let metrics = self.metrics.clone();
+        watchdog::Watchdog::start(&self.config.watchdog, self.heartbeats.clone(), move |event| {
+            let subsystem = event.subsystem().as_str();
+            match &event {
+                watchdog::WatchdogEvent::Stalled { .. } => {
+                    warn!("Watchdog: {}", event);
+                    metrics.increment("luna_watchdog_stalls_total", &[("subsystem", subsystem)]);
+                }
+                watchdog::WatchdogEvent::Degraded { .. } => {
+                    error!("Watchdog: {}", event);
+                    metrics.increment("luna_watchdog_degraded_total", &[("subsystem", subsystem)]);
+                }
+                _ => info!("Watchdog: {}", event),
+            }
+            if let Ok(subscribers) = subscribers.lock() {
+                for callback in subscribers.iter() {
+                    callback(LunaEvent::Watchdog { event: event.clone() });
+                }
+            }
         })
     }
 
+    /// Subsystems the watchdog put in degraded mode after repeated stalls
+    pub fn degraded_subsystems(&self) -> Vec<watchdog::Subsystem> {
+        self.heartbeats.degraded()
+    }
+
+    /// Where typed text would go: as the platform reports it, or else the
+    /// bar that blinks between `frame` and frames captured after it. The
+    /// sandbox draws no caret, so it is not watched for one; a VNC desktop's
+    /// caret is only found by its blink.
+    fn locate_caret(&mut self, frame: &Image) -> Option<crate::vision::caret::Caret> {
+        if let Some(caret) = self.desktop.caret().filter(|_| !self.config.vnc.enabled) {
+            return Some(caret);
+        }
+        let caret = self.config.caret.clone();
+        if !caret.blink_fallback || self.capabilities.capture_backend == "sandbox" {
+            return None;
+        }
+        let mut before = frame.clone();
+        for _ in 0..caret.blink_samples {
+            std::thread::sleep(Duration::from_millis(caret.blink_ms));
+            let after = self.capture_screen().ok()?;
+            if let Some(found) = crate::vision::caret::find_blinking_caret(&before, &after) {
+                debug!("Text cursor blinking at ({}, {})", found.x, found.y);
+                return Some(found);
+            }
+            before = after;
+        }
+        None
+    }
+
+    /// Risk of `action` as the input system rates it; rated the same way
+    /// before input is set up
+    fn risk_level(&self, action: &InputAction) -> RiskLevel {
+        match &self.input_system {
+            Some(input) => input.risk_level(action),
+            None => BasicSafetyChecker::new().get_risk_level(action),
+        }
+    }
+
     /// Process user command and execute actions
     pub fn process_command(&mut self, command: &str) -> Result<Vec<LunaAction>> {
+        self.process_command_with_options(command, &ExecuteOptions::default())
+    }
+
+    /// Process user command with per-command options such as a region constraint
+    pub fn process_command_with_options(&mut self, command: &str, options: &ExecuteOptions) -> Result<Vec<LunaAction>> {
+        self.execute_command(command, options).map(|result| result.actions)
+    }
+
+    /// Process a command and report how it was handled
+    pub fn execute_command(&mut self, command: &str, options: &ExecuteOptions) -> Result<CommandResult> {
+        let restore = options.restore.unwrap_or(self.config.input.restore_after_command) && !options.dry_run;
+        let saved = restore.then(|| self.desktop.snapshot());
+        let window = (!options.dry_run).then(focus::active_window_title);
+        let mut result = self.run_command(command, options);
+        if let (Some(window), Ok(_)) = (window, &result) {
+            self.remember_command(command, window);
+        }
+        // However the command ended, no key it pressed stays down
+        let released = self.input_system.as_mut().map(InputController::release_held_keys).unwrap_or_default();
+        if !released.is_empty() {
+            warn!("Released keys still held when '{}' ended: {}", command, released.join(", "));
+        }
+        if let Some(saved) = saved {
+            let steps = self.restore_desktop(&saved);
+            for step in &steps {
+                debug!("After '{}': {}", command, step);
+            }
+            if let Ok(result) = result.as_mut() {
+                result.restoration = steps;
+            }
+        }
+        result
+    }
+
+    /// Focus the saved window and move the cursor back, where safe
+    fn restore_desktop(&mut self, saved: &restore::DesktopState) -> Vec<restore::RestoreStep> {
+        let focus = restore::restore_focus(self.desktop.as_ref(), saved);
+        let left_at = self.input_system.as_ref().and_then(InputController::last_position);
+        let cursor = match restore::cursor_target(saved, self.desktop.cursor(), left_at) {
+            Ok((x, y)) => {
+                let action = InputAction {
+                    action_type: ActionType::Move { x, y },
+                    target: Target { x, y, element_type: None },
+                    timestamp: Instant::now(),
+                };
+                restore::cursor_moved((x, y), self.ensure_input().execute_action(action).map_err(|e| e.to_string()))
+            }
+            Err(step) => step,
+        };
+        vec![focus, cursor]
+    }
+
+    fn run_command(&mut self, command: &str, options: &ExecuteOptions) -> Result<CommandResult> {
         let start_time = Instant::now();
-        
+        let mut profiler = self.config.resources.enabled
+            .then(|| resources::ResourceProfiler::start(&self.config.resources));
+        let mut stages = self.metrics.stage_timer();
+        let mut phase = |name| {
+            stages.enter(name);
+            if let Some(profiler) = profiler.as_mut() {
+                profiler.phase(name);
+            }
+        };
+        phase("safety");
+
         info!("Processing command: '{}'", command);
+        if let Some(region) = &options.region_constraint {
+            if region.is_empty() {
+                return Err(LunaError::InvalidArgument(format!("empty region constraint: {:?}", region)).into());
+            }
+            debug!("Constraining command to region {:?}", region);
+        }
         self.emit_event(LunaEvent::CommandReceived { 
             command: command.to_string() 
         });
+        // A new command abandons any unanswered question
+        self.pending_clarification = None;
+        self.escalation = None;
+        self.click_targets.clear();
+        self.provenance.clear();
+        self.hook_metadata.clear();
+        self.unarchived = None;
+
+        if let Some(speed) = options.speed.filter(|s| !config::SPEED_MULTIPLIER_RANGE.contains(s)) {
+            return Err(LunaError::InvalidArgument(format!("speed multiplier {} is outside {:?}", speed, config::SPEED_MULTIPLIER_RANGE)).into());
+        }
+        // Read before every pause so a live change applies to a running command
+        let live_speed = self.speed.clone();
+        let speed = || options.speed.unwrap_or_else(|| live_speed.get());
 
         // Step 1: Safety check
         if !self.safety_system.is_command_safe(command) {
@@ -139,22 +1175,65 @@ impl Luna {
             self.update_stats(|stats| stats.safety_blocks += 1);
             return Err(LunaError::UnsafeCommand(command.to_string()).into());
         }
+        if let Some(reason) = self.safety_system.check_context_command(options.source, command) {
+            return Err(self.refuse_in_context(command, None, None, options, reason));
+        }
+        let destructive = self.safety_system.destructive_verb(command);
 
-        // Step 2: Capture current screen
-        let screenshot = self.screen_capture.capture_screen()?;
-        debug!("Screen captured: {}x{}", screenshot.width, screenshot.height);
+        // Step 1b: Don't interrupt presentations or do-not-disturb unless the source allows it
+        if self.safety_system.disruption_policy(options.source) != config::DisruptionPolicy::Allow {
+            let focus = self.ensure_focus_monitor().state();
+            match self.safety_system.check_disruption(options.source, &focus) {
+                safety::DisruptionDecision::Allow => {}
+                safety::DisruptionDecision::Defer(reason) => {
+                    info!("Deferring {:?} command '{}': {}", options.source, command, reason);
+                    return Err(LunaError::Deferred(reason).into());
+                }
+                safety::DisruptionDecision::Refuse(reason) => {
+                    warn!("Refusing {:?} command '{}': {}", options.source, command, reason);
+                    self.update_stats(|stats| stats.safety_blocks += 1);
+                    return Err(LunaError::UnsafeCommand(
+                        format!("[{}] {}", safety::SafetyCategory::Disruption, reason)).into());
+                }
+            }
+        }
 
-        // Step 3: Analyze screen to understand current state
-        let dynamic_image = to_dynamic_image(&screenshot)?;
-        let analysis = self.ai_coordinator.analyze_screen(&dynamic_image)?;
-        debug!("Screen analysis complete: {} elements detected", analysis.elements.len());
-        
-        self.emit_event(LunaEvent::AnalysisComplete { 
-            analysis: analysis.clone() 
-        });
+        // Step 1c: A locked screen can be neither analyzed nor driven
+        self.wait_for_session(command, options.cancel.as_ref())?;
+        self.check_displays();
+        let layout = self.displays().map(<[DisplayInfo]>::to_vec);
 
-        // Step 4: Plan actions based on command and screen state
-        let actions = self.ai_coordinator.plan_actions(command, &analysis)?;
+        // Literal coordinates and keyboard-only commands need no screen state
+        let direct_actions = if options.force_full_pipeline {
+            None
+        } else {
+            self.ai_coordinator.plan_direct_actions(command)
+        };
+        let pipeline_skipped = direct_actions.is_some();
+
+        let actions = match direct_actions {
+            Some(actions) => {
+                debug!("Skipping capture and analysis for literal command");
+                if let Some(region) = &options.region_constraint {
+                    for action in &actions {
+                        if let Some((x, y)) = action.click_point() {
+                            if !region.contains_point(x, y) {
+                                return Err(LunaError::InvalidArgument(
+                                    format!("click at ({}, {}) is outside the region constraint", x, y)).into());
+                            }
+                        }
+                    }
+                }
+                self.ai_coordinator.ensure_executable(&actions)?;
+                self.provenance = provenance::ActionProvenance::for_actions(&actions, provenance::PlannedBy::Literal, None);
+                actions
+            }
+            // Steps 2-4: Capture, analyze and plan, unless the command names a taught anchor
+            None => match self.plan_anchor_actions(command, options, &mut phase)? {
+                Some(actions) => actions,
+                None => self.plan_from_screen(command, options, &mut phase)?,
+            },
+        };
         debug!("Planned {} actions", actions.len());
         
         self.emit_event(LunaEvent::ActionsPlanned { 
@@ -162,73 +1241,1966 @@ impl Luna {
         });
 
         // Step 5: Validate actions with safety system
-        for action in &actions {
-            if !self.safety_system.is_action_safe(action) {
-                warn!("Action blocked by safety system: {:?}", action);
-                self.update_stats(|stats| stats.safety_blocks += 1);
-                return Err(LunaError::UnsafeAction(format!("{:?}", action)).into());
+        self.validate_actions(command, &actions, options)?;
+        if !options.dry_run {
+            self.confirm_actions(command, &actions, options)?;
+        }
+
+        // Step 6: Execute actions
+        phase("execution");
+        if options.dry_run {
+            info!("Dry run: skipping execution of {} actions", actions.len());
+        } else if !self.ensure_input().has_sink() && actions.iter().any(|action| self.capabilities.required_for(action).is_some()) {
+            // Another LUNA process may own input; this one is then analysis-only
+            self.input_lease.ensure_owner()?;
+        }
+        if !options.dry_run && !actions.is_empty() {
+            self.countdown(scaled(self.config.speed.countdown_ms, speed()), options.cancel.as_ref(), command)?;
+        }
+        let mut actions = actions;
+        let mut retries = Vec::new();
+        let mut typing = Vec::new();
+        let mut replans = 0;
+        let mut next = 0;
+        self.stale_reference = None;
+        while let Some(action) = actions.get(next).filter(|_| !options.dry_run).cloned() {
+            let action = &action;
+            if options.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                info!("Command cancelled before {:?}", action);
+                return Err(LunaError::Cancelled(command.to_string()).into());
+            }
+
+            // Hold here while paused or stopped at a breakpoint
+            let target = self.provenance.get(next).and_then(|p| p.element.as_ref());
+            let at = || debugger::PausedAt { command: command.to_string(), index: next, action: format!("{:?}", action), breakpoint: None, confidence: None };
+            if let Some(paused) = self.debugger.gate(at, action_kind(action), target, options.cancel.as_ref())? {
+                debug!("Continuing after pause before action {} of '{}'", paused.index, command);
+            }
+
+            // Hold the rest of the sequence while locked; on unlock the target is re-checked
+            let resumed = self.wait_for_session(command, options.cancel.as_ref())?;
+            if resumed {
+                info!("Re-validating {:?} after the session was unlocked", action);
+            }
+
+            // Coordinates on a display unplugged or resized since the command began point elsewhere now
+            self.check_displays();
+            if let (Some(before), Some(after), Some((x, y))) = (&layout, self.displays(), action.click_point()) {
+                if let Some(lost) = displays::lost_display(before, after, x, y) {
+                    return Err(LunaError::StaleFrame(format!(
+                        "display {} was removed or resized after '{}' started; not sending {:?}", lost.name, command, action)).into());
+                }
             }
+
+            // A popup may have appeared since analysis; don't click into it blind
+            if let Some(changed) = self.stale_target(action, pipeline_skipped, resumed)? {
+                self.update_stats(|stats| stats.stale_frames += 1);
+                self.emit_event(LunaEvent::StaleFrame { action: action.clone(), changed_fraction: changed });
+                let guard = &self.config.stale_frame;
+                // Only re-plan before anything ran, or after scrolling a target
+                // into view, so no action that changes anything is repeated
+                let revealing = actions[..next].iter().all(|a| matches!(a, LunaAction::Hover { .. } | LunaAction::Scroll { .. } | LunaAction::Wait { .. }));
+                if guard.on_stale == config::StaleFramePolicy::Replan && revealing && replans < guard.max_replans {
+                    warn!("{:.0}% of the target of {:?} changed since analysis, re-planning", changed * 100.0, action);
+                    replans += 1;
+                    std::thread::sleep(Duration::from_millis(guard.settle_ms));
+                    self.stale_reference = None;
+                    let mut provenance: Vec<_> = self.provenance.drain(..next.min(self.provenance.len())).collect();
+                    // After a scroll, the elements analyzed have only moved
+                    let replanned = match self.follow_scroll(&actions[..next])?.map(|moved| self.plan_moved(command, options, &moved)) {
+                        Some(Ok(replanned)) => replanned,
+                        _ => self.plan_from_screen(command, options, &mut phase)?,
+                    };
+                    self.validate_actions(command, &replanned, options)?;
+                    self.confirm_actions(command, &replanned, options)?;
+                    // What already ran stays in the result, ahead of the new plan
+                    provenance.append(&mut self.provenance);
+                    self.provenance = provenance;
+                    actions.truncate(next);
+                    actions.extend(replanned);
+                    phase("execution");
+                    continue;
+                }
+                return Err(LunaError::StaleFrame(format!(
+                    "{:.0}% of the target region of {:?} changed since analysis", changed * 100.0, action)).into());
+            }
+
+            if let Some(verb) = destructive {
+                self.verify_destructive_click(command, verb, action)?;
+            }
+            let mut context = hooks::HookContext { command, options, metadata: &mut self.hook_metadata };
+            self.hooks.before_action(&mut context, action)?;
+
+            let pacing = self.pacing(speed());
+            self.ensure_input().set_pacing(pacing);
+            let target = self.click_target(action);
+            let before = target.as_ref().and_then(|_| self.capture_screen().ok());
+            let executed = self.execute_with_retry(action);
+            let mut context = hooks::HookContext { command, options, metadata: &mut self.hook_metadata };
+            self.hooks.after_action(&mut context, action, executed.is_ok());
+            let latency = executed.is_ok().then(|| start_time.elapsed());
+            for status in self.metrics.record_slo(action_kind(action), latency) {
+                let compliance = status.compliance.unwrap_or_default() * 100.0;
+                if status.violated {
+                    warn!("SLO '{}' violated: {:.1}% of {} action(s) within {}ms, target {:.1}%", status.name, compliance, status.samples, status.latency_ms, status.target * 100.0);
+                } else {
+                    info!("SLO '{}' met again: {:.1}% within {}ms", status.name, compliance, status.latency_ms);
+                }
+                self.emit_event(LunaEvent::Slo { status });
+            }
+            match executed {
+                Ok(retried) => {
+                    retries.push(retried);
+                    debug!("Action executed successfully: {:?}", action);
+                    self.emit_event(LunaEvent::ActionExecuted { 
+                        action: action.clone(), 
+                        success: true 
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to execute action {:?}: {}", action, e);
+                    self.emit_event(LunaEvent::ActionExecuted { 
+                        action: action.clone(), 
+                        success: false 
+                    });
+                    return Err(e);
+                }
+            }
+            if let (Some(fingerprint), Some(before)) = (target, before) {
+                self.verify_click(&fingerprint, &before);
+            }
+            if let LunaAction::Type { text } = action {
+                if let Some(report) = self.check_typing(&actions[..next], next, text, pipeline_skipped)? {
+                    typing.push(report);
+                }
+            }
+            
+            // Small delay between actions for stability
+            std::thread::sleep(scaled(self.config.safety.action_delay_ms, speed()));
+            next += 1;
+            if let Some(following) = actions.get(next) {
+                self.refresh_stale_reference(following, pipeline_skipped);
+            }
+        }
+        if !options.dry_run && actions.iter().any(|a| matches!(a, LunaAction::ModifierClick { .. })) {
+            self.verify_selection(&actions)?;
+        }
+
+        // Update statistics
+        stages.succeeded();
+        let processing_time = start_time.elapsed();
+        let processing_time_ms = processing_time.as_millis() as u64;
+        let resources = profiler.map(resources::ResourceProfiler::finish);
+        
+        self.update_stats(|stats| {
+            stats.commands_processed += 1;
+            if !options.dry_run {
+                stats.actions_executed += actions.len() as u64;
+            }
+            if pipeline_skipped {
+                stats.pipeline_skips += 1;
+            }
+            stats.total_processing_time_ms += processing_time_ms;
+            stats.average_processing_time_ms = 
+                stats.total_processing_time_ms as f64 / stats.commands_processed as f64;
+            if let Some(usage) = &resources {
+                stats.total_cpu_time_ms += usage.cpu_time_ms.unwrap_or(0);
+                stats.peak_rss_bytes = stats.peak_rss_bytes.max(usage.rss_peak_bytes.unwrap_or(0));
+                stats.peak_gpu_memory_bytes =
+                    stats.peak_gpu_memory_bytes.max(usage.gpu_memory_peak_bytes.unwrap_or(0));
+                for phase in &usage.phases {
+                    *stats.phase_cpu_time_ms.entry(phase.name).or_insert(0) += phase.cpu_time_ms.unwrap_or(0);
+                }
+            }
+        });
+        if let Some(usage) = &resources {
+            self.record_resource_usage(command, usage.clone());
+        }
+
+        info!("Command processed successfully in {}ms: {} actions executed", 
+              processing_time_ms, actions.len());
+
+        let command_id = provenance::next_command_id();
+        let provenance = std::mem::take(&mut self.provenance);
+        if self.config.transcript.enabled && !options.dry_run {
+            self.record_transcript(&command_id, command, options.client.as_deref(), &provenance);
+        }
+        if let Some(frame) = self.unarchived.take() {
+            self.archive_frame(&command_id, &frame);
+        }
+        self.maybe_run_storage_maintenance();
+
+        Ok(CommandResult {
+            actions,
+            retries,
+            pipeline_skipped,
+            dry_run: options.dry_run,
+            processing_time_ms,
+            resources,
+            escalation: self.escalation.take(),
+            raw_outputs: (self.config.raw_outputs.include && !pipeline_skipped)
+                .then(|| self.ai_coordinator.raw_outputs(&self.config.raw_outputs))
+                .flatten(),
+            command_id,
+            provenance,
+            restoration: Vec::new(),
+            typing,
+            metadata: std::mem::take(&mut self.hook_metadata),
+        })
+    }
+
+    /// Append the frame a command was planned from to the archive. As with
+    /// the transcript, failing to write it doesn't fail the command.
+    fn archive_frame(&mut self, command_id: &str, frame: &Image) {
+        let written = self.frame_archive_path().and_then(|path| {
+            // Reopen if storage cleanup removed the file under the writer
+            if self.archive.as_ref().is_none_or(|writer| !writer.path().exists()) {
+                self.archive = Some(archive::ArchiveWriter::open(path, &self.config.archive)?);
+            }
+            let writer = self.archive.as_mut().expect("archive was just opened");
+            writer.append(frame, std::time::SystemTime::now(), Some(command_id))
+        });
+        if let Err(e) = written {
+            warn!("Could not archive the frame of command {}: {}", command_id, e);
+        }
+    }
+
+    /// Archive of analyzed frames (`archive.enabled`)
+    pub fn frame_archive_path(&self) -> Result<PathBuf> {
+        Ok(self.storage.store_path(storage::StoreKind::Recordings)?.join(archive::FRAMES_FILE))
+    }
+
+    /// Read the frame archive, e.g. to find the frame a command saw
+    pub fn archived_frames(&self) -> Result<archive::ArchiveReader> {
+        archive::ArchiveReader::open(&self.frame_archive_path()?)
+    }
+
+    /// Put archived frame `index` back on the inspector timeline, analyzed
+    /// again with the current detectors
+    pub fn inspect_archived(&mut self, index: usize) -> Result<()> {
+        let mut reader = self.archived_frames()?;
+        let frame = reader.frame(index)?;
+        let entry = &reader.entries()[index];
+        let analysis = self.detect(&to_dynamic_image(&frame)?)?;
+        self.inspector.record(InspectorFrame {
+            command: format!("archived #{} ({})", index, entry.command_id.as_deref().unwrap_or("no command")),
+            frame,
+            analysis,
+            actions: Vec::new(),
+            captured_at: entry.captured_at(),
+        });
+        Ok(())
+    }
+
+    /// Append an executed command's evidence to the transcript. Failing to
+    /// write it doesn't fail the command, which already ran.
+    fn record_transcript(&self, command_id: &str, command: &str, client: Option<&str>, actions: &[provenance::ActionProvenance]) {
+        let record = provenance::TranscriptRecord {
+            command_id: command_id.to_string(),
+            command: command.to_string(),
+            client: client.map(str::to_string),
+            executed_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            actions: actions.to_vec(),
+        };
+        let written = self.storage.store_path(storage::StoreKind::Transcripts)
+            .and_then(|dir| provenance::append(&dir.join(provenance::TRANSCRIPT_FILE), &record));
+        if let Err(e) = written {
+            warn!("Could not write the transcript of '{}': {}", command, e);
+        }
+    }
+
+    /// The evidence chain behind action `index` of a past command, from the
+    /// transcript; `None` when the command or action isn't in it
+    pub fn action_provenance(&self, command_id: &str, index: usize) -> Result<Option<provenance::ActionProvenance>> {
+        let path = self.storage.root().join(storage::StoreKind::Transcripts.dir_name()).join(provenance::TRANSCRIPT_FILE);
+        provenance::reconstruct(&path, command_id, index)
+    }
+
+    /// Gather a redacted bug-report bundle of `options.parts` (see
+    /// `diagnose`). A part that can't be read is listed as omitted rather
+    /// than failing the bundle.
+    pub fn diagnose(&self, options: &diagnose::DiagnoseOptions) -> Result<diagnose::DiagnosticBundle> {
+        let redactor = crate::ai::training::Redactor::new(&self.config.training.redact_patterns)?;
+        let mut bundle = diagnose::DiagnosticBundle::default();
+        for &part in &options.parts {
+            match self.diagnose_part(part, options, &redactor, &mut bundle) {
+                Ok(None) => bundle.included.push(part),
+                Ok(Some(reason)) => bundle.omitted.push(diagnose::Omission { part, reason }),
+                Err(e) => bundle.omitted.push(diagnose::Omission { part, reason: e.to_string() }),
+            }
+        }
+        Ok(bundle)
+    }
+
+    /// Add `part` to `bundle`; why it was left out when there is nothing to add
+    fn diagnose_part(
+        &self,
+        part: diagnose::BundlePart,
+        options: &diagnose::DiagnoseOptions,
+        redactor: &crate::ai::training::Redactor,
+        bundle: &mut diagnose::DiagnosticBundle,
+    ) -> Result<Option<String>> {
+        use diagnose::BundlePart;
+        match part {
+            BundlePart::Config => {
+                let mut config = serde_json::to_value(&self.config)?;
+                diagnose::strip_secrets(&mut config);
+                bundle.add("config.json", serde_json::to_vec_pretty(&config)?);
+            }
+            BundlePart::Capabilities => {
+                bundle.add("capabilities.json", serde_json::to_vec_pretty(&self.capabilities)?);
+                bundle.add("capabilities.txt", self.capabilities.summary().into_bytes());
+            }
+            BundlePart::Transcripts => {
+                let path = self.storage.root().join(storage::StoreKind::Transcripts.dir_name()).join(provenance::TRANSCRIPT_FILE);
+                let records = provenance::read(&path)?;
+                if records.is_empty() {
+                    return Ok(Some("no commands have been transcribed".to_string()));
+                }
+                let mut lines = String::new();
+                for record in &records[records.len().saturating_sub(options.transcripts)..] {
+                    lines.push_str(&serde_json::to_string(&diagnose::redact_transcript(record, redactor))?);
+                    lines.push('\n');
+                }
+                bundle.add("transcripts.jsonl", lines.into_bytes());
+            }
+            BundlePart::Frames => {
+                // The inspector's frames are the ones the planner saw; a
+                // one-shot run has none, so fall back to the archive
+                let recent: Vec<&InspectorFrame> = self.inspector.timeline().collect();
+                if !recent.is_empty() {
+                    for (i, frame) in recent.iter().enumerate().skip(recent.len().saturating_sub(options.frames)) {
+                        bundle.add(format!("frames/inspector-{}.png", i), encode_png(&diagnose::pixelate(&frame.frame))?);
+                    }
+                } else {
+                    let mut archive = match self.archived_frames() {
+                        Ok(archive) if !archive.is_empty() => archive,
+                        _ => return Ok(Some("no frames in the inspector or the frame archive".to_string())),
+                    };
+                    for index in archive.len().saturating_sub(options.frames)..archive.len() {
+                        let frame = archive.frame(index)?;
+                        bundle.add(format!("frames/archive-{}.png", index), encode_png(&diagnose::pixelate(&frame))?);
+                    }
+                }
+            }
+            BundlePart::Logs => {
+                let Some(dir) = self.config.logging.log_dir.as_ref().filter(|dir| dir.is_dir()) else {
+                    return Ok(Some("logging.log_dir is not set or doesn't exist".to_string()));
+                };
+                let files = diagnose::log_files(dir)?;
+                if files.is_empty() {
+                    return Ok(Some(format!("no log files in {}", dir.display())));
+                }
+                for path in files {
+                    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                    bundle.add(format!("logs/{}", name), diagnose::log_tail(&path, redactor)?);
+                }
+            }
+            BundlePart::Models => {
+                let models = serde_json::json!({
+                    "luna_version": env!("CARGO_PKG_VERSION"),
+                    "models": self.capabilities.models,
+                    "language_packs": self.language_packs().installed()?,
+                });
+                bundle.add("models.json", serde_json::to_vec_pretty(&models)?);
+            }
+            BundlePart::Metrics => bundle.add("metrics.prom", self.metrics.render().into_bytes()),
+        }
+        Ok(None)
+    }
+
+    /// Capture the screen, analyze it and plan `command` against it
+    fn plan_from_screen(
+        &mut self,
+        command: &str,
+        options: &ExecuteOptions,
+        phase: &mut impl FnMut(&'static str),
+    ) -> Result<Vec<LunaAction>> {
+        // An answered question resumes against the analysis it was asked about;
+        // the stale-frame check still catches a screen that changed meanwhile
+        if let Some((pending, element)) = self.answered.take_if(|(pending, _)| pending.command == command) {
+            phase("planning");
+            let actions = self.ai_coordinator.plan_choice(command, &pending.analysis, element)?;
+            let planned_by = provenance::PlannedBy::Answer { question: pending.clarification.question.clone() };
+            self.provenance = provenance::ActionProvenance::for_actions(&actions, planned_by, Some(&pending.analysis));
+            return Ok(actions);
+        }
+
+        // Apps with enough review verdicts are analyzed with their learned threshold
+        let app = self.config.review.enabled.then(focus::active_window_title).flatten();
+        let learned = self.learned_thresholds(app.as_deref());
+        let configured = self.ai_coordinator.thresholds();
+        if let Some(thresholds) = learned {
+            debug!("Detection threshold {:.2} learned for this app", thresholds.detection);
+            self.ai_coordinator.set_thresholds(thresholds);
+        }
+        let planned = self.analyze_and_plan(command, options, phase, app.as_deref(), learned.is_none());
+        self.ai_coordinator.set_thresholds(configured);
+        planned
+    }
+
+    /// Steps 2-4 against a fresh capture; `use_warm` allows an analysis made
+    /// while idle, which applied the configured thresholds
+    fn analyze_and_plan(
+        &mut self,
+        command: &str,
+        options: &ExecuteOptions,
+        phase: &mut impl FnMut(&'static str),
+        app: Option<&str>,
+        use_warm: bool,
+    ) -> Result<Vec<LunaAction>> {
+        let mut context = hooks::HookContext { command, options, metadata: &mut self.hook_metadata };
+        self.hooks.before_analysis(&mut context)?;
+
+        // Step 2: Capture current screen
+        phase("capture");
+        let started = Instant::now();
+        let screenshot = self.capture_screen()?;
+        debug!("Screen captured: {}x{}", screenshot.width, screenshot.height);
+
+        // Step 3: Analyze screen to understand current state, unless it was
+        // analyzed while idle and has not changed since
+        phase("analysis");
+        let dynamic_image = to_dynamic_image(&screenshot)?;
+        let warm = if use_warm {
+            self.take_warm_analysis(&screenshot)
+        } else {
+            self.warm = None;
+            None
+        };
+        let mut analysis = match warm {
+            Some(analysis) => analysis,
+            None => {
+                let mut analysis = self.detect(&dynamic_image)?;
+                self.annotate_reliability(&mut analysis);
+                analysis
+            }
+        };
+        let mut context = hooks::HookContext { command, options, metadata: &mut self.hook_metadata };
+        self.hooks.after_analysis(&mut context, &mut analysis)?;
+        if self.config.caret.enabled && crate::ai::needs_caret(command) {
+            analysis.context.caret = self.locate_caret(&screenshot);
+        }
+        let inspected = (self.config.inspector.history > 0).then(|| screenshot.clone());
+        if self.config.archive.enabled {
+            self.unarchived = Some(screenshot.clone());
+        }
+        self.last_frame = Some(screenshot);
+        debug!("Screen analysis complete: {} elements detected", analysis.elements.len());
+
+        self.emit_event(LunaEvent::AnalysisComplete { 
+            analysis: analysis.clone() 
+        });
+        self.track_elements(&analysis);
+
+        // Step 4: Plan actions based on command and screen state
+        phase("planning");
+        let mut planned = self.ai_coordinator.plan_actions_with_options(command, &analysis, options);
+
+        // Step 4b: No target on the fast pass; look again harder before giving up
+        let effort = self.config.escalation.clone();
+        let not_found = planned.as_ref().is_err_and(|e| matches!(e.downcast_ref::<LunaError>(), Some(LunaError::NotFound(_) | LunaError::NoMatchingElement(_))));
+        if not_found && effort.enabled && started.elapsed() < Duration::from_millis(effort.budget_ms) {
+            let reason = planned.as_ref().err().map(ToString::to_string).unwrap_or_default();
+            phase("escalation");
+            self.metrics.increment("luna_escalations_total", &[]);
+            let escalated_at = Instant::now();
+            let busy = self.heartbeats.busy(watchdog::Subsystem::Inference);
+            let mut thorough = self.ai_coordinator.analyze_thorough(&dynamic_image, &effort)?;
+            drop(busy);
+            self.annotate_reliability(&mut thorough);
+            let mut context = hooks::HookContext { command, options, metadata: &mut self.hook_metadata };
+            self.hooks.after_analysis(&mut context, &mut thorough)?;
+            info!("{}; re-analyzed thoroughly: {} -> {} elements", reason, analysis.elements.len(), thorough.elements.len());
+            self.emit_event(LunaEvent::AnalysisComplete { analysis: thorough.clone() });
+            self.track_elements(&thorough);
+            planned = self.ai_coordinator.plan_actions_with_options(command, &thorough, options).map_err(|e| match e.downcast::<LunaError>() {
+                Ok(LunaError::NotFound(message)) => LunaError::NotFound(format!("{}, even after thorough re-analysis", message)).into(),
+                Ok(LunaError::NoMatchingElement(mut no_match)) => {
+                    no_match.reason.push_str(", even after thorough re-analysis");
+                    LunaError::NoMatchingElement(no_match).into()
+                }
+                Ok(other) => other.into(),
+                Err(e) => e,
+            });
+            self.escalation = Some(Escalation {
+                reason,
+                elements_before: analysis.elements.len(),
+                elements_after: thorough.elements.len(),
+                elapsed_ms: escalated_at.elapsed().as_millis() as u64,
+            });
+            analysis = thorough;
+        }
+
+        let planned = match planned.map_err(|e| e.downcast::<Clarification>()) {
+            Err(Ok(clarification)) => {
+                info!("Asking: {}", clarification.question);
+                self.emit_event(LunaEvent::ClarificationNeeded {
+                    command: command.to_string(),
+                    clarification: clarification.clone(),
+                });
+                let question = clarification.question.clone();
+                self.pending_clarification = Some(PendingClarification {
+                    command: command.to_string(),
+                    analysis: analysis.clone(),
+                    frame: self.last_frame.clone(),
+                    clarification,
+                });
+                Err(LunaError::NeedsClarification(question).into())
+            }
+            Err(Err(e)) => Err(e),
+            Ok(actions) => Ok(actions),
+        };
+        if let Ok(actions) = planned.as_ref() {
+            let planned_by = provenance::PlannedBy::Analysis { thorough: self.escalation.is_some() };
+            self.provenance = provenance::ActionProvenance::for_actions(actions, planned_by, Some(&analysis));
+            self.record_click_strategies(&analysis, actions);
+            if self.config.element_stats.enabled {
+                self.click_targets = self.click_targets(&analysis, actions);
+            }
+        }
+        if self.config.review.enabled {
+            self.queue_for_review(command, app, &analysis);
+        }
+        // Failed plans are kept too: a wrong or missing target is what the inspector is for
+        if let Some(frame) = inspected {
+            self.inspector.record(InspectorFrame {
+                command: command.to_string(),
+                frame,
+                analysis,
+                actions: planned.as_ref().map(Vec::clone).unwrap_or_default(),
+                captured_at: std::time::SystemTime::now(),
+            });
+        }
+        planned
+    }
+
+    /// Click a remembered anchor named by `command`, re-located by its fingerprint.
+    /// Returns `None` when the command names no anchor, or the anchor is not
+    /// on screen and the planner should try instead.
+    fn plan_anchor_actions(
+        &mut self,
+        command: &str,
+        options: &ExecuteOptions,
+        phase: &mut impl FnMut(&'static str),
+    ) -> Result<Option<Vec<LunaAction>>> {
+        const CLICK_VERBS: [&str; 4] = ["click", "tap", "press", "hit"];
+        let lower = command.trim().to_lowercase();
+        if options.force_full_pipeline || !CLICK_VERBS.iter().any(|verb| lower.starts_with(verb)) {
+            return Ok(None);
+        }
+        let Some(anchor) = self.anchors.find_in_command(command).cloned() else {
+            return Ok(None);
+        };
+
+        phase("capture");
+        let Some(element) = self.find_again(&anchor.fingerprint)? else {
+            let anchor = self.anchors.mark_missed(&anchor.name)?.unwrap_or(anchor);
+            let context = anchor.app.as_ref().map(|app| format!(" (remembered in '{}')", app)).unwrap_or_default();
+            let advice = if anchor.is_stale() {
+                format!("; the UI may have changed, teach it again with `luna remember \"{}\"`", anchor.name)
+            } else {
+                String::new()
+            };
+            warn!("Anchor '{}' is not on screen{}{}; planning '{}' normally", anchor.name, context, advice, command);
+            return Ok(None);
+        };
+
+        // Keep the stored fingerprint current so small UI drift doesn't accumulate
+        let moved = (element.bounds.x, element.bounds.y) != (anchor.fingerprint.last_x, anchor.fingerprint.last_y);
+        let refreshed = if moved { self.fingerprint(&element).ok() } else { None };
+        self.anchors.mark_found(&anchor.name, refreshed)?;
+
+        let (x, y) = element.click_point();
+        if let Some(region) = &options.region_constraint {
+            if !region.contains_point(x, y) {
+                return Err(LunaError::InvalidArgument(
+                    format!("anchor '{}' at ({}, {}) is outside the region constraint", anchor.name, x, y)).into());
+            }
+        }
+        debug!("Resolved anchor '{}' to ({}, {})", anchor.name, x, y);
+        let actions = vec![LunaAction::Click { x, y }];
+        self.ai_coordinator.ensure_executable(&actions)?;
+        self.provenance = provenance::ActionProvenance::for_actions(&actions, provenance::PlannedBy::Anchor { name: anchor.name.clone() }, None);
+        self.provenance[0].element = Some(provenance::ElementEvidence::new(&element, None, (x, y)));
+        Ok(Some(actions))
+    }
+
+    /// Remember the element at `at` (or under the mouse cursor) as `name`
+    pub fn remember(&mut self, name: &str, at: Option<(i32, i32)>) -> Result<anchors::Anchor> {
+        let (x, y) = match at {
+            Some(point) => point,
+            None => crate::input::cursor_position().ok_or_else(|| {
+                LunaError::Input("cursor position is unavailable here; pass the location explicitly".to_string())
+            })?,
+        };
+        let analysis = self.analyze_current_screen()?;
+        let element = analysis.elements
+            .iter()
+            .filter(|e| e.bounds.contains_point(x, y))
+            .min_by_key(|e| e.bounds.width as i64 * e.bounds.height as i64)
+            .ok_or_else(|| LunaError::NotFound(format!("no element at ({}, {})", x, y)))?;
+
+        let anchor = anchors::Anchor::new(name, self.fingerprint(element)?, focus::active_window_title());
+        info!("Remembered {} at ({}, {}) as '{}'", element.element_type, element.bounds.x, element.bounds.y, anchor.name);
+        self.anchors.insert(anchor.clone())?;
+        Ok(anchor)
+    }
+
+    /// Anchors taught with `remember`
+    pub fn anchors(&self) -> &anchors::AnchorStore {
+        &self.anchors
+    }
+
+    /// Delete a remembered anchor; returns whether it existed
+    pub fn forget(&mut self, name: &str) -> Result<bool> {
+        self.anchors.remove(name)
+    }
+
+    /// Hold until every action that needs out-of-band approval has it
+    fn confirm_actions(&mut self, command: &str, actions: &[LunaAction], options: &ExecuteOptions) -> Result<()> {
+        for (index, action) in actions.iter().enumerate() {
+            // Waits and screenshots have no input risk
+            let Ok(input_action) = to_input_action(action) else {
+                continue;
+            };
+            let risk = self.risk_level(&input_action);
+            let confirmation = self.confirmations.confirm(
+                command, &format!("{:?}", action), risk, options.source, options.client.as_deref())?;
+            if let Some(provenance) = self.provenance.get_mut(index) {
+                provenance.safety.confirmation = confirmation;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check each action against the safety rules and the caller's risk
+    /// ceiling, recording the verdict and input risk in its provenance
+    fn validate_actions(&mut self, command: &str, actions: &[LunaAction], options: &ExecuteOptions) -> Result<()> {
+        // Keys pressed by earlier actions, which later key presses chord with
+        let mut held: Vec<String> = Vec::new();
+        for (index, action) in actions.iter().enumerate() {
+            if !self.safety_system.is_action_safe(action) {
+                warn!("Action blocked by safety system: {:?}", action);
+                self.update_stats(|stats| stats.safety_blocks += 1);
+                return Err(LunaError::UnsafeAction(format!("{:?}", action)).into());
+            }
+            // Waits and screenshots have no input risk
+            let risk = to_input_action(action).ok().map(|input| {
+                let input = match &input.action_type {
+                    ActionType::Key { key } | ActionType::KeyDown { key } if !held.is_empty() => InputAction {
+                        action_type: ActionType::Key { key: crate::input::held_chord(&held, key) },
+                        ..input
+                    },
+                    _ => input,
+                };
+                self.risk_level(&input)
+            });
+            match action {
+                LunaAction::KeyDown { key } => held.push(key.to_lowercase()),
+                LunaAction::KeyUp { key } => held.retain(|k| !k.eq_ignore_ascii_case(key)),
+                _ => {}
+            }
+            if let Some((risk, max_risk)) = risk.zip(options.max_risk).filter(|(risk, max_risk)| risk > max_risk) {
+                return Err(LunaError::PermissionDenied(format!(
+                    "{:?} is {:?} risk; this caller may run at most {:?}", action, risk, max_risk)).into());
+            }
+            if let Some(reason) = self.safety_system.check_context_action(options.source, action, risk) {
+                return Err(self.refuse_in_context(command, Some(action), risk, options, reason));
+            }
+            if let Some(provenance) = self.provenance.get_mut(index) {
+                provenance.safety = provenance::SafetyVerdict { allowed: true, risk, confirmation: None };
+            }
+        }
+        Ok(())
+    }
+
+    /// Refusal of a command or action its source's context policy forbids,
+    /// after auditing it
+    fn refuse_in_context(
+        &mut self,
+        command: &str,
+        action: Option<&LunaAction>,
+        risk: Option<crate::input::RiskLevel>,
+        options: &ExecuteOptions,
+        reason: String,
+    ) -> anyhow::Error {
+        warn!("Refusing {} command '{}': {}", options.source.name(), command, reason);
+        self.update_stats(|stats| stats.safety_blocks += 1);
+        let action = action.map(|action| format!("{:?}", action));
+        if let Err(e) = self.confirmations.record_refusal(command, action.as_deref(), risk, options.source, options.client.as_deref(), &reason) {
+            warn!("Could not audit the refusal: {}", e);
+        }
+        LunaError::PermissionDenied(format!("[{}] {}", safety::SafetyCategory::Context, reason)).into()
+    }
+
+    /// Typing and cursor pacing at `speed`
+    fn pacing(&self, speed: f64) -> Pacing {
+        Pacing {
+            type_delay: scaled(self.config.input.type_delay_ms, speed),
+            move_duration: scaled(self.config.speed.move_duration_ms, speed),
+        }
+    }
+
+    /// Wait `duration` before the first action, giving up early if cancelled
+    fn countdown(&self, duration: Duration, cancel: Option<&CancelToken>, command: &str) -> Result<()> {
+        if duration.is_zero() {
+            return Ok(());
+        }
+        info!("Starting in {:.1}s", duration.as_secs_f64());
+        let deadline = Instant::now() + duration;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|r| !r.is_zero()) {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                return Err(LunaError::Cancelled(command.to_string()).into());
+            }
+            std::thread::sleep(remaining.min(Duration::from_millis(50)));
+        }
+        Ok(())
+    }
+
+    /// Re-capture the area around a click target and compare it to the analyzed
+    /// frame. Returns the changed fraction when it exceeds the configured threshold.
+    /// `force` checks even when the guard is disabled (after a session unlock).
+    fn stale_target(&mut self, action: &LunaAction, pipeline_skipped: bool, force: bool) -> Result<Option<f64>> {
+        let guard = self.config.stale_frame.clone();
+        let Some((x, y)) = action.click_point() else {
+            return Ok(None);
+        };
+        // Literal commands were never analyzed, so there is nothing to be stale against
+        if self.last_frame.is_none() || !(guard.enabled || force) || pipeline_skipped {
+            return Ok(None);
+        }
+        let region = self.stale_region(x, y);
+        let current = self.capture_area(&region)?;
+        let reference = match self.stale_reference.take() {
+            Some((settled, reference)) if settled == region => reference,
+            _ => match &self.last_frame {
+                Some(analyzed) => analyzed.crop(&region),
+                None => return Ok(None),
+            },
+        };
+        let whole = Rectangle::new(0.0, 0.0, region.width, region.height);
+        let changed = image_processing::changed_fraction(&reference, &current, &whole, guard.pixel_tolerance);
+        debug!("Target region of {:?} changed {:.1}% since analysis", action, changed * 100.0);
+        Ok((changed > guard.threshold).then_some(changed))
+    }
+
+    /// The area around a click target the stale-frame guard compares
+    fn stale_region(&self, x: i32, y: i32) -> Rectangle {
+        let margin = self.config.stale_frame.margin_px as f64;
+        Rectangle::new((x as f64 - margin).max(0.0), (y as f64 - margin).max(0.0), 2.0 * margin + 1.0, 2.0 * margin + 1.0)
+    }
+
+    /// Once an action has settled, remember how the next one's target looks,
+    /// so what the plan itself changed there isn't taken for a stale frame
+    fn refresh_stale_reference(&mut self, following: &LunaAction, pipeline_skipped: bool) {
+        self.stale_reference = None;
+        let Some((x, y)) = following.click_point() else {
+            return;
+        };
+        if self.last_frame.is_none() || pipeline_skipped {
+            return;
+        }
+        let region = self.stale_region(x, y);
+        match self.capture_area(&region) {
+            Ok(settled) => self.stale_reference = Some((region, settled)),
+            Err(e) => debug!("Could not capture the target of {:?} after the previous action: {}", following, e),
+        }
+    }
+
+    /// After the last scroll among `done`, the last analysis with the
+    /// elements of the scrolled area moved by how far its content moved, if
+    /// a sample of them is found at the new place (see `vision::scroll`).
+    /// `None` when the shift can't be measured or checked, and the screen
+    /// has to be analyzed again.
+    fn follow_scroll(&mut self, done: &[LunaAction]) -> Result<Option<ScreenAnalysis>> {
+        let tracking = self.config.scroll_tracking.clone();
+        let scrolled = done.iter().rev().find_map(|action| match action {
+            LunaAction::Scroll { direction, amount } if direction == "down" => Some(*amount),
+            LunaAction::Scroll { direction, amount } if direction == "up" => Some(-*amount),
+            _ => None,
+        });
+        let (Some(notches), Some(before), Some(mut analysis)) = (scrolled, self.last_frame.clone(), self.latest_analysis.clone()) else {
+            return Ok(None);
+        };
+        if !tracking.enabled {
+            return Ok(None);
+        }
+        let pointer = done.iter().rev().find_map(|action| match action {
+            LunaAction::Hover { x, y } => Some(geometry::Point::new(*x as f64, *y as f64)),
+            _ => None,
+        });
+        let area = scroll_area(&analysis, pointer);
+        let after = self.capture_screen()?;
+        let expected = notches * self.config.input.notch_pixels as i32;
+        let shift = crate::vision::scroll::estimate_shift(&before, &after, &area, area.height as usize, expected)
+            .filter(|shift| shift.correlation >= tracking.min_correlation);
+        let Some(shift) = shift else {
+            debug!("No scroll shift measured in {:?}", area);
+            self.metrics.increment("luna_scroll_tracking_total", &[("result", "unmeasured")]);
+            return Ok(None);
+        };
+
+        let analyzed = analysis.clone();
+        let moved = analysis.scroll_content(&area, shift.dy);
+        // Elements in view both before and after, largest first
+        let in_view = |b: &ElementBounds| {
+            let r = Rectangle::from(b);
+            area.intersection(&r).is_some_and(|i| i.area() >= r.area())
+        };
+        let mut samples: Vec<usize> = moved
+            .into_iter()
+            .filter(|&i| in_view(&analyzed.elements[i].bounds) && in_view(&analysis.elements[i].bounds))
+            .collect();
+        samples.sort_by_key(|&i| std::cmp::Reverse(analyzed.elements[i].bounds.width * analyzed.elements[i].bounds.height));
+        samples.truncate(tracking.samples);
+        let tolerance = self.config.stale_frame.pixel_tolerance;
+        let verified = !samples.is_empty()
+            && samples.iter().all(|&i| {
+                let bounds = Rectangle::from(&analyzed.elements[i].bounds);
+                crate::vision::scroll::shifted_match(&before, &after, &bounds, shift.dy, tolerance) >= tracking.min_match
+            });
+        self.metrics.increment("luna_scroll_tracking_total", &[("result", if verified { "verified" } else { "unverified" })]);
+        if !verified {
+            debug!("Scroll of {}px not confirmed by {} sampled element(s)", shift.dy, samples.len());
+            return Ok(None);
+        }
+        info!("Content scrolled {}px (correlation {:.2}); moved the analyzed elements instead of analyzing again", shift.dy, shift.correlation);
+        self.last_frame = Some(after);
+        self.track_elements(&analysis);
+        Ok(Some(analysis))
+    }
+
+    /// Plan against an analysis moved to match the screen by `follow_scroll`
+    fn plan_moved(&mut self, command: &str, options: &ExecuteOptions, analysis: &ScreenAnalysis) -> Result<Vec<LunaAction>> {
+        let actions = self.ai_coordinator.plan_actions_with_options(command, analysis, options)?;
+        let planned_by = provenance::PlannedBy::Analysis { thorough: false };
+        self.provenance = provenance::ActionProvenance::for_actions(&actions, planned_by, Some(analysis));
+        Ok(actions)
+    }
+
+    /// Before a click in a command with a destructive verb, read the screen
+    /// again and require the dialog the click lands in to say the same thing
+    fn verify_destructive_click(&mut self, command: &str, verb: &str, action: &LunaAction) -> Result<()> {
+        let LunaAction::Click { x, y } = action else {
+            return Ok(());
+        };
+        std::thread::sleep(Duration::from_millis(self.config.destructive_check.settle_ms));
+        let screenshot = self.capture_screen()?;
+        let analysis = self.detect(&to_dynamic_image(&screenshot)?)?;
+        let text = safety::confirmation_text(&analysis.elements, *x, *y);
+        if let Some(evidence) = self.safety_system.destructive_evidence(&text) {
+            info!("'{}' in '{}': click at ({}, {}) confirmed by {:?} in {:?}", verb, command, x, y, evidence, text);
+            return Ok(());
+        }
+        warn!("'{}' in '{}': nothing at ({}, {}) confirms it, read {:?}", verb, command, x, y, text);
+        self.update_stats(|stats| stats.safety_blocks += 1);
+        Err(LunaError::UnsafeAction(format!(
+            "[{}] click at ({}, {}) for '{}': the dialog there doesn't mention it ({:?})",
+            safety::SafetyCategory::Destructive, x, y, verb, text)).into())
+    }
+
+    /// Tag elements that have a click history with their reliability, so
+    /// ranking prefers the ones that have worked before
+    fn annotate_reliability(&mut self, analysis: &mut ScreenAnalysis) {
+        let Some(frame) = self.last_frame.as_ref().filter(|_| self.config.element_stats.enabled) else {
+            return;
+        };
+        for element in &mut analysis.elements {
+            if !self.element_history.knows(&element.element_type, element.text.as_deref()) {
+                continue;
+            }
+            let Some(fingerprint) = ElementFingerprint::capture(frame, element) else {
+                continue;
+            };
+            if let Some(reliability) = self.element_history.get(&fingerprint).map(|stats| stats.reliability()) {
+                element.attributes.insert(RELIABILITY_ATTRIBUTE.to_string(), format!("{:.2}", reliability));
+                self.element_history.mark_seen(&fingerprint);
+            }
+        }
+    }
+
+    /// Count how the click point of each element clicked in `actions` was
+    /// chosen. Clicks that aren't an element's click point (literal
+    /// coordinates, title bars grabbed to raise a window) are not counted.
+    fn record_click_strategies(&self, analysis: &ScreenAnalysis, actions: &[LunaAction]) {
+        for (x, y) in actions.iter().filter_map(LunaAction::click_point) {
+            let Some((element, strategy)) = analysis.elements.iter().find_map(|e| {
+                let (point, strategy) = e.click_target();
+                (point == (x, y)).then_some((e, strategy))
+            }) else {
+                continue;
+            };
+            debug!("Click on {} at ({}, {}) placed by {}", element.element_type, x, y, strategy.as_str());
+            self.metrics.increment("luna_click_strategy_total", &[("strategy", strategy.as_str())]);
+        }
+    }
+
+    /// Fingerprints of the innermost elements under the clicks in `actions`
+    fn click_targets(&self, analysis: &ScreenAnalysis, actions: &[LunaAction]) -> Vec<((i32, i32), ElementFingerprint)> {
+        let Some(frame) = self.last_frame.as_ref() else {
+            return Vec::new();
+        };
+        actions
+            .iter()
+            .filter_map(|action| {
+                let (x, y) = action.click_point()?;
+                let element = analysis
+                    .elements
+                    .iter()
+                    .filter(|e| e.bounds.contains_point(x, y))
+                    .min_by_key(|e| e.bounds.width as i64 * e.bounds.height as i64)?;
+                Some(((x, y), ElementFingerprint::capture(frame, element)?))
+            })
+            .collect()
+    }
+
+    fn click_target(&self, action: &LunaAction) -> Option<ElementFingerprint> {
+        let (x, y) = action.click_point()?;
+        self.click_targets.iter().find(|(point, _)| *point == (x, y)).map(|(_, fingerprint)| fingerprint.clone())
+    }
+
+    /// Wait for the screen to react to a click on `fingerprint`'s element and
+    /// record whether it did and how fast
+    fn verify_click(&mut self, fingerprint: &ElementFingerprint, before: &Image) {
+        let timeout = Duration::from_millis(self.config.element_stats.verify_timeout_ms);
+        let min_changed = self.config.element_stats.min_changed;
+        let tolerance = self.config.stale_frame.pixel_tolerance;
+        let screen = Rectangle::new(0.0, 0.0, before.width as f64, before.height as f64);
+        let clicked = Instant::now();
+        let effect_ms = loop {
+            let changed = self.capture_screen().ok()
+                .map(|after| image_processing::changed_fraction(before, &after, &screen, tolerance));
+            if changed.is_some_and(|changed| changed >= min_changed) {
+                break Some(clicked.elapsed().as_millis() as u64);
+            }
+            if clicked.elapsed() >= timeout {
+                break None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        match self.element_history.record(fingerprint, effect_ms) {
+            Ok(stats) => debug!("Click on {} {}: {}/{} verified",
+                fingerprint.element_type, if effect_ms.is_some() { "had an effect" } else { "had no effect" },
+                stats.successes, stats.clicks()),
+            Err(e) => warn!("Could not save element stats: {}", e),
+        }
+    }
+
+    /// Read back the field `text` was just typed into as action `index` and
+    /// type again what didn't arrive (see `typing`). Only text typed right
+    /// after clicking a field that the analysis found is checked; `earlier`
+    /// are the actions before it.
+    fn check_typing(&mut self, earlier: &[LunaAction], index: usize, text: &str, pipeline_skipped: bool) -> Result<Option<typing::TypingReport>> {
+        if !self.config.typing_check.enabled || pipeline_skipped {
+            return Ok(None);
+        }
+        let Some(LunaAction::Click { x, y }) = earlier.iter().rev().find(|a| !matches!(a, LunaAction::Wait { .. } | LunaAction::Hover { .. })) else {
+            return Ok(None);
+        };
+        let point = (*x, *y);
+        let Some(before) = self.latest_analysis.as_ref().and_then(|analysis| field_text(analysis, point)) else {
+            return Ok(None);
+        };
+        let mut report = typing::TypingReport { action: index, expected: before + text, read: None, verified: false, corrections: 0 };
+        // A reading with characters missing, waiting for a second one to agree
+        let mut unconfirmed: Option<String> = None;
+        loop {
+            std::thread::sleep(Duration::from_millis(self.config.typing_check.settle_ms));
+            let frame = self.capture_screen()?;
+            report.read = field_text(&self.analyze_frame(frame)?, point);
+            let Some(read) = report.read.as_deref() else {
+                warn!("The field typed into at ({}, {}) is no longer found; typing not verified", point.0, point.1);
+                break;
+            };
+            match typing::check_field(&report.expected, read) {
+                typing::FieldCheck::Matches => {
+                    report.verified = true;
+                    break;
+                }
+                typing::FieldCheck::Missing { .. } if unconfirmed.is_none() => {
+                    debug!("Field reads {:?} instead of {:?}; reading it again", read, report.expected);
+                    unconfirmed = Some(read.to_string());
+                }
+                typing::FieldCheck::Missing { erase, retype }
+                    if unconfirmed.as_deref() == Some(read) && report.corrections < self.config.typing_check.max_corrections =>
+                {
+                    info!("Field reads {:?} instead of {:?}; typing {:?} again", read, report.expected, retype);
+                    unconfirmed = None;
+                    report.corrections += 1;
+                    let mut corrections = vec![LunaAction::KeyCombo { keys: vec!["end".to_string()] }];
+                    corrections.extend((0..erase).map(|_| LunaAction::KeyCombo { keys: vec!["backspace".to_string()] }));
+                    corrections.push(LunaAction::Type { text: retype });
+                    for correction in &corrections {
+                        self.execute_with_retry(correction)?;
+                    }
+                }
+                _ => {
+                    warn!("Field reads {:?} instead of {:?}; left as it is", read, report.expected);
+                    break;
+                }
+            }
+        }
+        Ok(Some(report))
+    }
+
+    /// Check that a selection's clicks left highlighted exactly the items
+    /// they should have (see `selection::expected_selection`), starting from
+    /// what the analyzed frame showed selected. The items are the elements of
+    /// the type clicked first. Without any highlight in sight the selection
+    /// can't be judged, and only a warning is logged.
+    fn verify_selection(&mut self, actions: &[LunaAction]) -> Result<()> {
+        let analyzed = self.last_frame.take();
+        let frame = self.capture_screen()?;
+        let analysis = self.analyze_frame(frame.clone())?;
+        let Some(kind) = actions.iter().filter_map(LunaAction::click_point).find_map(|(x, y)| {
+            analysis.elements.iter()
+                .filter(|e| e.bounds.contains_point(x, y))
+                .min_by_key(|e| e.bounds.width as i64 * e.bounds.height as i64)
+                .map(|e| e.element_type.clone())
+        }) else {
+            return Ok(());
+        };
+        let items: Vec<&ScreenElement> = analysis.elements.iter().filter(|e| e.element_type == kind).collect();
+        let bounds: Vec<Rectangle> = items.iter().map(|e| Rectangle::from(&e.bounds)).collect();
+        let before: Vec<bool> = match &analyzed {
+            Some(analyzed) => bounds.iter().map(|b| selection::is_highlighted(analyzed, b)).collect(),
+            None => vec![false; bounds.len()],
+        };
+        let expected = selection::expected_selection(&bounds, &before, actions);
+        let highlighted: Vec<bool> = bounds.iter().map(|b| selection::is_highlighted(&frame, b)).collect();
+        if !highlighted.contains(&true) {
+            warn!("No selection highlight visible among {} {} item(s); selection not verified", items.len(), kind);
+            return Ok(());
+        }
+
+        let wrong: Vec<String> = items
+            .iter()
+            .zip(&expected)
+            .zip(&highlighted)
+            .filter(|((_, expected), highlighted)| expected != highlighted)
+            .map(|((item, _), highlighted)| format!("'{}' is {}", item.text.as_deref().unwrap_or(&kind),
+                if *highlighted { "selected" } else { "not selected" }))
+            .collect();
+        if wrong.is_empty() {
+            debug!("Selection verified: {} of {} {} item(s) highlighted", highlighted.iter().filter(|h| **h).count(), items.len(), kind);
+            Ok(())
+        } else {
+            Err(LunaError::Input(format!("selection did not come out as planned: {}", wrong.join(", "))).into())
+        }
+    }
+
+    /// Click history of the element `fingerprint` describes (see `fingerprint`)
+    pub fn element_stats(&self, fingerprint: &ElementFingerprint) -> Option<&element_stats::ElementStats> {
+        self.element_history.get(fingerprint)
+    }
+
+    /// Every element with a click history, most recently seen first
+    pub fn element_history(&self) -> &element_stats::ElementHistory {
+        &self.element_history
+    }
+
+    /// Disk usage of every managed store
+    pub fn storage_status(&self) -> Result<Vec<storage::StoreStatus>> {
+        self.storage.status()
+    }
+
+    /// Space freed since startup by removing temporary files that operations
+    /// and earlier runs left behind (see `scratch`)
+    pub fn reclaimed_space(&self) -> scratch::ReclaimedSpace {
+        self.scratch.reclaimed()
+    }
+
+    /// Run LRU cleanup on one store, or on every store when `store` is `None`
+    pub fn clean_storage(&self, store: Option<storage::StoreKind>) -> Result<Vec<(storage::StoreKind, storage::CleanReport)>> {
+        match store {
+            Some(kind) => Ok(vec![(kind, self.storage.clean(kind)?)]),
+            None => self.storage.clean_all(),
+        }
+    }
+
+    /// Check quotas, emit warnings and clean over-quota stores if enabled
+    pub fn run_storage_maintenance(&mut self) -> Result<()> {
+        self.last_storage_check = Some(Instant::now());
+
+        for warning in self.storage.check_quotas()? {
+            warn!("Storage '{}' at {} of {} bytes", warning.kind, warning.used_bytes, warning.quota_bytes);
+            self.emit_event(LunaEvent::StorageQuotaWarning {
+                store: warning.kind,
+                used_bytes: warning.used_bytes,
+                quota_bytes: warning.quota_bytes,
+            });
+        }
+
+        if self.config.storage.auto_clean {
+            for (kind, report) in self.storage.clean_all()? {
+                if report.files_removed > 0 {
+                    info!("Storage cleanup freed {} bytes from '{}' ({} files)",
+                          report.bytes_freed, kind, report.files_removed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn language_packs(&self) -> language_packs::LanguagePacks<'_> {
+        language_packs::LanguagePacks::new(&self.storage, self.config.ocr.catalog_url.as_deref())
+            .with_catalog_digest(self.config.ocr.catalog_sha256.as_deref())
+            .with_scratch(self.scratch.clone())
+    }
+
+    /// Installed OCR language packs and those the catalog offers
+    pub fn list_language_packs(&self) -> Result<Vec<language_packs::PackListing>> {
+        self.language_packs().list()
+    }
+
+    /// Download and verify a language pack into the model cache
+    pub fn install_language_pack(&self, language: &str) -> Result<language_packs::PackEntry> {
+        self.language_packs().install(language)
+    }
+
+    /// Keep a language pack through storage cleanup, or stop keeping it
+    pub fn pin_language_pack(&self, language: &str, pinned: bool) -> Result<()> {
+        self.language_packs().set_pinned(language, pinned)
+    }
+
+    /// Returns whether the pack was installed
+    pub fn remove_language_pack(&self, language: &str) -> Result<bool> {
+        self.language_packs().remove(language)
+    }
+
+    /// OCR languages for the app in front, downloading missing packs when
+    /// `ocr.auto_download` is on
+    pub fn ocr_languages(&self) -> Result<Vec<String>> {
+        let languages = language_packs::languages_for(&self.config.ocr, focus::active_window_title().as_deref());
+        if self.config.ocr.auto_download && self.config.ocr.catalog_url.is_some() {
+            for pack in self.language_packs().ensure(&languages)? {
+                info!("Downloaded {} OCR language pack for the active app", pack.name);
+            }
+        }
+        Ok(languages)
+    }
+
+    fn maybe_run_storage_maintenance(&mut self) {
+        let interval = Duration::from_secs(self.config.storage.check_interval_secs);
+        let due = self.last_storage_check.is_none_or(|last| last.elapsed() >= interval);
+        if due {
+            if let Err(e) = self.run_storage_maintenance() {
+                warn!("Storage maintenance failed: {}", e);
+            }
+        }
+    }
+
+    /// Analyze a screenshot saved as an image file; sets up neither screen
+    /// capture nor input
+    pub fn analyze_image_file(&mut self, path: &std::path::Path) -> Result<ScreenAnalysis> {
+        let rgb = image::open(path)?.to_rgb8();
+        self.analyze_frame(Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw()))
+    }
+
+    /// Analyze two screenshots of the same screen (two builds, say) and
+    /// compare them with the `compare` tolerances. Returns the differences and
+    /// the after screenshot with them outlined.
+    pub fn compare_image_files(
+        &mut self,
+        before: &std::path::Path,
+        after: &std::path::Path,
+    ) -> Result<(crate::ai::compare::ScreenComparison, Image)> {
+        let load = |path: &std::path::Path| -> Result<Image> {
+            let rgb = image::open(path)?.to_rgb8();
+            Ok(Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw()))
+        };
+        let (before_frame, after_frame) = (load(before)?, load(after)?);
+        let before_analysis = self.analyze_frame(before_frame.clone())?;
+        let after_analysis = self.analyze_frame(after_frame.clone())?;
+        let comparison = crate::ai::compare::compare_frames(
+            (&before_analysis, &before_frame),
+            (&after_analysis, &after_frame),
+            &self.config.compare,
+        );
+        let diff = crate::ai::compare::annotate(&after_frame, &comparison);
+        Ok((comparison, diff))
+    }
+
+    /// Map the UI of the app in front by hovering and scrolling, never
+    /// clicking (see `explore`). With `out`, each frame is saved there with
+    /// the elements first found in it outlined. With `seed_anchors`, named
+    /// elements visible without scrolling are remembered as anchors, except
+    /// where an anchor of that name exists. Lists scrolled through are
+    /// scrolled back and the pointer returned where it was.
+    pub fn explore(&mut self, out: Option<&std::path::Path>, seed_anchors: bool, cancel: &CancelToken) -> Result<explore::UiMap> {
+        let settings = self.config.explore.clone();
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(settings.budget_secs);
+        let settle = Duration::from_millis(settings.settle_ms);
+        let in_time = || !cancel.is_cancelled() && Instant::now() < deadline;
+        self.input_lease.ensure_owner()?;
+        let home = crate::input::cursor_position();
+        if let Some(dir) = out {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut map = explore::UiMap::new(focus::active_window_title());
+        let frame = self.capture_screen()?;
+        let base = self.analyze_frame(frame.clone())?;
+        let found = map.record(&base, explore::MappedFrame::default());
+        if let Some(dir) = out {
+            map.save_frame(dir, &frame, &found)?;
+        }
+        info!("Exploring {}: {} element(s) in view", map.app.as_deref().unwrap_or("the screen"), found.len());
+
+        let mut complete = true;
+        if settings.hover_unlabeled {
+            for element in map.unnamed() {
+                if !in_time() {
+                    complete = false;
+                    break;
+                }
+                let (x, y) = map.elements[element].center();
+                self.explore_input(LunaAction::Hover { x, y })?;
+                std::thread::sleep(settle);
+                let hovered = self.analyze_current_screen()?;
+                let bounds = &base.elements[map.elements[element].index].bounds;
+                if let Some(tip) = explore::tooltip(&base, &hovered, bounds) {
+                    debug!("Tooltip of {} at ({}, {}): {}", map.elements[element].element_type, x, y, tip);
+                    map.name_by_tooltip(element, &tip);
+                }
+            }
+        }
+
+        if seed_anchors {
+            for mapped in map.elements.iter().filter(|e| e.frame == 0) {
+                let Some(name) = mapped.name.as_deref().filter(|name| self.anchors.get(name).is_none()) else {
+                    continue;
+                };
+                let Some(fingerprint) = ElementFingerprint::capture(&frame, &base.elements[mapped.index]) else {
+                    continue;
+                };
+                let anchor = anchors::Anchor::new(name, fingerprint, map.app.clone());
+                map.anchors.push(anchor.name.clone());
+                self.anchors.insert(anchor)?;
+            }
+        }
+
+        for area in explore::scroll_areas(&base) {
+            if !in_time() {
+                complete = false;
+                break;
+            }
+            let center = area.center();
+            let (x, y) = (center.x.round() as i32, center.y.round() as i32);
+            let mut scrolled = 0;
+            for _ in 0..settings.max_scroll_steps {
+                if !in_time() {
+                    complete = false;
+                    break;
+                }
+                self.explore_input(LunaAction::Hover { x, y })?;
+                self.explore_input(LunaAction::Scroll { direction: "down".to_string(), amount: settings.scroll_step })?;
+                scrolled += settings.scroll_step;
+                std::thread::sleep(settle);
+                let frame = self.capture_screen()?;
+                let analysis = self.analyze_frame(frame.clone())?;
+                let scrolled_area = Some((area.x as i32, area.y as i32, area.width as i32, area.height as i32));
+                let found = map.record(&analysis, explore::MappedFrame { scrolled_area, scrolled, ..Default::default() });
+                if let Some(dir) = out {
+                    map.save_frame(dir, &frame, &found)?;
+                }
+                // The end of the list, or nothing more worth mapping in it
+                if found.is_empty() {
+                    break;
+                }
+            }
+            if scrolled > 0 {
+                self.explore_input(LunaAction::Hover { x, y })?;
+                self.explore_input(LunaAction::Scroll { direction: "up".to_string(), amount: scrolled })?;
+                std::thread::sleep(settle);
+            }
+        }
+
+        if let Some((x, y)) = home {
+            self.explore_input(LunaAction::Hover { x, y })?;
+        }
+        map.finish(started.elapsed(), complete);
+        info!("Mapped {} element(s) in {} frame(s) in {}ms", map.elements.len(), map.frames.len(), map.elapsed_ms);
+        Ok(map)
+    }
+
+    /// Perform a hover or scroll for `explore`, if safety allows it
+    fn explore_input(&mut self, action: LunaAction) -> Result<()> {
+        if !self.safety_system.is_action_safe(&action) {
+            return Err(LunaError::UnsafeAction(format!("{:?} while exploring", action)).into());
+        }
+        self.execute_with_retry(&action).map(|_| ())
+    }
+
+    /// Up to `n` commands worth offering for what is on screen now: closing
+    /// the dialog in front, clicking the most prominent named controls, and
+    /// commands recently run in the same window (see `ai::suggest`). The
+    /// screen is analyzed again when the foreground window changed since
+    /// the last suggestions; otherwise the latest analysis is used.
+    pub fn suggest_commands(&mut self, n: usize) -> Result<Vec<suggest::Suggestion>> {
+        let window = focus::active_window_title();
+        let analysis = match &self.latest_analysis {
+            Some(analysis) if self.suggested_for.as_ref() == Some(&window) => analysis.clone(),
+            _ => self.analyze_current_screen()?,
+        };
+        self.suggested_for = Some(window.clone());
+        let history: Vec<String> =
+            self.recent_commands.iter().rev().filter(|(_, ran_in)| *ran_in == window).map(|(command, _)| command.clone()).collect();
+        Ok(suggest::merge(suggest::from_screen(&analysis), &history, n))
+    }
+
+    fn remember_command(&mut self, command: &str, window: Option<String>) {
+        if self.recent_commands.len() >= SUGGESTION_HISTORY_LEN {
+            self.recent_commands.pop_front();
+        }
+        self.recent_commands.push_back((command.trim().to_string(), window));
+    }
+
+    /// Get current screen analysis without executing actions
+    pub fn analyze_current_screen(&mut self) -> Result<ScreenAnalysis> {
+        let screenshot = self.capture_screen()?;
+        self.analyze_frame(screenshot)
+    }
+
+    /// Analyze the screen ahead of the next command, when pre-analysis is
+    /// enabled. Called while idle; a screen that has not changed since the
+    /// last pre-analysis is not analyzed again. Returns whether a new
+    /// analysis was made.
+    pub fn pre_analyze(&mut self) -> Result<bool> {
+        if !self.config.pre_analysis.enabled || !self.session_state().is_active() {
+            return Ok(false);
+        }
+        // Degraded subsystems only do what a command needs
+        let degraded = self.heartbeats.degraded();
+        if degraded.contains(&watchdog::Subsystem::Capture) || degraded.contains(&watchdog::Subsystem::Inference) {
+            return Ok(false);
+        }
+        let frame = self.capture_screen()?;
+        let window = focus::active_window_title();
+        if self.warm.as_ref().is_some_and(|warm| self.is_warm_fresh(warm, &frame, &window)) {
+            return Ok(false);
+        }
+        let mut analysis = self.detect(&to_dynamic_image(&frame)?)?;
+        self.annotate_reliability(&mut analysis);
+        debug!("Pre-analyzed {} elements while idle", analysis.elements.len());
+        self.warm = Some(WarmAnalysis { frame, analysis, window, at: Instant::now() });
+        Ok(true)
+    }
+
+    /// The idle-time analysis, if it is still what `screenshot` shows
+    fn take_warm_analysis(&mut self, screenshot: &Image) -> Option<ScreenAnalysis> {
+        let warm = self.warm.take().filter(|_| self.config.pre_analysis.enabled)?;
+        let fresh = self.is_warm_fresh(&warm, screenshot, &focus::active_window_title());
+        let result = if fresh { "hit" } else { "miss" };
+        self.metrics.increment("luna_pre_analysis_total", &[("result", result)]);
+        debug!("Pre-analysis {}, {}ms old", result, warm.at.elapsed().as_millis());
+        fresh.then_some(warm.analysis)
+    }
+
+    /// Whether a warm analysis is recent, of the same window and of a frame
+    /// identical to `frame`
+    fn is_warm_fresh(&self, warm: &WarmAnalysis, frame: &Image, window: &Option<String>) -> bool {
+        let screen = Rectangle::new(0.0, 0.0, frame.width as f64, frame.height as f64);
+        warm.at.elapsed() < Duration::from_millis(self.config.pre_analysis.max_age_ms)
+            && warm.window == *window
+            && (warm.frame.width, warm.frame.height) == (frame.width, frame.height)
+            && image_processing::changed_fraction(&warm.frame, frame, &screen, self.config.stale_frame.pixel_tolerance) == 0.0
+    }
+
+    fn analyze_frame(&mut self, screenshot: Image) -> Result<ScreenAnalysis> {
+        let dynamic_image = to_dynamic_image(&screenshot)?;
+        let analysis = self.detect(&dynamic_image)?;
+        self.last_frame = Some(screenshot);
+        self.track_elements(&analysis);
+        Ok(analysis)
+    }
+
+    /// Carry element IDs over to `analysis` and announce what changed
+    fn track_elements(&mut self, analysis: &ScreenAnalysis) {
+        let delta = self.tracker.update(&analysis.elements);
+        if !delta.is_empty() {
+            self.emit_event(LunaEvent::ElementsChanged { delta });
+        }
+        self.latest_analysis = Some(analysis.clone());
+    }
+
+    /// Every element of the last analysis under its stable ID, for an
+    /// `ElementsChanged` listener starting out or one that missed a delta
+    pub fn element_snapshot(&self) -> tracker::ElementDelta {
+        self.tracker.snapshot()
+    }
+
+    /// Plan `command` against a recorded `frame`, with `detector` standing in
+    /// for the model, and run the safety checks, without executing anything.
+    /// Deterministic for a given frame and detector output.
+    pub fn plan_command(&mut self, command: &str, frame: &Image, detector: &mut dyn ElementDetector) -> Result<Vec<LunaAction>> {
+        if !self.safety_system.is_command_safe(command) {
+            return Err(LunaError::UnsafeCommand(command.to_string()).into());
+        }
+        self.provenance.clear();
+        let actions = match self.ai_coordinator.plan_direct_actions(command) {
+            Some(actions) => {
+                self.ai_coordinator.ensure_executable(&actions)?;
+                actions
+            }
+            None => {
+                let analysis = self.ai_coordinator.analyze_with(&to_dynamic_image(frame)?, detector)?;
+                self.ai_coordinator.plan_actions(command, &analysis).map_err(|e| match e.downcast::<Clarification>() {
+                    Ok(clarification) => LunaError::NeedsClarification(clarification.question).into(),
+                    Err(e) => e,
+                })?
+            }
+        };
+        self.validate_actions(command, &actions, &ExecuteOptions::default())?;
+        Ok(actions)
+    }
+
+    /// Analyze the screen continuously. Frames are captured every `interval`
+    /// on a separate thread and only the latest one is analyzed; frames that
+    /// arrive while analysis is busy, or while the session is locked, are
+    /// dropped, never queued. Runs until
+    /// `on_analysis` returns false or `cancel` is set.
+    pub fn watch_screen(
+        &mut self,
+        interval: Duration,
+        cancel: &CancelToken,
+        mut on_analysis: impl FnMut(&mut Self, &ScreenAnalysis, &frames::FrameMetrics) -> bool,
+    ) -> Result<frames::FrameMetrics> {
+        let slot = Arc::new(frames::FrameSlot::new());
+        let channel = self.ensure_capture()?.frame_channel().cloned();
+        // A producer the watchdog found hung is abandoned, and a new one
+        // takes over the slot
+        let spawn_producer = |heartbeats: watchdog::Heartbeats| {
+            let slot = Arc::clone(&slot);
+            let cancel = cancel.clone();
+            let channel = channel.clone();
+            let retired = CancelToken::new();
+            let thread = std::thread::spawn({
+                let retired = retired.clone();
+                move || {
+                    let mut capture = ScreenCapture::new(CaptureConfig::default());
+                    capture.share_frames(channel);
+                    while !cancel.is_cancelled() && !retired.is_cancelled() {
+                        let started = Instant::now();
+                        let captured = {
+                            let _busy = heartbeats.busy_cancellable(watchdog::Subsystem::Capture, retired.clone());
+                            capture.capture_screen()
+                        };
+                        if retired.is_cancelled() {
+                            return;
+                        }
+                        match captured {
+                            Ok(frame) => {
+                                if !slot.offer(frame) {
+                                    break;
+                                }
+                            }
+                            Err(e) => warn!("Continuous capture failed: {}", e),
+                        }
+                        std::thread::sleep(interval.saturating_sub(started.elapsed()));
+                    }
+                    slot.close();
+                }
+            });
+            (thread, retired)
+        };
+        let (mut producer, mut retired) = spawn_producer(self.heartbeats.clone());
+
+        let mut reported_drops = 0;
+        let result = loop {
+            if cancel.is_cancelled() {
+                break Ok(());
+            }
+            // The watchdog cancels the token of a capture that stalls
+            if retired.is_cancelled() {
+                warn!("Replacing the hung continuous capture thread");
+                retired.cancel();
+                (producer, retired) = spawn_producer(self.heartbeats.clone());
+            }
+            let Some(frame) = slot.take(Duration::from_millis(100)) else {
+                if slot.is_closed() {
+                    break Ok(());
+                }
+                continue;
+            };
+            // Frames of the lock screen are dropped; analysis resumes on unlock
+            if self.config.session.pause_when_locked && !self.session_state().is_active() {
+                continue;
+            }
+            let started = Instant::now();
+            let analysis = match self.analyze_frame(frame) {
+                Ok(analysis) => analysis,
+                Err(e) => break Err(e),
+            };
+            slot.record_analysis(started.elapsed());
+
+            let metrics = slot.metrics();
+            self.update_stats(|stats| stats.frames_dropped += metrics.dropped - reported_drops);
+            reported_drops = metrics.dropped;
+            if !on_analysis(self, &analysis, &metrics) {
+                break Ok(());
+            }
+        };
+
+        slot.close();
+        let _ = producer.join();
+        let metrics = slot.metrics();
+        self.update_stats(|stats| stats.frames_dropped += metrics.dropped - reported_drops);
+        result.map(|_| metrics)
+    }
+
+    /// Analyze the screen and return the elements matching a query such as
+    /// `"button:submit"` (see `query::ElementQuery`)
+    pub fn find(&mut self, query: &str) -> Result<Vec<ScreenElement>> {
+        let query = query::ElementQuery::parse(query);
+        let analysis = self.analyze_current_screen()?;
+        Ok(analysis.elements.into_iter().filter(|e| query.matches(e)).collect())
+    }
+
+    /// Wait up to `timeout` for an element matching `query` (see `find`);
+    /// `None` if none appeared
+    pub fn wait_for_element(&mut self, query: &str, timeout: Duration) -> Result<Option<ScreenElement>> {
+        let query = query::ElementQuery::parse(query);
+        let mut found = None;
+        self.poll_analysis(timeout, |analysis| {
+            found = analysis.elements.iter().find(|e| query.matches(e)).cloned();
+            match found {
+                Some(_) => WaitCheck::Done,
+                None => WaitCheck::Watch(Rectangle::new(0.0, 0.0, analysis.screen_size.0 as f64, analysis.screen_size.1 as f64)),
+            }
+        })?;
+        Ok(found)
+    }
+
+    /// Wait up to `timeout` until no element matches `text` (a `find` query);
+    /// returns whether it went. Only the regions it was last seen in are
+    /// watched, so a busy screen elsewhere doesn't trigger re-analysis.
+    pub fn wait_for_text_gone(&mut self, text: &str, timeout: Duration) -> Result<bool> {
+        let query = query::ElementQuery::parse(text);
+        self.poll_analysis(timeout, |analysis| {
+            let matches: Vec<Rectangle> = analysis.elements.iter()
+                .filter(|e| query.matches(e))
+                .map(|e| Rectangle::from(&e.bounds))
+                .collect();
+            match matches.into_iter().reduce(|a, b| a.union(&b)) {
+                Some(region) => WaitCheck::Watch(region),
+                None => WaitCheck::Done,
+            }
+        })
+    }
+
+    /// Wait up to `timeout` until no pixel has changed for `quiet`; returns
+    /// whether the screen settled. Only captures, never analyzes.
+    pub fn wait_for_screen_idle(&mut self, quiet: Duration, timeout: Duration) -> Result<bool> {
+        let started = Instant::now();
+        let tolerance = self.config.stale_frame.pixel_tolerance;
+        let mut last = self.capture_screen()?;
+        let mut changed_at = Instant::now();
+        loop {
+            if changed_at.elapsed() >= quiet {
+                return Ok(true);
+            }
+            if started.elapsed() >= timeout {
+                return Ok(false);
+            }
+            std::thread::sleep(Duration::from_millis(IDLE_POLL_MS).min(quiet));
+            let frame = self.capture_screen()?;
+            let screen = Rectangle::new(0.0, 0.0, frame.width as f64, frame.height as f64);
+            if (frame.width, frame.height) != (last.width, last.height)
+                || image_processing::changed_fraction(&last, &frame, &screen, tolerance) > 0.0
+            {
+                changed_at = Instant::now();
+            }
+            last = frame;
+        }
+    }
+
+    /// Analyze the screen until `check` is done with an analysis or `timeout`
+    /// passes; returns whether it finished. A frame in which the region the
+    /// last check watched is unchanged isn't analyzed again, so waiting on a
+    /// still screen costs captures, not detections.
+    fn poll_analysis(&mut self, timeout: Duration, mut check: impl FnMut(&ScreenAnalysis) -> WaitCheck) -> Result<bool> {
+        let started = Instant::now();
+        let tolerance = self.config.stale_frame.pixel_tolerance;
+        let mut watched: Option<(Image, Rectangle)> = None;
+        loop {
+            let frame = self.capture_screen()?;
+            let unchanged = watched.as_ref().is_some_and(|(last, region)| {
+                (last.width, last.height) == (frame.width, frame.height)
+                    && image_processing::changed_fraction(last, &frame, region, tolerance) == 0.0
+            });
+            if !unchanged {
+                let analysis = self.analyze_frame(frame.clone())?;
+                match check(&analysis) {
+                    WaitCheck::Done => return Ok(true),
+                    WaitCheck::Watch(region) => watched = Some((frame, region)),
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Ok(false);
+            }
+            std::thread::sleep(Duration::from_millis(WAIT_POLL_MS));
+        }
+    }
+
+    /// Analyze the neighbourhood of `cursor` and report the element under it
+    /// (see `spy`)
+    pub fn spy_at(&mut self, cursor: (i32, i32)) -> Result<spy::SpyReport> {
+        let region = spy::region_around(cursor);
+        let crop = self.screenshot(Some(&region))?;
+        let mut analysis = self.detect(&to_dynamic_image(&crop)?)?;
+        spy::to_screen(&mut analysis, (region.x.max(0), region.y.max(0)));
+        Ok(spy::SpyReport::from_analysis(cursor, &analysis))
+    }
+
+    /// Spy on the mouse cursor: report the element under it every `interval`
+    /// while it moves. Runs until `on_report` returns false or `cancel` is set.
+    pub fn spy(
+        &mut self,
+        interval: Duration,
+        cancel: &CancelToken,
+        mut on_report: impl FnMut(&spy::SpyReport) -> bool,
+    ) -> Result<()> {
+        let mut last = None;
+        while !cancel.is_cancelled() {
+            let started = Instant::now();
+            let cursor = crate::input::cursor_position()
+                .ok_or_else(|| LunaError::Input("cursor position is not available on this platform".to_string()))?;
+            if last != Some(cursor) {
+                last = Some(cursor);
+                if !on_report(&self.spy_at(cursor)?) {
+                    break;
+                }
+            }
+            std::thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+        Ok(())
+    }
+
+    /// Color of the screen pixel at (`x`, `y`)
+    pub fn pixel_at(&mut self, x: i32, y: i32) -> Result<Rgb> {
+        let screenshot = self.capture_screen()?;
+        usize::try_from(x).ok().zip(usize::try_from(y).ok())
+            .and_then(|(x, y)| color::pixel_at(&screenshot, x, y))
+            .ok_or_else(|| LunaError::InvalidArgument(format!("({}, {}) is outside the screen", x, y)).into())
+    }
+
+    /// Bounds of the on-screen patches within `tolerance` (per channel) of `color`
+    pub fn find_color_regions(&mut self, color: Rgb, tolerance: u8) -> Result<Vec<Rectangle>> {
+        let screenshot = self.capture_screen()?;
+        Ok(color::find_color_regions(&screenshot, color, tolerance))
+    }
+
+    /// Capture the screen, or just `region` of it
+    pub fn screenshot(&mut self, region: Option<&ElementBounds>) -> Result<Image> {
+        let screenshot = self.capture_screen()?;
+        let Some(region) = region else {
+            return Ok(screenshot);
+        };
+        let (x0, y0) = (region.x.max(0), region.y.max(0));
+        let x1 = (region.x + region.width).min(screenshot.width as i32);
+        let y1 = (region.y + region.height).min(screenshot.height as i32);
+        if x1 <= x0 || y1 <= y0 {
+            return Err(LunaError::InvalidArgument(format!("region {:?} is outside the screen", region)).into());
+        }
+        Ok(screenshot.crop(&Rectangle::new(x0 as f64, y0 as f64, (x1 - x0) as f64, (y1 - y0) as f64)))
+    }
+
+    /// Capture the screen or `region` of it as PNG, onto the clipboard or
+    /// into a file
+    pub fn save_screenshot(&mut self, region: Option<&ElementBounds>, target: &ShotTarget) -> Result<()> {
+        let png = encode_png(&self.screenshot(region)?)?;
+        match target {
+            ShotTarget::File(path) => {
+                std::fs::write(path, png)?;
+                info!("Screenshot saved to {}", path.display());
+            }
+            ShotTarget::Clipboard => {
+                if !crate::input::copy_image_to_clipboard(&png) {
+                    return Err(LunaError::Input("no clipboard tool accepted the screenshot".to_string()).into());
+                }
+                info!("Screenshot copied to the clipboard");
+            }
+        }
+        Ok(())
+    }
+
+    /// Fingerprint an element from the most recently analyzed frame so it can
+    /// be found again with `find_again` after the layout changes
+    pub fn fingerprint(&self, element: &ScreenElement) -> Result<ElementFingerprint> {
+        let frame = self.last_frame.as_ref()
+            .ok_or_else(|| LunaError::NotFound("no analyzed frame to fingerprint".to_string()))?;
+        ElementFingerprint::capture(frame, element).ok_or_else(|| {
+            LunaError::InvalidArgument(format!("element bounds {:?} are outside the frame or too small", element.bounds)).into()
+        })
+    }
+
+    /// Re-locate a fingerprinted element on the current screen.
+    ///
+    /// Searches the raw frame by appearance first and only runs full screen
+    /// analysis when that finds nothing.
+    pub fn find_again(&mut self, fingerprint: &ElementFingerprint) -> Result<Option<ScreenElement>> {
+        let screenshot = self.capture_screen()?;
+
+        if let Some(found) = fingerprint::locate(&screenshot, fingerprint) {
+            debug!("Re-located {} by fingerprint at {:?}", fingerprint.element_type, found.bounds);
+            self.last_frame = Some(screenshot);
+            self.metrics.increment("luna_fingerprint_lookups_total", &[("result", "hit")]);
+            return Ok(Some(fingerprinted_element(fingerprint, found, "fingerprint")));
+        }
+
+        debug!("Fingerprint search failed, falling back to full analysis");
+        let analysis = self.detect(&to_dynamic_image(&screenshot)?)?;
+        let found = fingerprint::best_match(&screenshot, &analysis.elements, fingerprint)
+            .map(|(element, found)| {
+                let mut element = element.clone();
+                element.attributes.insert("located_by".to_string(), "analysis".to_string());
+                element.attributes.insert("hash_distance".to_string(), found.hash_distance.to_string());
+                element
+            });
+        self.last_frame = Some(screenshot);
+        let result = if found.is_some() { "fallback" } else { "miss" };
+        self.metrics.increment("luna_fingerprint_lookups_total", &[("result", result)]);
+        Ok(found)
+    }
+
+    /// Report that LUNA chose the wrong element on the most recent frame.
+    ///
+    /// When `training.export_corrections` is enabled the correction is saved as a
+    /// labeled training sample; returns the record unless it was a duplicate.
+    pub fn report_correction(&mut self, correction: Correction) -> Result<Option<ExportRecord>> {
+        info!("Correction reported for '{}': {}", correction.command, correction.correct_label);
+
+        let record = if self.config.training.export_corrections {
+            let frame = self.last_frame.take()
+                .ok_or_else(|| LunaError::NotFound("no captured frame to export a correction from".to_string()))?;
+            let exported = self.training_exporter().and_then(|exporter| exporter.export(&frame, &correction));
+            self.last_frame = Some(frame);
+            exported?
+        } else {
+            None
+        };
+
+        self.emit_event(LunaEvent::CorrectionReported {
+            command: correction.command,
+            label: correction.correct_label,
+            exported: record.is_some(),
+        });
+        Ok(record)
+    }
+
+    /// Write a COCO annotation file over all exported correction samples
+    pub fn export_training_coco(&self) -> Result<std::path::PathBuf> {
+        let training = &self.config.training;
+        TrainingExporter::new(
+            self.storage.store_path(storage::StoreKind::TrainingData)?,
+            training.context_margin,
+            training.redact,
+            &training.redact_patterns,
+        )?
+        .write_coco()
+    }
+
+    /// The exporter for correction samples, created on first use
+    fn training_exporter(&mut self) -> Result<&mut TrainingExporter> {
+        if self.training_exporter.is_none() {
+            let training = &self.config.training;
+            self.training_exporter = Some(TrainingExporter::new(
+                self.storage.store_path(storage::StoreKind::TrainingData)?,
+                training.context_margin,
+                training.redact,
+                &training.redact_patterns,
+            )?
+            .with_scratch(self.scratch.clone()));
         }
+        Ok(self.training_exporter.as_mut().expect("exporter was just created"))
+    }
 
-        // Step 6: Execute actions
-        for action in &actions {
-            match self.execute_single_action(action) {
-                Ok(_) => {
-                    debug!("Action executed successfully: {:?}", action);
-                    self.emit_event(LunaEvent::ActionExecuted { 
-                        action: action.clone(), 
-                        success: true 
-                    });
-                }
-                Err(e) => {
-                    error!("Failed to execute action {:?}: {}", action, e);
-                    self.emit_event(LunaEvent::ActionExecuted { 
-                        action: action.clone(), 
-                        success: false 
-                    });
-                    return Err(e);
-                }
+    /// Detections waiting for review, oldest first
+    pub fn review_queue(&self) -> &[review::ReviewItem] {
+        self.review.pending()
+    }
+
+    /// Where the crop around a queued detection is saved, for showing it
+    pub fn review_crop_path(&self, id: u64) -> Option<PathBuf> {
+        self.review.crop_path(self.review.get(id)?)
+    }
+
+    /// Confirm, relabel or reject a queued detection. A relabel is saved as
+    /// a correction sample when `training.export_corrections` is on; every
+    /// verdict counts toward the learned threshold of the item's app.
+    pub fn resolve_review(&mut self, id: u64, verdict: review::Verdict) -> Result<review::ReviewOutcome> {
+        let item = self.review.get(id).cloned()
+            .ok_or_else(|| LunaError::NotFound(format!("no review item #{}", id)))?;
+        let mut exported = false;
+        if let review::Verdict::Relabel { label } = &verdict {
+            if self.config.training.export_corrections {
+                let crop = self.review.load_crop(&item)?;
+                let correction = Correction {
+                    command: item.command.clone(),
+                    predicted: Some(ScreenElement {
+                        element_type: item.element_type.clone(),
+                        bounds: item.bounds_in_crop(),
+                        shape: None,
+                        confidence: item.confidence,
+                        text: item.text.clone(),
+                        attributes: std::collections::HashMap::new(),
+                        parent: None,
+                        children: Vec::new(),
+                    }),
+                    correct_bounds: item.bounds_in_crop(),
+                    correct_label: label.clone(),
+                };
+                exported = self.training_exporter()?.export(&crop, &correction)?.is_some();
             }
-            
-            // Small delay between actions for stability
-            std::thread::sleep(Duration::from_millis(50));
+            self.emit_event(LunaEvent::CorrectionReported { command: item.command.clone(), label: label.clone(), exported });
         }
 
-        // Update statistics
-        let processing_time = start_time.elapsed();
-        let processing_time_ms = processing_time.as_millis() as u64;
-        
-        self.update_stats(|stats| {
-            stats.commands_processed += 1;
-            stats.actions_executed += actions.len() as u64;
-            stats.total_processing_time_ms += processing_time_ms;
-            stats.average_processing_time_ms = 
-                stats.total_processing_time_ms as f64 / stats.commands_processed as f64;
-        });
+        let item = self.review.resolve(id, &verdict)?;
+        info!("Reviewed {}: {:?}", item, verdict);
+        let threshold = self.review
+            .verdicts(item.app.as_deref())
+            .and_then(|verdicts| verdicts.threshold(self.config.vision.confidence_threshold, self.config.review.min_verdicts));
+        Ok(review::ReviewOutcome { item, exported, threshold })
+    }
 
-        info!("Command processed successfully in {}ms: {} actions executed", 
-              processing_time_ms, actions.len());
+    /// Detection thresholds learned per app from review verdicts; "" is
+    /// for detections whose app was unknown
+    pub fn learned_app_thresholds(&self) -> Vec<(String, f32)> {
+        self.review.thresholds(self.config.vision.confidence_threshold, self.config.review.min_verdicts)
+    }
 
-        Ok(actions)
+    /// The configured thresholds with the detection threshold learned for
+    /// `app`, when reviewing adapts them and it has enough verdicts
+    fn learned_thresholds(&self, app: Option<&str>) -> Option<ConfidenceThresholds> {
+        let review = &self.config.review;
+        if !review.enabled || !review.adapt_thresholds {
+            return None;
+        }
+        let configured = self.ai_coordinator.thresholds();
+        let detection = self.review.verdicts(app)?.threshold(configured.detection, review.min_verdicts)?;
+        (detection != configured.detection).then_some(ConfidenceThresholds { detection, ..configured })
     }
 
-    /// Get current screen analysis without executing actions
-    pub fn analyze_current_screen(&mut self) -> Result<ScreenAnalysis> {
-        let screenshot = self.screen_capture.capture_screen()?;
-        let dynamic_image = to_dynamic_image(&screenshot)?;
-        self.ai_coordinator.analyze_screen(&dynamic_image)
+    /// Queue what a plan leans on but may have wrong: click targets detected
+    /// with low confidence, or every element a question was asked about
+    fn queue_for_review(&mut self, command: &str, app: Option<&str>, analysis: &ScreenAnalysis) {
+        let doubtful: Vec<(review::ReviewReason, usize)> = match &self.pending_clarification {
+            Some(pending) => pending.clarification.options.iter().map(|option| (review::ReviewReason::Ambiguous, option.element)).collect(),
+            None => self
+                .provenance
+                .iter()
+                .filter_map(|chain| chain.element.as_ref())
+                .filter(|element| element.confidence < self.config.review.confidence_below)
+                .filter_map(|element| Some((review::ReviewReason::LowConfidence, element.detection?)))
+                .collect(),
+        };
+        let Some(frame) = &self.last_frame else {
+            return;
+        };
+        let mut queued = Vec::new();
+        for (reason, index) in doubtful {
+            let Some(element) = analysis.elements.get(index) else {
+                continue;
+            };
+            let item = review::ReviewItem::new(reason, command, app, element);
+            match self.review.push(item, frame, self.config.training.context_margin) {
+                Ok(Some(item)) => {
+                    debug!("Queued for review: {}", item);
+                    queued.push((item.id, reason));
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Could not queue a detection for review: {}", e),
+            }
+        }
+        let pending = self.review.pending().len();
+        for (id, reason) in queued {
+            self.emit_event(LunaEvent::ReviewQueued { id, reason, pending });
+        }
     }
 
-    /// Execute one planned action through the guarded input layer
-    fn execute_single_action(&mut self, action: &LunaAction) -> Result<()> {
-        if let LunaAction::Wait { milliseconds } = action {
-            std::thread::sleep(Duration::from_millis(*milliseconds));
-            return Ok(());
+    /// Execute one planned action under its type's retry policy; returns
+    /// how many retries it took. Only transient failures are retried, and
+    /// with `verify_before_retry` only when the failed attempt left the
+    /// screen unchanged, so input that got through is never repeated.
+    fn execute_with_retry(&mut self, action: &LunaAction) -> Result<u32> {
+        let kind = action_kind(action);
+        let policy = self.config.retry.policy(kind);
+        let mut retries = 0;
+        loop {
+            let retry_left = retries + 1 < policy.max_attempts;
+            let before = (retry_left && policy.verify_before_retry)
+                .then(|| self.capture_screen().ok())
+                .flatten();
+            let error = match self.execute_single_action(action) {
+                Ok(()) => return Ok(retries),
+                Err(e) if retry_left && is_transient(&e) => e,
+                Err(e) => return Err(e),
+            };
+            std::thread::sleep(policy.backoff(retries + 1));
+            if policy.verify_before_retry {
+                let changed = before.as_ref().and_then(|before| {
+                    let after = self.capture_screen().ok()?;
+                    let screen = Rectangle::new(0.0, 0.0, before.width as f64, before.height as f64);
+                    Some(image_processing::changed_fraction(before, &after, &screen, self.config.stale_frame.pixel_tolerance))
+                });
+                // Any change may be the attempt's doing; a blinking caret only costs a retry
+                if changed.is_none_or(|changed| changed > 0.0) {
+                    warn!("Not retrying {:?}: the failed attempt may have had an effect", action);
+                    return Err(error);
+                }
+            }
+            retries += 1;
+            warn!("Retrying {:?} ({} of {}) after: {}", action, retries, policy.max_attempts - 1, error);
+            self.metrics.increment("luna_action_retries_total", &[("type", kind)]);
         }
+    }
 
-        let input_action = to_input_action(action)?;
-        self.input_system.execute_action(input_action)?;
-        Ok(())
+    /// Execute one planned action through the guarded input layer, once
+    fn execute_single_action(&mut self, action: &LunaAction) -> Result<()> {
+        let kind = action_kind(action);
+        self.recover_stalled(watchdog::Subsystem::Input);
+        let mut send = |action: &LunaAction| -> Result<()> {
+            let input_action = to_input_action(action)?;
+            let _busy = self.heartbeats.busy(watchdog::Subsystem::Input);
+            Ok(self.ensure_input().execute_action(input_action)?)
+        };
+        let result = match action {
+            LunaAction::Wait { milliseconds } => {
+                std::thread::sleep(Duration::from_millis(*milliseconds));
+                Ok(())
+            }
+            LunaAction::Screenshot { region, target } => self.save_screenshot(region.as_ref(), target),
+            LunaAction::PasteImage { path } => load_clipboard_image(path).and_then(|()| send(action)),
+            LunaAction::WaitForElement { query, timeout_ms } => {
+                match self.wait_for_element(query, Duration::from_millis(*timeout_ms)) {
+                    Ok(Some(_)) => Ok(()),
+                    Ok(None) => Err(LunaError::Timeout(format!("no \"{}\" appeared within {}ms", query, timeout_ms)).into()),
+                    Err(e) => Err(e),
+                }
+            }
+            LunaAction::WaitForTextGone { text, timeout_ms } => {
+                match self.wait_for_text_gone(text, Duration::from_millis(*timeout_ms)) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(LunaError::Timeout(format!("\"{}\" still on screen after {}ms", text, timeout_ms)).into()),
+                    Err(e) => Err(e),
+                }
+            }
+            LunaAction::WaitForScreenIdle { quiet_ms, timeout_ms } => {
+                match self.wait_for_screen_idle(Duration::from_millis(*quiet_ms), Duration::from_millis(*timeout_ms)) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(LunaError::Timeout(format!("screen not idle for {}ms within {}ms", quiet_ms, timeout_ms)).into()),
+                    Err(e) => Err(e),
+                }
+            }
+            _ => send(action),
+        };
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.metrics.increment("luna_input_actions_total", &[("type", kind), ("outcome", outcome)]);
+        result
     }
 
     /// Subscribe to Luna events
@@ -249,16 +3221,547 @@ impl Luna {
             .clone()
     }
 
+    /// Practice in a simulated desktop from now on: capture shows `scene`,
+    /// input lands in it and analysis reads its layout. Neither the real
+    /// screen nor real input is touched again by this instance.
+    pub fn enter_sandbox(&mut self, scene: sandbox::SandboxScene) -> sandbox::Sandbox {
+        let sandbox = sandbox::Sandbox::new(scene);
+        // Capture and input are built now only to be pointed at the sandbox;
+        // a frame channel that cannot be created leaves capture to its default
+        match self.ensure_capture() {
+            Ok(capture) => capture.set_source(Some(sandbox.frame_source())),
+            Err(e) => {
+                warn!("Frame channel unavailable in the sandbox: {}", e);
+                let mut capture = ScreenCapture::new(CaptureConfig::default());
+                capture.set_source(Some(sandbox.frame_source()));
+                self.screen_capture = Some(capture);
+            }
+        }
+        self.ensure_input().set_sink(Some(sandbox.input_sink()));
+        self.ai_coordinator.set_detector(Some(sandbox.detector()));
+        self.capabilities.capture_backend = "sandbox".to_string();
+        self.capabilities.capture = capabilities::CapabilityStatus::Available;
+        self.capabilities.mouse_input = capabilities::CapabilityStatus::Available;
+        self.capabilities.keyboard_input = capabilities::CapabilityStatus::Available;
+        self.ai_coordinator.set_capabilities(self.capabilities.clone());
+        info!("Entered the sandbox; commands act on a simulated desktop");
+        sandbox
+    }
+
+    /// Counters and histograms for `/metrics` or a push gateway; clones
+    /// share the same series
+    pub fn metrics(&self) -> metrics::MetricsCollector {
+        self.metrics.clone()
+    }
+
     /// Get configuration
     pub fn get_config(&self) -> &LunaConfig {
         &self.config
     }
 
-    /// Update configuration
-    pub fn update_config(&mut self, config: LunaConfig) -> Result<()> {
+    /// Safety rules in force, built-in and from `safety.blocked_patterns`
+    pub fn safety_rules(&self) -> Vec<safety::SafetyRule> {
+        self.safety_system.rules()
+    }
+
+    /// Check `command` against the safety rules as if `source` had sent it,
+    /// without running anything. Commands planned without the screen
+    /// (coordinates, keys, typing) have their actions checked too.
+    pub fn test_safety(&mut self, command: &str, source: CommandSource) -> safety::SafetyTest {
+        let focus = if self.safety_system.disruption_policy(source) != config::DisruptionPolicy::Allow {
+            self.ensure_focus_monitor().state()
+        } else {
+            focus::FocusState::default()
+        };
+        let actions = self.ai_coordinator.plan_direct_actions(command).unwrap_or_default();
+        safety::SafetyTest {
+            command: self.safety_system.evaluate_command(source, command, &focus),
+            actions: actions
+                .into_iter()
+                .map(|action| {
+                    let risk = to_input_action(&action).ok().map(|input| self.risk_level(&input));
+                    let hits = self.safety_system.evaluate_action(source, &action, risk);
+                    (action, hits)
+                })
+                .collect(),
+        }
+    }
+
+    /// Change vision settings without restarting, reporting which ones
+    /// needed a detector rebuild
+    pub fn reconfigure_vision(&mut self, changes: &config::PartialVisionConfig) -> Result<ReconfigureReport> {
+        let mut updated = self.config.clone();
+        changes.apply_to(&mut updated.vision);
+        updated.validate()?;
+
+        self.config = updated;
+        Ok(self.ai_coordinator.reconfigure(changes))
+    }
+
+    /// Change confidence thresholds live. The last analysis is re-filtered
+    /// under the new values without recapturing and returned, so a slider can
+    /// show immediately which elements appear; `None` if nothing was analyzed
+    /// yet. The change is not saved; see `persist_thresholds`.
+    pub fn set_runtime_thresholds(&mut self, thresholds: ConfidenceThresholds) -> Result<Option<ScreenAnalysis>> {
+        let mut updated = self.config.clone();
+        updated.vision.confidence_threshold = thresholds.detection;
+        updated.vision.text_confidence_threshold = thresholds.text;
+        updated.validate()?;
+
+        self.config = updated;
+        self.ai_coordinator.set_thresholds(thresholds);
+        let Some(frame) = &self.last_frame else {
+            return Ok(None);
+        };
+        Ok(self.ai_coordinator.refilter(&to_dynamic_image(frame)?))
+    }
+
+    pub fn runtime_thresholds(&self) -> ConfidenceThresholds {
+        self.ai_coordinator.thresholds()
+    }
+
+    /// How many elements of the last analysis each detection threshold in
+    /// `levels` would keep, for picking an operating point
+    pub fn threshold_sweep(&self, levels: &[f32]) -> Vec<(f32, usize)> {
+        self.ai_coordinator.threshold_sweep(levels)
+    }
+
+    /// Write the current thresholds to the config file at the default
+    /// location, leaving its other settings as they are
+    pub fn persist_thresholds(&self) -> Result<std::path::PathBuf> {
+        let path = LunaConfig::default_config_path()?;
+        let mut saved = if path.exists() { LunaConfig::from_file(&path)? } else { LunaConfig::default() };
+        saved.vision.confidence_threshold = self.config.vision.confidence_threshold;
+        saved.vision.text_confidence_threshold = self.config.vision.text_confidence_threshold;
+        saved.save_to_file(&path)?;
+        Ok(path)
+    }
+
+    /// Write the current `safety.blocked_patterns` to the config file at the
+    /// default location, leaving its other settings as they are
+    pub fn persist_safety_patterns(&self) -> Result<std::path::PathBuf> {
+        let path = LunaConfig::default_config_path()?;
+        let mut saved = if path.exists() { LunaConfig::from_file(&path)? } else { LunaConfig::default() };
+        saved.safety.blocked_patterns = self.config.safety.blocked_patterns.clone();
+        saved.save_to_file(&path)?;
+        Ok(path)
+    }
+
+    /// Apply a new configuration as one transaction: every changed setting is
+    /// validated and everything that can fail is built before anything
+    /// changes, so a rejected update leaves the running configuration intact.
+    /// The report lists applied and rejected keys and is also emitted as a
+    /// `ConfigApplied` event.
+    pub fn update_config(&mut self, config: LunaConfig) -> Result<config::ConfigReport> {
+        let changed = self.config.changed_keys(&config)?;
+        let staged = match self.stage_config(&config, &changed) {
+            Ok(staged) => staged,
+            Err(rejected) => return Err(self.reject_config(&changed, rejected)),
+        };
+
+        // Shared state that can still fail goes first, so it alone needs undoing
+        let previous_speed = self.speed.get();
+        if let Err(e) = self.speed.set(config.speed.multiplier()) {
+            return Err(self.reject_config(&changed, vec![("speed".to_string(), e.to_string())]));
+        }
+        let lease_changed = config.instance.enabled != self.config.instance.enabled
+            || config.instance.lease_dir != self.config.instance.lease_dir;
+        if lease_changed {
+            if let Err(e) = self.input_lease.release() {
+                self.speed.set(previous_speed)?;
+                return Err(self.reject_config(&changed, vec![("instance".to_string(), e.to_string())]));
+            }
+            self.input_lease = acquire_input_lease(&config);
+        }
+
+        self.ai_coordinator.reconfigure(&config::PartialVisionConfig::from(&config.vision));
+        // A partial update cannot clear the text threshold; set both outright
+        self.ai_coordinator.set_thresholds(ConfidenceThresholds::from(&config.vision));
+        self.ai_coordinator.set_remote_backend(staged.remote);
+        self.capabilities = capabilities::Capabilities::probe(&config);
+        self.ai_coordinator.set_capabilities(self.capabilities.clone());
+        self.ai_coordinator.set_locale(input_locale(&config));
+        self.ai_coordinator.set_quantities(&config.input);
+        let inspector_resized = config.inspector.history != self.config.inspector.history;
+        let vnc_changed = changed.iter().any(|key| key.starts_with("vnc."));
+        let watchdog_changed = changed.iter().any(|key| key.starts_with("watchdog."));
         self.config = config.clone();
+        if watchdog_changed {
+            self.watchdog = None;
+            self.watchdog = self.start_watchdog();
+        }
         self.safety_system = Arc::new(safety::SafetySystem::new(&config));
-        Ok(())
+        self.storage = staged.storage;
+        self.anchors = staged.anchors;
+        self.element_history = staged.element_history;
+        self.review = staged.review;
+        self.confirmations = confirmation::ConfirmationGate::new(
+            &config.confirmation, self.storage.root().join(confirmation::AUDIT_FILE)).with_contexts(&config.contexts);
+        self.debugger.supervise(config.supervision.threshold());
+        self.metrics.set_slo_objectives(&config.slo.objectives);
+        self.training_exporter = None;
+        self.archive = None;
+        self.warm = None;
+        // Rebuilt with the new settings on next use
+        self.focus_monitor = None;
+        self.session_monitor = None;
+        self.display_monitor = None;
+        if vnc_changed {
+            // Capture and input are rebuilt pointing at the new desktop
+            self.vnc = None;
+            self.screen_capture = None;
+            self.input_system = None;
+        }
+        if inspector_resized {
+            self.inspector = Inspector::new(config.inspector.history);
+        }
+
+        let report = config::ConfigReport { applied: changed, rejected: Vec::new() };
+        info!("Configuration updated: {} setting(s) changed", report.applied.len());
+        self.emit_event(LunaEvent::ConfigApplied { report: report.clone() });
+        Ok(report)
+    }
+
+    /// Validate `config` and build its fallible parts without touching the
+    /// running instance; on failure, the offending keys and why
+    fn stage_config(&self, config: &LunaConfig, changed: &[String]) -> std::result::Result<StagedConfig, Vec<(String, String)>> {
+        if let Err(e) = config.validate() {
+            // Pin the failure on the keys that fail on their own
+            let culprits: Vec<(String, String)> = changed
+                .iter()
+                .filter_map(|key| {
+                    let alone = self.config.with_key_from(config, key).and_then(|c| c.validate());
+                    alone.err().map(|e| (key.clone(), e.to_string()))
+                })
+                .collect();
+            return Err(if culprits.is_empty() { vec![("*".to_string(), e.to_string())] } else { culprits });
+        }
+        let rejected = |section: &'static str| move |e: anyhow::Error| vec![(section.to_string(), e.to_string())];
+        let remote = remote_backend(config).map_err(rejected("remote_inference"))?;
+        let storage = storage::StorageManager::from_config(&config.storage).map_err(rejected("storage"))?;
+        let anchors = anchors::AnchorStore::open(storage.root().join(anchors::ANCHOR_FILE)).map_err(rejected("storage"))?;
+        let element_history = element_stats::ElementHistory::open(
+            storage.root().join(element_stats::ELEMENT_STATS_FILE), config.element_stats.max_elements)
+            .map_err(rejected("storage"))?;
+        let review = review::ReviewQueue::open(storage.root().join(review::REVIEW_DIR), config.review.max_items)
+            .map_err(rejected("storage"))?
+            .with_scratch(self.scratch.clone());
+        Ok(StagedConfig { remote, storage, anchors, element_history, review })
+    }
+
+    /// Report a rolled-back update: changed keys under a `failed` key or
+    /// section get its error, every other changed key is rolled back
+    fn reject_config(&self, changed: &[String], failed: Vec<(String, String)>) -> anyhow::Error {
+        let under = |key: &str, failed: &str| failed == "*" || key == failed || key.starts_with(&format!("{}.", failed));
+        let mut rejected: Vec<config::RejectedKey> = changed
+            .iter()
+            .map(|key| {
+                let reason = failed.iter().find(|(failed, _)| under(key, failed)).map(|(_, reason)| reason.clone());
+                config::RejectedKey { key: key.clone(), reason: reason.unwrap_or_else(|| "rolled back".to_string()) }
+            })
+            .collect();
+        // A failure no changed key accounts for, e.g. a storage root that vanished
+        for (key, reason) in &failed {
+            if !changed.iter().any(|changed| under(changed, key)) {
+                rejected.push(config::RejectedKey { key: key.clone(), reason: reason.clone() });
+            }
+        }
+        let summary: Vec<String> = failed.iter().map(|(key, reason)| format!("{}: {}", key, reason)).collect();
+        warn!("Configuration update rolled back ({})", summary.join("; "));
+        self.emit_event(LunaEvent::ConfigApplied { report: config::ConfigReport { applied: Vec::new(), rejected } });
+        LunaError::Config(format!("update rolled back: {}", summary.join("; "))).into()
+    }
+
+    /// Speed multiplier used by commands that don't set their own
+    pub fn speed(&self) -> f64 {
+        self.speed.get()
+    }
+
+    /// Change the speed multiplier from the next action on; 2.0 runs twice as
+    /// fast as normal. The config file is unchanged.
+    pub fn set_speed(&mut self, multiplier: f64) -> Result<()> {
+        self.speed.set(multiplier)
+    }
+
+    /// Shared multiplier for changing the speed from another thread while a command runs
+    pub fn speed_control(&self) -> SpeedControl {
+        self.speed.clone()
+    }
+
+    /// Shared pause, step and breakpoint controls for the actions of running commands
+    pub fn step_control(&self) -> debugger::StepControl {
+        self.debugger.clone()
+    }
+
+    /// Use `confirmer` whenever the confirmation config selects `kind`,
+    /// e.g. to relay approvals through an app's own notification channel
+    pub fn set_confirmer(&mut self, kind: config::ConfirmerKind, confirmer: Box<dyn confirmation::Confirmer>) {
+        self.confirmations.set_confirmer(kind, confirmer);
+    }
+
+    /// Call `hook` around every analysis and executed action from now on,
+    /// after the hooks already added
+    pub fn add_hook(&mut self, hook: Box<dyn hooks::PipelineHook>) {
+        self.hooks.add(hook);
+    }
+
+    /// Stop calling the hooks named `name`; false when none was added
+    pub fn remove_hook(&mut self, name: &str) -> bool {
+        self.hooks.remove(name)
+    }
+
+    pub fn hook_names(&self) -> Vec<&str> {
+        self.hooks.names()
+    }
+
+    /// The question asked about the last command, if it is still unanswered
+    pub fn pending_clarification(&self) -> Option<&Clarification> {
+        self.pending_clarification.as_ref().map(|pending| &pending.clarification)
+    }
+
+    /// Answer the pending question ("Notepad", "the second one") and run the
+    /// ambiguous command against the chosen element. An answer that picks no
+    /// option leaves the question pending.
+    pub fn answer_clarification(&mut self, answer: &str, options: &ExecuteOptions) -> Result<CommandResult> {
+        let pending = self.pending_clarification.as_ref()
+            .ok_or_else(|| LunaError::InvalidArgument("no question is waiting for an answer".to_string()))?;
+        let Some(element) = pending.clarification.resolve(answer).map(|option| option.element) else {
+            let labels: Vec<&str> = pending.clarification.options.iter().map(|o| o.label.as_str()).collect();
+            return Err(LunaError::InvalidArgument(
+                format!("'{}' doesn't pick one of: {}", answer.trim(), labels.join(", "))).into());
+        };
+
+        let pending = self.pending_clarification.take().expect("checked above");
+        let command = pending.command.clone();
+        debug!("'{}' answered with element {}", command, element);
+        self.export_answer(&pending, element);
+        self.answered = Some((pending, element));
+        let result = self.execute_command(&command, options);
+        self.answered = None;
+        result
+    }
+
+    /// Save the element a question was answered with as a training label
+    /// when `training.export_corrections` is on. The planner had no single
+    /// pick, so the sample has no predicted label.
+    fn export_answer(&mut self, pending: &PendingClarification, element: usize) {
+        if !self.config.training.export_corrections {
+            return;
+        }
+        let (Some(frame), Some(chosen)) = (&pending.frame, pending.analysis.elements.get(element)) else {
+            return;
+        };
+        let correction = Correction {
+            command: pending.command.clone(),
+            predicted: None,
+            correct_bounds: chosen.bounds.clone(),
+            correct_label: chosen.element_type.clone(),
+        };
+        match self.training_exporter().and_then(|exporter| exporter.export(frame, &correction)) {
+            Ok(record) => self.emit_event(LunaEvent::CorrectionReported {
+                command: correction.command,
+                label: correction.correct_label,
+                exported: record.is_some(),
+            }),
+            Err(e) => warn!("Could not export the answer to '{}': {}", pending.command, e),
+        }
+    }
+
+    /// Who approved or denied which high-risk actions, oldest first
+    pub fn confirmation_log(&self) -> Result<Vec<confirmation::AuditRecord>> {
+        self.confirmations.audit_log()
+    }
+
+    /// Recent analyses with their frames and planned actions
+    pub fn inspector(&self) -> &Inspector {
+        &self.inspector
+    }
+
+    /// Inspector view state: timeline position, layers and selection
+    pub fn inspector_mut(&mut self) -> &mut Inspector {
+        &mut self.inspector
+    }
+
+    /// Whether this instance owns input or another LUNA process does
+    pub fn input_ownership(&self) -> instance::InputOwnership {
+        self.input_lease.ownership()
+    }
+
+    /// Take input ownership if it is free; otherwise ask the owner to hand it
+    /// over at its next command and stay analysis-only until then
+    pub fn request_input_ownership(&mut self) -> Result<instance::InputOwnership> {
+        let ownership = self.input_lease.try_acquire()?;
+        if !ownership.can_inject() {
+            self.input_lease.request()?;
+        }
+        Ok(ownership)
+    }
+
+    /// Give input ownership up so another instance can take it
+    pub fn release_input_ownership(&mut self) -> Result<()> {
+        self.input_lease.release()
+    }
+
+    /// What will work before calling: capture, input, models, voice, overlay
+    pub fn capabilities(&self) -> &capabilities::Capabilities {
+        &self.capabilities
+    }
+
+    /// Resource usage of recent commands, oldest first
+    pub fn resource_history(&self) -> impl Iterator<Item = &CommandResources> {
+        self.resource_history.iter()
+    }
+
+    fn record_resource_usage(&mut self, command: &str, usage: resources::ResourceUsage) {
+        let limit = self.config.resources.history_len;
+        if limit == 0 {
+            return;
+        }
+        while self.resource_history.len() >= limit {
+            self.resource_history.pop_front();
+        }
+        self.resource_history.push_back(CommandResources { command: command.to_string(), usage });
+    }
+
+    /// Replace how presentation and do-not-disturb state is detected
+    pub fn set_focus_probe(&mut self, probe: Box<dyn focus::FocusProbe + Send>) {
+        let interval = Duration::from_secs(self.config.disruption.probe_interval_secs);
+        self.focus_monitor = Some(focus::FocusMonitor::new(probe, interval));
+    }
+
+    /// Replace how the cursor and focused window are read and restored
+    pub fn set_desktop_probe(&mut self, desktop: Box<dyn restore::DesktopProbe + Send>) {
+        self.desktop = desktop;
+    }
+
+    pub fn set_session_probe(&mut self, probe: Box<dyn session::SessionProbe + Send>) {
+        self.session_monitor = Some(session::SessionMonitor::new(probe, Duration::from_millis(self.config.session.poll_ms)));
+    }
+
+    /// Whether the session is locked or on the secure desktop; emits
+    /// `SystemState` when that changed since the last check
+    pub fn session_state(&mut self) -> session::SessionState {
+        let (state, changed) = self.ensure_session_monitor().poll();
+        if changed {
+            info!("Session is now {}", state);
+            self.emit_event(LunaEvent::SystemState { state });
+        }
+        state
+    }
+
+    /// Replace how the display layout is read
+    pub fn set_display_probe(&mut self, probe: Box<dyn displays::DisplayProbe + Send>) {
+        self.display_monitor = Some(displays::DisplayMonitor::new(probe, Duration::from_millis(self.config.displays.poll_ms)));
+    }
+
+    /// Connected displays as last checked; `None` until checked, or when the
+    /// platform can't say
+    pub fn displays(&self) -> Option<&[DisplayInfo]> {
+        self.display_monitor.as_ref()?.displays()
+    }
+
+    /// What the desktop looks like now, for `verify_environment` to compare
+    /// later runs against (see `environment`)
+    pub fn environment_snapshot(&mut self) -> Result<environment::EnvironmentSnapshot> {
+        self.check_displays();
+        let frame = self.capture_screen()?;
+        let displays = self.displays().map(<[DisplayInfo]>::to_vec).unwrap_or_default();
+        Ok(environment::EnvironmentSnapshot::capture(&displays, &frame, &self.config))
+    }
+
+    /// Fail with `LunaError::EnvironmentMismatch` unless the desktop matches
+    /// what `expected` recorded
+    pub fn verify_environment(&mut self, expected: &environment::EnvironmentSnapshot) -> Result<()> {
+        let found = self.environment_snapshot()?;
+        match expected.compare(&found) {
+            None => Ok(()),
+            Some(mismatch) => {
+                warn!("Environment mismatch: {}", mismatch);
+                Err(LunaError::EnvironmentMismatch(Box::new(mismatch)).into())
+            }
+        }
+    }
+
+    /// Check the display layout. When it changed, drop the idle analysis,
+    /// the analyzed frame and tracked element IDs, which were made for the
+    /// old layout, and emit `DisplayChanged`.
+    pub fn check_displays(&mut self) -> Option<displays::DisplayChange> {
+        if !self.config.displays.watch {
+            return None;
+        }
+        if self.display_monitor.is_none() {
+            let probe = match self.vnc_client() {
+                Some(client) => client.display_probe(),
+                None => Box::new(displays::SystemDisplayProbe),
+            };
+            self.set_display_probe(probe);
+        }
+        let change = self.display_monitor.as_mut()?.poll()?;
+        let (removed, added) = (change.removed(), change.added());
+        info!("Display layout changed: {} removed or resized, {} added; dropping cached analysis", removed.len(), added.len());
+        self.warm = None;
+        self.last_frame = None;
+        self.tracker = tracker::ElementTracker::new();
+        self.emit_event(LunaEvent::DisplayChanged { displays: change.after.clone(), removed, added });
+        Some(change)
+    }
+
+    /// Block while the session is locked or on the secure desktop, polling
+    /// until it is back. Returns whether it had to wait.
+    fn wait_for_session(&mut self, command: &str, cancel: Option<&CancelToken>) -> Result<bool> {
+        let settings = self.config.session.clone();
+        let state = self.session_state();
+        if !settings.pause_when_locked || state.is_active() {
+            return Ok(false);
+        }
+        info!("Pausing '{}' while the session is {}", command, state);
+        let paused = Instant::now();
+        while !self.session_state().is_active() {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                return Err(LunaError::Cancelled(command.to_string()).into());
+            }
+            if settings.max_pause_ms > 0 && paused.elapsed() >= Duration::from_millis(settings.max_pause_ms) {
+                return Err(LunaError::Deferred(format!(
+                    "session stayed {} for {}ms", self.session_state(), settings.max_pause_ms)).into());
+            }
+            std::thread::sleep(Duration::from_millis(settings.poll_ms));
+        }
+        info!("Session unlocked after {}ms, resuming '{}'", paused.elapsed().as_millis(), command);
+        std::thread::sleep(Duration::from_millis(settings.resume_settle_ms));
+        Ok(true)
+    }
+
+    /// Subsystem checks behind the `/readyz` probe (see `health`)
+    pub fn readiness(&mut self) -> Vec<health::HealthCheck> {
+        let capture = &self.capabilities.capture;
+        let mut checks = vec![health::HealthCheck::new("capture", capture.is_usable(), capture.to_string())];
+
+        let local = self.capabilities.models.iter().any(|m| m.status.is_usable());
+        checks.push(match self.ai_coordinator.remote_status() {
+            Some((endpoint, false)) => health::HealthCheck::new("models", local, format!("{} is backing off; detecting locally", endpoint)),
+            Some((endpoint, true)) => health::HealthCheck::new("models", true, format!("remote inference at {}", endpoint)),
+            None => health::HealthCheck::new("models", local, "local detectors loaded"),
+        });
+
+        let input = [&self.capabilities.mouse_input, &self.capabilities.keyboard_input];
+        let ownership = self.input_ownership();
+        let permitted = input.iter().all(|status| status.is_usable()) && ownership.can_inject();
+        checks.push(health::HealthCheck::new("input", permitted, format!("mouse {}, keyboard {}; {:?}", input[0], input[1], ownership)));
+
+        let session = self.session_state();
+        checks.push(health::HealthCheck::new("session", session.is_active(), session.to_string()));
+
+        let degraded: Vec<&str> = self.heartbeats.degraded().into_iter().map(watchdog::Subsystem::as_str).collect();
+        checks.push(match degraded.as_slice() {
+            [] => health::HealthCheck::new("watchdog", true, "no subsystem stalling"),
+            names => health::HealthCheck::new("watchdog", false, format!("degraded after repeated stalls: {}", names.join(", "))),
+        });
+        checks
+    }
+
+    /// Whether the user is presenting or in do-not-disturb right now
+    pub fn focus_state(&mut self) -> focus::FocusState {
+        self.ensure_focus_monitor().state()
     }
 
     /// Check if Luna is ready to process commands
@@ -282,7 +3785,7 @@ impl Luna {
         F: FnOnce(&mut ProcessingStats),
     {
         if let Ok(mut stats) = self.stats.lock() {
-            updater(&mut *stats);
+            updater(&mut stats);
         }
     }
 }
@@ -299,7 +3802,8 @@ impl Luna {
     pub fn click(&mut self, x: i32, y: i32) -> Result<()> {
         let action = LunaAction::Click { x, y };
         if self.safety_system.is_action_safe(&action) {
-            self.execute_single_action(&action)
+            self.input_lease.ensure_owner()?;
+            self.execute_with_retry(&action).map(|_| ())
         } else {
             Err(LunaError::UnsafeAction(format!("Click at ({}, {})", x, y)).into())
         }
@@ -309,7 +3813,8 @@ impl Luna {
     pub fn type_text(&mut self, text: &str) -> Result<()> {
         let action = LunaAction::Type { text: text.to_string() };
         if self.safety_system.is_action_safe(&action) {
-            self.execute_single_action(&action)
+            self.input_lease.ensure_owner()?;
+            self.execute_with_retry(&action).map(|_| ())
         } else {
             Err(LunaError::UnsafeAction(format!("Type text: {}", text)).into())
         }
@@ -319,7 +3824,8 @@ impl Luna {
     pub fn send_keys(&mut self, keys: Vec<String>) -> Result<()> {
         let action = LunaAction::KeyCombo { keys };
         if self.safety_system.is_action_safe(&action) {
-            self.execute_single_action(&action)
+            self.input_lease.ensure_owner()?;
+            self.execute_with_retry(&action).map(|_| ())
         } else {
             Err(LunaError::UnsafeAction("Key combination".to_string()).into())
         }
@@ -332,15 +3838,169 @@ impl Luna {
             amount
         };
         if self.safety_system.is_action_safe(&action) {
-            self.execute_single_action(&action)
+            self.input_lease.ensure_owner()?;
+            self.execute_with_retry(&action).map(|_| ())
         } else {
             Err(LunaError::UnsafeAction(format!("Scroll {}", direction)).into())
         }
     }
 }
 
+/// What a semantic wait makes of one analysis
+enum WaitCheck {
+    /// The condition holds
+    Done,
+    /// Not yet; nothing can change until this region does
+    Watch(Rectangle),
+}
+
+/// Action type as named in metrics and the `retry` config
+fn action_kind(action: &LunaAction) -> &'static str {
+    match action {
+        LunaAction::Click { .. } | LunaAction::ModifierClick { .. } => "click",
+        LunaAction::Type { .. } => "type",
+        LunaAction::KeyCombo { .. } | LunaAction::KeyDown { .. } | LunaAction::KeyUp { .. } => "keys",
+        LunaAction::Scroll { .. } => "scroll",
+        LunaAction::Hover { .. } => "hover",
+        LunaAction::Wait { .. } => "wait",
+        LunaAction::Screenshot { .. } => "screenshot",
+        LunaAction::PasteImage { .. } => "paste",
+        LunaAction::WaitForElement { .. } => "wait_for_element",
+        LunaAction::WaitForTextGone { .. } => "wait_for_text_gone",
+        LunaAction::WaitForScreenIdle { .. } => "wait_idle",
+    }
+}
+
+/// Whether a failed action may succeed if tried again: backend and capture
+/// errors and rate limiting, not safety blocks or invalid actions
+fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<crate::input::InputError>() {
+        return matches!(error, crate::input::InputError::RateLimited | crate::input::InputError::PlatformError(_));
+    }
+    if let Some(error) = error.downcast_ref::<LunaError>() {
+        return matches!(error, LunaError::Input(_) | LunaError::ScreenCapture(_));
+    }
+    error.downcast_ref::<std::io::Error>().is_some()
+}
+
+/// `ms` milliseconds at normal speed, at `speed`
+fn scaled(ms: u64, speed: f64) -> Duration {
+    Duration::from_secs_f64(ms as f64 / 1000.0 / speed)
+}
+
+/// The smallest scrollable container under `pointer`, where the wheel
+/// scrolls; the whole screen when there is none
+fn scroll_area(analysis: &ScreenAnalysis, pointer: Option<geometry::Point>) -> Rectangle {
+    let (width, height) = analysis.screen_size;
+    let screen = Rectangle::new(0.0, 0.0, width as f64, height as f64);
+    pointer
+        .and_then(|pointer| {
+            analysis
+                .elements
+                .iter()
+                .filter(|e| CLIPPING_TYPES.contains(&e.element_type.as_str()))
+                .map(|e| Rectangle::from(&e.bounds))
+                .filter(|r| r.contains_point(&pointer))
+                .min_by(|a, b| a.area().total_cmp(&b.area()))
+        })
+        .and_then(|area| area.intersection(&screen))
+        .unwrap_or(screen)
+}
+
+/// What the smallest field at `point` shows, or `None` when there is no
+/// field there. A field showing its own label is empty, with the label as
+/// placeholder.
+fn field_text(analysis: &ScreenAnalysis, point: (i32, i32)) -> Option<String> {
+    let point = geometry::Point::new(point.0 as f64, point.1 as f64);
+    let field = analysis
+        .elements
+        .iter()
+        .filter(|e| crate::ai::FIELD_TYPES.contains(&e.element_type.as_str()))
+        .filter(|e| Rectangle::from(&e.bounds).contains_point(&point))
+        .min_by(|a, b| Rectangle::from(&a.bounds).area().total_cmp(&Rectangle::from(&b.bounds).area()))?;
+    let text = field.text.as_deref().unwrap_or_default();
+    Some(if field.label() == Some(text) { String::new() } else { text.to_string() })
+}
+
+fn acquire_input_lease(config: &LunaConfig) -> instance::InputLease {
+    let mut lease = instance::InputLease::new(&config.instance);
+    match lease.try_acquire() {
+        Ok(instance::InputOwnership::AnalysisOnly { owner }) => {
+            warn!("Input is owned by {}; running analysis-only", owner);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Could not coordinate input with other instances: {}", e),
+    }
+    lease
+}
+
+fn build_ai_coordinator(config: &LunaConfig, capabilities: &capabilities::Capabilities) -> Result<AICoordinator> {
+    let mut coordinator = AICoordinator::from_config(&config.vision);
+    coordinator.set_remote_backend(remote_backend(config)?);
+    coordinator.set_capabilities(capabilities.clone());
+    coordinator.set_locale(input_locale(config));
+    coordinator.set_quantities(&config.input);
+    Ok(coordinator)
+}
+
+/// The locale typed dates and amounts are written for: the configured one,
+/// else the system's
+fn input_locale(config: &LunaConfig) -> Locale {
+    config.input.locale.as_deref().and_then(Locale::parse).unwrap_or_else(Locale::system)
+}
+
+fn fingerprinted_element(fingerprint: &ElementFingerprint, found: FingerprintMatch, located_by: &str) -> ScreenElement {
+    let mut attributes = std::collections::HashMap::new();
+    attributes.insert("located_by".to_string(), located_by.to_string());
+    attributes.insert("hash_distance".to_string(), found.hash_distance.to_string());
+    ScreenElement {
+        element_type: fingerprint.element_type.clone(),
+        confidence: found.confidence(),
+        bounds: found.bounds,
+        shape: None,
+        text: fingerprint.text.clone(),
+        attributes,
+        parent: None,
+        children: Vec::new(),
+    }
+}
+
+/// Screen capture, publishing its frames when the frame channel is enabled
+fn build_screen_capture(config: &LunaConfig) -> Result<ScreenCapture> {
+    let mut capture = ScreenCapture::new(CaptureConfig::default());
+    let channel = &config.frame_channel;
+    if channel.enabled {
+        let path = channel.path.clone().unwrap_or_else(|| frame_channel::default_path("luna"));
+        info!("Publishing captured frames to {}", path.display());
+        capture.share_frames(Some(frame_channel::FramePublisher::create(path, channel.slots, channel.max_frame_bytes)?));
+    }
+    Ok(capture)
+}
+
+fn build_focus_monitor(config: &LunaConfig) -> focus::FocusMonitor {
+    focus::FocusMonitor::new(
+        Box::new(focus::SystemFocusProbe::new(&config.disruption.presentation_apps)),
+        Duration::from_secs(config.disruption.probe_interval_secs),
+    )
+}
+
+fn build_session_monitor(config: &LunaConfig) -> session::SessionMonitor {
+    session::SessionMonitor::new(
+        Box::new(session::SystemSessionProbe),
+        Duration::from_millis(config.session.poll_ms),
+    )
+}
+
+fn remote_backend(config: &LunaConfig) -> Result<Option<RemoteDetector>> {
+    if config.remote_inference.enabled {
+        RemoteDetector::new(config.remote_inference.clone()).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
 /// Convert the internal image buffer to an `image::DynamicImage` for the CV pipeline
-fn to_dynamic_image(image: &Image) -> Result<image::DynamicImage> {
+pub fn to_dynamic_image(image: &Image) -> Result<image::DynamicImage> {
     let width = image.width as u32;
     let height = image.height as u32;
     let data = image.data.clone();
@@ -364,13 +4024,46 @@ fn to_dynamic_image(image: &Image) -> Result<image::DynamicImage> {
     })
 }
 
+/// PNG encoding of a captured image
+pub(crate) fn encode_png(image: &Image) -> Result<Vec<u8>> {
+    let mut png = std::io::Cursor::new(Vec::new());
+    to_dynamic_image(image)?.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Put the image file at `path` on the clipboard, as PNG whatever its format
+fn load_clipboard_image(path: &std::path::Path) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    let png = if bytes.starts_with(b"\x89PNG") {
+        bytes
+    } else {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::load_from_memory(&bytes)?.write_to(&mut png, image::ImageOutputFormat::Png)?;
+        png.into_inner()
+    };
+    if !crate::input::copy_image_to_clipboard(&png) {
+        return Err(LunaError::Input(format!("no clipboard tool accepted {}", path.display())).into());
+    }
+    Ok(())
+}
+
 /// Convert a planned `LunaAction` into the input layer's `InputAction`.
 ///
-/// `Wait` is handled by the coordinator directly and is rejected here.
+/// `Wait` and `Screenshot` are handled by the coordinator directly and are
+/// rejected here; `PasteImage` is the paste shortcut, sent once the image is
+/// on the clipboard.
 fn to_input_action(action: &LunaAction) -> Result<InputAction> {
     let (action_type, target) = match action {
         LunaAction::Click { x, y } => (
-            ActionType::Click { button: MouseButton::Left },
+            ActionType::Click { button: MouseButton::Left, modifiers: Vec::new() },
+            Target { x: *x, y: *y, element_type: None },
+        ),
+        LunaAction::ModifierClick { x, y, modifiers } => (
+            ActionType::Click { button: MouseButton::Left, modifiers: modifiers.iter().map(|m| m.to_lowercase()).collect() },
+            Target { x: *x, y: *y, element_type: None },
+        ),
+        LunaAction::Hover { x, y } => (
+            ActionType::Move { x: *x, y: *y },
             Target { x: *x, y: *y, element_type: None },
         ),
         LunaAction::Type { text } => (
@@ -381,6 +4074,14 @@ fn to_input_action(action: &LunaAction) -> Result<InputAction> {
             ActionType::Key { key: keys.join("+").to_lowercase() },
             Target { x: 0, y: 0, element_type: None },
         ),
+        LunaAction::KeyDown { key } => (
+            ActionType::KeyDown { key: key.to_lowercase() },
+            Target { x: 0, y: 0, element_type: None },
+        ),
+        LunaAction::KeyUp { key } => (
+            ActionType::KeyUp { key: key.to_lowercase() },
+            Target { x: 0, y: 0, element_type: None },
+        ),
         LunaAction::Scroll { direction, amount } => {
             let scroll_direction = match direction.to_lowercase().as_str() {
                 "up" => ScrollDirection::Up,
@@ -393,8 +4094,16 @@ fn to_input_action(action: &LunaAction) -> Result<InputAction> {
                 Target { x: 0, y: 0, element_type: None },
             )
         }
-        LunaAction::Wait { .. } => {
-            return Err(anyhow::anyhow!("Wait actions are executed by the coordinator"));
+        LunaAction::PasteImage { .. } => (
+            ActionType::Key { key: if cfg!(target_os = "macos") { "cmd+v" } else { "ctrl+v" }.to_string() },
+            Target { x: 0, y: 0, element_type: None },
+        ),
+        LunaAction::Wait { .. }
+        | LunaAction::WaitForElement { .. }
+        | LunaAction::WaitForTextGone { .. }
+        | LunaAction::WaitForScreenIdle { .. }
+        | LunaAction::Screenshot { .. } => {
+            return Err(anyhow::anyhow!("Wait and Screenshot actions are executed by the coordinator"));
         }
     };
 
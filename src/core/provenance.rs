@@ -0,0 +1,245 @@
+/*!
+ * Luna Provenance - Why an action went where it did
+ *
+ * Every planned action carries the evidence behind it: how it was planned
+ * (literal coordinates, screen analysis, a taught anchor or an answered
+ * question), the detected element its click lands on with the detector's
+ * confidence, recognized text and learned reliability, how the click point
+ * was placed, and what the safety checks made of it. The chain comes back
+ * in `CommandResult::provenance` and, with transcripts enabled, is appended
+ * to the transcript store so it can be looked up after the fact by command
+ * ID and action index.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{LunaAction, ScreenAnalysis, ScreenElement};
+use crate::input::RiskLevel;
+
+/// File name of the provenance transcript in the transcript store
+pub const TRANSCRIPT_FILE: &str = "provenance.jsonl";
+
+static NEXT_COMMAND: AtomicU64 = AtomicU64::new(0);
+
+/// Identifier for a command, unique within a transcript
+pub fn next_command_id() -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+    format!("{}-{}", millis, NEXT_COMMAND.fetch_add(1, Ordering::Relaxed))
+}
+
+/// How an action was planned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "by", rename_all = "snake_case")]
+pub enum PlannedBy {
+    /// Coordinates or keys spelled out in the command; no screen was analyzed
+    Literal,
+    /// Planned against a screen analysis; `thorough` after escalation
+    Analysis { thorough: bool },
+    /// A taught anchor, re-located by its fingerprint
+    Anchor { name: String },
+    /// The user's answer to a clarifying question
+    Answer { question: String },
+}
+
+/// The detected element an action targets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElementEvidence {
+    /// Index of the element in the analysis it was detected in; `None` for
+    /// an anchor, which is found by its fingerprint instead
+    pub detection: Option<usize>,
+    pub element_type: String,
+    /// `(x, y, width, height)`
+    pub bounds: (i32, i32, i32, i32),
+    /// Detector confidence
+    pub confidence: f32,
+    /// Recognized text
+    pub text: Option<String>,
+    /// Learned click success rate, when element stats are kept
+    pub reliability: Option<f32>,
+    /// How the click point was placed ("mask" or "bounds"); `None` when the
+    /// click does not land on the element's own click point
+    pub click_strategy: Option<String>,
+}
+
+impl ElementEvidence {
+    /// Evidence for a click at `point`: the element whose click point it is,
+    /// else the smallest element containing it
+    pub fn for_click(analysis: &ScreenAnalysis, point: (i32, i32)) -> Option<Self> {
+        let index = analysis.elements.iter().position(|e| e.click_point() == point).or_else(|| {
+            analysis
+                .elements
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| e.bounds.contains_point(point.0, point.1))
+                .min_by_key(|(_, e)| e.bounds.width as i64 * e.bounds.height as i64)
+                .map(|(index, _)| index)
+        })?;
+        Some(Self::new(&analysis.elements[index], Some(index), point))
+    }
+
+    /// Evidence for a click at `point` on `element`
+    pub fn new(element: &ScreenElement, detection: Option<usize>, point: (i32, i32)) -> Self {
+        let b = &element.bounds;
+        let (target, strategy) = element.click_target();
+        Self {
+            detection,
+            element_type: element.element_type.clone(),
+            bounds: (b.x, b.y, b.width, b.height),
+            confidence: element.confidence,
+            text: element.text.clone(),
+            reliability: element.attributes.get(super::RELIABILITY_ATTRIBUTE).and_then(|v| v.parse().ok()),
+            click_strategy: (target == point).then(|| strategy.as_str().to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ElementEvidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (x, y, width, height) = self.bounds;
+        write!(f, "{}", self.element_type)?;
+        if let Some(text) = &self.text {
+            write!(f, " {:?}", text)?;
+        }
+        write!(f, " at ({}, {}) {}x{}, confidence {:.2}", x, y, width, height, self.confidence)?;
+        if let Some(reliability) = self.reliability {
+            write!(f, ", reliability {:.2}", reliability)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the safety checks made of an action
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SafetyVerdict {
+    /// Passed the per-action safety rules
+    pub allowed: bool,
+    /// Input risk level; `None` for waits and screenshots
+    pub risk: Option<RiskLevel>,
+    /// ID of the confirmation audit record that approved it, when one was needed
+    pub confirmation: Option<String>,
+}
+
+/// The evidence chain behind one action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionProvenance {
+    /// Position in the command's actions
+    pub index: usize,
+    /// The action as planned
+    pub action: String,
+    #[serde(flatten)]
+    pub planned_by: PlannedBy,
+    pub element: Option<ElementEvidence>,
+    pub safety: SafetyVerdict,
+}
+
+impl ActionProvenance {
+    /// Provenance for each of `actions`, with element evidence from
+    /// `analysis` for the ones that click
+    pub fn for_actions(actions: &[LunaAction], planned_by: PlannedBy, analysis: Option<&ScreenAnalysis>) -> Vec<Self> {
+        actions
+            .iter()
+            .enumerate()
+            .map(|(index, action)| Self {
+                index,
+                action: format!("{:?}", action),
+                planned_by: planned_by.clone(),
+                element: analysis.zip(action.click_point()).and_then(|(analysis, point)| ElementEvidence::for_click(analysis, point)),
+                safety: SafetyVerdict::default(),
+            })
+            .collect()
+    }
+}
+
+/// One command's line in the transcript
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptRecord {
+    pub command_id: String,
+    pub command: String,
+    /// The command API client that sent it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// Unix seconds
+    pub executed_at: u64,
+    pub actions: Vec<ActionProvenance>,
+}
+
+/// Append `record` to the transcript at `path`
+pub fn append(path: &Path, record: &TranscriptRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Commands in the transcript at `path`, oldest first
+pub fn read(path: &Path) -> Result<Vec<TranscriptRecord>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => contents.lines().map(|line| Ok(serde_json::from_str(line)?)).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The evidence chain recorded for action `index` of command `command_id`
+pub fn reconstruct(path: &Path, command_id: &str, index: usize) -> Result<Option<ActionProvenance>> {
+    Ok(read(path)?
+        .into_iter()
+        .find(|record| record.command_id == command_id)
+        .and_then(|record| record.actions.into_iter().find(|action| action.index == index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ElementBounds;
+
+    fn element(element_type: &str, bounds: ElementBounds) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds,
+            shape: None,
+            confidence: 0.8,
+            text: Some("OK".to_string()),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_evidence_round_trips_through_the_transcript() {
+        let analysis = ScreenAnalysis {
+            elements: vec![
+                element("window", ElementBounds::new(0, 0, 400, 300)),
+                element("button", ElementBounds::new(100, 100, 40, 20)),
+            ],
+            confidence: 0.8,
+            processing_time_ms: 0,
+            screen_size: (400, 300),
+            context: Default::default(),
+        };
+        let actions = [LunaAction::Click { x: 120, y: 110 }, LunaAction::Click { x: 105, y: 105 }, LunaAction::Type { text: "hi".to_string() }];
+        let chain = ActionProvenance::for_actions(&actions, PlannedBy::Analysis { thorough: false }, Some(&analysis));
+
+        // The button's own click point, then a point inside it placed some other way
+        let on_target = chain[0].element.as_ref().unwrap();
+        assert_eq!((on_target.detection, on_target.click_strategy.as_deref()), (Some(1), Some("bounds")));
+        assert_eq!(chain[1].element.as_ref().map(|e| (e.detection, e.click_strategy.is_none())), Some((Some(1), true)));
+        assert!(chain[2].element.is_none());
+        assert_eq!(on_target.to_string(), "button \"OK\" at (100, 100) 40x20, confidence 0.80");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(TRANSCRIPT_FILE);
+        let record = TranscriptRecord { command_id: next_command_id(), command: "click OK".to_string(), client: None, executed_at: 0, actions: chain.clone() };
+        append(&path, &record).unwrap();
+        assert_eq!(reconstruct(&path, &record.command_id, 1).unwrap(), Some(chain[1].clone()));
+        assert_eq!(reconstruct(&path, "missing", 0).unwrap(), None);
+    }
+}
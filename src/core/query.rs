@@ -0,0 +1,114 @@
+/*!
+ * Luna Query - Element selectors shared by the CLI, scripts and watchers
+ *
+ * Syntax:
+ * - `submit`         element type equal to "submit" or text containing it
+ * - `button:submit`  buttons whose text contains "submit"
+ * - `button:`        any button
+ * - `*:submit`       any element whose text contains "submit"
+ *
+ * Matching ignores case.
+ */
+
+use super::ScreenElement;
+use crate::utils::text;
+
+/// Parsed element selector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementQuery {
+    /// Bare term: matches the element type exactly or a substring of the text
+    Any(String),
+    /// `type:text`, either side may be empty or `*` to match anything
+    Typed {
+        element_type: Option<String>,
+        text: Option<String>,
+    },
+}
+
+impl ElementQuery {
+    pub fn parse(query: &str) -> Self {
+        let wildcard = |part: &str| {
+            let part = part.trim();
+            (!part.is_empty() && part != "*").then(|| text::fold_case(part))
+        };
+        match query.split_once(':') {
+            Some((element_type, text)) => ElementQuery::Typed {
+                element_type: wildcard(element_type),
+                text: wildcard(text),
+            },
+            None => ElementQuery::Any(text::fold_case(query.trim())),
+        }
+    }
+
+    pub fn matches(&self, element: &ScreenElement) -> bool {
+        self.matches_parts(&element.element_type, element.text.as_deref())
+    }
+
+    /// Match an element known only by its type and recognized text
+    pub fn matches_parts(&self, element_type: &str, element_text: Option<&str>) -> bool {
+        let text_contains = |needle: &str| {
+            element_text.is_some_and(|t| text::fold_case(t).contains(needle))
+        };
+        match self {
+            ElementQuery::Any(term) => text::fold_case(element_type) == *term || text_contains(term),
+            ElementQuery::Typed { element_type: wanted, text } => {
+                wanted.as_ref().is_none_or(|t| text::fold_case(element_type) == *t)
+                    && text.as_deref().is_none_or(text_contains)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ElementQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ElementQuery::Any(term) => write!(f, "{}", term),
+            ElementQuery::Typed { element_type, text } => write!(
+                f,
+                "{}:{}",
+                element_type.as_deref().unwrap_or("*"),
+                text.as_deref().unwrap_or("*")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ElementBounds;
+
+    fn element(element_type: &str, text: Option<&str>) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(0, 0, 10, 10),
+            shape: None,
+            confidence: 0.9,
+            text: text.map(String::from),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_match() {
+        let submit = element("button", Some("Submit order"));
+        let field = element("text_input", Some("Submit to"));
+        let untitled = element("button", None);
+
+        let typed = ElementQuery::parse("Button:submit");
+        assert!(typed.matches(&submit));
+        assert!(!typed.matches(&field));
+        assert!(!typed.matches(&untitled));
+
+        let any_button = ElementQuery::parse("button:");
+        assert!(any_button.matches(&untitled));
+        assert_eq!(any_button.to_string(), "button:*");
+
+        let bare = ElementQuery::parse("submit");
+        assert!(bare.matches(&submit) && bare.matches(&field));
+        assert!(ElementQuery::parse("button").matches(&untitled));
+        assert!(ElementQuery::parse("*:order").matches(&submit));
+    }
+}
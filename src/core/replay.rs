@@ -0,0 +1,182 @@
+/*!
+ * Luna Replay - Recorded frames for deterministic end-to-end tests
+ *
+ * A corpus case is a directory holding a (redacted) screenshot, the response
+ * a model gave for it in the inference server's `/v1/detect` format, and the
+ * commands to plan against it with their expected outcome. Replaying a case
+ * runs the real safety checks, control detectors, ranking and planner; only
+ * the model is replaced by its recorded output, so a refactor that changes
+ * what gets clicked shows up as a failed case.
+ *
+ * `tests/corpus/` holds the cases run by `cargo test`. To add one, save the
+ * frame as PNG (`luna shot`), record the detector output for it, and write a
+ * `case.json` naming both. The frame can also come from a frame archive
+ * (`core::archive`), picked by index or by the command it was analyzed for.
+ */
+
+use anyhow::Result;
+use image::DynamicImage;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use super::{archive, Luna, LunaAction, LunaError};
+use crate::ai::remote::{DetectResponse, RemoteElement};
+use crate::ai::{ElementDetection, ElementDetector};
+use crate::utils::image_processing::Image;
+
+/// Case description file in each case directory
+pub const CASE_FILE: &str = "case.json";
+
+/// Detector that answers with recorded model output, whatever the image
+pub struct RecordedDetector {
+    elements: Vec<RemoteElement>,
+}
+
+impl RecordedDetector {
+    pub fn new(elements: Vec<RemoteElement>) -> Self {
+        Self { elements }
+    }
+
+    /// Load a saved `/v1/detect` response
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let response: DetectResponse = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        Ok(Self::new(response.elements))
+    }
+
+    pub fn elements(&self) -> &[RemoteElement] {
+        &self.elements
+    }
+}
+
+impl ElementDetector for RecordedDetector {
+    fn detect_elements(&mut self, _image: &DynamicImage) -> Result<Vec<ElementDetection>> {
+        Ok(self.elements.iter().cloned().map(ElementDetection::from).collect())
+    }
+}
+
+/// A command and what planning it must produce
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpectedOutcome {
+    pub command: String,
+    /// Exact plan, each action in `Debug` form, e.g. `Click { x: 10, y: 20 }`
+    #[serde(default)]
+    pub actions: Option<Vec<String>>,
+    /// Text of the recorded element the first click must land in
+    #[serde(default)]
+    pub target: Option<String>,
+    /// `LunaError::kind` the command must fail with, e.g. "unsafe"
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// One recorded frame and its expectations
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorpusCase {
+    /// Directory name
+    #[serde(skip)]
+    pub name: String,
+    #[serde(skip)]
+    dir: PathBuf,
+    /// Screenshot or frame archive, relative to the case directory
+    pub frame: String,
+    /// Frame of the archive to use; the last one by default
+    #[serde(default)]
+    pub frame_index: Option<usize>,
+    /// Or the frame archived for this command ID
+    #[serde(default)]
+    pub frame_command: Option<String>,
+    /// Recorded `/v1/detect` response, relative to the case directory
+    pub detections: String,
+    pub commands: Vec<ExpectedOutcome>,
+}
+
+impl CorpusCase {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut case: CorpusCase = serde_json::from_str(&std::fs::read_to_string(dir.join(CASE_FILE))?)
+            .map_err(|e| LunaError::Config(format!("{}: {}", dir.join(CASE_FILE).display(), e)))?;
+        case.name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        case.dir = dir.to_path_buf();
+        Ok(case)
+    }
+
+    /// Every case under `root`, by name
+    pub fn load_all(root: &Path) -> Result<Vec<Self>> {
+        let mut dirs: Vec<PathBuf> = std::fs::read_dir(root)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.join(CASE_FILE).is_file())
+            .collect();
+        dirs.sort();
+        dirs.iter().map(|dir| Self::load(dir)).collect()
+    }
+
+    pub fn load_frame(&self) -> Result<Image> {
+        let path = self.dir.join(&self.frame);
+        if path.extension().is_some_and(|ext| ext == archive::EXTENSION) {
+            return self.load_archived_frame(&path);
+        }
+        let rgb = image::open(path)?.to_rgb8();
+        Ok(Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw()))
+    }
+
+    fn load_archived_frame(&self, path: &Path) -> Result<Image> {
+        let mut reader = archive::ArchiveReader::open(path)?;
+        let index = match (&self.frame_command, self.frame_index) {
+            (Some(command_id), _) => reader.index_of_command(command_id)
+                .ok_or_else(|| LunaError::NotFound(format!("{}: no frame archived for command {}", self.name, command_id)))?,
+            (None, Some(index)) => index,
+            (None, None) => reader.len().checked_sub(1)
+                .ok_or_else(|| LunaError::NotFound(format!("{}: {} is empty", self.name, self.frame)))?,
+        };
+        reader.frame(index)
+    }
+
+    pub fn detector(&self) -> Result<RecordedDetector> {
+        RecordedDetector::from_file(&self.dir.join(&self.detections))
+    }
+
+    /// Plan every command against the frame; one message per unmet expectation
+    pub fn run(&self, luna: &mut Luna) -> Result<Vec<String>> {
+        let frame = self.load_frame()?;
+        let mut detector = self.detector()?;
+        let mut failures = Vec::new();
+        for expected in &self.commands {
+            let outcome = luna.plan_command(&expected.command, &frame, &mut detector);
+            let mut fail = |message: String| failures.push(format!("{}: '{}': {}", self.name, expected.command, message));
+            match (outcome, &expected.error) {
+                (Ok(actions), Some(kind)) => fail(format!("expected a {} error, planned {:?}", kind, actions)),
+                (Ok(actions), None) => {
+                    if let Some(plan) = &expected.actions {
+                        let planned: Vec<String> = actions.iter().map(|a| format!("{:?}", a)).collect();
+                        if &planned != plan {
+                            fail(format!("planned {:?}, expected {:?}", planned, plan));
+                        }
+                    }
+                    if let Some(target) = &expected.target {
+                        match clicked_text(&actions, detector.elements()) {
+                            Some(text) if text == target => {}
+                            clicked => fail(format!("clicked {:?}, expected '{}'", clicked, target)),
+                        }
+                    }
+                }
+                (Err(error), Some(kind)) => {
+                    let actual = error.downcast_ref::<LunaError>().map_or("internal", LunaError::kind);
+                    if actual != kind {
+                        fail(format!("failed with {} ({}), expected {}", actual, error, kind));
+                    }
+                }
+                (Err(error), None) => fail(format!("failed: {}", error)),
+            }
+        }
+        Ok(failures)
+    }
+}
+
+/// Text of the smallest recorded element containing the first click
+fn clicked_text<'a>(actions: &[LunaAction], elements: &'a [RemoteElement]) -> Option<&'a str> {
+    let (x, y) = actions.iter().find_map(LunaAction::click_point)?;
+    elements
+        .iter()
+        .filter(|e| e.text.is_some() && x >= e.x && x < e.x + e.width && y >= e.y && y < e.y + e.height)
+        .min_by_key(|e| e.width * e.height)
+        .and_then(|e| e.text.as_deref())
+}
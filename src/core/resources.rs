@@ -0,0 +1,377 @@
+/*!
+ * Luna Resources - Per-command CPU, memory and GPU usage
+ *
+ * A `ResourceProfiler` runs for the duration of one command. One sampler
+ * thread, shared by all profilers and idle between commands, tracks peak
+ * resident memory while the pipeline marks phase boundaries (capture,
+ * analysis, planning, execution), so spikes can be attributed to the stage -
+ * and therefore the model or backend - that caused them.
+ *
+ * CPU and memory come from `/proc/self` on Linux and from GetProcessTimes and
+ * GetProcessMemoryInfo on Windows; GPU memory comes from `nvidia-smi` when
+ * enabled and installed.
+ */
+
+use log::debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+
+use super::config::ResourceConfig;
+
+/// Peak-memory counters of the profilers currently running
+static WATCHERS: Mutex<Vec<Arc<AtomicU64>>> = Mutex::new(Vec::new());
+static SAMPLE_INTERVAL_MS: AtomicU64 = AtomicU64::new(25);
+static SAMPLER: OnceLock<Option<Thread>> = OnceLock::new();
+
+/// Point-in-time reading of this process
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResourceSample {
+    /// User plus system CPU time consumed so far
+    pub cpu_time_ms: Option<u64>,
+    /// Resident set size
+    pub rss_bytes: Option<u64>,
+}
+
+impl ResourceSample {
+    /// Read the current process counters
+    pub fn now() -> Self {
+        Self { cpu_time_ms: process_cpu_time_ms(), rss_bytes: process_rss_bytes() }
+    }
+}
+
+/// Usage during one phase of a command
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseUsage {
+    pub name: &'static str,
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: Option<u64>,
+    /// Resident memory at the end of the phase minus at its start
+    pub rss_delta_bytes: Option<i64>,
+}
+
+/// Resource summary for one command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceUsage {
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: Option<u64>,
+    pub rss_start_bytes: Option<u64>,
+    pub rss_peak_bytes: Option<u64>,
+    pub rss_end_bytes: Option<u64>,
+    /// Peak GPU memory held by this process, when GPU sampling is enabled
+    pub gpu_memory_peak_bytes: Option<u64>,
+    pub phases: Vec<PhaseUsage>,
+}
+
+impl ResourceUsage {
+    /// Average CPU utilisation over the command, 100.0 = one core fully busy
+    pub fn cpu_percent(&self) -> Option<f64> {
+        let cpu = self.cpu_time_ms?;
+        Some(cpu as f64 * 100.0 / self.wall_time_ms.max(1) as f64)
+    }
+
+    /// Phase that used the most CPU time (or wall time when CPU is unavailable)
+    pub fn heaviest_phase(&self) -> Option<&PhaseUsage> {
+        self.phases
+            .iter()
+            .max_by_key(|phase| (phase.cpu_time_ms.unwrap_or(0), phase.wall_time_ms))
+    }
+}
+
+impl std::fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.cpu_time_ms, self.cpu_percent()) {
+            (Some(cpu), Some(percent)) => write!(f, "cpu {}ms ({:.0}%)", cpu, percent)?,
+            _ => write!(f, "cpu n/a")?,
+        }
+        match self.rss_peak_bytes {
+            Some(peak) => write!(f, ", peak rss {}", super::storage::format_bytes(peak))?,
+            None => write!(f, ", rss n/a")?,
+        }
+        if let Some(gpu) = self.gpu_memory_peak_bytes {
+            write!(f, ", gpu {}", super::storage::format_bytes(gpu))?;
+        }
+        if let Some(phase) = self.heaviest_phase() {
+            write!(f, ", heaviest phase: {}", phase.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Samples resource usage while a command runs
+pub struct ResourceProfiler {
+    sample_gpu: bool,
+    started: Instant,
+    start: ResourceSample,
+    gpu_peak: Option<u64>,
+    phases: Vec<PhaseUsage>,
+    current_phase: Option<(&'static str, Instant, ResourceSample)>,
+    peak_rss: Arc<AtomicU64>,
+    /// Registered with the shared sampler thread
+    sampling: bool,
+}
+
+impl ResourceProfiler {
+    /// Take the starting sample and have the sampler thread track peak memory
+    pub fn start(config: &ResourceConfig) -> Self {
+        let start = ResourceSample::now();
+        let peak_rss = Arc::new(AtomicU64::new(start.rss_bytes.unwrap_or(0)));
+
+        let sampler = (start.rss_bytes.is_some() && config.sample_interval_ms > 0)
+            .then(sampler_thread)
+            .flatten();
+        if let Some(sampler) = sampler {
+            SAMPLE_INTERVAL_MS.store(config.sample_interval_ms, Ordering::Relaxed);
+            WATCHERS.lock().unwrap_or_else(PoisonError::into_inner).push(peak_rss.clone());
+            sampler.unpark();
+        }
+
+        Self {
+            sample_gpu: config.sample_gpu,
+            started: Instant::now(),
+            start,
+            gpu_peak: if config.sample_gpu { gpu_memory_bytes() } else { None },
+            phases: Vec::new(),
+            current_phase: None,
+            peak_rss,
+            sampling: sampler.is_some(),
+        }
+    }
+
+    /// End the current phase (if any) and start measuring `name`
+    pub fn phase(&mut self, name: &'static str) {
+        let now = ResourceSample::now();
+        self.close_phase(now);
+        self.current_phase = Some((name, Instant::now(), now));
+    }
+
+    /// Stop sampling and summarise
+    pub fn finish(mut self) -> ResourceUsage {
+        let end = ResourceSample::now();
+        self.close_phase(end);
+        self.stop_sampler();
+
+        if self.sample_gpu {
+            self.gpu_peak = self.gpu_peak.max(gpu_memory_bytes());
+        }
+        let peak = self.peak_rss.load(Ordering::Relaxed);
+        let rss_peak_bytes = end.rss_bytes.map(|rss| rss.max(peak));
+
+        let usage = ResourceUsage {
+            wall_time_ms: self.started.elapsed().as_millis() as u64,
+            cpu_time_ms: cpu_delta(&self.start, &end),
+            rss_start_bytes: self.start.rss_bytes,
+            rss_peak_bytes,
+            rss_end_bytes: end.rss_bytes,
+            gpu_memory_peak_bytes: self.gpu_peak,
+            phases: std::mem::take(&mut self.phases),
+        };
+        debug!("Command resources: {}", usage);
+        usage
+    }
+
+    fn close_phase(&mut self, now: ResourceSample) {
+        if let Some(rss) = now.rss_bytes {
+            self.peak_rss.fetch_max(rss, Ordering::Relaxed);
+        }
+        if let Some((name, started, start)) = self.current_phase.take() {
+            self.phases.push(PhaseUsage {
+                name,
+                wall_time_ms: started.elapsed().as_millis() as u64,
+                cpu_time_ms: cpu_delta(&start, &now),
+                rss_delta_bytes: now.rss_bytes.zip(start.rss_bytes).map(|(end, start)| end as i64 - start as i64),
+            });
+        }
+    }
+
+    fn stop_sampler(&mut self) {
+        if std::mem::take(&mut self.sampling) {
+            WATCHERS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|peak| !Arc::ptr_eq(peak, &self.peak_rss));
+        }
+    }
+}
+
+impl Drop for ResourceProfiler {
+    /// Commands that fail early drop the profiler without finishing it
+    fn drop(&mut self) {
+        self.stop_sampler();
+    }
+}
+
+/// The process-wide sampler, started on first use. While no profiler is
+/// running it stays parked instead of waking every interval.
+fn sampler_thread() -> Option<&'static Thread> {
+    SAMPLER
+        .get_or_init(|| {
+            std::thread::Builder::new()
+                .name("luna-resource-sampler".to_string())
+                .spawn(|| loop {
+                    let watchers = WATCHERS.lock().unwrap_or_else(PoisonError::into_inner).clone();
+                    if watchers.is_empty() {
+                        std::thread::park();
+                        continue;
+                    }
+                    if let Some(rss) = process_rss_bytes() {
+                        for peak in &watchers {
+                            peak.fetch_max(rss, Ordering::Relaxed);
+                        }
+                    }
+                    std::thread::park_timeout(Duration::from_millis(SAMPLE_INTERVAL_MS.load(Ordering::Relaxed)));
+                })
+                .ok()
+                .map(|handle| handle.thread().clone())
+        })
+        .as_ref()
+}
+
+fn cpu_delta(start: &ResourceSample, end: &ResourceSample) -> Option<u64> {
+    Some(end.cpu_time_ms?.saturating_sub(start.cpu_time_ms?))
+}
+
+/// utime + stime from `/proc/self/stat`
+#[cfg(target_os = "linux")]
+fn process_cpu_time_ms() -> Option<u64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    parse_cpu_time_ms(&stat, clock_ticks_per_sec()?)
+}
+
+/// Kernel plus user time from GetProcessTimes
+#[cfg(target_os = "windows")]
+fn process_cpu_time_ms() -> Option<u64> {
+    crate::utils::win32::process_cpu_time_ms()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn process_cpu_time_ms() -> Option<u64> {
+    None
+}
+
+/// Units of the `/proc/self/stat` times (USER_HZ), from sysconf(_SC_CLK_TCK)
+#[cfg(target_os = "linux")]
+fn clock_ticks_per_sec() -> Option<u64> {
+    const SC_CLK_TCK: std::ffi::c_int = 2;
+    extern "C" {
+        fn sysconf(name: std::ffi::c_int) -> std::ffi::c_long;
+    }
+    // SAFETY: sysconf only reads a system constant
+    let ticks = unsafe { sysconf(SC_CLK_TCK) };
+    u64::try_from(ticks).ok().filter(|&ticks| ticks > 0)
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cpu_time_ms(stat: &str, ticks_per_sec: u64) -> Option<u64> {
+    // The command name may contain spaces, so count fields after its closing paren
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) * 1000 / ticks_per_sec)
+}
+
+/// VmRSS from `/proc/self/status`
+#[cfg(target_os = "linux")]
+fn process_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_rss_bytes(&status)
+}
+
+/// Working set from GetProcessMemoryInfo
+#[cfg(target_os = "windows")]
+fn process_rss_bytes() -> Option<u64> {
+    crate::utils::win32::process_working_set_bytes()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn process_rss_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_rss_bytes(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// GPU memory used by this process according to `nvidia-smi`
+fn gpu_memory_bytes() -> Option<u64> {
+    let output = std::process::Command::new("nvidia-smi")
+        .args(["--query-compute-apps=pid,used_memory", "--format=csv,noheader,nounits"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_gpu_memory_bytes(&String::from_utf8_lossy(&output.stdout), std::process::id())
+}
+
+fn parse_gpu_memory_bytes(csv: &str, pid: u32) -> Option<u64> {
+    let mib: u64 = csv
+        .lines()
+        .filter_map(|line| line.split_once(','))
+        .filter(|(line_pid, _)| line_pid.trim().parse() == Ok(pid))
+        .filter_map(|(_, used)| used.trim().parse::<u64>().ok())
+        .sum();
+    Some(mib * 1024 * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_files() {
+        let stat = "1234 (luna worker) S 1 1234 1234 0 -1 4194560 500 0 0 0 250 50 0 0 20 0 4 0";
+        assert_eq!(parse_cpu_time_ms(stat, 100), Some(3000));
+        assert_eq!(parse_cpu_time_ms(stat, 250), Some(1200));
+
+        let status = "Name:\tluna\nVmPeak:\t  900000 kB\nVmRSS:\t   20480 kB\nThreads:\t4\n";
+        assert_eq!(parse_rss_bytes(status), Some(20 * 1024 * 1024));
+        assert_eq!(parse_rss_bytes("Name:\tluna\n"), None);
+    }
+
+    #[test]
+    fn test_parse_gpu_memory() {
+        let csv = "4242, 512\n99, 2048\n4242, 256\n";
+        assert_eq!(parse_gpu_memory_bytes(csv, 4242), Some(768 * 1024 * 1024));
+        assert_eq!(parse_gpu_memory_bytes(csv, 7), Some(0));
+    }
+
+    #[test]
+    fn test_profiler_records_phases() {
+        let mut profiler = ResourceProfiler::start(&ResourceConfig::default());
+        profiler.phase("analysis");
+        let buffer = vec![1u8; 8 * 1024 * 1024];
+        std::hint::black_box(&buffer);
+        profiler.phase("execution");
+        drop(buffer);
+        let usage = profiler.finish();
+
+        let names: Vec<_> = usage.phases.iter().map(|p| p.name).collect();
+        assert_eq!(names, ["analysis", "execution"]);
+        if cfg!(any(target_os = "linux", target_os = "windows")) {
+            assert!(usage.cpu_time_ms.is_some());
+            assert!(usage.rss_peak_bytes >= usage.rss_start_bytes);
+        }
+        assert!(usage.to_string().starts_with("cpu"));
+    }
+
+    #[test]
+    fn test_profilers_share_one_sampler_thread() {
+        let config = ResourceConfig { sample_interval_ms: 1, ..ResourceConfig::default() };
+        let first = ResourceProfiler::start(&config);
+        let second = ResourceProfiler::start(&config);
+        let peaks = [first.peak_rss.clone(), second.peak_rss.clone()];
+        let registered = |peak: &Arc<AtomicU64>| WATCHERS.lock().unwrap().iter().any(|p| Arc::ptr_eq(p, peak));
+
+        if first.sampling {
+            assert!(peaks.iter().all(registered));
+        }
+        first.finish();
+        drop(second);
+        assert!(!peaks.iter().any(registered), "finished and dropped profilers stop being sampled");
+    }
+}
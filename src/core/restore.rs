@@ -0,0 +1,300 @@
+/*!
+ * Luna Restore - Put the cursor and focus back after a command
+ *
+ * With `input.restore_after_command` (or `ExecuteOptions::restore`), the
+ * cursor position and the focused window are saved before a command runs
+ * and put back once it ends, however it ended. Each is only restored when
+ * that is safe: the cursor stays where it is if the user moved it while the
+ * command ran, and focus stays if the saved window has closed. What was
+ * done is reported as `RestoreStep`s in the command result.
+ */
+
+use serde::{Deserialize, Serialize};
+
+use super::focus::command_output;
+use crate::vision::caret::Caret;
+
+/// The cursor position and focused window before a command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DesktopState {
+    pub cursor: Option<(i32, i32)>,
+    /// Platform window id of the focused window
+    pub window: Option<String>,
+}
+
+/// Reads and changes the cursor and window focus, and reads the caret
+pub trait DesktopProbe {
+    fn cursor(&self) -> Option<(i32, i32)>;
+    fn active_window(&self) -> Option<String>;
+    fn window_exists(&self, window: &str) -> bool;
+    /// Raise and focus `window`; false when that failed
+    fn activate_window(&self, window: &str) -> bool;
+    /// Where typed text would go, when the platform can say
+    fn caret(&self) -> Option<Caret> {
+        None
+    }
+
+    fn snapshot(&self) -> DesktopState {
+        DesktopState { cursor: self.cursor(), window: self.active_window() }
+    }
+}
+
+/// The real desktop, through `xdotool` on X11. Elsewhere nothing can be
+/// read, so nothing is restored and the steps say why. The caret is read
+/// on Windows (see `vision::caret`).
+pub struct SystemDesktop;
+
+impl DesktopProbe for SystemDesktop {
+    fn cursor(&self) -> Option<(i32, i32)> {
+        crate::input::cursor_position()
+    }
+
+    fn active_window(&self) -> Option<String> {
+        command_output("xdotool", &["getactivewindow"])
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    fn window_exists(&self, window: &str) -> bool {
+        command_output("xdotool", &["getwindowname", window]).is_some()
+    }
+
+    fn activate_window(&self, window: &str) -> bool {
+        command_output("xdotool", &["windowactivate", "--sync", window]).is_some()
+    }
+
+    fn caret(&self) -> Option<Caret> {
+        crate::vision::caret::platform_caret()
+    }
+}
+
+/// What was restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreTarget {
+    Cursor,
+    Focus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreOutcome {
+    Restored,
+    /// Already as it was saved
+    Unchanged,
+    /// Left alone because restoring wasn't safe or possible
+    Skipped,
+    Failed,
+}
+
+/// One restoration in a command's report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreStep {
+    pub target: RestoreTarget,
+    pub outcome: RestoreOutcome,
+    pub detail: String,
+}
+
+impl RestoreStep {
+    fn new(target: RestoreTarget, outcome: RestoreOutcome, detail: impl Into<String>) -> Self {
+        Self { target, outcome, detail: detail.into() }
+    }
+}
+
+impl std::fmt::Display for RestoreStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let target = match self.target {
+            RestoreTarget::Cursor => "cursor",
+            RestoreTarget::Focus => "focus",
+        };
+        let outcome = match self.outcome {
+            RestoreOutcome::Restored => "restored",
+            RestoreOutcome::Unchanged => "unchanged",
+            RestoreOutcome::Skipped => "skipped",
+            RestoreOutcome::Failed => "failed",
+        };
+        write!(f, "{} {} ({})", target, outcome, self.detail)
+    }
+}
+
+/// Focus the saved window again, unless it is still focused or has closed
+pub fn restore_focus(desktop: &dyn DesktopProbe, saved: &DesktopState) -> RestoreStep {
+    let step = |outcome, detail: String| RestoreStep::new(RestoreTarget::Focus, outcome, detail);
+    let Some(window) = &saved.window else {
+        return step(RestoreOutcome::Skipped, "focused window unknown".to_string());
+    };
+    if desktop.active_window().as_ref() == Some(window) {
+        return step(RestoreOutcome::Unchanged, format!("window {}", window));
+    }
+    if !desktop.window_exists(window) {
+        return step(RestoreOutcome::Skipped, format!("window {} has closed", window));
+    }
+    match desktop.activate_window(window) {
+        true => step(RestoreOutcome::Restored, format!("window {}", window)),
+        false => step(RestoreOutcome::Failed, format!("window {} could not be activated", window)),
+    }
+}
+
+/// Where to move the cursor back to, or the step saying why not. `left_at`
+/// is where the command's last click or move put the cursor: if it is
+/// somewhere else now, the user moved it and it stays.
+pub fn cursor_target(saved: &DesktopState, now: Option<(i32, i32)>, left_at: Option<(i32, i32)>) -> Result<(i32, i32), RestoreStep> {
+    let step = |outcome, detail: String| RestoreStep::new(RestoreTarget::Cursor, outcome, detail);
+    let (Some(saved), Some(now)) = (saved.cursor, now) else {
+        return Err(step(RestoreOutcome::Skipped, "cursor position unavailable".to_string()));
+    };
+    if now == saved {
+        return Err(step(RestoreOutcome::Unchanged, format!("at ({}, {})", now.0, now.1)));
+    }
+    if left_at != Some(now) {
+        return Err(step(RestoreOutcome::Skipped, format!("moved by the user to ({}, {})", now.0, now.1)));
+    }
+    Ok(saved)
+}
+
+/// The step for moving the cursor back to `to`
+pub fn cursor_moved(to: (i32, i32), result: Result<(), String>) -> RestoreStep {
+    match result {
+        Ok(()) => RestoreStep::new(RestoreTarget::Cursor, RestoreOutcome::Restored, format!("to ({}, {})", to.0, to.1)),
+        Err(e) => RestoreStep::new(RestoreTarget::Cursor, RestoreOutcome::Failed, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::sandbox::SandboxScene;
+    use crate::core::{ExecuteOptions, Luna, LunaConfig};
+    use crate::input::{ActionType, InputAction, InputError, InputSink};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Desktop {
+        cursor: Option<(i32, i32)>,
+        focused: Option<String>,
+        windows: Vec<String>,
+        caret: Option<Caret>,
+    }
+
+    /// A desktop whose cursor follows the actions sent to it
+    #[derive(Clone, Default)]
+    struct FakeDesktop(Arc<Mutex<Desktop>>);
+
+    impl FakeDesktop {
+        /// The cursor at (5, 5) in a focused editor window
+        fn editing() -> Self {
+            let desktop = Self::default();
+            desktop.reset();
+            desktop
+        }
+
+        fn reset(&self) {
+            *self.0.lock().unwrap() = Desktop { cursor: Some((5, 5)), focused: Some("editor".to_string()), windows: vec!["editor".to_string()], caret: None };
+        }
+
+        fn focus(&self, window: &str) {
+            self.0.lock().unwrap().focused = Some(window.to_string());
+        }
+    }
+
+    impl DesktopProbe for FakeDesktop {
+        fn cursor(&self) -> Option<(i32, i32)> {
+            self.0.lock().unwrap().cursor
+        }
+        fn active_window(&self) -> Option<String> {
+            self.0.lock().unwrap().focused.clone()
+        }
+        fn window_exists(&self, window: &str) -> bool {
+            self.0.lock().unwrap().windows.iter().any(|w| w == window)
+        }
+        fn activate_window(&self, window: &str) -> bool {
+            self.focus(window);
+            true
+        }
+        fn caret(&self) -> Option<Caret> {
+            self.0.lock().unwrap().caret
+        }
+    }
+
+    struct Follow(FakeDesktop, Box<dyn InputSink>);
+
+    impl InputSink for Follow {
+        fn send(&mut self, action: &InputAction) -> Result<(), InputError> {
+            match action.action_type {
+                // Clicking in the sandbox focuses its window
+                ActionType::Click { .. } => {
+                    self.0 .0.lock().unwrap().cursor = Some((action.target.x, action.target.y));
+                    self.0.focus("sandbox");
+                }
+                ActionType::Move { x, y } => self.0 .0.lock().unwrap().cursor = Some((x, y)),
+                _ => {}
+            }
+            self.1.send(action)
+        }
+    }
+
+    #[test]
+    fn test_restore_only_when_safe() {
+        let desktop = FakeDesktop::editing();
+        let saved = desktop.snapshot();
+
+        assert_eq!(restore_focus(&desktop, &saved).outcome, RestoreOutcome::Unchanged);
+        desktop.focus("popup");
+        assert_eq!(restore_focus(&desktop, &saved).outcome, RestoreOutcome::Restored);
+        assert_eq!(desktop.active_window().as_deref(), Some("editor"));
+        desktop.0.lock().unwrap().windows.clear();
+        desktop.focus("popup");
+        assert_eq!(restore_focus(&desktop, &saved).outcome, RestoreOutcome::Skipped);
+
+        assert_eq!(cursor_target(&saved, Some((300, 200)), Some((300, 200))), Ok((5, 5)));
+        assert_eq!(cursor_target(&saved, Some((5, 5)), Some((300, 200))).unwrap_err().outcome, RestoreOutcome::Unchanged);
+        let moved = cursor_target(&saved, Some((900, 10)), Some((300, 200))).unwrap_err();
+        assert_eq!(moved.outcome, RestoreOutcome::Skipped);
+        assert_eq!(moved.to_string(), "cursor skipped (moved by the user to (900, 10))");
+        assert_eq!(cursor_target(&DesktopState::default(), Some((1, 1)), None).unwrap_err().outcome, RestoreOutcome::Skipped);
+    }
+
+    #[test]
+    fn test_command_restores_cursor_and_focus() {
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let desktop = FakeDesktop::editing();
+        luna.set_desktop_probe(Box::new(desktop.clone()));
+        luna.ensure_input().set_sink(Some(Box::new(Follow(desktop.clone(), sandbox.input_sink()))));
+
+        let result = luna.execute_command("click Save", &ExecuteOptions::default()).unwrap();
+        assert!(result.restoration.is_empty(), "off by default");
+        assert_eq!(desktop.active_window().as_deref(), Some("sandbox"));
+
+        desktop.reset();
+        let options = ExecuteOptions { restore: Some(true), ..ExecuteOptions::default() };
+        let result = luna.execute_command("click Save", &options).unwrap();
+        assert_eq!(sandbox.scene().widget("Save").unwrap().clicks, 2);
+        let outcomes: Vec<_> = result.restoration.iter().map(|step| (step.target, step.outcome)).collect();
+        assert_eq!(outcomes, [(RestoreTarget::Focus, RestoreOutcome::Restored), (RestoreTarget::Cursor, RestoreOutcome::Restored)]);
+        assert_eq!(desktop.snapshot(), DesktopState { cursor: Some((5, 5)), window: Some("editor".to_string()) });
+
+        // Nothing runs in a dry run, so nothing is restored
+        let dry = ExecuteOptions { dry_run: true, ..options };
+        assert!(luna.execute_command("click Save", &dry).unwrap().restoration.is_empty());
+    }
+
+    #[test]
+    fn test_typing_keeps_a_caret_already_in_the_field() {
+        use crate::vision::caret::CaretSource;
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let desktop = FakeDesktop::editing();
+        luna.set_desktop_probe(Box::new(desktop.clone()));
+
+        let err = luna.execute_command("type Al at the cursor", &ExecuteOptions::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<crate::core::LunaError>(), Some(crate::core::LunaError::Vision(_))), "{}", err);
+
+        luna.execute_command("click Name", &ExecuteOptions::default()).unwrap();
+        desktop.0.lock().unwrap().caret = Some(Caret { x: 110, y: 108, height: 16, source: CaretSource::System });
+        luna.execute_command("type Bob into Name", &ExecuteOptions::default()).unwrap();
+        luna.execute_command("type ! at the cursor", &ExecuteOptions::default()).unwrap();
+        let name = sandbox.scene().widget("Name").unwrap().clone();
+        assert_eq!((name.value.as_str(), name.clicks), ("Bob!", 1));
+    }
+}
@@ -0,0 +1,433 @@
+/*!
+ * Luna Review - Doubtful detections queued for the user to check
+ *
+ * When a plan leans on a detection LUNA is unsure of - a click target below
+ * `review.confidence_below`, or the look-alikes a clarifying question was
+ * asked about - the element is queued with a crop of the screen around it.
+ * When the user has a spare moment (`review` in the REPL, or
+ * `GET`/`POST /v1/review`) each item is confirmed, relabeled or rejected.
+ * Relabels become correction samples for `training`, and every verdict is
+ * kept per app: once an app has `review.min_verdicts`, the detection
+ * threshold that best separates its confirmed from its rejected detections
+ * is used for that app's analyses.
+ */
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::scratch::ScratchRegistry;
+use super::{ElementBounds, LunaError, ScreenElement};
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// Directory of the queue and its crops under the storage root
+pub const REVIEW_DIR: &str = "review";
+const QUEUE_FILE: &str = "queue.json";
+/// Verdicts kept per app and kind; the oldest are dropped first
+const MAX_VERDICTS: usize = 200;
+
+/// Why a detection was queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewReason {
+    /// A click target detected with low confidence
+    LowConfidence,
+    /// One of several elements a command fitted equally well
+    Ambiguous,
+}
+
+/// A detection waiting for the user's verdict
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReviewItem {
+    pub id: u64,
+    pub reason: ReviewReason,
+    pub command: String,
+    /// Title of the window in front; `None` when it could not be read
+    pub app: Option<String>,
+    pub element_type: String,
+    pub text: Option<String>,
+    /// `(x, y, width, height)` on the screen
+    pub bounds: (i32, i32, i32, i32),
+    pub confidence: f32,
+    /// The crop around the element, relative to the review directory
+    pub crop_file: Option<String>,
+    /// Screen position of the crop's top-left corner
+    pub crop_origin: (i32, i32),
+    /// Unix seconds
+    pub queued_at: u64,
+}
+
+impl ReviewItem {
+    /// An item for `element`, numbered and cropped when it is queued
+    pub fn new(reason: ReviewReason, command: &str, app: Option<&str>, element: &ScreenElement) -> Self {
+        let b = &element.bounds;
+        Self {
+            id: 0,
+            reason,
+            command: command.to_string(),
+            app: app.map(str::to_string),
+            element_type: element.element_type.clone(),
+            text: element.text.clone(),
+            bounds: (b.x, b.y, b.width, b.height),
+            confidence: element.confidence,
+            crop_file: None,
+            crop_origin: (b.x, b.y),
+            queued_at: 0,
+        }
+    }
+
+    /// The element's bounds within its crop
+    pub fn bounds_in_crop(&self) -> ElementBounds {
+        let (x, y, width, height) = self.bounds;
+        ElementBounds::new(x - self.crop_origin.0, y - self.crop_origin.1, width, height)
+    }
+
+    fn is_same(&self, other: &ReviewItem) -> bool {
+        (&self.app, &self.element_type, &self.text, self.bounds) == (&other.app, &other.element_type, &other.text, other.bounds)
+    }
+}
+
+impl std::fmt::Display for ReviewItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (x, y, width, height) = self.bounds;
+        write!(f, "#{} {}", self.id, self.element_type)?;
+        if let Some(text) = &self.text {
+            write!(f, " '{}'", text)?;
+        }
+        write!(f, " {}x{} at ({}, {}), confidence {:.2}", width, height, x, y, self.confidence)?;
+        if let Some(app) = &self.app {
+            write!(f, " in '{}'", app)?;
+        }
+        let why = match self.reason {
+            ReviewReason::LowConfidence => "low confidence",
+            ReviewReason::Ambiguous => "ambiguous",
+        };
+        write!(f, " for '{}' ({})", self.command, why)
+    }
+}
+
+/// The user's answer about a queued detection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum Verdict {
+    /// Detected correctly
+    Confirm,
+    /// There is an element, but of type `label`
+    Relabel { label: String },
+    /// Nothing is there
+    Reject,
+}
+
+impl Verdict {
+    /// "y"/"yes", "n"/"no", or an element type to relabel as
+    pub fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_lowercase().as_str() {
+            "" => None,
+            "y" | "yes" | "confirm" => Some(Verdict::Confirm),
+            "n" | "no" | "reject" => Some(Verdict::Reject),
+            label if label.chars().all(|c| c.is_alphanumeric() || c == '_') => Some(Verdict::Relabel { label: label.to_string() }),
+            _ => None,
+        }
+    }
+
+    /// Whether there is an element where it was detected
+    fn is_real(&self) -> bool {
+        !matches!(self, Verdict::Reject)
+    }
+}
+
+/// What resolving an item did
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReviewOutcome {
+    pub item: ReviewItem,
+    /// The relabel was saved as a correction sample
+    pub exported: bool,
+    /// Detection threshold learned for the item's app, once it has enough verdicts
+    pub threshold: Option<f32>,
+}
+
+/// Confidences of one app's reviewed detections
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppVerdicts {
+    pub confirmed: Vec<f32>,
+    pub rejected: Vec<f32>,
+}
+
+impl AppVerdicts {
+    fn record(&mut self, confidence: f32, real: bool) {
+        let list = if real { &mut self.confirmed } else { &mut self.rejected };
+        list.push(confidence);
+        if list.len() > MAX_VERDICTS {
+            list.remove(0);
+        }
+    }
+
+    /// The detection threshold that misjudges the fewest verdicts - keeping
+    /// a rejected detection or dropping a confirmed one - and of those the
+    /// one nearest `current`. `None` below `min_verdicts`.
+    pub fn threshold(&self, current: f32, min_verdicts: usize) -> Option<f32> {
+        if self.confirmed.len() + self.rejected.len() < min_verdicts.max(1) {
+            return None;
+        }
+        let errors = |t: f32| {
+            self.confirmed.iter().filter(|c| **c < t).count() + self.rejected.iter().filter(|r| **r >= t).count()
+        };
+        // Just above a rejected confidence, on a readable 0.01 step
+        let above = |r: f32| {
+            let step = ((r * 100.0).floor() + 1.0) / 100.0;
+            if step > r { step } else { step + 0.01 }
+        };
+        std::iter::once(current)
+            .chain(self.confirmed.iter().copied())
+            .chain(self.rejected.iter().map(|r| above(*r)))
+            .map(|t| t.clamp(0.0, 1.0))
+            .min_by(|a, b| errors(*a).cmp(&errors(*b)).then((a - current).abs().total_cmp(&(b - current).abs())))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    next_id: u64,
+    items: Vec<ReviewItem>,
+    /// By app; "" for detections whose app was unknown
+    apps: BTreeMap<String, AppVerdicts>,
+}
+
+/// Pending items and past verdicts, persisted as one JSON file beside the crops
+pub struct ReviewQueue {
+    dir: PathBuf,
+    saved: Saved,
+    /// The oldest items are dropped beyond this many
+    max_items: usize,
+    /// Crops are registered here until the queue entry naming them is saved
+    scratch: ScratchRegistry,
+}
+
+impl ReviewQueue {
+    /// Load the queue in `dir`; a missing directory is an empty queue
+    pub fn open(dir: impl Into<PathBuf>, max_items: usize) -> Result<Self> {
+        let dir = dir.into();
+        let saved = match std::fs::read_to_string(dir.join(QUEUE_FILE)) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Saved::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { dir, saved, max_items, scratch: ScratchRegistry::default() })
+    }
+
+    /// Journal crops in `scratch`, so one whose entry never got saved is removed
+    pub fn with_scratch(mut self, scratch: ScratchRegistry) -> Self {
+        self.scratch = scratch;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Pending items, oldest first
+    pub fn pending(&self) -> &[ReviewItem] {
+        &self.saved.items
+    }
+
+    pub fn get(&self, id: u64) -> Option<&ReviewItem> {
+        self.saved.items.iter().find(|item| item.id == id)
+    }
+
+    /// Queue `item` with a crop of `frame` reaching `margin` pixels around it.
+    /// `None` when the same detection is already pending.
+    pub fn push(&mut self, mut item: ReviewItem, frame: &Image, margin: i32) -> Result<Option<&ReviewItem>> {
+        if self.saved.items.iter().any(|pending| pending.is_same(&item)) {
+            return Ok(None);
+        }
+        self.saved.next_id += 1;
+        item.id = self.saved.next_id;
+        item.queued_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+
+        let scope = self.scratch.scope("review crop");
+        let (x, y, width, height) = item.bounds;
+        let margin = margin.max(0);
+        let (left, top) = ((x - margin).max(0), (y - margin).max(0));
+        let right = (x + width + margin).min(frame.width as i32);
+        let bottom = (y + height + margin).min(frame.height as i32);
+        if right > left && bottom > top {
+            let crop = frame.crop(&Rectangle::new(left as f64, top as f64, (right - left) as f64, (bottom - top) as f64));
+            let file = format!("crops/{}.png", item.id);
+            std::fs::create_dir_all(self.dir.join("crops"))?;
+            super::to_dynamic_image(&crop)?.save(scope.register(self.dir.join(&file))?)?;
+            item.crop_file = Some(file);
+            item.crop_origin = (left, top);
+        }
+
+        self.saved.items.push(item);
+        while self.saved.items.len() > self.max_items.max(1) {
+            let dropped = self.saved.items.remove(0);
+            self.remove_crop(&dropped);
+        }
+        self.save()?;
+        if let Some(path) = self.saved.items.last().and_then(|item| self.crop_path(item)) {
+            scope.keep(&path);
+        }
+        Ok(self.saved.items.last())
+    }
+
+    /// Where the crop of `item` is saved
+    pub fn crop_path(&self, item: &ReviewItem) -> Option<PathBuf> {
+        item.crop_file.as_ref().map(|file| self.dir.join(file))
+    }
+
+    pub fn load_crop(&self, item: &ReviewItem) -> Result<Image> {
+        let path = self.crop_path(item)
+            .ok_or_else(|| LunaError::NotFound(format!("review item #{} has no crop", item.id)))?;
+        let rgb = image::open(path)?.to_rgb8();
+        Ok(Image::from_rgb_data(rgb.width() as usize, rgb.height() as usize, rgb.into_raw()))
+    }
+
+    /// Record `verdict` on item `id` under its app and take the item out of the queue
+    pub fn resolve(&mut self, id: u64, verdict: &Verdict) -> Result<ReviewItem> {
+        let index = self.saved.items.iter().position(|item| item.id == id)
+            .ok_or_else(|| LunaError::NotFound(format!("no review item #{}", id)))?;
+        let item = self.saved.items.remove(index);
+        let app = item.app.clone().unwrap_or_default();
+        self.saved.apps.entry(app).or_default().record(item.confidence, verdict.is_real());
+        self.remove_crop(&item);
+        self.save()?;
+        Ok(item)
+    }
+
+    /// Verdicts on detections in `app`
+    pub fn verdicts(&self, app: Option<&str>) -> Option<&AppVerdicts> {
+        self.saved.apps.get(app.unwrap_or_default())
+    }
+
+    /// Detection threshold learned for each app with enough verdicts; ""
+    /// stands for detections whose app was unknown
+    pub fn thresholds(&self, current: f32, min_verdicts: usize) -> Vec<(String, f32)> {
+        self.saved
+            .apps
+            .iter()
+            .filter_map(|(app, verdicts)| Some((app.clone(), verdicts.threshold(current, min_verdicts)?)))
+            .collect()
+    }
+
+    fn remove_crop(&self, item: &ReviewItem) {
+        if let Some(path) = self.crop_path(item) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Write then rename so a crash never leaves a truncated queue
+        let path = self.dir.join(QUEUE_FILE);
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(&self.saved)?)?;
+        std::fs::rename(&temp, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::sandbox::SandboxScene;
+    use crate::core::{ExecuteOptions, Luna, LunaConfig};
+
+    fn element(text: &str, x: i32, confidence: f32) -> ScreenElement {
+        ScreenElement {
+            element_type: "button".to_string(),
+            bounds: ElementBounds::new(x, 10, 40, 20),
+            shape: None,
+            confidence,
+            text: Some(text.to_string()),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_queue_crops_dedupes_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = Image::new(200, 100, 3);
+        let mut queue = ReviewQueue::open(dir.path(), 2).unwrap();
+
+        let item = ReviewItem::new(ReviewReason::LowConfidence, "click ok", Some("Editor"), &element("OK", 5, 0.4));
+        let queued = queue.push(item.clone(), &frame, 10).unwrap().unwrap().clone();
+        assert_eq!((queued.id, queued.crop_origin), (1, (0, 0)));
+        assert_eq!(queued.bounds_in_crop(), ElementBounds::new(5, 10, 40, 20));
+        assert_eq!(queue.load_crop(&queued).unwrap().width, 55);
+        assert!(queue.push(item, &frame, 10).unwrap().is_none(), "already pending");
+
+        queue.push(ReviewItem::new(ReviewReason::Ambiguous, "click ok", None, &element("OK", 60, 0.9)), &frame, 10).unwrap();
+        queue.push(ReviewItem::new(ReviewReason::Ambiguous, "click ok", None, &element("OK", 120, 0.9)), &frame, 10).unwrap();
+        let ids: Vec<u64> = ReviewQueue::open(dir.path(), 2).unwrap().pending().iter().map(|item| item.id).collect();
+        assert_eq!(ids, [2, 3]);
+        assert!(!queue.crop_path(&queued).unwrap().exists(), "dropped items take their crop along");
+    }
+
+    #[test]
+    fn test_thresholds_separate_rejected_from_confirmed() {
+        let verdicts = AppVerdicts { confirmed: vec![0.62, 0.7, 0.81], rejected: vec![0.41, 0.55] };
+        assert_eq!(verdicts.threshold(0.3, 5), Some(0.56));
+        assert_eq!(verdicts.threshold(0.3, 6), None);
+        // With nothing rejected there is no reason to move
+        assert_eq!(AppVerdicts { confirmed: vec![0.4, 0.5], rejected: Vec::new() }.threshold(0.3, 1), Some(0.3));
+        // A stray rejection above confirmed ones costs less than dropping them
+        let noisy = AppVerdicts { confirmed: vec![0.5, 0.6, 0.7], rejected: vec![0.45, 0.65] };
+        assert_eq!(noisy.threshold(0.3, 1), Some(0.46));
+        assert_eq!(AppVerdicts { confirmed: Vec::new(), rejected: vec![0.41] }.threshold(0.3, 1), Some(0.42));
+
+        assert_eq!(Verdict::parse(" Yes "), Some(Verdict::Confirm));
+        assert_eq!(Verdict::parse("n"), Some(Verdict::Reject));
+        assert_eq!(Verdict::parse("checkbox"), Some(Verdict::Relabel { label: "checkbox".to_string() }));
+        assert_eq!(Verdict::parse("two words"), None);
+    }
+
+    #[test]
+    fn test_reviewed_targets_export_corrections_and_tune_thresholds() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        config.review.enabled = true;
+        config.review.confidence_below = 0.99;
+        config.review.min_verdicts = 2;
+        config.training.export_corrections = true;
+        config.escalation.enabled = false;
+        let mut luna = Luna::new(config).unwrap();
+        luna.enter_sandbox(SandboxScene::tutorial());
+
+        for command in ["click Save", "click Cancel", "click the Name field"] {
+            luna.execute_command(command, &ExecuteOptions::default()).unwrap();
+        }
+        let queued: Vec<_> = luna.review_queue().iter().map(|item| (item.reason, item.element_type.as_str())).collect();
+        assert_eq!(queued, [
+            (ReviewReason::LowConfidence, "button"),
+            (ReviewReason::LowConfidence, "button"),
+            (ReviewReason::LowConfidence, "textfield"),
+        ]);
+        // The same target again is not queued twice
+        luna.execute_command("click Save", &ExecuteOptions::default()).unwrap();
+        assert_eq!(luna.review_queue().len(), 3);
+
+        let ids: Vec<u64> = luna.review_queue().iter().map(|item| item.id).collect();
+        let relabeled = luna.resolve_review(ids[2], Verdict::Relabel { label: "combobox".to_string() }).unwrap();
+        assert!(relabeled.exported);
+        assert_eq!(relabeled.threshold, None, "one verdict is not enough");
+        assert_eq!(luna.resolve_review(ids[0], Verdict::Reject).unwrap().threshold, Some(0.6), "no better split yet");
+        assert_eq!(luna.resolve_review(ids[1], Verdict::Reject).unwrap().threshold, Some(0.96));
+        assert!(luna.review_queue().is_empty());
+        assert!(matches!(
+            luna.resolve_review(ids[1], Verdict::Confirm).unwrap_err().downcast_ref::<LunaError>(),
+            Some(LunaError::NotFound(_))
+        ));
+
+        // The sandbox's 0.95 detections now fall below the learned threshold
+        assert!(luna.execute_command("click Save", &ExecuteOptions::default()).is_err());
+        luna.update_config(LunaConfig { review: Default::default(), ..luna.get_config().clone() }).unwrap();
+        assert!(luna.execute_command("click Save", &ExecuteOptions::default()).is_ok(), "learned thresholds only apply while reviewing");
+    }
+}
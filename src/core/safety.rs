@@ -4,10 +4,26 @@
 // It blocks obviously destructive text commands and rejects actions with
 // out-of-range parameters. The input layer applies its own per-action
 // safety check and rate limiting on top of this (see crate::input).
+//
+// Commands with a destructive verb get a second look at execution time: each
+// click re-reads the dialog it lands in, and only goes ahead when that text
+// says the same thing (see `destructive_verb` and `confirmation_text`).
+//
+// What triggered a command also limits what it may do: scheduled, watcher
+// and API commands each have a context policy (`config.contexts`) capping
+// the risk and types of their actions (see `check_context_action`).
+//
+// `rules` lists what is in force and `evaluate_command` / `evaluate_action`
+// report every rule a command or action would trip without running it, so
+// a policy can be checked while it is being edited.
 
-use super::config::LunaConfig;
-use super::LunaAction;
-use regex::RegexSet;
+use super::config::{ContextPolicy, ContextsConfig, DestructiveCheckConfig, DisruptionConfig, DisruptionPolicy, LunaConfig};
+use super::focus::FocusState;
+use super::{CommandSource, LunaAction, ScreenElement, ShotTarget};
+use crate::input::{keys, RiskLevel};
+use log::warn;
+use regex::{Regex, RegexSet};
+use serde::Serialize;
 
 /// Maximum length of a text command or typed string the agent will accept.
 const MAX_TEXT_LENGTH: usize = 1000;
@@ -18,14 +34,106 @@ const MAX_SCROLL_AMOUNT: i32 = 100;
 /// Maximum wait a planned action may request (milliseconds).
 const MAX_WAIT_MS: u64 = 60_000;
 
+/// Files `PasteImage` may put on the clipboard, by extension
+const PASTE_IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// Verbs that make a command destructive, lowercase
+pub const DESTRUCTIVE_VERBS: &[&str] = &["delete", "remove", "uninstall", "format", "discard", "erase", "wipe"];
+
+/// Element types whose whole text is read as one confirmation
+const DIALOG_TYPES: [&str; 2] = ["dialog", "alert"];
+
+/// Family of safety rules a check belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyCategory {
+    /// Destructive commands and typed text (format, rm -rf, ...)
+    Destructive,
+    /// Out-of-range action parameters
+    Limits,
+    /// Automation while the user is presenting or in do-not-disturb
+    Disruption,
+    /// Actions beyond what the command's source may do
+    Context,
+}
+
+impl std::fmt::Display for SafetyCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SafetyCategory::Destructive => write!(f, "destructive"),
+            SafetyCategory::Limits => write!(f, "limits"),
+            SafetyCategory::Disruption => write!(f, "disruption"),
+            SafetyCategory::Context => write!(f, "context"),
+        }
+    }
+}
+
+/// One rule in force, as listed by `SafetySystem::rules`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SafetyRule {
+    /// Stable name, e.g. "text_length", "pattern (?i)mkfs" or "context.watcher"
+    pub name: String,
+    pub category: SafetyCategory,
+    pub description: String,
+}
+
+/// A rule a command or action would trip, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleHit {
+    pub rule: String,
+    pub category: SafetyCategory,
+    pub reason: String,
+}
+
+impl RuleHit {
+    fn new(rule: impl Into<String>, category: SafetyCategory, reason: impl Into<String>) -> Self {
+        Self { rule: rule.into(), category, reason: reason.into() }
+    }
+}
+
+impl std::fmt::Display for RuleHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}: {}", self.category, self.rule, self.reason)
+    }
+}
+
+/// Dry evaluation of one command, from `Luna::test_safety`
+#[derive(Debug, Clone, Default)]
+pub struct SafetyTest {
+    /// Rules the command itself trips
+    pub command: Vec<RuleHit>,
+    /// Actions planned without the screen, each with the rules it trips;
+    /// empty when planning needs a screen analysis
+    pub actions: Vec<(LunaAction, Vec<RuleHit>)>,
+}
+
+impl SafetyTest {
+    /// Whether nothing would be refused, deferred or double-checked
+    pub fn is_clear(&self) -> bool {
+        self.command.is_empty() && self.actions.iter().all(|(_, hits)| hits.is_empty())
+    }
+}
+
+/// Outcome of the disruption rule for one command
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisruptionDecision {
+    Allow,
+    Defer(String),
+    Refuse(String),
+}
+
 pub struct SafetySystem {
     enabled: bool,
     blocked_patterns: RegexSet,
+    disruption: DisruptionConfig,
+    contexts: ContextsConfig,
+    /// Text a destructive click's dialog must match; `None` when the check is off
+    dialog_pattern: Option<Regex>,
 }
 
 impl SafetySystem {
     pub fn new(config: &LunaConfig) -> Self {
-        let patterns = [
+        let builtin = [
             r"(?i)format\s+[a-z]:",
             r"(?i)rm\s+-rf",
             r"(?i)del\s+/[fqs]",
@@ -35,12 +143,90 @@ impl SafetySystem {
             r"(?i)reg\s+delete",
             r"(?i)mkfs",
         ];
+        let custom = config.safety.blocked_patterns.iter().filter(|pattern| match Regex::new(pattern) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Ignoring invalid safety pattern '{}': {}", pattern, e);
+                false
+            }
+        });
+        let patterns = builtin.iter().map(|p| p.to_string()).chain(custom.cloned());
 
         Self {
             enabled: config.safety.enabled,
             blocked_patterns: RegexSet::new(patterns)
-                .expect("static safety patterns must compile"),
+                .expect("safety patterns are checked before use"),
+            disruption: config.disruption.clone(),
+            contexts: config.contexts.clone(),
+            dialog_pattern: config.destructive_check.enabled.then(|| {
+                Regex::new(&config.destructive_check.dialog_pattern).unwrap_or_else(|e| {
+                    warn!("Invalid destructive check pattern ({}), using the default", e);
+                    Regex::new(&DestructiveCheckConfig::default().dialog_pattern).expect("default pattern must compile")
+                })
+            }),
+        }
+    }
+
+    /// The destructive verb in `command`, when its clicks need the double-check
+    pub fn destructive_verb(&self, command: &str) -> Option<&'static str> {
+        if !self.enabled || self.dialog_pattern.is_none() {
+            return None;
+        }
+        find_destructive_verb(command)
+    }
+
+    /// Part of `text` that confirms a destructive action, `None` if nothing does
+    pub fn destructive_evidence<'t>(&self, text: &'t str) -> Option<&'t str> {
+        self.dialog_pattern.as_ref()?.find(text).map(|m| m.as_str())
+    }
+
+    /// Policy the disruption rule applies to commands from `source`
+    pub fn disruption_policy(&self, source: CommandSource) -> DisruptionPolicy {
+        if !self.enabled || !self.disruption.enabled {
+            return DisruptionPolicy::Allow;
+        }
+        match source {
+            CommandSource::Interactive => self.disruption.interactive,
+            CommandSource::Scheduled => self.disruption.scheduled,
+            CommandSource::Watcher => self.disruption.watcher,
+            CommandSource::Api => self.disruption.api,
+        }
+    }
+
+    /// Apply the disruption rule to a command from `source` given the current focus state.
+    pub fn check_disruption(&self, source: CommandSource, focus: &FocusState) -> DisruptionDecision {
+        let policy = self.disruption_policy(source);
+        let Some(reason) = focus.disruption_reason(self.disruption.respect_do_not_disturb) else {
+            return DisruptionDecision::Allow;
+        };
+        match policy {
+            DisruptionPolicy::Allow => DisruptionDecision::Allow,
+            DisruptionPolicy::Defer => DisruptionDecision::Defer(reason),
+            DisruptionPolicy::Refuse => DisruptionDecision::Refuse(reason),
+        }
+    }
+
+    /// Limits on commands from `source`; none while safety is off
+    pub fn context_policy(&self, source: CommandSource) -> Option<&ContextPolicy> {
+        self.enabled.then(|| self.contexts.policy(source))
+    }
+
+    /// Why commands from `source` may not run `command` at all, if they may not
+    pub fn check_context_command(&self, source: CommandSource, command: &str) -> Option<String> {
+        let policy = self.context_policy(source)?;
+        let verb = find_destructive_verb(command).filter(|_| policy.refuse_destructive)?;
+        Some(format!("{} commands may not {}", source.name(), verb))
+    }
+
+    /// Why commands from `source` may not run `action` at input risk `risk`,
+    /// if they may not
+    pub fn check_context_action(&self, source: CommandSource, action: &LunaAction, risk: Option<RiskLevel>) -> Option<String> {
+        let policy = self.context_policy(source)?;
+        if policy.allowed_actions.as_ref().is_some_and(|allowed| !allowed.iter().any(|kind| kind == action.kind())) {
+            return Some(format!("{} commands may not run {} actions", source.name(), action.kind()));
         }
+        let (risk, max_risk) = risk.zip(policy.max_risk).filter(|(risk, max_risk)| risk > max_risk)?;
+        Some(format!("{:?} is {:?} risk; {} commands may run at most {:?}", action, risk, source.name(), max_risk))
     }
 
     /// Check whether a raw user command is safe to process at all.
@@ -60,20 +246,194 @@ impl SafetySystem {
             return true;
         }
         match action {
-            LunaAction::Click { x, y } => *x >= 0 && *y >= 0,
+            LunaAction::Click { x, y } | LunaAction::Hover { x, y } => *x >= 0 && *y >= 0,
+            LunaAction::ModifierClick { x, y, modifiers } => {
+                *x >= 0 && *y >= 0 && !modifiers.is_empty() && modifiers.len() <= 3
+                    && modifiers.iter().all(|m| keys::lookup_key(m).is_some_and(|code| code.is_modifier()))
+            }
             LunaAction::Type { text } => {
                 text.len() <= MAX_TEXT_LENGTH && !self.blocked_patterns.is_match(text)
             }
             LunaAction::KeyCombo { keys } => !keys.is_empty() && keys.len() <= 5,
+            LunaAction::KeyDown { key } | LunaAction::KeyUp { key } => keys::lookup_key(key).is_some(),
             LunaAction::Scroll { amount, .. } => amount.abs() <= MAX_SCROLL_AMOUNT,
             LunaAction::Wait { milliseconds } => *milliseconds <= MAX_WAIT_MS,
+            LunaAction::WaitForElement { query: text, timeout_ms }
+            | LunaAction::WaitForTextGone { text, timeout_ms } => {
+                !text.trim().is_empty() && *timeout_ms <= MAX_WAIT_MS
+            }
+            LunaAction::WaitForScreenIdle { quiet_ms, timeout_ms } => {
+                quiet_ms <= timeout_ms && *timeout_ms <= MAX_WAIT_MS
+            }
+            // Screenshots only ever write PNG files, so no other file can be overwritten
+            LunaAction::Screenshot { region, target } => {
+                region.as_ref().is_none_or(|r| r.width > 0 && r.height > 0)
+                    && match target {
+                        ShotTarget::Clipboard => true,
+                        ShotTarget::File(path) => has_extension(path, &["png"]),
+                    }
+            }
+            LunaAction::PasteImage { path } => has_extension(path, PASTE_IMAGE_EXTENSIONS),
+        }
+    }
+
+    /// Every rule in force, built-in and configured; none while safety is off
+    pub fn rules(&self) -> Vec<SafetyRule> {
+        if !self.enabled {
+            return Vec::new();
         }
+        let rule = |name: String, category, description: String| SafetyRule { name, category, description };
+        let mut rules = vec![rule(
+            "text_length".to_string(),
+            SafetyCategory::Limits,
+            format!("commands and typed text up to {} characters", MAX_TEXT_LENGTH),
+        )];
+        rules.extend(self.blocked_patterns.patterns().iter().map(|pattern| {
+            rule(format!("pattern {}", pattern), SafetyCategory::Destructive, "refuses matching commands and typed text".to_string())
+        }));
+        rules.push(rule(
+            "action_limits".to_string(),
+            SafetyCategory::Limits,
+            format!("clicks on screen, scrolls up to {}, waits up to {}ms, screenshots only to PNG", MAX_SCROLL_AMOUNT, MAX_WAIT_MS),
+        ));
+        if let Some(pattern) = &self.dialog_pattern {
+            rules.push(rule(
+                "destructive_check".to_string(),
+                SafetyCategory::Destructive,
+                format!("clicks of commands that {} need dialog text matching {}", DESTRUCTIVE_VERBS.join("/"), pattern),
+            ));
+        }
+        for source in CommandSource::ALL {
+            rules.push(rule(format!("context.{}", source.name()), SafetyCategory::Context, describe_policy(self.contexts.policy(source))));
+            match self.disruption_policy(source) {
+                DisruptionPolicy::Allow => {}
+                policy => rules.push(rule(
+                    format!("disruption.{}", source.name()),
+                    SafetyCategory::Disruption,
+                    format!("{:?} while the user is presenting{}", policy,
+                        if self.disruption.respect_do_not_disturb { " or in do-not-disturb" } else { "" }),
+                )),
+            }
+        }
+        rules
     }
+
+    /// Every rule `command` from `source` would trip before planning, with
+    /// the user's focus state as `focus`; nothing is run
+    pub fn evaluate_command(&self, source: CommandSource, command: &str, focus: &FocusState) -> Vec<RuleHit> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut hits = self.text_hits(command);
+        if let Some(reason) = self.check_context_command(source, command) {
+            hits.push(RuleHit::new(format!("context.{}", source.name()), SafetyCategory::Context, reason));
+        }
+        if let Some(verb) = self.destructive_verb(command) {
+            hits.push(RuleHit::new("destructive_check", SafetyCategory::Destructive,
+                format!("'{}' is destructive; each click must land in a dialog confirming it", verb)));
+        }
+        let disruption = |reason| RuleHit::new(format!("disruption.{}", source.name()), SafetyCategory::Disruption, reason);
+        match self.check_disruption(source, focus) {
+            DisruptionDecision::Allow => {}
+            DisruptionDecision::Defer(reason) => hits.push(disruption(format!("deferred: {}", reason))),
+            DisruptionDecision::Refuse(reason) => hits.push(disruption(format!("refused: {}", reason))),
+        }
+        hits
+    }
+
+    /// Every rule `action` from `source`, at input risk `risk`, would trip;
+    /// nothing is run
+    pub fn evaluate_action(&self, source: CommandSource, action: &LunaAction, risk: Option<RiskLevel>) -> Vec<RuleHit> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut hits = match action {
+            LunaAction::Type { text } => self.text_hits(text),
+            _ if !self.is_action_safe(action) => {
+                vec![RuleHit::new("action_limits", SafetyCategory::Limits, format!("{:?} is out of range", action))]
+            }
+            _ => Vec::new(),
+        };
+        if let Some(reason) = self.check_context_action(source, action, risk) {
+            hits.push(RuleHit::new(format!("context.{}", source.name()), SafetyCategory::Context, reason));
+        }
+        hits
+    }
+
+    /// Length and blocked-pattern hits for a command or typed text
+    fn text_hits(&self, text: &str) -> Vec<RuleHit> {
+        let mut hits = Vec::new();
+        if text.len() > MAX_TEXT_LENGTH {
+            hits.push(RuleHit::new("text_length", SafetyCategory::Limits,
+                format!("{} characters, at most {}", text.len(), MAX_TEXT_LENGTH)));
+        }
+        let patterns = self.blocked_patterns.patterns();
+        hits.extend(self.blocked_patterns.matches(text).iter().map(|index| {
+            RuleHit::new(format!("pattern {}", patterns[index]), SafetyCategory::Destructive, "matches a blocked pattern")
+        }));
+        hits
+    }
+}
+
+/// What a context policy allows, in a few words
+fn describe_policy(policy: &ContextPolicy) -> String {
+    let mut limits = Vec::new();
+    if let Some(max_risk) = policy.max_risk {
+        limits.push(format!("risk up to {:?}", max_risk));
+    }
+    if let Some(allowed) = &policy.allowed_actions {
+        limits.push(format!("only {} actions", allowed.join("/")));
+    }
+    if policy.refuse_destructive {
+        limits.push("no destructive commands".to_string());
+    }
+    if limits.is_empty() {
+        "no limits".to_string()
+    } else {
+        limits.join(", ")
+    }
+}
+
+fn find_destructive_verb(command: &str) -> Option<&'static str> {
+    command
+        .split(|c: char| !c.is_alphanumeric())
+        .find_map(|word| DESTRUCTIVE_VERBS.iter().copied().find(|verb| verb.eq_ignore_ascii_case(word)))
+}
+
+/// Whether `path` ends in one of `extensions`, ignoring case
+fn has_extension(path: &std::path::Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+/// Text a click at (`x`, `y`) is judged by: everything inside the innermost
+/// dialog around it in reading order, or the clicked element's own text when
+/// it isn't in one
+pub fn confirmation_text(elements: &[ScreenElement], x: i32, y: i32) -> String {
+    let area = |e: &ScreenElement| e.bounds.width as i64 * e.bounds.height as i64;
+    let dialog = elements
+        .iter()
+        .filter(|e| DIALOG_TYPES.contains(&e.element_type.as_str()) && e.bounds.contains_point(x, y))
+        .min_by_key(|e| area(e));
+    let mut texts: Vec<&ScreenElement> = match dialog {
+        Some(dialog) => {
+            let inside = |e: &ScreenElement| {
+                let (b, d) = (&e.bounds, &dialog.bounds);
+                b.x >= d.x && b.y >= d.y && b.x + b.width <= d.x + d.width && b.y + b.height <= d.y + d.height
+            };
+            elements.iter().filter(|e| inside(e)).collect()
+        }
+        None => elements.iter().filter(|e| e.bounds.contains_point(x, y)).min_by_key(|e| area(e)).into_iter().collect(),
+    };
+    texts.sort_by_key(|e| (e.bounds.y, e.bounds.x));
+    texts.iter().filter_map(|e| e.text.as_deref()).collect::<Vec<_>>().join(" ")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::ElementBounds;
 
     fn system() -> SafetySystem {
         SafetySystem::new(&LunaConfig::default())
@@ -104,4 +464,96 @@ mod tests {
         }));
         assert!(s.is_action_safe(&LunaAction::Click { x: 100, y: 100 }));
     }
+
+    #[test]
+    fn destructive_clicks_must_be_confirmed_by_their_dialog() {
+        let s = system();
+        assert_eq!(s.destructive_verb("Delete report.docx"), Some("delete"));
+        assert_eq!(s.destructive_verb("click remove-all"), Some("remove"));
+        assert_eq!(s.destructive_verb("click the formatting toolbar"), None);
+
+        let element = |element_type: &str, text: &str, x, y, width, height| ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(x, y, width, height),
+            shape: None,
+            confidence: 0.9,
+            text: Some(text.to_string()),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        };
+        let screen = vec![
+            element("button", "Delete", 10, 10, 60, 20),
+            element("dialog", "Confirm", 100, 100, 300, 150),
+            element("text", "Permanently delete report.docx?", 120, 130, 260, 20),
+            element("button", "OK", 300, 200, 60, 24),
+            element("button", "Save", 500, 10, 60, 20),
+        ];
+        let dialog = confirmation_text(&screen, 320, 210);
+        assert_eq!(dialog, "Confirm Permanently delete report.docx? OK");
+        assert_eq!(s.destructive_evidence(&dialog), Some("Permanently"));
+        assert_eq!(s.destructive_evidence(&confirmation_text(&screen, 20, 15)), Some("Delete"));
+        assert_eq!(s.destructive_evidence(&confirmation_text(&screen, 520, 15)), None);
+    }
+
+    #[test]
+    fn unattended_sources_get_stricter_contexts() {
+        let s = system();
+        let paste = LunaAction::PasteImage { path: "chart.png".into() };
+        let click = LunaAction::Click { x: 10, y: 10 };
+        assert_eq!(s.check_context_action(CommandSource::Interactive, &paste, Some(RiskLevel::Critical)), None);
+        assert_eq!(s.check_context_action(CommandSource::Scheduled, &click, Some(RiskLevel::High)), None);
+        assert!(s.check_context_action(CommandSource::Scheduled, &click, Some(RiskLevel::Critical)).unwrap().contains("at most High"));
+        assert_eq!(s.check_context_action(CommandSource::Watcher, &paste, Some(RiskLevel::Low)).unwrap(),
+            "watcher commands may not run paste_image actions");
+        assert!(s.check_context_action(CommandSource::Watcher, &click, Some(RiskLevel::High)).is_some());
+        assert_eq!(s.check_context_action(CommandSource::Watcher, &LunaAction::Wait { milliseconds: 10 }, None), None);
+
+        assert_eq!(s.check_context_command(CommandSource::Watcher, "Delete the popup").unwrap(), "watcher commands may not delete");
+        assert_eq!(s.check_context_command(CommandSource::Scheduled, "Delete old drafts"), None);
+        assert_eq!(s.check_context_command(CommandSource::Watcher, "click OK"), None);
+
+        let mut config = LunaConfig::default();
+        config.safety.enabled = false;
+        let off = SafetySystem::new(&config);
+        assert_eq!(off.check_context_action(CommandSource::Watcher, &paste, Some(RiskLevel::Critical)), None);
+    }
+
+    #[test]
+    fn rules_can_be_listed_and_dry_evaluated() {
+        let mut config = LunaConfig::default();
+        config.safety.blocked_patterns = vec![r"(?i)drop\s+table".to_string()];
+        let s = SafetySystem::new(&config);
+        let names: Vec<String> = s.rules().into_iter().map(|rule| rule.name).collect();
+        assert!(names.contains(&r"pattern (?i)drop\s+table".to_string()), "{:?}", names);
+        assert!(names.contains(&"context.watcher".to_string()) && names.contains(&"disruption.scheduled".to_string()));
+
+        let calm = FocusState::default();
+        assert!(s.evaluate_command(CommandSource::Interactive, "click save", &calm).is_empty());
+        let hits = s.evaluate_command(CommandSource::Watcher, "type DROP TABLE users and delete it", &calm);
+        let rules: Vec<&str> = hits.iter().map(|hit| hit.rule.as_str()).collect();
+        assert_eq!(rules, [r"pattern (?i)drop\s+table", "context.watcher", "destructive_check"]);
+        let presenting = FocusState { presentation_app: Some("powerpnt".to_string()), do_not_disturb: false };
+        assert!(s.evaluate_command(CommandSource::Scheduled, "click save", &presenting)[0].reason.starts_with("deferred"));
+
+        let typed = LunaAction::Type { text: "rm -rf ~".to_string() };
+        assert_eq!(s.evaluate_action(CommandSource::Interactive, &typed, None)[0].category, SafetyCategory::Destructive);
+        let hits = s.evaluate_action(CommandSource::Watcher, &LunaAction::Scroll { direction: "down".to_string(), amount: 500 }, None);
+        assert_eq!(hits[0].rule, "action_limits");
+        assert!(s.evaluate_action(CommandSource::Interactive, &LunaAction::Click { x: 5, y: 5 }, Some(RiskLevel::Low)).is_empty());
+
+        config.safety.blocked_patterns.push("(unclosed".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("(unclosed"));
+    }
+
+    #[test]
+    fn disruption_policy_depends_on_source() {
+        let s = system();
+        let presenting = FocusState { presentation_app: Some("powerpnt".to_string()), do_not_disturb: false };
+
+        assert_eq!(s.check_disruption(CommandSource::Interactive, &presenting), DisruptionDecision::Allow);
+        assert!(matches!(s.check_disruption(CommandSource::Scheduled, &presenting), DisruptionDecision::Defer(_)));
+        assert!(matches!(s.check_disruption(CommandSource::Watcher, &presenting), DisruptionDecision::Refuse(_)));
+        assert_eq!(s.check_disruption(CommandSource::Watcher, &FocusState::default()), DisruptionDecision::Allow);
+    }
 }
@@ -0,0 +1,737 @@
+/*!
+ * Luna Sandbox - A simulated desktop to practice commands on
+ *
+ * Trying "click Save" for the first time against a real desktop is a leap of
+ * faith. In the sandbox the same command goes through the same pipeline
+ * (safety, capture, analysis, planning, confirmation, execution) but capture
+ * shows a simulated scene, input lands in that scene, and nothing reaches the
+ * real screen, mouse or keyboard. The scene reacts like a small form would:
+ * fields take focus and text, checkboxes toggle, buttons count presses, and
+ * every effect is written to its log so the user sees what a command did.
+ *
+ * The scene is its own model: detection reports its widgets as they are,
+ * since the hand-written detectors cannot read the labels it draws. What the
+ * user practices is everything after that - how commands are understood,
+ * which element is chosen and what the actions do.
+ */
+
+use anyhow::Result;
+use log::info;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{ElementBounds, COLOR_ATTRIBUTE, LABEL_ATTRIBUTE, SHAPE_ATTRIBUTE};
+use crate::ai::{ElementDetection, ElementDetector};
+use crate::input::{ActionType, InputAction, InputError, InputSink};
+use crate::utils::image_processing::Image;
+use crate::vision::color::Rgb;
+use crate::vision::screen_capture::{CaptureError, FrameSource};
+
+/// Width of one character in drawn text placeholders
+const CHAR_WIDTH: i32 = 8;
+const BACKGROUND: Rgb = Rgb { r: 236, g: 236, b: 236 };
+const INK: Rgb = Rgb { r: 40, g: 40, b: 40 };
+const BORDER: Rgb = Rgb { r: 120, g: 120, b: 120 };
+const FOCUS: Rgb = Rgb { r: 40, g: 110, b: 220 };
+const SELECTION: Rgb = Rgb { r: 0, g: 120, b: 215 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WidgetKind {
+    Button,
+    TextField,
+    Checkbox,
+    /// A colored disc, named by color ("the red circle")
+    Circle,
+    /// A colored square, named by color
+    Square,
+    /// A file or row that can be selected, alone or with others
+    ListItem,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Widget {
+    pub kind: WidgetKind,
+    pub label: String,
+    pub bounds: ElementBounds,
+    pub color: Rgb,
+    /// Contents of a text field
+    pub value: String,
+    /// State of a checkbox
+    pub checked: bool,
+    /// Whether a list item is selected
+    pub selected: bool,
+    pub clicks: u32,
+}
+
+impl Widget {
+    pub fn new(kind: WidgetKind, label: &str, bounds: ElementBounds) -> Self {
+        let color = match kind {
+            WidgetKind::Button => Rgb::new(205, 205, 205),
+            _ => Rgb::new(255, 255, 255),
+        };
+        Self { kind, label: label.to_string(), bounds, color, value: String::new(), checked: false, selected: false, clicks: 0 }
+    }
+
+    pub fn shape(kind: WidgetKind, color: Rgb, bounds: ElementBounds) -> Self {
+        let label = format!("{} {}", color.name(), if kind == WidgetKind::Circle { "circle" } else { "square" });
+        Self { color, ..Self::new(kind, &label, bounds) }
+    }
+}
+
+/// The simulated desktop
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxScene {
+    pub width: usize,
+    pub height: usize,
+    /// Back to front
+    pub widgets: Vec<Widget>,
+    /// Index of the text field typing goes to
+    pub focused: Option<usize>,
+    /// List item shift-clicks extend the selection from
+    pub anchor: Option<usize>,
+    /// Keys held down, in press order; they modify clicks like a chord's modifiers
+    pub held: Vec<String>,
+    /// What input did to the scene, oldest first
+    pub log: Vec<String>,
+    /// Characters lost the way a busy app drops keystrokes, by position
+    /// counting from the next one typed
+    pub lose_keystrokes: Vec<usize>,
+}
+
+impl SandboxScene {
+    pub fn new(width: usize, height: usize, widgets: Vec<Widget>) -> Self {
+        Self { width, height, widgets, focused: None, anchor: None, held: Vec::new(), log: Vec::new(), lose_keystrokes: Vec::new() }
+    }
+
+    /// A sign-up form with colored shapes beside it: enough for clicking,
+    /// typing, toggling and naming things by color
+    pub fn tutorial() -> Self {
+        let b = ElementBounds::new;
+        Self::new(1280, 800, vec![
+            Widget::new(WidgetKind::TextField, "Name", b(100, 100, 300, 32)),
+            Widget::new(WidgetKind::TextField, "Email", b(100, 150, 300, 32)),
+            Widget::new(WidgetKind::Checkbox, "Subscribe", b(100, 205, 20, 20)),
+            Widget::new(WidgetKind::Button, "Save", b(100, 260, 100, 36)),
+            Widget::new(WidgetKind::Button, "Cancel", b(220, 260, 100, 36)),
+            Widget::shape(WidgetKind::Circle, Rgb::new(220, 40, 40), b(600, 100, 60, 60)),
+            Widget::shape(WidgetKind::Square, Rgb::new(40, 170, 70), b(700, 100, 60, 60)),
+            Widget::shape(WidgetKind::Square, Rgb::new(40, 90, 220), b(800, 100, 60, 60)),
+        ])
+    }
+
+    /// Frontmost widget under (`x`, `y`)
+    pub fn widget_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.widgets.iter().rposition(|w| w.bounds.contains_point(x, y))
+    }
+
+    pub fn widget(&self, label: &str) -> Option<&Widget> {
+        self.widgets.iter().find(|w| w.label.eq_ignore_ascii_case(label))
+    }
+
+    /// Draw the scene. Text is drawn as dark bars one character wide per
+    /// letter; there is no font to draw glyphs with.
+    pub fn render(&self) -> Image {
+        let mut image = Image::from_rgb_data(self.width, self.height, [BACKGROUND.r, BACKGROUND.g, BACKGROUND.b].repeat(self.width * self.height));
+        for (index, widget) in self.widgets.iter().enumerate() {
+            let b = &widget.bounds;
+            match widget.kind {
+                WidgetKind::Circle => {
+                    let (cx, cy, r) = (b.x + b.width / 2, b.y + b.height / 2, b.width.min(b.height) / 2);
+                    fill(&mut image, b, widget.color, |x, y| (x - cx).pow(2) + (y - cy).pow(2) <= r * r);
+                }
+                WidgetKind::ListItem if widget.selected => fill(&mut image, b, SELECTION, |_, _| true),
+                _ => fill(&mut image, b, widget.color, |_, _| true),
+            }
+            let border = if self.focused == Some(index) { FOCUS } else { BORDER };
+            match widget.kind {
+                WidgetKind::Button | WidgetKind::TextField | WidgetKind::Checkbox => outline(&mut image, b, border),
+                _ => {}
+            }
+            let text_row = |width| ElementBounds::new(b.x + 8, b.y + b.height / 2 - 3, width, 6);
+            match widget.kind {
+                WidgetKind::Button => {
+                    let width = (widget.label.chars().count() as i32 * CHAR_WIDTH).min(b.width - 16);
+                    let row = text_row(width);
+                    fill(&mut image, &ElementBounds { x: b.x + (b.width - width) / 2, ..row }, INK, |_, _| true);
+                }
+                WidgetKind::TextField if !widget.value.is_empty() => {
+                    let width = (widget.value.chars().count() as i32 * CHAR_WIDTH).min(b.width - 16);
+                    fill(&mut image, &text_row(width), INK, |_, _| true);
+                }
+                WidgetKind::ListItem => {
+                    let width = (widget.label.chars().count() as i32 * CHAR_WIDTH).min(b.width - 16);
+                    fill(&mut image, &text_row(width), INK, |_, _| true);
+                }
+                WidgetKind::Checkbox if widget.checked => {
+                    fill(&mut image, &ElementBounds::new(b.x + 4, b.y + 4, b.width - 8, b.height - 8), INK, |_, _| true);
+                }
+                _ => {}
+            }
+        }
+        image
+    }
+
+    /// Apply one input action the way the widgets would react, returning
+    /// what happened
+    pub fn apply(&mut self, action: &InputAction) -> Option<String> {
+        let event = match &action.action_type {
+            ActionType::Move { .. } => return None,
+            ActionType::Click { modifiers, .. } => {
+                let modifiers: Vec<String> = modifiers.iter().chain(&self.held).cloned().collect();
+                let (x, y) = (action.target.x, action.target.y);
+                match self.widget_at(x, y) {
+                    None => {
+                        self.focused = None;
+                        format!("clicked the empty desktop at ({}, {})", x, y)
+                    }
+                    Some(index) => {
+                        let widget = &mut self.widgets[index];
+                        widget.clicks += 1;
+                        match widget.kind {
+                            WidgetKind::TextField => {
+                                self.focused = Some(index);
+                                format!("focused the {} field", widget.label)
+                            }
+                            WidgetKind::Checkbox => {
+                                widget.checked = !widget.checked;
+                                format!("{} {}", if widget.checked { "checked" } else { "unchecked" }, widget.label)
+                            }
+                            WidgetKind::Button => format!("pressed {} ({} time(s))", widget.label, widget.clicks),
+                            WidgetKind::Circle | WidgetKind::Square => format!("clicked the {}", widget.label),
+                            WidgetKind::ListItem => self.select(index, &modifiers),
+                        }
+                    }
+                }
+            }
+            ActionType::Type { text } => match self.focused {
+                Some(index) => {
+                    let field = &mut self.widgets[index];
+                    for c in text.chars() {
+                        if !self.lose_keystrokes.contains(&0) {
+                            field.value.push(c);
+                        }
+                        self.lose_keystrokes = self.lose_keystrokes.iter().filter_map(|i| i.checked_sub(1)).collect();
+                    }
+                    format!("{} now reads {:?}", field.label, field.value)
+                }
+                None => format!("typed {:?} with no field focused; it went nowhere", text),
+            },
+            ActionType::Key { key } => match (key.to_lowercase().as_str(), self.focused) {
+                ("backspace", Some(index)) => {
+                    let field = &mut self.widgets[index];
+                    field.value.pop();
+                    format!("{} now reads {:?}", field.label, field.value)
+                }
+                ("tab", _) => {
+                    let fields: Vec<usize> = (0..self.widgets.len()).filter(|&i| self.widgets[i].kind == WidgetKind::TextField).collect();
+                    let next = fields.iter().copied().find(|&i| Some(i) > self.focused).or(fields.first().copied());
+                    self.focused = next;
+                    match next {
+                        Some(index) => format!("focused the {} field", self.widgets[index].label),
+                        None => "pressed Tab; there is no field to focus".to_string(),
+                    }
+                }
+                _ => format!("pressed {}", key),
+            },
+            ActionType::KeyDown { key } => {
+                self.held.push(key.to_lowercase());
+                format!("holding {}", key)
+            }
+            ActionType::KeyUp { key } => {
+                self.held.retain(|held| !held.eq_ignore_ascii_case(key));
+                format!("released {}", key)
+            }
+            ActionType::Scroll { direction, amount } => format!("scrolled {:?} by {}; the scene doesn't scroll", direction, amount),
+        };
+        info!("Sandbox: {}", event);
+        self.log.push(event.clone());
+        Some(event)
+    }
+
+    /// Select list item `index` the way file managers do: a plain click
+    /// selects it alone, ctrl (cmd) adds or removes it, and shift selects
+    /// every item from the last plain or ctrl click to it
+    fn select(&mut self, index: usize, modifiers: &[String]) -> String {
+        let items: Vec<usize> = (0..self.widgets.len()).filter(|&i| self.widgets[i].kind == WidgetKind::ListItem).collect();
+        let held = |names: &[&str]| modifiers.iter().any(|m| names.contains(&m.as_str()));
+        if held(&["shift"]) {
+            let from = self.anchor.unwrap_or(index);
+            let (low, high) = (from.min(index), from.max(index));
+            for &i in &items {
+                self.widgets[i].selected = (low..=high).contains(&i);
+            }
+        } else if held(&["ctrl", "cmd", "win"]) {
+            self.widgets[index].selected = !self.widgets[index].selected;
+            self.anchor = Some(index);
+        } else {
+            for &i in &items {
+                self.widgets[i].selected = i == index;
+            }
+            self.anchor = Some(index);
+        }
+        let selected: Vec<&str> = items.iter().filter(|&&i| self.widgets[i].selected).map(|&i| self.widgets[i].label.as_str()).collect();
+        format!("selected {}", if selected.is_empty() { "nothing".to_string() } else { selected.join(", ") })
+    }
+
+    /// Each widget as the detection a model reading this scene would return
+    pub fn detections(&self) -> Vec<ElementDetection> {
+        self.widgets
+            .iter()
+            .map(|widget| {
+                let mut attributes = HashMap::new();
+                let (element_type, text) = match widget.kind {
+                    WidgetKind::Button => ("button", Some(widget.label.clone())),
+                    WidgetKind::TextField => {
+                        attributes.insert(LABEL_ATTRIBUTE.to_string(), widget.label.clone());
+                        let shown = if widget.value.is_empty() { &widget.label } else { &widget.value };
+                        ("textfield", Some(shown.clone()))
+                    }
+                    WidgetKind::Checkbox => {
+                        attributes.insert("checked".to_string(), widget.checked.to_string());
+                        ("checkbox", Some(widget.label.clone()))
+                    }
+                    WidgetKind::Circle | WidgetKind::Square => {
+                        attributes.insert(COLOR_ATTRIBUTE.to_string(), widget.color.name().to_string());
+                        let shape = if widget.kind == WidgetKind::Circle { "circle" } else { "square" };
+                        attributes.insert(SHAPE_ATTRIBUTE.to_string(), shape.to_string());
+                        ("icon", None)
+                    }
+                    WidgetKind::ListItem => ("list_item", Some(widget.label.clone())),
+                };
+                ElementDetection {
+                    element_type: element_type.to_string(),
+                    bounds: widget.bounds.clone(),
+                    shape: None,
+                    confidence: 0.95,
+                    text,
+                    attributes,
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for SandboxScene {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, widget) in self.widgets.iter().enumerate() {
+            let b = &widget.bounds;
+            write!(f, "  {:?} '{}' at ({}, {}) {}x{}", widget.kind, widget.label, b.x, b.y, b.width, b.height)?;
+            match widget.kind {
+                WidgetKind::TextField => write!(f, " = {:?}{}", widget.value, if self.focused == Some(index) { " (focused)" } else { "" })?,
+                WidgetKind::Checkbox => write!(f, " [{}]", if widget.checked { "x" } else { " " })?,
+                WidgetKind::ListItem if widget.selected => write!(f, " (selected)")?,
+                _ => {}
+            }
+            writeln!(f)?;
+        }
+        for event in self.log.iter().rev().take(5).rev() {
+            writeln!(f, "  - {}", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// A scene shared by the capture, input and detection it stands in for
+#[derive(Clone)]
+pub struct Sandbox {
+    scene: Arc<Mutex<SandboxScene>>,
+}
+
+impl Sandbox {
+    pub fn new(scene: SandboxScene) -> Self {
+        Self { scene: Arc::new(Mutex::new(scene)) }
+    }
+
+    pub fn scene(&self) -> MutexGuard<'_, SandboxScene> {
+        self.scene.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn frame_source(&self) -> Arc<Mutex<dyn FrameSource>> {
+        Arc::new(Mutex::new(SandboxCapture(self.clone())))
+    }
+
+    pub fn input_sink(&self) -> Box<dyn InputSink> {
+        Box::new(SandboxInput(self.clone()))
+    }
+
+    pub fn detector(&self) -> Box<dyn ElementDetector + Send> {
+        Box::new(SandboxDetector(self.clone()))
+    }
+}
+
+struct SandboxCapture(Sandbox);
+
+impl FrameSource for SandboxCapture {
+    fn capture(&mut self) -> Result<Image, CaptureError> {
+        Ok(self.0.scene().render())
+    }
+}
+
+struct SandboxInput(Sandbox);
+
+impl InputSink for SandboxInput {
+    fn send(&mut self, action: &InputAction) -> Result<(), InputError> {
+        self.0.scene().apply(action);
+        Ok(())
+    }
+}
+
+struct SandboxDetector(Sandbox);
+
+impl ElementDetector for SandboxDetector {
+    fn detect_elements(&mut self, _image: &image::DynamicImage) -> Result<Vec<ElementDetection>> {
+        Ok(self.0.scene().detections())
+    }
+}
+
+/// Fill the pixels of `bounds` for which `inside` holds
+fn fill(image: &mut Image, bounds: &ElementBounds, color: Rgb, inside: impl Fn(i32, i32) -> bool) {
+    for y in bounds.y.max(0)..(bounds.y + bounds.height).min(image.height as i32) {
+        for x in bounds.x.max(0)..(bounds.x + bounds.width).min(image.width as i32) {
+            if inside(x, y) {
+                image.set_pixel(x as usize, y as usize, &[color.r, color.g, color.b]);
+            }
+        }
+    }
+}
+
+fn outline(image: &mut Image, b: &ElementBounds, color: Rgb) {
+    for edge in [
+        ElementBounds::new(b.x, b.y, b.width, 1),
+        ElementBounds::new(b.x, b.y + b.height - 1, b.width, 1),
+        ElementBounds::new(b.x, b.y, 1, b.height),
+        ElementBounds::new(b.x + b.width - 1, b.y, 1, b.height),
+    ] {
+        fill(image, &edge, color, |_, _| true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::environment::{EnvironmentSnapshot, Theme};
+    use crate::core::provenance::PlannedBy;
+    use crate::core::{ExecuteOptions, Luna, LunaAction, LunaConfig, LunaError};
+    use crate::vision::color::pixel_at;
+
+    #[test]
+    fn test_commands_act_on_the_scene_only() {
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let options = ExecuteOptions::default();
+
+        let result = luna.execute_command("click Save", &options).unwrap();
+        assert!(matches!(result.actions[..], [LunaAction::Click { x: 150, y: 278 }]), "{:?}", result.actions);
+        assert_eq!(sandbox.scene().widget("Save").unwrap().clicks, 1);
+
+        luna.execute_command("click the Email field", &options).unwrap();
+        luna.execute_command("type \"me@example.com\"", &options).unwrap();
+        luna.execute_command("click the green square", &options).unwrap();
+        let scene = sandbox.scene();
+        assert_eq!(scene.widget("Email").unwrap().value, "me@example.com");
+        assert_eq!(scene.widget("green square").unwrap().clicks, 1);
+        assert_eq!(scene.log.last().unwrap(), "clicked the green square");
+        assert!(scene.to_string().contains("TextField 'Email' at (100, 150) 300x32 = \"me@example.com\" (focused)"));
+
+        let frame = scene.render();
+        assert_eq!(pixel_at(&frame, 630, 130), Some(Rgb::new(220, 40, 40)));
+        assert_eq!(pixel_at(&frame, 100, 150), Some(FOCUS), "focused field outline");
+    }
+
+    /// Fails the next `failures` clicks and keystrokes; `landed` ones still
+    /// reach the scene
+    struct Flaky {
+        inner: Box<dyn InputSink>,
+        failures: Arc<Mutex<(u32, bool)>>,
+    }
+
+    impl InputSink for Flaky {
+        fn send(&mut self, action: &InputAction) -> Result<(), InputError> {
+            let mut failures = self.failures.lock().unwrap();
+            if failures.0 == 0 || matches!(action.action_type, ActionType::Move { .. }) {
+                return self.inner.send(action);
+            }
+            failures.0 -= 1;
+            if failures.1 {
+                self.inner.send(action)?;
+            }
+            Err(InputError::PlatformError("device busy".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_failed_actions_retry_only_when_nothing_happened() {
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let failures = Arc::new(Mutex::new((0, false)));
+        luna.ensure_input().set_sink(Some(Box::new(Flaky { inner: sandbox.input_sink(), failures: failures.clone() })));
+        let options = ExecuteOptions::default();
+
+        *failures.lock().unwrap() = (2, false);
+        let result = luna.execute_command("click Save", &options).unwrap();
+        assert_eq!(result.retries, vec![2]);
+        assert_eq!(sandbox.scene().widget("Save").unwrap().clicks, 1);
+
+        // Typing is not retried by default
+        *failures.lock().unwrap() = (1, false);
+        assert!(luna.execute_command("type \"abc\"", &options).is_err());
+
+        // A click that failed after landing (the field got focus) is not repeated
+        *failures.lock().unwrap() = (1, true);
+        assert!(luna.execute_command("click the Email field", &options).is_err());
+        assert_eq!(sandbox.scene().widget("Email").unwrap().clicks, 1);
+        assert_eq!(luna.metrics().counter("luna_action_retries_total", &[("type", "click")]), 2);
+    }
+
+    #[test]
+    fn test_selections_follow_layout_and_are_verified() {
+        let files = (0..6)
+            .map(|i| Widget::new(WidgetKind::ListItem, &format!("report{}", i + 1), ElementBounds::new(100 + (i % 3) * 150, 100 + (i / 3) * 120, 120, 90)))
+            .collect();
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::new(800, 400, files));
+        let options = ExecuteOptions::default();
+        let selected = || -> Vec<String> {
+            sandbox.scene().widgets.iter().filter(|w| w.selected).map(|w| w.label.clone()).collect()
+        };
+
+        // Named back to front, the range still starts at the end that comes first
+        let result = luna.execute_command("select files report5 through report2", &options).unwrap();
+        assert!(matches!(&result.actions[..], [LunaAction::Click { x: 310, y: 145 }, LunaAction::ModifierClick { x: 310, y: 265, modifiers }]
+            if modifiers == &["shift"]), "{:?}", result.actions);
+        assert_eq!(selected(), ["report2", "report3", "report4", "report5"]);
+
+        luna.execute_command("ctrl-click report1 and report3", &options).unwrap();
+        assert_eq!(selected(), ["report1", "report2", "report4", "report5"]);
+
+        let vague = luna.execute_command("ctrl-click these three items", &options).unwrap_err();
+        assert!(matches!(vague.downcast_ref::<crate::core::LunaError>(), Some(crate::core::LunaError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_a_plans_own_clicks_are_not_a_stale_frame() {
+        // Selecting the big item repaints over a quarter of the area the guard checks around the small one
+        let b = ElementBounds::new;
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::new(400, 300, vec![
+            Widget::new(WidgetKind::ListItem, "report1", b(0, 0, 200, 200)),
+            Widget::new(WidgetKind::ListItem, "report2", b(200, 100, 20, 20)),
+        ]));
+
+        let result = luna.execute_command("ctrl-click report1 and report2", &ExecuteOptions::default()).unwrap();
+        assert_eq!(result.actions.len(), 2, "{:?}", result.actions);
+        assert!(sandbox.scene().widgets.iter().all(|w| w.selected), "{}", *sandbox.scene());
+        assert_eq!(luna.get_stats().stale_frames, 0);
+    }
+
+    #[test]
+    fn test_answered_questions_are_exported_as_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        config.training.export_corrections = true;
+        let b = ElementBounds::new;
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::new(800, 400, vec![
+            Widget::new(WidgetKind::Button, "Apply", b(100, 100, 100, 36)),
+            Widget::new(WidgetKind::Button, "Apply", b(500, 100, 100, 36)),
+        ]));
+        let options = ExecuteOptions::default();
+
+        let asked = luna.execute_command("click apply", &options).unwrap_err();
+        assert!(matches!(asked.downcast_ref::<LunaError>(), Some(LunaError::NeedsClarification(_))));
+        luna.answer_clarification("the second one", &options).unwrap();
+        assert_eq!(sandbox.scene().widgets[1].clicks, 1);
+
+        let labels = std::fs::read_to_string(dir.path().join("training_data/labels.jsonl")).unwrap();
+        let record: crate::ai::training::ExportRecord = serde_json::from_str(labels.trim()).unwrap();
+        assert_eq!(record.label, "button");
+        assert_eq!(record.predicted_label, None);
+        assert_eq!(record.command, "click apply");
+        assert!(record.bbox[0] >= 480, "{:?}", record.bbox);
+    }
+
+    #[test]
+    fn test_missing_anchor_falls_back_to_the_planner() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        luna.remember("save", Some((630, 130))).unwrap();
+        sandbox.scene().widgets.retain(|w| !matches!(w.kind, WidgetKind::Circle | WidgetKind::Square));
+
+        let result = luna.execute_command("click save", &ExecuteOptions::default()).unwrap();
+        assert!(matches!(result.actions[..], [LunaAction::Click { x: 150, y: 278 }]), "{:?}", result.actions);
+        assert_eq!(sandbox.scene().widget("Save").unwrap().clicks, 1);
+        assert_eq!(luna.anchors().get("save").unwrap().misses, 1);
+    }
+
+    #[test]
+    fn test_held_keys_modify_clicks_and_are_released_when_a_command_aborts() {
+        let files = (0..3)
+            .map(|i| Widget::new(WidgetKind::ListItem, &format!("report{}", i + 1), ElementBounds::new(100 + i * 150, 100, 120, 90)))
+            .collect();
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::new(800, 400, files));
+        let options = ExecuteOptions::default();
+
+        luna.execute_command("click report1", &options).unwrap();
+        luna.execute_command("while holding shift, click report3", &options).unwrap();
+        let selected = sandbox.scene().widgets.iter().filter(|w| w.selected).count();
+        assert_eq!(selected, 3);
+        assert!(sandbox.scene().held.is_empty());
+
+        // Alt is down when f4 is refused; it does not stay down
+        assert!(luna.execute_command("hold alt and press f4", &options).is_err());
+        let scene = sandbox.scene();
+        assert!(scene.held.is_empty());
+        assert_eq!(scene.log[scene.log.len() - 2..], ["holding alt", "released alt"]);
+        assert!(luna.ensure_input().held_keys().is_empty());
+    }
+
+    #[test]
+    fn test_semantic_waits_follow_the_scene() {
+        let mut luna = Luna::new(LunaConfig::default()).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let timeout = std::time::Duration::from_secs(5);
+
+        assert!(luna.wait_for_element("button:save", timeout).unwrap().is_some());
+        assert!(luna.wait_for_element("spinner", std::time::Duration::from_millis(150)).unwrap().is_none());
+        assert!(luna.wait_for_screen_idle(std::time::Duration::from_millis(100), timeout).unwrap());
+
+        // The dialog goes away while Luna waits on it
+        let closer = {
+            let sandbox = sandbox.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                sandbox.scene().widgets.retain(|w| w.label != "Cancel");
+            })
+        };
+        let result = luna.execute_command("wait for Cancel to disappear", &ExecuteOptions::default()).unwrap();
+        closer.join().unwrap();
+        assert!(matches!(&result.actions[..], [LunaAction::WaitForTextGone { text, .. }] if text == "Cancel"));
+        assert!(sandbox.scene().widget("Cancel").is_none());
+    }
+
+    #[test]
+    fn test_commands_start_from_an_idle_analysis_while_it_is_fresh() {
+        let mut config = LunaConfig::default();
+        config.pre_analysis.enabled = true;
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        let hits = |luna: &Luna, result| luna.metrics().counter("luna_pre_analysis_total", &[("result", result)]);
+
+        // An unchanged screen is analyzed once, and the next command uses it
+        assert!(luna.pre_analyze().unwrap());
+        assert!(!luna.pre_analyze().unwrap());
+        luna.execute_command("click Save", &ExecuteOptions::default()).unwrap();
+        assert_eq!(hits(&luna, "hit"), 1);
+
+        // A screen that changed after the idle analysis is analyzed again
+        assert!(luna.pre_analyze().unwrap());
+        sandbox.scene().widgets.retain(|w| w.label != "Name");
+        let result = luna.execute_command("click Cancel", &ExecuteOptions::default()).unwrap();
+        assert_eq!((hits(&luna, "hit"), hits(&luna, "miss")), (1, 1));
+        assert!(matches!(result.actions[..], [LunaAction::Click { .. }]));
+    }
+
+    #[test]
+    fn test_executed_clicks_keep_their_evidence() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        config.transcript.enabled = true;
+        let mut luna = Luna::new(config).unwrap();
+        luna.enter_sandbox(SandboxScene::tutorial());
+
+        let result = luna.execute_command("click Save", &ExecuteOptions::default()).unwrap();
+        let chain = &result.provenance[0];
+        assert_eq!(chain.planned_by, PlannedBy::Analysis { thorough: false });
+        let element = chain.element.as_ref().expect("the click has a detected target");
+        assert_eq!((element.element_type.as_str(), element.text.as_deref()), ("button", Some("Save")));
+        assert_eq!(element.click_strategy.as_deref(), Some("bounds"));
+        assert!(chain.safety.allowed && chain.safety.risk.is_some());
+
+        // Literal coordinates have no element behind them
+        let literal = luna.execute_command("click at 5,5", &ExecuteOptions::default()).unwrap();
+        assert_eq!((&literal.provenance[0].planned_by, literal.provenance[0].element.is_some()), (&PlannedBy::Literal, false));
+
+        // Both can be looked up afterwards; dry runs aren't recorded
+        assert_eq!(luna.action_provenance(&result.command_id, 0).unwrap().as_ref(), Some(chain));
+        let dry = luna.execute_command("click Save", &ExecuteOptions { dry_run: true, ..ExecuteOptions::default() }).unwrap();
+        assert_eq!(luna.action_provenance(&dry.command_id, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_keyboard_commands_bypass_the_screen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        config.transcript.enabled = true;
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+        luna.execute_command("click the Email field", &ExecuteOptions::default()).unwrap();
+
+        // Nothing is captured or analyzed, so even a scene without widgets is fine
+        let widgets = std::mem::take(&mut sandbox.scene().widgets);
+        let pressed = luna.execute_command("press ctrl+a", &ExecuteOptions::default()).unwrap();
+        assert!(pressed.pipeline_skipped && pressed.processing_time_ms < 100, "{}ms", pressed.processing_time_ms);
+        sandbox.scene().widgets = widgets;
+
+        let typed = luna.execute_command("type me@example.com and press tab", &ExecuteOptions::default()).unwrap();
+        assert!(typed.pipeline_skipped);
+        assert_eq!(sandbox.scene().widget("Email").unwrap().value, "me@example.com");
+        // Recorded like any other command
+        let chain = luna.action_provenance(&typed.command_id, 1).unwrap().unwrap();
+        assert_eq!((chain.planned_by, chain.action.as_str()), (PlannedBy::Literal, "KeyCombo { keys: [\"tab\"] }"));
+        assert_eq!(luna.get_stats().pipeline_skips, 2);
+    }
+
+    #[test]
+    fn test_dropped_keystrokes_are_typed_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        config.typing_check.enabled = true;
+        config.typing_check.settle_ms = 0;
+        let mut luna = Luna::new(config).unwrap();
+        let sandbox = luna.enter_sandbox(SandboxScene::tutorial());
+
+        // The app misses the "e" and "p" of "example"
+        sandbox.scene().lose_keystrokes = vec![4, 8];
+        let typed = luna.execute_command("type ada@example.com into the Email field", &ExecuteOptions::default()).unwrap();
+        assert_eq!(sandbox.scene().widget("Email").unwrap().value, "ada@example.com");
+        let report = &typed.typing[0];
+        assert_eq!((report.expected.as_str(), report.read.as_deref()), ("ada@example.com", Some("ada@example.com")));
+        assert!(report.verified);
+        assert_eq!(report.corrections, 1);
+        // Back to the first gap, then the rest typed again
+        assert!(sandbox.scene().log.iter().any(|event| event == "Email now reads \"ada@xamle.com\""));
+    }
+
+    #[test]
+    fn test_runs_check_the_environment_they_expect() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = LunaConfig::default();
+        config.storage.root_dir = Some(dir.path().to_path_buf());
+        let mut luna = Luna::new(config).unwrap();
+        luna.enter_sandbox(SandboxScene::tutorial());
+
+        let snapshot = luna.environment_snapshot().unwrap();
+        assert_eq!(snapshot.theme, Some(Theme::Light));
+        let path = dir.path().join("env.json");
+        snapshot.save(&path).unwrap();
+        let saved = EnvironmentSnapshot::load(&path).unwrap();
+        luna.verify_environment(&saved).unwrap();
+
+        let mut recorded_elsewhere = saved;
+        recorded_elsewhere.theme = Some(Theme::Dark);
+        recorded_elsewhere.config.insert("ocr.languages".to_string(), serde_json::json!(["jpn"]));
+        let err = luna.verify_environment(&recorded_elsewhere).unwrap_err();
+        let mismatch = err.downcast_ref::<LunaError>().and_then(LunaError::environment_mismatch).unwrap();
+        let settings: Vec<&str> = mismatch.differences.iter().map(|d| d.setting.as_str()).collect();
+        assert_eq!(settings, ["theme", "config.ocr.languages"]);
+        assert!(err.to_string().contains("expected dark theme"), "{}", err);
+    }
+}
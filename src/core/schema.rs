@@ -0,0 +1,745 @@
+/*!
+ * Luna Schema - Versioned JSON documents for results and events
+ *
+ * Everything Luna hands to other programs (`luna do --json`, the C ABI,
+ * event callbacks) is a JSON document tagged with its schema and version,
+ * `"schema": "luna.do/v1"`. The records here are the one definition of
+ * each document; the CLI and the FFI both serialize through them, and
+ * `luna schema` writes their JSON Schemas to `schemas/`.
+ *
+ * Compatibility rules:
+ * - Within a version, fields are only added, and an added field is optional:
+ *   readers fill in its default, so documents written before it still read.
+ * - Readers ignore fields they don't know, so a document from a newer
+ *   release of the same version still reads.
+ * - Removing, renaming or retyping a field, or changing what one means,
+ *   is a new version. `from_document` rejects versions newer than it knows.
+ * - `tests/schema/` keeps documents as released; they must keep reading.
+ */
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use super::provenance::ActionProvenance;
+use super::restore::RestoreStep;
+use super::tracker::ElementUpdate;
+use super::typing::TypingReport;
+use super::watchdog::WatchdogEvent;
+use super::{CommandResult, ElementBounds, Escalation, LunaAction, LunaError, LunaEvent, ScreenAnalysis, ScreenElement, ShotTarget};
+use crate::utils::geometry::{Point, Polygon};
+use crate::vision::screen_capture::DisplayInfo;
+
+/// A top-level document: serialized with its `schema` tag
+pub trait Record: Serialize + DeserializeOwned {
+    /// Schema name without the version ("luna.do")
+    const NAME: &'static str;
+    const VERSION: u32;
+
+    /// JSON Schema (draft 2020-12) of the document
+    fn json_schema() -> Value;
+
+    /// The tag documents carry, "luna.do/v1"
+    fn tag() -> String {
+        format!("{}/v{}", Self::NAME, Self::VERSION)
+    }
+}
+
+/// `record` as a document tagged with its schema
+pub fn to_document<R: Record>(record: &R) -> Value {
+    let mut document = serde_json::to_value(record).unwrap_or(Value::Null);
+    if let Value::Object(fields) = &mut document {
+        fields.insert("schema".to_string(), Value::String(R::tag()));
+    }
+    document
+}
+
+/// Read a document of `R`'s schema, written by this or an earlier release
+pub fn from_document<R: Record>(document: &Value) -> Result<R, LunaError> {
+    let tag = document.get("schema").and_then(Value::as_str).ok_or_else(|| invalid(format!("not a {} document: no schema tag", R::NAME)))?;
+    let version = tag
+        .strip_prefix(R::NAME)
+        .and_then(|rest| rest.strip_prefix("/v"))
+        .and_then(|version| version.parse::<u32>().ok())
+        .ok_or_else(|| invalid(format!("expected a {} document, got {}", R::NAME, tag)))?;
+    if version == 0 || version > R::VERSION {
+        return Err(invalid(format!("{} is newer than this release reads ({})", tag, R::tag())));
+    }
+    serde_json::from_value(document.clone()).map_err(|e| invalid(format!("{}: {}", tag, e)))
+}
+
+fn invalid(message: String) -> LunaError {
+    LunaError::InvalidArgument(message)
+}
+
+/// Every document's JSON Schema with its file name ("luna.do.v1.json")
+pub fn json_schemas() -> Vec<(String, Value)> {
+    fn entry<R: Record>() -> (String, Value) {
+        (format!("{}.v{}.json", R::NAME, R::VERSION), R::json_schema())
+    }
+    vec![entry::<CommandRecord>(), entry::<AnalysisRecord>(), entry::<FindRecord>(), entry::<EventRecord>(), entry::<ErrorRecord>()]
+}
+
+/// One action, tagged by `type`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActionRecord {
+    Click { x: i32, y: i32 },
+    Type { text: String },
+    Keys { keys: Vec<String> },
+    Scroll { direction: String, amount: i32 },
+    Wait { milliseconds: u64 },
+    Screenshot {
+        /// x, y, width, height; the whole screen when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<[i32; 4]>,
+        /// "clipboard" or the file written
+        target: String,
+    },
+    PasteImage { path: String },
+    WaitForElement { query: String, timeout_ms: u64 },
+    WaitForTextGone { text: String, timeout_ms: u64 },
+    WaitForScreenIdle { quiet_ms: u64, timeout_ms: u64 },
+    ModifierClick { x: i32, y: i32, modifiers: Vec<String> },
+    KeyDown { key: String },
+    KeyUp { key: String },
+    Hover { x: i32, y: i32 },
+}
+
+impl From<&LunaAction> for ActionRecord {
+    fn from(action: &LunaAction) -> Self {
+        match action.clone() {
+            LunaAction::Click { x, y } => ActionRecord::Click { x, y },
+            LunaAction::Type { text } => ActionRecord::Type { text },
+            LunaAction::KeyCombo { keys } => ActionRecord::Keys { keys },
+            LunaAction::Scroll { direction, amount } => ActionRecord::Scroll { direction, amount },
+            LunaAction::Wait { milliseconds } => ActionRecord::Wait { milliseconds },
+            LunaAction::Screenshot { region, target } => ActionRecord::Screenshot {
+                region: region.map(|r| [r.x, r.y, r.width, r.height]),
+                target: match target {
+                    ShotTarget::Clipboard => "clipboard".to_string(),
+                    ShotTarget::File(path) => path.display().to_string(),
+                },
+            },
+            LunaAction::PasteImage { path } => ActionRecord::PasteImage { path: path.display().to_string() },
+            LunaAction::WaitForElement { query, timeout_ms } => ActionRecord::WaitForElement { query, timeout_ms },
+            LunaAction::WaitForTextGone { text, timeout_ms } => ActionRecord::WaitForTextGone { text, timeout_ms },
+            LunaAction::WaitForScreenIdle { quiet_ms, timeout_ms } => ActionRecord::WaitForScreenIdle { quiet_ms, timeout_ms },
+            LunaAction::ModifierClick { x, y, modifiers } => ActionRecord::ModifierClick { x, y, modifiers },
+            LunaAction::KeyDown { key } => ActionRecord::KeyDown { key },
+            LunaAction::KeyUp { key } => ActionRecord::KeyUp { key },
+            LunaAction::Hover { x, y } => ActionRecord::Hover { x, y },
+        }
+    }
+}
+
+impl From<ActionRecord> for LunaAction {
+    fn from(record: ActionRecord) -> Self {
+        match record {
+            ActionRecord::Click { x, y } => LunaAction::Click { x, y },
+            ActionRecord::Type { text } => LunaAction::Type { text },
+            ActionRecord::Keys { keys } => LunaAction::KeyCombo { keys },
+            ActionRecord::Scroll { direction, amount } => LunaAction::Scroll { direction, amount },
+            ActionRecord::Wait { milliseconds } => LunaAction::Wait { milliseconds },
+            ActionRecord::Screenshot { region, target } => LunaAction::Screenshot {
+                region: region.map(|[x, y, width, height]| ElementBounds::new(x, y, width, height)),
+                target: match target.as_str() {
+                    "clipboard" => ShotTarget::Clipboard,
+                    path => ShotTarget::File(PathBuf::from(path)),
+                },
+            },
+            ActionRecord::PasteImage { path } => LunaAction::PasteImage { path: PathBuf::from(path) },
+            ActionRecord::WaitForElement { query, timeout_ms } => LunaAction::WaitForElement { query, timeout_ms },
+            ActionRecord::WaitForTextGone { text, timeout_ms } => LunaAction::WaitForTextGone { text, timeout_ms },
+            ActionRecord::WaitForScreenIdle { quiet_ms, timeout_ms } => LunaAction::WaitForScreenIdle { quiet_ms, timeout_ms },
+            ActionRecord::ModifierClick { x, y, modifiers } => LunaAction::ModifierClick { x, y, modifiers },
+            ActionRecord::KeyDown { key } => LunaAction::KeyDown { key },
+            ActionRecord::KeyUp { key } => LunaAction::KeyUp { key },
+            ActionRecord::Hover { x, y } => LunaAction::Hover { x, y },
+        }
+    }
+}
+
+/// One detected element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElementRecord {
+    #[serde(rename = "type")]
+    pub element_type: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub confidence: f32,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    /// Outline as `[x, y]` points, when not an axis-aligned box
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape: Option<Vec<[f64; 2]>>,
+    /// Index of the containing element; only in `luna.analyze` documents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    /// Indices of the contained elements; only in `luna.analyze` documents
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<usize>,
+}
+
+impl From<&ScreenElement> for ElementRecord {
+    /// Without `parent` and `children`, which index an analysis
+    fn from(element: &ScreenElement) -> Self {
+        Self {
+            element_type: element.element_type.clone(),
+            x: element.bounds.x,
+            y: element.bounds.y,
+            width: element.bounds.width,
+            height: element.bounds.height,
+            confidence: element.confidence,
+            text: element.text.clone(),
+            attributes: element.attributes.clone(),
+            shape: element.shape.as_ref().map(|shape| shape.points.iter().map(|p| [p.x, p.y]).collect()),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl From<ElementRecord> for ScreenElement {
+    fn from(record: ElementRecord) -> Self {
+        Self {
+            element_type: record.element_type,
+            bounds: ElementBounds::new(record.x, record.y, record.width, record.height),
+            shape: record.shape.map(|points| Polygon::new(points.into_iter().map(|[x, y]| Point::new(x, y)).collect())),
+            confidence: record.confidence,
+            text: record.text,
+            attributes: record.attributes,
+            parent: record.parent,
+            children: record.children,
+        }
+    }
+}
+
+/// `luna.analyze`: a screen analysis
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisRecord {
+    /// Width and height in pixels
+    pub screen_size: [u32; 2],
+    pub confidence: f32,
+    pub processing_time_ms: u64,
+    pub elements: Vec<ElementRecord>,
+    /// The image file analyzed, or "screen"; only from `luna-analyze`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Analysis preset used ("fast", "balanced", "thorough"); only from `luna-analyze`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// Wall-clock timings; only from `luna-analyze`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<AnalysisTimings>,
+}
+
+/// How long producing an analysis took, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisTimings {
+    /// Reading and decoding the image, or capturing the screen
+    pub load_ms: f64,
+    /// Each analysis of the same frame, in run order
+    pub analysis_ms: Vec<f64>,
+}
+
+impl From<&ScreenAnalysis> for AnalysisRecord {
+    fn from(analysis: &ScreenAnalysis) -> Self {
+        let elements = analysis
+            .elements
+            .iter()
+            .map(|element| ElementRecord { parent: element.parent, children: element.children.clone(), ..ElementRecord::from(element) })
+            .collect();
+        Self {
+            screen_size: [analysis.screen_size.0, analysis.screen_size.1],
+            confidence: analysis.confidence,
+            processing_time_ms: analysis.processing_time_ms,
+            elements,
+            source: None,
+            preset: None,
+            timings: None,
+        }
+    }
+}
+
+impl From<AnalysisRecord> for ScreenAnalysis {
+    fn from(record: AnalysisRecord) -> Self {
+        Self {
+            elements: record.elements.into_iter().map(ScreenElement::from).collect(),
+            confidence: record.confidence,
+            processing_time_ms: record.processing_time_ms,
+            screen_size: (record.screen_size[0], record.screen_size[1]),
+            context: Default::default(),
+        }
+    }
+}
+
+impl Record for AnalysisRecord {
+    const NAME: &'static str = "luna.analyze";
+    const VERSION: u32 = 1;
+
+    fn json_schema() -> Value {
+        let timings = object(&[("load_ms", number(), true), ("analysis_ms", array_of(number()), true)]);
+        document::<Self>(
+            "A screen analysis",
+            &[
+                ("screen_size", array_of(integer()), true),
+                ("confidence", number(), true),
+                ("processing_time_ms", integer(), true),
+                ("elements", array_of(element_schema()), true),
+                ("source", string(), false),
+                ("preset", string(), false),
+                ("timings", timings, false),
+            ],
+        )
+    }
+}
+
+/// `luna.do`: the outcome of one command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub command: String,
+    pub dry_run: bool,
+    pub pipeline_skipped: bool,
+    pub processing_time_ms: u64,
+    /// Executed actions, or the planned ones for a dry run
+    pub actions: Vec<ActionRecord>,
+    /// Retries each executed action needed, parallel to `actions`
+    #[serde(default)]
+    pub retries: Vec<u32>,
+    /// Present when the first analysis found no target and a thorough one ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<Escalation>,
+    /// Present when `raw_outputs.include` is set in the config; free-form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_outputs: Option<Value>,
+    /// Identifies the command in the provenance transcript
+    #[serde(default)]
+    pub command_id: String,
+    /// Evidence behind each action, parallel to `actions`
+    #[serde(default)]
+    pub provenance: Vec<ActionProvenance>,
+    /// How the cursor and focus were put back, when restoring was asked for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restoration: Vec<RestoreStep>,
+    /// What each field typed into read as afterwards
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub typing: Vec<TypingReport>,
+    /// Attached by pipeline hooks
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl CommandRecord {
+    pub fn new(command: &str, result: &CommandResult) -> Self {
+        Self {
+            command: command.to_string(),
+            dry_run: result.dry_run,
+            pipeline_skipped: result.pipeline_skipped,
+            processing_time_ms: result.processing_time_ms,
+            actions: result.actions.iter().map(ActionRecord::from).collect(),
+            retries: result.retries.clone(),
+            escalation: result.escalation.clone(),
+            raw_outputs: result.raw_outputs.as_ref().and_then(|raw| serde_json::to_value(raw).ok()),
+            command_id: result.command_id.clone(),
+            provenance: result.provenance.clone(),
+            restoration: result.restoration.clone(),
+            typing: result.typing.clone(),
+            metadata: result.metadata.clone(),
+        }
+    }
+}
+
+impl Record for CommandRecord {
+    const NAME: &'static str = "luna.do";
+    const VERSION: u32 = 1;
+
+    fn json_schema() -> Value {
+        let escalation = object(
+            &[
+                ("reason", string(), true),
+                ("elements_before", integer(), true),
+                ("elements_after", integer(), true),
+                ("elapsed_ms", integer(), true),
+            ],
+        );
+        let restore_step = object(&[
+            ("target", enumeration(&["cursor", "focus"]), true),
+            ("outcome", enumeration(&["restored", "unchanged", "skipped", "failed"]), true),
+            ("detail", string(), true),
+        ]);
+        let typing_report = object(&[
+            ("action", integer(), true),
+            ("expected", string(), true),
+            ("read", nullable(string()), true),
+            ("verified", boolean(), true),
+            ("corrections", integer(), true),
+        ]);
+        document::<Self>(
+            "The outcome of one command",
+            &[
+                ("command", string(), true),
+                ("dry_run", boolean(), true),
+                ("pipeline_skipped", boolean(), true),
+                ("processing_time_ms", integer(), true),
+                ("actions", array_of(action_schema()), true),
+                ("retries", array_of(integer()), false),
+                ("escalation", nullable(escalation), false),
+                ("raw_outputs", nullable(free_form()), false),
+                ("command_id", string(), false),
+                ("provenance", array_of(free_form()), false),
+                ("restoration", array_of(restore_step), false),
+                ("typing", array_of(typing_report), false),
+                ("metadata", json!({ "type": "object", "additionalProperties": string() }), false),
+            ],
+        )
+    }
+}
+
+/// `luna.find`: elements matching a query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FindRecord {
+    /// The query as parsed, "button:submit"
+    pub query: String,
+    pub elements: Vec<ElementRecord>,
+}
+
+impl Record for FindRecord {
+    const NAME: &'static str = "luna.find";
+    const VERSION: u32 = 1;
+
+    fn json_schema() -> Value {
+        document::<Self>("Elements matching a query", &[("query", string(), true), ("elements", array_of(element_schema()), true)])
+    }
+}
+
+/// `luna.error`: why a call failed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    /// `LunaError::kind`, "usage" for a bad command line, or "internal"
+    pub kind: String,
+    pub error: String,
+    /// Closest candidates when a command found no target; free-form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Value>,
+    /// The question to answer when the command was ambiguous; free-form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clarification: Option<Value>,
+    /// What differed from the expected environment snapshot; free-form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Value>,
+}
+
+impl From<&anyhow::Error> for ErrorRecord {
+    fn from(error: &anyhow::Error) -> Self {
+        let luna_error = error.downcast_ref::<LunaError>();
+        Self {
+            kind: luna_error.map_or("internal", LunaError::kind).to_string(),
+            error: error.to_string(),
+            explanation: luna_error.and_then(LunaError::no_match).and_then(|no_match| serde_json::to_value(no_match).ok()),
+            clarification: None,
+            environment: luna_error.and_then(LunaError::environment_mismatch).and_then(|mismatch| serde_json::to_value(mismatch).ok()),
+        }
+    }
+}
+
+impl Record for ErrorRecord {
+    const NAME: &'static str = "luna.error";
+    const VERSION: u32 = 1;
+
+    fn json_schema() -> Value {
+        document::<Self>(
+            "Why a call failed",
+            &[
+                ("kind", string(), true),
+                ("error", string(), true),
+                ("explanation", nullable(free_form()), false),
+                ("clarification", nullable(free_form()), false),
+                ("environment", nullable(free_form()), false),
+            ],
+        )
+    }
+}
+
+/// `luna.event`: one `LunaEvent`, tagged by `event`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventRecord {
+    CommandReceived { command: String },
+    ConfigApplied { applied: bool },
+    /// The analysis itself is not sent; `luna_analyze` returns it
+    AnalysisComplete { elements: usize, confidence: f32 },
+    ElementsChanged { sequence: u64, updates: Vec<ElementUpdate> },
+    ActionsPlanned { actions: Vec<ActionRecord> },
+    ActionExecuted { action: ActionRecord, success: bool },
+    CorrectionReported { command: String, label: String, exported: bool },
+    StaleFrame { action: ActionRecord, changed_fraction: f64 },
+    StorageQuotaWarning { store: String, used_bytes: u64, quota_bytes: u64 },
+    /// `clarification` is free-form
+    ClarificationNeeded { command: String, clarification: Value },
+    ReviewQueued { id: u64, reason: String, pending: usize },
+    SystemState { state: String },
+    /// Names of the displays; `removed` and `added` include resized ones
+    DisplayChanged { displays: Vec<String>, removed: Vec<String>, added: Vec<String> },
+    /// `state` is stalled, degraded, recovered or restored; `stalled_ms` is
+    /// how long it had hung, for stalled and recovered
+    Watchdog {
+        subsystem: String,
+        state: String,
+        #[serde(default)]
+        stalled_ms: Option<u64>,
+    },
+    /// A latency objective became violated, or stopped being violated
+    Slo {
+        objective: String,
+        violated: bool,
+        #[serde(default)]
+        compliance: Option<f64>,
+        target: f64,
+        samples: usize,
+    },
+    Error { error: String },
+}
+
+impl From<&LunaEvent> for EventRecord {
+    fn from(event: &LunaEvent) -> Self {
+        match event {
+            LunaEvent::CommandReceived { command } => EventRecord::CommandReceived { command: command.clone() },
+            LunaEvent::ConfigApplied { report } => EventRecord::ConfigApplied { applied: report.is_applied() },
+            LunaEvent::AnalysisComplete { analysis } => {
+                EventRecord::AnalysisComplete { elements: analysis.elements.len(), confidence: analysis.confidence }
+            }
+            LunaEvent::ElementsChanged { delta } => EventRecord::ElementsChanged { sequence: delta.sequence, updates: delta.updates.clone() },
+            LunaEvent::ActionsPlanned { actions } => EventRecord::ActionsPlanned { actions: actions.iter().map(ActionRecord::from).collect() },
+            LunaEvent::ActionExecuted { action, success } => EventRecord::ActionExecuted { action: action.into(), success: *success },
+            LunaEvent::CorrectionReported { command, label, exported } => {
+                EventRecord::CorrectionReported { command: command.clone(), label: label.clone(), exported: *exported }
+            }
+            LunaEvent::StaleFrame { action, changed_fraction } => {
+                EventRecord::StaleFrame { action: action.into(), changed_fraction: *changed_fraction }
+            }
+            LunaEvent::StorageQuotaWarning { store, used_bytes, quota_bytes } => {
+                EventRecord::StorageQuotaWarning { store: format!("{:?}", store), used_bytes: *used_bytes, quota_bytes: *quota_bytes }
+            }
+            LunaEvent::ClarificationNeeded { command, clarification } => EventRecord::ClarificationNeeded {
+                command: command.clone(),
+                clarification: serde_json::to_value(clarification).unwrap_or(Value::Null),
+            },
+            LunaEvent::ReviewQueued { id, reason, pending } => EventRecord::ReviewQueued {
+                id: *id,
+                reason: serde_json::to_value(reason).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default(),
+                pending: *pending,
+            },
+            LunaEvent::SystemState { state } => EventRecord::SystemState { state: format!("{:?}", state) },
+            LunaEvent::DisplayChanged { displays, removed, added } => {
+                let names = |displays: &[DisplayInfo]| displays.iter().map(|d| d.name.clone()).collect();
+                EventRecord::DisplayChanged { displays: names(displays), removed: names(removed), added: names(added) }
+            }
+            LunaEvent::Watchdog { event } => EventRecord::Watchdog {
+                subsystem: event.subsystem().as_str().to_string(),
+                state: event.state().to_string(),
+                stalled_ms: match event {
+                    WatchdogEvent::Stalled { stalled_ms, .. } => Some(*stalled_ms),
+                    WatchdogEvent::Recovered { hung_ms, .. } => Some(*hung_ms),
+                    _ => None,
+                },
+            },
+            LunaEvent::Slo { status } => EventRecord::Slo {
+                objective: status.name.clone(),
+                violated: status.violated,
+                compliance: status.compliance,
+                target: status.target,
+                samples: status.samples,
+            },
+            LunaEvent::Error { error } => EventRecord::Error { error: error.clone() },
+        }
+    }
+}
+
+impl Record for EventRecord {
+    const NAME: &'static str = "luna.event";
+    const VERSION: u32 = 1;
+
+    fn json_schema() -> Value {
+        let variants = [
+            ("command_received", vec![("command", string(), true)]),
+            ("config_applied", vec![("applied", boolean(), true)]),
+            ("analysis_complete", vec![("elements", integer(), true), ("confidence", number(), true)]),
+            ("elements_changed", vec![("sequence", integer(), true), ("updates", array_of(free_form()), true)]),
+            ("actions_planned", vec![("actions", array_of(action_schema()), true)]),
+            ("action_executed", vec![("action", action_schema(), true), ("success", boolean(), true)]),
+            ("correction_reported", vec![("command", string(), true), ("label", string(), true), ("exported", boolean(), true)]),
+            ("stale_frame", vec![("action", action_schema(), true), ("changed_fraction", number(), true)]),
+            ("storage_quota_warning", vec![("store", string(), true), ("used_bytes", integer(), true), ("quota_bytes", integer(), true)]),
+            ("clarification_needed", vec![("command", string(), true), ("clarification", free_form(), true)]),
+            ("review_queued", vec![("id", integer(), true), ("reason", string(), true), ("pending", integer(), true)]),
+            ("system_state", vec![("state", string(), true)]),
+            ("display_changed", vec![("displays", array_of(string()), true), ("removed", array_of(string()), true), ("added", array_of(string()), true)]),
+            ("watchdog", vec![("subsystem", string(), true), ("state", string(), true), ("stalled_ms", nullable(integer()), false)]),
+            (
+                "slo",
+                vec![
+                    ("objective", string(), true),
+                    ("violated", boolean(), true),
+                    ("compliance", nullable(number()), false),
+                    ("target", number(), true),
+                    ("samples", integer(), true),
+                ],
+            ),
+            ("error", vec![("error", string(), true)]),
+        ];
+        let mut schema = document::<Self>("One event", &[("event", string(), true)]);
+        schema["oneOf"] = variants.into_iter().map(|(name, fields)| tagged("event", name, &fields)).collect();
+        schema
+    }
+}
+
+// JSON Schema building blocks
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer" })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+/// An object whose fields are documented elsewhere
+fn free_form() -> Value {
+    json!({ "type": "object" })
+}
+
+/// One of the strings `values`
+fn enumeration(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// `schema` or `null`, for `Option` fields
+fn nullable(schema: Value) -> Value {
+    json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+/// `(name, schema, required)` fields
+fn object(fields: &[(&str, Value, bool)]) -> Value {
+    let properties: Map<String, Value> = fields.iter().map(|(name, schema, _)| (name.to_string(), schema.clone())).collect();
+    let required: Vec<&str> = fields.iter().filter(|(_, _, required)| *required).map(|(name, _, _)| *name).collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// An object whose `tag` field is `name`
+fn tagged(tag: &str, name: &str, fields: &[(&str, Value, bool)]) -> Value {
+    let mut schema = object(fields);
+    schema["properties"][tag] = json!({ "const": name });
+    if let Some(required) = schema["required"].as_array_mut() {
+        required.insert(0, json!(tag));
+    }
+    schema
+}
+
+/// The top level of `R`'s document
+fn document<R: Record>(title: &str, fields: &[(&str, Value, bool)]) -> Value {
+    let mut schema = object(fields);
+    schema["properties"]["schema"] = json!({ "const": R::tag() });
+    if let Some(required) = schema["required"].as_array_mut() {
+        required.insert(0, json!("schema"));
+    }
+    let mut document = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": R::tag(),
+        "title": title,
+    });
+    document.as_object_mut().expect("object").extend(schema.as_object().cloned().unwrap_or_default());
+    document
+}
+
+fn element_schema() -> Value {
+    object(&[
+        ("type", string(), true),
+        ("x", integer(), true),
+        ("y", integer(), true),
+        ("width", integer(), true),
+        ("height", integer(), true),
+        ("confidence", number(), true),
+        ("text", nullable(string()), false),
+        ("attributes", json!({ "type": "object", "additionalProperties": string() }), false),
+        ("shape", nullable(array_of(array_of(number()))), false),
+        ("parent", nullable(integer()), false),
+        ("children", array_of(integer()), false),
+    ])
+}
+
+fn action_schema() -> Value {
+    let strings = array_of(string());
+    let variants = [
+        ("click", vec![("x", integer(), true), ("y", integer(), true)]),
+        ("type", vec![("text", string(), true)]),
+        ("keys", vec![("keys", strings.clone(), true)]),
+        ("scroll", vec![("direction", string(), true), ("amount", integer(), true)]),
+        ("wait", vec![("milliseconds", integer(), true)]),
+        ("screenshot", vec![("region", nullable(array_of(integer())), false), ("target", string(), true)]),
+        ("paste_image", vec![("path", string(), true)]),
+        ("wait_for_element", vec![("query", string(), true), ("timeout_ms", integer(), true)]),
+        ("wait_for_text_gone", vec![("text", string(), true), ("timeout_ms", integer(), true)]),
+        ("wait_for_screen_idle", vec![("quiet_ms", integer(), true), ("timeout_ms", integer(), true)]),
+        ("modifier_click", vec![("x", integer(), true), ("y", integer(), true), ("modifiers", strings, true)]),
+        ("key_down", vec![("key", string(), true)]),
+        ("key_up", vec![("key", string(), true)]),
+        ("hover", vec![("x", integer(), true), ("y", integer(), true)]),
+    ];
+    json!({ "oneOf": variants.into_iter().map(|(name, fields)| tagged("type", name, &fields)).collect::<Vec<_>>() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_documents_are_tagged_and_versions_checked() {
+        let record = FindRecord { query: "button:save".to_string(), elements: Vec::new() };
+        let document = to_document(&record);
+        assert_eq!(document["schema"], "luna.find/v1");
+        assert_eq!(from_document::<FindRecord>(&document).unwrap(), record);
+
+        let mut newer = document.clone();
+        newer["schema"] = json!("luna.find/v2");
+        assert!(from_document::<FindRecord>(&newer).unwrap_err().to_string().contains("newer"));
+        assert!(from_document::<CommandRecord>(&document).is_err());
+
+        // Unknown fields are ignored within a version
+        newer["schema"] = json!("luna.find/v1");
+        newer["added_later"] = json!(true);
+        assert_eq!(from_document::<FindRecord>(&newer).unwrap(), record);
+    }
+
+    #[test]
+    fn test_actions_round_trip() {
+        let actions = [
+            LunaAction::Screenshot { region: Some(ElementBounds::new(1, 2, 3, 4)), target: ShotTarget::Clipboard },
+            LunaAction::Screenshot { region: None, target: ShotTarget::File(PathBuf::from("shot.png")) },
+            LunaAction::ModifierClick { x: 5, y: 6, modifiers: vec!["shift".to_string()] },
+        ];
+        for action in actions {
+            let record = ActionRecord::from(&action);
+            let read: ActionRecord = serde_json::from_value(serde_json::to_value(&record).unwrap()).unwrap();
+            assert_eq!(format!("{:?}", LunaAction::from(read)), format!("{:?}", action));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+/*!
+ * Luna Scratch - Temporary artifacts that don't outlive what made them
+ *
+ * A file written on the way to a result (a language pack staged before it
+ * is swapped in, a review crop before its queue entry is saved, training
+ * crops before their label line is appended) is registered against a scope
+ * before it is written. When the scope ends without keeping it, because
+ * the operation failed, returned early on cancellation or panicked, the
+ * file is deleted. Registrations are journaled per registry under the
+ * storage root, so what a crashed process left behind is deleted by the
+ * sweep at the next startup. Journal names carry the PID and a nonce
+ * picked when the process starts, so a restart that gets the same PID (PID
+ * 1 in a container, every time) still tells its own journals from a
+ * crashed run's. Space reclaimed either way is counted for `storage status`.
+ */
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::instance::process_alive;
+
+/// Directory under the storage root holding one journal per registry
+pub const SCRATCH_DIR: &str = "scratch";
+
+/// Registries created by this process, for journal names unique within it
+static NEXT_REGISTRY: AtomicU64 = AtomicU64::new(0);
+
+/// Picked once per process start, to tell this run's journals from those
+/// of an earlier run with the same PID
+fn start_nonce() -> &'static str {
+    static NONCE: OnceLock<String> = OnceLock::new();
+    NONCE.get_or_init(|| {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        format!("{:x}", nanos)
+    })
+}
+
+/// Journal name prefix of this process's registries
+fn own_prefix() -> String {
+    format!("{}-{}-", std::process::id(), start_nonce())
+}
+
+/// Files deleted and the bytes they took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Reclaimed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    fn add(&mut self, other: Reclaimed) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Space reclaimed from temporary artifacts since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ReclaimedSpace {
+    /// Left behind by scopes that ended without keeping them
+    pub abandoned: Reclaimed,
+    /// Left behind by processes that exited without cleaning up, found at startup
+    pub orphaned: Reclaimed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Artifact {
+    path: PathBuf,
+    scope: u64,
+    /// What the scope was for, for logs
+    label: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// `None` keeps registrations in memory only
+    journal: Option<PathBuf>,
+    artifacts: Vec<Artifact>,
+    next_scope: u64,
+    reclaimed: ReclaimedSpace,
+}
+
+impl Inner {
+    /// Rewrite the journal; it is removed once nothing is registered
+    fn write_journal(&self) -> Result<()> {
+        let Some(journal) = &self.journal else {
+            return Ok(());
+        };
+        if self.artifacts.is_empty() {
+            return match fs::remove_file(journal) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        if let Some(parent) = journal.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write then rename so a crash never leaves a truncated journal
+        let temp = journal.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(&self.artifacts)?)?;
+        fs::rename(&temp, journal)?;
+        Ok(())
+    }
+}
+
+/// Temporary artifacts of this process and what cleaning them up reclaimed.
+/// Clones share the registry.
+#[derive(Clone, Default)]
+pub struct ScratchRegistry {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ScratchRegistry {
+    /// Registry journaling under `root`'s `SCRATCH_DIR`
+    pub fn new(root: &Path) -> Self {
+        let name = format!("{}{}.json", own_prefix(), NEXT_REGISTRY.fetch_add(1, Ordering::Relaxed));
+        let inner = Inner { journal: Some(root.join(SCRATCH_DIR).join(name)), ..Default::default() };
+        Self { inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    /// A new scope; what is registered in it and not kept is deleted when it is dropped
+    pub fn scope(&self, label: &str) -> ScratchScope {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_scope += 1;
+        ScratchScope { registry: self.clone(), id: inner.next_scope, label: label.to_string() }
+    }
+
+    /// Delete the artifacts in journals of processes that are no longer
+    /// running, and the journals. Journals of running processes, this one
+    /// included, are left alone; one with this process's PID but not its
+    /// nonce is from an earlier run. A file that can't be removed is logged
+    /// and skipped.
+    pub fn sweep(&self) -> Result<Reclaimed> {
+        let mut reclaimed = Reclaimed::default();
+        let Some(dir) = self.inner.lock().unwrap().journal.as_ref().and_then(|j| j.parent()).map(Path::to_path_buf) else {
+            return Ok(reclaimed);
+        };
+        if !dir.is_dir() {
+            return Ok(reclaimed);
+        }
+        let own_prefix = own_prefix();
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let path = entry.path();
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            let Some(pid) = name.split('-').next().and_then(|pid| pid.parse::<u32>().ok()) else {
+                continue;
+            };
+            let running = if pid == std::process::id() { name.starts_with(&own_prefix) } else { process_alive(pid) };
+            if running {
+                continue;
+            }
+            if name.ends_with(".json") {
+                let artifacts: Vec<Artifact> = match fs::read(&path).map_err(anyhow::Error::from).and_then(|b| Ok(serde_json::from_slice(&b)?)) {
+                    Ok(artifacts) => artifacts,
+                    Err(e) => {
+                        warn!("Unreadable scratch journal {}: {}", path.display(), e);
+                        Vec::new()
+                    }
+                };
+                for artifact in artifacts {
+                    debug!("Removing {} left by pid {} ({})", artifact.path.display(), pid, artifact.label);
+                    reclaimed.add(remove_artifact(&artifact.path));
+                }
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Could not remove scratch journal {}: {}", path.display(), e);
+            }
+        }
+        self.inner.lock().unwrap().reclaimed.orphaned.add(reclaimed);
+        Ok(reclaimed)
+    }
+
+    /// Space reclaimed since startup
+    pub fn reclaimed(&self) -> ReclaimedSpace {
+        self.inner.lock().unwrap().reclaimed
+    }
+}
+
+/// Artifacts of one operation, deleted on drop unless kept
+pub struct ScratchScope {
+    registry: ScratchRegistry,
+    id: u64,
+    label: String,
+}
+
+impl ScratchScope {
+    /// Register `path` (a file or directory) before writing it. Nothing is
+    /// registered when the journal can't be written.
+    pub fn register(&self, path: impl Into<PathBuf>) -> Result<PathBuf> {
+        let path = path.into();
+        let mut inner = self.registry.inner.lock().unwrap();
+        inner.artifacts.push(Artifact { path: path.clone(), scope: self.id, label: self.label.clone() });
+        if let Err(e) = inner.write_journal() {
+            inner.artifacts.pop();
+            return Err(e);
+        }
+        Ok(path)
+    }
+
+    /// `path` became a result: stop tracking it without deleting it
+    pub fn keep(&self, path: &Path) {
+        let mut inner = self.registry.inner.lock().unwrap();
+        inner.artifacts.retain(|a| a.scope != self.id || a.path != path);
+        if let Err(e) = inner.write_journal() {
+            warn!("Could not update the scratch journal: {}", e);
+        }
+    }
+}
+
+impl Drop for ScratchScope {
+    fn drop(&mut self) {
+        let mut inner = match self.registry.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let (abandoned, kept): (Vec<Artifact>, Vec<Artifact>) = inner.artifacts.drain(..).partition(|a| a.scope == self.id);
+        inner.artifacts = kept;
+        if abandoned.is_empty() {
+            return;
+        }
+        for artifact in &abandoned {
+            debug!("Removing {} abandoned by {}", artifact.path.display(), self.label);
+            let reclaimed = remove_artifact(&artifact.path);
+            inner.reclaimed.abandoned.add(reclaimed);
+        }
+        if let Err(e) = inner.write_journal() {
+            warn!("Could not update the scratch journal: {}", e);
+        }
+    }
+}
+
+/// Delete a file or directory tree; what it took, or nothing when it is gone already
+fn remove_artifact(path: &Path) -> Reclaimed {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Reclaimed::default();
+    };
+    let mut reclaimed = Reclaimed::default();
+    if metadata.is_dir() {
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+                match entry.metadata() {
+                    Ok(m) if m.is_dir() => pending.push(entry.path()),
+                    Ok(m) => reclaimed.add(Reclaimed { files: 1, bytes: m.len() }),
+                    Err(_) => {}
+                }
+            }
+        }
+        if let Err(e) = fs::remove_dir_all(path) {
+            warn!("Could not remove {}: {}", path.display(), e);
+            return Reclaimed::default();
+        }
+    } else {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Could not remove {}: {}", path.display(), e);
+            return Reclaimed::default();
+        }
+        reclaimed = Reclaimed { files: 1, bytes: metadata.len() };
+    }
+    reclaimed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_removes_what_it_did_not_keep() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = ScratchRegistry::new(dir.path());
+        let (kept, dropped) = (dir.path().join("kept.png"), dir.path().join("partial"));
+        {
+            let scope = registry.scope("test");
+            scope.register(&kept).unwrap();
+            scope.register(&dropped).unwrap();
+            fs::write(&kept, [0u8; 10]).unwrap();
+            fs::create_dir_all(&dropped).unwrap();
+            fs::write(dropped.join("data"), [0u8; 100]).unwrap();
+            assert_eq!(fs::read_dir(dir.path().join(SCRATCH_DIR)).unwrap().count(), 1);
+            scope.keep(&kept);
+        }
+        assert!(kept.exists());
+        assert!(!dropped.exists());
+        assert_eq!(registry.reclaimed().abandoned, Reclaimed { files: 1, bytes: 100 });
+        // Nothing is registered, so there is no journal to sweep
+        assert_eq!(fs::read_dir(dir.path().join(SCRATCH_DIR)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_sweep_removes_artifacts_of_dead_processes_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let scratch = dir.path().join(SCRATCH_DIR);
+        fs::create_dir_all(&scratch).unwrap();
+        let [orphan, restarted, live] = ["orphan.png", "restarted.png", "live.png"].map(|name| dir.path().join(name));
+        for path in [&orphan, &restarted, &live] {
+            fs::write(path, [0u8; 42]).unwrap();
+        }
+        let journal = |path: &Path| serde_json::to_string(&[Artifact { path: path.to_path_buf(), scope: 1, label: "test".into() }]).unwrap();
+        fs::write(scratch.join(format!("{}-1-0.json", u32::MAX)), journal(&orphan)).unwrap();
+        // An earlier run that had this process's PID, as after a container restart
+        fs::write(scratch.join(format!("{}-1-0.json", std::process::id())), journal(&restarted)).unwrap();
+        fs::write(scratch.join(format!("{}99.json", own_prefix())), journal(&live)).unwrap();
+
+        let registry = ScratchRegistry::new(dir.path());
+        assert_eq!(registry.sweep().unwrap(), Reclaimed { files: 2, bytes: 84 });
+        assert!(!orphan.exists());
+        assert!(!restarted.exists());
+        assert!(live.exists());
+        assert_eq!(registry.reclaimed().orphaned.bytes, 84);
+        assert_eq!(fs::read_dir(&scratch).unwrap().count(), 1);
+    }
+}
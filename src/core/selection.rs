@@ -0,0 +1,98 @@
+/*!
+ * Luna Selection - Multi-selection by modifier-held clicks
+ *
+ * "select report1 through report5" clicks the first item and shift-clicks
+ * the last; "ctrl-click a, b and c" toggles each one. File managers, lists
+ * and tables all follow the same rules, so what a sequence of clicks should
+ * leave selected can be worked out ahead of time and compared with the
+ * selection highlight on screen afterwards.
+ */
+
+use super::LunaAction;
+use crate::utils::geometry::{Point, Rectangle};
+use crate::utils::image_processing::Image;
+use crate::vision::{color, hierarchy};
+
+/// Modifier that adds an item to the selection or takes it out
+pub const TOGGLE_MODIFIER: &str = if cfg!(target_os = "macos") { "cmd" } else { "ctrl" };
+/// Modifier that extends the selection from the last clicked item
+pub const RANGE_MODIFIER: &str = "shift";
+
+/// Whether `modifier` is one of the names the toggle modifier goes by
+fn is_toggle(modifier: &str) -> bool {
+    matches!(modifier.to_lowercase().as_str(), "ctrl" | "control" | "cmd" | "command" | "win" | "meta")
+}
+
+/// Which of `items` `actions` should leave selected, given which were
+/// selected before. Shift ranges run in reading order from the last item
+/// clicked without shift.
+pub fn expected_selection(items: &[Rectangle], before: &[bool], actions: &[LunaAction]) -> Vec<bool> {
+    let order = hierarchy::reading_order(items);
+    let rank = |item: usize| order.iter().position(|&i| i == item).unwrap_or_default();
+    let mut selected = before.to_vec();
+    selected.resize(items.len(), false);
+    let mut anchor: Option<usize> = None;
+
+    for action in actions {
+        let Some((x, y)) = action.click_point() else {
+            continue;
+        };
+        let Some(item) = items.iter().position(|r| r.contains_point(&Point::new(x as f64, y as f64))) else {
+            continue;
+        };
+        let modifiers: &[String] = match action {
+            LunaAction::ModifierClick { modifiers, .. } => modifiers,
+            _ => &[],
+        };
+        if modifiers.iter().any(|m| m.eq_ignore_ascii_case(RANGE_MODIFIER)) {
+            let from = anchor.unwrap_or(item);
+            let (low, high) = (rank(from).min(rank(item)), rank(from).max(rank(item)));
+            for (position, &i) in order.iter().enumerate() {
+                selected[i] = (low..=high).contains(&position);
+            }
+        } else if modifiers.iter().any(|m| is_toggle(m)) {
+            selected[item] = !selected[item];
+            anchor = Some(item);
+        } else {
+            selected.iter_mut().for_each(|s| *s = false);
+            selected[item] = true;
+            anchor = Some(item);
+        }
+    }
+    selected
+}
+
+/// Whether `rect` shows a selection highlight: its main color is a hue
+/// rather than white, gray or black. Gray highlights (an unfocused list)
+/// are not told apart from unselected rows.
+pub fn is_highlighted(image: &Image, rect: &Rectangle) -> bool {
+    color::dominant_color(image, rect).is_some_and(color::is_chromatic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(x: i32, y: i32, modifiers: &[&str]) -> LunaAction {
+        match modifiers {
+            [] => LunaAction::Click { x, y },
+            _ => LunaAction::ModifierClick { x, y, modifiers: modifiers.iter().map(|m| m.to_string()).collect() },
+        }
+    }
+
+    #[test]
+    fn test_expected_selection() {
+        let rows: Vec<Rectangle> = (0..5).map(|i| Rectangle::new(0.0, i as f64 * 20.0, 100.0, 18.0)).collect();
+        let before = [true, false, false, true, false];
+
+        // Click the second row, shift-click the fourth: a range, whichever end came first
+        let range = expected_selection(&rows, &before, &[click(10, 25, &[]), click(10, 65, &["shift"])]);
+        assert_eq!(range, [false, true, true, true, false]);
+        let backwards = expected_selection(&rows, &before, &[click(10, 65, &[]), click(10, 25, &["shift"])]);
+        assert_eq!(backwards, range);
+
+        // Toggles keep the rest of the selection and take out what was in it
+        let toggled = expected_selection(&rows, &before, &[click(10, 5, &["ctrl"]), click(10, 85, &["cmd"])]);
+        assert_eq!(toggled, [false, false, false, true, true]);
+    }
+}
@@ -0,0 +1,200 @@
+/*!
+ * Luna Session - Screen lock and secure desktop awareness
+ *
+ * While the workstation is locked, or Windows has switched to the secure
+ * desktop for a UAC prompt, captures show the lock screen and injected input
+ * goes nowhere (or somewhere worse). Commands and watchers pause instead, a
+ * `SystemState` event reports each transition, and an interrupted sequence
+ * only continues once its next target checks out on a fresh capture.
+ *
+ * The state is polled: `loginctl`'s LockedHint on Linux, the LogonUI and
+ * consent processes on Windows (WTS session notifications need a window
+ * message loop this crate does not run), and the CGSession lock flag on macOS.
+ */
+
+use log::debug;
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::focus::{command_output, running_processes};
+
+/// Whether automation can see and drive the user's desktop
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    #[default]
+    Active,
+    /// Workstation locked; the lock screen covers the desktop
+    Locked,
+    /// Secure desktop (UAC consent prompt) showing; input cannot reach it
+    SecureDesktop,
+}
+
+impl SessionState {
+    pub fn is_active(self) -> bool {
+        self == SessionState::Active
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionState::Active => "active",
+            SessionState::Locked => "locked",
+            SessionState::SecureDesktop => "on the secure desktop",
+        })
+    }
+}
+
+/// Source of the current session state
+pub trait SessionProbe {
+    fn probe(&self) -> SessionState;
+}
+
+/// Asks the OS; platforms it cannot query read as active
+pub struct SystemSessionProbe;
+
+impl SessionProbe for SystemSessionProbe {
+    fn probe(&self) -> SessionState {
+        if cfg!(target_os = "linux") {
+            let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "self".to_string());
+            command_output("loginctl", &["show-session", &session, "-p", "LockedHint"])
+                .map_or(SessionState::Active, |output| linux_state(&output))
+        } else if cfg!(target_os = "windows") {
+            windows_state(&running_processes().into_iter().map(|p| p.name).collect::<Vec<_>>())
+        } else if cfg!(target_os = "macos") {
+            command_output("ioreg", &["-n", "Root", "-d1"]).map_or(SessionState::Active, |output| macos_state(&output))
+        } else {
+            SessionState::Active
+        }
+    }
+}
+
+/// Caches probe results between polls and tracks transitions
+pub struct SessionMonitor {
+    probe: Box<dyn SessionProbe + Send>,
+    interval: Duration,
+    cached: Option<(Instant, SessionState)>,
+    reported: SessionState,
+}
+
+impl SessionMonitor {
+    pub fn new(probe: Box<dyn SessionProbe + Send>, interval: Duration) -> Self {
+        Self { probe, interval, cached: None, reported: SessionState::Active }
+    }
+
+    /// Current state, probing again once the cached value is older than the
+    /// interval, and whether it changed since the previous call
+    pub fn poll(&mut self) -> (SessionState, bool) {
+        let state = match self.cached {
+            Some((at, state)) if at.elapsed() < self.interval => state,
+            _ => {
+                let state = self.probe.probe();
+                debug!("Session state: {}", state);
+                self.cached = Some((Instant::now(), state));
+                state
+            }
+        };
+        let changed = state != self.reported;
+        self.reported = state;
+        (state, changed)
+    }
+}
+
+/// `loginctl show-session ... -p LockedHint` output ("LockedHint=yes")
+fn linux_state(output: &str) -> SessionState {
+    let locked = output.lines().any(|line| line.trim() == "LockedHint=yes");
+    if locked { SessionState::Locked } else { SessionState::Active }
+}
+
+/// LogonUI runs while the lock screen is up, consent while a UAC prompt is
+fn windows_state(processes: &[String]) -> SessionState {
+    if processes.iter().any(|p| p == "consent") {
+        SessionState::SecureDesktop
+    } else if processes.iter().any(|p| p == "logonui") {
+        SessionState::Locked
+    } else {
+        SessionState::Active
+    }
+}
+
+/// `ioreg -n Root -d1` lists `"CGSSessionScreenIsLocked" = Yes` while locked
+fn macos_state(output: &str) -> SessionState {
+    let locked = output.lines().any(|line| {
+        line.contains("\"CGSSessionScreenIsLocked\"") && line.trim_end().ends_with("Yes")
+    });
+    if locked { SessionState::Locked } else { SessionState::Active }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ExecuteOptions, Luna, LunaConfig, LunaError, LunaEvent};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Locked for the first `locked_polls` probes, active afterwards
+    struct Unlocks {
+        probes: Arc<AtomicUsize>,
+        locked_polls: usize,
+    }
+
+    impl SessionProbe for Unlocks {
+        fn probe(&self) -> SessionState {
+            if self.probes.fetch_add(1, Ordering::SeqCst) < self.locked_polls {
+                SessionState::Locked
+            } else {
+                SessionState::Active
+            }
+        }
+    }
+
+    #[test]
+    fn test_platform_outputs() {
+        assert_eq!(linux_state("LockedHint=yes\n"), SessionState::Locked);
+        assert_eq!(linux_state("LockedHint=no\n"), SessionState::Active);
+
+        let running = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(windows_state(&running(&["explorer", "logonui"])), SessionState::Locked);
+        assert_eq!(windows_state(&running(&["logonui", "consent"])), SessionState::SecureDesktop);
+        assert_eq!(windows_state(&running(&["explorer"])), SessionState::Active);
+
+        let ioreg = "    |   \"CGSSessionScreenIsLocked\" = Yes\n    |   \"CGSSessionUniqueSessionUUID\" = \"...\"";
+        assert_eq!(macos_state(ioreg), SessionState::Locked);
+        assert_eq!(macos_state("    |   \"IOConsoleLocked\" = No"), SessionState::Active);
+    }
+
+    fn luna_with(locked_polls: usize, max_pause_ms: u64) -> (Luna, Arc<Mutex<Vec<SessionState>>>) {
+        let mut config = LunaConfig::default();
+        config.session.poll_ms = 5;
+        config.session.resume_settle_ms = 0;
+        config.session.max_pause_ms = max_pause_ms;
+        let mut luna = Luna::new(config).unwrap();
+        luna.set_session_probe(Box::new(Unlocks { probes: Arc::new(AtomicUsize::new(0)), locked_polls }));
+
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&transitions);
+        luna.subscribe_to_events(move |event| {
+            if let LunaEvent::SystemState { state } = event {
+                seen.lock().unwrap().push(state);
+            }
+        });
+        (luna, transitions)
+    }
+
+    #[test]
+    fn test_commands_wait_for_unlock() {
+        let (mut luna, transitions) = luna_with(3, 0);
+        let result = luna.execute_command("press enter", &ExecuteOptions::default()).unwrap();
+        assert_eq!(result.actions.len(), 1);
+        assert_eq!(*transitions.lock().unwrap(), vec![SessionState::Locked, SessionState::Active]);
+    }
+
+    #[test]
+    fn test_pause_gives_up_after_max_pause() {
+        let (mut luna, _) = luna_with(usize::MAX, 20);
+        let err = luna.execute_command("press enter", &ExecuteOptions::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LunaError>(), Some(LunaError::Deferred(_))), "{}", err);
+    }
+}
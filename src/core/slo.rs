@@ -0,0 +1,193 @@
+/*!
+ * Luna SLO - Latency objectives for actions, over a rolling window
+ *
+ * An objective such as "95% of clicks complete within 500ms of the command
+ * being received" is a `slo.objectives` entry: the action type it covers,
+ * the latency, the fraction that must meet it and the window it is
+ * measured over. After every executed action the command's elapsed time is
+ * recorded against the objectives covering its type; an action that failed
+ * counts as missing the objective.
+ *
+ * `SloTracker` lives in the `MetricsCollector`, so `/metrics` and the
+ * health server's `/slo` report compliance without waiting for the worker.
+ * When an objective's compliance drops below its target, or climbs back,
+ * Luna sends `LunaEvent::Slo`. Windows with fewer than `min_samples`
+ * actions are not judged and keep the last verdict, so one slow click after
+ * a quiet hour is not an alert. Objectives are judged when an action is
+ * recorded; an idle instance keeps its last verdict too.
+ */
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::config::SloObjective;
+
+/// Matches every action type in `SloObjective::action`
+pub const ANY_ACTION: &str = "*";
+
+/// Where an objective stands over its window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SloStatus {
+    pub name: String,
+    pub action: String,
+    pub latency_ms: u64,
+    pub target: f64,
+    pub window_secs: u64,
+    /// Actions recorded in the window
+    pub samples: usize,
+    /// Of those, the ones that succeeded within `latency_ms`
+    pub met: usize,
+    /// `met / samples`; `None` with no samples
+    pub compliance: Option<f64>,
+    /// Compliance was below target, over at least `min_samples` actions,
+    /// when last judged
+    pub violated: bool,
+}
+
+struct Tracked {
+    objective: SloObjective,
+    /// When each action finished and its latency; `None` for failures
+    samples: VecDeque<(Instant, Option<Duration>)>,
+    violated: bool,
+}
+
+impl Tracked {
+    fn new(objective: SloObjective) -> Self {
+        Self { objective, samples: VecDeque::new(), violated: false }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let window = Duration::from_secs(self.objective.window_secs);
+        while self.samples.front().is_some_and(|(at, _)| now.saturating_duration_since(*at) > window) {
+            self.samples.pop_front();
+        }
+    }
+
+    fn status(&self) -> SloStatus {
+        let limit = Duration::from_millis(self.objective.latency_ms);
+        let met = self.samples.iter().filter(|(_, latency)| latency.is_some_and(|l| l <= limit)).count();
+        let samples = self.samples.len();
+        SloStatus {
+            name: self.objective.name.clone(),
+            action: self.objective.action.clone(),
+            latency_ms: self.objective.latency_ms,
+            target: self.objective.target,
+            window_secs: self.objective.window_secs,
+            samples,
+            met,
+            compliance: (samples > 0).then(|| met as f64 / samples as f64),
+            violated: self.violated,
+        }
+    }
+}
+
+/// Compliance of every configured objective
+#[derive(Default)]
+pub struct SloTracker {
+    tracked: Vec<Tracked>,
+}
+
+impl SloTracker {
+    /// Track `objectives` from now on. An objective whose name and action
+    /// are unchanged keeps its samples, judged against its new latency and
+    /// target; the others start empty.
+    pub fn set_objectives(&mut self, objectives: &[SloObjective]) {
+        let mut previous = std::mem::take(&mut self.tracked);
+        self.tracked = objectives
+            .iter()
+            .map(|objective| {
+                let kept = previous.iter().position(|t| t.objective.name == objective.name && t.objective.action == objective.action);
+                match kept.map(|index| previous.swap_remove(index)) {
+                    Some(tracked) => Tracked { objective: objective.clone(), ..tracked },
+                    None => Tracked::new(objective.clone()),
+                }
+            })
+            .collect();
+    }
+
+    /// Record an action of type `kind` that finished `latency` after its
+    /// command was received, `None` when it failed. Returns the objectives
+    /// that became violated or stopped being violated.
+    pub fn record(&mut self, kind: &str, latency: Option<Duration>, now: Instant) -> Vec<SloStatus> {
+        let mut changed = Vec::new();
+        for tracked in self.tracked.iter_mut().filter(|t| t.objective.action == kind || t.objective.action == ANY_ACTION) {
+            tracked.samples.push_back((now, latency));
+            tracked.expire(now);
+            let status = tracked.status();
+            if status.samples < tracked.objective.min_samples {
+                continue;
+            }
+            let violated = status.compliance.is_some_and(|c| c < tracked.objective.target);
+            if violated != tracked.violated {
+                tracked.violated = violated;
+                changed.push(SloStatus { violated, ..status });
+            }
+        }
+        changed
+    }
+
+    /// Every objective, with samples older than its window dropped
+    pub fn statuses(&mut self, now: Instant) -> Vec<SloStatus> {
+        self.tracked
+            .iter_mut()
+            .map(|tracked| {
+                tracked.expire(now);
+                tracked.status()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clicks(min_samples: usize) -> SloObjective {
+        SloObjective { name: "clicks".to_string(), min_samples, ..SloObjective::default() }
+    }
+
+    #[test]
+    fn test_violations_are_reported_once_and_cleared_on_recovery() {
+        let mut tracker = SloTracker::default();
+        tracker.set_objectives(&[clicks(4)]);
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        // Too few samples to judge, however slow
+        assert!(tracker.record("click", Some(ms(900)), start).is_empty());
+        assert!(tracker.record("type", None, start).is_empty(), "other action types don't count");
+        assert!(tracker.record("click", None, start).is_empty());
+        assert!(tracker.record("click", Some(ms(100)), start).is_empty());
+        let violated = tracker.record("click", Some(ms(200)), start);
+        assert_eq!(violated.len(), 1);
+        assert_eq!((violated[0].samples, violated[0].met, violated[0].compliance), (4, 2, Some(0.5)));
+        assert!(violated[0].violated);
+        assert!(tracker.record("click", Some(ms(100)), start).is_empty(), "already reported");
+
+        // The slow ones age out of the window
+        let later = start + Duration::from_secs(301);
+        for _ in 0..3 {
+            tracker.record("click", Some(ms(50)), later);
+        }
+        let recovered = tracker.record("click", Some(ms(50)), later);
+        assert_eq!(recovered.len(), 1);
+        assert!(!recovered[0].violated);
+        assert_eq!(tracker.statuses(later)[0].compliance, Some(1.0));
+    }
+
+    #[test]
+    fn test_changing_objectives_keeps_samples_of_unchanged_ones() {
+        let mut tracker = SloTracker::default();
+        tracker.set_objectives(&[clicks(1), SloObjective { name: "anything".to_string(), action: ANY_ACTION.to_string(), ..clicks(1) }]);
+        let now = Instant::now();
+        tracker.record("click", Some(Duration::from_millis(400)), now);
+        tracker.record("keys", Some(Duration::from_millis(400)), now);
+
+        let stricter = SloObjective { latency_ms: 300, ..clicks(1) };
+        tracker.set_objectives(&[stricter, SloObjective { name: "anything".to_string(), action: "keys".to_string(), ..clicks(1) }]);
+        let statuses = tracker.statuses(now);
+        assert_eq!((statuses[0].samples, statuses[0].met), (1, 0));
+        assert_eq!(statuses[1].samples, 0, "a different action type starts over");
+    }
+}
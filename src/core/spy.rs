@@ -0,0 +1,177 @@
+/*!
+ * Luna Spy - What LUNA sees under the mouse cursor
+ *
+ * Spy mode analyzes a small square around the pointer instead of the whole
+ * screen, so it keeps up as the pointer moves, and reports the innermost
+ * element there: its type, text, confidence and bounds, plus a query string
+ * (see `query::ElementQuery`) that selects it in `luna find`, scripts and
+ * watch rules. The report is also what a detection bug needs: what LUNA
+ * thought was under the cursor when it went wrong.
+ */
+
+use serde::Serialize;
+use std::fmt;
+
+use super::query::ElementQuery;
+use super::{ElementBounds, ScreenAnalysis, ScreenElement};
+use crate::utils::geometry::{Point, Rectangle};
+
+/// Half the side of the square analyzed around the cursor, in pixels
+pub const SPY_RADIUS: i32 = 160;
+
+/// The element under the cursor and how to select it
+#[derive(Debug, Clone, Serialize)]
+pub struct SpyReport {
+    pub cursor: (i32, i32),
+    pub element: Option<SpiedElement>,
+}
+
+/// What was detected under the cursor, in screen coordinates
+#[derive(Debug, Clone, Serialize)]
+pub struct SpiedElement {
+    pub element_type: String,
+    pub text: Option<String>,
+    pub confidence: f32,
+    /// `(x, y, width, height)`
+    pub bounds: (i32, i32, i32, i32),
+    /// Query selecting this element, e.g. `button:save`
+    pub query: String,
+    /// Elements in the analyzed square the query selects, this one included;
+    /// more than one means a script using it would need more context
+    pub query_matches: usize,
+}
+
+impl SpyReport {
+    /// Report on the innermost element of `analysis` containing `cursor`
+    pub fn from_analysis(cursor: (i32, i32), analysis: &ScreenAnalysis) -> Self {
+        let point = Point::new(cursor.0 as f64, cursor.1 as f64);
+        let element = analysis
+            .elements
+            .iter()
+            .filter(|e| Rectangle::from(&e.bounds).contains_point(&point))
+            .min_by(|a, b| Rectangle::from(&a.bounds).area().total_cmp(&Rectangle::from(&b.bounds).area()))
+            .map(|element| {
+                let query = query_for(element);
+                let parsed = ElementQuery::parse(&query);
+                SpiedElement {
+                    element_type: element.element_type.clone(),
+                    text: element.text.clone(),
+                    confidence: element.confidence,
+                    bounds: (element.bounds.x, element.bounds.y, element.bounds.width, element.bounds.height),
+                    query_matches: analysis.elements.iter().filter(|e| parsed.matches(e)).count(),
+                    query,
+                }
+            });
+        Self { cursor, element }
+    }
+
+    /// Tooltip lines, most useful first
+    pub fn lines(&self) -> Vec<String> {
+        let Some(element) = &self.element else {
+            return vec![format!("nothing detected at ({}, {})", self.cursor.0, self.cursor.1)];
+        };
+        let (x, y, width, height) = element.bounds;
+        let mut lines = vec![match &element.text {
+            Some(text) => format!("{} \"{}\"", element.element_type, text.trim()),
+            None => element.element_type.clone(),
+        }];
+        lines.push(format!("confidence {:.0}%", element.confidence * 100.0));
+        lines.push(format!("bounds {},{} {}x{}", x, y, width, height));
+        if element.query_matches > 1 {
+            lines.push(format!("query {} ({} matches)", element.query, element.query_matches));
+        } else {
+            lines.push(format!("query {}", element.query));
+        }
+        lines
+    }
+}
+
+impl fmt::Display for SpyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.lines().join("\n"))
+    }
+}
+
+/// `type:text` selecting `element`; `type:` when it has no text
+pub fn query_for(element: &ScreenElement) -> String {
+    let element_type = element.element_type.to_lowercase();
+    match element.text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        Some(text) => format!("{}:{}", element_type, text.to_lowercase()),
+        None => format!("{}:", element_type),
+    }
+}
+
+/// Square of `SPY_RADIUS` around `cursor`; `Luna::screenshot` clips it to the screen
+pub fn region_around(cursor: (i32, i32)) -> ElementBounds {
+    ElementBounds::new(cursor.0 - SPY_RADIUS, cursor.1 - SPY_RADIUS, 2 * SPY_RADIUS, 2 * SPY_RADIUS)
+}
+
+/// Move an analysis of a crop whose top-left corner is `origin` into screen coordinates
+pub fn to_screen(analysis: &mut ScreenAnalysis, origin: (i32, i32)) {
+    let (dx, dy) = origin;
+    for element in &mut analysis.elements {
+        element.bounds.x += dx;
+        element.bounds.y += dy;
+        if let Some(shape) = &mut element.shape {
+            for point in &mut shape.points {
+                *point = point.translate(dx as f64, dy as f64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(element_type: &str, text: Option<&str>, bounds: ElementBounds) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds,
+            shape: None,
+            confidence: 0.93,
+            text: text.map(String::from),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn analysis(elements: Vec<ScreenElement>) -> ScreenAnalysis {
+        ScreenAnalysis { elements, confidence: 0.9, processing_time_ms: 0, screen_size: (320, 320), context: Default::default() }
+    }
+
+    #[test]
+    fn test_report_names_innermost_element_and_its_query() {
+        let mut frame = analysis(vec![
+            element("window", Some("Settings"), ElementBounds::new(0, 0, 300, 300)),
+            element("button", Some(" Save  "), ElementBounds::new(20, 240, 80, 24)),
+            element("button", Some("Save as"), ElementBounds::new(120, 240, 80, 24)),
+        ]);
+        to_screen(&mut frame, (1000, 500));
+
+        let report = SpyReport::from_analysis((1050, 750), &frame);
+        let spied = report.element.as_ref().unwrap();
+        assert_eq!(spied.bounds, (1020, 740, 80, 24));
+        assert_eq!(spied.query, "button:save");
+        assert!(ElementQuery::parse(&spied.query).matches(&frame.elements[1]));
+        assert_eq!(spied.query_matches, 2, "'Save as' contains 'save' too");
+        assert_eq!(report.lines(), vec![
+            "button \"Save\"".to_string(),
+            "confidence 93%".to_string(),
+            "bounds 1020,740 80x24".to_string(),
+            "query button:save (2 matches)".to_string(),
+        ]);
+
+        let window = SpyReport::from_analysis((1290, 510), &frame);
+        assert_eq!(window.element.unwrap().query, "window:settings");
+        assert!(SpyReport::from_analysis((10, 10), &frame).element.is_none());
+    }
+
+    #[test]
+    fn test_untitled_elements_are_selected_by_type() {
+        let icon = element("icon", None, ElementBounds::new(0, 0, 16, 16));
+        assert_eq!(query_for(&icon), "icon:");
+        assert!(ElementQuery::parse(&query_for(&icon)).matches(&icon));
+    }
+}
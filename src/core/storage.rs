@@ -0,0 +1,471 @@
+/*!
+ * Luna Storage - Per-store disk quotas with least-recently-used cleanup
+ *
+ * Transcripts, recordings, caches and history each live in their own
+ * directory under the data root and are capped independently. Paths can be
+ * pinned (e.g. a language pack in the model cache) so cleanup never
+ * removes them. Training data is only warned about, never cleaned: its
+ * crops are referenced from labels.jsonl and the COCO export.
+ */
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::config::StorageConfig;
+
+/// File under the storage root listing each store's pinned paths
+pub const PINS_FILE: &str = "pins.json";
+
+/// A managed on-disk store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StoreKind {
+    Transcripts,
+    Recordings,
+    ModelCache,
+    Embeddings,
+    History,
+    TrainingData,
+}
+
+impl StoreKind {
+    pub const ALL: [StoreKind; 6] = [
+        StoreKind::Transcripts,
+        StoreKind::Recordings,
+        StoreKind::ModelCache,
+        StoreKind::Embeddings,
+        StoreKind::History,
+        StoreKind::TrainingData,
+    ];
+
+    /// Directory name under the storage root
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            StoreKind::Transcripts => "transcripts",
+            StoreKind::Recordings => "recordings",
+            StoreKind::ModelCache => "model_cache",
+            StoreKind::Embeddings => "embeddings",
+            StoreKind::History => "history",
+            StoreKind::TrainingData => "training_data",
+        }
+    }
+
+    /// Whether LRU cleanup may delete files from this store. Training data
+    /// is a dataset whose index would be left pointing at deleted crops.
+    pub fn is_evictable(&self) -> bool {
+        !matches!(self, StoreKind::TrainingData)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase().replace('-', "_");
+        Self::ALL.into_iter().find(|kind| kind.dir_name() == name)
+    }
+}
+
+impl std::fmt::Display for StoreKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.dir_name())
+    }
+}
+
+/// Disk usage of one store
+#[derive(Debug, Clone)]
+pub struct StoreStatus {
+    pub kind: StoreKind,
+    pub path: PathBuf,
+    pub used_bytes: u64,
+    pub quota_bytes: u64,
+    pub file_count: usize,
+}
+
+impl StoreStatus {
+    /// Fraction of the quota in use; 0.0 for unlimited stores
+    pub fn usage_ratio(&self) -> f64 {
+        if self.quota_bytes == 0 {
+            0.0
+        } else {
+            self.used_bytes as f64 / self.quota_bytes as f64
+        }
+    }
+}
+
+/// Result of a cleanup pass over one store
+#[derive(Debug, Clone, Default)]
+pub struct CleanReport {
+    pub files_removed: usize,
+    pub bytes_freed: u64,
+}
+
+/// Store whose usage crossed the warning threshold
+#[derive(Debug, Clone)]
+pub struct QuotaWarning {
+    pub kind: StoreKind,
+    pub used_bytes: u64,
+    pub quota_bytes: u64,
+}
+
+/// Tracks and enforces per-store quotas under one root directory
+pub struct StorageManager {
+    root: PathBuf,
+    config: StorageConfig,
+}
+
+struct StoredFile {
+    path: PathBuf,
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl StorageManager {
+    pub fn new(root: impl Into<PathBuf>, config: StorageConfig) -> Self {
+        Self { root: root.into(), config }
+    }
+
+    /// Storage manager rooted at the configured directory, or the user data dir
+    pub fn from_config(config: &StorageConfig) -> Result<Self> {
+        let root = match &config.root_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let mut dir = dirs::data_dir()
+                    .map(Ok)
+                    .unwrap_or_else(std::env::current_dir)?;
+                dir.push("luna");
+                dir
+            }
+        };
+        Ok(Self::new(root, config.clone()))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Directory for a store, created on first use
+    pub fn store_path(&self, kind: StoreKind) -> Result<PathBuf> {
+        let path = self.root.join(kind.dir_name());
+        fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+
+    pub fn quota_bytes(&self, kind: StoreKind) -> u64 {
+        self.config.quota_mb(kind) * 1024 * 1024
+    }
+
+    /// Usage of every store
+    pub fn status(&self) -> Result<Vec<StoreStatus>> {
+        StoreKind::ALL.iter().map(|&kind| self.store_status(kind)).collect()
+    }
+
+    pub fn store_status(&self, kind: StoreKind) -> Result<StoreStatus> {
+        let path = self.root.join(kind.dir_name());
+        let files = collect_files(&path)?;
+        Ok(StoreStatus {
+            kind,
+            path,
+            used_bytes: files.iter().map(|f| f.size).sum(),
+            quota_bytes: self.quota_bytes(kind),
+            file_count: files.len(),
+        })
+    }
+
+    /// Stores at or above the configured warning ratio
+    pub fn check_quotas(&self) -> Result<Vec<QuotaWarning>> {
+        Ok(self
+            .status()?
+            .into_iter()
+            .filter(|s| s.quota_bytes > 0 && s.usage_ratio() >= self.config.warn_ratio)
+            .map(|s| QuotaWarning { kind: s.kind, used_bytes: s.used_bytes, quota_bytes: s.quota_bytes })
+            .collect())
+    }
+
+    /// Keep `path` (a file or directory, relative to the store) through
+    /// every cleanup until it is unpinned
+    pub fn pin(&self, kind: StoreKind, path: impl AsRef<Path>) -> Result<()> {
+        let mut pins = self.read_pins()?;
+        let pinned = pins.entry(kind.dir_name().to_string()).or_default();
+        if !pinned.iter().any(|p| p == path.as_ref()) {
+            pinned.push(path.as_ref().to_path_buf());
+            self.write_pins(&pins)?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether `path` was pinned
+    pub fn unpin(&self, kind: StoreKind, path: impl AsRef<Path>) -> Result<bool> {
+        let mut pins = self.read_pins()?;
+        let pinned = pins.entry(kind.dir_name().to_string()).or_default();
+        let before = pinned.len();
+        pinned.retain(|p| p != path.as_ref());
+        let removed = pinned.len() != before;
+        if removed {
+            self.write_pins(&pins)?;
+        }
+        Ok(removed)
+    }
+
+    /// Pinned paths of a store, relative to it
+    pub fn pinned(&self, kind: StoreKind) -> Result<Vec<PathBuf>> {
+        Ok(self.read_pins()?.remove(kind.dir_name()).unwrap_or_default())
+    }
+
+    fn read_pins(&self) -> Result<BTreeMap<String, Vec<PathBuf>>> {
+        match fs::read(self.root.join(PINS_FILE)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_pins(&self, pins: &BTreeMap<String, Vec<PathBuf>>) -> Result<()> {
+        fs::create_dir_all(&self.root)?;
+        fs::write(self.root.join(PINS_FILE), serde_json::to_vec_pretty(pins)?)?;
+        Ok(())
+    }
+
+    /// Files of a store that cleanup may remove: all but the pinned ones
+    fn removable_files(&self, kind: StoreKind) -> Result<Vec<StoredFile>> {
+        let dir = self.root.join(kind.dir_name());
+        let pinned: Vec<PathBuf> = self.pinned(kind)?.iter().map(|p| dir.join(p)).collect();
+        let mut files = collect_files(&dir)?;
+        files.retain(|file| !pinned.iter().any(|p| file.path.starts_with(p)));
+        Ok(files)
+    }
+
+    /// Delete least-recently-used files until the store is back under its cleanup target.
+    ///
+    /// Does nothing for stores that are within quota, unlimited or not
+    /// evictable. Pinned files count toward the quota but are never deleted.
+    pub fn clean(&self, kind: StoreKind) -> Result<CleanReport> {
+        let quota = self.quota_bytes(kind);
+        if quota == 0 || !kind.is_evictable() {
+            return Ok(CleanReport::default());
+        }
+
+        let used: u64 = self.store_status(kind)?.used_bytes;
+        if used <= quota {
+            return Ok(CleanReport::default());
+        }
+        let mut files = self.removable_files(kind)?;
+
+        let target = (quota as f64 * self.config.clean_target_ratio) as u64;
+        files.sort_by_key(|f| f.last_used);
+        Ok(evict(files, used, target))
+    }
+
+    /// Delete every file in a store regardless of quota, except pinned ones
+    pub fn purge(&self, kind: StoreKind) -> Result<CleanReport> {
+        let files = self.removable_files(kind)?;
+        let used = files.iter().map(|f| f.size).sum();
+        Ok(evict(files, used, 0))
+    }
+
+    /// Clean every store that is over quota
+    pub fn clean_all(&self) -> Result<Vec<(StoreKind, CleanReport)>> {
+        StoreKind::ALL
+            .iter()
+            .map(|&kind| self.clean(kind).map(|report| (kind, report)))
+            .collect()
+    }
+}
+
+/// Remove `files` in order until `used` is down to `target`. A file that
+/// can't be removed (locked, or deleted meanwhile) is logged and skipped, so
+/// one busy file doesn't stop the rest of the store being evicted.
+fn evict(files: Vec<StoredFile>, mut used: u64, target: u64) -> CleanReport {
+    let mut report = CleanReport::default();
+    for file in files {
+        if used <= target {
+            break;
+        }
+        match fs::remove_file(&file.path) {
+            Ok(()) => {
+                used = used.saturating_sub(file.size);
+                report.files_removed += 1;
+                report.bytes_freed += file.size;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => used = used.saturating_sub(file.size),
+            Err(e) => warn!("Could not remove {}: {}", file.path.display(), e),
+        }
+    }
+    report
+}
+
+/// All regular files below `dir`; a missing directory is an empty store
+fn collect_files(dir: &Path) -> Result<Vec<StoredFile>> {
+    let mut files = Vec::new();
+    if !dir.exists() {
+        return Ok(files);
+    }
+
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else if metadata.is_file() {
+                let last_used = metadata
+                    .accessed()
+                    .or_else(|_| metadata.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push(StoredFile { path: entry.path(), size: metadata.len(), last_used });
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Human-readable byte count
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn manager_with_quota(root: &Path, history_mb: u64) -> StorageManager {
+        let config = StorageConfig { history_mb, ..StorageConfig::default() };
+        StorageManager::new(root, config)
+    }
+
+    fn write_file(dir: &Path, name: &str, size: usize, age_secs: u64) {
+        let path = dir.join(name);
+        fs::write(&path, vec![0u8; size]).unwrap();
+        let time = SystemTime::now() - Duration::from_secs(age_secs);
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_times(fs::FileTimes::new().set_accessed(time).set_modified(time)).unwrap();
+    }
+
+    #[test]
+    fn test_store_names_round_trip() {
+        for kind in StoreKind::ALL {
+            assert_eq!(StoreKind::from_name(kind.dir_name()), Some(kind));
+        }
+        assert_eq!(StoreKind::from_name("model-cache"), Some(StoreKind::ModelCache));
+        assert_eq!(StoreKind::from_name("bogus"), None);
+    }
+
+    #[test]
+    fn test_status_of_missing_store_is_empty() {
+        let temp = tempfile::tempdir().unwrap();
+        let manager = manager_with_quota(temp.path(), 1);
+        let status = manager.store_status(StoreKind::History).unwrap();
+        assert_eq!(status.used_bytes, 0);
+        assert_eq!(status.file_count, 0);
+        assert_eq!(status.quota_bytes, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_clean_removes_least_recently_used_first() {
+        let temp = tempfile::tempdir().unwrap();
+        let manager = manager_with_quota(temp.path(), 1);
+        let dir = manager.store_path(StoreKind::History).unwrap();
+
+        // 1.5 MB total against a 1 MB quota
+        write_file(&dir, "oldest.log", 512 * 1024, 300);
+        write_file(&dir, "middle.log", 512 * 1024, 200);
+        write_file(&dir, "newest.log", 512 * 1024, 100);
+
+        assert_eq!(manager.check_quotas().unwrap().len(), 1);
+
+        let report = manager.clean(StoreKind::History).unwrap();
+        assert_eq!(report.files_removed, 2);
+        assert!(!dir.join("oldest.log").exists());
+        assert!(!dir.join("middle.log").exists());
+        assert!(dir.join("newest.log").exists());
+        assert!(manager.check_quotas().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_eviction_skips_files_it_cannot_remove() {
+        let temp = tempfile::tempdir().unwrap();
+        let (busy, gone, old) = (temp.path().join("busy"), temp.path().join("gone.log"), temp.path().join("old.log"));
+        // remove_file fails on a directory, as it would on a locked file
+        fs::create_dir(&busy).unwrap();
+        fs::write(&old, [0u8; 10]).unwrap();
+        let stored = |path: &Path| StoredFile { path: path.to_path_buf(), size: 10, last_used: SystemTime::UNIX_EPOCH };
+
+        let report = evict(vec![stored(&busy), stored(&gone), stored(&old)], 30, 0);
+        assert_eq!((report.files_removed, report.bytes_freed), (1, 10));
+        assert!(busy.exists());
+        assert!(!old.exists());
+    }
+
+    #[test]
+    fn test_clean_within_quota_is_noop() {
+        let temp = tempfile::tempdir().unwrap();
+        let manager = manager_with_quota(temp.path(), 1);
+        let dir = manager.store_path(StoreKind::History).unwrap();
+        write_file(&dir, "small.log", 1024, 10);
+
+        let report = manager.clean(StoreKind::History).unwrap();
+        assert_eq!(report.files_removed, 0);
+        assert!(dir.join("small.log").exists());
+
+        let report = manager.purge(StoreKind::History).unwrap();
+        assert_eq!(report.files_removed, 1);
+        assert_eq!(report.bytes_freed, 1024);
+    }
+
+    #[test]
+    fn test_pinned_files_survive_cleanup() {
+        let temp = tempfile::tempdir().unwrap();
+        let manager = manager_with_quota(temp.path(), 1);
+        let dir = manager.store_path(StoreKind::History).unwrap();
+        fs::create_dir_all(dir.join("keep")).unwrap();
+        write_file(&dir, "keep/oldest.log", 768 * 1024, 300);
+        write_file(&dir, "middle.log", 512 * 1024, 200);
+        write_file(&dir, "newest.log", 256 * 1024, 100);
+
+        manager.pin(StoreKind::History, "keep").unwrap();
+        manager.pin(StoreKind::History, "keep").unwrap();
+        assert_eq!(manager.pinned(StoreKind::History).unwrap(), [PathBuf::from("keep")]);
+        let report = manager.clean(StoreKind::History).unwrap();
+        assert_eq!(report.files_removed, 2);
+        assert!(dir.join("keep/oldest.log").exists());
+
+        assert_eq!(manager.purge(StoreKind::History).unwrap().files_removed, 0);
+        assert!(manager.unpin(StoreKind::History, "keep").unwrap());
+        assert_eq!(manager.purge(StoreKind::History).unwrap().files_removed, 1);
+    }
+
+    #[test]
+    fn test_training_data_is_never_evicted() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = StorageConfig { training_data_mb: 1, ..StorageConfig::default() };
+        let manager = StorageManager::new(temp.path(), config);
+        let dir = manager.store_path(StoreKind::TrainingData).unwrap();
+        write_file(&dir, "crop.png", 1024 * 1024, 300);
+        write_file(&dir, "labels.jsonl", 512 * 1024, 100);
+
+        assert_eq!(manager.check_quotas().unwrap().len(), 1);
+        assert_eq!(manager.clean_all().unwrap().iter().map(|(_, r)| r.files_removed).sum::<usize>(), 0);
+        assert!(dir.join("crop.png").exists());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MB");
+    }
+}
@@ -0,0 +1,233 @@
+/*!
+ * Luna Tracker - Stable element IDs and sparse updates between analyses
+ *
+ * Consecutive analyses of a mostly unchanged screen detect mostly the same
+ * elements. `ElementTracker` matches each analysis against the previous one
+ * (same type, overlapping or nearby bounds), keeps an element's ID for as
+ * long as it is matched, and reports only what changed: elements added,
+ * removed, moved, or whose recognized text changed. The overlay applies
+ * these deltas instead of redrawing a full element list every frame, and
+ * they are small enough to push to remote dashboards as they happen.
+ *
+ * Deltas are numbered. A receiver that sees a gap in `sequence` has missed
+ * one and should start over from `ElementTracker::snapshot`.
+ */
+
+use serde::{Deserialize, Serialize};
+
+use super::{ElementBounds, ScreenElement};
+use crate::utils::geometry::Rectangle;
+
+/// Overlap above which a detection is the same element, moved or resized
+const MATCH_IOU: f64 = 0.3;
+/// Center distance, in pixels, within which a detection that no longer
+/// overlaps (a small element that moved) is still the same element
+const MATCH_DISTANCE: f64 = 24.0;
+
+/// `(x, y, width, height)`
+pub type Bounds = (i32, i32, i32, i32);
+
+/// One change to the tracked elements
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ElementUpdate {
+    Added { id: u64, element_type: String, bounds: Bounds, text: Option<String> },
+    Removed { id: u64 },
+    Moved { id: u64, bounds: Bounds },
+    /// Recognized text changed; `None` when it is no longer recognized
+    TextChanged { id: u64, text: Option<String> },
+}
+
+/// Changes from one analysis to the next
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ElementDelta {
+    /// Counts deltas from 1; a snapshot carries the sequence it replaces up to
+    pub sequence: u64,
+    pub updates: Vec<ElementUpdate>,
+}
+
+impl ElementDelta {
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Tracked {
+    id: u64,
+    element_type: String,
+    bounds: ElementBounds,
+    text: Option<String>,
+}
+
+/// Assigns stable IDs to detected elements across analyses
+#[derive(Debug, Default)]
+pub struct ElementTracker {
+    /// The elements of the last analysis, in its order
+    tracked: Vec<Tracked>,
+    next_id: u64,
+    sequence: u64,
+}
+
+impl ElementTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match `elements` (one analysis) against the tracked ones and return
+    /// what changed
+    pub fn update(&mut self, elements: &[ScreenElement]) -> ElementDelta {
+        let matched = self.match_previous(elements);
+        let mut updates = Vec::new();
+        let mut kept = vec![false; self.tracked.len()];
+        let mut tracked = Vec::with_capacity(elements.len());
+
+        for (element, previous) in elements.iter().zip(matched) {
+            let id = match previous {
+                Some(index) => {
+                    kept[index] = true;
+                    let before = &self.tracked[index];
+                    if before.bounds != element.bounds {
+                        updates.push(ElementUpdate::Moved { id: before.id, bounds: bounds_of(&element.bounds) });
+                    }
+                    if before.text != element.text {
+                        updates.push(ElementUpdate::TextChanged { id: before.id, text: element.text.clone() });
+                    }
+                    before.id
+                }
+                None => {
+                    self.next_id += 1;
+                    updates.push(ElementUpdate::Added {
+                        id: self.next_id,
+                        element_type: element.element_type.clone(),
+                        bounds: bounds_of(&element.bounds),
+                        text: element.text.clone(),
+                    });
+                    self.next_id
+                }
+            };
+            tracked.push(Tracked { id, element_type: element.element_type.clone(), bounds: element.bounds.clone(), text: element.text.clone() });
+        }
+        let removed = self.tracked.iter().zip(&kept).filter(|(_, kept)| !**kept);
+        updates.splice(0..0, removed.map(|(gone, _)| ElementUpdate::Removed { id: gone.id }));
+
+        self.tracked = tracked;
+        self.sequence += 1;
+        ElementDelta { sequence: self.sequence, updates }
+    }
+
+    /// Every tracked element as an addition, for a receiver starting out or
+    /// catching up after a gap
+    pub fn snapshot(&self) -> ElementDelta {
+        let updates = self
+            .tracked
+            .iter()
+            .map(|t| ElementUpdate::Added { id: t.id, element_type: t.element_type.clone(), bounds: bounds_of(&t.bounds), text: t.text.clone() })
+            .collect();
+        ElementDelta { sequence: self.sequence, updates }
+    }
+
+    /// Stable ID of element `index` of the last analysis
+    pub fn id_of(&self, index: usize) -> Option<u64> {
+        self.tracked.get(index).map(|t| t.id)
+    }
+
+    /// For each of `elements`, the index of the tracked element it continues.
+    /// Candidate pairs are taken closest first so each is used once.
+    fn match_previous(&self, elements: &[ScreenElement]) -> Vec<Option<usize>> {
+        let mut costs: Vec<(f64, usize, usize)> = elements
+            .iter()
+            .enumerate()
+            .flat_map(|(e, element)| {
+                self.tracked
+                    .iter()
+                    .enumerate()
+                    .filter(move |(_, t)| t.element_type == element.element_type)
+                    .filter_map(move |(t, tracked)| Some((match_cost(&tracked.bounds, &element.bounds)?, e, t)))
+            })
+            .collect();
+        costs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut matched = vec![None; elements.len()];
+        let mut taken = vec![false; self.tracked.len()];
+        for (_, e, t) in costs {
+            if matched[e].is_none() && !taken[t] {
+                matched[e] = Some(t);
+                taken[t] = true;
+            }
+        }
+        matched
+    }
+}
+
+/// How unlike `a` is to `b`, or `None` when they cannot be the same element
+fn match_cost(a: &ElementBounds, b: &ElementBounds) -> Option<f64> {
+    let iou = Rectangle::from(a).iou(&Rectangle::from(b));
+    if iou >= MATCH_IOU {
+        return Some(1.0 - iou);
+    }
+    let ((ax, ay), (bx, by)) = (a.center(), b.center());
+    let distance = f64::from(ax - bx).hypot(f64::from(ay - by));
+    // Always costlier than any overlapping match
+    (distance <= MATCH_DISTANCE).then(|| 1.0 + distance / MATCH_DISTANCE)
+}
+
+fn bounds_of(bounds: &ElementBounds) -> Bounds {
+    (bounds.x, bounds.y, bounds.width, bounds.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(element_type: &str, x: i32, y: i32, text: Option<&str>) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(x, y, 80, 30),
+            shape: None,
+            confidence: 0.8,
+            text: text.map(str::to_string),
+            attributes: Default::default(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_reports_only_what_changed_under_stable_ids() {
+        let mut tracker = ElementTracker::new();
+        let first = tracker.update(&[element("button", 10, 10, Some("Save")), element("text", 200, 10, Some("3 items"))]);
+        assert_eq!(first.sequence, 1);
+        assert_eq!(first.updates.len(), 2);
+        assert!(matches!(first.updates[0], ElementUpdate::Added { id: 1, .. }));
+
+        // Unchanged screen: nothing to send
+        let same = tracker.update(&[element("button", 10, 10, Some("Save")), element("text", 200, 10, Some("3 items"))]);
+        assert!(same.is_empty());
+
+        // The button shifts, the count changes, a dialog opens; order doesn't matter
+        let delta = tracker.update(&[
+            element("text", 200, 10, Some("4 items")),
+            element("button", 14, 12, Some("Save")),
+            element("window", 400, 300, None),
+        ]);
+        assert_eq!(
+            delta.updates,
+            vec![
+                ElementUpdate::TextChanged { id: 2, text: Some("4 items".to_string()) },
+                ElementUpdate::Moved { id: 1, bounds: (14, 12, 80, 30) },
+                ElementUpdate::Added { id: 3, element_type: "window".to_string(), bounds: (400, 300, 80, 30), text: None },
+            ]
+        );
+        assert_eq!((tracker.id_of(0), tracker.id_of(1)), (Some(2), Some(1)));
+
+        // A different type in the same place is a new element
+        let delta = tracker.update(&[element("checkbox", 14, 12, None)]);
+        assert_eq!(delta.updates.len(), 4);
+        assert!(matches!(delta.updates[..3], [ElementUpdate::Removed { id: 2 }, ElementUpdate::Removed { id: 1 }, ElementUpdate::Removed { id: 3 }]));
+        assert_eq!(tracker.snapshot().sequence, 4);
+
+        let json = serde_json::to_string(&delta.updates[3]).unwrap();
+        assert_eq!(json, r#"{"op":"added","id":4,"element_type":"checkbox","bounds":[14,12,80,30],"text":null}"#);
+    }
+}
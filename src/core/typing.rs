@@ -0,0 +1,104 @@
+/*!
+ * Luna Typing - Reading typed text back and putting back what was lost
+ *
+ * Apps that are busy when keystrokes arrive sometimes drop some. After text
+ * is typed into a field the command clicked, the field is read again and
+ * compared with what it should hold: what it showed before plus the typed
+ * text. When characters are missing, the field is read a second time, and
+ * only if both readings agree does the caret go to the end, the text from
+ * the first missing character on get erased and typed again, and the field
+ * get read once more, up to `typing_check.max_corrections` times.
+ *
+ * Only a reading that is the expected text with characters missing is
+ * corrected, and OCR dropping a character looks just like the app dropping
+ * one; the second reading catches a glyph that was missed once, not one
+ * that is always missed. Anything else (an empty or unreadable field,
+ * characters that weren't typed, a field scrolled so its start is hidden,
+ * masked password dots) is reported unverified and left alone rather than
+ * "fixed" into something worse. The check is off by default.
+ */
+
+use serde::{Deserialize, Serialize};
+
+/// Characters password fields show instead of text
+pub(crate) const MASK_CHARACTERS: [char; 4] = ['•', '●', '*', '∙'];
+
+/// Outcome of typing into one field, for the command result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypingReport {
+    /// Position of the `Type` action in the command's actions
+    pub action: usize,
+    /// What the field should hold: its earlier content and the typed text
+    pub expected: String,
+    /// What the field was last read as; `None` when it couldn't be read
+    pub read: Option<String>,
+    /// The last reading matched `expected`
+    pub verified: bool,
+    /// Times the end of the text was erased and typed again
+    pub corrections: u32,
+}
+
+/// How a reading of a field compares with what it should hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldCheck {
+    Matches,
+    /// Characters are missing: erase the last `erase` characters and type `retype`
+    Missing { erase: usize, retype: String },
+    /// Not the expected text with characters missing; leave it alone
+    Unexpected,
+}
+
+/// Compare what a field was read as with what it should hold. Trailing
+/// whitespace is ignored, since it can't be seen. An empty reading is more
+/// likely text OCR couldn't make out than text that never arrived.
+pub fn check_field(expected: &str, read: &str) -> FieldCheck {
+    let expected: Vec<char> = expected.trim_end().chars().collect();
+    let read: Vec<char> = read.trim_end().chars().collect();
+    if read == expected {
+        return FieldCheck::Matches;
+    }
+    if read.is_empty() {
+        return FieldCheck::Unexpected;
+    }
+    if read.len() >= expected.len() || read.iter().any(|c| MASK_CHARACTERS.contains(c)) || !is_subsequence(&read, &expected) {
+        return FieldCheck::Unexpected;
+    }
+    let kept = read.iter().zip(&expected).take_while(|(r, e)| r == e).count();
+    // Nothing in common at the start: more likely scrolled out of view than lost
+    if kept == 0 {
+        return FieldCheck::Unexpected;
+    }
+    FieldCheck::Missing { erase: read.len() - kept, retype: expected[kept..].iter().collect() }
+}
+
+/// Whether `short` is `long` with some characters left out
+fn is_subsequence(short: &[char], long: &[char]) -> bool {
+    let mut long = long.iter();
+    short.iter().all(|c| long.any(|l| l == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropped_characters_are_retyped_from_the_first_gap() {
+        assert_eq!(check_field("ada@example.com", "ada@example.com "), FieldCheck::Matches);
+        assert_eq!(
+            check_field("ada@example.com", "ada@exmple.cm"),
+            FieldCheck::Missing { erase: 7, retype: "ample.com".to_string() }
+        );
+        assert_eq!(check_field("ada@example.com", "ada@example"), FieldCheck::Missing { erase: 0, retype: ".com".to_string() });
+    }
+
+    #[test]
+    fn test_readings_that_are_not_just_missing_characters_are_left_alone() {
+        // Unreadable, a misread character, an extra one, a field scrolled past its start, a password
+        assert_eq!(check_field("Ada", ""), FieldCheck::Unexpected);
+        assert_eq!(check_field("Ada", "  "), FieldCheck::Unexpected);
+        assert_eq!(check_field("ada@example.com", "ada@examp1e.com"), FieldCheck::Unexpected);
+        assert_eq!(check_field("ada", "adda"), FieldCheck::Unexpected);
+        assert_eq!(check_field("averyverylongname@example.com", "longname@example.com"), FieldCheck::Unexpected);
+        assert_eq!(check_field("hunter2", "•••••"), FieldCheck::Unexpected);
+    }
+}
@@ -0,0 +1,774 @@
+/*!
+ * Luna VNC - Capture from and send input to a remote machine over RFB
+ *
+ * With `vnc.enabled`, frames come from a VNC server's framebuffer and
+ * actions go back as RFB pointer and key events instead of to this desktop,
+ * so a VM or a remote machine is automated without installing anything in
+ * it. Which machine a config file points at makes it the profile for that
+ * machine.
+ *
+ * The client speaks RFB 3.3 to 3.8 with raw encoding, asking for 32-bit
+ * true colour. It uses the "None" security type when the server offers it
+ * and VNC authentication otherwise, with the password from
+ * `LUNA_VNC_PASSWORD` or the file named by `vnc.password_file`, never from
+ * the config itself. VNC authentication only proves the password (DES, at
+ * most 8 characters) and doesn't encrypt the session, so keep the server on
+ * a trusted network or behind an SSH tunnel. RDP is not spoken. The connection is opened on first use and reopened
+ * after an I/O error. Window, focus and caret queries still look at this
+ * desktop; the display layout is the remote framebuffer's size.
+ */
+
+use log::{debug, info, warn};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::config::VncConfig;
+use super::displays::DisplayProbe;
+use crate::input::keys::{canonical_key_name, parse_chord};
+use crate::input::{ActionType, InputAction, InputError, InputSink, MouseButton, ScrollDirection};
+use crate::utils::des::des_encrypt_block;
+use crate::utils::image_processing::Image;
+use crate::vision::screen_capture::{CaptureError, DisplayInfo, FrameSource, SharedFrameSource};
+
+const SECURITY_NONE: u8 = 1;
+const SECURITY_VNC_AUTH: u8 = 2;
+/// Environment variable holding the VNC password
+const PASSWORD_ENV: &str = "LUNA_VNC_PASSWORD";
+const ENCODING_RAW: i32 = 0;
+/// Pseudo-encoding announcing a framebuffer resize
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+/// Largest server string (name, failure reason, clipboard) read
+const MAX_STRING_BYTES: usize = 1 << 20;
+
+/// One RFB input message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RfbEvent {
+    /// Pointer at (`x`, `y`) with the buttons in `mask` held (bit 0 left,
+    /// 1 middle, 2 right, 3/4 wheel up/down, 5/6 wheel left/right)
+    Pointer { mask: u8, x: u16, y: u16 },
+    Key { down: bool, keysym: u32 },
+}
+
+impl RfbEvent {
+    fn encode(&self) -> Vec<u8> {
+        match *self {
+            RfbEvent::Pointer { mask, x, y } => {
+                let mut message = vec![5, mask];
+                message.extend_from_slice(&x.to_be_bytes());
+                message.extend_from_slice(&y.to_be_bytes());
+                message
+            }
+            RfbEvent::Key { down, keysym } => {
+                let mut message = vec![4, down as u8, 0, 0];
+                message.extend_from_slice(&keysym.to_be_bytes());
+                message
+            }
+        }
+    }
+}
+
+/// X11 keysym of a canonical key name (see `input::keys`)
+pub fn keysym(name: &str) -> Option<u32> {
+    let name = canonical_key_name(name)?;
+    let sym = match name {
+        "ctrl" | "lctrl" => 0xffe3,
+        "rctrl" => 0xffe4,
+        "shift" | "lshift" => 0xffe1,
+        "rshift" => 0xffe2,
+        "alt" | "lalt" => 0xffe9,
+        "ralt" => 0xffea,
+        "win" | "lwin" => 0xffeb,
+        "rwin" => 0xffec,
+        "apps" => 0xff67,
+        "enter" => 0xff0d,
+        "escape" => 0xff1b,
+        "backspace" => 0xff08,
+        "tab" => 0xff09,
+        "space" => 0x20,
+        "capslock" => 0xffe5,
+        "numlock" => 0xff7f,
+        "scrolllock" => 0xff14,
+        "pause" => 0xff13,
+        "printscreen" => 0xff61,
+        "insert" => 0xff63,
+        "delete" => 0xffff,
+        "home" => 0xff50,
+        "end" => 0xff57,
+        "pageup" => 0xff55,
+        "pagedown" => 0xff56,
+        "left" => 0xff51,
+        "up" => 0xff52,
+        "right" => 0xff53,
+        "down" => 0xff54,
+        "multiply" => 0xffaa,
+        "add" => 0xffab,
+        "subtract" => 0xffad,
+        "decimal" => 0xffae,
+        "divide" => 0xffaf,
+        "numpadenter" => 0xff8d,
+        "volumemute" => 0x1008ff12,
+        "volumedown" => 0x1008ff11,
+        "volumeup" => 0x1008ff13,
+        "nexttrack" => 0x1008ff17,
+        "prevtrack" => 0x1008ff16,
+        "mediastop" => 0x1008ff15,
+        "playpause" => 0x1008ff14,
+        "browserback" => 0x1008ff26,
+        "browserforward" => 0x1008ff27,
+        "browserrefresh" => 0x1008ff29,
+        "browserhome" => 0x1008ff18,
+        _ => {
+            if let Some(digit) = name.strip_prefix("numpad") {
+                return digit.parse::<u32>().ok().map(|d| 0xffb0 + d);
+            }
+            if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()) {
+                return Some(0xffbe + number - 1);
+            }
+            let mut chars = name.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(char_keysym(c)),
+                _ => None,
+            };
+        }
+    };
+    Some(sym)
+}
+
+/// Keysym that types `c`: Latin-1 as is, the rest as Unicode keysyms
+fn char_keysym(c: char) -> u32 {
+    match c {
+        '\n' => 0xff0d,
+        '\t' => 0xff09,
+        c if (c as u32) < 0x100 => c as u32,
+        c => 0x0100_0000 + c as u32,
+    }
+}
+
+fn key_events(name: &str, down: bool) -> Result<RfbEvent, InputError> {
+    let keysym = keysym(name).ok_or_else(|| InputError::InvalidKey(name.to_string()))?;
+    Ok(RfbEvent::Key { down, keysym })
+}
+
+/// Press every key of `names`, then release them in reverse
+fn chord_events(names: &[&str]) -> Result<Vec<RfbEvent>, InputError> {
+    let mut events = Vec::with_capacity(names.len() * 2);
+    for name in names {
+        events.push(key_events(name, true)?);
+    }
+    for name in names.iter().rev() {
+        events.push(key_events(name, false)?);
+    }
+    Ok(events)
+}
+
+/// The RFB events that perform `action`, with the pointer last at `pointer`
+pub fn input_events(action: &InputAction, pointer: (u16, u16)) -> Result<Vec<RfbEvent>, InputError> {
+    let at = |x: i32, y: i32| (x.clamp(0, u16::MAX as i32) as u16, y.clamp(0, u16::MAX as i32) as u16);
+    Ok(match &action.action_type {
+        ActionType::Click { button, modifiers } => {
+            let (x, y) = at(action.target.x, action.target.y);
+            let mask = match button {
+                MouseButton::Left => 1,
+                MouseButton::Middle => 2,
+                MouseButton::Right => 4,
+            };
+            let mut events = Vec::new();
+            for modifier in modifiers {
+                events.push(key_events(modifier, true)?);
+            }
+            events.extend([
+                RfbEvent::Pointer { mask: 0, x, y },
+                RfbEvent::Pointer { mask, x, y },
+                RfbEvent::Pointer { mask: 0, x, y },
+            ]);
+            for modifier in modifiers.iter().rev() {
+                events.push(key_events(modifier, false)?);
+            }
+            events
+        }
+        ActionType::Move { x, y } => {
+            let (x, y) = at(*x, *y);
+            vec![RfbEvent::Pointer { mask: 0, x, y }]
+        }
+        ActionType::Scroll { direction, amount } => {
+            let mask = match direction {
+                ScrollDirection::Up => 8,
+                ScrollDirection::Down => 16,
+                ScrollDirection::Left => 32,
+                ScrollDirection::Right => 64,
+            };
+            let (x, y) = pointer;
+            (0..amount.unsigned_abs().max(1))
+                .flat_map(|_| [RfbEvent::Pointer { mask, x, y }, RfbEvent::Pointer { mask: 0, x, y }])
+                .collect()
+        }
+        ActionType::Type { text } => text
+            .chars()
+            .flat_map(|c| {
+                let keysym = char_keysym(c);
+                [RfbEvent::Key { down: true, keysym }, RfbEvent::Key { down: false, keysym }]
+            })
+            .collect(),
+        ActionType::Key { key } => {
+            let chord = parse_chord(key).map_err(|e| InputError::InvalidKey(e.to_string()))?;
+            let mut names = chord.modifiers.clone();
+            names.push(chord.key);
+            chord_events(&names)?
+        }
+        ActionType::KeyDown { key } => vec![key_events(key, true)?],
+        ActionType::KeyUp { key } => vec![key_events(key, false)?],
+    })
+}
+
+/// An open RFB session and the framebuffer as last received
+struct Connection {
+    stream: TcpStream,
+    name: String,
+    frame: Image,
+    pointer: (u16, u16),
+}
+
+impl Connection {
+    fn open(config: &VncConfig) -> io::Result<Self> {
+        let address = (config.host.as_str(), config.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host did not resolve"))?;
+        let stream = TcpStream::connect_timeout(&address, Duration::from_millis(config.connect_timeout_ms))?;
+        stream.set_read_timeout(Some(Duration::from_millis(config.timeout_ms)))?;
+        stream.set_write_timeout(Some(Duration::from_millis(config.timeout_ms)))?;
+        stream.set_nodelay(true)?;
+        let password = vnc_password(config.password_file.as_deref())?;
+        Self::handshake(stream, config.shared, password.as_deref())
+    }
+
+    fn handshake(mut stream: TcpStream, shared: bool, password: Option<&str>) -> io::Result<Self> {
+        let mut version = [0u8; 12];
+        stream.read_exact(&mut version)?;
+        let minor = parse_version(&version)?;
+        stream.write_all(format!("RFB 003.{:03}\n", minor).as_bytes())?;
+
+        // 3.3 servers pick the type; later ones offer a list to choose from
+        let security = if minor == 3 {
+            match read_u32(&mut stream)? {
+                0 => return Err(refused(read_string(&mut stream)?)),
+                other => other.min(255) as u8,
+            }
+        } else {
+            let count = read_u8(&mut stream)? as usize;
+            if count == 0 {
+                return Err(refused(read_string(&mut stream)?));
+            }
+            let mut types = vec![0u8; count];
+            stream.read_exact(&mut types)?;
+            let chosen = [SECURITY_NONE, SECURITY_VNC_AUTH]
+                .into_iter()
+                .find(|kind| types.contains(kind))
+                .ok_or_else(|| unsupported_security(&types))?;
+            stream.write_all(&[chosen])?;
+            chosen
+        };
+
+        match security {
+            SECURITY_NONE if minor < 8 => {}
+            SECURITY_NONE => security_result(&mut stream, minor)?,
+            SECURITY_VNC_AUTH => {
+                let password = password.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("server asks for a password; set {} or vnc.password_file", PASSWORD_ENV),
+                    )
+                })?;
+                let mut challenge = [0u8; 16];
+                stream.read_exact(&mut challenge)?;
+                stream.write_all(&vnc_auth_response(password, &challenge))?;
+                security_result(&mut stream, minor)?;
+            }
+            other => return Err(unsupported_security(&[other])),
+        }
+
+        stream.write_all(&[shared as u8])?;
+        let width = read_u16(&mut stream)? as usize;
+        let height = read_u16(&mut stream)? as usize;
+        let mut server_format = [0u8; 16];
+        stream.read_exact(&mut server_format)?;
+        let name = read_string(&mut stream)?;
+
+        // 32 bits per pixel, depth 24, little-endian true colour, 8 bits a
+        // channel: each pixel arrives as [blue, green, red, unused]
+        let mut set_format = vec![0u8, 0, 0, 0, 32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 16, 8, 0, 0, 0, 0];
+        let mut set_encodings = vec![2u8, 0, 0, 2];
+        set_encodings.extend_from_slice(&ENCODING_RAW.to_be_bytes());
+        set_encodings.extend_from_slice(&ENCODING_DESKTOP_SIZE.to_be_bytes());
+        set_format.extend_from_slice(&set_encodings);
+        stream.write_all(&set_format)?;
+
+        info!("Connected to VNC desktop '{}' ({}x{})", name, width, height);
+        Ok(Self { stream, name, frame: Image::new(width, height, 3), pointer: (0, 0) })
+    }
+
+    /// Ask for the whole framebuffer and read messages until it arrives
+    fn refresh(&mut self) -> io::Result<Image> {
+        let (width, height) = (self.frame.width as u16, self.frame.height as u16);
+        let mut request = vec![3u8, 0, 0, 0, 0, 0];
+        request.extend_from_slice(&width.to_be_bytes());
+        request.extend_from_slice(&height.to_be_bytes());
+        self.stream.write_all(&request)?;
+        loop {
+            match read_u8(&mut self.stream)? {
+                0 => {
+                    self.read_update()?;
+                    return Ok(self.frame.clone());
+                }
+                // SetColourMapEntries, unused with true colour
+                1 => {
+                    let mut header = [0u8; 5];
+                    self.stream.read_exact(&mut header)?;
+                    let colours = u16::from_be_bytes([header[3], header[4]]) as usize;
+                    skip(&mut self.stream, colours * 6)?;
+                }
+                // Bell
+                2 => {}
+                // ServerCutText
+                3 => {
+                    skip(&mut self.stream, 3)?;
+                    read_string(&mut self.stream)?;
+                }
+                other => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown server message type {}", other)));
+                }
+            }
+        }
+    }
+
+    fn read_update(&mut self) -> io::Result<()> {
+        skip(&mut self.stream, 1)?;
+        let rectangles = read_u16(&mut self.stream)?;
+        for _ in 0..rectangles {
+            let x = read_u16(&mut self.stream)? as usize;
+            let y = read_u16(&mut self.stream)? as usize;
+            let width = read_u16(&mut self.stream)? as usize;
+            let height = read_u16(&mut self.stream)? as usize;
+            match read_u32(&mut self.stream)? as i32 {
+                ENCODING_RAW => {
+                    let mut pixels = vec![0u8; width * height * 4];
+                    self.stream.read_exact(&mut pixels)?;
+                    for row in 0..height {
+                        for column in 0..width {
+                            let (px, py) = (x + column, y + row);
+                            if px < self.frame.width && py < self.frame.height {
+                                let p = &pixels[(row * width + column) * 4..][..4];
+                                self.frame.set_pixel(px, py, &[p[2], p[1], p[0]]);
+                            }
+                        }
+                    }
+                }
+                ENCODING_DESKTOP_SIZE => {
+                    debug!("VNC desktop resized to {}x{}", width, height);
+                    self.frame = Image::new(width, height, 3);
+                }
+                other => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("server sent unrequested encoding {}", other)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, events: &[RfbEvent]) -> io::Result<()> {
+        let mut message = Vec::new();
+        for event in events {
+            if let RfbEvent::Pointer { x, y, .. } = event {
+                self.pointer = (*x, *y);
+            }
+            message.extend(event.encode());
+        }
+        self.stream.write_all(&message)
+    }
+}
+
+/// Minor version to speak: 3, 7 or 8 (later and vendor versions get 8)
+fn parse_version(version: &[u8; 12]) -> io::Result<u32> {
+    let text = std::str::from_utf8(version).ok().filter(|t| t.starts_with("RFB 003.") && t.ends_with('\n'));
+    let minor = text.and_then(|t| t[8..11].parse::<u32>().ok()).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("not an RFB server: {:?}", String::from_utf8_lossy(version)))
+    })?;
+    Ok(match minor {
+        0..=6 => 3,
+        7 => 7,
+        _ => 8,
+    })
+}
+
+fn refused(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("server refused the connection: {}", reason))
+}
+
+fn unsupported_security(types: &[u8]) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("server offers no supported security type {:?}", types))
+}
+
+/// Read SecurityResult; 3.3 and 3.7 servers send no reason on failure
+fn security_result(stream: &mut impl Read, minor: u32) -> io::Result<()> {
+    if read_u32(stream)? == 0 {
+        return Ok(());
+    }
+    let reason = if minor >= 8 { read_string(stream)? } else { "authentication failed".to_string() };
+    Err(refused(reason))
+}
+
+/// The password from `LUNA_VNC_PASSWORD`, else from `file` without its trailing newline
+fn vnc_password(file: Option<&Path>) -> io::Result<Option<String>> {
+    if let Some(password) = std::env::var(PASSWORD_ENV).ok().filter(|password| !password.is_empty()) {
+        return Ok(Some(password));
+    }
+    let Some(file) = file else { return Ok(None) };
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read VNC password file {}: {}", file.display(), e)))?;
+    Ok(Some(contents.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// VNC authentication: the challenge DES-encrypted under the password,
+/// truncated or zero-padded to 8 bytes with each byte's bits reversed
+fn vnc_auth_response(password: &str, challenge: &[u8; 16]) -> [u8; 16] {
+    let mut key = [0u8; 8];
+    for (slot, byte) in key.iter_mut().zip(password.bytes()) {
+        *slot = byte.reverse_bits();
+    }
+    let mut response = [0u8; 16];
+    for (out, block) in response.chunks_exact_mut(8).zip(challenge.chunks_exact(8)) {
+        out.copy_from_slice(&des_encrypt_block(&key, block.try_into().expect("8-byte block")));
+    }
+    response
+}
+
+fn read_u8(stream: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(stream: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    stream.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32(stream: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_string(stream: &mut impl Read) -> io::Result<String> {
+    let length = read_u32(stream)? as usize;
+    if length > MAX_STRING_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}-byte string from server", length)));
+    }
+    let mut buf = vec![0u8; length];
+    stream.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn skip(stream: &mut impl Read, bytes: usize) -> io::Result<()> {
+    io::copy(&mut stream.take(bytes as u64), &mut io::sink())?;
+    Ok(())
+}
+
+struct State {
+    config: VncConfig,
+    connection: Option<Connection>,
+}
+
+/// A VNC server used as frame source, input sink and display layout; the
+/// clones share one connection
+#[derive(Clone)]
+pub struct VncClient {
+    state: Arc<Mutex<State>>,
+}
+
+impl VncClient {
+    pub fn new(config: VncConfig) -> Self {
+        Self { state: Arc::new(Mutex::new(State { config, connection: None })) }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// "host:port", for logs and errors
+    pub fn address(&self) -> String {
+        let state = self.state();
+        format!("{}:{}", state.config.host, state.config.port)
+    }
+
+    /// Desktop name the server announced, once connected
+    pub fn desktop_name(&self) -> Option<String> {
+        self.state().connection.as_ref().map(|c| c.name.clone())
+    }
+
+    /// Run `f` on the connection, opening it first if needed and dropping
+    /// it when it fails so the next use reconnects
+    fn with_connection<T>(&self, f: impl FnOnce(&mut Connection) -> io::Result<T>) -> io::Result<T> {
+        let mut state = self.state();
+        if state.connection.is_none() {
+            state.connection = Some(Connection::open(&state.config)?);
+        }
+        let result = f(state.connection.as_mut().expect("connection was just opened"));
+        if let Err(e) = &result {
+            warn!("VNC connection to {}:{} lost: {}", state.config.host, state.config.port, e);
+            state.connection = None;
+        }
+        result
+    }
+
+    pub fn frame_source(&self) -> SharedFrameSource {
+        Arc::new(Mutex::new(VncCapture(self.clone())))
+    }
+
+    pub fn input_sink(&self) -> Box<dyn InputSink> {
+        Box::new(VncInput(self.clone()))
+    }
+
+    pub fn display_probe(&self) -> Box<dyn DisplayProbe + Send> {
+        Box::new(VncDisplays(self.clone()))
+    }
+}
+
+struct VncCapture(VncClient);
+
+impl FrameSource for VncCapture {
+    fn capture(&mut self) -> Result<Image, CaptureError> {
+        self.0
+            .with_connection(Connection::refresh)
+            .map_err(|e| CaptureError::PlatformError(format!("VNC {}: {}", self.0.address(), e)))
+    }
+}
+
+struct VncInput(VncClient);
+
+impl InputSink for VncInput {
+    fn send(&mut self, action: &InputAction) -> Result<(), InputError> {
+        let pointer = self.0.state().connection.as_ref().map_or((0, 0), |c| c.pointer);
+        let events = input_events(action, pointer)?;
+        self.0
+            .with_connection(|connection| connection.send(&events))
+            .map_err(|e| InputError::PlatformError(format!("VNC {}: {}", self.0.address(), e)))
+    }
+}
+
+struct VncDisplays(VncClient);
+
+impl DisplayProbe for VncDisplays {
+    /// The framebuffer as last received, connecting first if needed;
+    /// unknown while the server can't be reached
+    fn displays(&self) -> Option<Vec<DisplayInfo>> {
+        let (width, height) = self.0.with_connection(|c| Ok((c.frame.width, c.frame.height))).ok()?;
+        Some(vec![DisplayInfo {
+            id: 0,
+            name: format!("vnc://{}", self.0.address()),
+            width: width as u32,
+            height: height as u32,
+            x: 0,
+            y: 0,
+            is_primary: true,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    fn action(action_type: ActionType, x: i32, y: i32) -> InputAction {
+        InputAction { action_type, target: crate::input::Target { x, y, element_type: None }, timestamp: Instant::now() }
+    }
+
+    #[test]
+    fn test_actions_become_rfb_events() {
+        let click = action(ActionType::Click { button: MouseButton::Right, modifiers: vec!["shift".to_string()] }, 40, -3);
+        assert_eq!(input_events(&click, (0, 0)).unwrap(), [
+            RfbEvent::Key { down: true, keysym: 0xffe1 },
+            RfbEvent::Pointer { mask: 0, x: 40, y: 0 },
+            RfbEvent::Pointer { mask: 4, x: 40, y: 0 },
+            RfbEvent::Pointer { mask: 0, x: 40, y: 0 },
+            RfbEvent::Key { down: false, keysym: 0xffe1 },
+        ]);
+
+        let chord = input_events(&action(ActionType::Key { key: "ctrl+shift+F5".to_string() }, 0, 0), (0, 0)).unwrap();
+        let syms: Vec<(bool, u32)> = chord.iter().map(|e| match e {
+            RfbEvent::Key { down, keysym } => (*down, *keysym),
+            other => panic!("{:?}", other),
+        }).collect();
+        assert_eq!(syms, [(true, 0xffe3), (true, 0xffe1), (true, 0xffc2), (false, 0xffc2), (false, 0xffe1), (false, 0xffe3)]);
+
+        let typed = input_events(&action(ActionType::Type { text: "é€\n".to_string() }, 0, 0), (0, 0)).unwrap();
+        assert_eq!(typed[0], RfbEvent::Key { down: true, keysym: 0xe9 });
+        assert_eq!(typed[2], RfbEvent::Key { down: true, keysym: 0x0100_20ac });
+        assert_eq!(typed[4], RfbEvent::Key { down: true, keysym: 0xff0d });
+
+        // Scrolling happens wherever the pointer was left
+        let scroll = input_events(&action(ActionType::Scroll { direction: ScrollDirection::Down, amount: 2 }, 0, 0), (7, 9)).unwrap();
+        assert_eq!(scroll.len(), 4);
+        assert_eq!(scroll[0], RfbEvent::Pointer { mask: 16, x: 7, y: 9 });
+
+        assert_eq!((keysym("numpad3"), keysym("PageDown"), keysym(";"), keysym("q")), (Some(0xffb3), Some(0xff56), Some(0x3b), Some(0x71)));
+        assert!(matches!(input_events(&action(ActionType::KeyDown { key: "hyper".to_string() }, 0, 0), (0, 0)), Err(InputError::InvalidKey(_))));
+    }
+
+    /// A one-client RFB 3.8 server with a 4x2 desktop whose left half is
+    /// red; returns the client's input messages once it disconnects
+    fn serve(listener: TcpListener, security: &'static [u8]) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"RFB 003.008\n").unwrap();
+            let mut version = [0u8; 12];
+            stream.read_exact(&mut version).unwrap();
+            assert_eq!(&version, b"RFB 003.008\n");
+            stream.write_all(&[security.len() as u8]).unwrap();
+            stream.write_all(security).unwrap();
+            match read_u8(&mut stream) {
+                Ok(SECURITY_NONE) => stream.write_all(&0u32.to_be_bytes()).unwrap(),
+                // Challenge 00..0f; the answer is only right for "secret1"
+                Ok(SECURITY_VNC_AUTH) => {
+                    let challenge: Vec<u8> = (0..16).collect();
+                    let mut response = [0u8; 16];
+                    if stream.write_all(&challenge).and_then(|_| stream.read_exact(&mut response)).is_err() {
+                        return Vec::new();
+                    }
+                    if response[..] != [0xbf, 0xa0, 0x7a, 0x37, 0x7a, 0x81, 0x9a, 0x0b, 0xc3, 0xc9, 0xfb, 0xb1, 0x3c, 0x98, 0x69, 0xcd] {
+                        let mut failure = 1u32.to_be_bytes().to_vec();
+                        failure.extend_from_slice(&14u32.to_be_bytes());
+                        failure.extend_from_slice(b"wrong password");
+                        stream.write_all(&failure).unwrap();
+                        return Vec::new();
+                    }
+                    stream.write_all(&0u32.to_be_bytes()).unwrap();
+                }
+                other => panic!("unexpected security type {:?}", other),
+            }
+            assert_eq!(read_u8(&mut stream).unwrap(), 1, "shared");
+            let mut init = vec![0, 4, 0, 2];
+            init.extend_from_slice(&[0u8; 16]);
+            init.extend_from_slice(&2u32.to_be_bytes());
+            init.extend_from_slice(b"vm");
+            stream.write_all(&init).unwrap();
+
+            let mut setup = [0u8; 20 + 12];
+            stream.read_exact(&mut setup).unwrap();
+            assert_eq!((setup[0], setup[4], setup[20]), (0, 32, 2));
+            // Every frame request gets a bell, then the whole desktop in
+            // one raw rectangle; input messages are collected
+            let mut input = Vec::new();
+            while let Ok(kind) = read_u8(&mut stream) {
+                let mut message = vec![0u8; if kind == 4 { 7 } else if kind == 5 { 5 } else { 9 }];
+                stream.read_exact(&mut message).unwrap();
+                if kind != 3 {
+                    input.push(kind);
+                    input.extend(message);
+                    continue;
+                }
+                let mut update = vec![2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 2, 0, 0, 0, 0];
+                for _ in 0..2 {
+                    update.extend_from_slice(&[0, 0, 255, 0, 0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 0]);
+                }
+                stream.write_all(&update).unwrap();
+            }
+            input
+        })
+    }
+
+    fn config(listener: &TcpListener) -> VncConfig {
+        VncConfig {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_frames_and_input_go_over_rfb() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = VncClient::new(config(&listener));
+        let server = serve(listener, &[SECURITY_VNC_AUTH, SECURITY_NONE]);
+
+        let frame = client.frame_source().lock().unwrap().capture().unwrap();
+        assert_eq!((frame.width, frame.height), (4, 2));
+        assert_eq!(frame.get_pixel(1, 1), Some(&[255, 0, 0][..]));
+        assert_eq!(frame.get_pixel(2, 0), Some(&[0, 0, 255][..]));
+        assert_eq!(client.desktop_name().as_deref(), Some("vm"));
+        assert_eq!(client.display_probe().displays().unwrap()[0].width, 4);
+
+        let mut sink = client.input_sink();
+        sink.send(&action(ActionType::Click { button: MouseButton::Left, modifiers: Vec::new() }, 3, 1)).unwrap();
+        sink.send(&action(ActionType::Key { key: "enter".to_string() }, 0, 0)).unwrap();
+        drop(sink);
+        drop(client);
+        let input = server.join().unwrap();
+        assert_eq!(&input[..12], [5, 0, 0, 3, 0, 1, 5, 1, 0, 3, 0, 1]);
+        assert_eq!(&input[18..], [4, 1, 0, 0, 0, 0, 0xff, 0x0d, 4, 0, 0, 0, 0, 0, 0xff, 0x0d]);
+    }
+
+    #[test]
+    fn test_vnc_auth_encrypts_the_challenge_under_the_password() {
+        let challenge: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let response = vnc_auth_response("secret1", &challenge);
+        assert_eq!(response[..8], [0xbf, 0xa0, 0x7a, 0x37, 0x7a, 0x81, 0x9a, 0x0b]);
+        // Only the first 8 characters count
+        assert_eq!(vnc_auth_response("secret1\0-and-more", &challenge), response);
+        assert_ne!(vnc_auth_response("secret2", &challenge), response);
+    }
+
+    #[test]
+    fn test_password_servers_need_the_password() {
+        // The password comes from a file here, since LUNA_VNC_PASSWORD is
+        // process-wide and other tests run alongside
+        let file = std::env::temp_dir().join(format!("luna-vnc-password-{}", std::process::id()));
+        let connect = |password: Option<&str>| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut config = config(&listener);
+            if let Some(password) = password {
+                std::fs::write(&file, format!("{}\n", password)).unwrap();
+                config.password_file = Some(file.clone());
+            }
+            let server = serve(listener, &[SECURITY_VNC_AUTH]);
+            let frame = VncClient::new(config).frame_source().lock().unwrap().capture();
+            server.join().unwrap();
+            frame
+        };
+
+        let frame = connect(Some("secret1")).unwrap();
+        assert_eq!((frame.width, frame.height), (4, 2));
+        let err = connect(Some("secret2")).unwrap_err();
+        assert!(err.to_string().contains("wrong password"), "{}", err);
+        if std::env::var_os(PASSWORD_ENV).is_none() {
+            let err = connect(None).unwrap_err();
+            assert!(err.to_string().contains("LUNA_VNC_PASSWORD"), "{}", err);
+        }
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_luna_drives_the_remote_desktop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = crate::core::LunaConfig::default();
+        config.vnc = self::config(&listener);
+        config.escalation.enabled = false;
+        let server = serve(listener, &[SECURITY_NONE]);
+
+        let mut luna = crate::core::Luna::new(config).unwrap();
+        assert_eq!(luna.capabilities().capture_backend, "vnc");
+        luna.execute_command("click at 3, 1", &crate::core::ExecuteOptions::default()).unwrap();
+        assert_eq!(luna.displays().map(|d| (d[0].width, d[0].height)), Some((4, 2)));
+        drop(luna);
+
+        let input = server.join().unwrap();
+        assert!(input.windows(6).any(|m| m == [5, 1, 0, 3, 0, 1]), "{:?}", input);
+    }
+}
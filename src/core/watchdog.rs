@@ -0,0 +1,368 @@
+/*!
+ * Luna Watchdog - Noticing and recovering from hung subsystems
+ *
+ * A capture call or model inference that never returns used to freeze LUNA
+ * without a word. Now screen capture, inference and input injection mark
+ * themselves busy in the shared `Heartbeats` while they work, and may beat
+ * while a long operation makes progress. A `Watchdog` thread looks at them
+ * every `watchdog.check_interval_ms`. An operation busy past its stall
+ * threshold without a beat is reported (`LunaEvent::Watchdog` and
+ * `luna_watchdog_stalls_total`). The cancel token it registered, if any, is
+ * cancelled, and the subsystem is marked for re-initialization. The Luna
+ * thread rebuilds it before its next use; continuous capture replaces a
+ * hung capture thread at once.
+ *
+ * A subsystem that stalls `degrade_after` times without `recover_after`
+ * healthy runs in between goes into degraded mode. Readiness then fails and
+ * idle-time pre-analysis stops, so an orchestrator routes work elsewhere.
+ * It leaves degraded mode after `recover_after` healthy runs in a row.
+ */
+
+use log::warn;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::config::WatchdogConfig;
+use super::handle::CancelToken;
+
+/// A part of LUNA that can hang
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    Capture,
+    Inference,
+    Input,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 3] = [Subsystem::Capture, Subsystem::Inference, Subsystem::Input];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Subsystem::Capture => "capture",
+            Subsystem::Inference => "inference",
+            Subsystem::Input => "input",
+        }
+    }
+
+    fn stall_threshold(self, config: &WatchdogConfig) -> Duration {
+        Duration::from_millis(match self {
+            Subsystem::Capture => config.capture_stall_ms,
+            Subsystem::Inference => config.inference_stall_ms,
+            Subsystem::Input => config.input_stall_ms,
+        })
+    }
+}
+
+/// What the watchdog found
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum WatchdogEvent {
+    /// Busy past its threshold without a beat; re-initialized before its next use
+    Stalled { subsystem: Subsystem, stalled_ms: u64 },
+    /// Stalled `degrade_after` times without enough healthy runs in between
+    Degraded { subsystem: Subsystem, stalls: u32 },
+    /// The stalled operation returned after all
+    Recovered { subsystem: Subsystem, hung_ms: u64 },
+    /// Out of degraded mode after `recover_after` healthy runs in a row
+    Restored { subsystem: Subsystem },
+}
+
+impl WatchdogEvent {
+    pub fn subsystem(&self) -> Subsystem {
+        match self {
+            WatchdogEvent::Stalled { subsystem, .. }
+            | WatchdogEvent::Degraded { subsystem, .. }
+            | WatchdogEvent::Recovered { subsystem, .. }
+            | WatchdogEvent::Restored { subsystem } => *subsystem,
+        }
+    }
+
+    /// "stalled", "degraded", "recovered" or "restored"
+    pub fn state(&self) -> &'static str {
+        match self {
+            WatchdogEvent::Stalled { .. } => "stalled",
+            WatchdogEvent::Degraded { .. } => "degraded",
+            WatchdogEvent::Recovered { .. } => "recovered",
+            WatchdogEvent::Restored { .. } => "restored",
+        }
+    }
+}
+
+impl fmt::Display for WatchdogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchdogEvent::Stalled { subsystem, stalled_ms } => {
+                write!(f, "{} has not responded for {}ms; re-initializing it", subsystem.as_str(), stalled_ms)
+            }
+            WatchdogEvent::Degraded { subsystem, stalls } => {
+                write!(f, "{} stalled {} times; running degraded", subsystem.as_str(), stalls)
+            }
+            WatchdogEvent::Recovered { subsystem, hung_ms } => {
+                write!(f, "stalled {} returned after {}ms", subsystem.as_str(), hung_ms)
+            }
+            WatchdogEvent::Restored { subsystem } => write!(f, "{} is healthy again", subsystem.as_str()),
+        }
+    }
+}
+
+/// One subsystem's heartbeat
+#[derive(Default)]
+struct Pulse {
+    /// Operations running, on any thread
+    active: u32,
+    busy_since: Option<Instant>,
+    last_beat: Option<Instant>,
+    cancel: Option<CancelToken>,
+    /// The running operation was reported stalled
+    stalled: bool,
+    stalls: u32,
+    healthy_runs: u32,
+    degraded: bool,
+    reset_pending: bool,
+}
+
+#[derive(Default)]
+struct State {
+    config: WatchdogConfig,
+    pulses: [Pulse; 3],
+    /// Found when an operation ended, reported with the next check
+    events: Vec<WatchdogEvent>,
+}
+
+/// Busy and beat state of every subsystem, shared with the watchdog thread
+#[derive(Clone, Default)]
+pub struct Heartbeats(Arc<Mutex<State>>);
+
+impl Heartbeats {
+    /// Use `config`'s thresholds from the next check on
+    pub fn configure(&self, config: &WatchdogConfig) {
+        self.lock().config = config.clone();
+    }
+
+    /// Mark `subsystem` busy until the returned guard drops
+    pub fn busy(&self, subsystem: Subsystem) -> Busy {
+        self.start(subsystem, None)
+    }
+
+    /// Like `busy`, with `cancel` cancelled if the operation stalls
+    pub fn busy_cancellable(&self, subsystem: Subsystem, cancel: CancelToken) -> Busy {
+        self.start(subsystem, Some(cancel))
+    }
+
+    /// The running operation of `subsystem` is making progress
+    pub fn beat(&self, subsystem: Subsystem) {
+        self.lock().pulses[subsystem as usize].last_beat = Some(Instant::now());
+    }
+
+    /// Whether `subsystem` stalled since this was last asked, and should be
+    /// re-initialized before its next use
+    pub fn take_reset(&self, subsystem: Subsystem) -> bool {
+        std::mem::take(&mut self.lock().pulses[subsystem as usize].reset_pending)
+    }
+
+    pub fn is_degraded(&self, subsystem: Subsystem) -> bool {
+        self.lock().pulses[subsystem as usize].degraded
+    }
+
+    /// Subsystems in degraded mode
+    pub fn degraded(&self) -> Vec<Subsystem> {
+        Subsystem::ALL.into_iter().filter(|&s| self.is_degraded(s)).collect()
+    }
+
+    /// Report operations busy past their threshold at `now`, cancelling
+    /// and flagging them for re-initialization, along with what happened
+    /// since the last check
+    pub fn check(&self, now: Instant) -> Vec<WatchdogEvent> {
+        let mut state = self.lock();
+        let mut events = std::mem::take(&mut state.events);
+        let config = state.config.clone();
+        for subsystem in Subsystem::ALL {
+            let pulse = &mut state.pulses[subsystem as usize];
+            let Some(since) = pulse.last_beat.or(pulse.busy_since) else {
+                continue;
+            };
+            let quiet = now.saturating_duration_since(since);
+            if pulse.stalled || quiet < subsystem.stall_threshold(&config) {
+                continue;
+            }
+            pulse.stalled = true;
+            pulse.stalls += 1;
+            pulse.healthy_runs = 0;
+            pulse.reset_pending = true;
+            if let Some(cancel) = &pulse.cancel {
+                cancel.cancel();
+            }
+            events.push(WatchdogEvent::Stalled { subsystem, stalled_ms: quiet.as_millis() as u64 });
+            if !pulse.degraded && pulse.stalls >= config.degrade_after {
+                pulse.degraded = true;
+                events.push(WatchdogEvent::Degraded { subsystem, stalls: pulse.stalls });
+            }
+        }
+        events
+    }
+
+    fn start(&self, subsystem: Subsystem, cancel: Option<CancelToken>) -> Busy {
+        let started = Instant::now();
+        let mut state = self.lock();
+        let pulse = &mut state.pulses[subsystem as usize];
+        pulse.active += 1;
+        pulse.busy_since.get_or_insert(started);
+        if cancel.is_some() {
+            pulse.cancel = cancel;
+        }
+        Busy { heartbeats: self.clone(), subsystem, started }
+    }
+
+    fn finish(&self, subsystem: Subsystem, started: Instant) {
+        let mut state = self.lock();
+        let recover_after = state.config.recover_after;
+        let pulse = &mut state.pulses[subsystem as usize];
+        pulse.active = pulse.active.saturating_sub(1);
+        let mut event = None;
+        if pulse.stalled {
+            pulse.stalled = false;
+            event = Some(WatchdogEvent::Recovered { subsystem, hung_ms: started.elapsed().as_millis() as u64 });
+        } else {
+            pulse.healthy_runs += 1;
+            if pulse.healthy_runs >= recover_after {
+                pulse.stalls = 0;
+                if std::mem::take(&mut pulse.degraded) {
+                    event = Some(WatchdogEvent::Restored { subsystem });
+                }
+            }
+        }
+        // Operations still running on other threads are timed from here
+        pulse.busy_since = (pulse.active > 0).then(Instant::now);
+        pulse.last_beat = None;
+        if pulse.active == 0 {
+            pulse.cancel = None;
+        }
+        state.events.extend(event);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// A subsystem operation in progress; ends when dropped
+pub struct Busy {
+    heartbeats: Heartbeats,
+    subsystem: Subsystem,
+    started: Instant,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.heartbeats.finish(self.subsystem, self.started);
+    }
+}
+
+/// Thread checking the heartbeats; stops when dropped
+pub struct Watchdog {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Check `heartbeats` with `config`'s thresholds on a thread of its own,
+    /// handing each finding to `report`; `None` when disabled
+    pub fn start(config: &WatchdogConfig, heartbeats: Heartbeats, report: impl Fn(WatchdogEvent) + Send + 'static) -> Option<Self> {
+        heartbeats.configure(config);
+        if !config.enabled {
+            return None;
+        }
+        let interval = Duration::from_millis(config.check_interval_ms);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("luna-watchdog".to_string())
+            .spawn({
+                let stop = Arc::clone(&stop);
+                move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        std::thread::park_timeout(interval);
+                        for event in heartbeats.check(Instant::now()) {
+                            report(event);
+                        }
+                    }
+                }
+            });
+        match thread {
+            Ok(thread) => Some(Self { stop, thread: Some(thread) }),
+            Err(e) => {
+                warn!("Watchdog not started: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeats() -> Heartbeats {
+        let heartbeats = Heartbeats::default();
+        heartbeats.configure(&WatchdogConfig { capture_stall_ms: 100, degrade_after: 2, recover_after: 2, ..WatchdogConfig::default() });
+        heartbeats
+    }
+
+    #[test]
+    fn test_stall_is_cancelled_flagged_and_recovered() {
+        let heartbeats = heartbeats();
+        let cancel = CancelToken::new();
+        let busy = heartbeats.busy_cancellable(Subsystem::Capture, cancel.clone());
+        let now = Instant::now();
+        assert!(heartbeats.check(now + Duration::from_millis(50)).is_empty());
+
+        heartbeats.beat(Subsystem::Capture);
+        assert!(heartbeats.check(Instant::now() + Duration::from_millis(50)).is_empty(), "the beat restarted the clock");
+        let events = heartbeats.check(Instant::now() + Duration::from_millis(150));
+        assert!(matches!(events.as_slice(), [WatchdogEvent::Stalled { subsystem: Subsystem::Capture, stalled_ms }] if *stalled_ms >= 150));
+        assert!(cancel.is_cancelled());
+        assert!(heartbeats.check(Instant::now() + Duration::from_secs(1)).is_empty(), "reported once");
+
+        drop(busy);
+        let events = heartbeats.check(Instant::now());
+        assert!(matches!(events.as_slice(), [WatchdogEvent::Recovered { subsystem: Subsystem::Capture, .. }]));
+        assert!(heartbeats.take_reset(Subsystem::Capture));
+        assert!(!heartbeats.take_reset(Subsystem::Capture));
+        assert!(!heartbeats.take_reset(Subsystem::Inference));
+    }
+
+    #[test]
+    fn test_repeated_stalls_degrade_until_healthy_runs() {
+        let heartbeats = heartbeats();
+        let stall = || {
+            let _busy = heartbeats.busy(Subsystem::Capture);
+            heartbeats.check(Instant::now() + Duration::from_millis(200))
+        };
+        stall();
+        assert!(!heartbeats.is_degraded(Subsystem::Capture));
+        let events = stall();
+        assert_eq!(events.last(), Some(&WatchdogEvent::Degraded { subsystem: Subsystem::Capture, stalls: 2 }));
+        assert_eq!(heartbeats.degraded(), vec![Subsystem::Capture]);
+
+        drop(heartbeats.busy(Subsystem::Capture));
+        assert!(heartbeats.is_degraded(Subsystem::Capture), "one healthy run is not enough");
+        drop(heartbeats.busy(Subsystem::Capture));
+        let events = heartbeats.check(Instant::now());
+        assert_eq!(events.last(), Some(&WatchdogEvent::Restored { subsystem: Subsystem::Capture }));
+        assert!(heartbeats.degraded().is_empty());
+    }
+}
@@ -0,0 +1,775 @@
+// Recording of human demonstrations and conversion into editable scripts
+// Raw mouse/keyboard events are segmented into semantic steps (click this
+// element, type this text there, press this chord) using the screen analysis
+// captured when each click started, then rendered as a Rhai script draft.
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use super::{keys, MouseButton, ScrollDirection};
+use crate::core::typing::MASK_CHARACTERS;
+use crate::core::{ElementBounds, ScreenAnalysis, ScreenElement};
+
+/// Presses closer together than this on the same spot count as a multi-click
+const DOUBLE_CLICK_MS: u64 = 400;
+/// Pointer travel between press and release that still counts as a click
+const DRAG_TOLERANCE_PX: i32 = 6;
+/// Scroll ticks this close together are merged into one step
+const SCROLL_MERGE_MS: u64 = 500;
+/// Idle gaps longer than this become explicit waits in the script
+const PAUSE_MS: u64 = 1500;
+/// Words in a field's label or type that mark what is typed there as secret
+const SECRET_FIELD_WORDS: &[&str] = &["password", "passwd", "pwd", "passcode", "passphrase", "pin", "secret"];
+
+/// A raw input event as delivered by a hook
+#[derive(Debug, Clone)]
+pub enum DemoEvent {
+    MouseDown { x: i32, y: i32, button: MouseButton },
+    MouseUp { x: i32, y: i32, button: MouseButton },
+    /// Key press (or auto-repeat); `key` is any name `keys::canonical_key_name` accepts
+    KeyDown { key: String },
+    KeyUp { key: String },
+    Scroll { x: i32, y: i32, direction: ScrollDirection, amount: i32 },
+}
+
+/// An event stamped with its offset from the start of the recording
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    pub at_ms: u64,
+    pub event: DemoEvent,
+}
+
+/// Global source of input events.
+///
+/// Events carry the instant they happened so that slow screen analysis
+/// during recording does not distort timing (and double-click detection).
+pub trait InputHook {
+    fn next_event(&mut self, timeout: Duration) -> Option<(Instant, DemoEvent)>;
+}
+
+/// Hook fed by another part of the program, e.g. a GUI frontend or a
+/// platform hook callback, through `DemoSender`
+pub struct ChannelHook {
+    receiver: mpsc::Receiver<(Instant, DemoEvent)>,
+}
+
+/// Sending half of a `ChannelHook`
+#[derive(Clone)]
+pub struct DemoSender(mpsc::Sender<(Instant, DemoEvent)>);
+
+impl DemoSender {
+    /// Deliver an event that happened just now; false once the hook is gone
+    pub fn send(&self, event: DemoEvent) -> bool {
+        self.0.send((Instant::now(), event)).is_ok()
+    }
+}
+
+impl ChannelHook {
+    pub fn new() -> (Self, DemoSender) {
+        let (sender, receiver) = mpsc::channel();
+        (Self { receiver }, DemoSender(sender))
+    }
+}
+
+impl InputHook for ChannelHook {
+    fn next_event(&mut self, timeout: Duration) -> Option<(Instant, DemoEvent)> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+/// Global hook on Windows: low-level keyboard and mouse hooks feeding a `ChannelHook`
+#[cfg(target_os = "windows")]
+pub struct WindowsHook {
+    hook: ChannelHook,
+    /// Where the hook callbacks deliver their events
+    _sender: DemoSender,
+}
+
+#[cfg(target_os = "windows")]
+impl WindowsHook {
+    pub fn open() -> anyhow::Result<Self> {
+        // Minimal Windows API implementation
+        // In real implementation, would install WH_KEYBOARD_LL and WH_MOUSE_LL with
+        // SetWindowsHookExW on a thread running a message loop, and translate each
+        // KBDLLHOOKSTRUCT/MSLLHOOKSTRUCT into a DemoEvent sent through the DemoSender
+        log::info!("Windows input hook installed");
+        let (hook, sender) = ChannelHook::new();
+        Ok(Self { hook, _sender: sender })
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl InputHook for WindowsHook {
+    fn next_event(&mut self, timeout: Duration) -> Option<(Instant, DemoEvent)> {
+        self.hook.next_event(timeout)
+    }
+}
+
+/// The platform's global input hook for recording.
+///
+/// On Linux this reads evdev devices and sees every keystroke on the machine,
+/// so it is only available when built with the `evdev-hook` feature.
+#[cfg(target_os = "windows")]
+pub fn global_hook(_screen_size: (u32, u32)) -> anyhow::Result<Box<dyn InputHook>> {
+    Ok(Box::new(WindowsHook::open()?))
+}
+
+#[cfg(all(target_os = "linux", feature = "evdev-hook"))]
+pub fn global_hook(screen_size: (u32, u32)) -> anyhow::Result<Box<dyn InputHook>> {
+    Ok(Box::new(super::evdev::EvdevHook::open(screen_size)?))
+}
+
+#[cfg(not(any(target_os = "windows", all(target_os = "linux", feature = "evdev-hook"))))]
+pub fn global_hook(_screen_size: (u32, u32)) -> anyhow::Result<Box<dyn InputHook>> {
+    Err(crate::core::LunaError::Input(
+        "recording needs the Windows input hook, or on Linux a build with --features evdev-hook".to_string(),
+    )
+    .into())
+}
+
+/// Options for `DemonstrationRecorder::record_from`
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    /// Key that ends the recording; it is not recorded itself
+    pub stop_key: String,
+    /// Upper bound on the recording length
+    pub max_duration: Duration,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        Self {
+            stop_key: "pause".to_string(),
+            max_duration: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Collects input events and the screen analyses taken while recording
+pub struct DemonstrationRecorder {
+    started: Instant,
+    events: Vec<RecordedEvent>,
+    snapshots: Vec<(u64, ScreenAnalysis)>,
+}
+
+impl Default for DemonstrationRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DemonstrationRecorder {
+    pub fn new() -> Self {
+        Self { started: Instant::now(), events: Vec::new(), snapshots: Vec::new() }
+    }
+
+    /// Record an event that happened at `at_ms` into the recording
+    pub fn record_at(&mut self, at_ms: u64, event: DemoEvent) {
+        self.events.push(RecordedEvent { at_ms, event });
+    }
+
+    /// Record an event that happened at `at`
+    pub fn record(&mut self, at: Instant, event: DemoEvent) {
+        let at_ms = at.saturating_duration_since(self.started).as_millis() as u64;
+        self.record_at(at_ms, event);
+    }
+
+    /// Attach the screen as it looked at `at_ms`; clicks are matched against
+    /// the latest analysis taken at or before them
+    pub fn attach_analysis_at(&mut self, at_ms: u64, analysis: ScreenAnalysis) {
+        self.snapshots.push((at_ms, analysis));
+    }
+
+    /// Read events from `hook` until the stop key or the time limit.
+    ///
+    /// `analyze` is called at the start and on every mouse press, before the
+    /// click has had any effect on screen.
+    pub fn record_from(
+        &mut self,
+        hook: &mut dyn InputHook,
+        options: &RecordOptions,
+        mut analyze: impl FnMut() -> Option<ScreenAnalysis>,
+    ) {
+        let stop_key = keys::canonical_key_name(&options.stop_key);
+        let deadline = self.started + options.max_duration;
+
+        if let Some(analysis) = analyze() {
+            self.attach_analysis_at(0, analysis);
+        }
+
+        while let Some((at, event)) = hook.next_event(deadline.saturating_duration_since(Instant::now())) {
+            if let DemoEvent::KeyDown { key } = &event {
+                if stop_key.is_some() && keys::canonical_key_name(key) == stop_key {
+                    break;
+                }
+            }
+            let at_ms = at.saturating_duration_since(self.started).as_millis() as u64;
+            if matches!(event, DemoEvent::MouseDown { .. }) {
+                if let Some(analysis) = analyze() {
+                    self.attach_analysis_at(at_ms, analysis);
+                }
+            }
+            self.record_at(at_ms, event);
+        }
+    }
+
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// Segment the recording into semantic steps
+    pub fn finish(mut self) -> Demonstration {
+        self.events.sort_by_key(|e| e.at_ms);
+        self.snapshots.sort_by_key(|(at, _)| *at);
+        Demonstration { steps: segment(&self.events, &self.snapshots) }
+    }
+}
+
+/// Element a step acted on, as seen when the step happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepTarget {
+    pub element_type: String,
+    pub text: Option<String>,
+    pub bounds: ElementBounds,
+}
+
+impl StepTarget {
+    fn describe(&self) -> String {
+        match &self.text {
+            Some(text) => format!("{} \"{}\"", self.element_type, text),
+            None => self.element_type.clone(),
+        }
+    }
+
+    /// Password and similar fields, whose contents must never reach a script
+    fn is_secret(&self) -> bool {
+        let label = format!("{} {}", self.element_type, self.text.as_deref().unwrap_or("")).to_lowercase();
+        let named_secret = label
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| SECRET_FIELD_WORDS.contains(&word));
+        let masked = self
+            .text
+            .as_ref()
+            .is_some_and(|text| text.chars().any(|c| MASK_CHARACTERS.contains(&c)));
+        named_secret || masked
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepAction {
+    Click { x: i32, y: i32, button: &'static str, count: u32 },
+    Drag { from: (i32, i32), to: (i32, i32) },
+    Type { text: String },
+    /// Typing into a password field; the text itself is not kept
+    TypeSecret,
+    Keys { chord: String },
+    Scroll { direction: &'static str, amount: i32 },
+    Wait { milliseconds: u64 },
+}
+
+/// One semantic step of a demonstration
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemoStep {
+    pub at_ms: u64,
+    pub action: StepAction,
+    /// Clicked element, or for typing the element clicked before it
+    pub target: Option<StepTarget>,
+}
+
+/// A segmented demonstration
+#[derive(Debug, Clone, Default)]
+pub struct Demonstration {
+    pub steps: Vec<DemoStep>,
+}
+
+impl Demonstration {
+    /// Render the steps as a Rhai script for `ScriptEngine`, with a comment per step.
+    ///
+    /// Clicks on recognised elements look the element up again by its text
+    /// (or type), so the script survives windows moving between runs.
+    pub fn to_script(&self) -> String {
+        let mut script = format!(
+            "// Recorded demonstration ({} steps). Review before running.\n",
+            self.steps.len()
+        );
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let target = step.target.as_ref();
+            let number = index + 1;
+            match &step.action {
+                StepAction::Click { x, y, button, count } => {
+                    let what = target.map(StepTarget::describe).unwrap_or_else(|| format!("({}, {})", x, y));
+                    let clicks = if *count > 1 { format!("{}x ", count) } else { String::new() };
+                    script.push_str(&format!("\n// Step {}: {}{} click {}\n", number, clicks, button, what));
+                    if *button != "left" {
+                        script.push_str("// Only left clicks can be scripted; adjust by hand\n");
+                        continue;
+                    }
+                    let call = match target {
+                        Some(t) => format!("click(find({})[0]);\n", quote(&element_query(t))),
+                        None => format!("click({}, {});\n", x, y),
+                    };
+                    script.push_str(&call.repeat(*count as usize));
+                }
+                StepAction::Drag { from, to } => {
+                    script.push_str(&format!(
+                        "\n// Step {}: drag from ({}, {}) to ({}, {})\n// Dragging cannot be scripted; adjust by hand\n",
+                        number, from.0, from.1, to.0, to.1
+                    ));
+                }
+                StepAction::Type { text } => {
+                    let into = target.map(|t| format!(" into {}", t.describe())).unwrap_or_default();
+                    script.push_str(&format!("\n// Step {}: type{}\ntype_text({});\n", number, into, quote(text)));
+                }
+                StepAction::TypeSecret => {
+                    let into = target.map(|t| format!(" into {}", t.describe())).unwrap_or_default();
+                    script.push_str(&format!(
+                        "\n// Step {}: type a secret{}\n// Secrets are not recorded; fill it in by hand\n// type_text(\"\");\n",
+                        number, into
+                    ));
+                }
+                StepAction::Keys { chord } => {
+                    script.push_str(&format!("\n// Step {}: press {}\nkeys({});\n", number, chord, quote(chord)));
+                }
+                StepAction::Scroll { direction, amount } => {
+                    script.push_str(&format!(
+                        "\n// Step {}: scroll {} {}\nscroll({}, {});\n",
+                        number, direction, amount, quote(direction), amount
+                    ));
+                }
+                StepAction::Wait { milliseconds } => {
+                    script.push_str(&format!("\n// Step {}: pause\nwait({});\n", number, milliseconds));
+                }
+            }
+        }
+        script
+    }
+}
+
+/// Query that `find` in scripts resolves back to this element
+fn element_query(target: &StepTarget) -> String {
+    target
+        .text
+        .as_ref()
+        .filter(|text| !text.trim().is_empty())
+        .cloned()
+        .unwrap_or_else(|| target.element_type.clone())
+}
+
+/// Rhai string literal
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn button_name(button: &MouseButton) -> &'static str {
+    match button {
+        MouseButton::Left => "left",
+        MouseButton::Right => "right",
+        MouseButton::Middle => "middle",
+    }
+}
+
+fn direction_name(direction: &ScrollDirection) -> &'static str {
+    match direction {
+        ScrollDirection::Up => "up",
+        ScrollDirection::Down => "down",
+        ScrollDirection::Left => "left",
+        ScrollDirection::Right => "right",
+    }
+}
+
+/// Generic modifier for a canonical key name ("lctrl" -> "ctrl")
+fn modifier_of(key: &str) -> Option<&'static str> {
+    match key {
+        "ctrl" | "lctrl" | "rctrl" => Some("ctrl"),
+        "alt" | "lalt" | "ralt" => Some("alt"),
+        "shift" | "lshift" | "rshift" => Some("shift"),
+        "win" | "lwin" | "rwin" => Some("win"),
+        _ => None,
+    }
+}
+
+/// Character a key types on a US layout, if any
+fn typed_char(key: &str, shift: bool) -> Option<char> {
+    const SHIFTED: &[(char, char)] = &[
+        ('1', '!'), ('2', '@'), ('3', '#'), ('4', '$'), ('5', '%'), ('6', '^'), ('7', '&'), ('8', '*'),
+        ('9', '('), ('0', ')'), ('-', '_'), ('=', '+'), ('[', '{'), (']', '}'), ('\\', '|'), (';', ':'),
+        ('\'', '"'), (',', '<'), ('.', '>'), ('/', '?'), ('`', '~'),
+    ];
+
+    if key == "space" {
+        return Some(' ');
+    }
+    let mut chars = key.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    if c.is_ascii_alphabetic() {
+        return Some(if shift { c.to_ascii_uppercase() } else { c });
+    }
+    let shifted = SHIFTED.iter().find(|(plain, _)| *plain == c)?;
+    Some(if shift { shifted.1 } else { shifted.0 })
+}
+
+/// The most specific element containing the point in the latest analysis
+/// taken at or before `at_ms`
+fn target_at(snapshots: &[(u64, ScreenAnalysis)], at_ms: u64, x: i32, y: i32) -> Option<StepTarget> {
+    let (_, analysis) = snapshots.iter().rev().find(|(taken, _)| *taken <= at_ms).or(snapshots.first())?;
+    analysis
+        .elements
+        .iter()
+        .filter(|e| e.bounds.contains_point(x, y))
+        .min_by_key(|e| e.bounds.width as i64 * e.bounds.height as i64)
+        .map(|e: &ScreenElement| StepTarget {
+            element_type: e.element_type.clone(),
+            text: e.text.clone(),
+            bounds: e.bounds.clone(),
+        })
+}
+
+/// Turns raw events into steps, tracking held modifiers and in-progress text
+struct Segmenter<'a> {
+    snapshots: &'a [(u64, ScreenAnalysis)],
+    steps: Vec<DemoStep>,
+    /// When the last finished step ended, for inserting waits and multi-clicks
+    last_step_end: Option<u64>,
+    modifiers: Vec<&'static str>,
+    /// Text being typed: start, last keystroke, text so far
+    text: Option<(u64, u64, String)>,
+    /// Scrolling in progress: start, last tick, direction, total amount
+    scroll: Option<(u64, u64, &'static str, i32)>,
+    /// Mouse button held down: when, where, which and what it landed on
+    press: Option<(u64, i32, i32, &'static str, Option<StepTarget>)>,
+    /// Element most recently clicked, where typed text goes
+    focus: Option<StepTarget>,
+}
+
+impl Segmenter<'_> {
+    fn push(&mut self, at_ms: u64, end_ms: u64, action: StepAction, target: Option<StepTarget>) {
+        if let Some(last) = self.last_step_end {
+            let gap = at_ms.saturating_sub(last);
+            if gap > PAUSE_MS {
+                let milliseconds = gap / 100 * 100;
+                self.steps.push(DemoStep { at_ms: last, action: StepAction::Wait { milliseconds }, target: None });
+            }
+        }
+        self.steps.push(DemoStep { at_ms, action, target });
+        self.last_step_end = Some(end_ms);
+    }
+
+    fn flush_text(&mut self) {
+        if let Some((start, end, text)) = self.text.take() {
+            if !text.is_empty() {
+                let target = self.focus.clone();
+                let action = match &target {
+                    Some(field) if field.is_secret() => StepAction::TypeSecret,
+                    _ => StepAction::Type { text },
+                };
+                self.push(start, end, action, target);
+            }
+        }
+    }
+
+    fn flush_scroll(&mut self) {
+        if let Some((start, end, direction, amount)) = self.scroll.take() {
+            self.push(start, end, StepAction::Scroll { direction, amount }, None);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.flush_text();
+        self.flush_scroll();
+    }
+
+    /// Count a click as another press of the previous click if it came quickly on the same spot
+    fn extend_click(&mut self, down_ms: u64, up_ms: u64, x: i32, y: i32, button: &str) -> bool {
+        let quick = self.last_step_end.is_some_and(|last| down_ms.saturating_sub(last) <= DOUBLE_CLICK_MS);
+        match self.steps.last_mut() {
+            Some(DemoStep { action: StepAction::Click { x: px, y: py, button: pb, count }, .. })
+                if quick
+                    && *pb == button
+                    && (x - *px).abs() <= DRAG_TOLERANCE_PX
+                    && (y - *py).abs() <= DRAG_TOLERANCE_PX =>
+            {
+                *count += 1;
+                self.last_step_end = Some(up_ms);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn handle(&mut self, recorded: &RecordedEvent) {
+        let at_ms = recorded.at_ms;
+        match &recorded.event {
+            DemoEvent::MouseDown { x, y, button } => {
+                self.flush();
+                let target = target_at(self.snapshots, at_ms, *x, *y);
+                self.press = Some((at_ms, *x, *y, button_name(button), target));
+            }
+            DemoEvent::MouseUp { x, y, button } => {
+                let Some((down_ms, down_x, down_y, pressed, target)) = self.press.take() else { return };
+                if pressed != button_name(button) {
+                    return;
+                }
+                if (x - down_x).abs() > DRAG_TOLERANCE_PX || (y - down_y).abs() > DRAG_TOLERANCE_PX {
+                    self.push(down_ms, at_ms, StepAction::Drag { from: (down_x, down_y), to: (*x, *y) }, target);
+                    return;
+                }
+                if !self.extend_click(down_ms, at_ms, down_x, down_y, pressed) {
+                    self.focus = target.clone();
+                    self.push(down_ms, at_ms, StepAction::Click { x: down_x, y: down_y, button: pressed, count: 1 }, target);
+                }
+            }
+            DemoEvent::KeyDown { key } => {
+                let Some(key) = keys::canonical_key_name(key) else { return };
+                if let Some(modifier) = modifier_of(key) {
+                    if !self.modifiers.contains(&modifier) {
+                        self.modifiers.push(modifier);
+                    }
+                    return;
+                }
+
+                let shift = self.modifiers.contains(&"shift");
+                let shortcut = self.modifiers.iter().any(|m| *m != "shift");
+                if !shortcut {
+                    if let Some(c) = typed_char(key, shift) {
+                        self.flush_scroll();
+                        let (_, end, text) = self.text.get_or_insert_with(|| (at_ms, at_ms, String::new()));
+                        *end = at_ms;
+                        text.push(c);
+                        return;
+                    }
+                    if let Some((_, end, text)) = self.text.as_mut().filter(|(_, _, text)| !text.is_empty()) {
+                        if key == "backspace" {
+                            *end = at_ms;
+                            text.pop();
+                            return;
+                        }
+                    }
+                }
+
+                self.flush();
+                let mut chord: Vec<&str> = ["ctrl", "alt", "shift", "win"]
+                    .into_iter()
+                    .filter(|m| self.modifiers.contains(m))
+                    .collect();
+                chord.push(key);
+                self.push(at_ms, at_ms, StepAction::Keys { chord: chord.join("+") }, None);
+            }
+            DemoEvent::KeyUp { key } => {
+                if let Some(modifier) = keys::canonical_key_name(key).and_then(modifier_of) {
+                    self.modifiers.retain(|m| *m != modifier);
+                }
+            }
+            DemoEvent::Scroll { direction, amount, .. } => {
+                self.flush_text();
+                let direction = direction_name(direction);
+                match &mut self.scroll {
+                    Some((_, end, current, total))
+                        if *current == direction && at_ms.saturating_sub(*end) <= SCROLL_MERGE_MS =>
+                    {
+                        *end = at_ms;
+                        *total += amount;
+                    }
+                    _ => {
+                        self.flush_scroll();
+                        self.scroll = Some((at_ms, at_ms, direction, *amount));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn segment(events: &[RecordedEvent], snapshots: &[(u64, ScreenAnalysis)]) -> Vec<DemoStep> {
+    let mut segmenter = Segmenter {
+        snapshots,
+        steps: Vec::new(),
+        last_step_end: None,
+        modifiers: Vec::new(),
+        text: None,
+        scroll: None,
+        press: None,
+        focus: None,
+    };
+    for event in events {
+        segmenter.handle(event);
+    }
+    segmenter.flush();
+    segmenter.steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn element(element_type: &str, text: &str, x: i32, y: i32, width: i32, height: i32) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds: ElementBounds::new(x, y, width, height),
+            shape: None,
+            confidence: 0.9,
+            text: Some(text.to_string()),
+            attributes: HashMap::new(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn form() -> ScreenAnalysis {
+        ScreenAnalysis {
+            elements: vec![
+                element("window", "Settings", 0, 0, 800, 600),
+                element("text_input", "Name", 100, 100, 200, 30),
+                element("button", "Save", 100, 200, 80, 30),
+            ],
+            confidence: 0.9,
+            processing_time_ms: 5,
+            screen_size: (800, 600),
+            context: Default::default(),
+        }
+    }
+
+    fn key(recorder: &mut DemonstrationRecorder, at_ms: u64, name: &str) {
+        recorder.record_at(at_ms, DemoEvent::KeyDown { key: name.to_string() });
+        recorder.record_at(at_ms + 5, DemoEvent::KeyUp { key: name.to_string() });
+    }
+
+    fn click(recorder: &mut DemonstrationRecorder, at_ms: u64, x: i32, y: i32) {
+        recorder.record_at(at_ms, DemoEvent::MouseDown { x, y, button: MouseButton::Left });
+        recorder.record_at(at_ms + 50, DemoEvent::MouseUp { x, y, button: MouseButton::Left });
+    }
+
+    #[test]
+    fn test_segments_click_type_and_shortcut() {
+        let mut recorder = DemonstrationRecorder::new();
+        recorder.attach_analysis_at(0, form());
+
+        click(&mut recorder, 100, 150, 110);
+        recorder.record_at(300, DemoEvent::KeyDown { key: "shift".to_string() });
+        key(&mut recorder, 310, "a");
+        recorder.record_at(320, DemoEvent::KeyUp { key: "shift".to_string() });
+        key(&mut recorder, 400, "d");
+        key(&mut recorder, 450, "x");
+        key(&mut recorder, 500, "backspace");
+        key(&mut recorder, 550, "a");
+        recorder.record_at(700, DemoEvent::KeyDown { key: "lctrl".to_string() });
+        key(&mut recorder, 710, "s");
+        recorder.record_at(720, DemoEvent::KeyUp { key: "lctrl".to_string() });
+        click(&mut recorder, 5000, 120, 210);
+
+        let steps = recorder.finish().steps;
+        let actions: Vec<_> = steps.iter().map(|s| s.action.clone()).collect();
+        assert_eq!(
+            actions,
+            vec![
+                StepAction::Click { x: 150, y: 110, button: "left", count: 1 },
+                StepAction::Type { text: "Ada".to_string() },
+                StepAction::Keys { chord: "ctrl+s".to_string() },
+                StepAction::Wait { milliseconds: 4200 },
+                StepAction::Click { x: 120, y: 210, button: "left", count: 1 },
+            ]
+        );
+        // Typing is attributed to the field clicked before it
+        assert_eq!(steps[1].target.as_ref().unwrap().text.as_deref(), Some("Name"));
+        assert_eq!(steps[4].target.as_ref().unwrap().element_type, "button");
+    }
+
+    #[test]
+    fn test_double_click_drag_and_scroll() {
+        let mut recorder = DemonstrationRecorder::new();
+        click(&mut recorder, 0, 10, 10);
+        click(&mut recorder, 150, 11, 10);
+        recorder.record_at(1000, DemoEvent::MouseDown { x: 10, y: 10, button: MouseButton::Left });
+        recorder.record_at(1200, DemoEvent::MouseUp { x: 200, y: 80, button: MouseButton::Left });
+        for at in [1300, 1400, 1500] {
+            recorder.record_at(at, DemoEvent::Scroll { x: 0, y: 0, direction: ScrollDirection::Down, amount: 1 });
+        }
+
+        let actions: Vec<_> = recorder.finish().steps.into_iter().map(|s| s.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                StepAction::Click { x: 10, y: 10, button: "left", count: 2 },
+                StepAction::Drag { from: (10, 10), to: (200, 80) },
+                StepAction::Scroll { direction: "down", amount: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_draft() {
+        let mut recorder = DemonstrationRecorder::new();
+        recorder.attach_analysis_at(0, form());
+        click(&mut recorder, 100, 150, 110);
+        key(&mut recorder, 200, "'");
+        recorder.record_at(300, DemoEvent::KeyDown { key: "shift".to_string() });
+        key(&mut recorder, 310, "'");
+        recorder.record_at(320, DemoEvent::KeyUp { key: "shift".to_string() });
+        click(&mut recorder, 600, 700, 500);
+        key(&mut recorder, 800, "enter");
+
+        let script = recorder.finish().to_script();
+        assert!(script.contains("click(find(\"Name\")[0]);"));
+        assert!(script.contains("type_text(\"'\\\"\");"));
+        // The window itself has text, so the click is replayed against it
+        assert!(script.contains("click(find(\"Settings\")[0]);"));
+        assert!(script.contains("keys(\"enter\");"));
+    }
+
+    #[test]
+    fn test_password_fields_are_redacted() {
+        let mut recorder = DemonstrationRecorder::new();
+        let mut analysis = form();
+        analysis.elements.push(element("text_input", "Password", 100, 300, 200, 30));
+        recorder.attach_analysis_at(0, analysis);
+        click(&mut recorder, 100, 150, 310);
+        for (at, name) in [(200, "h"), (250, "u"), (300, "n"), (350, "t"), (400, "e"), (450, "r"), (500, "2")] {
+            key(&mut recorder, at, name);
+        }
+        click(&mut recorder, 700, 150, 110);
+        key(&mut recorder, 800, "a");
+
+        let demonstration = recorder.finish();
+        assert_eq!(demonstration.steps[1].action, StepAction::TypeSecret);
+        assert_eq!(demonstration.steps[3].action, StepAction::Type { text: "a".to_string() });
+        let script = demonstration.to_script();
+        assert!(!script.contains("hunter2"));
+        assert!(script.contains("// type_text(\"\");"));
+    }
+
+    #[test]
+    fn test_record_from_hook_stops_on_stop_key() {
+        let (mut hook, sender) = ChannelHook::new();
+        sender.send(DemoEvent::MouseDown { x: 120, y: 210, button: MouseButton::Left });
+        sender.send(DemoEvent::MouseUp { x: 120, y: 210, button: MouseButton::Left });
+        sender.send(DemoEvent::KeyDown { key: "Pause".to_string() });
+        sender.send(DemoEvent::KeyDown { key: "a".to_string() });
+
+        let mut analyses = 0;
+        let mut recorder = DemonstrationRecorder::new();
+        recorder.record_from(&mut hook, &RecordOptions::default(), || {
+            analyses += 1;
+            Some(form())
+        });
+
+        assert_eq!(analyses, 2);
+        assert_eq!(recorder.events().len(), 2);
+        let steps = recorder.finish().steps;
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].target.as_ref().unwrap().text.as_deref(), Some("Save"));
+    }
+}
@@ -0,0 +1,190 @@
+// Global input hook reading Linux evdev devices, for recording demonstrations
+// Only built with the `evdev-hook` feature: it sees every keystroke on the
+// machine, in every application, so it has to be asked for explicitly.
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use super::demonstration::{DemoEvent, InputHook};
+use super::{MouseButton, ScrollDirection};
+use crate::core::LunaError;
+
+/// Global hook reading Linux evdev devices (`/dev/input/event*`).
+///
+/// Sees input for every application, under X11 and Wayland alike, but needs
+/// read access to the devices (usually membership of the `input` group).
+/// Mice only report relative motion, so the pointer position is tracked from
+/// a known starting point and clamped to the screen; call `set_cursor` to
+/// resynchronise it.
+pub struct EvdevHook {
+    receiver: mpsc::Receiver<(Instant, u16, u16, i32)>,
+    cursor: (i32, i32),
+    screen_size: (u32, u32),
+}
+
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const REL_X: u16 = 0;
+const REL_Y: u16 = 1;
+const REL_HWHEEL: u16 = 6;
+const REL_WHEEL: u16 = 8;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+
+impl EvdevHook {
+    /// Open every readable input device; the cursor starts at the screen center
+    pub fn open(screen_size: (u32, u32)) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let mut opened = 0;
+
+        for entry in std::fs::read_dir("/dev/input")?.flatten() {
+            let path = entry.path();
+            if !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("event")) {
+                continue;
+            }
+            let Ok(mut device) = File::open(&path) else { continue };
+            let sender = sender.clone();
+            std::thread::Builder::new()
+                .name(format!("luna-evdev-{}", path.display()))
+                .spawn(move || {
+                    // struct input_event: timeval, u16 type, u16 code, i32 value
+                    let timeval_len = 2 * std::mem::size_of::<usize>();
+                    let mut record = vec![0u8; timeval_len + 8];
+                    while device.read_exact(&mut record).is_ok() {
+                        let raw = &record[timeval_len..];
+                        let kind = u16::from_ne_bytes([raw[0], raw[1]]);
+                        let code = u16::from_ne_bytes([raw[2], raw[3]]);
+                        let value = i32::from_ne_bytes([raw[4], raw[5], raw[6], raw[7]]);
+                        if sender.send((Instant::now(), kind, code, value)).is_err() {
+                            break;
+                        }
+                    }
+                })?;
+            opened += 1;
+        }
+
+        if opened == 0 {
+            return Err(LunaError::PermissionDenied(
+                "no readable /dev/input/event* devices (is the user in the 'input' group?)".to_string(),
+            )
+            .into());
+        }
+        Ok(Self {
+            receiver,
+            cursor: (screen_size.0 as i32 / 2, screen_size.1 as i32 / 2),
+            screen_size,
+        })
+    }
+
+    pub fn set_cursor(&mut self, x: i32, y: i32) {
+        self.cursor = (x, y);
+    }
+
+    fn translate(&mut self, kind: u16, code: u16, value: i32) -> Option<DemoEvent> {
+        let (x, y) = self.cursor;
+        match (kind, code) {
+            (EV_REL, REL_X) => {
+                self.cursor.0 = (x + value).clamp(0, self.screen_size.0 as i32 - 1);
+                None
+            }
+            (EV_REL, REL_Y) => {
+                self.cursor.1 = (y + value).clamp(0, self.screen_size.1 as i32 - 1);
+                None
+            }
+            (EV_REL, REL_WHEEL) | (EV_REL, REL_HWHEEL) if value != 0 => {
+                let direction = match (code, value > 0) {
+                    (REL_WHEEL, true) => ScrollDirection::Up,
+                    (REL_WHEEL, false) => ScrollDirection::Down,
+                    (_, true) => ScrollDirection::Right,
+                    (_, false) => ScrollDirection::Left,
+                };
+                Some(DemoEvent::Scroll { x, y, direction, amount: value.abs() })
+            }
+            (EV_KEY, BTN_LEFT..=BTN_MIDDLE) => {
+                let button = match code {
+                    BTN_LEFT => MouseButton::Left,
+                    BTN_RIGHT => MouseButton::Right,
+                    _ => MouseButton::Middle,
+                };
+                match value {
+                    1 => Some(DemoEvent::MouseDown { x, y, button }),
+                    0 => Some(DemoEvent::MouseUp { x, y, button }),
+                    _ => None,
+                }
+            }
+            (EV_KEY, _) => {
+                let key = evdev_key_name(code)?.to_string();
+                match value {
+                    0 => Some(DemoEvent::KeyUp { key }),
+                    _ => Some(DemoEvent::KeyDown { key }),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl InputHook for EvdevHook {
+    fn next_event(&mut self, timeout: Duration) -> Option<(Instant, DemoEvent)> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (at, kind, code, value) = self.receiver.recv_timeout(remaining).ok()?;
+            if let Some(event) = self.translate(kind, code, value) {
+                return Some((at, event));
+            }
+        }
+    }
+}
+
+/// Key names for the Linux KEY_* codes of a US keyboard
+fn evdev_key_name(code: u16) -> Option<&'static str> {
+    const ROW_1: &[&str] = &["1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "-", "=", "backspace", "tab"];
+    const ROW_Q: &[&str] = &["q", "w", "e", "r", "t", "y", "u", "i", "o", "p", "[", "]", "enter", "lctrl"];
+    const ROW_A: &[&str] = &["a", "s", "d", "f", "g", "h", "j", "k", "l", ";", "'", "`", "lshift", "\\"];
+    const ROW_Z: &[&str] = &["z", "x", "c", "v", "b", "n", "m", ",", ".", "/", "rshift"];
+    const FUNCTION: &[&str] = &["f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10"];
+    const NAVIGATION: &[&str] = &["home", "up", "pageup", "left", "right", "end", "down", "pagedown", "insert", "delete"];
+
+    let name = match code {
+        1 => "escape",
+        2..=15 => ROW_1[(code - 2) as usize],
+        16..=29 => ROW_Q[(code - 16) as usize],
+        30..=43 => ROW_A[(code - 30) as usize],
+        44..=54 => ROW_Z[(code - 44) as usize],
+        56 => "lalt",
+        57 => "space",
+        58 => "capslock",
+        59..=68 => FUNCTION[(code - 59) as usize],
+        87 => "f11",
+        88 => "f12",
+        97 => "rctrl",
+        100 => "ralt",
+        102..=111 => NAVIGATION[(code - 102) as usize],
+        119 => "pause",
+        125 => "lwin",
+        126 => "rwin",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::keys;
+
+    #[test]
+    fn test_evdev_key_names_are_canonical() {
+        for code in 1..=126 {
+            if let Some(name) = evdev_key_name(code) {
+                assert!(keys::canonical_key_name(name).is_some(), "{} ({})", name, code);
+            }
+        }
+        assert_eq!(evdev_key_name(30), Some("a"));
+        assert_eq!(evdev_key_name(28), Some("enter"));
+    }
+}
@@ -0,0 +1,506 @@
+// Key name table and chord parsing for keyboard input
+// Maps human-readable key names to Windows virtual-key codes and set-1 scan codes
+
+/// A physical key: Windows virtual-key code plus its set-1 scan code.
+///
+/// `extended` marks keys that need the E0 prefix (KEYEVENTF_EXTENDEDKEY),
+/// e.g. the arrow cluster, right-hand modifiers and the media keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCode {
+    pub vk: u16,
+    pub scan_code: u16,
+    pub extended: bool,
+}
+
+impl KeyCode {
+    const fn new(vk: u16, scan_code: u16) -> Self {
+        Self { vk, scan_code, extended: false }
+    }
+
+    const fn ext(vk: u16, scan_code: u16) -> Self {
+        Self { vk, scan_code, extended: true }
+    }
+
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self.vk,
+            VK_SHIFT | VK_CONTROL | VK_MENU | VK_LWIN | VK_RWIN
+                | 0xA0..=0xA5 // left/right shift, ctrl, alt
+        )
+    }
+}
+
+const VK_SHIFT: u16 = 0x10;
+const VK_CONTROL: u16 = 0x11;
+const VK_MENU: u16 = 0x12;
+const VK_LWIN: u16 = 0x5B;
+const VK_RWIN: u16 = 0x5C;
+
+/// Canonical key names. Lookups are case-insensitive and ignore spaces,
+/// underscores and dashes in multi-character names ("Page_Up" == "pageup").
+const KEY_TABLE: &[(&str, KeyCode)] = &[
+    // Modifiers
+    ("ctrl", KeyCode::new(VK_CONTROL, 0x1D)),
+    ("shift", KeyCode::new(VK_SHIFT, 0x2A)),
+    ("alt", KeyCode::new(VK_MENU, 0x38)),
+    ("win", KeyCode::ext(VK_LWIN, 0x5B)),
+    ("lctrl", KeyCode::new(0xA2, 0x1D)),
+    ("rctrl", KeyCode::ext(0xA3, 0x1D)),
+    ("lshift", KeyCode::new(0xA0, 0x2A)),
+    ("rshift", KeyCode::new(0xA1, 0x36)),
+    ("lalt", KeyCode::new(0xA4, 0x38)),
+    ("ralt", KeyCode::ext(0xA5, 0x38)),
+    ("lwin", KeyCode::ext(VK_LWIN, 0x5B)),
+    ("rwin", KeyCode::ext(VK_RWIN, 0x5C)),
+    ("apps", KeyCode::ext(0x5D, 0x5D)),
+    // Editing and whitespace
+    ("enter", KeyCode::new(0x0D, 0x1C)),
+    ("escape", KeyCode::new(0x1B, 0x01)),
+    ("backspace", KeyCode::new(0x08, 0x0E)),
+    ("tab", KeyCode::new(0x09, 0x0F)),
+    ("space", KeyCode::new(0x20, 0x39)),
+    ("capslock", KeyCode::new(0x14, 0x3A)),
+    ("numlock", KeyCode::ext(0x90, 0x45)),
+    ("scrolllock", KeyCode::new(0x91, 0x46)),
+    ("pause", KeyCode::new(0x13, 0x45)),
+    ("printscreen", KeyCode::ext(0x2C, 0x37)),
+    // Navigation cluster
+    ("insert", KeyCode::ext(0x2D, 0x52)),
+    ("delete", KeyCode::ext(0x2E, 0x53)),
+    ("home", KeyCode::ext(0x24, 0x47)),
+    ("end", KeyCode::ext(0x23, 0x4F)),
+    ("pageup", KeyCode::ext(0x21, 0x49)),
+    ("pagedown", KeyCode::ext(0x22, 0x51)),
+    ("left", KeyCode::ext(0x25, 0x4B)),
+    ("up", KeyCode::ext(0x26, 0x48)),
+    ("right", KeyCode::ext(0x27, 0x4D)),
+    ("down", KeyCode::ext(0x28, 0x50)),
+    // Letters
+    ("a", KeyCode::new(0x41, 0x1E)),
+    ("b", KeyCode::new(0x42, 0x30)),
+    ("c", KeyCode::new(0x43, 0x2E)),
+    ("d", KeyCode::new(0x44, 0x20)),
+    ("e", KeyCode::new(0x45, 0x12)),
+    ("f", KeyCode::new(0x46, 0x21)),
+    ("g", KeyCode::new(0x47, 0x22)),
+    ("h", KeyCode::new(0x48, 0x23)),
+    ("i", KeyCode::new(0x49, 0x17)),
+    ("j", KeyCode::new(0x4A, 0x24)),
+    ("k", KeyCode::new(0x4B, 0x25)),
+    ("l", KeyCode::new(0x4C, 0x26)),
+    ("m", KeyCode::new(0x4D, 0x32)),
+    ("n", KeyCode::new(0x4E, 0x31)),
+    ("o", KeyCode::new(0x4F, 0x18)),
+    ("p", KeyCode::new(0x50, 0x19)),
+    ("q", KeyCode::new(0x51, 0x10)),
+    ("r", KeyCode::new(0x52, 0x13)),
+    ("s", KeyCode::new(0x53, 0x1F)),
+    ("t", KeyCode::new(0x54, 0x14)),
+    ("u", KeyCode::new(0x55, 0x16)),
+    ("v", KeyCode::new(0x56, 0x2F)),
+    ("w", KeyCode::new(0x57, 0x11)),
+    ("x", KeyCode::new(0x58, 0x2D)),
+    ("y", KeyCode::new(0x59, 0x15)),
+    ("z", KeyCode::new(0x5A, 0x2C)),
+    // Top-row digits
+    ("0", KeyCode::new(0x30, 0x0B)),
+    ("1", KeyCode::new(0x31, 0x02)),
+    ("2", KeyCode::new(0x32, 0x03)),
+    ("3", KeyCode::new(0x33, 0x04)),
+    ("4", KeyCode::new(0x34, 0x05)),
+    ("5", KeyCode::new(0x35, 0x06)),
+    ("6", KeyCode::new(0x36, 0x07)),
+    ("7", KeyCode::new(0x37, 0x08)),
+    ("8", KeyCode::new(0x38, 0x09)),
+    ("9", KeyCode::new(0x39, 0x0A)),
+    // Punctuation (US layout OEM keys)
+    (";", KeyCode::new(0xBA, 0x27)),
+    ("=", KeyCode::new(0xBB, 0x0D)),
+    (",", KeyCode::new(0xBC, 0x33)),
+    ("-", KeyCode::new(0xBD, 0x0C)),
+    (".", KeyCode::new(0xBE, 0x34)),
+    ("/", KeyCode::new(0xBF, 0x35)),
+    ("`", KeyCode::new(0xC0, 0x29)),
+    ("[", KeyCode::new(0xDB, 0x1A)),
+    ("\\", KeyCode::new(0xDC, 0x2B)),
+    ("]", KeyCode::new(0xDD, 0x1B)),
+    ("'", KeyCode::new(0xDE, 0x28)),
+    // Numpad
+    ("numpad0", KeyCode::new(0x60, 0x52)),
+    ("numpad1", KeyCode::new(0x61, 0x4F)),
+    ("numpad2", KeyCode::new(0x62, 0x50)),
+    ("numpad3", KeyCode::new(0x63, 0x51)),
+    ("numpad4", KeyCode::new(0x64, 0x4B)),
+    ("numpad5", KeyCode::new(0x65, 0x4C)),
+    ("numpad6", KeyCode::new(0x66, 0x4D)),
+    ("numpad7", KeyCode::new(0x67, 0x47)),
+    ("numpad8", KeyCode::new(0x68, 0x48)),
+    ("numpad9", KeyCode::new(0x69, 0x49)),
+    ("multiply", KeyCode::new(0x6A, 0x37)),
+    ("add", KeyCode::new(0x6B, 0x4E)),
+    ("subtract", KeyCode::new(0x6D, 0x4A)),
+    ("decimal", KeyCode::new(0x6E, 0x53)),
+    ("divide", KeyCode::ext(0x6F, 0x35)),
+    ("numpadenter", KeyCode::ext(0x0D, 0x1C)),
+    // Function keys
+    ("f1", KeyCode::new(0x70, 0x3B)),
+    ("f2", KeyCode::new(0x71, 0x3C)),
+    ("f3", KeyCode::new(0x72, 0x3D)),
+    ("f4", KeyCode::new(0x73, 0x3E)),
+    ("f5", KeyCode::new(0x74, 0x3F)),
+    ("f6", KeyCode::new(0x75, 0x40)),
+    ("f7", KeyCode::new(0x76, 0x41)),
+    ("f8", KeyCode::new(0x77, 0x42)),
+    ("f9", KeyCode::new(0x78, 0x43)),
+    ("f10", KeyCode::new(0x79, 0x44)),
+    ("f11", KeyCode::new(0x7A, 0x57)),
+    ("f12", KeyCode::new(0x7B, 0x58)),
+    ("f13", KeyCode::new(0x7C, 0x64)),
+    ("f14", KeyCode::new(0x7D, 0x65)),
+    ("f15", KeyCode::new(0x7E, 0x66)),
+    ("f16", KeyCode::new(0x7F, 0x67)),
+    ("f17", KeyCode::new(0x80, 0x68)),
+    ("f18", KeyCode::new(0x81, 0x69)),
+    ("f19", KeyCode::new(0x82, 0x6A)),
+    ("f20", KeyCode::new(0x83, 0x6B)),
+    ("f21", KeyCode::new(0x84, 0x6C)),
+    ("f22", KeyCode::new(0x85, 0x6D)),
+    ("f23", KeyCode::new(0x86, 0x6E)),
+    ("f24", KeyCode::new(0x87, 0x76)),
+    // Media and browser keys
+    ("volumemute", KeyCode::ext(0xAD, 0x20)),
+    ("volumedown", KeyCode::ext(0xAE, 0x2E)),
+    ("volumeup", KeyCode::ext(0xAF, 0x30)),
+    ("nexttrack", KeyCode::ext(0xB0, 0x19)),
+    ("prevtrack", KeyCode::ext(0xB1, 0x10)),
+    ("mediastop", KeyCode::ext(0xB2, 0x24)),
+    ("playpause", KeyCode::ext(0xB3, 0x22)),
+    ("browserback", KeyCode::ext(0xA6, 0x6A)),
+    ("browserforward", KeyCode::ext(0xA7, 0x69)),
+    ("browserrefresh", KeyCode::ext(0xA8, 0x67)),
+    ("browserhome", KeyCode::ext(0xAC, 0x32)),
+];
+
+/// Alternative spellings, resolved to a canonical name before lookup.
+const KEY_ALIASES: &[(&str, &str)] = &[
+    ("control", "ctrl"),
+    ("ctl", "ctrl"),
+    ("option", "alt"),
+    ("menu", "alt"),
+    ("cmd", "win"),
+    ("command", "win"),
+    ("super", "win"),
+    ("meta", "win"),
+    ("windows", "win"),
+    ("context", "apps"),
+    ("return", "enter"),
+    ("esc", "escape"),
+    ("bksp", "backspace"),
+    ("back", "backspace"),
+    ("spacebar", "space"),
+    ("caps", "capslock"),
+    ("break", "pause"),
+    ("prtsc", "printscreen"),
+    ("prtscr", "printscreen"),
+    ("print", "printscreen"),
+    ("snapshot", "printscreen"),
+    ("ins", "insert"),
+    ("del", "delete"),
+    ("pgup", "pageup"),
+    ("pgdn", "pagedown"),
+    ("pgdown", "pagedown"),
+    ("arrowleft", "left"),
+    ("arrowup", "up"),
+    ("arrowright", "right"),
+    ("arrowdown", "down"),
+    ("semicolon", ";"),
+    ("+", "="),
+    ("plus", "="),
+    ("equals", "="),
+    ("equal", "="),
+    ("comma", ","),
+    ("minus", "-"),
+    ("dash", "-"),
+    ("hyphen", "-"),
+    ("period", "."),
+    ("dot", "."),
+    ("slash", "/"),
+    ("backtick", "`"),
+    ("grave", "`"),
+    ("leftbracket", "["),
+    ("lbracket", "["),
+    ("backslash", "\\"),
+    ("rightbracket", "]"),
+    ("rbracket", "]"),
+    ("quote", "'"),
+    ("apostrophe", "'"),
+    ("num0", "numpad0"),
+    ("num1", "numpad1"),
+    ("num2", "numpad2"),
+    ("num3", "numpad3"),
+    ("num4", "numpad4"),
+    ("num5", "numpad5"),
+    ("num6", "numpad6"),
+    ("num7", "numpad7"),
+    ("num8", "numpad8"),
+    ("num9", "numpad9"),
+    ("numpadmultiply", "multiply"),
+    ("numpadadd", "add"),
+    ("numpadplus", "add"),
+    ("numpadsubtract", "subtract"),
+    ("numpadminus", "subtract"),
+    ("numpaddecimal", "decimal"),
+    ("numpaddivide", "divide"),
+    ("mute", "volumemute"),
+    ("voldown", "volumedown"),
+    ("volup", "volumeup"),
+    ("medianext", "nexttrack"),
+    ("mediaprev", "prevtrack"),
+    ("previoustrack", "prevtrack"),
+    ("mediaplaypause", "playpause"),
+    ("play", "playpause"),
+];
+
+/// Modifier ordering used when rendering a chord back to text.
+const MODIFIER_ORDER: &[&str] = &["ctrl", "lctrl", "rctrl", "alt", "lalt", "ralt", "shift", "lshift", "rshift", "win", "lwin", "rwin"];
+
+/// A key combination such as "ctrl+shift+p": held modifiers plus one main key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChord {
+    /// Canonical names of the modifiers, in canonical order
+    pub modifiers: Vec<&'static str>,
+    /// Canonical name of the key pressed while the modifiers are held
+    pub key: &'static str,
+}
+
+impl KeyChord {
+    /// Key codes of the modifiers, in press order.
+    pub fn modifier_codes(&self) -> Vec<KeyCode> {
+        self.modifiers.iter().filter_map(|name| lookup_canonical(name)).collect()
+    }
+
+    /// Key code of the main key.
+    pub fn key_code(&self) -> KeyCode {
+        lookup_canonical(self.key).expect("chord keys are canonical names")
+    }
+}
+
+impl std::fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for modifier in &self.modifiers {
+            write!(f, "{}+", modifier)?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyParseError {
+    Empty,
+    UnknownKey(String),
+    DuplicateKey(String),
+}
+
+impl std::fmt::Display for KeyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyParseError::Empty => write!(f, "Empty key name"),
+            KeyParseError::UnknownKey(name) => write!(f, "Unknown key name: {}", name),
+            KeyParseError::DuplicateKey(name) => write!(f, "Key appears twice in chord: {}", name),
+        }
+    }
+}
+
+impl std::error::Error for KeyParseError {}
+
+/// Resolve a key name or alias to its canonical table name.
+pub fn canonical_key_name(name: &str) -> Option<&'static str> {
+    let normalized = normalize(name);
+    let resolved = KEY_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == normalized)
+        .map(|(_, canonical)| *canonical)
+        .unwrap_or(normalized.as_str());
+
+    KEY_TABLE.iter().find(|(key, _)| *key == resolved).map(|(key, _)| *key)
+}
+
+/// Look up the key code for a single key name or alias.
+pub fn lookup_key(name: &str) -> Option<KeyCode> {
+    canonical_key_name(name).and_then(lookup_canonical)
+}
+
+/// Windows virtual-key code for a single key name or alias.
+pub fn get_virtual_key_code(name: &str) -> Option<u16> {
+    lookup_key(name).map(|code| code.vk)
+}
+
+/// Parse a chord like "ctrl+shift+p", "Ctrl++" or "cmd+space".
+///
+/// All keys but the last must be modifiers; a lone modifier ("shift")
+/// is a valid chord whose main key is that modifier.
+pub fn parse_chord(text: &str) -> Result<KeyChord, KeyParseError> {
+    let tokens = split_chord(text)?;
+    let (last, rest) = tokens.split_last().ok_or(KeyParseError::Empty)?;
+
+    let mut modifiers: Vec<&'static str> = Vec::new();
+    for token in rest {
+        let name = canonical_key_name(token)
+            .ok_or_else(|| KeyParseError::UnknownKey(token.clone()))?;
+        if !lookup_canonical(name).is_some_and(|code| code.is_modifier()) {
+            return Err(KeyParseError::UnknownKey(format!("{} (not a modifier)", token)));
+        }
+        if modifiers.contains(&name) {
+            return Err(KeyParseError::DuplicateKey(name.to_string()));
+        }
+        modifiers.push(name);
+    }
+
+    let key = canonical_key_name(last).ok_or_else(|| KeyParseError::UnknownKey(last.clone()))?;
+    if modifiers.contains(&key) {
+        return Err(KeyParseError::DuplicateKey(key.to_string()));
+    }
+
+    modifiers.sort_by_key(|m| MODIFIER_ORDER.iter().position(|o| o == m).unwrap_or(usize::MAX));
+    Ok(KeyChord { modifiers, key })
+}
+
+/// The side-neutral name of a left/right modifier ("lalt" -> "alt"); other
+/// names are returned unchanged.
+pub fn generic_modifier(name: &'static str) -> &'static str {
+    match name {
+        "lctrl" | "rctrl" => "ctrl",
+        "lalt" | "ralt" => "alt",
+        "lshift" | "rshift" => "shift",
+        "lwin" | "rwin" => "win",
+        _ => name,
+    }
+}
+
+/// All canonical key names, in table order.
+pub fn supported_key_names() -> impl Iterator<Item = &'static str> {
+    KEY_TABLE.iter().map(|(name, _)| *name)
+}
+
+fn lookup_canonical(name: &str) -> Option<KeyCode> {
+    KEY_TABLE.iter().find(|(key, _)| *key == name).map(|(_, code)| *code)
+}
+
+fn normalize(name: &str) -> String {
+    let trimmed = name.trim();
+    if trimmed.chars().count() <= 1 {
+        return trimmed.to_lowercase();
+    }
+    trimmed
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Split on '+', treating a '+' that starts a token as the literal plus key.
+fn split_chord(text: &str) -> Result<Vec<String>, KeyParseError> {
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in compact.chars() {
+        if c == '+' && !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+    }
+
+    if current.is_empty() {
+        return Err(KeyParseError::Empty);
+    }
+    tokens.push(current);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_canonical_name_resolves() {
+        for name in supported_key_names() {
+            let code = lookup_key(name).unwrap_or_else(|| panic!("{} should resolve", name));
+            assert!(code.vk > 0, "{} has no virtual-key code", name);
+            assert!(code.scan_code > 0, "{} has no scan code", name);
+            assert_eq!(parse_chord(name).unwrap().key, name);
+        }
+    }
+
+    #[test]
+    fn test_every_alias_resolves() {
+        for (alias, canonical) in KEY_ALIASES {
+            assert_eq!(canonical_key_name(alias), Some(*canonical), "alias {}", alias);
+        }
+    }
+
+    #[test]
+    fn test_known_virtual_key_codes() {
+        assert_eq!(get_virtual_key_code("A"), Some(0x41));
+        assert_eq!(get_virtual_key_code("+"), Some(0xBB));
+        assert_eq!(get_virtual_key_code(","), Some(0xBC));
+        assert_eq!(get_virtual_key_code("numpad7"), Some(0x67));
+        assert_eq!(get_virtual_key_code("Num 7"), Some(0x67));
+        assert_eq!(get_virtual_key_code("volume_up"), Some(0xAF));
+        assert_eq!(get_virtual_key_code("Print Screen"), Some(0x2C));
+        assert_eq!(get_virtual_key_code("cmd"), Some(0x5B));
+        assert_eq!(get_virtual_key_code("super"), Some(0x5B));
+        assert_eq!(get_virtual_key_code("F24"), Some(0x87));
+        assert_eq!(get_virtual_key_code("hyperspace"), None);
+    }
+
+    #[test]
+    fn test_extended_keys() {
+        assert!(lookup_key("up").unwrap().extended);
+        assert!(lookup_key("divide").unwrap().extended);
+        assert!(lookup_key("numpadenter").unwrap().extended);
+        assert!(!lookup_key("numpad8").unwrap().extended);
+        // Numpad 8 and Up share a scan code; only the E0 prefix differs
+        assert_eq!(lookup_key("numpad8").unwrap().scan_code, lookup_key("up").unwrap().scan_code);
+    }
+
+    #[test]
+    fn test_parse_chord() {
+        let chord = parse_chord("ctrl+shift+p").unwrap();
+        assert_eq!(chord.modifiers, vec!["ctrl", "shift"]);
+        assert_eq!(chord.key, "p");
+
+        // Modifier order and aliases are normalized
+        assert_eq!(parse_chord("Shift + Control + P").unwrap(), chord);
+        assert_eq!(parse_chord("cmd+space").unwrap().to_string(), "win+space");
+        assert_eq!(parse_chord("alt+f4").unwrap().to_string(), "alt+f4");
+    }
+
+    #[test]
+    fn test_parse_chord_with_plus_key() {
+        assert_eq!(parse_chord("+").unwrap().key, "=");
+        let zoom = parse_chord("ctrl++").unwrap();
+        assert_eq!(zoom.modifiers, vec!["ctrl"]);
+        assert_eq!(zoom.key, "=");
+        assert_eq!(parse_chord("ctrl+plus").unwrap(), zoom);
+    }
+
+    #[test]
+    fn test_parse_chord_errors() {
+        assert_eq!(parse_chord(""), Err(KeyParseError::Empty));
+        assert_eq!(parse_chord("ctrl+"), Err(KeyParseError::Empty));
+        assert!(matches!(parse_chord("ctrl+bogus"), Err(KeyParseError::UnknownKey(_))));
+        assert!(matches!(parse_chord("a+b"), Err(KeyParseError::UnknownKey(_))));
+        assert!(matches!(parse_chord("ctrl+control+s"), Err(KeyParseError::DuplicateKey(_))));
+    }
+
+    #[test]
+    fn test_chord_key_codes() {
+        let chord = parse_chord("ctrl+alt+delete").unwrap();
+        let modifiers: Vec<u16> = chord.modifier_codes().iter().map(|k| k.vk).collect();
+        assert_eq!(modifiers, vec![0x11, 0x12]);
+        assert_eq!(chord.key_code().vk, 0x2E);
+        assert!(chord.key_code().extended);
+    }
+}
@@ -0,0 +1,225 @@
+// Keyboard layouts: which key strokes type which characters
+// Typing "é" with virtual keys needs to know the layout: on AZERTY it is
+// one key, on QWERTY there is no key for it at all. A layout table lists
+// what each physical key (by scan code) produces plain, with Shift and
+// with AltGr. Text is typed key by key where the layout has the
+// character, and injected as Unicode where it doesn't.
+
+use std::fmt;
+
+use super::keys;
+
+/// What one level of a key produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOutput {
+    Char(char),
+    /// A dead key: produces nothing itself and accents the next character
+    Dead(char),
+}
+
+/// One physical key and what it types at each level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutKey {
+    pub scan_code: u16,
+    pub base: Option<KeyOutput>,
+    pub shift: Option<KeyOutput>,
+    pub altgr: Option<KeyOutput>,
+}
+
+/// How one character is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keystroke {
+    /// Tap the key with `scan_code`, holding Shift and/or AltGr
+    Key { scan_code: u16, shift: bool, altgr: bool },
+    /// The layout has no key for it: inject the character itself
+    Unicode(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardLayout {
+    pub name: String,
+    pub keys: Vec<LayoutKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutParseError {
+    MissingName,
+    /// Line number (from 1) and what is wrong with it
+    InvalidLine(usize, String),
+}
+
+impl fmt::Display for LayoutParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutParseError::MissingName => write!(f, "Layout has no 'name' line"),
+            LayoutParseError::InvalidLine(line, reason) => write!(f, "Layout line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for LayoutParseError {}
+
+impl KeyboardLayout {
+    /// Parse a layout table. One key per line: its scan code, then what it
+    /// types plain, with Shift and with AltGr. Trailing levels may be left
+    /// out, `none` skips one, `space` is the space character and `dead:^`
+    /// a dead key. `#` starts a comment line; `name X` names the layout.
+    ///
+    /// ```text
+    /// name azerty
+    /// 0x03  é  2  dead:~
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, LayoutParseError> {
+        let mut name = None;
+        let mut keys = Vec::new();
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| LayoutParseError::InvalidLine(number, reason);
+            let mut fields = line.split_whitespace();
+            let first = fields.next().unwrap_or_default();
+            if first == "name" {
+                name = Some(fields.collect::<Vec<_>>().join(" "));
+                continue;
+            }
+            let scan_code = first
+                .strip_prefix("0x")
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid(format!("'{}' is not a hex scan code", first)))?;
+            let levels = fields.map(|field| parse_output(field).ok_or_else(|| invalid(format!("'{}' is not a key output", field))));
+            let levels: Vec<Option<KeyOutput>> = levels.collect::<Result<_, _>>()?;
+            if levels.is_empty() || levels.len() > 3 {
+                return Err(invalid(format!("expected 1 to 3 levels after the scan code, found {}", levels.len())));
+            }
+            let level = |i: usize| levels.get(i).copied().flatten();
+            keys.push(LayoutKey { scan_code, base: level(0), shift: level(1), altgr: level(2) });
+        }
+        Ok(Self { name: name.ok_or(LayoutParseError::MissingName)?, keys })
+    }
+
+    /// Key strokes that type `text`, using the fewest modifiers for each
+    /// character and Unicode injection for characters the layout lacks or
+    /// only has as dead keys
+    pub fn keystrokes(&self, text: &str) -> Vec<Keystroke> {
+        text.chars().map(|c| self.keystroke(c)).collect()
+    }
+
+    fn keystroke(&self, c: char) -> Keystroke {
+        if let Some(scan_code) = control_key(c) {
+            return Keystroke::Key { scan_code, shift: false, altgr: false };
+        }
+        let levels = [(false, false), (true, false), (false, true)];
+        levels
+            .iter()
+            .find_map(|&(shift, altgr)| {
+                let key = self.keys.iter().find(|key| key.level(shift, altgr) == Some(KeyOutput::Char(c)))?;
+                Some(Keystroke::Key { scan_code: key.scan_code, shift, altgr })
+            })
+            .unwrap_or(Keystroke::Unicode(c))
+    }
+
+    /// The characters of `text` that would be injected as Unicode, each once
+    pub fn fallbacks(&self, text: &str) -> Vec<char> {
+        let mut fallbacks = Vec::new();
+        for stroke in self.keystrokes(text) {
+            if let Keystroke::Unicode(c) = stroke {
+                if !fallbacks.contains(&c) {
+                    fallbacks.push(c);
+                }
+            }
+        }
+        fallbacks
+    }
+
+    /// What an application receives for `strokes` on this layout. Strokes on
+    /// dead keys, or on levels the key doesn't have, type nothing.
+    pub fn replay(&self, strokes: &[Keystroke]) -> String {
+        strokes
+            .iter()
+            .filter_map(|stroke| match *stroke {
+                Keystroke::Unicode(c) => Some(c),
+                Keystroke::Key { scan_code, shift, altgr } => {
+                    if let Some(c) = ['\n', '\t'].into_iter().find(|&c| control_key(c) == Some(scan_code)) {
+                        return Some(c);
+                    }
+                    let key = self.keys.iter().find(|key| key.scan_code == scan_code)?;
+                    match key.level(shift, altgr)? {
+                        KeyOutput::Char(c) => Some(c),
+                        KeyOutput::Dead(_) => None,
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl LayoutKey {
+    fn level(&self, shift: bool, altgr: bool) -> Option<KeyOutput> {
+        match (shift, altgr) {
+            (false, false) => self.base,
+            (true, false) => self.shift,
+            (false, true) => self.altgr,
+            (true, true) => None,
+        }
+    }
+}
+
+impl fmt::Display for Keystroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Keystroke::Key { scan_code, shift, altgr } => {
+                let modifiers = [(*shift, "shift+"), (*altgr, "altgr+")];
+                let held: String = modifiers.iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+                write!(f, "{}scan 0x{:02X}", held, scan_code)
+            }
+            Keystroke::Unicode(c) => write!(f, "U+{:04X}", u32::from(*c)),
+        }
+    }
+}
+
+/// Enter and Tab are the same key on every layout
+fn control_key(c: char) -> Option<u16> {
+    let name = match c {
+        '\n' => "enter",
+        '\t' => "tab",
+        _ => return None,
+    };
+    keys::lookup_key(name).map(|code| code.scan_code)
+}
+
+fn parse_output(field: &str) -> Option<Option<KeyOutput>> {
+    let single = |text: &str| {
+        let mut chars = text.chars();
+        chars.next().filter(|_| chars.next().is_none())
+    };
+    match field {
+        "none" => Some(None),
+        "space" => Some(Some(KeyOutput::Char(' '))),
+        _ => match field.strip_prefix("dead:") {
+            Some(accent) => single(accent).map(|c| Some(KeyOutput::Dead(c))),
+            None => single(field).map(|c| Some(KeyOutput::Char(c))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_fall_back_for_dead_keys() {
+        let layout = KeyboardLayout::parse("# two keys\nname tiny\n0x1E a A\n0x1A dead:^ dead:¨\n0x39 space\n").unwrap();
+        assert_eq!(layout.name, "tiny");
+        let strokes = layout.keystrokes("Aa ^\n");
+        assert_eq!(strokes[0], Keystroke::Key { scan_code: 0x1E, shift: true, altgr: false });
+        assert_eq!(strokes[3], Keystroke::Unicode('^'));
+        assert_eq!(layout.replay(&strokes), "Aa ^\n");
+        assert_eq!(layout.replay(&[Keystroke::Key { scan_code: 0x1A, shift: false, altgr: false }]), "");
+        assert_eq!(strokes[0].to_string(), "shift+scan 0x1E");
+
+        assert_eq!(KeyboardLayout::parse("0x1E a"), Err(LayoutParseError::MissingName));
+        assert!(matches!(KeyboardLayout::parse("name x\n1E a"), Err(LayoutParseError::InvalidLine(2, _))));
+        assert!(matches!(KeyboardLayout::parse("name x\n0x1E ab"), Err(LayoutParseError::InvalidLine(2, _))));
+    }
+}
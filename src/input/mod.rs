@@ -3,6 +3,13 @@
 
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
+use log::{info, warn};
+
+pub mod demonstration;
+#[cfg(all(target_os = "linux", feature = "evdev-hook"))]
+pub mod evdev;
+pub mod keys;
+pub mod layout;
 
 #[derive(Debug, Clone)]
 pub struct InputAction {
@@ -13,9 +20,13 @@ pub struct InputAction {
 
 #[derive(Debug, Clone)]
 pub enum ActionType {
-    Click { button: MouseButton },
+    /// `modifiers` are key names ("shift", "ctrl") held down during the click
+    Click { button: MouseButton, modifiers: Vec<String> },
     Type { text: String },
     Key { key: String },
+    /// Press and hold a single key until a matching `KeyUp`
+    KeyDown { key: String },
+    KeyUp { key: String },
     Scroll { direction: ScrollDirection, amount: i32 },
     Move { x: i32, y: i32 },
 }
@@ -46,14 +57,44 @@ pub struct InputController {
     action_history: Vec<InputAction>,
     rate_limiter: RateLimiter,
     safety_checker: Box<dyn SafetyChecker>,
+    pacing: Pacing,
+    /// Where the last click or move left the cursor
+    last_position: Option<(i32, i32)>,
+    /// Receives actions instead of the operating system when set
+    sink: Option<Box<dyn InputSink>>,
+    /// Keys pressed with `KeyDown` and not yet released, in press order
+    held_keys: Vec<String>,
+    /// Keyboard layout text is typed with; without one, text is injected
+    /// as Unicode
+    layout: Option<layout::KeyboardLayout>,
+}
+
+/// Receives checked actions in place of the operating system, e.g. a
+/// simulated desktop
+pub trait InputSink: Send {
+    fn send(&mut self, action: &InputAction) -> Result<(), InputError>;
+}
+
+/// How visibly actions are performed. The default types in one go and
+/// jumps straight to click targets.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pacing {
+    /// Pause after each typed character
+    pub type_delay: Duration,
+    /// Time the cursor takes to glide to a click target
+    pub move_duration: Duration,
 }
 
+/// Interval between cursor positions while gliding
+const GLIDE_STEP: Duration = Duration::from_millis(15);
+
 pub trait SafetyChecker {
     fn is_action_safe(&self, action: &InputAction) -> bool;
     fn get_risk_level(&self, action: &InputAction) -> RiskLevel;
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RiskLevel {
     Safe,
     Low,
@@ -79,7 +120,7 @@ impl RateLimiter {
 
     pub fn check_rate_limit(&mut self, action_type: &str) -> bool {
         let now = Instant::now();
-        let actions = self.action_counts.entry(action_type.to_string()).or_insert_with(Vec::new);
+        let actions = self.action_counts.entry(action_type.to_string()).or_default();
         
         // Remove old entries
         actions.retain(|&timestamp| now.duration_since(timestamp) < Duration::from_secs(60));
@@ -104,12 +145,77 @@ impl InputController {
             action_history: Vec::new(),
             rate_limiter: RateLimiter::new(100, 10), // 100/min, 10/sec
             safety_checker,
+            pacing: Pacing::default(),
+            last_position: None,
+            sink: None,
+            held_keys: Vec::new(),
+            layout: None,
+        }
+    }
+
+    /// Type text with the keys of `layout` where it has them
+    pub fn set_layout(&mut self, layout: Option<layout::KeyboardLayout>) {
+        self.layout = layout;
+    }
+
+    /// How `text` would be sent: key strokes on the layout, Unicode for the
+    /// rest (all of it without a layout)
+    pub fn keystrokes_for(&self, text: &str) -> Vec<layout::Keystroke> {
+        match &self.layout {
+            Some(layout) => layout.keystrokes(text),
+            None => text.chars().map(layout::Keystroke::Unicode).collect(),
         }
     }
 
+    /// Send actions to `sink` instead of the operating system, or to the
+    /// operating system again with `None`
+    pub fn set_sink(&mut self, sink: Option<Box<dyn InputSink>>) {
+        self.sink = sink;
+    }
+
+    pub fn has_sink(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// How risky the safety checker rates `action`
+    pub fn risk_level(&self, action: &InputAction) -> RiskLevel {
+        self.safety_checker.get_risk_level(action)
+    }
+
     pub fn execute_action(&mut self, action: InputAction) -> Result<(), InputError> {
-        // Safety check
-        if !self.safety_checker.is_action_safe(&action) {
+        // Reject key names we cannot map to a virtual-key code
+        match &action.action_type {
+            ActionType::Key { key } => {
+                keys::parse_chord(key).map_err(|e| InputError::InvalidKey(e.to_string()))?;
+            }
+            ActionType::KeyDown { key } | ActionType::KeyUp { key } if keys::lookup_key(key).is_none() => {
+                return Err(InputError::InvalidKey(format!("{} (hold one key at a time)", key)));
+            }
+            ActionType::Click { modifiers, .. } => {
+                if let Some(bad) = modifiers.iter().find(|m| !keys::lookup_key(m).is_some_and(|code| code.is_modifier())) {
+                    return Err(InputError::InvalidKey(format!("{} (not a modifier)", bad)));
+                }
+            }
+            _ => {}
+        }
+
+        // Releasing a key is never refused, and pressing one already held does nothing
+        match &action.action_type {
+            ActionType::KeyUp { key } => return self.release_key(key, action.clone()),
+            ActionType::KeyDown { key } if self.is_held(key) => return Ok(()),
+            _ => {}
+        }
+
+        // Safety check, of a key together with the keys held down under it
+        if !self.safety_checker.is_action_safe(&action) || !self.safety_checker.is_action_safe(&self.with_held_keys(&action)) {
             return Err(InputError::SafetyViolation);
         }
 
@@ -120,7 +226,10 @@ impl InputController {
         }
 
         // Execute platform-specific action
-        self.execute_platform_action(&action)?;
+        self.execute_paced(&action)?;
+        if let ActionType::KeyDown { key } = &action.action_type {
+            self.held_keys.push(normalized_chord(key));
+        }
         
         // Record action
         self.action_history.push(action);
@@ -128,13 +237,60 @@ impl InputController {
         Ok(())
     }
 
+    /// Keys pressed with `KeyDown` and not yet released, in press order
+    pub fn held_keys(&self) -> &[String] {
+        &self.held_keys
+    }
+
+    fn is_held(&self, key: &str) -> bool {
+        let key = normalized_chord(key);
+        self.held_keys.contains(&key)
+    }
+
+    /// `action` as the system sees it with the held keys down: a key press
+    /// becomes the chord of the held keys and the key
+    pub fn with_held_keys(&self, action: &InputAction) -> InputAction {
+        match &action.action_type {
+            ActionType::Key { key } | ActionType::KeyDown { key } if !self.held_keys.is_empty() => {
+                InputAction { action_type: ActionType::Key { key: held_chord(&self.held_keys, key) }, ..action.clone() }
+            }
+            _ => action.clone(),
+        }
+    }
+
+    fn release_key(&mut self, key: &str, action: InputAction) -> Result<(), InputError> {
+        let key = normalized_chord(key);
+        self.dispatch(&action)?;
+        self.held_keys.retain(|held| *held != key);
+        self.action_history.push(action);
+        Ok(())
+    }
+
+    /// Release every held key, last pressed first, and return the keys that
+    /// were held. Called when a sequence ends, however it ends, so an
+    /// aborted "hold shift while dragging" doesn't leave shift down.
+    pub fn release_held_keys(&mut self) -> Vec<String> {
+        let held = std::mem::take(&mut self.held_keys);
+        for key in held.iter().rev() {
+            let action = InputAction {
+                action_type: ActionType::KeyUp { key: key.clone() },
+                target: Target { x: 0, y: 0, element_type: None },
+                timestamp: Instant::now(),
+            };
+            if let Err(e) = self.dispatch(&action) {
+                warn!("Could not release held key {}: {}", key, e);
+            }
+        }
+        held
+    }
+
     #[cfg(target_os = "windows")]
     fn execute_platform_action(&self, action: &InputAction) -> Result<(), InputError> {
         // Simplified Windows implementation without heavy dependencies
         match &action.action_type {
-            ActionType::Click { button } => {
+            ActionType::Click { button, modifiers } => {
                 // Use minimal Windows API calls
-                self.windows_click(action.target.x, action.target.y, button)
+                self.windows_click(action.target.x, action.target.y, button, modifiers)
             }
             ActionType::Type { text } => {
                 self.windows_type_text(text)
@@ -142,6 +298,12 @@ impl InputController {
             ActionType::Key { key } => {
                 self.windows_send_key(key)
             }
+            ActionType::KeyDown { key } => {
+                self.windows_key_transition(key, true)
+            }
+            ActionType::KeyUp { key } => {
+                self.windows_key_transition(key, false)
+            }
             ActionType::Move { x, y } => {
                 self.windows_move_cursor(*x, *y)
             }
@@ -155,30 +317,88 @@ impl InputController {
     fn execute_platform_action(&self, action: &InputAction) -> Result<(), InputError> {
         // Cross-platform fallback (X11, Wayland simulation)
         match &action.action_type {
-            ActionType::Click { .. } => {
+            ActionType::Click { modifiers, .. } => {
                 // Log the action for testing/simulation
-                println!("SIMULATE: Click at ({}, {})", action.target.x, action.target.y);
+                if modifiers.is_empty() {
+                    info!("SIMULATE: Click at ({}, {})", action.target.x, action.target.y);
+                } else {
+                    info!("SIMULATE: {}+click at ({}, {})", modifiers.join("+"), action.target.x, action.target.y);
+                }
                 Ok(())
             }
             ActionType::Type { text } => {
-                println!("SIMULATE: Type text: {}", text);
+                info!("SIMULATE: Type text: {}", text);
                 Ok(())
             }
             ActionType::Key { key } => {
-                println!("SIMULATE: Send key: {}", key);
+                info!("SIMULATE: Send key: {}", key);
+                Ok(())
+            }
+            ActionType::KeyDown { key } => {
+                info!("SIMULATE: Key down: {}", key);
+                Ok(())
+            }
+            ActionType::KeyUp { key } => {
+                info!("SIMULATE: Key up: {}", key);
                 Ok(())
             }
             ActionType::Move { x, y } => {
-                println!("SIMULATE: Move cursor to ({}, {})", x, y);
+                info!("SIMULATE: Move cursor to ({}, {})", x, y);
                 Ok(())
             }
             ActionType::Scroll { direction, amount } => {
-                println!("SIMULATE: Scroll {:?} by {}", direction, amount);
+                info!("SIMULATE: Scroll {:?} by {}", direction, amount);
                 Ok(())
             }
         }
     }
 
+    /// Run an already-checked action, gliding to click targets and typing
+    /// character by character as the pacing asks
+    fn execute_paced(&mut self, action: &InputAction) -> Result<(), InputError> {
+        let step = |action_type| InputAction { action_type, target: action.target.clone(), timestamp: Instant::now() };
+        match &action.action_type {
+            ActionType::Click { .. } if !self.pacing.move_duration.is_zero() => {
+                let (x, y) = (action.target.x, action.target.y);
+                let cursor = if self.sink.is_some() { None } else { cursor_position() };
+                if let Some((from_x, from_y)) = self.last_position.or(cursor) {
+                    let steps = (self.pacing.move_duration.as_millis() / GLIDE_STEP.as_millis()).max(1) as i32;
+                    for i in 1..steps {
+                        let at = |from: i32, to: i32| from + (to - from) * i / steps;
+                        self.dispatch(&step(ActionType::Move { x: at(from_x, x), y: at(from_y, y) }))?;
+                        std::thread::sleep(GLIDE_STEP);
+                    }
+                }
+                self.dispatch(action)?;
+            }
+            ActionType::Type { text } if !self.pacing.type_delay.is_zero() => {
+                for c in text.chars() {
+                    self.dispatch(&step(ActionType::Type { text: c.to_string() }))?;
+                    std::thread::sleep(self.pacing.type_delay);
+                }
+            }
+            _ => self.dispatch(action)?,
+        }
+        match &action.action_type {
+            ActionType::Click { .. } => self.last_position = Some((action.target.x, action.target.y)),
+            ActionType::Move { x, y } => self.last_position = Some((*x, *y)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, action: &InputAction) -> Result<(), InputError> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.send(action),
+            None => self.execute_platform_action(action),
+        }
+    }
+
+    /// Where the last click or move left the cursor
+    pub fn last_position(&self) -> Option<(i32, i32)> {
+        self.last_position
+    }
+
     pub fn get_action_history(&self) -> &[InputAction] {
         &self.action_history
     }
@@ -188,37 +408,58 @@ impl InputController {
     }
 }
 
+impl Drop for InputController {
+    fn drop(&mut self) {
+        self.release_held_keys();
+    }
+}
+
 #[cfg(target_os = "windows")]
 impl InputController {
-    fn windows_click(&self, x: i32, y: i32, button: &MouseButton) -> Result<(), InputError> {
+    fn windows_click(&self, x: i32, y: i32, button: &MouseButton, modifiers: &[String]) -> Result<(), InputError> {
         // Minimal Windows API implementation
-        // In real implementation, would use SetCursorPos and mouse_event
-        println!("Windows click at ({}, {}) with {:?}", x, y, button);
+        // In real implementation, would use SetCursorPos and mouse_event,
+        // with the modifiers pressed before and released after
+        info!("Windows click at ({}, {}) with {:?} holding {:?}", x, y, button, modifiers);
         Ok(())
     }
 
     fn windows_type_text(&self, text: &str) -> Result<(), InputError> {
         // Minimal Windows API implementation
-        // In real implementation, would use SendInput with VK_* codes
-        println!("Windows type: {}", text);
+        // In real implementation, would use SendInput with KEYEVENTF_SCANCODE
+        // for key strokes and KEYEVENTF_UNICODE for the rest
+        let strokes: Vec<String> = self.keystrokes_for(text).iter().map(ToString::to_string).collect();
+        info!("Windows type: {} ({})", text, strokes.join(" "));
         Ok(())
     }
 
     fn windows_send_key(&self, key: &str) -> Result<(), InputError> {
         // Minimal Windows API implementation
-        println!("Windows key: {}", key);
+        // In real implementation, would press modifiers, tap the key, then release in reverse
+        let chord = keys::parse_chord(key).map_err(|e| InputError::InvalidKey(e.to_string()))?;
+        let code = chord.key_code();
+        info!("Windows key: {} (vk 0x{:02X}, scan 0x{:02X}, extended {})",
+                 chord, code.vk, code.scan_code, code.extended);
+        Ok(())
+    }
+
+    fn windows_key_transition(&self, key: &str, down: bool) -> Result<(), InputError> {
+        // Minimal Windows API implementation
+        // In real implementation, would use SendInput with KEYEVENTF_KEYUP on release
+        let code = keys::lookup_key(key).ok_or_else(|| InputError::InvalidKey(key.to_string()))?;
+        info!("Windows key {}: {} (vk 0x{:02X})", if down { "down" } else { "up" }, key, code.vk);
         Ok(())
     }
 
     fn windows_move_cursor(&self, x: i32, y: i32) -> Result<(), InputError> {
         // Minimal Windows API implementation
-        println!("Windows move cursor to ({}, {})", x, y);
+        info!("Windows move cursor to ({}, {})", x, y);
         Ok(())
     }
 
     fn windows_scroll(&self, x: i32, y: i32, direction: &ScrollDirection, amount: i32) -> Result<(), InputError> {
         // Minimal Windows API implementation
-        println!("Windows scroll at ({}, {}) {:?} by {}", x, y, direction, amount);
+        info!("Windows scroll at ({}, {}) {:?} by {}", x, y, direction, amount);
         Ok(())
     }
 }
@@ -230,6 +471,7 @@ pub enum InputError {
     PlatformError(String),
     InvalidTarget,
     InvalidAction,
+    InvalidKey(String),
 }
 
 impl std::fmt::Display for InputError {
@@ -240,6 +482,7 @@ impl std::fmt::Display for InputError {
             InputError::PlatformError(msg) => write!(f, "Platform error: {}", msg),
             InputError::InvalidTarget => write!(f, "Invalid target location"),
             InputError::InvalidAction => write!(f, "Invalid action type"),
+            InputError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
         }
     }
 }
@@ -265,6 +508,12 @@ impl BasicSafetyChecker {
     }
 }
 
+impl Default for BasicSafetyChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SafetyChecker for BasicSafetyChecker {
     fn is_action_safe(&self, action: &InputAction) -> bool {
         match &action.action_type {
@@ -273,8 +522,8 @@ impl SafetyChecker for BasicSafetyChecker {
                 !self.forbidden_patterns.iter().any(|pattern| text_lower.contains(pattern))
             }
             ActionType::Key { key } => {
-                // Block dangerous key combinations
-                !matches!(key.as_str(), "ctrl+alt+delete" | "alt+f4" | "win+r")
+                // Block dangerous key combinations, however they are spelled
+                !matches!(normalized_chord(key).as_str(), "ctrl+alt+delete" | "alt+f4" | "win+r")
             }
             _ => true, // Other actions are generally safe
         }
@@ -293,7 +542,7 @@ impl SafetyChecker for BasicSafetyChecker {
                 }
             }
             ActionType::Key { key } => {
-                if matches!(key.as_str(), "ctrl+alt+delete" | "alt+f4") {
+                if matches!(normalized_chord(key).as_str(), "ctrl+alt+delete" | "alt+f4") {
                     RiskLevel::High
                 } else {
                     RiskLevel::Low
@@ -304,10 +553,132 @@ impl SafetyChecker for BasicSafetyChecker {
     }
 }
 
+/// Current mouse cursor position (GetCursorPos)
+#[cfg(target_os = "windows")]
+pub fn cursor_position() -> Option<(i32, i32)> {
+    crate::utils::win32::cursor_position()
+}
+
+/// Current mouse cursor position, when the platform exposes it (`xdotool` on X11)
+#[cfg(not(target_os = "windows"))]
+pub fn cursor_position() -> Option<(i32, i32)> {
+    let output = std::process::Command::new("xdotool")
+        .args(["getmouselocation", "--shell"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_mouse_location(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Put `text` on the system clipboard with the first clipboard tool found
+/// (`wl-copy`, `xclip`, `xsel`, `pbcopy` or `clip`); false when none worked
+pub fn copy_to_clipboard(text: &str) -> bool {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    const TOOLS: &[(&str, &[&str])] = &[
+        ("wl-copy", &[]),
+        ("xclip", &["-selection", "clipboard"]),
+        ("xsel", &["--clipboard", "--input"]),
+        ("pbcopy", &[]),
+        ("clip", &[]),
+    ];
+    TOOLS.iter().any(|(tool, args)| {
+        let Ok(mut child) = Command::new(tool).args(*args).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).spawn() else {
+            return false;
+        };
+        let written = child.stdin.take().is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+        child.wait().is_ok_and(|status| status.success()) && written
+    })
+}
+
+/// Put a PNG image on the system clipboard; false when no tool took it.
+/// Wayland and X11 get the PNG itself (`wl-copy`, `xclip -t image/png`).
+/// Windows and macOS read it from a temporary file: .NET's `SetImage`
+/// stores a CF_DIB bitmap and AppleScript a PNGf picture, the formats
+/// native applications paste.
+pub fn copy_image_to_clipboard(png: &[u8]) -> bool {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    const PIPED: &[(&str, &[&str])] = &[
+        ("wl-copy", &["--type", "image/png"]),
+        ("xclip", &["-selection", "clipboard", "-t", "image/png"]),
+    ];
+    let piped = PIPED.iter().any(|(tool, args)| {
+        let Ok(mut child) = Command::new(tool).args(*args).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).spawn() else {
+            return false;
+        };
+        let written = child.stdin.take().is_some_and(|mut stdin| stdin.write_all(png).is_ok());
+        child.wait().is_ok_and(|status| status.success()) && written
+    });
+    if piped || !cfg!(any(target_os = "windows", target_os = "macos")) {
+        return piped;
+    }
+
+    let path = std::env::temp_dir().join(format!("luna-clipboard-{}.png", std::process::id()));
+    if std::fs::write(&path, png).is_err() {
+        return false;
+    }
+    let quoted = path.display().to_string().replace('\'', "''");
+    let status = if cfg!(target_os = "windows") {
+        let script = format!(
+            "Add-Type -AssemblyName System.Windows.Forms,System.Drawing; \
+             [System.Windows.Forms.Clipboard]::SetImage([System.Drawing.Image]::FromFile('{}'))",
+            quoted
+        );
+        Command::new("powershell").args(["-NoProfile", "-STA", "-Command", &script]).status()
+    } else {
+        let script = format!("set the clipboard to (read (POSIX file \"{}\") as «class PNGf»)", path.display());
+        Command::new("osascript").args(["-e", &script]).status()
+    };
+    let _ = std::fs::remove_file(&path);
+    status.is_ok_and(|status| status.success())
+}
+
+/// Parse `xdotool getmouselocation --shell` output ("X=10\nY=20\nSCREEN=0\n...")
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+fn parse_mouse_location(output: &str) -> Option<(i32, i32)> {
+    let value = |key: &str| {
+        output.lines().find_map(|line| line.strip_prefix(key)?.strip_prefix('=')?.trim().parse().ok())
+    };
+    Some((value("X")?, value("Y")?))
+}
+
+/// Canonical spelling of a chord ("Alt+Cmd+R" -> "alt+win+r"), or the lowercased input if it does not parse.
+/// Left/right modifiers are folded into the generic ones ("lalt+f4" -> "alt+f4") so they can't slip past
+/// the blocklist.
+fn normalized_chord(key: &str) -> String {
+    keys::parse_chord(key)
+        .map(|chord| {
+            // Canonical order keeps the sides of a modifier next to each other
+            let mut modifiers: Vec<&'static str> = chord.modifiers.iter().map(|m| keys::generic_modifier(m)).collect();
+            modifiers.dedup();
+            keys::KeyChord { modifiers, key: keys::generic_modifier(chord.key) }.to_string()
+        })
+        .unwrap_or_else(|_| key.to_lowercase())
+}
+
+/// The chord pressing `key` makes with `held` keys down ("shift" held,
+/// "alt" held, "f4" -> "shift+alt+f4")
+pub fn held_chord(held: &[String], key: &str) -> String {
+    let mut parts: Vec<&str> = held.iter().map(String::as_str).collect();
+    parts.push(key);
+    parts.join("+")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_mouse_location() {
+        assert_eq!(parse_mouse_location("X=812\nY=433\nSCREEN=0\nWINDOW=6291463\n"), Some((812, 433)));
+        assert_eq!(parse_mouse_location("SCREEN=0\n"), None);
+    }
+
     #[test]
     fn test_rate_limiter() {
         let mut limiter = RateLimiter::new(5, 2);
@@ -320,6 +691,30 @@ mod tests {
         assert!(!limiter.check_rate_limit("click"));
     }
 
+    #[test]
+    fn test_pacing_slows_typing_and_glides_to_clicks() {
+        let action = |action_type| InputAction {
+            action_type,
+            target: Target { x: 300, y: 200, element_type: None },
+            timestamp: Instant::now(),
+        };
+        let mut controller = InputController::new(Box::new(BasicSafetyChecker::new()));
+        controller.set_pacing(Pacing { type_delay: Duration::from_millis(10), move_duration: Duration::from_millis(60) });
+
+        let started = Instant::now();
+        controller.execute_action(action(ActionType::Type { text: "hello".to_string() })).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        controller.last_position = Some((0, 0));
+        let started = Instant::now();
+        controller.execute_action(action(ActionType::Click { button: MouseButton::Left, modifiers: Vec::new() })).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(45), "glide takes three steps");
+        assert_eq!(controller.last_position, Some((300, 200)));
+
+        // Paced actions are still one action each for history and rate limiting
+        assert_eq!(controller.get_action_history().len(), 2);
+    }
+
     #[test]
     fn test_safety_checker() {
         let checker = BasicSafetyChecker::new();
@@ -339,4 +734,49 @@ mod tests {
         assert!(checker.is_action_safe(&safe_action));
         assert!(!checker.is_action_safe(&unsafe_action));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_safety_checker_normalizes_chords() {
+        let checker = BasicSafetyChecker::new();
+        let key_action = |key: &str| InputAction {
+            action_type: ActionType::Key { key: key.to_string() },
+            target: Target { x: 0, y: 0, element_type: None },
+            timestamp: Instant::now(),
+        };
+
+        assert!(!checker.is_action_safe(&key_action("Ctrl+Alt+Del")));
+        assert!(!checker.is_action_safe(&key_action("alt + F4")));
+        assert!(!checker.is_action_safe(&key_action("super+r")));
+        assert!(checker.is_action_safe(&key_action("ctrl+shift+p")));
+        assert_eq!(checker.get_risk_level(&key_action("Ctrl+Alt+Del")), RiskLevel::High);
+    }
+
+    #[test]
+    fn test_side_specific_modifiers_are_blocked() {
+        let checker = BasicSafetyChecker::new();
+        let key_action = |key: &str| InputAction {
+            action_type: ActionType::Key { key: key.to_string() },
+            target: Target { x: 0, y: 0, element_type: None },
+            timestamp: Instant::now(),
+        };
+
+        for key in ["lalt+f4", "RAlt+F4", "lctrl+lalt+delete", "rctrl+lalt+del", "lwin+r", "rwin+R"] {
+            assert!(!checker.is_action_safe(&key_action(key)), "{} was allowed", key);
+        }
+        assert_eq!(checker.get_risk_level(&key_action("lalt+f4")), RiskLevel::High);
+        assert_eq!(checker.get_risk_level(&key_action("lctrl+ralt+delete")), RiskLevel::High);
+        assert!(checker.is_action_safe(&key_action("lctrl+rctrl+c")));
+    }
+
+    #[test]
+    fn test_unknown_key_rejected() {
+        let mut controller = InputController::new(Box::new(BasicSafetyChecker::new()));
+        let action = InputAction {
+            action_type: ActionType::Key { key: "ctrl+hyperspace".to_string() },
+            target: Target { x: 0, y: 0, element_type: None },
+            timestamp: Instant::now(),
+        };
+
+        assert!(matches!(controller.execute_action(action), Err(InputError::InvalidKey(_))));
+    }
+}
@@ -28,6 +28,7 @@
 //! - [`input`] - Input actions with safety checks and rate limiting
 //! - [`overlay`] - Visual feedback data structures
 //! - [`utils`] - Geometry, image processing, logging
+//! - `scripting` - Sandboxed Rhai scripts over the Luna API (`scripting` feature)
 
 pub mod ai;
 pub mod core;
@@ -35,9 +36,11 @@ pub mod input;
 pub mod utils;
 pub mod vision;
 pub mod overlay;
+#[cfg(feature = "scripting")]
+pub mod scripting;
 
 // Re-export main types for convenient access
-pub use core::{Luna, LunaConfig, LunaError};
+pub use core::{CommandResult, ExecuteOptions, Luna, LunaConfig, LunaError, LunaHandle};
 pub use vision::{UIElement, ElementType, VisionError};
 pub use input::{InputAction, ActionType, InputError};
 pub use overlay::{OverlayManager, OverlayConfig, Color};
@@ -188,6 +191,9 @@ fn get_enabled_features() -> Vec<String> {
         "screen-capture".to_string(),
     ];
 
+    #[cfg(feature = "scripting")]
+    features.push("scripting".to_string());
+
     #[cfg(target_os = "windows")]
     features.push("windows-input".to_string());
 
@@ -238,9 +244,11 @@ pub mod test_utils {
         
         UIElement {
             bounds: Rectangle::new(10.0, 10.0, 100.0, 50.0),
+            shape: None,
             element_type: ElementType::Button,
             confidence: 0.8,
             properties: HashMap::new(),
+            associated_label: None,
         }
     }
 }
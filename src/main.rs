@@ -9,33 +9,112 @@
 // synthetic screen and logs actions instead of performing them.
 
 use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
 
-use luna::{Luna, LunaConfig};
+mod cli;
+
+use luna::ai::remote::InferenceServer;
+use luna::ai::suggest::SuggestionSource;
+use luna::ai::{ConfidenceThresholds, VisionProcessor};
+use luna::core::config::{SpeedPreset, SupervisionConfig};
+use luna::core::debugger::Breakpoint;
+use luna::core::review::Verdict;
+use luna::core::sandbox::{Sandbox, SandboxScene};
+use luna::core::storage::{format_bytes, StoreKind};
+use luna::core::ElementBounds;
+use luna::overlay::inspector::InspectorLayer;
+use luna::utils::geometry::Point;
+use luna::input::demonstration::{self, DemonstrationRecorder, RecordOptions};
+use luna::core::{CancelToken, CommandResult, CommandSource};
+use luna::{ExecuteOptions, Luna, LunaConfig, LunaError};
 
 fn main() -> anyhow::Result<()> {
     let config = LunaConfig::default();
     config.apply_logging()?;
 
+    // `luna schema [dir]` and `luna inference-server [addr]` don't drive this
+    // desktop, so they run before an instance takes the input lease, starts
+    // its watchdog and sweeps storage
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("schema") => return write_schemas(args.get(1).map(String::as_str).unwrap_or("schemas")),
+        Some("inference-server") => return run_inference_server(args.get(1).map(String::as_str).unwrap_or("127.0.0.1:8700")),
+        _ => {}
+    }
+
     let mut luna = Luna::new(config)?;
 
+    // One-shot subcommands: `luna storage status|clean [store]`, `luna langs list|install|pin|unpin|remove`,
+    // `luna record [seconds]`, and `luna do|find|shot|watch|remember|anchors|forget|compare|explore|diagnose` (see cli.rs)
+    if let Some(first) = args.first() {
+        return match first.as_str() {
+            "storage" => run_storage_command(&luna, &args[1..]),
+            "langs" => run_langs_command(&luna, &args[1..]),
+            "record" => run_recording(&mut luna, args.get(1).map(String::as_str)),
+            "do" | "find" | "shot" | "watch" | "remember" | "anchors" | "forget" | "compare" | "explore" | "diagnose" | "env" => std::process::exit(cli::run(&mut luna, first, &args[1..])),
+            other => Err(anyhow::anyhow!(
+                "unknown subcommand '{}' (expected: do, find, shot, watch, remember, anchors, forget, compare, explore, diagnose, storage, langs, schema, inference-server, record)",
+                other
+            )),
+        };
+    }
+
+    let mut options = ExecuteOptions::default();
+    let mut sandbox: Option<Sandbox> = None;
+
     println!("LUNA prototype ({})", env!("CARGO_PKG_VERSION"));
     println!("Commands:");
     println!("  analyze            - capture and analyze the screen");
     println!("  stats              - show processing statistics");
+    println!("  capabilities       - show which subsystems work on this machine");
+    println!("  input              - show which LUNA instance owns input");
+    println!("  inspect [back|forward|N] - timeline of recent analyses (what Luna saw)");
+    println!("  inspect layer L    - toggle a layer: edges, candidates, text, scores, targets");
+    println!("  inspect at X Y     - properties of the element under a point");
+    println!("  inspect save FILE  - write the shown frame with its layers as PNG");
+    println!("  inspect archive [N] - frames in the archive; N puts one back on the timeline");
+    println!("  input take|release - request or give up input ownership");
+    println!("  spy                - what LUNA sees under the mouse cursor; copies its query");
+    println!("  suggest [N]        - commands worth trying on the current screen and window");
+    println!("  threshold [D [T]]  - set detection (and text) confidence; re-filters the last analysis");
+    println!("  threshold save     - write the current thresholds to the config file");
+    println!("  speed [P|N]        - show or set speed: demo, normal, fast or a multiplier");
+    println!("  safety             - list the safety rules in force");
+    println!("  safety test [SRC] C - which rules command C would trip, as sent by SRC");
+    println!("                       (interactive, scheduled, watcher or api); nothing runs");
+    println!("  safety add|remove P - add or remove a blocked pattern (regex); safety save keeps them");
+    println!("  pause | continue   - hold the next command before its first action, or stop holding");
+    println!("  break [K|QUERY]    - list breakpoints, or pause before every K action (click, type,");
+    println!("                       keys, ...) or before actions on elements matching QUERY");
+    println!("  unbreak [K|QUERY]  - remove a breakpoint (or all of them)");
+    println!("  supervise [C|off]  - hold actions on targets detected with confidence below C");
+    println!("                       (default 0.9) for approval; the rest run unattended");
+    println!("  region X Y W H     - only act on elements inside this region");
+    println!("  region clear       - remove the region constraint");
+    println!("  sandbox            - practice on a simulated desktop (real input is never used again");
+    println!("                       this session); again to show the scene and what happened");
+    println!("  storage status     - show disk usage per store");
+    println!("  storage clean [S]  - trim over-quota stores (or just store S)");
+    println!("  langs [list]       - OCR language packs, installed and in the catalog");
+    println!("  langs install|pin|unpin|remove L - download, keep through cleanup, or delete a pack");
+    println!("  review             - confirm or correct doubtful detections (review.enabled queues them)");
+    println!("  review thresholds  - detection thresholds learned per app from your verdicts");
     println!("  quit               - exit");
     println!("  anything else      - processed as an automation command,");
     println!("                       e.g. 'click the save button'");
+    println!("                       (or, after a question, as its answer)");
     println!();
 
-    let stdin = io::stdin();
+    let mut lines = spawn_line_reader();
     loop {
         print!("> ");
         io::stdout().flush()?;
 
-        let mut line = String::new();
-        if stdin.lock().read_line(&mut line)? == 0 {
+        let Some(line) = next_line(&mut luna, &lines)? else {
             break; // EOF
-        }
+        };
         let command = line.trim();
 
         match command {
@@ -66,20 +145,703 @@ fn main() -> anyhow::Result<()> {
             "stats" => {
                 let stats = luna.get_stats();
                 println!(
-                    "commands: {}, actions: {}, safety blocks: {}, avg time: {:.1}ms",
+                    "commands: {}, actions: {}, safety blocks: {}, direct: {}, avg time: {:.1}ms",
                     stats.commands_processed,
                     stats.actions_executed,
                     stats.safety_blocks,
+                    stats.pipeline_skips,
                     stats.average_processing_time_ms
                 );
+                if stats.frames_dropped > 0 {
+                    println!("continuous analysis dropped {} frame(s) to keep up", stats.frames_dropped);
+                }
+                if stats.total_cpu_time_ms > 0 || stats.peak_rss_bytes > 0 {
+                    println!(
+                        "cpu: {}ms total, peak rss: {}",
+                        stats.total_cpu_time_ms,
+                        format_bytes(stats.peak_rss_bytes)
+                    );
+                }
+                let startup: Vec<String> = luna
+                    .startup_times()
+                    .iter()
+                    .map(|(subsystem, took)| format!("{} {:.1}ms", subsystem, took.as_secs_f64() * 1000.0))
+                    .collect();
+                println!("startup: {}", startup.join(", "));
+            }
+            "capabilities" => println!("{}", luna.capabilities().summary()),
+            "input" => println!("{}", luna.input_ownership()),
+            "input take" => match luna.request_input_ownership() {
+                Ok(ownership) if ownership.can_inject() => println!("{}", ownership),
+                Ok(ownership) => println!("{} (requested; the owner hands over at its next command)", ownership),
+                Err(e) => eprintln!("Input request failed: {}", e),
+            },
+            "input release" => match luna.release_input_ownership() {
+                Ok(()) => println!("{}", luna.input_ownership()),
+                Err(e) => eprintln!("Input release failed: {}", e),
+            },
+            "spy" => match luna::input::cursor_position()
+                .ok_or_else(|| anyhow::anyhow!("cursor position is not available on this platform"))
+                .and_then(|cursor| luna.spy_at(cursor))
+            {
+                Ok(report) => {
+                    println!("{}", report);
+                    if let Some(element) = &report.element {
+                        if luna::input::copy_to_clipboard(&element.query) {
+                            println!("(query copied to the clipboard)");
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Spy failed: {}", e),
+            },
+            "sandbox" => {
+                let scene = sandbox.get_or_insert_with(|| {
+                    println!("Sandbox: commands now act on a simulated form. Try 'click Save', 'click the Name field',");
+                    println!("'type \"Ada\"', 'click the red circle'. 'inspect save FILE' writes what LUNA sees.");
+                    luna.enter_sandbox(SandboxScene::tutorial())
+                });
+                print!("{}", scene.scene());
+            }
+            "pause" => {
+                luna.step_control().pause();
+                println!("The next command pauses before its first action");
+            }
+            "continue" | "resume" => {
+                luna.step_control().resume();
+                println!("Commands run freely until a breakpoint");
+            }
+            "break" => {
+                let breakpoints = luna.step_control().state().breakpoints;
+                if breakpoints.is_empty() {
+                    println!("No breakpoints");
+                }
+                for breakpoint in breakpoints {
+                    println!("  {}", breakpoint);
+                }
+            }
+            "unbreak" => {
+                luna.step_control().clear_breakpoints();
+                println!("Breakpoints cleared");
+            }
+            _ if command.starts_with("break ") => {
+                let breakpoint = Breakpoint::parse(&command[6..]);
+                println!("Pausing {}", breakpoint);
+                luna.step_control().add_breakpoint(breakpoint);
             }
-            _ => match luna.process_command(command) {
-                Ok(actions) => println!("Executed {} action(s): {:?}", actions.len(), actions),
-                Err(e) => eprintln!("Command failed: {}", e),
+            "supervise" | "supervise off" => {
+                let below = (command == "supervise").then_some(SupervisionConfig::default().auto_above);
+                luna.step_control().supervise(below);
+                match below {
+                    Some(below) => println!("Actions on targets below {:.0}% confidence wait for approval", below * 100.0),
+                    None => println!("Actions run without approval"),
+                }
+            }
+            _ if command.starts_with("supervise ") => match command[10..].trim().parse::<f32>() {
+                Ok(below) if (0.0..=1.0).contains(&below) => {
+                    luna.step_control().supervise(Some(below));
+                    println!("Actions on targets below {:.0}% confidence wait for approval", below * 100.0);
+                }
+                _ => eprintln!("Usage: supervise [CONFIDENCE between 0 and 1|off]"),
             },
+            _ if command.starts_with("unbreak ") => {
+                let breakpoint = Breakpoint::parse(&command[8..]);
+                if !luna.step_control().remove_breakpoint(&breakpoint) {
+                    eprintln!("No breakpoint {}", breakpoint);
+                }
+            }
+            "region clear" => {
+                options.region_constraint = None;
+                println!("Region constraint cleared");
+            }
+            _ if command == "suggest" || command.starts_with("suggest ") => {
+                let count = match command["suggest".len()..].trim() {
+                    "" => Ok(5),
+                    n => n.parse::<usize>(),
+                };
+                match count {
+                    Ok(n) => {
+                        if let Err(e) = run_suggest(&mut luna, n) {
+                            eprintln!("Suggest failed: {}", e);
+                        }
+                    }
+                    Err(_) => eprintln!("Usage: suggest [N]"),
+                }
+            }
+            _ if command.starts_with("inspect") => {
+                let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+                if let Err(e) = run_inspect_command(&mut luna, &args) {
+                    eprintln!("Inspect command failed: {}", e);
+                }
+            }
+            _ if command.starts_with("speed") => {
+                match command.split_whitespace().nth(1) {
+                    None => println!("speed {}x", luna.speed()),
+                    Some(value) => match SpeedPreset::from_name(value).map(SpeedPreset::multiplier).or_else(|| value.parse().ok()) {
+                        Some(multiplier) => match luna.set_speed(multiplier) {
+                            Ok(()) => println!("speed {}x", multiplier),
+                            Err(e) => eprintln!("Speed change failed: {}", e),
+                        },
+                        None => eprintln!("Usage: speed [demo|normal|fast|MULTIPLIER]"),
+                    },
+                }
+            }
+            _ if command.starts_with("threshold") => {
+                let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+                if let Err(e) = run_threshold_command(&mut luna, &args) {
+                    eprintln!("Threshold command failed: {}", e);
+                }
+            }
+            _ if command == "safety" || command.starts_with("safety ") => {
+                if let Err(e) = run_safety_command(&mut luna, command["safety".len()..].trim()) {
+                    eprintln!("Safety command failed: {}", e);
+                }
+            }
+            _ if command.starts_with("storage") => {
+                let args: Vec<String> = command.split_whitespace().skip(1).map(String::from).collect();
+                if let Err(e) = run_storage_command(&luna, &args) {
+                    eprintln!("Storage command failed: {}", e);
+                }
+            }
+            "review" => {
+                if let Err(e) = run_review(&mut luna, &lines) {
+                    eprintln!("Review failed: {}", e);
+                }
+            }
+            "review thresholds" => {
+                let thresholds = luna.learned_app_thresholds();
+                if thresholds.is_empty() {
+                    println!("No app has enough verdicts yet");
+                }
+                for (app, threshold) in thresholds {
+                    println!("  {:.2}  {}", threshold, if app.is_empty() { "(unknown app)" } else { &app });
+                }
+            }
+            _ if command.starts_with("langs") => {
+                let args: Vec<String> = command.split_whitespace().skip(1).map(String::from).collect();
+                if let Err(e) = run_langs_command(&luna, &args) {
+                    eprintln!("Language pack command failed: {}", e);
+                }
+            }
+            _ if command.starts_with("region ") => {
+                let values: Vec<i32> = command[7..]
+                    .split_whitespace()
+                    .filter_map(|v| v.parse().ok())
+                    .collect();
+                match values.as_slice() {
+                    &[x, y, width, height] if width > 0 && height > 0 => {
+                        options.region_constraint = Some(ElementBounds::new(x, y, width, height));
+                        println!("Commands constrained to {}x{} at ({}, {})", width, height, x, y);
+                    }
+                    _ => eprintln!("Usage: region X Y W H  |  region clear"),
+                }
+            }
+            _ => {
+                // A line that picks one of the offered options answers the pending question
+                let answers = luna.pending_clarification().is_some_and(|c| c.resolve(command).is_some());
+                let outcome = run_debugged(&mut luna, &mut lines, &options, |luna, options| {
+                    if answers {
+                        luna.answer_clarification(command, options)
+                    } else {
+                        luna.execute_command(command, options)
+                    }
+                });
+                match outcome {
+                    Ok(result) => {
+                        println!(
+                            "Executed {} action(s) in {}ms{}: {:?}",
+                            result.actions.len(),
+                            result.processing_time_ms,
+                            if result.pipeline_skipped { " (direct, no screen analysis)" } else { "" },
+                            result.actions
+                        );
+                        if let Some(escalation) = &result.escalation {
+                            println!("  {}", escalation);
+                        }
+                        if let Some(resources) = &result.resources {
+                            println!("  {}", resources);
+                        }
+                    }
+                    Err(_) if luna.pending_clarification().is_some() => {
+                        let clarification = luna.pending_clarification().expect("checked above");
+                        println!("{}", clarification.question);
+                        for (number, option) in clarification.options.iter().enumerate() {
+                            println!("  {}. {}", number + 1, option.label);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Command failed: {}", e);
+                        if let Some(no_match) = e.downcast_ref::<LunaError>().and_then(LunaError::no_match) {
+                            for candidate in &no_match.candidates {
+                                eprintln!("  considered {}", candidate);
+                            }
+                        }
+                    }
+                }
+            }
         }
     }
 
     println!("Bye.");
     Ok(())
 }
+
+/// Run a command, prompting whenever it pauses before an action: `step`
+/// (or Enter) runs that one action, `continue` runs on to the next
+/// breakpoint and `abort` cancels the command. An action held by
+/// supervision is answered with `approve` (or Enter) or `reject`.
+fn run_debugged(
+    luna: &mut Luna,
+    lines: &mut mpsc::Receiver<io::Result<String>>,
+    options: &ExecuteOptions,
+    run: impl FnOnce(&mut Luna, &ExecuteOptions) -> anyhow::Result<CommandResult>,
+) -> anyhow::Result<CommandResult> {
+    let control = luna.step_control();
+    let token = CancelToken::new();
+    let options = ExecuteOptions { cancel: Some(token.clone()), ..options.clone() };
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let (control, token, done) = (&control, &token, &done);
+        scope.spawn(move || {
+            while !done.load(Ordering::SeqCst) {
+                let Some(paused) = control.wait_until_paused(Duration::from_millis(100)) else {
+                    continue;
+                };
+                // Supervision holds an uncertain action for approval; anything else is a debugger stop
+                let choices = match paused.confidence {
+                    Some(confidence) => {
+                        print!("Approve action {} ({:.0}% sure of the target): {}", paused.index + 1, confidence * 100.0, paused.action);
+                        "approve, reject, continue or abort"
+                    }
+                    None => {
+                        let reason = paused.breakpoint.as_ref().map(|b| format!(" ({})", b)).unwrap_or_default();
+                        print!("Paused before action {}: {}{}", paused.index + 1, paused.action, reason);
+                        "step, continue or abort"
+                    }
+                };
+                print!("\n[{}]> ", choices.replace(", ", "|").replace(" or ", "|"));
+                let _ = io::stdout().flush();
+                let held = paused.confidence.is_some();
+                match lines.recv() {
+                    Ok(Ok(line)) if !line.is_empty() => match (line.trim(), held) {
+                        ("" | "step" | "s", false) => control.step(),
+                        ("" | "approve" | "a" | "y", true) => {
+                            control.approve();
+                        }
+                        ("reject" | "r" | "n", true) => {
+                            control.reject();
+                        }
+                        ("continue" | "c", _) => control.resume(),
+                        ("abort", _) => token.cancel(),
+                        (other, _) => {
+                            println!("'{}'? Enter {}", other, choices);
+                            continue;
+                        }
+                    },
+                    // End of input: nobody is left to resume it
+                    _ => token.cancel(),
+                }
+                // Don't prompt again for the action just released
+                while !done.load(Ordering::SeqCst) && control.state().waiting.as_ref() == Some(&paused) {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+        });
+        let outcome = run(luna, &options);
+        done.store(true, Ordering::SeqCst);
+        outcome
+    })
+}
+
+/// Read stdin on its own thread, so the REPL can do idle work while waiting
+fn spawn_line_reader() -> mpsc::Receiver<io::Result<String>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = io::stdin();
+        loop {
+            let mut line = String::new();
+            let read = stdin.lock().read_line(&mut line);
+            let eof = matches!(read, Ok(0) | Err(_));
+            if sender.send(read.map(|_| line)).is_err() || eof {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// The next line typed, `None` at end of input. With pre-analysis enabled the
+/// screen is analyzed whenever nothing is typed for the idle time.
+fn next_line(luna: &mut Luna, lines: &mpsc::Receiver<io::Result<String>>) -> io::Result<Option<String>> {
+    loop {
+        let pre_analysis = &luna.get_config().pre_analysis;
+        let received = if pre_analysis.enabled {
+            match lines.recv_timeout(Duration::from_millis(pre_analysis.idle_ms)) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Err(e) = luna.pre_analyze() {
+                        log::debug!("Idle pre-analysis failed: {}", e);
+                    }
+                    continue;
+                }
+                received => received.ok(),
+            }
+        } else {
+            lines.recv().ok()
+        };
+        return match received {
+            Some(Ok(line)) if !line.is_empty() => Ok(Some(line)),
+            Some(Err(e)) => Err(e),
+            _ => Ok(None),
+        };
+    }
+}
+
+/// Walk the review queue: `y` confirms a detection, `n` rejects it, an
+/// element type relabels it, `s` skips it and `q` stops
+fn run_review(luna: &mut Luna, lines: &mpsc::Receiver<io::Result<String>>) -> anyhow::Result<()> {
+    let items = luna.review_queue().to_vec();
+    if items.is_empty() {
+        println!("Nothing to review");
+        return Ok(());
+    }
+    for (number, item) in items.iter().enumerate() {
+        println!("[{}/{}] {}", number + 1, items.len(), item);
+        if let Some(path) = luna.review_crop_path(item.id) {
+            println!("  crop: {}", path.display());
+        }
+        let verdict = loop {
+            print!("  correct? [y]es, [n]o, a type to relabel as, [s]kip, [q]uit: ");
+            io::stdout().flush()?;
+            let Some(answer) = next_line(luna, lines)? else {
+                return Ok(());
+            };
+            match answer.trim() {
+                "s" | "skip" => break None,
+                "q" | "quit" => return Ok(()),
+                answer => match Verdict::parse(answer) {
+                    Some(verdict) => break Some(verdict),
+                    None => continue,
+                },
+            }
+        };
+        if let Some(verdict) = verdict {
+            let outcome = luna.resolve_review(item.id, verdict)?;
+            if outcome.exported {
+                println!("  saved as a correction sample");
+            }
+            if let Some(threshold) = outcome.threshold {
+                println!("  detection threshold for this app: {:.2}", threshold);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_inspect_command(luna: &mut Luna, args: &[&str]) -> anyhow::Result<()> {
+    match args {
+        ["archive"] => return list_archived_frames(luna),
+        ["archive", index] => luna.inspect_archived(index.parse()?)?,
+        _ => {}
+    }
+    let inspector = luna.inspector_mut();
+    match args {
+        [] | ["archive", _] => {}
+        ["back"] => {
+            inspector.step(-1);
+        }
+        ["forward"] => {
+            inspector.step(1);
+        }
+        ["layer", name] => {
+            let layer = InspectorLayer::from_name(name).ok_or_else(|| anyhow::anyhow!("unknown layer '{}'", name))?;
+            let shown = inspector.toggle_layer(layer);
+            println!("Layer {} {}", layer.name(), if shown { "shown" } else { "hidden" });
+            return Ok(());
+        }
+        ["at", x, y] => {
+            let point = Point::new(x.parse()?, y.parse()?);
+            match inspector.select_at(point) {
+                Some(index) => {
+                    for (key, value) in inspector.properties(index) {
+                        println!("  {:<12} {}", key, value);
+                    }
+                }
+                None => println!("No element at ({}, {})", x, y),
+            }
+            return Ok(());
+        }
+        ["save", path] => {
+            let image = inspector.compose().ok_or_else(|| anyhow::anyhow!("nothing analyzed yet"))?;
+            cli::save_png(&image, std::path::Path::new(path))?;
+            println!("Saved {}", path);
+            return Ok(());
+        }
+        [index] => {
+            inspector.scrub(index.parse()?);
+        }
+        _ => return Err(anyhow::anyhow!("usage: inspect [back|forward|N|layer L|at X Y|save FILE|archive [N]]")),
+    }
+
+    let layers: Vec<&str> = InspectorLayer::ALL
+        .iter()
+        .filter(|layer| inspector.is_layer_enabled(**layer))
+        .map(|layer| layer.name())
+        .collect();
+    println!("Layers: {}", layers.join(", "));
+    for (index, frame) in inspector.timeline().enumerate() {
+        println!(
+            "{} {:>2}: '{}' {} element(s), {} action(s)",
+            if index == inspector.position() { ">" } else { " " },
+            index,
+            frame.command,
+            frame.analysis.elements.len(),
+            frame.actions.len()
+        );
+    }
+    Ok(())
+}
+
+/// The most recent frames in the archive, with how well it compresses
+fn list_archived_frames(luna: &Luna) -> anyhow::Result<()> {
+    const SHOWN: usize = 20;
+    let reader = luna.archived_frames()?;
+    let entries = reader.entries();
+    for (index, entry) in entries.iter().enumerate().skip(entries.len().saturating_sub(SHOWN)) {
+        println!(
+            "  {:>4}: {} {}x{} {:<5} {}",
+            index,
+            entry.command_id.as_deref().unwrap_or("-"),
+            entry.width,
+            entry.height,
+            if entry.keyframe { "key" } else { "delta" },
+            format_bytes(entry.bytes)
+        );
+    }
+    let (raw, stored) = reader.sizes();
+    println!("{} frame(s), {} stored for {} decoded; 'inspect archive N' analyzes one again", entries.len(), format_bytes(stored), format_bytes(raw));
+    Ok(())
+}
+
+/// Text stand-in for the threshold slider: set values, see the element count
+/// change on the last analysis, and the counts other values would give
+fn run_threshold_command(luna: &mut Luna, args: &[&str]) -> anyhow::Result<()> {
+    let current = luna.runtime_thresholds();
+    let thresholds = match args {
+        [] => current,
+        ["save"] => {
+            println!("Saved thresholds to {}", luna.persist_thresholds()?.display());
+            return Ok(());
+        }
+        [detection] => ConfidenceThresholds { detection: detection.parse()?, ..current },
+        [detection, text] => ConfidenceThresholds { detection: detection.parse()?, text: Some(text.parse()?) },
+        _ => return Err(anyhow::anyhow!("usage: threshold [DETECTION [TEXT]] | threshold save")),
+    };
+
+    let refiltered = luna.set_runtime_thresholds(thresholds)?;
+    let text = thresholds.text.map_or("same".to_string(), |t| format!("{:.2}", t));
+    match refiltered {
+        Some(analysis) => println!(
+            "detection {:.2}, text {}: {} element(s) in the last analysis",
+            thresholds.detection,
+            text,
+            analysis.elements.len()
+        ),
+        None => println!("detection {:.2}, text {} (nothing analyzed yet)", thresholds.detection, text),
+    }
+    let levels: Vec<f32> = (1..10).map(|step| step as f32 / 10.0).collect();
+    let sweep: Vec<String> = luna
+        .threshold_sweep(&levels)
+        .into_iter()
+        .map(|(level, count)| format!("{:.1}:{}", level, count))
+        .collect();
+    println!("  elements by detection threshold: {}", sweep.join("  "));
+    Ok(())
+}
+
+/// List, dry-test and edit the safety rules. Patterns are whole regexes, so
+/// everything after the subcommand is taken as is.
+fn run_safety_command(luna: &mut Luna, args: &str) -> anyhow::Result<()> {
+    let (subcommand, rest) = args.split_once(' ').map_or((args, ""), |(s, rest)| (s, rest.trim()));
+    match subcommand {
+        "" => {
+            for rule in luna.safety_rules() {
+                println!("  [{}] {}: {}", rule.category, rule.name, rule.description);
+            }
+        }
+        "test" if !rest.is_empty() => {
+            let (source, command) = match rest.split_once(' ') {
+                Some((name, command)) => match CommandSource::ALL.into_iter().find(|s| s.name() == name) {
+                    Some(source) => (source, command.trim()),
+                    None => (CommandSource::Interactive, rest),
+                },
+                None => (CommandSource::Interactive, rest),
+            };
+            let test = luna.test_safety(command, source);
+            for hit in &test.command {
+                println!("  {}", hit);
+            }
+            for (action, hits) in &test.actions {
+                println!("  {:?}", action);
+                for hit in hits {
+                    println!("    {}", hit);
+                }
+            }
+            if test.is_clear() {
+                println!("  no rule fires for {} commands", source.name());
+            }
+        }
+        "add" | "remove" if !rest.is_empty() => {
+            let mut config = luna.get_config().clone();
+            let patterns = &mut config.safety.blocked_patterns;
+            if subcommand == "add" {
+                patterns.push(rest.to_string());
+            } else if let Some(index) = patterns.iter().position(|p| p == rest) {
+                patterns.remove(index);
+            } else {
+                return Err(anyhow::anyhow!("no blocked pattern '{}' (built-in patterns can't be removed)", rest));
+            }
+            luna.update_config(config)?;
+            println!("  {} blocked pattern(s) configured", luna.get_config().safety.blocked_patterns.len());
+        }
+        "save" => println!("Saved blocked patterns to {}", luna.persist_safety_patterns()?.display()),
+        _ => return Err(anyhow::anyhow!("usage: safety | safety test [SOURCE] COMMAND | safety add|remove PATTERN | safety save")),
+    }
+    Ok(())
+}
+
+fn run_suggest(luna: &mut Luna, n: usize) -> anyhow::Result<()> {
+    let suggestions = luna.suggest_commands(n)?;
+    if suggestions.is_empty() {
+        println!("Nothing to suggest for this screen");
+    }
+    for (number, suggestion) in suggestions.iter().enumerate() {
+        let recent = if suggestion.source == SuggestionSource::History { "  (recent)" } else { "" };
+        println!("  {}. {}{}", number + 1, suggestion.command, recent);
+    }
+    Ok(())
+}
+
+fn run_storage_command(luna: &Luna, args: &[String]) -> anyhow::Result<()> {
+    match args.first().map(String::as_str) {
+        None | Some("status") => {
+            for store in luna.storage_status()? {
+                let quota = if store.quota_bytes == 0 {
+                    "unlimited".to_string()
+                } else {
+                    format!("{} ({:.0}%)", format_bytes(store.quota_bytes), store.usage_ratio() * 100.0)
+                };
+                println!(
+                    "  {:<12} {:>10} of {:<18} {} file(s)  {}",
+                    store.kind,
+                    format_bytes(store.used_bytes),
+                    quota,
+                    store.file_count,
+                    store.path.display()
+                );
+            }
+            let reclaimed = luna.reclaimed_space();
+            println!(
+                "  temporary files removed: {} ({}) left by unfinished operations, {} ({}) by earlier runs",
+                reclaimed.abandoned.files,
+                format_bytes(reclaimed.abandoned.bytes),
+                reclaimed.orphaned.files,
+                format_bytes(reclaimed.orphaned.bytes)
+            );
+            Ok(())
+        }
+        Some("clean") => {
+            let store = match args.get(1) {
+                Some(name) => Some(
+                    StoreKind::from_name(name)
+                        .ok_or_else(|| anyhow::anyhow!("unknown store '{}'", name))?,
+                ),
+                None => None,
+            };
+            for (kind, report) in luna.clean_storage(store)? {
+                println!(
+                    "  {:<12} removed {} file(s), freed {}",
+                    kind,
+                    report.files_removed,
+                    format_bytes(report.bytes_freed)
+                );
+            }
+            Ok(())
+        }
+        Some(other) => Err(anyhow::anyhow!("unknown storage command '{}' (expected: status, clean)", other)),
+    }
+}
+
+fn run_langs_command(luna: &Luna, args: &[String]) -> anyhow::Result<()> {
+    let language = || args.get(1).map(String::as_str).ok_or_else(|| anyhow::anyhow!("which language? (e.g. 'langs install de')"));
+    match args.first().map(String::as_str) {
+        None | Some("list") => {
+            let packs = luna.list_language_packs()?;
+            if packs.is_empty() {
+                println!("  no language packs installed and no catalog configured (ocr.catalog_url)");
+            }
+            for pack in packs {
+                println!("  {}", pack);
+            }
+            Ok(())
+        }
+        Some("install") => {
+            let pack = luna.install_language_pack(language()?)?;
+            println!("  installed {} {} ({})", pack.name, pack.version, format_bytes(pack.size_bytes));
+            Ok(())
+        }
+        Some(action @ ("pin" | "unpin")) => {
+            luna.pin_language_pack(language()?, action == "pin")?;
+            println!("  {}ned {}", action, language()?);
+            Ok(())
+        }
+        Some("remove") => {
+            match luna.remove_language_pack(language()?)? {
+                true => println!("  removed {}", language()?),
+                false => println!("  {} is not installed", language()?),
+            }
+            Ok(())
+        }
+        Some(other) => Err(anyhow::anyhow!("unknown langs command '{}' (expected: list, install, pin, unpin, remove)", other)),
+    }
+}
+
+/// Write the JSON Schema of every versioned document to `dir`
+fn write_schemas(dir: &str) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (name, schema) in luna::core::schema::json_schemas() {
+        let path = std::path::Path::new(dir).join(name);
+        std::fs::write(&path, serde_json::to_string_pretty(&schema)? + "\n")?;
+        println!("  wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Serve element detection to other machines with the local detector.
+/// Clients must send the token from `LUNA_INFERENCE_TOKEN`, when set.
+fn run_inference_server(addr: &str) -> anyhow::Result<()> {
+    let token = std::env::var("LUNA_INFERENCE_TOKEN").ok().filter(|token| !token.is_empty());
+    let server = InferenceServer::bind(addr, token)?;
+    println!("Inference server listening on {}", server.local_addr()?);
+    server.serve(&mut VisionProcessor::new())
+}
+
+/// Record a demonstration from the global input hook and print a script draft
+fn run_recording(luna: &mut Luna, seconds: Option<&str>) -> anyhow::Result<()> {
+    let mut options = RecordOptions::default();
+    if let Some(seconds) = seconds {
+        let seconds: u64 = seconds.parse().map_err(|_| anyhow::anyhow!("invalid duration '{}'", seconds))?;
+        options.max_duration = std::time::Duration::from_secs(seconds);
+    }
+
+    let screen_size = luna.analyze_current_screen()?.screen_size;
+    let mut hook = demonstration::global_hook(screen_size)?;
+    eprintln!(
+        "Recording for up to {}s; press {} to stop.",
+        options.max_duration.as_secs(),
+        options.stop_key
+    );
+
+    let mut recorder = DemonstrationRecorder::new();
+    recorder.record_from(hook.as_mut(), &options, || luna.analyze_current_screen().ok());
+    print!("{}", recorder.finish().to_script());
+    Ok(())
+}
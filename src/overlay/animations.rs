@@ -404,7 +404,6 @@ pub fn create_pulse_animation(duration: Duration) -> AnimationBuilder {
 // Animation sequence builder
 pub struct AnimationSequence {
     steps: Vec<AnimationStep>,
-    current_step: usize,
     total_duration: Duration,
 }
 
@@ -420,7 +419,6 @@ impl AnimationSequence {
     pub fn new() -> Self {
         Self {
             steps: Vec::new(),
-            current_step: 0,
             total_duration: Duration::from_millis(0),
         }
     }
@@ -446,10 +444,10 @@ impl AnimationSequence {
         let mut current_time = Duration::from_millis(0);
         
         for step in &self.steps {
-            if elapsed_time >= current_time && elapsed_time < current_time + step.delay + step.duration {
-                if elapsed_time >= current_time + step.delay {
-                    return Some((&step.animation, step.easing));
-                }
+            if elapsed_time >= current_time + step.delay
+                && elapsed_time < current_time + step.delay + step.duration
+            {
+                return Some((&step.animation, step.easing));
             }
             current_time += step.delay + step.duration;
         }
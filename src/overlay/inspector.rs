@@ -0,0 +1,380 @@
+// "What did Luna see" inspector: the last few analyzed frames with toggleable
+// layers (edges, candidate rects, text boxes, scores, final targets), element
+// properties for the hovered or selected element, and a timeline to scrub
+// back through earlier analyses.
+
+use super::rendering::Renderer;
+use super::{Color, OverlayConfig, OverlayManager};
+use crate::core::{LunaAction, ScreenAnalysis, ScreenElement};
+use crate::utils::geometry::{Point, Rectangle};
+use crate::utils::image_processing::{sobel_edge_detection, Image};
+use std::collections::{BTreeSet, VecDeque};
+use std::time::SystemTime;
+
+/// Sobel magnitude above which a pixel is drawn on the edges layer
+const EDGE_THRESHOLD: u8 = 64;
+/// Radius of the marker drawn at each click target
+const TARGET_RADIUS: f64 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InspectorLayer {
+    /// Sobel edges the detector worked from
+    Edges,
+    /// Every detected element
+    Candidates,
+    /// Elements carrying recognized text
+    TextBoxes,
+    /// Detection confidence next to each element
+    Scores,
+    /// Points the planned actions click
+    Targets,
+}
+
+impl InspectorLayer {
+    pub const ALL: [InspectorLayer; 5] = [
+        InspectorLayer::Edges,
+        InspectorLayer::Candidates,
+        InspectorLayer::TextBoxes,
+        InspectorLayer::Scores,
+        InspectorLayer::Targets,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InspectorLayer::Edges => "edges",
+            InspectorLayer::Candidates => "candidates",
+            InspectorLayer::TextBoxes => "text",
+            InspectorLayer::Scores => "scores",
+            InspectorLayer::Targets => "targets",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|layer| layer.name() == name)
+    }
+}
+
+/// One analysis as the planner saw it
+#[derive(Debug, Clone)]
+pub struct InspectorFrame {
+    pub command: String,
+    pub frame: Image,
+    pub analysis: ScreenAnalysis,
+    pub actions: Vec<LunaAction>,
+    pub captured_at: SystemTime,
+}
+
+/// Timeline of recent analyses plus the view state of the inspector panel
+pub struct Inspector {
+    history: VecDeque<InspectorFrame>,
+    capacity: usize,
+    /// Index into `history` being shown
+    position: usize,
+    layers: BTreeSet<InspectorLayer>,
+    selected: Option<usize>,
+}
+
+impl Inspector {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            history: VecDeque::new(),
+            capacity,
+            position: 0,
+            layers: [InspectorLayer::Candidates, InspectorLayer::Targets].into_iter().collect(),
+            selected: None,
+        }
+    }
+
+    /// Add an analysis and jump the timeline to it
+    pub fn record(&mut self, frame: InspectorFrame) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.history.len() >= self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+        self.position = self.history.len() - 1;
+        self.selected = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    /// Recorded analyses, oldest first
+    pub fn timeline(&self) -> impl Iterator<Item = &InspectorFrame> {
+        self.history.iter()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn current(&self) -> Option<&InspectorFrame> {
+        self.history.get(self.position)
+    }
+
+    /// Show the analysis at `index` (clamped to the timeline)
+    pub fn scrub(&mut self, index: usize) -> Option<&InspectorFrame> {
+        let index = index.min(self.history.len().saturating_sub(1));
+        if index != self.position {
+            self.position = index;
+            self.selected = None;
+        }
+        self.current()
+    }
+
+    /// Move `delta` analyses forward (positive) or back (negative)
+    pub fn step(&mut self, delta: isize) -> Option<&InspectorFrame> {
+        self.scrub(self.position.saturating_add_signed(delta))
+    }
+
+    /// Show the latest analysis captured at or before `time`
+    pub fn scrub_to_time(&mut self, time: SystemTime) -> Option<&InspectorFrame> {
+        let index = self.history.iter().rposition(|frame| frame.captured_at <= time)?;
+        self.scrub(index)
+    }
+
+    pub fn is_layer_enabled(&self, layer: InspectorLayer) -> bool {
+        self.layers.contains(&layer)
+    }
+
+    /// Flip a layer on or off; returns whether it is now shown
+    pub fn toggle_layer(&mut self, layer: InspectorLayer) -> bool {
+        if !self.layers.remove(&layer) {
+            self.layers.insert(layer);
+        }
+        self.is_layer_enabled(layer)
+    }
+
+    /// Innermost element under `point` in the shown analysis (hover)
+    pub fn element_at(&self, point: Point) -> Option<usize> {
+        let elements = &self.current()?.analysis.elements;
+        elements
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| Rectangle::from(&e.bounds).contains_point(&point))
+            .min_by(|a, b| Rectangle::from(&a.1.bounds).area().total_cmp(&Rectangle::from(&b.1.bounds).area()))
+            .map(|(index, _)| index)
+    }
+
+    /// Select the element under `point` (click); `None` clears the selection
+    pub fn select_at(&mut self, point: Point) -> Option<usize> {
+        self.selected = self.element_at(point);
+        self.selected
+    }
+
+    pub fn selected_element(&self) -> Option<&ScreenElement> {
+        self.current()?.analysis.elements.get(self.selected?)
+    }
+
+    /// Side-panel rows for an element of the shown analysis
+    pub fn properties(&self, index: usize) -> Vec<(String, String)> {
+        let Some(frame) = self.current() else {
+            return Vec::new();
+        };
+        let Some(element) = frame.analysis.elements.get(index) else {
+            return Vec::new();
+        };
+        let b = &element.bounds;
+        let mut rows = vec![
+            ("index".to_string(), index.to_string()),
+            ("type".to_string(), element.element_type.clone()),
+            ("bounds".to_string(), format!("{}x{} at ({}, {})", b.width, b.height, b.x, b.y)),
+            ("confidence".to_string(), format!("{:.2}", element.confidence)),
+        ];
+        if let Some(text) = &element.text {
+            rows.push(("text".to_string(), text.clone()));
+        }
+        if let Some(parent) = element.parent {
+            rows.push(("parent".to_string(), format!("{} ({})", parent, frame.analysis.elements[parent].element_type)));
+        }
+        if !element.children.is_empty() {
+            rows.push(("children".to_string(), element.children.len().to_string()));
+        }
+        let targeted = frame.actions.iter().any(|action| action.click_point().is_some_and(|(x, y)| b.contains_point(x, y)));
+        rows.push(("targeted".to_string(), targeted.to_string()));
+        let mut attributes: Vec<_> = element.attributes.iter().collect();
+        attributes.sort();
+        rows.extend(attributes.into_iter().map(|(k, v)| (k.clone(), v.clone())));
+        rows
+    }
+
+    /// The shown frame with the enabled layers drawn over it
+    pub fn compose(&self) -> Option<Image> {
+        let frame = self.current()?;
+        let mut image = to_rgb(&frame.frame);
+
+        if self.is_layer_enabled(InspectorLayer::Edges) {
+            let edges = sobel_edge_detection(&frame.frame);
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    if edges.get_pixel(x, y).is_some_and(|p| p[0] >= EDGE_THRESHOLD) {
+                        image.set_pixel(x, y, &[0, 220, 255]);
+                    }
+                }
+            }
+        }
+
+        let mut overlay = OverlayManager::new(OverlayConfig { enable_animations: false, ..OverlayConfig::default() });
+        for (index, element) in frame.analysis.elements.iter().enumerate() {
+            let bounds = Rectangle::from(&element.bounds);
+            if self.is_layer_enabled(InspectorLayer::Candidates) {
+                match &element.shape {
+                    Some(shape) => overlay.add_polygon(shape, Color::rgba(0, 255, 0, 40), None),
+                    None => overlay.add_highlight(bounds, Color::rgba(0, 255, 0, 40), None),
+                };
+            }
+            if self.is_layer_enabled(InspectorLayer::Candidates) && element.occlusion() > 0.0 {
+                overlay.add_hatched(bounds, Color::rgba(255, 80, 80, 110), Some(format!("{:.0}% hidden", element.occlusion() * 100.0)));
+            }
+            if self.is_layer_enabled(InspectorLayer::TextBoxes) && element.text.is_some() {
+                overlay.add_highlight(bounds, Color::rgba(0, 120, 255, 60), None);
+            }
+            if self.is_layer_enabled(InspectorLayer::Scores) {
+                let position = Point::new(bounds.x, (bounds.y - 14.0).max(0.0));
+                overlay.add_label(position, format!("{:.2}", element.confidence), Color::rgb(255, 255, 0));
+            }
+            if self.selected == Some(index) {
+                overlay.add_highlight(bounds, Color::rgba(255, 200, 0, 90), None);
+            }
+        }
+        if self.is_layer_enabled(InspectorLayer::Targets) {
+            for action in &frame.actions {
+                if let Some((x, y)) = action.click_point() {
+                    overlay.add_circle(Point::new(x as f64, y as f64), TARGET_RADIUS, Color::rgb(255, 0, 0));
+                }
+            }
+        }
+
+        let layer = Renderer::new(image.width, image.height).render_overlay(&overlay.get_visible_elements()).ok()?;
+        blend(&mut image, &layer);
+        Some(image)
+    }
+}
+
+pub(super) fn to_rgb(image: &Image) -> Image {
+    let mut rgb = Image::new(image.width, image.height, 3);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if let Some(p) = image.get_pixel(x, y) {
+                let pixel = if p.len() < 3 { [p[0]; 3] } else { [p[0], p[1], p[2]] };
+                rgb.set_pixel(x, y, &pixel);
+            }
+        }
+    }
+    rgb
+}
+
+/// Alpha-blend an RGBA layer onto an RGB image of the same size
+pub(super) fn blend(image: &mut Image, layer: &Image) {
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (Some(base), Some(top)) = (image.get_pixel(x, y), layer.get_pixel(x, y)) else {
+                continue;
+            };
+            let alpha = top[3] as u32;
+            if alpha == 0 {
+                continue;
+            }
+            let mix = |b: u8, t: u8| ((b as u32 * (255 - alpha) + t as u32 * alpha) / 255) as u8;
+            let pixel = [mix(base[0], top[0]), mix(base[1], top[1]), mix(base[2], top[2])];
+            image.set_pixel(x, y, &pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ElementBounds;
+    use std::collections::HashMap;
+
+    fn element(element_type: &str, bounds: ElementBounds, text: Option<&str>) -> ScreenElement {
+        ScreenElement {
+            element_type: element_type.to_string(),
+            bounds,
+            shape: None,
+            confidence: 0.8,
+            text: text.map(String::from),
+            attributes: HashMap::new(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn frame(command: &str) -> InspectorFrame {
+        let mut analysis = ScreenAnalysis {
+            elements: vec![
+                element("dialog", ElementBounds::new(0, 0, 100, 80), None),
+                element("button", ElementBounds::new(10, 40, 40, 20), Some("OK")),
+            ],
+            confidence: 0.8,
+            processing_time_ms: 5,
+            screen_size: (120, 100),
+            context: Default::default(),
+        };
+        analysis.link_hierarchy();
+        InspectorFrame {
+            command: command.to_string(),
+            frame: Image::from_rgb_data(120, 100, vec![200; 120 * 100 * 3]),
+            analysis,
+            actions: vec![LunaAction::Click { x: 30, y: 50 }],
+            captured_at: SystemTime::now(),
+        }
+    }
+
+    #[test]
+    fn test_timeline_keeps_last_n_and_scrubs() {
+        let mut inspector = Inspector::new(2);
+        assert!(inspector.compose().is_none());
+        for command in ["click a", "click b", "click c"] {
+            inspector.record(frame(command));
+        }
+        assert_eq!(inspector.len(), 2);
+        assert_eq!(inspector.current().unwrap().command, "click c");
+        assert_eq!(inspector.step(-5).unwrap().command, "click b");
+        assert_eq!(inspector.step(1).unwrap().command, "click c");
+        assert_eq!(inspector.scrub(9).unwrap().command, "click c");
+    }
+
+    #[test]
+    fn test_hover_selection_and_properties() {
+        let mut inspector = Inspector::new(4);
+        inspector.record(frame("click ok"));
+
+        assert_eq!(inspector.element_at(Point::new(20.0, 50.0)), Some(1));
+        assert_eq!(inspector.element_at(Point::new(90.0, 10.0)), Some(0));
+        assert_eq!(inspector.select_at(Point::new(20.0, 50.0)), Some(1));
+        assert_eq!(inspector.selected_element().unwrap().element_type, "button");
+
+        let rows: HashMap<_, _> = inspector.properties(1).into_iter().collect();
+        assert_eq!(rows["text"], "OK");
+        assert_eq!(rows["parent"], "0 (dialog)");
+        assert_eq!(rows["targeted"], "true");
+        assert!(inspector.select_at(Point::new(110.0, 90.0)).is_none());
+        assert!(inspector.selected_element().is_none());
+    }
+
+    #[test]
+    fn test_layers_change_the_composed_image() {
+        let mut inspector = Inspector::new(1);
+        inspector.record(frame("click ok"));
+        let target = |image: &Image| image.get_pixel(30 + TARGET_RADIUS as usize, 50).unwrap().to_vec();
+
+        let with_targets = inspector.compose().unwrap();
+        assert_eq!(with_targets.channels, 3);
+        assert_ne!(target(&with_targets), [200, 200, 200]);
+
+        assert!(!inspector.toggle_layer(InspectorLayer::Targets));
+        assert!(!inspector.toggle_layer(InspectorLayer::Candidates));
+        let plain = inspector.compose().unwrap();
+        assert_eq!(plain.data, frame("").frame.data);
+        assert_eq!(InspectorLayer::from_name("text"), Some(InspectorLayer::TextBoxes));
+    }
+}
@@ -1,13 +1,22 @@
 // Visual overlay system with minimal dependencies
 // Custom implementation for drawing UI overlays without heavy GUI frameworks
 
-use crate::utils::geometry::{Point, Rectangle};
+use crate::core::spy::SpyReport;
+use crate::core::ScreenElement;
+use crate::core::tracker::{self, ElementDelta, ElementUpdate};
+use crate::input::keys;
+use crate::utils::geometry::{Point, Polygon, Rectangle};
+use crate::utils::text;
 use crate::vision::{UIElement, ElementType};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
 pub mod rendering;
 pub mod animations;
+pub mod inspector;
+pub mod recorder;
+
+use recorder::OverlayRecorder;
 
 #[derive(Debug, Clone)]
 pub struct OverlayConfig {
@@ -19,6 +28,15 @@ pub struct OverlayConfig {
     pub border_width: f64,
     pub font_size: f64,
     pub fade_duration: Duration,
+    pub selection_color: Color,
+    /// Chord that toggles the confidence heatmap
+    pub heatmap_hotkey: String,
+    /// Alpha of a heatmap cell with confidence 1.0
+    pub heatmap_max_alpha: u8,
+    /// Chord that turns spy mode (live info on the element under the cursor) on and off
+    pub spy_hotkey: String,
+    /// Overlay states kept for scrubbing back (0 = don't record)
+    pub history: usize,
 }
 
 impl Default for OverlayConfig {
@@ -32,6 +50,11 @@ impl Default for OverlayConfig {
             border_width: 2.0,
             font_size: 12.0,
             fade_duration: Duration::from_millis(300),
+            selection_color: Color::rgba(255, 200, 0, 200), // Amber
+            heatmap_hotkey: "ctrl+alt+h".to_string(),
+            heatmap_max_alpha: 160,
+            spy_hotkey: "ctrl+alt+s".to_string(),
+            history: 200,
         }
     }
 }
@@ -79,6 +102,28 @@ pub struct OverlayElement {
     pub properties: HashMap<String, String>,
 }
 
+impl OverlayElement {
+    /// Outline of a `Polygon` element, from its "x,y x,y ..." `points` property
+    pub fn polygon(&self) -> Option<Polygon> {
+        let points = self.properties.get("points")?
+            .split_whitespace()
+            .map(|pair| {
+                let (x, y) = pair.split_once(',')?;
+                Some(Point::new(x.parse().ok()?, y.parse().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        (points.len() >= 3).then(|| Polygon::new(points))
+    }
+
+    /// Hit test that follows the outline of polygon elements
+    pub fn contains_point(&self, point: &Point) -> bool {
+        match self.polygon() {
+            Some(polygon) => polygon.contains_point(point),
+            None => self.bounds.contains_point(point),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum OverlayElementType {
     Highlight,
@@ -86,23 +131,67 @@ pub enum OverlayElementType {
     Border,
     Arrow,
     Circle,
+    /// Translucent fill with no outline or text
+    Heatmap,
+    /// Highlight following a non-rectangular outline (`points` property)
+    Polygon,
+    /// Diagonal stripes with an outline, for elements hidden behind other windows
+    Hatched,
     Custom(String),
 }
 
+/// How confidence scores are laid out in the heatmap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeatmapMode {
+    /// One cell per scored region
+    PerElement,
+    /// Aggregate into square cells, each taking the highest score that overlaps it
+    Grid { cell_size: f64 },
+}
+
+fn tracked_id(id: u64) -> String {
+    format!("tracked_{}", id)
+}
+
+fn tracked_bounds((x, y, width, height): tracker::Bounds) -> Rectangle {
+    Rectangle::new(x as f64, y as f64, width as f64, height as f64)
+}
+
+/// Id of the live rubber-band rectangle while a selection is being dragged
+const SELECTION_PREVIEW_ID: &str = "selection_preview";
+/// Offset of the spy tooltip from the cursor, so the pointer does not hide it
+const SPY_TOOLTIP_OFFSET: f64 = 16.0;
+/// Drags smaller than this (in either dimension) are treated as clicks, not selections
+const MIN_SELECTION_SIZE: f64 = 4.0;
+
 pub struct OverlayManager {
     config: OverlayConfig,
     elements: HashMap<String, OverlayElement>,
     animations: HashMap<String, Animation>,
     next_id: u64,
+    selection_anchor: Option<Point>,
+    constraint_id: Option<String>,
+    heatmap_ids: Vec<String>,
+    heatmap_visible: bool,
+    spy_ids: Vec<String>,
+    spy_active: bool,
+    recorder: OverlayRecorder,
 }
 
 impl OverlayManager {
     pub fn new(config: OverlayConfig) -> Self {
         Self {
-            config,
             elements: HashMap::new(),
             animations: HashMap::new(),
             next_id: 0,
+            selection_anchor: None,
+            constraint_id: None,
+            heatmap_ids: Vec::new(),
+            heatmap_visible: false,
+            spy_ids: Vec::new(),
+            spy_active: false,
+            recorder: OverlayRecorder::new(config.history),
+            config,
         }
     }
 
@@ -111,15 +200,19 @@ impl OverlayManager {
             let color = self.get_color_for_element_type(&element.element_type);
             let id = self.generate_id();
             
+            let (element_type, properties) = match &element.shape {
+                Some(shape) => (OverlayElementType::Polygon, polygon_properties(shape)),
+                None => (OverlayElementType::Highlight, HashMap::new()),
+            };
             let overlay_element = OverlayElement {
                 id: id.clone(),
-                element_type: OverlayElementType::Highlight,
+                element_type,
                 bounds: element.bounds,
                 color,
                 text: Some(format!("{} ({:.1}%)", element.element_type, element.confidence * 100.0)),
                 visible: true,
                 created_at: Instant::now(),
-                properties: HashMap::new(),
+                properties,
             };
             
             self.elements.insert(id.clone(), overlay_element);
@@ -131,6 +224,44 @@ impl OverlayManager {
         }
     }
 
+    /// Bring the highlights of tracked elements up to date with a delta
+    /// from the core's element tracker, touching only what changed. Each
+    /// tracked element is drawn under the ID `tracked_<id>`.
+    pub fn apply_delta(&mut self, delta: &ElementDelta) {
+        for update in &delta.updates {
+            match update {
+                ElementUpdate::Added { id, element_type, bounds, text } => {
+                    let id = tracked_id(*id);
+                    let overlay_element = OverlayElement {
+                        id: id.clone(),
+                        element_type: OverlayElementType::Highlight,
+                        bounds: tracked_bounds(*bounds),
+                        color: self.config.highlight_color,
+                        text: Some(text.clone().unwrap_or_else(|| element_type.clone())),
+                        visible: true,
+                        created_at: Instant::now(),
+                        properties: HashMap::new(),
+                    };
+                    self.elements.insert(id.clone(), overlay_element);
+                    if self.config.enable_animations {
+                        self.add_fade_in_animation(&id);
+                    }
+                }
+                ElementUpdate::Removed { id } => self.remove_element(&tracked_id(*id)),
+                ElementUpdate::Moved { id, bounds } => {
+                    if let Some(element) = self.elements.get_mut(&tracked_id(*id)) {
+                        element.bounds = tracked_bounds(*bounds);
+                    }
+                }
+                ElementUpdate::TextChanged { id, text } => {
+                    if let Some(element) = self.elements.get_mut(&tracked_id(*id)) {
+                        element.text = text.clone();
+                    }
+                }
+            }
+        }
+    }
+
     pub fn add_highlight(&mut self, bounds: Rectangle, color: Color, text: Option<String>) -> String {
         let id = self.generate_id();
         
@@ -149,6 +280,44 @@ impl OverlayManager {
         id
     }
 
+    /// Striped highlight marking an element that other windows cover
+    pub fn add_hatched(&mut self, bounds: Rectangle, color: Color, text: Option<String>) -> String {
+        let id = self.generate_id();
+
+        let overlay_element = OverlayElement {
+            id: id.clone(),
+            element_type: OverlayElementType::Hatched,
+            bounds,
+            color,
+            text,
+            visible: true,
+            created_at: Instant::now(),
+            properties: HashMap::new(),
+        };
+
+        self.elements.insert(id.clone(), overlay_element);
+        id
+    }
+
+    /// Highlight that follows `polygon` instead of its bounding box
+    pub fn add_polygon(&mut self, polygon: &Polygon, color: Color, text: Option<String>) -> String {
+        let id = self.generate_id();
+
+        let overlay_element = OverlayElement {
+            id: id.clone(),
+            element_type: OverlayElementType::Polygon,
+            bounds: polygon.bounding_rectangle().unwrap_or(Rectangle::new(0.0, 0.0, 0.0, 0.0)),
+            color,
+            text,
+            visible: true,
+            created_at: Instant::now(),
+            properties: polygon_properties(polygon),
+        };
+
+        self.elements.insert(id.clone(), overlay_element);
+        id
+    }
+
     pub fn add_label(&mut self, position: Point, text: String, color: Color) -> String {
         let id = self.generate_id();
         
@@ -156,7 +325,7 @@ impl OverlayManager {
         let bounds = Rectangle::new(
             position.x,
             position.y,
-            text.len() as f64 * self.config.font_size * 0.6, // Rough text width estimation
+            text::display_width(&text) as f64 * self.config.font_size * 0.6, // Rough text width estimation
             self.config.font_size,
         );
         
@@ -245,13 +414,21 @@ impl OverlayManager {
     pub fn clear_all(&mut self) {
         self.elements.clear();
         self.animations.clear();
+        self.selection_anchor = None;
+        self.constraint_id = None;
+        self.heatmap_ids.clear();
+        self.heatmap_visible = false;
+        self.spy_ids.clear();
     }
 
+    /// Remove elements older than `duration`; the region constraint persists until cleared
     pub fn clear_older_than(&mut self, duration: Duration) {
         let cutoff_time = Instant::now() - duration;
         
         self.elements.retain(|id, element| {
-            let should_keep = element.created_at > cutoff_time;
+            let should_keep = element.created_at > cutoff_time
+                || self.constraint_id.as_deref() == Some(id.as_str())
+                || id == SELECTION_PREVIEW_ID;
             if !should_keep {
                 self.animations.remove(id);
             }
@@ -286,6 +463,27 @@ impl OverlayManager {
         for id in finished_animations {
             self.animations.remove(&id);
         }
+        self.record_snapshot();
+    }
+
+    /// Record the visible overlay for time travel if it changed; called on
+    /// every `update_animations` tick. Returns whether a snapshot was added.
+    pub fn record_snapshot(&mut self) -> bool {
+        let visible = self.get_visible_elements().into_iter().cloned().collect();
+        self.recorder.record(visible)
+    }
+
+    /// Line a command lifecycle event up with the recorded overlay states
+    pub fn observe_event(&mut self, event: &crate::core::LunaEvent) {
+        self.recorder.observe(event);
+    }
+
+    pub fn recorder(&self) -> &OverlayRecorder {
+        &self.recorder
+    }
+
+    pub fn recorder_mut(&mut self) -> &mut OverlayRecorder {
+        &mut self.recorder
     }
 
     pub fn get_visible_elements(&self) -> Vec<&OverlayElement> {
@@ -300,10 +498,242 @@ impl OverlayManager {
 
     pub fn get_elements_at_point(&self, point: &Point) -> Vec<&OverlayElement> {
         self.elements.values()
-            .filter(|element| element.visible && element.bounds.contains_point(point))
+            .filter(|element| element.visible && element.contains_point(point))
             .collect()
     }
 
+    /// Start a rubber-band selection at `point`
+    pub fn begin_selection(&mut self, point: Point) {
+        self.selection_anchor = Some(point);
+        self.set_selection_preview(Rectangle::from_points(point, point));
+    }
+
+    /// Stretch the in-progress selection to `point`
+    pub fn update_selection(&mut self, point: Point) {
+        if let Some(anchor) = self.selection_anchor {
+            self.set_selection_preview(Rectangle::from_points(anchor, point));
+        }
+    }
+
+    /// Finish the drag at `point`, replacing any previous region constraint.
+    ///
+    /// Returns `None` if no selection was in progress or the drag was too small.
+    pub fn finish_selection(&mut self, point: Point) -> Option<Rectangle> {
+        let anchor = self.selection_anchor.take()?;
+        self.elements.remove(SELECTION_PREVIEW_ID);
+
+        let bounds = Rectangle::from_points(anchor, point);
+        if bounds.width < MIN_SELECTION_SIZE || bounds.height < MIN_SELECTION_SIZE {
+            return None;
+        }
+
+        self.set_region_constraint(bounds);
+        Some(bounds)
+    }
+
+    pub fn cancel_selection(&mut self) {
+        self.selection_anchor = None;
+        self.elements.remove(SELECTION_PREVIEW_ID);
+    }
+
+    pub fn is_selecting(&self) -> bool {
+        self.selection_anchor.is_some()
+    }
+
+    /// Show `bounds` as the active region constraint, replacing any previous one
+    pub fn set_region_constraint(&mut self, bounds: Rectangle) -> String {
+        self.clear_region_constraint();
+
+        let id = self.generate_id();
+        let mut properties = HashMap::new();
+        properties.insert("role".to_string(), "region_constraint".to_string());
+
+        let overlay_element = OverlayElement {
+            id: id.clone(),
+            element_type: OverlayElementType::Border,
+            bounds,
+            color: self.config.selection_color,
+            text: Some("Only within this region".to_string()),
+            visible: true,
+            created_at: Instant::now(),
+            properties,
+        };
+
+        self.elements.insert(id.clone(), overlay_element);
+        self.constraint_id = Some(id.clone());
+        id
+    }
+
+    /// Current region constraint, if one has been selected
+    pub fn region_constraint(&self) -> Option<Rectangle> {
+        self.constraint_id
+            .as_ref()
+            .and_then(|id| self.elements.get(id))
+            .map(|element| element.bounds)
+    }
+
+    pub fn clear_region_constraint(&mut self) {
+        if let Some(id) = self.constraint_id.take() {
+            self.remove_element(&id);
+        }
+    }
+
+    /// Replace the heatmap with translucent cells scaled by each region's score (0.0-1.0).
+    ///
+    /// Returns the number of cells drawn.
+    pub fn show_confidence_heatmap(&mut self, scores: &[(Rectangle, f64)], mode: HeatmapMode) -> usize {
+        self.hide_confidence_heatmap();
+
+        let cells = match mode {
+            HeatmapMode::PerElement => scores.to_vec(),
+            HeatmapMode::Grid { cell_size } => aggregate_heatmap_grid(scores, cell_size),
+        };
+
+        for (bounds, score) in &cells {
+            let id = self.generate_id();
+            let mut properties = HashMap::new();
+            properties.insert("score".to_string(), format!("{:.3}", score));
+
+            let overlay_element = OverlayElement {
+                id: id.clone(),
+                element_type: OverlayElementType::Heatmap,
+                bounds: *bounds,
+                color: heatmap_color(*score, self.config.heatmap_max_alpha),
+                text: None,
+                visible: true,
+                created_at: Instant::now(),
+                properties,
+            };
+
+            self.elements.insert(id.clone(), overlay_element);
+            self.heatmap_ids.push(id);
+        }
+
+        self.heatmap_visible = true;
+        cells.len()
+    }
+
+    /// Heatmap of vision-detected elements
+    pub fn show_element_heatmap(&mut self, ui_elements: &[UIElement], mode: HeatmapMode) -> usize {
+        let scores: Vec<(Rectangle, f64)> = ui_elements
+            .iter()
+            .map(|e| (e.bounds, e.confidence))
+            .collect();
+        self.show_confidence_heatmap(&scores, mode)
+    }
+
+    /// Heatmap of the elements from a coordinator screen analysis
+    pub fn show_analysis_heatmap(&mut self, elements: &[ScreenElement], mode: HeatmapMode) -> usize {
+        let scores: Vec<(Rectangle, f64)> = elements
+            .iter()
+            .map(|e| (Rectangle::from(&e.bounds), e.confidence as f64))
+            .collect();
+        self.show_confidence_heatmap(&scores, mode)
+    }
+
+    pub fn hide_confidence_heatmap(&mut self) {
+        for id in std::mem::take(&mut self.heatmap_ids) {
+            self.remove_element(&id);
+        }
+        self.heatmap_visible = false;
+    }
+
+    /// Show or hide the current heatmap cells without recomputing them
+    pub fn toggle_confidence_heatmap(&mut self) -> bool {
+        self.heatmap_visible = !self.heatmap_visible;
+        for id in &self.heatmap_ids {
+            if let Some(element) = self.elements.get_mut(id) {
+                element.visible = self.heatmap_visible;
+            }
+        }
+        self.heatmap_visible
+    }
+
+    pub fn is_heatmap_visible(&self) -> bool {
+        self.heatmap_visible && !self.heatmap_ids.is_empty()
+    }
+
+    /// Replace the spy tooltip: the element's outline and its report lines
+    /// stacked beside the cursor
+    pub fn show_spy_tooltip(&mut self, report: &SpyReport) {
+        self.hide_spy_tooltip();
+        if let Some(element) = &report.element {
+            let (x, y, width, height) = element.bounds;
+            let bounds = Rectangle::new(x as f64, y as f64, width as f64, height as f64);
+            let id = self.generate_id();
+            let outline = OverlayElement {
+                id: id.clone(),
+                element_type: OverlayElementType::Border,
+                bounds,
+                color: self.config.selection_color,
+                text: None,
+                visible: true,
+                created_at: Instant::now(),
+                properties: HashMap::new(),
+            };
+            self.elements.insert(id.clone(), outline);
+            self.spy_ids.push(id);
+        }
+
+        let line_height = self.config.font_size * 1.4;
+        let (x, y) = (report.cursor.0 as f64 + SPY_TOOLTIP_OFFSET, report.cursor.1 as f64 + SPY_TOOLTIP_OFFSET);
+        for (row, line) in report.lines().into_iter().enumerate() {
+            let id = self.add_label(Point::new(x, y + row as f64 * line_height), line, self.config.label_color);
+            self.spy_ids.push(id);
+        }
+    }
+
+    pub fn hide_spy_tooltip(&mut self) {
+        for id in std::mem::take(&mut self.spy_ids) {
+            self.remove_element(&id);
+        }
+    }
+
+    /// Turn spy mode on or off; turning it off removes the tooltip
+    pub fn toggle_spy(&mut self) -> bool {
+        self.spy_active = !self.spy_active;
+        if !self.spy_active {
+            self.hide_spy_tooltip();
+        }
+        self.spy_active
+    }
+
+    /// Whether the caller should keep feeding `show_spy_tooltip` reports
+    pub fn is_spy_active(&self) -> bool {
+        self.spy_active
+    }
+
+    /// Handle an overlay hotkey; returns true if the chord was consumed
+    pub fn handle_hotkey(&mut self, chord: &str) -> bool {
+        let Ok(pressed) = keys::parse_chord(chord) else {
+            return false;
+        };
+        let bound = |hotkey: &str| keys::parse_chord(hotkey).is_ok_and(|chord| chord == pressed);
+        if bound(&self.config.heatmap_hotkey) {
+            self.toggle_confidence_heatmap();
+        } else if bound(&self.config.spy_hotkey) {
+            self.toggle_spy();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn set_selection_preview(&mut self, bounds: Rectangle) {
+        let overlay_element = OverlayElement {
+            id: SELECTION_PREVIEW_ID.to_string(),
+            element_type: OverlayElementType::Border,
+            bounds,
+            color: self.config.selection_color.with_alpha(128),
+            text: None,
+            visible: true,
+            created_at: Instant::now(),
+            properties: HashMap::new(),
+        };
+
+        self.elements.insert(SELECTION_PREVIEW_ID.to_string(), overlay_element);
+    }
+
     fn generate_id(&mut self) -> String {
         let id = format!("overlay_{}", self.next_id);
         self.next_id += 1;
@@ -319,6 +749,12 @@ impl OverlayManager {
             ElementType::Window => Color::rgb(255, 165, 0),   // Orange
             ElementType::Icon => Color::rgb(0, 255, 255),     // Cyan
             ElementType::Image => Color::rgb(128, 0, 128),    // Purple
+            ElementType::Checkbox => Color::rgb(0, 200, 120),  // Teal green
+            ElementType::Radio => Color::rgb(0, 160, 200),     // Teal blue
+            ElementType::Slider => Color::rgb(255, 105, 180),  // Pink
+            ElementType::Tab => Color::rgb(160, 120, 255),     // Lavender
+            ElementType::Dropdown => Color::rgb(100, 150, 255), // Light blue
+            ElementType::Toggle => Color::rgb(200, 255, 0),    // Lime
             ElementType::Unknown => Color::rgb(128, 128, 128), // Gray
         }
     }
@@ -466,7 +902,56 @@ impl Animation {
     }
 }
 
+/// Heatmap color for a confidence score: red (weak) through yellow to green (strong),
+/// with alpha scaled by the score
+pub fn heatmap_color(score: f64, max_alpha: u8) -> Color {
+    let score = score.clamp(0.0, 1.0);
+    let (r, g) = if score < 0.5 {
+        (255.0, score * 2.0 * 255.0)
+    } else {
+        ((1.0 - score) * 2.0 * 255.0, 255.0)
+    };
+    Color::rgba(r as u8, g as u8, 0, (score * max_alpha as f64) as u8)
+}
+
+/// Bucket scored regions into a grid of `cell_size` squares, keeping the best score per cell
+fn aggregate_heatmap_grid(scores: &[(Rectangle, f64)], cell_size: f64) -> Vec<(Rectangle, f64)> {
+    if cell_size <= 0.0 {
+        return scores.to_vec();
+    }
+
+    let mut cells: HashMap<(i64, i64), f64> = HashMap::new();
+    for (bounds, score) in scores {
+        let min_col = (bounds.x / cell_size).floor() as i64;
+        let max_col = ((bounds.x + bounds.width) / cell_size).ceil() as i64;
+        let min_row = (bounds.y / cell_size).floor() as i64;
+        let max_row = ((bounds.y + bounds.height) / cell_size).ceil() as i64;
+
+        for row in min_row..max_row.max(min_row + 1) {
+            for col in min_col..max_col.max(min_col + 1) {
+                let best = cells.entry((col, row)).or_insert(0.0);
+                *best = best.max(*score);
+            }
+        }
+    }
+
+    let mut result: Vec<(Rectangle, f64)> = cells
+        .into_iter()
+        .map(|((col, row), score)| {
+            let cell = Rectangle::new(col as f64 * cell_size, row as f64 * cell_size, cell_size, cell_size);
+            (cell, score)
+        })
+        .collect();
+    result.sort_by(|a, b| (a.0.y, a.0.x).partial_cmp(&(b.0.y, b.0.x)).unwrap_or(std::cmp::Ordering::Equal));
+    result
+}
+
 // Utility functions for common overlay operations
+fn polygon_properties(polygon: &Polygon) -> HashMap<String, String> {
+    let points: Vec<String> = polygon.points.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+    HashMap::from([("points".to_string(), points.join(" "))])
+}
+
 pub fn create_ui_highlights(ui_elements: &[UIElement]) -> OverlayManager {
     let mut manager = OverlayManager::default();
     manager.add_ui_element_highlights(ui_elements);
@@ -636,4 +1121,171 @@ mod tests {
         let elements3 = manager.get_elements_at_point(&point3);
         assert_eq!(elements3.len(), 0); // Should find no elements
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_rubber_band_selection() {
+        let mut manager = OverlayManager::default();
+
+        manager.begin_selection(Point::new(100.0, 80.0));
+        assert!(manager.is_selecting());
+        manager.update_selection(Point::new(40.0, 200.0));
+        assert_eq!(
+            manager.get_element(SELECTION_PREVIEW_ID).unwrap().bounds,
+            Rectangle::new(40.0, 80.0, 60.0, 120.0)
+        );
+
+        let region = manager.finish_selection(Point::new(300.0, 200.0)).unwrap();
+        assert_eq!(region, Rectangle::new(100.0, 80.0, 200.0, 120.0));
+        assert!(!manager.is_selecting());
+        assert!(manager.get_element(SELECTION_PREVIEW_ID).is_none());
+        assert_eq!(manager.region_constraint(), Some(region));
+    }
+
+    #[test]
+    fn test_tiny_drag_is_not_a_selection() {
+        let mut manager = OverlayManager::default();
+        manager.begin_selection(Point::new(10.0, 10.0));
+        assert!(manager.finish_selection(Point::new(12.0, 11.0)).is_none());
+        assert!(manager.region_constraint().is_none());
+        assert!(manager.elements.is_empty());
+    }
+
+    #[test]
+    fn test_region_constraint_persists_until_cleared() {
+        let mut manager = OverlayManager::default();
+        manager.set_region_constraint(Rectangle::new(0.0, 0.0, 50.0, 50.0));
+        manager.add_highlight(Rectangle::new(0.0, 0.0, 10.0, 10.0), Color::rgb(255, 0, 0), None);
+
+        manager.clear_older_than(Duration::ZERO);
+        assert_eq!(manager.elements.len(), 1);
+        assert!(manager.region_constraint().is_some());
+
+        // A new selection replaces the old constraint
+        manager.set_region_constraint(Rectangle::new(10.0, 10.0, 20.0, 20.0));
+        assert_eq!(manager.elements.len(), 1);
+
+        manager.clear_region_constraint();
+        assert!(manager.region_constraint().is_none());
+        assert!(manager.elements.is_empty());
+    }
+
+    #[test]
+    fn test_heatmap_color_scales_with_score() {
+        let weak = heatmap_color(0.1, 200);
+        let strong = heatmap_color(0.9, 200);
+        assert!(weak.r > weak.g);
+        assert!(strong.g > strong.r);
+        assert!(weak.a < strong.a);
+        assert_eq!(heatmap_color(1.0, 200).a, 200);
+        assert_eq!(heatmap_color(-1.0, 200).a, 0);
+    }
+
+    #[test]
+    fn test_per_element_heatmap() {
+        let mut manager = OverlayManager::default();
+        let scores = vec![
+            (Rectangle::new(0.0, 0.0, 50.0, 20.0), 0.9),
+            (Rectangle::new(100.0, 0.0, 50.0, 20.0), 0.2),
+        ];
+
+        assert_eq!(manager.show_confidence_heatmap(&scores, HeatmapMode::PerElement), 2);
+        assert!(manager.is_heatmap_visible());
+        assert!(manager.get_visible_elements().iter().all(|e| matches!(e.element_type, OverlayElementType::Heatmap)));
+
+        // Showing again replaces rather than stacks
+        manager.show_confidence_heatmap(&scores[..1], HeatmapMode::PerElement);
+        assert_eq!(manager.elements.len(), 1);
+
+        manager.hide_confidence_heatmap();
+        assert!(manager.elements.is_empty());
+        assert!(!manager.is_heatmap_visible());
+    }
+
+    #[test]
+    fn test_grid_heatmap_keeps_best_score() {
+        let mut manager = OverlayManager::default();
+        let scores = vec![
+            (Rectangle::new(0.0, 0.0, 10.0, 10.0), 0.3),
+            (Rectangle::new(5.0, 5.0, 10.0, 10.0), 0.8),
+        ];
+
+        let cells = aggregate_heatmap_grid(&scores, 10.0);
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0], (Rectangle::new(0.0, 0.0, 10.0, 10.0), 0.8));
+        assert_eq!(manager.show_confidence_heatmap(&scores, HeatmapMode::Grid { cell_size: 10.0 }), 4);
+    }
+
+    #[test]
+    fn test_heatmap_hotkey_toggles_visibility() {
+        let mut manager = OverlayManager::default();
+        manager.show_confidence_heatmap(&[(Rectangle::new(0.0, 0.0, 10.0, 10.0), 0.5)], HeatmapMode::PerElement);
+
+        assert!(manager.handle_hotkey("Alt+Ctrl+H"));
+        assert!(!manager.is_heatmap_visible());
+        assert!(manager.get_visible_elements().is_empty());
+
+        assert!(manager.handle_hotkey("ctrl+alt+h"));
+        assert!(manager.is_heatmap_visible());
+
+        assert!(!manager.handle_hotkey("ctrl+h"));
+    }
+
+    #[test]
+    fn test_spy_tooltip_replaces_previous_one() {
+        use crate::core::spy::SpiedElement;
+
+        let mut manager = OverlayManager::default();
+        assert!(manager.handle_hotkey("ctrl+alt+s"));
+        assert!(manager.is_spy_active());
+
+        let report = SpyReport {
+            cursor: (100, 50),
+            element: Some(SpiedElement {
+                element_type: "button".to_string(),
+                text: Some("Save".to_string()),
+                confidence: 0.9,
+                bounds: (80, 40, 60, 20),
+                query: "button:save".to_string(),
+                query_matches: 1,
+            }),
+        };
+        manager.show_spy_tooltip(&report);
+        manager.show_spy_tooltip(&report);
+        let visible = manager.get_visible_elements();
+        assert_eq!(visible.len(), 1 + report.lines().len());
+        let labels: Vec<&str> = visible.iter().filter_map(|e| e.text.as_deref()).collect();
+        assert!(labels.contains(&"query button:save"), "{:?}", labels);
+        assert!(visible.iter().all(|e| !matches!(e.element_type, OverlayElementType::Label) || e.bounds.x > 100.0));
+
+        assert!(manager.handle_hotkey("ctrl+alt+s"));
+        assert!(!manager.is_spy_active());
+        assert!(manager.get_visible_elements().is_empty());
+    }
+
+    #[test]
+    fn test_apply_delta_updates_only_tracked_elements() {
+        let mut manager = OverlayManager::default();
+        let own = manager.add_label(Point::new(0.0, 0.0), "mine".to_string(), Color::rgb(255, 255, 255));
+        let added = |id, text: Option<&str>| ElementUpdate::Added {
+            id,
+            element_type: "button".to_string(),
+            bounds: (10, 10, 80, 30),
+            text: text.map(str::to_string),
+        };
+        manager.apply_delta(&ElementDelta { sequence: 1, updates: vec![added(1, Some("Save")), added(2, None)] });
+        assert_eq!(manager.get_element("tracked_2").unwrap().text.as_deref(), Some("button"));
+
+        manager.apply_delta(&ElementDelta {
+            sequence: 2,
+            updates: vec![
+                ElementUpdate::Removed { id: 2 },
+                ElementUpdate::Moved { id: 1, bounds: (20, 15, 80, 30) },
+                ElementUpdate::TextChanged { id: 1, text: Some("Saved".to_string()) },
+            ],
+        });
+        let moved = manager.get_element("tracked_1").unwrap();
+        assert_eq!((moved.bounds, moved.text.as_deref()), (Rectangle::new(20.0, 15.0, 80.0, 30.0), Some("Saved")));
+        assert!(manager.get_element("tracked_2").is_none());
+        assert!(manager.get_element(&own).is_some());
+    }
+}
@@ -0,0 +1,270 @@
+// Overlay time travel: a ring buffer of what the overlay showed and when,
+// tagged with the command lifecycle events that happened around each state.
+// When a user reports that a highlight "jumped" to the wrong element, the
+// recorder can scrub back to that moment and re-render it, over the analyzed
+// frame in the inspector or as an exported image sequence.
+
+use super::inspector::{blend, to_rgb, Inspector};
+use super::rendering::Renderer;
+use super::OverlayElement;
+use crate::core::LunaEvent;
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File written next to an exported image sequence, one JSON line per image
+pub const TIMELINE_FILE: &str = "timeline.jsonl";
+
+/// A command lifecycle event, as it lines up with the overlay states
+#[derive(Debug, Clone, PartialEq)]
+pub struct LifecycleMark {
+    pub at: SystemTime,
+    pub event: String,
+}
+
+/// The visible overlay at one moment
+#[derive(Debug, Clone)]
+pub struct OverlaySnapshot {
+    pub at: SystemTime,
+    /// Visible elements, ordered by id
+    pub elements: Vec<OverlayElement>,
+    /// Command being handled when the state was recorded
+    pub command: Option<String>,
+    /// Lifecycle events since the previous snapshot
+    pub events: Vec<LifecycleMark>,
+}
+
+/// What a snapshot shows, for telling real changes from repaints. Alpha is
+/// left out so fade animations don't fill the buffer.
+type StateKey = (String, Rectangle, (u8, u8, u8), Option<String>);
+
+fn state_key(elements: &[OverlayElement]) -> Vec<StateKey> {
+    elements
+        .iter()
+        .map(|e| (e.id.clone(), e.bounds, (e.color.r, e.color.g, e.color.b), e.text.clone()))
+        .collect()
+}
+
+/// Last `capacity` overlay states plus a scrubber position
+pub struct OverlayRecorder {
+    snapshots: VecDeque<OverlaySnapshot>,
+    capacity: usize,
+    /// Index into `snapshots` being shown
+    position: usize,
+    command: Option<String>,
+    pending: Vec<LifecycleMark>,
+}
+
+impl OverlayRecorder {
+    pub fn new(capacity: usize) -> Self {
+        Self { snapshots: VecDeque::new(), capacity, position: 0, command: None, pending: Vec::new() }
+    }
+
+    /// Note a command lifecycle event; it is attached to the next snapshot
+    pub fn observe(&mut self, event: &LunaEvent) {
+        let described = match event {
+            LunaEvent::CommandReceived { command } => {
+                self.command = Some(command.clone());
+                format!("command received: '{}'", command)
+            }
+            LunaEvent::AnalysisComplete { analysis } => format!("analysis complete: {} element(s)", analysis.elements.len()),
+            LunaEvent::ActionsPlanned { actions } => format!("planned: {:?}", actions),
+            LunaEvent::ActionExecuted { action, success } => {
+                format!("{}: {:?}", if *success { "executed" } else { "failed" }, action)
+            }
+            LunaEvent::StaleFrame { action, changed_fraction } => {
+                format!("stale frame ({:.0}% changed) before {:?}", changed_fraction * 100.0, action)
+            }
+            LunaEvent::Error { error } => format!("error: {}", error),
+            _ => return,
+        };
+        if self.capacity > 0 {
+            self.pending.push(LifecycleMark { at: SystemTime::now(), event: described });
+        }
+    }
+
+    /// Record the visible `elements` if they differ from the last snapshot or
+    /// events happened since; returns whether a snapshot was added
+    pub fn record(&mut self, mut elements: Vec<OverlayElement>) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        elements.sort_by(|a, b| a.id.cmp(&b.id));
+        let unchanged = self.snapshots.back().is_some_and(|last| state_key(&last.elements) == state_key(&elements));
+        if unchanged && self.pending.is_empty() {
+            return false;
+        }
+        while self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(OverlaySnapshot {
+            at: SystemTime::now(),
+            elements,
+            command: self.command.clone(),
+            events: std::mem::take(&mut self.pending),
+        });
+        self.position = self.snapshots.len() - 1;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Recorded states, oldest first
+    pub fn timeline(&self) -> impl Iterator<Item = &OverlaySnapshot> {
+        self.snapshots.iter()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn current(&self) -> Option<&OverlaySnapshot> {
+        self.snapshots.get(self.position)
+    }
+
+    /// Show the state at `index` (clamped to the timeline)
+    pub fn scrub(&mut self, index: usize) -> Option<&OverlaySnapshot> {
+        self.position = index.min(self.snapshots.len().saturating_sub(1));
+        self.current()
+    }
+
+    /// Move `delta` states forward (positive) or back (negative)
+    pub fn step(&mut self, delta: isize) -> Option<&OverlaySnapshot> {
+        self.scrub(self.position.saturating_add_signed(delta))
+    }
+
+    /// Show the state the overlay was in at `time`
+    pub fn scrub_to_time(&mut self, time: SystemTime) -> Option<&OverlaySnapshot> {
+        let index = self.snapshots.iter().rposition(|snapshot| snapshot.at <= time)?;
+        self.scrub(index)
+    }
+
+    /// The shown state drawn over `background` (any channel count; RGB out)
+    pub fn render_onto(&self, background: &Image) -> Option<Image> {
+        let snapshot = self.current()?;
+        let mut image = to_rgb(background);
+        let elements: Vec<&OverlayElement> = snapshot.elements.iter().collect();
+        let layer = Renderer::new(image.width, image.height).render_overlay(&elements).ok()?;
+        blend(&mut image, &layer);
+        Some(image)
+    }
+
+    /// The shown state drawn over the analysis the inspector had at that
+    /// moment, with the inspector's layers
+    pub fn render_in(&self, inspector: &mut Inspector) -> Option<Image> {
+        inspector.scrub_to_time(self.current()?.at)?;
+        self.render_onto(&inspector.compose()?)
+    }
+
+    /// Every recorded state drawn over a black `width` x `height` frame as
+    /// `overlay_NNNN.png` in `dir`, plus a `TIMELINE_FILE` with the time,
+    /// command and events of each image
+    pub fn export_sequence(&mut self, dir: &Path, width: usize, height: usize) -> Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+        let background = Image::new(width, height, 3);
+        let shown = self.position;
+        let mut paths = Vec::with_capacity(self.snapshots.len());
+        let mut timeline = String::new();
+        for index in 0..self.snapshots.len() {
+            self.scrub(index);
+            let image = self.render_onto(&background).ok_or_else(|| anyhow::anyhow!("could not render overlay state {}", index))?;
+            let path = dir.join(format!("overlay_{:04}.png", index));
+            std::fs::write(&path, crate::core::encode_png(&image)?)?;
+
+            let snapshot = &self.snapshots[index];
+            let millis = |at: SystemTime| at.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default();
+            let line = serde_json::json!({
+                "image": path.file_name().map(|name| name.to_string_lossy()),
+                "at_ms": millis(snapshot.at),
+                "command": snapshot.command,
+                "elements": snapshot.elements.len(),
+                "events": snapshot.events.iter().map(|mark| serde_json::json!({ "at_ms": millis(mark.at), "event": mark.event })).collect::<Vec<_>>(),
+            });
+            timeline.push_str(&line.to_string());
+            timeline.push('\n');
+            paths.push(path);
+        }
+        std::fs::write(dir.join(TIMELINE_FILE), timeline)?;
+        self.position = shown;
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::LunaAction;
+    use crate::overlay::{Color, OverlayConfig, OverlayManager};
+    use crate::utils::geometry::Point;
+
+    #[test]
+    fn test_records_changes_with_their_lifecycle_events() {
+        let mut overlay = OverlayManager::new(OverlayConfig { history: 3, ..OverlayConfig::default() });
+        overlay.observe_event(&LunaEvent::CommandReceived { command: "click Save".to_string() });
+        let id = overlay.add_highlight(Rectangle::new(10.0, 10.0, 20.0, 10.0), Color::rgb(0, 255, 0), None);
+        assert!(overlay.record_snapshot());
+
+        // A fade is not a change
+        overlay.set_element_visibility(&id, true);
+        overlay.add_fade_out_animation(&id);
+        overlay.update_animations(std::time::Duration::from_millis(16));
+        assert_eq!(overlay.recorder().len(), 1);
+
+        // The highlight jumps, and the click that followed is on the record
+        overlay.remove_element(&id);
+        overlay.add_highlight(Rectangle::new(60.0, 40.0, 20.0, 10.0), Color::rgb(0, 255, 0), None);
+        overlay.observe_event(&LunaEvent::ActionExecuted { action: LunaAction::Click { x: 70, y: 45 }, success: true });
+        assert!(overlay.record_snapshot());
+        assert!(!overlay.record_snapshot());
+
+        let recorder = overlay.recorder_mut();
+        let jumped = recorder.current().unwrap();
+        assert_eq!(jumped.command.as_deref(), Some("click Save"));
+        assert_eq!(jumped.events.len(), 1);
+        assert!(jumped.events[0].event.starts_with("executed: Click"), "{:?}", jumped.events);
+
+        let first_at = recorder.timeline().next().unwrap().at;
+        let before = recorder.scrub_to_time(first_at).unwrap();
+        assert_eq!(before.elements[0].bounds.x, 10.0);
+        assert_eq!(before.events[0].event, "command received: 'click Save'");
+        assert!(recorder.scrub_to_time(UNIX_EPOCH).is_none());
+
+        // Re-rendered over a frame, then as an image sequence
+        let frame = recorder.render_onto(&Image::new(100, 60, 1)).unwrap();
+        assert_ne!(frame.get_pixel(15, 10).unwrap(), [0, 0, 0]);
+        assert_eq!(frame.get_pixel(65, 40).unwrap(), [0, 0, 0]);
+        let dir = tempfile::tempdir().unwrap();
+        let paths = recorder.export_sequence(dir.path(), 100, 60).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(recorder.position(), 0);
+        let timeline = std::fs::read_to_string(dir.path().join(TIMELINE_FILE)).unwrap();
+        assert_eq!(timeline.lines().count(), 2);
+        assert!(timeline.contains("overlay_0001.png"), "{}", timeline);
+    }
+
+    #[test]
+    fn test_keeps_the_last_n_states() {
+        let mut recorder = OverlayRecorder::new(2);
+        let mut overlay = OverlayManager::new(OverlayConfig { history: 0, ..OverlayConfig::default() });
+        for x in 0..3 {
+            overlay.add_label(Point::new(x as f64, 0.0), "OK".to_string(), Color::rgb(255, 255, 255));
+            assert!(recorder.record(overlay.get_visible_elements().into_iter().cloned().collect()));
+        }
+        assert_eq!(recorder.len(), 2);
+        assert_eq!(recorder.step(-1).unwrap().elements.len(), 2);
+        assert_eq!(recorder.scrub(7).unwrap().elements.len(), 3);
+
+        // History off records nothing
+        assert!(!overlay.record_snapshot());
+        assert!(overlay.recorder().is_empty());
+    }
+}
@@ -3,6 +3,7 @@
 
 use super::{OverlayElement, OverlayElementType, Color};
 use crate::utils::geometry::{Point, Rectangle};
+use crate::utils::text;
 use crate::utils::image_processing::Image;
 use std::collections::HashMap;
 
@@ -59,6 +60,15 @@ impl Renderer {
             OverlayElementType::Circle => {
                 self.render_circle(canvas, element)?;
             }
+            OverlayElementType::Polygon => {
+                self.render_polygon(canvas, element)?;
+            }
+            OverlayElementType::Hatched => {
+                self.render_hatched(canvas, element)?;
+            }
+            OverlayElementType::Heatmap => {
+                self.fill_rectangle(canvas, &element.bounds, element.color)?;
+            }
             OverlayElementType::Custom(_) => {
                 // Custom elements can be implemented by extending this
                 self.render_highlight(canvas, element)?; // Fallback to highlight
@@ -88,6 +98,63 @@ impl Renderer {
         Ok(())
     }
 
+    fn render_hatched(&self, canvas: &mut Image, element: &OverlayElement) -> Result<(), RenderError> {
+        const STRIPE_PERIOD: usize = 8;
+        const STRIPE_WIDTH: usize = 3;
+
+        let bounds = &element.bounds;
+        let (x0, y0) = (bounds.x.max(0.0) as usize, bounds.y.max(0.0) as usize);
+        let x1 = ((bounds.x + bounds.width).max(0.0) as usize).min(canvas.width);
+        let y1 = ((bounds.y + bounds.height).max(0.0) as usize).min(canvas.height);
+        let pixel = [element.color.r, element.color.g, element.color.b, element.color.a];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if (x + y) % STRIPE_PERIOD < STRIPE_WIDTH {
+                    self.blend_pixel(canvas, x, y, &pixel);
+                }
+            }
+        }
+        self.draw_rectangle_outline(canvas, bounds, element.color.with_alpha(255), 1)?;
+
+        if let Some(ref text) = element.text {
+            self.draw_text(canvas, text, Point::new(bounds.x + 5.0, bounds.y - 5.0), Color::rgb(255, 255, 255))?;
+        }
+        Ok(())
+    }
+
+    fn render_polygon(&self, canvas: &mut Image, element: &OverlayElement) -> Result<(), RenderError> {
+        let Some(polygon) = element.polygon() else {
+            return self.render_highlight(canvas, element);
+        };
+
+        // Fill the pixels whose centers fall inside the outline
+        let pixel = [element.color.r, element.color.g, element.color.b, element.color.a];
+        let bounds = &element.bounds;
+        let (x0, y0) = (bounds.x.max(0.0) as usize, bounds.y.max(0.0) as usize);
+        let x1 = ((bounds.x + bounds.width).ceil().max(0.0) as usize).min(canvas.width);
+        let y1 = ((bounds.y + bounds.height).ceil().max(0.0) as usize).min(canvas.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if polygon.contains_point(&Point::new(x as f64 + 0.5, y as f64 + 0.5)) {
+                    self.blend_pixel(canvas, x, y, &pixel);
+                }
+            }
+        }
+
+        let border_color = element.color.with_alpha(255);
+        let n = polygon.points.len();
+        for i in 0..n {
+            self.draw_line(canvas, polygon.points[i], polygon.points[(i + 1) % n], border_color)?;
+        }
+
+        if let Some(ref text) = element.text {
+            let text_pos = Point::new(bounds.x + 5.0, bounds.y - 5.0);
+            self.draw_text(canvas, text, text_pos, Color::rgb(255, 255, 255))?;
+        }
+
+        Ok(())
+    }
+
     fn render_label(&self, canvas: &mut Image, element: &OverlayElement) -> Result<(), RenderError> {
         if let Some(ref text) = element.text {
             // Draw text background
@@ -295,16 +362,18 @@ impl Renderer {
         let _char_height = 12;
         let mut x_offset = 0;
 
-        for ch in text.chars() {
-            if let Some(bitmap) = self.font_cache.get_character_bitmap(ch) {
+        // One glyph per grapheme, advancing two cells for wide characters
+        for cluster in text::graphemes(text) {
+            let bitmap = cluster.chars().next().and_then(|ch| self.font_cache.get_character_bitmap(ch));
+            if let Some(bitmap) = bitmap {
                 self.draw_character_bitmap(
                     canvas,
-                    &bitmap,
+                    bitmap,
                     Point::new(position.x + x_offset as f64, position.y),
                     color,
                 )?;
             }
-            x_offset += char_width;
+            x_offset += char_width * text::display_width(cluster);
         }
 
         Ok(())
@@ -542,6 +611,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_render_polygon_follows_outline() {
+        let mut manager = super::super::OverlayManager::default();
+        let diamond = crate::utils::geometry::RotatedRect::new(Point::new(50.0, 50.0), 40.0, 40.0, std::f64::consts::FRAC_PI_4);
+        manager.add_polygon(&diamond.to_polygon(), Color::rgba(255, 0, 0, 255), None);
+
+        let canvas = Renderer::new(100, 100).render_overlay(&manager.get_visible_elements()).unwrap();
+        assert_eq!(canvas.get_pixel(50, 50).unwrap()[0], 255);
+        // Inside the bounding box but outside the rotated outline
+        assert_eq!(canvas.get_pixel(25, 25).unwrap()[3], 0);
+        assert_eq!(manager.get_elements_at_point(&Point::new(50.0, 50.0)).len(), 1);
+        assert!(manager.get_elements_at_point(&Point::new(25.0, 25.0)).is_empty());
+    }
+
+    #[test]
+    fn test_render_hatched_leaves_gaps_between_stripes() {
+        let mut manager = super::super::OverlayManager::default();
+        manager.add_hatched(Rectangle::new(10.0, 10.0, 40.0, 40.0), Color::rgba(255, 0, 0, 255), None);
+
+        let canvas = Renderer::new(100, 100).render_overlay(&manager.get_visible_elements()).unwrap();
+        assert_eq!(canvas.get_pixel(20, 20).unwrap()[3], 255, "on a stripe");
+        assert_eq!(canvas.get_pixel(24, 20).unwrap()[3], 0, "between stripes");
+        assert_eq!(canvas.get_pixel(60, 60).unwrap()[3], 0, "outside");
+    }
+
     #[test]
     fn test_font_cache() {
         let font_cache = FontCache::new();
@@ -0,0 +1,336 @@
+/*!
+ * Luna Scripting - Sandboxed Rhai scripts over a curated Luna API
+ *
+ * Scripts can analyze the screen, query elements, click, type and wait, but
+ * have no filesystem, module or network access and run under operation and
+ * wall-clock limits taken from `ScriptingConfig`.
+ */
+
+use anyhow::Result;
+use log::info;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::core::config::ScriptingConfig;
+use crate::core::query::ElementQuery;
+use crate::core::{Luna, LunaError, ScreenElement};
+use crate::input::keys;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Rhai engine bound to a Luna instance.
+///
+/// Script variables persist across `run` calls until `reset_variables`.
+///
+/// Available functions: `analyze()`, `find(query)`, `click(x, y)`,
+/// `click(element)`, `type_text(text)`, `keys(chord)`, `scroll(direction, amount)`,
+/// `wait(ms)`, `wait_for(query, timeout_ms)`, `wait_for_text_gone(text, timeout_ms)`,
+/// `wait_idle(quiet_ms, timeout_ms)` and `log(message)`.
+pub struct ScriptEngine {
+    engine: Engine,
+    scope: Scope<'static>,
+    luna: Rc<RefCell<Luna>>,
+    deadline: Rc<RefCell<Instant>>,
+    limits: ScriptingConfig,
+}
+
+impl ScriptEngine {
+    /// Wrap `luna`, using the limits from its configuration
+    pub fn new(luna: Luna) -> Self {
+        let limits = luna.get_config().scripting.clone();
+        Self::with_limits(luna, limits)
+    }
+
+    pub fn with_limits(luna: Luna, limits: ScriptingConfig) -> Self {
+        let luna = Rc::new(RefCell::new(luna));
+        let deadline = Rc::new(RefCell::new(Instant::now()));
+        let engine = build_engine(&luna, &deadline, &limits);
+
+        Self {
+            engine,
+            scope: Scope::new(),
+            luna,
+            deadline,
+            limits,
+        }
+    }
+
+    /// Compile and run a script, returning its final value
+    pub fn run(&mut self, script: &str) -> Result<Dynamic> {
+        *self.deadline.borrow_mut() = Instant::now() + Duration::from_millis(self.limits.max_runtime_ms);
+
+        self.engine
+            .eval_with_scope::<Dynamic>(&mut self.scope, script)
+            .map_err(|e| LunaError::Script(e.to_string()).into())
+    }
+
+    /// Make a host value visible to scripts as a variable
+    pub fn set_variable<T: std::any::Any + Clone>(&mut self, name: &str, value: T) {
+        self.scope.set_value(name.to_string(), value);
+    }
+
+    pub fn get_variable<T: std::any::Any + Clone>(&self, name: &str) -> Option<T> {
+        self.scope.get_value(name)
+    }
+
+    pub fn reset_variables(&mut self) {
+        self.scope.clear();
+    }
+
+    /// Release the wrapped Luna instance
+    pub fn into_luna(self) -> Luna {
+        let Self { engine, luna, .. } = self;
+        // The registered functions hold the other references to `luna`
+        drop(engine);
+        match Rc::try_unwrap(luna) {
+            Ok(cell) => cell.into_inner(),
+            Err(_) => unreachable!("script functions are dropped with the engine"),
+        }
+    }
+}
+
+fn build_engine(luna: &Rc<RefCell<Luna>>, deadline: &Rc<RefCell<Instant>>, limits: &ScriptingConfig) -> Engine {
+    let mut engine = Engine::new();
+
+    // Sandbox: no `import`, bounded work and memory
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.set_max_operations(limits.max_operations);
+    engine.set_max_call_levels(limits.max_call_depth);
+    engine.set_max_string_size(limits.max_collection_size);
+    engine.set_max_array_size(limits.max_collection_size);
+    engine.set_max_map_size(limits.max_collection_size);
+
+    let progress_deadline = deadline.clone();
+    engine.on_progress(move |_| {
+        if Instant::now() >= *progress_deadline.borrow() {
+            Some("script exceeded its time limit".into())
+        } else {
+            None
+        }
+    });
+    engine.on_print(|text| info!("[script] {}", text));
+    engine.register_fn("log", |text: &str| info!("[script] {}", text));
+
+    let l = luna.clone();
+    engine.register_fn("analyze", move || -> ScriptResult<Array> {
+        analyze(&l).map(|elements| elements.iter().map(element_to_dynamic).collect())
+    });
+
+    let l = luna.clone();
+    engine.register_fn("find", move |query: &str| -> ScriptResult<Array> {
+        let elements = analyze(&l)?;
+        Ok(elements.iter().filter(|e| matches_query(e, query)).map(element_to_dynamic).collect())
+    });
+
+    let l = luna.clone();
+    engine.register_fn("click", move |x: i64, y: i64| -> ScriptResult<()> {
+        l.borrow_mut().click(x as i32, y as i32).map_err(script_error)
+    });
+
+    let l = luna.clone();
+    engine.register_fn("click", move |element: Map| -> ScriptResult<()> {
+        let (x, y) = element_center(&element)?;
+        l.borrow_mut().click(x, y).map_err(script_error)
+    });
+
+    let l = luna.clone();
+    engine.register_fn("type_text", move |text: &str| -> ScriptResult<()> {
+        l.borrow_mut().type_text(text).map_err(script_error)
+    });
+
+    let l = luna.clone();
+    engine.register_fn("keys", move |chord: &str| -> ScriptResult<()> {
+        let chord = keys::parse_chord(chord).map_err(|e| script_error(LunaError::InvalidArgument(e.to_string()).into()))?;
+        let mut keys: Vec<String> = chord.modifiers.iter().map(|m| m.to_string()).collect();
+        keys.push(chord.key.to_string());
+        l.borrow_mut().send_keys(keys).map_err(script_error)
+    });
+
+    let l = luna.clone();
+    engine.register_fn("scroll", move |direction: &str, amount: i64| -> ScriptResult<()> {
+        l.borrow_mut().scroll(direction, amount as i32).map_err(script_error)
+    });
+
+    let d = deadline.clone();
+    engine.register_fn("wait", move |ms: i64| -> ScriptResult<()> {
+        sleep_within_deadline(&d, Duration::from_millis(ms.max(0) as u64))
+    });
+
+    let l = luna.clone();
+    let d = deadline.clone();
+    engine.register_fn("wait_for", move |query: &str, timeout_ms: i64| -> ScriptResult<Dynamic> {
+        let (timeout, clamped) = timeout_within_deadline(&d, timeout_ms);
+        match l.borrow_mut().wait_for_element(query, timeout).map_err(script_error)? {
+            Some(element) => Ok(element_to_dynamic(&element)),
+            None if clamped => Err("script exceeded its time limit".into()),
+            None => Ok(Dynamic::UNIT),
+        }
+    });
+
+    let l = luna.clone();
+    let d = deadline.clone();
+    engine.register_fn("wait_for_text_gone", move |text: &str, timeout_ms: i64| -> ScriptResult<bool> {
+        let (timeout, clamped) = timeout_within_deadline(&d, timeout_ms);
+        match l.borrow_mut().wait_for_text_gone(text, timeout).map_err(script_error)? {
+            false if clamped => Err("script exceeded its time limit".into()),
+            gone => Ok(gone),
+        }
+    });
+
+    let l = luna.clone();
+    let d = deadline.clone();
+    engine.register_fn("wait_idle", move |quiet_ms: i64, timeout_ms: i64| -> ScriptResult<bool> {
+        let (timeout, clamped) = timeout_within_deadline(&d, timeout_ms);
+        let quiet = Duration::from_millis(quiet_ms.max(0) as u64);
+        match l.borrow_mut().wait_for_screen_idle(quiet, timeout).map_err(script_error)? {
+            false if clamped => Err("script exceeded its time limit".into()),
+            idle => Ok(idle),
+        }
+    });
+
+    engine
+}
+
+fn analyze(luna: &Rc<RefCell<Luna>>) -> ScriptResult<Vec<ScreenElement>> {
+    luna.borrow_mut()
+        .analyze_current_screen()
+        .map(|analysis| analysis.elements)
+        .map_err(script_error)
+}
+
+/// Matches a selector such as `"save"` or `"button:save"`, ignoring case
+fn matches_query(element: &ScreenElement, query: &str) -> bool {
+    ElementQuery::parse(query).matches(element)
+}
+
+fn element_to_dynamic(element: &ScreenElement) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("type".into(), element.element_type.clone().into());
+    map.insert("x".into(), (element.bounds.x as i64).into());
+    map.insert("y".into(), (element.bounds.y as i64).into());
+    map.insert("width".into(), (element.bounds.width as i64).into());
+    map.insert("height".into(), (element.bounds.height as i64).into());
+    map.insert("confidence".into(), (element.confidence as f64).into());
+    map.insert(
+        "text".into(),
+        element.text.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT),
+    );
+    map.into()
+}
+
+fn element_center(element: &Map) -> ScriptResult<(i32, i32)> {
+    let field = |name: &str| -> ScriptResult<i64> {
+        element
+            .get(name)
+            .and_then(|v| v.as_int().ok())
+            .ok_or_else(|| format!("element is missing integer field '{}'", name).into())
+    };
+    let (x, y, w, h) = (field("x")?, field("y")?, field("width")?, field("height")?);
+    Ok(((x + w / 2) as i32, (y + h / 2) as i32))
+}
+
+/// Sleep, but never past the script's deadline
+fn sleep_within_deadline(deadline: &Rc<RefCell<Instant>>, duration: Duration) -> ScriptResult<()> {
+    let remaining = deadline.borrow().saturating_duration_since(Instant::now());
+    if duration > remaining {
+        std::thread::sleep(remaining);
+        return Err("script exceeded its time limit".into());
+    }
+    std::thread::sleep(duration);
+    Ok(())
+}
+
+/// `timeout_ms` cut to what is left of the script's time, and whether it was cut
+fn timeout_within_deadline(deadline: &Rc<RefCell<Instant>>, timeout_ms: i64) -> (Duration, bool) {
+    let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+    let remaining = deadline.borrow().saturating_duration_since(Instant::now());
+    (timeout.min(remaining), remaining < timeout)
+}
+
+fn script_error(error: anyhow::Error) -> Box<EvalAltResult> {
+    error.to_string().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::LunaConfig;
+
+    fn engine_with(limits: ScriptingConfig) -> ScriptEngine {
+        ScriptEngine::with_limits(Luna::new(LunaConfig::default()).unwrap(), limits)
+    }
+
+    #[test]
+    fn test_variables_persist_between_runs() {
+        let mut engine = ScriptEngine::new(Luna::new(LunaConfig::default()).unwrap());
+        engine.set_variable("base", 40_i64);
+        let _ = engine.run("let answer = base + 2;").unwrap();
+        assert_eq!(engine.get_variable::<i64>("answer"), Some(42));
+
+        engine.reset_variables();
+        assert!(engine.get_variable::<i64>("answer").is_none());
+    }
+
+    #[test]
+    fn test_curated_api() {
+        let mut engine = engine_with(ScriptingConfig::default());
+        let result = engine
+            .run(
+                r#"
+                let elements = analyze();
+                click(#{ x: 10, y: 20, width: 100, height: 40 });
+                type_text("hello");
+                keys("ctrl+s");
+                elements.len()
+                "#,
+            )
+            .unwrap();
+        assert!(result.as_int().unwrap() >= 0);
+
+        let luna = engine.into_luna();
+        assert!(luna.is_ready());
+    }
+
+    #[test]
+    fn test_keys_parse_chords_like_commands() {
+        let mut engine = engine_with(ScriptingConfig::default());
+        assert!(engine.run(r#"keys("ctrl++"); keys("Ctrl + Shift + T")"#).is_ok());
+        let err = engine.run(r#"keys("ctrl+nosuchkey")"#).unwrap_err();
+        assert!(err.to_string().contains("Unknown key name: nosuchkey"), "{}", err);
+        assert!(engine.run(r#"keys("ctrl+alt+delete")"#).is_err(), "still goes through the safety checker");
+    }
+
+    #[test]
+    fn test_safety_applies_to_scripts() {
+        let mut engine = engine_with(ScriptingConfig::default());
+        assert!(engine.run(r#"type_text("rm -rf /")"#).is_err());
+    }
+
+    #[test]
+    fn test_operation_limit() {
+        let mut engine = engine_with(ScriptingConfig { max_operations: 1_000, ..ScriptingConfig::default() });
+        let err = engine.run("let x = 0; loop { x += 1; }").unwrap_err();
+        assert!(err.to_string().contains("Script error"));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let mut engine = engine_with(ScriptingConfig {
+            max_operations: 0, // unlimited; only the clock stops this
+            max_runtime_ms: 100,
+            ..ScriptingConfig::default()
+        });
+        let start = Instant::now();
+        assert!(engine.run("loop { }").is_err());
+        assert!(engine.run("wait(10000)").is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_imports_are_disabled() {
+        let mut engine = engine_with(ScriptingConfig::default());
+        assert!(engine.run(r#"import "secrets" as s;"#).is_err());
+    }
+}
@@ -147,6 +147,62 @@ impl Rectangle {
             self.height + 2.0 * margin,
         )
     }
+
+    /// Intersection over union, 0.0 (disjoint) to 1.0 (identical)
+    pub fn iou(&self, other: &Rectangle) -> f64 {
+        let intersection = self.intersection(other).map_or(0.0, |r| r.area());
+        let union = self.area() + other.area() - intersection;
+        if union > 0.0 { intersection / union } else { 0.0 }
+    }
+
+    /// Corners clockwise from the top-left
+    pub fn to_polygon(&self) -> Polygon {
+        Polygon::new(vec![self.top_left(), self.top_right(), self.bottom_right(), self.bottom_left()])
+    }
+}
+
+/// Rectangle rotated by `angle` radians (clockwise on screen) around its center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RotatedRect {
+    pub center: Point,
+    pub width: f64,
+    pub height: f64,
+    pub angle: f64,
+}
+
+impl RotatedRect {
+    pub fn new(center: Point, width: f64, height: f64, angle: f64) -> Self {
+        Self { center, width, height, angle }
+    }
+
+    /// Corners in order, starting from the (unrotated) top-left
+    pub fn corners(&self) -> [Point; 4] {
+        let (hw, hh) = (self.width / 2.0, self.height / 2.0);
+        [(-hw, -hh), (hw, -hh), (hw, hh), (-hw, hh)]
+            .map(|(dx, dy)| self.center.translate(dx, dy).rotate_around(&self.center, self.angle))
+    }
+
+    pub fn to_polygon(&self) -> Polygon {
+        Polygon::new(self.corners().to_vec())
+    }
+
+    pub fn contains_point(&self, point: &Point) -> bool {
+        // Rotate the point into the rectangle's frame instead of the rectangle into the screen's
+        let local = point.rotate_around(&self.center, -self.angle);
+        (local.x - self.center.x).abs() <= self.width / 2.0 && (local.y - self.center.y).abs() <= self.height / 2.0
+    }
+
+    pub fn area(&self) -> f64 {
+        self.width * self.height
+    }
+
+    pub fn bounding_rectangle(&self) -> Rectangle {
+        self.to_polygon().bounding_rectangle().unwrap_or(Rectangle::new(self.center.x, self.center.y, 0.0, 0.0))
+    }
+
+    pub fn iou(&self, other: &RotatedRect) -> f64 {
+        self.to_polygon().iou(&other.to_polygon())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -182,7 +238,8 @@ impl Circle {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Simple polygon, vertices in order (either winding)
+#[derive(Debug, Clone, PartialEq)]
 pub struct Polygon {
     pub points: Vec<Point>,
 }
@@ -260,6 +317,243 @@ impl Polygon {
             sum_y / self.points.len() as f64,
         ))
     }
+
+    pub fn is_convex(&self) -> bool {
+        let n = self.points.len();
+        if n < 3 {
+            return false;
+        }
+        let mut sign = 0.0;
+        for i in 0..n {
+            let cross = cross(&self.points[i], &self.points[(i + 1) % n], &self.points[(i + 2) % n]);
+            if cross != 0.0 {
+                if sign * cross < 0.0 {
+                    return false;
+                }
+                sign = cross;
+            }
+        }
+        true
+    }
+
+    /// This polygon clipped to a convex `clip` polygon (Sutherland-Hodgman)
+    pub fn clip_to_convex(&self, clip: &Polygon) -> Option<Polygon> {
+        let orientation = clip.signed_area().signum();
+        let mut output = self.points.clone();
+        let n = clip.points.len();
+        for i in 0..n {
+            let (a, b) = (clip.points[i], clip.points[(i + 1) % n]);
+            let inside = |p: &Point| cross(&a, &b, p) * orientation >= 0.0;
+            let input = std::mem::take(&mut output);
+            for (j, current) in input.iter().enumerate() {
+                let previous = &input[(j + input.len() - 1) % input.len()];
+                match (inside(previous), inside(current)) {
+                    (true, true) => output.push(*current),
+                    (true, false) => output.push(line_intersection(previous, current, &a, &b)),
+                    (false, true) => {
+                        output.push(line_intersection(previous, current, &a, &b));
+                        output.push(*current);
+                    }
+                    (false, false) => {}
+                }
+            }
+            if output.is_empty() {
+                return None;
+            }
+        }
+        let clipped = Polygon::new(output);
+        (clipped.area() > 0.0).then_some(clipped)
+    }
+
+    /// Area shared with `other`: exact when either is convex, sampled otherwise
+    pub fn intersection_area(&self, other: &Polygon) -> f64 {
+        if other.is_convex() {
+            return self.clip_to_convex(other).map_or(0.0, |p| p.area());
+        }
+        if self.is_convex() {
+            return other.clip_to_convex(self).map_or(0.0, |p| p.area());
+        }
+        let (Some(a), Some(b)) = (self.bounding_rectangle(), other.bounding_rectangle()) else {
+            return 0.0;
+        };
+        let Some(region) = a.intersection(&b) else {
+            return 0.0;
+        };
+        const SAMPLES: usize = 64;
+        let (dx, dy) = (region.width / SAMPLES as f64, region.height / SAMPLES as f64);
+        let hits = (0..SAMPLES * SAMPLES)
+            .map(|i| Point::new(region.x + (i % SAMPLES) as f64 * dx + dx / 2.0, region.y + (i / SAMPLES) as f64 * dy + dy / 2.0))
+            .filter(|p| self.contains_point(p) && other.contains_point(p))
+            .count();
+        hits as f64 * dx * dy
+    }
+
+    /// Intersection over union, 0.0 (disjoint) to 1.0 (identical)
+    pub fn iou(&self, other: &Polygon) -> f64 {
+        let intersection = self.intersection_area(other);
+        let union = self.area() + other.area() - intersection;
+        if union > 0.0 { intersection / union } else { 0.0 }
+    }
+
+    /// Whether `other` lies entirely inside this polygon
+    pub fn contains_polygon(&self, other: &Polygon) -> bool {
+        if other.points.is_empty() || !other.points.iter().all(|p| self.contains_point(p)) {
+            return false;
+        }
+        // All vertices inside is enough for a convex container; otherwise no edges may cross
+        self.is_convex() || !self.edges().any(|(a, b)| other.edges().any(|(c, d)| segments_cross(a, b, c, d)))
+    }
+
+    /// A point well inside the polygon, for clicking: the centroid when it is
+    /// inside, else the middle of the widest inside span on the centroid's row
+    pub fn interior_point(&self) -> Option<Point> {
+        let centroid = self.centroid()?;
+        if self.contains_point(&centroid) {
+            return Some(centroid);
+        }
+        let y = centroid.y;
+        let mut crossings: Vec<f64> = self
+            .edges()
+            .filter(|(a, b)| (a.y > y) != (b.y > y))
+            .map(|(a, b)| a.x + (y - a.y) * (b.x - a.x) / (b.y - a.y))
+            .collect();
+        crossings.sort_by(f64::total_cmp);
+        crossings
+            .chunks_exact(2)
+            .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+            .map(|span| Point::new((span[0] + span[1]) / 2.0, y))
+    }
+
+    /// Distance from `point` to the nearest edge; negative outside
+    pub fn signed_distance(&self, point: &Point) -> f64 {
+        let distance = self.edges().map(|(a, b)| segment_distance(point, a, b)).fold(f64::INFINITY, f64::min);
+        if self.contains_point(point) { distance } else { -distance }
+    }
+
+    /// The inside point farthest from every edge (pole of inaccessibility),
+    /// to within `precision`: cells covering the polygon are split best
+    /// first, skipping any that cannot hold a better point. `None` for a
+    /// polygon with no inside.
+    pub fn pole_of_inaccessibility(&self, precision: f64) -> Option<Point> {
+        let bounds = self.bounding_rectangle()?;
+        let size = bounds.width.min(bounds.height);
+        if self.points.len() < 3 || size <= 0.0 {
+            return None;
+        }
+        let cell = |center: Point, half: f64| PoleCell { center, half, distance: self.signed_distance(&center) };
+
+        let mut queue = std::collections::BinaryHeap::new();
+        let half = size / 2.0;
+        let mut y = bounds.y;
+        while y < bounds.y + bounds.height {
+            let mut x = bounds.x;
+            while x < bounds.x + bounds.width {
+                queue.push(cell(Point::new(x + half, y + half), half));
+                x += size;
+            }
+            y += size;
+        }
+        let mut best = cell(bounds.center(), 0.0);
+        if let Some(centroid) = self.centroid().map(|c| cell(c, 0.0)).filter(|c| c.distance > best.distance) {
+            best = centroid;
+        }
+        while let Some(current) = queue.pop() {
+            if current.distance > best.distance {
+                best = PoleCell { half: 0.0, ..current };
+            }
+            if current.potential() - best.distance <= precision {
+                continue;
+            }
+            let half = current.half / 2.0;
+            for (dx, dy) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                queue.push(cell(Point::new(current.center.x + dx * half, current.center.y + dy * half), half));
+            }
+        }
+        (best.distance > 0.0).then_some(best.center)
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (&Point, &Point)> {
+        let n = self.points.len();
+        (0..n).map(move |i| (&self.points[i], &self.points[(i + 1) % n]))
+    }
+
+    fn signed_area(&self) -> f64 {
+        self.edges().map(|(a, b)| a.x * b.y - b.x * a.y).sum::<f64>() / 2.0
+    }
+}
+
+impl From<Rectangle> for Polygon {
+    fn from(rect: Rectangle) -> Self {
+        rect.to_polygon()
+    }
+}
+
+impl From<RotatedRect> for Polygon {
+    fn from(rect: RotatedRect) -> Self {
+        rect.to_polygon()
+    }
+}
+
+/// Square cell searched by `Polygon::pole_of_inaccessibility`, ordered by the
+/// best distance any point inside it could have
+struct PoleCell {
+    center: Point,
+    half: f64,
+    /// Signed distance from the center to the polygon's edges
+    distance: f64,
+}
+
+impl PoleCell {
+    fn potential(&self) -> f64 {
+        self.distance + self.half * std::f64::consts::SQRT_2
+    }
+}
+
+impl PartialEq for PoleCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for PoleCell {}
+
+impl PartialOrd for PoleCell {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PoleCell {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.potential().total_cmp(&other.potential())
+    }
+}
+
+/// Distance from `p` to the segment a-b
+fn segment_distance(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 { (((p.x - a.x) * dx + (p.y - a.y) * dy) / length).clamp(0.0, 1.0) } else { 0.0 };
+    p.distance_to(&Point::new(a.x + t * dx, a.y + t * dy))
+}
+
+/// Z component of (b - a) x (c - a): positive when c is left of a->b
+fn cross(a: &Point, b: &Point, c: &Point) -> f64 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// Where segment p->q meets the infinite line through a and b
+fn line_intersection(p: &Point, q: &Point, a: &Point, b: &Point) -> Point {
+    let (d1, d2) = (cross(a, b, p), cross(a, b, q));
+    let t = d1 / (d1 - d2);
+    point_interpolate(p, q, t)
+}
+
+/// Proper crossing of segments a-b and c-d (touching endpoints don't count)
+fn segments_cross(a: &Point, b: &Point, c: &Point, d: &Point) -> bool {
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
 }
 
 // Utility functions for geometric calculations
@@ -321,7 +615,7 @@ impl SpatialGrid {
 
         for x in min_cell.0..=max_cell.0 {
             for y in min_cell.1..=max_cell.1 {
-                self.objects.entry((x, y)).or_insert_with(Vec::new).push(id);
+                self.objects.entry((x, y)).or_default().push(id);
             }
         }
     }
@@ -403,6 +697,75 @@ mod tests {
         assert_eq!(triangle.area(), 50.0);
     }
 
+    #[test]
+    fn test_rotated_rect() {
+        let diamond = RotatedRect::new(Point::new(10.0, 10.0), 10.0, 10.0, PI / 4.0);
+        assert!(diamond.contains_point(&Point::new(10.0, 3.5)));
+        // A corner of the axis-aligned box is outside the rotated one
+        assert!(!diamond.contains_point(&Point::new(6.0, 6.0)));
+        let bounds = diamond.bounding_rectangle();
+        assert!((bounds.width - 50f64.sqrt() * 2.0).abs() < 1e-9);
+        assert!((diamond.to_polygon().area() - 100.0).abs() < 1e-9);
+        assert!((diamond.iou(&diamond) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_polygon_intersection_and_iou() {
+        let a = Rectangle::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rectangle::new(5.0, 0.0, 10.0, 10.0);
+        assert!((a.iou(&b) - 50.0 / 150.0).abs() < 1e-9);
+        assert!((a.to_polygon().iou(&b.to_polygon()) - a.iou(&b)).abs() < 1e-9);
+        assert_eq!(a.to_polygon().intersection_area(&Rectangle::new(20.0, 0.0, 5.0, 5.0).to_polygon()), 0.0);
+
+        // Concave L-shape: exact against a convex polygon, sampled against another concave one
+        let l_shape = Polygon::new(vec![
+            Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 4.0),
+            Point::new(4.0, 4.0), Point::new(4.0, 10.0), Point::new(0.0, 10.0),
+        ]);
+        assert!(!l_shape.is_convex());
+        let notch = Polygon::new(vec![
+            Point::new(2.0, 2.0), Point::new(8.0, 2.0), Point::new(8.0, 8.0), Point::new(5.0, 9.0), Point::new(2.0, 8.0),
+        ]);
+        let exact = notch.clip_to_convex(&Rectangle::new(0.0, 0.0, 10.0, 10.0).to_polygon()).unwrap().area();
+        assert!((exact - notch.area()).abs() < 1e-9);
+        assert!((l_shape.intersection_area(&Rectangle::new(2.0, 2.0, 6.0, 6.0).to_polygon()) - 20.0).abs() < 1e-9);
+        assert!((l_shape.intersection_area(&l_shape) - l_shape.area()).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_polygon_containment_and_interior_point() {
+        let outer = Rectangle::new(0.0, 0.0, 20.0, 20.0).to_polygon();
+        let inner = RotatedRect::new(Point::new(10.0, 10.0), 6.0, 6.0, 0.3).to_polygon();
+        assert!(outer.contains_polygon(&inner));
+        assert!(!inner.contains_polygon(&outer));
+
+        // A C-shape's centroid falls in its gap; the click point must not
+        let c_shape = Polygon::new(vec![
+            Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 2.0), Point::new(2.0, 2.0),
+            Point::new(2.0, 8.0), Point::new(10.0, 8.0), Point::new(10.0, 10.0), Point::new(0.0, 10.0),
+        ]);
+        assert!(!c_shape.contains_point(&c_shape.centroid().unwrap()));
+        assert!(c_shape.contains_point(&c_shape.interior_point().unwrap()));
+        assert!(!c_shape.contains_polygon(&Rectangle::new(1.0, 1.0, 8.0, 8.0).to_polygon()));
+    }
+
+    #[test]
+    fn test_pole_of_inaccessibility() {
+        // A wide L: the pole sits in the thick foot, well away from every edge
+        let l_shape = Polygon::new(vec![
+            Point::new(0.0, 0.0), Point::new(4.0, 0.0), Point::new(4.0, 16.0),
+            Point::new(30.0, 16.0), Point::new(30.0, 30.0), Point::new(0.0, 30.0),
+        ]);
+        let pole = l_shape.pole_of_inaccessibility(0.1).unwrap();
+        assert!(l_shape.signed_distance(&pole) > 6.9, "{:?}", pole);
+        assert!(pole.y > 16.0);
+
+        let square = Rectangle::new(0.0, 0.0, 10.0, 10.0).to_polygon();
+        let pole = square.pole_of_inaccessibility(0.1).unwrap();
+        assert!(pole.distance_to(&Point::new(5.0, 5.0)) < 0.2);
+        assert!(Polygon::new(vec![Point::new(0.0, 0.0), Point::new(5.0, 0.0)]).pole_of_inaccessibility(0.1).is_none());
+    }
+
     #[test]
     fn test_spatial_grid() {
         let mut grid = SpatialGrid::new(10.0);
@@ -185,20 +185,20 @@ fn generate_gaussian_kernel(radius: usize) -> Vec<Vec<f64>> {
     let two_sigma_sq = 2.0 * sigma * sigma;
     let mut sum = 0.0;
 
-    for y in 0..size {
-        for x in 0..size {
+    for (y, row) in kernel.iter_mut().enumerate() {
+        for (x, cell) in row.iter_mut().enumerate() {
             let dx = x as f64 - radius as f64;
             let dy = y as f64 - radius as f64;
             let value = (-((dx * dx + dy * dy) / two_sigma_sq)).exp();
-            kernel[y][x] = value;
+            *cell = value;
             sum += value;
         }
     }
 
     // Normalize kernel
-    for y in 0..size {
-        for x in 0..size {
-            kernel[y][x] /= sum;
+    for row in kernel.iter_mut() {
+        for cell in row.iter_mut() {
+            *cell /= sum;
         }
     }
 
@@ -214,13 +214,12 @@ fn apply_convolution(image: &Image, kernel: &[Vec<f64>]) -> Image {
         for x in kernel_radius..image.width - kernel_radius {
             let mut new_pixel = vec![0.0; image.channels];
 
-            for ky in 0..kernel_size {
-                for kx in 0..kernel_size {
+            for (ky, kernel_row) in kernel.iter().enumerate() {
+                for (kx, &weight) in kernel_row.iter().enumerate() {
                     let pixel_x = x + kx - kernel_radius;
                     let pixel_y = y + ky - kernel_radius;
                     
                     if let Some(pixel) = image.get_pixel(pixel_x, pixel_y) {
-                        let weight = kernel[ky][kx];
                         for c in 0..image.channels {
                             new_pixel[c] += pixel[c] as f64 * weight;
                         }
@@ -417,10 +416,52 @@ pub fn calculate_histogram(image: &Image, channel: usize) -> Vec<u32> {
     histogram
 }
 
+// Fraction of pixels inside `rect` that differ between two same-sized frames
+// by more than `tolerance` in any channel
+pub fn changed_fraction(before: &Image, after: &Image, rect: &Rectangle, tolerance: u8) -> f64 {
+    let x0 = rect.x.max(0.0) as usize;
+    let y0 = rect.y.max(0.0) as usize;
+    let x1 = ((rect.x + rect.width).max(0.0) as usize).min(before.width).min(after.width);
+    let y1 = ((rect.y + rect.height).max(0.0) as usize).min(before.height).min(after.height);
+    if x1 <= x0 || y1 <= y0 {
+        return 0.0;
+    }
+
+    let mut changed = 0usize;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let differs = match (before.get_pixel(x, y), after.get_pixel(x, y)) {
+                (Some(a), Some(b)) if a.len() == b.len() => a.iter().zip(b).any(|(p, q)| p.abs_diff(*q) > tolerance),
+                _ => true,
+            };
+            changed += differs as usize;
+        }
+    }
+    changed as f64 / ((x1 - x0) * (y1 - y0)) as f64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_changed_fraction() {
+        let before = Image::from_rgb_data(10, 10, vec![100; 300]);
+        let mut after = before.clone();
+        for x in 0..5 {
+            after.set_pixel(x, 0, &[200, 100, 100]);
+        }
+        after.set_pixel(9, 9, &[110, 100, 100]);
+
+        let row = Rectangle::new(0.0, 0.0, 10.0, 1.0);
+        assert_eq!(changed_fraction(&before, &after, &row, 20), 0.5);
+        // Within tolerance
+        assert_eq!(changed_fraction(&before, &after, &Rectangle::new(9.0, 9.0, 1.0, 1.0), 20), 0.0);
+        // Clipped to the frame
+        assert_eq!(changed_fraction(&before, &after, &Rectangle::new(-5.0, -5.0, 10.0, 6.0), 20), 1.0);
+        assert_eq!(changed_fraction(&before, &after, &Rectangle::new(50.0, 50.0, 5.0, 5.0), 20), 0.0);
+    }
+
     #[test]
     fn test_image_creation() {
         let image = Image::new(100, 100, 3);
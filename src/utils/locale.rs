@@ -0,0 +1,264 @@
+// Locale-aware number and date formatting without ICU
+// A German form wants "1.234,56" and "16.10.2026" where an American one
+// wants "1,234.56" and "10/16/2026". Each supported locale is one row of
+// separators and date order; that covers what gets typed into fields
+// without pulling in the full CLDR data.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Order of the parts of a written date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// How numbers and dates are written in one locale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    /// BCP 47 tag, e.g. "de-DE"
+    pub tag: &'static str,
+    pub decimal: char,
+    /// Between groups of three integer digits
+    pub grouping: char,
+    pub date_order: DateOrder,
+    pub date_separator: char,
+}
+
+const fn locale(tag: &'static str, decimal: char, grouping: char, date_order: DateOrder, date_separator: char) -> Locale {
+    Locale { tag, decimal, grouping, date_order, date_separator }
+}
+
+use DateOrder::{DayMonthYear as Dmy, MonthDayYear as Mdy, YearMonthDay as Ymd};
+
+/// Supported locales; the first one of each language stands in for the
+/// language alone ("de" is de-DE)
+const LOCALES: &[Locale] = &[
+    locale("en-US", '.', ',', Mdy, '/'),
+    locale("en-GB", '.', ',', Dmy, '/'),
+    locale("de-DE", ',', '.', Dmy, '.'),
+    locale("de-AT", ',', '.', Dmy, '.'),
+    locale("de-CH", '.', '\'', Dmy, '.'),
+    locale("fr-FR", ',', ' ', Dmy, '/'),
+    locale("es-ES", ',', '.', Dmy, '/'),
+    locale("it-IT", ',', '.', Dmy, '/'),
+    locale("nl-NL", ',', '.', Dmy, '-'),
+    locale("pt-BR", ',', '.', Dmy, '/'),
+    locale("pl-PL", ',', ' ', Dmy, '.'),
+    locale("ru-RU", ',', ' ', Dmy, '.'),
+    locale("sv-SE", ',', ' ', Ymd, '-'),
+    locale("ja-JP", '.', ',', Ymd, '/'),
+    locale("zh-CN", '.', ',', Ymd, '/'),
+];
+
+/// Names a command may use for a locale
+const NAMES: &[(&str, &str)] = &[
+    ("english", "en-US"),
+    ("american", "en-US"),
+    ("us", "en-US"),
+    ("british", "en-GB"),
+    ("uk", "en-GB"),
+    ("german", "de-DE"),
+    ("austrian", "de-AT"),
+    ("swiss", "de-CH"),
+    ("french", "fr-FR"),
+    ("spanish", "es-ES"),
+    ("italian", "it-IT"),
+    ("dutch", "nl-NL"),
+    ("portuguese", "pt-BR"),
+    ("brazilian", "pt-BR"),
+    ("polish", "pl-PL"),
+    ("russian", "ru-RU"),
+    ("swedish", "sv-SE"),
+    ("japanese", "ja-JP"),
+    ("chinese", "zh-CN"),
+    ("iso", "sv-SE"),
+];
+
+/// Environment variables naming the system locale, most specific first
+const LOCALE_VARIABLES: [&str; 3] = ["LC_ALL", "LC_NUMERIC", "LANG"];
+
+impl Locale {
+    /// A locale by tag ("de-DE", "de_DE.UTF-8", "de") or name ("German")
+    pub fn parse(name: &str) -> Option<Locale> {
+        let name = name.trim().to_lowercase();
+        let tag = name.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+        if tag == "c" || tag == "posix" {
+            return Some(Self::default());
+        }
+        LOCALES
+            .iter()
+            .find(|l| l.tag.eq_ignore_ascii_case(&tag))
+            .or_else(|| LOCALES.iter().find(|l| l.tag.split('-').next() == Some(tag.as_str())))
+            .or_else(|| {
+                let (_, tag) = NAMES.iter().find(|(n, _)| *n == name)?;
+                LOCALES.iter().find(|l| l.tag == *tag)
+            })
+            .copied()
+    }
+
+    /// The locale the system is set to, or en-US when it names none we know
+    pub fn system() -> Locale {
+        LOCALE_VARIABLES
+            .iter()
+            .filter_map(|variable| std::env::var(variable).ok())
+            .find_map(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    /// `value` with `decimals` digits after the decimal separator and the
+    /// integer digits grouped ("1.234,56" in de-DE)
+    pub fn format_number(&self, value: f64, decimals: usize) -> String {
+        let fixed = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = fixed.split_once('.').unwrap_or((&fixed, ""));
+        let mut formatted = String::new();
+        if value < 0.0 && fixed.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            formatted.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                formatted.push(self.grouping);
+            }
+            formatted.push(digit);
+        }
+        if !fraction.is_empty() {
+            formatted.push(self.decimal);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+
+    /// `date` written with zero-padded day and month ("16.10.2026" in de-DE)
+    pub fn format_date(&self, date: Date) -> String {
+        let (year, month, day) = (format!("{:04}", date.year), format!("{:02}", date.month), format!("{:02}", date.day));
+        let parts = match self.date_order {
+            DateOrder::DayMonthYear => [day, month, year],
+            DateOrder::MonthDayYear => [month, day, year],
+            DateOrder::YearMonthDay => [year, month, day],
+        };
+        parts.join(&self.date_separator.to_string())
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        LOCALES[0]
+    }
+}
+
+/// A calendar date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// Today in UTC
+    pub fn today() -> Date {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        Self::from_days((seconds / 86_400) as i64)
+    }
+
+    /// The date `days` after 1970-01-01 (civil-from-days, proleptic Gregorian)
+    pub fn from_days(days: i64) -> Date {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+        let year = (year_of_era + era * 400 + i64::from(month <= 2)) as i32;
+        Date { year, month, day }
+    }
+
+    /// Days since 1970-01-01
+    pub fn to_days(self) -> i64 {
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month = i64::from(self.month);
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    pub fn add_days(self, days: i64) -> Date {
+        Self::from_days(self.to_days() + days)
+    }
+}
+
+/// A number as written in a command, with how many decimals it was written
+/// with. With both separators present the later one is the decimal point
+/// ("1.234,56", "1,234.56"). A lone dot is a decimal point, and so is a lone
+/// comma unless three digits follow it ("1,5" but "1,234").
+pub fn parse_amount(text: &str) -> Option<(f64, usize)> {
+    let text = text.trim();
+    let negative = text.starts_with('-');
+    let digits = text.trim_start_matches(['-', '+']);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '.') {
+        return None;
+    }
+    let count = |separator| digits.matches(separator).count();
+    let decimal = match (digits.rfind(','), digits.rfind('.')) {
+        (Some(comma), Some(dot)) => Some(if comma > dot { ',' } else { '.' }),
+        (None, Some(_)) if count('.') == 1 => Some('.'),
+        (Some(comma), None) if count(',') == 1 && digits.len() - comma - 1 != 3 => Some(','),
+        _ => None,
+    };
+    let (integer, fraction) = match decimal {
+        Some(separator) => digits.rsplit_once(separator)?,
+        None => (digits, ""),
+    };
+    // Whatever separates the integer digits groups them in threes
+    let groups: Vec<&str> = integer.split([',', '.']).collect();
+    let grouped = groups.len() == 1 || ((1..=3).contains(&groups[0].len()) && groups[1..].iter().all(|g| g.len() == 3));
+    if !grouped || fraction.contains([',', '.']) {
+        return None;
+    }
+    let value: f64 = format!("{}.{}0", groups.concat(), fraction).parse().ok()?;
+    Some((if negative { -value } else { value }, fraction.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locales_parse_and_format() {
+        let german = Locale::parse("de_DE.UTF-8").unwrap();
+        assert_eq!(Locale::parse("German"), Some(german));
+        assert_eq!(Locale::parse("de"), Some(german));
+        assert_eq!(Locale::parse("klingon"), None);
+
+        assert_eq!(german.format_number(1234.56, 2), "1.234,56");
+        assert_eq!(Locale::default().format_number(-1234567.0, 0), "-1,234,567");
+        assert_eq!(Locale::parse("fr-FR").unwrap().format_number(0.5, 2), "0,50");
+
+        let date = Date { year: 2026, month: 3, day: 7 };
+        assert_eq!(german.format_date(date), "07.03.2026");
+        assert_eq!(Locale::default().format_date(date), "03/07/2026");
+        assert_eq!(Locale::parse("ja").unwrap().format_date(date), "2026/03/07");
+    }
+
+    #[test]
+    fn test_dates_and_amounts() {
+        assert_eq!(Date::from_days(0), Date { year: 1970, month: 1, day: 1 });
+        assert_eq!(Date::from_days(20_742), Date { year: 2026, month: 10, day: 16 });
+        let leap = Date { year: 2024, month: 2, day: 28 };
+        assert_eq!(leap.add_days(1), Date { year: 2024, month: 2, day: 29 });
+        assert_eq!(leap.add_days(2).to_days() - leap.to_days(), 2);
+
+        assert_eq!(parse_amount("1,234.56"), Some((1234.56, 2)));
+        assert_eq!(parse_amount("1.234,5"), Some((1234.5, 1)));
+        assert_eq!(parse_amount("-42"), Some((-42.0, 0)));
+        assert_eq!(parse_amount("1,5"), Some((1.5, 1)));
+        assert_eq!(parse_amount("1.234.567"), Some((1234567.0, 0)));
+        assert_eq!(parse_amount("1.2.3"), None);
+        assert_eq!(parse_amount("12 apples"), None);
+    }
+}
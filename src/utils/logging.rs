@@ -136,19 +136,14 @@ fn format_timestamp() -> String {
 }
 
 // Global logger instance
-static mut GLOBAL_LOGGER: Option<Logger> = None;
-static LOGGER_INIT: std::sync::Once = std::sync::Once::new();
+static GLOBAL_LOGGER: std::sync::OnceLock<Logger> = std::sync::OnceLock::new();
 
 pub fn init_logger(logger: Logger) {
-    LOGGER_INIT.call_once(|| {
-        unsafe {
-            GLOBAL_LOGGER = Some(logger);
-        }
-    });
+    let _ = GLOBAL_LOGGER.set(logger);
 }
 
 pub fn get_logger() -> Option<&'static Logger> {
-    unsafe { GLOBAL_LOGGER.as_ref() }
+    GLOBAL_LOGGER.get()
 }
 
 // Convenience macros for global logging
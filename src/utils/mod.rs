@@ -6,9 +6,17 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod des;
+pub mod digest;
 pub mod logging;
 pub mod geometry;
 pub mod image_processing;
+pub mod locale;
+pub mod quantity;
+pub mod text;
+#[cfg(target_os = "windows")]
+pub(crate) mod win32;
+pub mod zip;
 
 // Simple error type for utility functions
 #[derive(Debug)]
@@ -234,12 +242,10 @@ pub fn sanitize_filename(filename: &str) -> String {
         .collect()
 }
 
+/// At most `max_length` characters as a reader counts them (grapheme
+/// clusters), ending in "..." when cut; see `text::truncate`
 pub fn truncate_string(s: &str, max_length: usize) -> String {
-    if s.len() <= max_length {
-        s.to_string()
-    } else {
-        format!("{}...", &s[..max_length.saturating_sub(3)])
-    }
+    text::truncate(s, max_length)
 }
 
 pub fn escape_regex(text: &str) -> String {
@@ -312,7 +318,7 @@ impl PerformanceMonitor {
         let duration = start.elapsed().unwrap_or_default().as_millis() as u64;
         
         self.measurements.entry(name.to_string())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(duration);
         
         result
@@ -0,0 +1,222 @@
+// Quantities in command text: durations, scroll distances and percentages
+// "wait two minutes", "scroll down half a page" and "set zoom to 150%" name
+// amounts in units an action doesn't take. Each parser here reads one kind
+// of quantity (digits, number words, "a", "half a") with its unit and
+// converts it to what the action needs: milliseconds, wheel notches or
+// zoom steps. How far a page or a notch scrolls is configured
+// (`input.page_pixels`, `input.notch_pixels`).
+
+/// Scrolling "to the bottom" or "to the top": the most one scroll action may
+/// move (the safety check's limit), which reaches the end of any document
+/// Luna is likely to scroll
+pub const SCROLL_TO_END_NOTCHES: i32 = 100;
+
+/// How far a scroll distance moves, stated in the command's units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrollAmount {
+    /// No amount given: the configured default
+    Default,
+    /// Wheel notches ("scroll down 5", "5 clicks")
+    Notches(f64),
+    Pixels(f64),
+    Pages(f64),
+    /// All the way to the top or bottom
+    ToEnd,
+}
+
+/// A parsed "scroll ..." command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scroll {
+    pub up: bool,
+    pub amount: ScrollAmount,
+}
+
+/// Sizes that convert scroll distances into wheel notches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrollUnits {
+    /// Notches for a scroll without an amount
+    pub default_notches: i32,
+    /// Pixels one wheel notch scrolls
+    pub notch_pixels: u32,
+    /// Pixels in a "page"
+    pub page_pixels: u32,
+}
+
+impl Default for ScrollUnits {
+    fn default() -> Self {
+        Self { default_notches: 3, notch_pixels: 40, page_pixels: 600 }
+    }
+}
+
+impl ScrollUnits {
+    /// Wheel notches for `amount`; at least one
+    pub fn notches(&self, amount: ScrollAmount) -> i32 {
+        let notch = f64::from(self.notch_pixels.max(1));
+        let notches = match amount {
+            ScrollAmount::Default => return self.default_notches,
+            ScrollAmount::ToEnd => return SCROLL_TO_END_NOTCHES,
+            ScrollAmount::Notches(notches) => notches,
+            ScrollAmount::Pixels(pixels) => pixels / notch,
+            ScrollAmount::Pages(pages) => pages * f64::from(self.page_pixels) / notch,
+        };
+        (notches.round() as i32).max(1)
+    }
+}
+
+/// "2 seconds", "500ms", "two minutes", "half a minute", "1 minute and 30
+/// seconds" in milliseconds
+pub fn parse_duration_ms(text: &str) -> Option<u64> {
+    let text = text.trim().to_lowercase();
+    let mut total = 0.0;
+    for part in text.split(" and ").flat_map(|part| part.split(", ")) {
+        let (amount, unit) = split_amount(part.trim())?;
+        let scale = match unit {
+            "ms" | "millisecond" | "milliseconds" => 1.0,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1000.0,
+            "min" | "mins" | "minute" | "minutes" => 60_000.0,
+            "h" | "hour" | "hours" => 3_600_000.0,
+            _ => return None,
+        };
+        total += amount * scale;
+    }
+    Some(total.round() as u64)
+}
+
+/// What follows "scroll": "down", "up 5", "down 300 pixels", "half a page",
+/// "two pages up", "to the bottom". `None` when it isn't a scroll distance.
+pub fn parse_scroll(text: &str) -> Option<Scroll> {
+    let text = text.trim().trim_end_matches(['.', '!']).to_lowercase();
+    let mut words: Vec<&str> = text.split_whitespace().collect();
+    let mut up = false;
+    words.retain(|word| match *word {
+        "up" | "upwards" => {
+            up = true;
+            false
+        }
+        "down" | "downwards" => false,
+        _ => true,
+    });
+    let rest = words.join(" ");
+    let rest = rest.strip_prefix("by ").unwrap_or(&rest);
+
+    if let Some(end) = ["to the ", "to "].iter().find_map(|to| rest.strip_prefix(to)) {
+        return match end {
+            "top" | "start" | "beginning" => Some(Scroll { up: true, amount: ScrollAmount::ToEnd }),
+            "bottom" | "end" => Some(Scroll { up: false, amount: ScrollAmount::ToEnd }),
+            _ => None,
+        };
+    }
+    if rest.is_empty() {
+        return Some(Scroll { up, amount: ScrollAmount::Default });
+    }
+    let (amount, unit) = split_amount(rest)?;
+    let amount = match unit {
+        "" | "notch" | "notches" | "click" | "clicks" | "time" | "times" => ScrollAmount::Notches(amount),
+        "px" | "pixel" | "pixels" => ScrollAmount::Pixels(amount),
+        "page" | "pages" | "screen" | "screens" | "screenful" => ScrollAmount::Pages(amount),
+        _ => return None,
+    };
+    Some(Scroll { up, amount })
+}
+
+/// "150%", "150 %", "150 percent"
+pub fn parse_percent(text: &str) -> Option<f64> {
+    let text = text.trim().trim_end_matches(['.', '!']).to_lowercase();
+    let number = text.strip_suffix('%').or_else(|| text.strip_suffix("percent"))?.trim();
+    number.parse().ok().filter(|p: &f64| p.is_finite() && *p > 0.0)
+}
+
+/// Zoom-in presses (negative: zoom-out) from 100% to the step in `steps`
+/// nearest `percent`, as browsers zoom in fixed steps. `None` without a
+/// 100% step to count from.
+pub fn zoom_presses(percent: f64, steps: &[u32]) -> Option<i32> {
+    let mut steps = steps.to_vec();
+    steps.sort_unstable();
+    steps.dedup();
+    let home = steps.iter().position(|&step| step == 100)?;
+    let nearest = (0..steps.len()).min_by(|&a, &b| {
+        (f64::from(steps[a]) - percent).abs().total_cmp(&(f64::from(steps[b]) - percent).abs())
+    })?;
+    Some(nearest as i32 - home as i32)
+}
+
+/// A leading amount and the unit after it: "1.5 seconds" -> (1.5,
+/// "seconds"), "500ms" -> (500, "ms"), "half a page" -> (0.5, "page")
+fn split_amount(text: &str) -> Option<(f64, &str)> {
+    const WORDS: [(&str, f64); 16] = [
+        ("half a ", 0.5),
+        ("half an ", 0.5),
+        ("a quarter of a ", 0.25),
+        ("a couple of ", 2.0),
+        ("a ", 1.0),
+        ("an ", 1.0),
+        ("one ", 1.0),
+        ("two ", 2.0),
+        ("three ", 3.0),
+        ("four ", 4.0),
+        ("five ", 5.0),
+        ("six ", 6.0),
+        ("seven ", 7.0),
+        ("eight ", 8.0),
+        ("nine ", 9.0),
+        ("ten ", 10.0),
+    ];
+    if let Some((amount, unit)) = WORDS.iter().find_map(|(word, amount)| Some((*amount, text.strip_prefix(word)?))) {
+        return Some((amount, unit.trim()));
+    }
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let amount: f64 = text[..split].parse().ok()?;
+    Some((amount, text[split..].trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_duration_ms("2 minutes"), Some(120_000));
+        assert_eq!(parse_duration_ms("1.5 seconds"), Some(1500));
+        assert_eq!(parse_duration_ms("500ms"), Some(500));
+        assert_eq!(parse_duration_ms("Two Seconds"), Some(2000));
+        assert_eq!(parse_duration_ms("half a minute"), Some(30_000));
+        assert_eq!(parse_duration_ms("a second"), Some(1000));
+        assert_eq!(parse_duration_ms("1 minute and 30 seconds"), Some(90_000));
+        assert_eq!(parse_duration_ms("for the dialog"), None);
+        assert_eq!(parse_duration_ms("2 pages"), None);
+        assert_eq!(parse_duration_ms("seconds"), None);
+    }
+
+    #[test]
+    fn test_scroll_amounts_in_notches() {
+        let units = ScrollUnits { default_notches: 3, notch_pixels: 40, page_pixels: 600 };
+        let notches = |text: &str| parse_scroll(text).map(|scroll| (scroll.up, units.notches(scroll.amount)));
+        assert_eq!(notches("down"), Some((false, 3)));
+        assert_eq!(notches("up 5"), Some((true, 5)));
+        assert_eq!(notches("down 5 times"), Some((false, 5)));
+        assert_eq!(notches("half a page"), Some((false, 8)));
+        assert_eq!(notches("up two pages"), Some((true, 30)));
+        assert_eq!(notches("a page up"), Some((true, 15)));
+        assert_eq!(notches("down by 300 pixels"), Some((false, 8)));
+        assert_eq!(notches("down 10px"), Some((false, 1)));
+        assert_eq!(notches("to the bottom"), Some((false, SCROLL_TO_END_NOTCHES)));
+        assert_eq!(notches("to the top."), Some((true, SCROLL_TO_END_NOTCHES)));
+        assert_eq!(notches("to the sidebar"), None);
+        assert_eq!(notches("the results list"), None);
+    }
+
+    #[test]
+    fn test_percentages_and_zoom_steps() {
+        assert_eq!(parse_percent("150%"), Some(150.0));
+        assert_eq!(parse_percent("80 percent"), Some(80.0));
+        assert_eq!(parse_percent("150"), None);
+        assert_eq!(parse_percent("-5%"), None);
+
+        let steps = [25, 33, 50, 67, 75, 80, 90, 100, 110, 125, 150, 175, 200];
+        assert_eq!(zoom_presses(150.0, &steps), Some(3));
+        assert_eq!(zoom_presses(100.0, &steps), Some(0));
+        assert_eq!(zoom_presses(70.0, &steps), Some(-4));
+        assert_eq!(zoom_presses(1000.0, &steps), Some(5));
+        assert_eq!(zoom_presses(150.0, &[125, 150]), None);
+    }
+}
@@ -0,0 +1,224 @@
+// Unicode-aware text helpers without external segmentation crates
+// OCR output and international labels are full of combining accents, CJK,
+// emoji and flags, so text is cut and measured by grapheme cluster (what a
+// reader sees as one character) rather than by byte or `char`. Clusters
+// follow the parts of UAX #29 that occur in UI text: combining marks,
+// zero-width joiner sequences, variation selectors and emoji modifiers,
+// regional-indicator flag pairs, Hangul jamo and CR LF. Widths follow East
+// Asian Width closely enough to size labels, not to lay out a terminal.
+
+/// Appended to truncated text
+const ELLIPSIS: &str = "...";
+
+const ZWJ: char = '\u{200D}';
+
+/// Grapheme clusters of a string, in order
+pub struct Graphemes<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut chars = self.rest.char_indices();
+        let (_, first) = chars.next()?;
+        let mut previous = first;
+        let mut end = first.len_utf8();
+        // Regional indicators pair up; a third starts the next flag
+        let mut indicators = usize::from(is_regional_indicator(first));
+
+        for (index, c) in chars {
+            // Controls stand alone, except CR LF
+            let joins = if first.is_control() {
+                first == '\r' && c == '\n' && previous == '\r'
+            } else {
+                is_extend(c)
+                    || (previous == ZWJ && !c.is_control())
+                    || (is_regional_indicator(c) && indicators == 1)
+                    || joins_hangul(previous, c)
+            };
+            if !joins {
+                break;
+            }
+            if is_regional_indicator(c) {
+                indicators += 1;
+            }
+            previous = c;
+            end = index + c.len_utf8();
+        }
+
+        let (cluster, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(cluster)
+    }
+}
+
+pub fn graphemes(text: &str) -> Graphemes<'_> {
+    Graphemes { rest: text }
+}
+
+pub fn grapheme_count(text: &str) -> usize {
+    graphemes(text).count()
+}
+
+/// At most `max_graphemes` clusters, the last three replaced by "..." when
+/// anything was cut. Never splits a character or a cluster.
+pub fn truncate(text: &str, max_graphemes: usize) -> String {
+    if graphemes(text).nth(max_graphemes).is_none() {
+        return text.to_string();
+    }
+    let kept: usize = graphemes(text).take(max_graphemes.saturating_sub(ELLIPSIS.len())).map(str::len).sum();
+    format!("{}{}", &text[..kept], ELLIPSIS)
+}
+
+/// Columns `text` takes in a fixed-width font: 2 for wide (CJK, Hangul,
+/// fullwidth forms, emoji), 0 for marks and joiners, 1 otherwise
+pub fn display_width(text: &str) -> usize {
+    graphemes(text).map(grapheme_width).sum()
+}
+
+fn grapheme_width(cluster: &str) -> usize {
+    let mut chars = cluster.chars();
+    let Some(first) = chars.next() else {
+        return 0;
+    };
+    if first.is_control() || is_extend(first) {
+        return 0;
+    }
+    // A flag, or a text symbol given emoji presentation, is drawn as an emoji
+    if is_regional_indicator(first) || is_wide(first) || cluster.contains('\u{FE0F}') {
+        2
+    } else {
+        1
+    }
+}
+
+/// Case-folded text for caseless matching: lowercase, with the full folds
+/// lowercasing misses ("Straße" and "STRASSE", final sigma, the long s,
+/// the dotted capital I) and typographic ligatures OCR tends to return
+pub fn fold_case(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            'ſ' => folded.push('s'),
+            'İ' => folded.push('i'),
+            'ﬀ' => folded.push_str("ff"),
+            'ﬁ' => folded.push_str("fi"),
+            'ﬂ' => folded.push_str("fl"),
+            'ﬃ' => folded.push_str("ffi"),
+            'ﬄ' => folded.push_str("ffl"),
+            'ﬅ' | 'ﬆ' => folded.push_str("st"),
+            _ => folded.extend(c.to_lowercase()),
+        }
+    }
+    folded
+}
+
+/// Whether `haystack` contains `needle`, ignoring case
+pub fn contains_folded(haystack: &str, needle: &str) -> bool {
+    fold_case(haystack).contains(&fold_case(needle))
+}
+
+/// Lowercase copy with the same byte offsets as `text`, for finding keywords
+/// in the copy and slicing the original at them. The few characters whose
+/// lowercase is longer or shorter in UTF-8 ("İ", "ẞ", "Ω" as the ohm sign)
+/// are kept as they are.
+pub fn lowercase_aligned(text: &str) -> String {
+    let mut lower = String::with_capacity(text.len());
+    for c in text.chars() {
+        let mut lowered = c.to_lowercase();
+        match (lowered.next(), lowered.next()) {
+            (Some(l), None) if l.len_utf8() == c.len_utf8() => lower.push(l),
+            _ => lower.push(c),
+        }
+    }
+    lower
+}
+
+/// Combining marks, joiners, variation selectors, emoji modifiers and tags:
+/// characters that attach to the one before
+pub fn is_extend(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}' | '\u{0483}'..='\u{0489}' | '\u{0591}'..='\u{05BD}' | '\u{05BF}'
+        | '\u{05C1}'..='\u{05C2}' | '\u{05C4}'..='\u{05C5}' | '\u{05C7}' | '\u{0610}'..='\u{061A}'
+        | '\u{064B}'..='\u{065F}' | '\u{0670}' | '\u{06D6}'..='\u{06DC}' | '\u{06DF}'..='\u{06E4}'
+        | '\u{06E7}'..='\u{06E8}' | '\u{06EA}'..='\u{06ED}' | '\u{0900}'..='\u{0903}'
+        | '\u{093A}'..='\u{093C}' | '\u{093E}'..='\u{094F}' | '\u{0951}'..='\u{0957}'
+        | '\u{0962}'..='\u{0963}' | '\u{0981}'..='\u{0983}' | '\u{09BC}' | '\u{09BE}'..='\u{09CD}'
+        | '\u{0E31}' | '\u{0E34}'..='\u{0E3A}' | '\u{0E47}'..='\u{0E4E}' | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}' | '\u{200C}' | ZWJ | '\u{20D0}'..='\u{20FF}' | '\u{302A}'..='\u{302F}'
+        | '\u{3099}'..='\u{309A}' | '\u{FE00}'..='\u{FE0F}' | '\u{FE20}'..='\u{FE2F}'
+        | '\u{1F3FB}'..='\u{1F3FF}' | '\u{E0020}'..='\u{E007F}' | '\u{E0100}'..='\u{E01EF}')
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c, '\u{1F1E6}'..='\u{1F1FF}')
+}
+
+/// Conjoining jamo build one syllable: a leading consonant takes vowels,
+/// vowels and syllables take trailing consonants
+fn joins_hangul(previous: char, c: char) -> bool {
+    let leading = |c| matches!(c, '\u{1100}'..='\u{115F}');
+    let vowel = |c| matches!(c, '\u{1160}'..='\u{11A7}');
+    let trailing = |c| matches!(c, '\u{11A8}'..='\u{11FF}');
+    let syllable = |c| matches!(c, '\u{AC00}'..='\u{D7A3}');
+    (leading(previous) && (leading(c) || vowel(c) || syllable(c)))
+        || ((vowel(previous) || syllable(previous)) && (vowel(c) || trailing(c)))
+        || (trailing(previous) && trailing(c))
+}
+
+fn is_wide(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{115F}' | '\u{2E80}'..='\u{303E}' | '\u{3041}'..='\u{33FF}'
+        | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{A000}'..='\u{A4CF}'
+        | '\u{AC00}'..='\u{D7A3}' | '\u{F900}'..='\u{FAFF}' | '\u{FE30}'..='\u{FE4F}'
+        | '\u{FF00}'..='\u{FF60}' | '\u{FFE0}'..='\u{FFE6}' | '\u{1F300}'..='\u{1F64F}'
+        | '\u{1F900}'..='\u{1F9FF}' | '\u{20000}'..='\u{3FFFD}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graphemes() {
+        let clusters = |text| graphemes(text).collect::<Vec<_>>();
+        // Decomposed accent, family emoji, two flags, skin tone, CR LF, jamo
+        assert_eq!(clusters("e\u{301}a"), ["e\u{301}", "a"]);
+        assert_eq!(clusters("👨\u{200D}👩\u{200D}👧!"), ["👨\u{200D}👩\u{200D}👧", "!"]);
+        assert_eq!(clusters("🇩🇪🇫🇷"), ["🇩🇪", "🇫🇷"]);
+        assert_eq!(clusters("👍🏽x"), ["👍🏽", "x"]);
+        assert_eq!(clusters("a\r\nb"), ["a", "\r\n", "b"]);
+        assert_eq!(clusters("\u{1112}\u{1161}\u{11AB}글"), ["\u{1112}\u{1161}\u{11AB}", "글"]);
+        assert_eq!(grapheme_count(""), 0);
+    }
+
+    #[test]
+    fn test_truncate_and_width() {
+        // Byte slicing would panic inside "é" or the emoji
+        assert_eq!(truncate("Café crème brûlée", 8), "Café ...");
+        assert_eq!(truncate("保存して閉じる", 5), "保存...");
+        assert_eq!(truncate("👨\u{200D}👩\u{200D}👧👨\u{200D}👩\u{200D}👧👍🏽👍🏽!", 4), "👨\u{200D}👩\u{200D}👧...");
+        assert_eq!(truncate("short", 5), "short");
+
+        assert_eq!(display_width("Save"), 4);
+        assert_eq!(display_width("保存"), 4);
+        assert_eq!(display_width("e\u{301}🇯🇵"), 3);
+    }
+
+    #[test]
+    fn test_fold_case() {
+        assert_eq!(fold_case("STRASSE"), fold_case("Straße"));
+        assert_eq!(fold_case("ΟΔΟΣ"), fold_case("οδος"));
+        assert_eq!(fold_case("İstanbul"), "istanbul");
+        assert!(contains_folded("Conﬁrm ﬁle deletion", "CONFIRM FILE"));
+
+        let command = "wait for İstanbul Ω to appear";
+        let lower = lowercase_aligned(command);
+        assert_eq!(lower.len(), command.len());
+        assert_eq!(&command[lower.find("to appear").unwrap()..], "to appear");
+    }
+}
@@ -0,0 +1,105 @@
+// The few Win32 calls LUNA makes directly on Windows
+// Declared by hand rather than pulling in a bindings crate for a handful of functions
+
+use std::ffi::c_void;
+
+#[repr(C)]
+#[derive(Default)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct FileTime {
+    low: u32,
+    high: u32,
+}
+
+impl FileTime {
+    /// 100-nanosecond intervals
+    fn ticks(&self) -> u64 {
+        (self.high as u64) << 32 | self.low as u64
+    }
+}
+
+/// PROCESS_MEMORY_COUNTERS
+#[repr(C)]
+#[derive(Default)]
+struct ProcessMemoryCounters {
+    cb: u32,
+    page_fault_count: u32,
+    peak_working_set_size: usize,
+    working_set_size: usize,
+    quota_peak_paged_pool_usage: usize,
+    quota_paged_pool_usage: usize,
+    quota_peak_non_paged_pool_usage: usize,
+    quota_non_paged_pool_usage: usize,
+    pagefile_usage: usize,
+    peak_pagefile_usage: usize,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetCurrentProcess() -> *mut c_void;
+    fn GetProcessTimes(
+        process: *mut c_void,
+        creation: *mut FileTime,
+        exit: *mut FileTime,
+        kernel: *mut FileTime,
+        user: *mut FileTime,
+    ) -> i32;
+}
+
+#[link(name = "psapi")]
+extern "system" {
+    fn GetProcessMemoryInfo(process: *mut c_void, counters: *mut ProcessMemoryCounters, size: u32) -> i32;
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn GetCursorPos(point: *mut Point) -> i32;
+    fn GetForegroundWindow() -> *mut c_void;
+    fn GetWindowTextW(window: *mut c_void, text: *mut u16, max_count: i32) -> i32;
+}
+
+/// Cursor position in screen coordinates
+pub fn cursor_position() -> Option<(i32, i32)> {
+    let mut point = Point::default();
+    // SAFETY: GetCursorPos only writes the POINT it is given
+    let ok = unsafe { GetCursorPos(&mut point) } != 0;
+    ok.then_some((point.x, point.y))
+}
+
+/// Title of the window that has keyboard focus
+pub fn foreground_window_title() -> Option<String> {
+    // SAFETY: no arguments; returns null when no window is in the foreground
+    let window = unsafe { GetForegroundWindow() };
+    if window.is_null() {
+        return None;
+    }
+    let mut text = [0u16; 512];
+    // SAFETY: the buffer is valid for text.len() UTF-16 units, including the terminator
+    let len = unsafe { GetWindowTextW(window, text.as_mut_ptr(), text.len() as i32) };
+    (len > 0).then(|| String::from_utf16_lossy(&text[..len as usize]))
+}
+
+/// Kernel plus user CPU time of this process
+pub fn process_cpu_time_ms() -> Option<u64> {
+    let (mut creation, mut exit, mut kernel, mut user) = Default::default();
+    // SAFETY: the pseudo-handle needs no closing; GetProcessTimes only writes the four FILETIMEs
+    let ok = unsafe { GetProcessTimes(GetCurrentProcess(), &mut creation, &mut exit, &mut kernel, &mut user) } != 0;
+    ok.then(|| (kernel.ticks() + user.ticks()) / 10_000)
+}
+
+/// Working set (resident memory) of this process
+pub fn process_working_set_bytes() -> Option<u64> {
+    let mut counters = ProcessMemoryCounters {
+        cb: std::mem::size_of::<ProcessMemoryCounters>() as u32,
+        ..Default::default()
+    };
+    // SAFETY: `cb` holds the size of the struct GetProcessMemoryInfo writes into
+    let ok = unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, counters.cb) } != 0;
+    ok.then_some(counters.working_set_size as u64)
+}
@@ -0,0 +1,172 @@
+// ZIP archives without a compression dependency
+// Entries are stored, not deflated: what goes into a diagnostic bundle is
+// mostly PNG frames and zstd-compressed data that would not shrink anyway,
+// and any unzip tool opens a stored archive. Names are UTF-8 and sizes are
+// limited to what the classic (non-ZIP64) format holds.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::digest::crc32;
+use super::locale::Date;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Version 2.0, the first to define stored entries in directories
+const VERSION: u16 = 20;
+/// General purpose flag bit 11: the name is UTF-8
+const UTF8_NAMES: u16 = 1 << 11;
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes a ZIP archive entry by entry; `finish` writes the central directory
+pub struct ZipWriter<W: Write> {
+    out: W,
+    written: u64,
+    entries: Vec<CentralEntry>,
+    /// MS-DOS time and date of every entry
+    modified: (u16, u16),
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, written: 0, entries: Vec::new(), modified: dos_time(SystemTime::now()) }
+    }
+
+    /// Add `data` as `name`; directories are given as part of the name ("frames/1.png")
+    pub fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, format!("{} is too large for a ZIP archive", name));
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(self.written).map_err(|_| too_large())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        let crc = crc32(data);
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        for field in [VERSION, UTF8_NAMES, 0, self.modified.0, self.modified.1] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [crc, size, size] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        header.extend_from_slice(&name_len.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.write(&header)?;
+        self.write(data)?;
+        self.entries.push(CentralEntry { name: name.to_string(), crc, size, offset });
+        Ok(())
+    }
+
+    /// Write the central directory and hand back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let start = self.written;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            for field in [VERSION, VERSION, UTF8_NAMES, 0, self.modified.0, self.modified.1] {
+                directory.extend_from_slice(&field.to_le_bytes());
+            }
+            for field in [entry.crc, entry.size, entry.size] {
+                directory.extend_from_slice(&field.to_le_bytes());
+            }
+            // Name length, then no extra field, comment, disk number or attributes
+            for field in [entry.name.len() as u16, 0, 0, 0, 0] {
+                directory.extend_from_slice(&field.to_le_bytes());
+            }
+            directory.extend_from_slice(&0u32.to_le_bytes());
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("too many {} for a ZIP archive", what));
+        let count = u16::try_from(self.entries.len()).map_err(|_| invalid("entries"))?;
+        let offset = u32::try_from(start).map_err(|_| invalid("bytes"))?;
+
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        for field in [0, 0, count, count] {
+            end.extend_from_slice(&field.to_le_bytes());
+        }
+        end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.write(&directory)?;
+        self.write(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// MS-DOS `(time, date)` of `at` in UTC, clamped to the format's 1980 start
+fn dos_time(at: SystemTime) -> (u16, u16) {
+    let seconds = at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let date = Date::from_days((seconds / 86_400) as i64);
+    if date.year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let of_day = seconds % 86_400;
+    let time = ((of_day / 3600) << 11) | ((of_day % 3600 / 60) << 5) | (of_day % 60 / 2);
+    let date = (((date.year - 1980).min(127) as u32) << 9) | (date.month << 5) | date.day;
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_stored_entries_are_found_through_the_central_directory() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add("manifest.json", b"{}").unwrap();
+        zip.add("frames/0.png", &[7u8; 100]).unwrap();
+        let bytes = zip.finish().unwrap();
+
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(&bytes, end), END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(u16_at(&bytes, end + 10), 2);
+        let mut at = u32_at(&bytes, end + 16) as usize;
+        let mut found = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(u32_at(&bytes, at), CENTRAL_HEADER);
+            let (crc, size, name_len) = (u32_at(&bytes, at + 16), u32_at(&bytes, at + 20) as usize, u16_at(&bytes, at + 28) as usize);
+            let name = String::from_utf8(bytes[at + 46..at + 46 + name_len].to_vec()).unwrap();
+            let local = u32_at(&bytes, at + 42) as usize;
+            assert_eq!(u32_at(&bytes, local), LOCAL_HEADER);
+            let data_at = local + 30 + u16_at(&bytes, local + 26) as usize;
+            assert_eq!(crc32(&bytes[data_at..data_at + size]), crc);
+            found.push((name, bytes[data_at..data_at + size].to_vec()));
+            at += 46 + name_len;
+        }
+        assert_eq!(found[0], ("manifest.json".to_string(), b"{}".to_vec()));
+        assert_eq!(found[1], ("frames/0.png".to_string(), vec![7u8; 100]));
+    }
+
+    #[test]
+    fn test_dos_time() {
+        // 2024-02-29 13:45:10 UTC
+        let at = UNIX_EPOCH + Duration::from_secs(1_709_214_310);
+        assert_eq!(dos_time(at), ((13 << 11) | (45 << 5) | 5, (44 << 9) | (2 << 5) | 29));
+        assert_eq!(dos_time(UNIX_EPOCH), (0, 33));
+    }
+}
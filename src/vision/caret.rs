@@ -0,0 +1,208 @@
+// Text cursor (caret) location
+// Typed and pasted text lands at the caret, so knowing where it is lets a
+// command type "at the cursor" and skip clicking into a field the caret is
+// already in (a click would move it). The platform says where it is when
+// asked: on Windows, the focused thread's system caret (GetGUIThreadInfo)
+// or, for apps that draw their own, the selection of the focused UI
+// Automation text element. Otherwise the caret is found by its blink: two
+// frames half a blink period apart differ in one thin vertical bar and
+// (almost) nowhere else.
+
+use crate::core::ElementBounds;
+use crate::utils::image_processing::{find_connected_components, Image};
+use serde::{Deserialize, Serialize};
+
+/// Luminance change that counts as a pixel blinking
+const BLINK_CONTRAST: i32 = 40;
+/// Widest bar taken for a caret, in pixels
+const MAX_CARET_WIDTH: f64 = 4.0;
+/// Shortest and tallest bar taken for a caret, in pixels
+const CARET_HEIGHT: (f64, f64) = (6.0, 80.0);
+/// Share of its bounding box a blinking bar must fill
+const MIN_FILL: f64 = 0.6;
+/// More of the screen changing than this is not a blink
+const MAX_CHANGED_SHARE: f64 = 0.01;
+
+/// How the caret was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaretSource {
+    /// The system caret of the focused window
+    System,
+    /// The focused element's text selection, through UI Automation
+    Automation,
+    /// A bar blinking between frames
+    Blink,
+}
+
+/// Where typed text will go: the top of the caret bar and its height
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Caret {
+    pub x: i32,
+    pub y: i32,
+    pub height: i32,
+    pub source: CaretSource,
+}
+
+impl Caret {
+    /// Whether the middle of the bar is inside `bounds`
+    pub fn is_inside(&self, bounds: &ElementBounds) -> bool {
+        bounds.contains_point(self.x, self.y + self.height / 2)
+    }
+}
+
+/// The caret as the platform reports it; `None` where it can't be asked
+/// (anything but Windows) or when no focused control has one
+pub fn platform_caret() -> Option<Caret> {
+    if !cfg!(target_os = "windows") {
+        return None;
+    }
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-Command", WINDOWS_CARET_SCRIPT])
+        .output()
+        .ok()?;
+    parse_caret_report(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Prints "system X Y HEIGHT" or "automation X Y HEIGHT", or nothing
+const WINDOWS_CARET_SCRIPT: &str = r#"
+Add-Type @'
+using System;
+using System.Runtime.InteropServices;
+public static class LunaCaret {
+    [StructLayout(LayoutKind.Sequential)] public struct RECT { public int Left, Top, Right, Bottom; }
+    [StructLayout(LayoutKind.Sequential)] public struct POINT { public int X, Y; }
+    [StructLayout(LayoutKind.Sequential)] public struct GUITHREADINFO {
+        public int cbSize, flags;
+        public IntPtr hwndActive, hwndFocus, hwndCapture, hwndMenuOwner, hwndMoveSize, hwndCaret;
+        public RECT rcCaret;
+    }
+    [DllImport("user32.dll")] static extern bool GetGUIThreadInfo(uint thread, ref GUITHREADINFO info);
+    [DllImport("user32.dll")] static extern bool ClientToScreen(IntPtr hwnd, ref POINT point);
+    public static string Caret() {
+        var info = new GUITHREADINFO();
+        info.cbSize = Marshal.SizeOf(info);
+        if (!GetGUIThreadInfo(0, ref info) || info.hwndCaret == IntPtr.Zero) return "";
+        var top = new POINT { X = info.rcCaret.Left, Y = info.rcCaret.Top };
+        ClientToScreen(info.hwndCaret, ref top);
+        return "system " + top.X + " " + top.Y + " " + (info.rcCaret.Bottom - info.rcCaret.Top);
+    }
+}
+'@
+$caret = [LunaCaret]::Caret()
+if (-not $caret) {
+    Add-Type -AssemblyName UIAutomationClient,UIAutomationTypes
+    $focused = [System.Windows.Automation.AutomationElement]::FocusedElement
+    $pattern = $null
+    if ($focused -and $focused.TryGetCurrentPattern([System.Windows.Automation.TextPattern]::Pattern, [ref]$pattern)) {
+        $rect = $pattern.GetSelection() | Select-Object -First 1 | ForEach-Object { $_.GetBoundingRectangles() } | Select-Object -First 1
+        if ($rect) { $caret = "automation {0} {1} {2}" -f [int]$rect.X, [int]$rect.Y, [int]$rect.Height }
+    }
+}
+$caret
+"#;
+
+/// Parse the caret script's "SOURCE X Y HEIGHT" line
+fn parse_caret_report(output: &str) -> Option<Caret> {
+    let mut fields = output.split_whitespace();
+    let source = match fields.next()? {
+        "system" => CaretSource::System,
+        "automation" => CaretSource::Automation,
+        _ => return None,
+    };
+    let mut number = || fields.next()?.parse::<i32>().ok();
+    let (x, y, height) = (number()?, number()?, number()?);
+    (height > 0).then_some(Caret { x, y, height, source })
+}
+
+/// The caret, from the one thin vertical bar that differs between `before`
+/// and `after`. `None` when nothing blinked (both frames caught the same
+/// phase), when other things changed too much to tell, or when more than
+/// one bar blinked.
+pub fn find_blinking_caret(before: &Image, after: &Image) -> Option<Caret> {
+    if (before.width, before.height) != (after.width, after.height) || before.width == 0 || before.height == 0 {
+        return None;
+    }
+    let (before, after) = (before.to_grayscale(), after.to_grayscale());
+    let mut changed = Image::new(before.width, before.height, 1);
+    let mut count = 0usize;
+    for (index, (a, b)) in before.data.iter().zip(&after.data).enumerate() {
+        if (*a as i32 - *b as i32).abs() >= BLINK_CONTRAST {
+            changed.data[index] = 255;
+            count += 1;
+        }
+    }
+    if count == 0 || count as f64 > MAX_CHANGED_SHARE * (before.width * before.height) as f64 {
+        return None;
+    }
+
+    let mut bars = find_connected_components(&changed).into_iter().filter_map(|pixels| {
+        let (min_x, max_x) = pixels.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| (lo.min(p.x), hi.max(p.x)));
+        let (min_y, max_y) = pixels.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| (lo.min(p.y), hi.max(p.y)));
+        let (width, height) = (max_x - min_x + 1.0, max_y - min_y + 1.0);
+        let is_bar = width <= MAX_CARET_WIDTH
+            && (CARET_HEIGHT.0..=CARET_HEIGHT.1).contains(&height)
+            && pixels.len() as f64 >= MIN_FILL * width * height;
+        is_bar.then_some(Caret { x: min_x as i32, y: min_y as i32, height: height as i32, source: CaretSource::Blink })
+    });
+    let caret = bars.next()?;
+    bars.next().is_none().then_some(caret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A light text field with some dark text, and the caret bar when `shown`
+    fn field(shown: bool) -> Image {
+        let mut image = Image::from_rgb_data(200, 60, vec![235; 200 * 60 * 3]);
+        for y in 20..36 {
+            for x in (30..80).step_by(3) {
+                image.set_pixel(x, y, &[20, 20, 20]);
+            }
+            if shown {
+                for x in 90..92 {
+                    image.set_pixel(x, y, &[0, 0, 0]);
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_blinking_bar_is_the_caret() {
+        let caret = find_blinking_caret(&field(true), &field(false)).unwrap();
+        assert_eq!((caret.x, caret.y, caret.height, caret.source), (90, 20, 16, CaretSource::Blink));
+        assert!(caret.is_inside(&ElementBounds::new(20, 10, 160, 40)));
+        assert!(!caret.is_inside(&ElementBounds::new(100, 10, 60, 40)));
+
+        // Same phase twice: nothing blinked
+        assert_eq!(find_blinking_caret(&field(true), &field(true)), None);
+
+        // A whole block changing is not a caret
+        let mut repainted = field(false);
+        for y in 5..45 {
+            for x in 120..190 {
+                repainted.set_pixel(x, y, &[40, 90, 200]);
+            }
+        }
+        assert_eq!(find_blinking_caret(&field(true), &repainted), None);
+
+        // Two bars blinking at once leave it ambiguous
+        let mut two = field(true);
+        for y in 20..36 {
+            two.set_pixel(150, y, &[0, 0, 0]);
+        }
+        assert_eq!(find_blinking_caret(&two, &field(false)), None);
+    }
+
+    #[test]
+    fn test_parses_platform_reports() {
+        assert_eq!(parse_caret_report("system 412 230 17\r\n"),
+            Some(Caret { x: 412, y: 230, height: 17, source: CaretSource::System }));
+        assert_eq!(parse_caret_report("automation 10 -4 20").map(|c| c.source), Some(CaretSource::Automation));
+        assert_eq!(parse_caret_report(""), None);
+        assert_eq!(parse_caret_report("system 1 2 0"), None);
+        assert_eq!(parse_caret_report("system 1 two 3"), None);
+    }
+}
@@ -0,0 +1,273 @@
+// Pixel colors and color-based search
+// Some targets are easiest to name by color ("the red circle", "the green
+// dot") and some checks only need one pixel ("is the status light green?").
+// Colors are named by hue, saturation and value so "red" covers every shade a
+// theme might use; exact matching takes an RGB value and a per-channel
+// tolerance. Mask passes are branch-free loops over the raw pixel bytes, which
+// the compiler vectorizes, and regions are found with one flood fill each.
+
+use serde::Serialize;
+
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// Names a pixel can be given, chromatic ones first
+pub const COLOR_NAMES: &[&str] = &[
+    "red", "orange", "yellow", "green", "cyan", "blue", "purple", "pink", "white", "gray", "black",
+];
+/// Names that describe a hue; regions are only found for these
+const CHROMATIC: usize = 8;
+/// Regions smaller than this are noise (anti-aliasing, text strokes)
+pub const MIN_REGION_PIXELS: usize = 24;
+/// Pixels sampled per side when naming an element's color
+const SAMPLES_PER_SIDE: usize = 8;
+
+/// An opaque RGB color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Hue in degrees (0-360), saturation and value (0-1)
+    pub fn hsv(&self) -> (f64, f64, f64) {
+        let (r, g, b) = (self.r as f64 / 255.0, self.g as f64 / 255.0, self.b as f64 / 255.0);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+        (hue, saturation, max)
+    }
+
+    /// Everyday name of the color, one of `COLOR_NAMES`
+    pub fn name(&self) -> &'static str {
+        COLOR_NAMES[self.name_index()]
+    }
+
+    fn name_index(&self) -> usize {
+        let (hue, saturation, value) = self.hsv();
+        if value < 0.2 {
+            return 10;
+        }
+        if saturation < 0.25 {
+            return if value > 0.85 { 8 } else { 9 };
+        }
+        match hue {
+            h if !(15.0..345.0).contains(&h) => 0,
+            h if h < 40.0 => 1,
+            h if h < 70.0 => 2,
+            h if h < 165.0 => 3,
+            h if h < 195.0 => 4,
+            h if h < 255.0 => 5,
+            h if h < 290.0 => 6,
+            _ => 7,
+        }
+    }
+
+    /// Whether every channel is within `tolerance` of `other`'s
+    pub fn is_near(&self, other: &Rgb, tolerance: u8) -> bool {
+        self.r.abs_diff(other.r) <= tolerance && self.g.abs_diff(other.g) <= tolerance && self.b.abs_diff(other.b) <= tolerance
+    }
+}
+
+/// A connected patch of one color
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorRegion {
+    pub bounds: Rectangle,
+    /// Pixels in the patch
+    pub pixels: usize,
+    /// One of `COLOR_NAMES`
+    pub color: &'static str,
+}
+
+impl ColorRegion {
+    /// Fraction of the bounding box the patch fills
+    pub fn fill(&self) -> f64 {
+        self.pixels as f64 / self.bounds.area().max(1.0)
+    }
+
+    /// "circle", "square" or "rectangle" when the patch looks like one
+    pub fn shape(&self) -> Option<&'static str> {
+        let aspect = self.bounds.width / self.bounds.height.max(1.0);
+        let fill = self.fill();
+        let squarish = (0.8..=1.25).contains(&aspect);
+        if squarish && (0.65..0.88).contains(&fill) {
+            Some("circle")
+        } else if squarish && fill >= 0.88 {
+            Some("square")
+        } else if fill >= 0.88 {
+            Some("rectangle")
+        } else {
+            None
+        }
+    }
+}
+
+/// Color of the pixel at (`x`, `y`), `None` outside the image
+pub fn pixel_at(image: &Image, x: usize, y: usize) -> Option<Rgb> {
+    let pixel = image.get_pixel(x, y)?;
+    Some(match pixel.len() {
+        1 | 2 => Rgb::new(pixel[0], pixel[0], pixel[0]),
+        _ => Rgb::new(pixel[0], pixel[1], pixel[2]),
+    })
+}
+
+/// Bounds of the patches within `tolerance` (per channel) of `color`
+pub fn find_color_regions(image: &Image, color: Rgb, tolerance: u8) -> Vec<Rectangle> {
+    let mask: Vec<u8> = pixels(image).map(|p| p.is_near(&color, tolerance) as u8).collect();
+    regions(image.width, image.height, &mask, |label| label == 1)
+        .into_iter()
+        .map(|(_, bounds, _)| bounds)
+        .collect()
+}
+
+/// Patches of every chromatic color (red, green, ... but not white, gray or black)
+pub fn named_color_regions(image: &Image) -> Vec<ColorRegion> {
+    let names: Vec<u8> = pixels(image).map(|p| p.name_index() as u8).collect();
+    regions(image.width, image.height, &names, |label| (label as usize) < CHROMATIC)
+        .into_iter()
+        .map(|(label, bounds, pixels)| ColorRegion { bounds, pixels, color: COLOR_NAMES[label as usize] })
+        .collect()
+}
+
+/// Whether a color name describes a hue rather than white, gray or black
+pub fn is_chromatic(name: &str) -> bool {
+    COLOR_NAMES[..CHROMATIC].contains(&name)
+}
+
+/// Most common color name on a sample grid over `rect`
+pub fn dominant_color(image: &Image, rect: &Rectangle) -> Option<&'static str> {
+    let mut counts = [0usize; 11];
+    let (step_x, step_y) = (rect.width / SAMPLES_PER_SIDE as f64, rect.height / SAMPLES_PER_SIDE as f64);
+    for row in 0..SAMPLES_PER_SIDE {
+        for column in 0..SAMPLES_PER_SIDE {
+            let x = rect.x + (column as f64 + 0.5) * step_x;
+            let y = rect.y + (row as f64 + 0.5) * step_y;
+            if x < 0.0 || y < 0.0 {
+                continue;
+            }
+            if let Some(pixel) = pixel_at(image, x as usize, y as usize) {
+                counts[pixel.name_index()] += 1;
+            }
+        }
+    }
+    let (index, &count) = counts.iter().enumerate().max_by_key(|(index, count)| (**count, std::cmp::Reverse(*index)))?;
+    (count > 0).then_some(COLOR_NAMES[index])
+}
+
+fn pixels(image: &Image) -> impl Iterator<Item = Rgb> + '_ {
+    let channels = image.channels.max(1);
+    image.data.chunks_exact(channels).map(move |p| match channels {
+        1 | 2 => Rgb::new(p[0], p[0], p[0]),
+        _ => Rgb::new(p[0], p[1], p[2]),
+    })
+}
+
+/// Connected patches of equal `labels` for which `keep` holds, as
+/// `(label, bounds, pixel count)`, dropping those under `MIN_REGION_PIXELS`
+fn regions(width: usize, height: usize, labels: &[u8], keep: impl Fn(u8) -> bool) -> Vec<(u8, Rectangle, usize)> {
+    let mut visited = vec![false; labels.len()];
+    let mut found = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..labels.len().min(width * height) {
+        let label = labels[start];
+        if visited[start] || !keep(label) {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (mut x0, mut y0, mut x1, mut y1, mut count) = (width, height, 0, 0, 0);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x), y1.max(y));
+            count += 1;
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+            for next in neighbours.into_iter().flatten() {
+                if !visited[next] && labels[next] == label {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        if count >= MIN_REGION_PIXELS {
+            let bounds = Rectangle::new(x0 as f64, y0 as f64, (x1 - x0 + 1) as f64, (y1 - y0 + 1) as f64);
+            found.push((label, bounds, count));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// White screen with a red disc, a green square and a slightly different red bar
+    fn screen() -> Image {
+        let mut image = Image::from_rgb_data(200, 100, vec![250; 200 * 100 * 3]);
+        for y in 0..100 {
+            for x in 0..200 {
+                let (dx, dy) = (x as f64 - 40.0, y as f64 - 50.0);
+                if dx * dx + dy * dy <= 15.0 * 15.0 {
+                    image.set_pixel(x, y, &[220, 30, 40]);
+                } else if (100..130).contains(&x) && (35..65).contains(&y) {
+                    image.set_pixel(x, y, &[40, 180, 60]);
+                } else if (150..190).contains(&x) && (10..20).contains(&y) {
+                    image.set_pixel(x, y, &[180, 20, 20]);
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_names_cover_shades() {
+        assert_eq!(Rgb::new(220, 30, 40).name(), "red");
+        assert_eq!(Rgb::new(150, 10, 10).name(), "red");
+        assert_eq!(Rgb::new(40, 180, 60).name(), "green");
+        assert_eq!(Rgb::new(30, 100, 230).name(), "blue");
+        assert_eq!(Rgb::new(250, 250, 250).name(), "white");
+        assert_eq!(Rgb::new(128, 128, 128).name(), "gray");
+        assert_eq!(Rgb::new(10, 10, 10).name(), "black");
+    }
+
+    #[test]
+    fn test_exact_color_search_respects_tolerance() {
+        let image = screen();
+        assert_eq!(pixel_at(&image, 40, 50), Some(Rgb::new(220, 30, 40)));
+        assert_eq!(pixel_at(&image, 200, 0), None);
+
+        let disc = find_color_regions(&image, Rgb::new(215, 35, 35), 10);
+        assert_eq!(disc, vec![Rectangle::new(25.0, 35.0, 31.0, 31.0)]);
+        assert_eq!(find_color_regions(&image, Rgb::new(215, 35, 35), 50).len(), 2, "the bar is within 50");
+    }
+
+    #[test]
+    fn test_named_regions_and_shapes() {
+        let image = screen();
+        let regions = named_color_regions(&image);
+        let shapes: Vec<(&str, Option<&str>)> = regions.iter().map(|r| (r.color, r.shape())).collect();
+        assert_eq!(shapes, vec![("red", Some("rectangle")), ("red", Some("circle")), ("green", Some("square"))]);
+
+        assert_eq!(dominant_color(&image, &Rectangle::new(100.0, 35.0, 30.0, 30.0)), Some("green"));
+        assert_eq!(dominant_color(&image, &Rectangle::new(0.0, 0.0, 200.0, 100.0)), Some("white"));
+    }
+}
@@ -0,0 +1,339 @@
+// Captured frames for other processes, without serialization
+// A visualization or recording process that wants every frame LUNA sees
+// shouldn't have to decode PNGs off a socket. `FramePublisher` keeps the last
+// few captured frames as raw pixels in a ring of slots in one file, and
+// `FrameReader` is the client side. Both sides use positioned reads and
+// writes, not a memory map; the file lives in the user's runtime directory
+// (usually a tmpfs) so no frame touches the disk, and it is created readable
+// by its owner only, since it holds whatever was on screen. A reader can also
+// mmap the file itself; the layout below is versioned and only ever grows.
+//
+// Layout (all integers little-endian):
+//   header, 64 bytes:
+//     0  magic "LUNAFRM\0"     8  version u32         12 slot count u32
+//     16 slot capacity u64    24 latest frame u64    32 writer open u32
+//   then `slot count` slots of 32 + capacity bytes each:
+//     0  sequence u64         8  frame number u64    16 width u32
+//     20 height u32           24 channels u32        28 length u32
+//     32 pixels, row-major, `channels` bytes each
+//
+// Each slot is a seqlock: the writer makes its sequence odd, writes, then
+// makes it even again. A reader that sees an odd sequence, or a different one
+// after reading, raced the writer and tries again. Frame numbers start at 1;
+// frame n goes to slot n % slot count.
+//
+// The publisher belongs to the capture subsystem: every frame `ScreenCapture`
+// takes is published, and the file is marked closed and removed when the
+// last capture holding it is dropped.
+
+use anyhow::{bail, Result};
+use log::debug;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::utils::image_processing::Image;
+
+pub const MAGIC: &[u8; 8] = b"LUNAFRM\0";
+/// Layout version written by this build
+pub const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 64;
+const SLOT_HEADER_LEN: u64 = 32;
+const LATEST_OFFSET: u64 = 24;
+const OPEN_OFFSET: u64 = 32;
+/// Attempts to read a consistent frame before giving up on a busy slot
+const READ_RETRIES: usize = 16;
+
+/// Default channel file for this process: `{name}-{pid}.frames` in the
+/// user's runtime directory, else the temp directory
+pub fn default_path(name: &str) -> PathBuf {
+    let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    dir.join(format!("{}-{}.frames", name, std::process::id()))
+}
+
+/// Writing end of a frame channel; clones share the same ring
+#[derive(Clone)]
+pub struct FramePublisher {
+    inner: Arc<Mutex<Ring>>,
+}
+
+struct Ring {
+    file: File,
+    path: PathBuf,
+    slots: u32,
+    capacity: u64,
+    next_frame: u64,
+}
+
+impl FramePublisher {
+    /// Create the channel file at `path` with `slots` slots of up to
+    /// `capacity` pixel bytes each. A file already there is only replaced
+    /// when it is a regular file of this user's, left by a run that crashed.
+    pub fn create(path: impl Into<PathBuf>, slots: u32, capacity: u64) -> Result<Self> {
+        let path = path.into();
+        if slots == 0 || capacity == 0 {
+            bail!("frame channel needs at least one slot of nonzero capacity");
+        }
+        let mut file = match create_private(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let stale = std::fs::symlink_metadata(&path)?;
+                if !stale.file_type().is_file() || !owned_by_current_user(&stale) {
+                    bail!("{} already exists and is not a frame channel of this user's", path.display());
+                }
+                debug!("Replacing stale frame channel {}", path.display());
+                std::fs::remove_file(&path)?;
+                create_private(&path)?
+            }
+            result => result?,
+        };
+        file.set_len(HEADER_LEN + slots as u64 * (SLOT_HEADER_LEN + capacity))?;
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&slots.to_le_bytes());
+        header.extend_from_slice(&capacity.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        file.write_all(&header)?;
+        debug!("Frame channel at {} ({} slots of {} bytes)", path.display(), slots, capacity);
+        Ok(Self { inner: Arc::new(Mutex::new(Ring { file, path, slots, capacity, next_frame: 1 })) })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.ring().path.clone()
+    }
+
+    /// Write `image` to the next slot; returns its frame number
+    pub fn publish(&self, image: &Image) -> Result<u64> {
+        let mut ring = self.ring();
+        let len = image.data.len() as u64;
+        if len > ring.capacity {
+            bail!("{}x{} frame ({} bytes) exceeds the slot capacity of {} bytes", image.width, image.height, len, ring.capacity);
+        }
+        let frame = ring.next_frame;
+        let slot = HEADER_LEN + (frame % ring.slots as u64) * (SLOT_HEADER_LEN + ring.capacity);
+        let sequence = read_u64(&mut ring.file, slot)?;
+        let sequence = sequence + 1 + sequence % 2;
+
+        write_at(&mut ring.file, slot, &sequence.to_le_bytes())?;
+        let mut meta = Vec::with_capacity(SLOT_HEADER_LEN as usize - 8);
+        meta.extend_from_slice(&frame.to_le_bytes());
+        for value in [image.width, image.height, image.channels, image.data.len()] {
+            meta.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        write_at(&mut ring.file, slot + 8, &meta)?;
+        write_at(&mut ring.file, slot + SLOT_HEADER_LEN, &image.data)?;
+        write_at(&mut ring.file, slot, &(sequence + 1).to_le_bytes())?;
+        write_at(&mut ring.file, LATEST_OFFSET, &frame.to_le_bytes())?;
+        ring.next_frame += 1;
+        Ok(frame)
+    }
+
+    fn ring(&self) -> std::sync::MutexGuard<'_, Ring> {
+        self.inner.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // Readers that still have the file open see it closed; new ones find nothing
+        let _ = write_at(&mut self.file, OPEN_OFFSET, &0u32.to_le_bytes());
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// One frame read from a channel
+#[derive(Debug, Clone)]
+pub struct SharedFrame {
+    pub number: u64,
+    pub image: Image,
+}
+
+/// Reading end of a frame channel, for the consuming process
+pub struct FrameReader {
+    file: File,
+    slots: u32,
+    capacity: u64,
+    last_frame: u64,
+}
+
+impl FrameReader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = File::open(path.as_ref())?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            bail!("{} is not a LUNA frame channel", path.as_ref().display());
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into()?);
+        if version != VERSION {
+            bail!("frame channel layout version {} is not supported (expected {})", version, VERSION);
+        }
+        Ok(Self {
+            file,
+            slots: u32::from_le_bytes(header[12..16].try_into()?),
+            capacity: u64::from_le_bytes(header[16..24].try_into()?),
+            last_frame: 0,
+        })
+    }
+
+    /// Whether the publisher still has the channel open
+    pub fn is_open(&mut self) -> Result<bool> {
+        let mut open = [0u8; 4];
+        read_at(&mut self.file, OPEN_OFFSET, &mut open)?;
+        Ok(u32::from_le_bytes(open) == 1)
+    }
+
+    /// Most recent complete frame, `None` before the first
+    pub fn latest(&mut self) -> Result<Option<SharedFrame>> {
+        for _ in 0..READ_RETRIES {
+            let frame = read_u64(&mut self.file, LATEST_OFFSET)?;
+            if frame == 0 {
+                return Ok(None);
+            }
+            if let Some(image) = self.read_slot(frame)? {
+                self.last_frame = frame;
+                return Ok(Some(SharedFrame { number: frame, image }));
+            }
+            std::thread::yield_now();
+        }
+        bail!("frame channel kept changing while being read")
+    }
+
+    /// Wait for a frame newer than the last one returned, polling every
+    /// `poll`; `None` on timeout or once the publisher has closed
+    pub fn next(&mut self, timeout: Duration, poll: Duration) -> Result<Option<SharedFrame>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if read_u64(&mut self.file, LATEST_OFFSET)? > self.last_frame {
+                return self.latest();
+            }
+            if !self.is_open()? || Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(poll);
+        }
+    }
+
+    /// Frame `frame` if its slot holds it consistently
+    fn read_slot(&mut self, frame: u64) -> Result<Option<Image>> {
+        let slot = HEADER_LEN + (frame % self.slots as u64) * (SLOT_HEADER_LEN + self.capacity);
+        let before = read_u64(&mut self.file, slot)?;
+        if before % 2 == 1 {
+            return Ok(None);
+        }
+        let mut meta = [0u8; SLOT_HEADER_LEN as usize - 8];
+        read_at(&mut self.file, slot + 8, &mut meta)?;
+        let field = |at: usize| u32::from_le_bytes(meta[at..at + 4].try_into().expect("4 bytes")) as usize;
+        let (number, len) = (u64::from_le_bytes(meta[..8].try_into()?), field(20));
+        if number != frame || len as u64 > self.capacity {
+            return Ok(None);
+        }
+        let mut data = vec![0u8; len];
+        read_at(&mut self.file, slot + SLOT_HEADER_LEN, &mut data)?;
+        if read_u64(&mut self.file, slot)? != before {
+            return Ok(None);
+        }
+        Ok(Some(Image { width: field(8), height: field(12), channels: field(16), data }))
+    }
+}
+
+/// Create `path` new, readable and writable by its owner only
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(unix)]
+fn owned_by_current_user(metadata: &std::fs::Metadata) -> bool {
+    extern "C" {
+        fn geteuid() -> u32;
+    }
+    // SAFETY: geteuid takes nothing and cannot fail
+    std::os::unix::fs::MetadataExt::uid(metadata) == unsafe { geteuid() }
+}
+
+/// The temp directory is per user on Windows
+#[cfg(not(unix))]
+fn owned_by_current_user(_metadata: &std::fs::Metadata) -> bool {
+    true
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)?;
+    Ok(())
+}
+
+fn write_at(file: &mut File, offset: u64, buf: &[u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)?;
+    Ok(())
+}
+
+fn read_u64(file: &mut File, offset: u64) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    read_at(file, offset, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(shade: u8) -> Image {
+        Image::from_rgb_data(4, 2, vec![shade; 4 * 2 * 3])
+    }
+
+    #[test]
+    fn test_frames_cross_the_channel_and_close_with_the_publisher() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.frames");
+        let publisher = FramePublisher::create(&path, 2, 64).unwrap();
+        let mut reader = FrameReader::open(&path).unwrap();
+        assert!(reader.latest().unwrap().is_none());
+
+        for shade in [10, 20, 30] {
+            publisher.publish(&frame(shade)).unwrap();
+        }
+        let latest = reader.latest().unwrap().unwrap();
+        assert_eq!(latest.number, 3);
+        assert_eq!((latest.image.width, latest.image.height, latest.image.channels), (4, 2, 3));
+        assert_eq!(latest.image.data, frame(30).data);
+        assert!(reader.next(Duration::ZERO, Duration::ZERO).unwrap().is_none(), "nothing newer yet");
+
+        assert!(publisher.publish(&Image::from_rgb_data(10, 10, vec![0; 300])).is_err(), "over capacity");
+        let clone = publisher.clone();
+        drop(publisher);
+        assert!(reader.is_open().unwrap(), "a clone still holds it");
+        drop(clone);
+        assert!(!reader.is_open().unwrap());
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_channel_is_private_and_replaces_only_its_own_leftovers() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.frames");
+        // Left by a run that crashed
+        std::fs::write(&path, b"stale").unwrap();
+        let publisher = FramePublisher::create(&path, 1, 16).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(publisher);
+
+        // Someone else's link is never followed or removed
+        let target = dir.path().join("elsewhere");
+        std::fs::write(&target, b"keep").unwrap();
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+        assert!(FramePublisher::create(&path, 1, 16).is_err());
+        assert_eq!(std::fs::read(&target).unwrap(), b"keep");
+    }
+}
@@ -0,0 +1,208 @@
+// Containment hierarchy for detected elements (windows -> panels -> controls)
+// Parents are found by bounds containment; large containers are additionally
+// split into panels along visual separators (thin lines spanning the container).
+
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// Fraction of a child's area that must lie inside its parent
+const MIN_CONTAINED_FRACTION: f64 = 0.95;
+/// Luminance difference between a separator line and the pixels beside it
+const SEPARATOR_CONTRAST: i32 = 30;
+/// Fraction of the container's width (or height) a separator must span
+const SEPARATOR_COVERAGE: f64 = 0.9;
+/// Separators this close to the container edge are its border, not a split
+const SEPARATOR_EDGE_MARGIN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
+
+/// A line that visually divides a container
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Separator {
+    pub orientation: Orientation,
+    /// Absolute y (horizontal) or x (vertical) of the line
+    pub position: f64,
+}
+
+/// Index of each element's parent: the smallest larger element that contains it
+pub fn containment_parents(bounds: &[Rectangle]) -> Vec<Option<usize>> {
+    bounds
+        .iter()
+        .enumerate()
+        .map(|(i, child)| {
+            bounds
+                .iter()
+                .enumerate()
+                .filter(|&(j, parent)| j != i && contains(parent, child))
+                .min_by(|a, b| a.1.area().total_cmp(&b.1.area()))
+                .map(|(j, _)| j)
+        })
+        .collect()
+}
+
+fn contains(parent: &Rectangle, child: &Rectangle) -> bool {
+    // Equal boxes would be each other's parent; the strict size check breaks the cycle
+    if parent.area() <= child.area() {
+        return false;
+    }
+    let inside = parent.intersection(child).map_or(0.0, |r| r.area());
+    inside >= child.area() * MIN_CONTAINED_FRACTION
+}
+
+/// Indices of `bounds` in reading order: rows top to bottom, left to right
+/// within a row. Boxes whose vertical centers are closer than half the
+/// smaller height share a row, so icon grids and lists both come out in the
+/// order a user would count them.
+pub fn reading_order(bounds: &[Rectangle]) -> Vec<usize> {
+    let center = |i: usize| bounds[i].y + bounds[i].height / 2.0;
+    let mut by_center: Vec<usize> = (0..bounds.len()).collect();
+    by_center.sort_by(|&a, &b| center(a).total_cmp(&center(b)));
+
+    let mut rows: Vec<usize> = vec![0; bounds.len()];
+    let mut row = 0;
+    let mut row_start: Option<usize> = None;
+    for &i in &by_center {
+        if let Some(start) = row_start {
+            if center(i) - center(start) >= bounds[i].height.min(bounds[start].height) / 2.0 {
+                row += 1;
+                row_start = Some(i);
+            }
+        } else {
+            row_start = Some(i);
+        }
+        rows[i] = row;
+    }
+    by_center.sort_by(|&a, &b| rows[a].cmp(&rows[b]).then(bounds[a].x.total_cmp(&bounds[b].x)));
+    by_center
+}
+
+/// Horizontal and vertical lines spanning `region`
+pub fn find_separators(image: &Image, region: &Rectangle) -> Vec<Separator> {
+    let x0 = region.x.max(0.0) as usize;
+    let y0 = region.y.max(0.0) as usize;
+    let x1 = ((region.x + region.width) as usize).min(image.width);
+    let y1 = ((region.y + region.height) as usize).min(image.height);
+    if x1 <= x0 + 2 * SEPARATOR_EDGE_MARGIN || y1 <= y0 + 2 * SEPARATOR_EDGE_MARGIN {
+        return Vec::new();
+    }
+
+    let luma = |x: usize, y: usize| -> i32 {
+        image.get_pixel(x, y).map_or(0, |p| match p.len() {
+            1 | 2 => p[0] as i32,
+            _ => (p[0] as i32 * 299 + p[1] as i32 * 587 + p[2] as i32 * 114) / 1000,
+        })
+    };
+    let stands_out = |here: i32, before: i32, after: i32| {
+        (here - before).abs() >= SEPARATOR_CONTRAST && (here - after).abs() >= SEPARATOR_CONTRAST
+    };
+
+    let rows = (y0 + SEPARATOR_EDGE_MARGIN..y1 - SEPARATOR_EDGE_MARGIN).filter(|&y| {
+        let hits = (x0..x1).filter(|&x| stands_out(luma(x, y), luma(x, y - 2), luma(x, y + 2))).count();
+        hits as f64 >= (x1 - x0) as f64 * SEPARATOR_COVERAGE
+    });
+    let columns = (x0 + SEPARATOR_EDGE_MARGIN..x1 - SEPARATOR_EDGE_MARGIN).filter(|&x| {
+        let hits = (y0..y1).filter(|&y| stands_out(luma(x, y), luma(x - 2, y), luma(x + 2, y))).count();
+        hits as f64 >= (y1 - y0) as f64 * SEPARATOR_COVERAGE
+    });
+
+    let mut separators = merge_runs(rows, Orientation::Horizontal);
+    separators.extend(merge_runs(columns, Orientation::Vertical));
+    separators
+}
+
+/// A thick line shows up as consecutive rows; report each run once, at its middle
+fn merge_runs(lines: impl Iterator<Item = usize>, orientation: Orientation) -> Vec<Separator> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for line in lines {
+        match runs.last_mut() {
+            Some((_, end)) if line <= *end + 3 => *end = line,
+            _ => runs.push((line, line)),
+        }
+    }
+    runs.into_iter()
+        .map(|(start, end)| Separator { orientation, position: (start + end) as f64 / 2.0 })
+        .collect()
+}
+
+/// Cells of `region` after cutting it at every separator
+pub fn split_panels(region: &Rectangle, separators: &[Separator]) -> Vec<Rectangle> {
+    let cuts = |orientation, start: f64, length: f64| {
+        let mut edges = vec![start];
+        edges.extend(separators.iter().filter(|s| s.orientation == orientation).map(|s| s.position));
+        edges.push(start + length);
+        edges.sort_by(f64::total_cmp);
+        edges
+    };
+    let ys = cuts(Orientation::Horizontal, region.y, region.height);
+    let xs = cuts(Orientation::Vertical, region.x, region.width);
+
+    let mut panels = Vec::new();
+    for rows in ys.windows(2) {
+        for columns in xs.windows(2) {
+            panels.push(Rectangle::new(columns[0], rows[0], columns[1] - columns[0], rows[1] - rows[0]));
+        }
+    }
+    panels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_containment_parents() {
+        let bounds = [
+            Rectangle::new(0.0, 0.0, 800.0, 600.0),   // window
+            Rectangle::new(10.0, 40.0, 380.0, 500.0), // left panel
+            Rectangle::new(20.0, 60.0, 80.0, 30.0),   // button in the panel
+            Rectangle::new(500.0, 60.0, 80.0, 30.0),  // button directly in the window
+            Rectangle::new(900.0, 60.0, 80.0, 30.0),  // outside everything
+        ];
+        assert_eq!(containment_parents(&bounds), [None, Some(0), Some(1), Some(0), None]);
+
+        // Identical boxes do not parent each other
+        let twins = [Rectangle::new(0.0, 0.0, 10.0, 10.0); 2];
+        assert_eq!(containment_parents(&twins), [None, None]);
+    }
+
+    #[test]
+    fn test_reading_order() {
+        let cell = |column: f64, row: f64| Rectangle::new(column * 100.0, row * 80.0 + column * 3.0, 90.0, 60.0);
+        // A grid listed column by column, with slightly ragged rows
+        let grid = [cell(0.0, 1.0), cell(2.0, 0.0), cell(0.0, 0.0), cell(1.0, 1.0), cell(1.0, 0.0)];
+        assert_eq!(reading_order(&grid), [2, 4, 1, 0, 3]);
+
+        let list: Vec<Rectangle> = (0..3).rev().map(|i| Rectangle::new(0.0, i as f64 * 24.0, 200.0, 22.0)).collect();
+        assert_eq!(reading_order(&list), [2, 1, 0]);
+    }
+
+    #[test]
+    fn test_separators_split_panels() {
+        let mut image = Image::from_rgb_data(200, 100, vec![230; 200 * 100 * 3]);
+        for y in 0..100 {
+            image.set_pixel(120, y, &[90, 90, 90]);
+        }
+        for x in 0..200 {
+            image.set_pixel(x, 50, &[90, 90, 90]);
+            image.set_pixel(x, 51, &[90, 90, 90]);
+        }
+
+        let region = Rectangle::new(0.0, 0.0, 200.0, 100.0);
+        let separators = find_separators(&image, &region);
+        assert_eq!(separators, [
+            Separator { orientation: Orientation::Horizontal, position: 50.5 },
+            Separator { orientation: Orientation::Vertical, position: 120.0 },
+        ]);
+
+        let panels = split_panels(&region, &separators);
+        assert_eq!(panels.len(), 4);
+        assert_eq!(panels[1], Rectangle::new(120.0, 0.0, 80.0, 50.5));
+
+        let blank = Image::from_rgb_data(200, 100, vec![230; 200 * 100 * 3]);
+        assert!(find_separators(&blank, &region).is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+// Label-for-control pairing
+// A form's "Email" label and the empty box beside it are separate detections.
+// Each control is paired with the label a reader would take to name it: one
+// that comes before it in reading order (to its left on the same row, or just
+// above it and left-aligned), close by, and not already claimed by a nearer
+// control. Pairs are one-to-one and chosen nearest first.
+
+use super::{ElementType, UIElement};
+use crate::utils::geometry::Rectangle;
+
+/// Widest gap between a label's right edge and the control after it
+const MAX_LEFT_GAP: f64 = 120.0;
+/// Tallest gap between a label's bottom edge and the control below it
+const MAX_ABOVE_GAP: f64 = 24.0;
+/// How far a label above may sit from the control's left edge
+const ABOVE_ALIGN_TOLERANCE: f64 = 16.0;
+/// Overlap allowed where a label is meant to end before its control starts
+const EDGE_TOLERANCE: f64 = 4.0;
+/// Misalignment counts this many times its pixels against a pairing
+const MISALIGNMENT_WEIGHT: f64 = 2.0;
+
+/// For each of `controls`, the index into `labels` of the label it pairs with
+pub fn pair_labels(labels: &[Rectangle], controls: &[Rectangle]) -> Vec<Option<usize>> {
+    let mut costs: Vec<(f64, usize, usize)> = controls
+        .iter()
+        .enumerate()
+        .flat_map(|(c, control)| labels.iter().enumerate().filter_map(move |(l, label)| Some((pairing_cost(label, control)?, l, c))))
+        .collect();
+    costs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut paired = vec![None; controls.len()];
+    let mut claimed = vec![false; labels.len()];
+    for (_, l, c) in costs {
+        if paired[c].is_none() && !claimed[l] {
+            paired[c] = Some(l);
+            claimed[l] = true;
+        }
+    }
+    paired
+}
+
+/// Distance plus misalignment of `label` naming `control`; `None` when it
+/// is not placed like a label for it
+fn pairing_cost(label: &Rectangle, control: &Rectangle) -> Option<f64> {
+    let label_center = label.y + label.height / 2.0;
+    let control_center = control.y + control.height / 2.0;
+
+    // Same row, to the left
+    let left_gap = control.x - (label.x + label.width);
+    if (-EDGE_TOLERANCE..=MAX_LEFT_GAP).contains(&left_gap) && (label_center - control_center).abs() <= control.height / 2.0 {
+        return Some(left_gap.max(0.0) + (label_center - control_center).abs() * MISALIGNMENT_WEIGHT);
+    }
+
+    // Just above, starting where the control starts
+    let above_gap = control.y - (label.y + label.height);
+    let misalignment = (label.x - control.x).abs();
+    if (-EDGE_TOLERANCE..=MAX_ABOVE_GAP).contains(&above_gap) && misalignment <= ABOVE_ALIGN_TOLERANCE {
+        return Some(above_gap.max(0.0) + misalignment * MISALIGNMENT_WEIGHT);
+    }
+    None
+}
+
+/// Whether a detected element takes a label from beside it rather than
+/// carrying its own text
+fn is_labelled_control(element_type: &ElementType) -> bool {
+    matches!(element_type, ElementType::TextBox | ElementType::Dropdown | ElementType::Slider | ElementType::Toggle)
+}
+
+/// Set `associated_label` on each control in `elements` to the index of the
+/// label that names it
+pub fn associate_labels(elements: &mut [UIElement]) {
+    let labels: Vec<usize> = (0..elements.len()).filter(|&i| elements[i].element_type == ElementType::Label).collect();
+    let controls: Vec<usize> = (0..elements.len()).filter(|&i| is_labelled_control(&elements[i].element_type)).collect();
+    let label_bounds: Vec<Rectangle> = labels.iter().map(|&i| elements[i].bounds).collect();
+    let control_bounds: Vec<Rectangle> = controls.iter().map(|&i| elements[i].bounds).collect();
+
+    for (control, label) in controls.into_iter().zip(pair_labels(&label_bounds, &control_bounds)) {
+        elements[control].associated_label = label.map(|l| labels[l]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn element(element_type: ElementType, x: f64, y: f64, width: f64, height: f64) -> UIElement {
+        UIElement {
+            bounds: Rectangle::new(x, y, width, height),
+            shape: None,
+            element_type,
+            confidence: 0.8,
+            properties: HashMap::new(),
+            associated_label: None,
+        }
+    }
+
+    #[test]
+    fn test_pairs_labels_beside_and_above_their_controls() {
+        let mut elements = vec![
+            element(ElementType::Label, 20.0, 106.0, 60.0, 20.0),  // "Name", left of its box
+            element(ElementType::TextBox, 100.0, 100.0, 300.0, 32.0),
+            element(ElementType::Label, 100.0, 150.0, 60.0, 16.0), // "Email", above its box
+            element(ElementType::TextBox, 100.0, 170.0, 300.0, 32.0),
+            element(ElementType::Label, 600.0, 400.0, 60.0, 16.0), // far from everything
+            element(ElementType::TextBox, 100.0, 300.0, 300.0, 32.0),
+            element(ElementType::Button, 100.0, 240.0, 80.0, 30.0),
+        ];
+        associate_labels(&mut elements);
+        let associated: Vec<Option<usize>> = elements.iter().map(|e| e.associated_label).collect();
+        assert_eq!(associated, vec![None, Some(0), None, Some(2), None, None, None]);
+    }
+
+    #[test]
+    fn test_each_label_names_one_control() {
+        // A label between two boxes on its row goes to the one it precedes
+        let labels = [Rectangle::new(100.0, 10.0, 40.0, 20.0)];
+        let controls = [Rectangle::new(0.0, 8.0, 90.0, 24.0), Rectangle::new(150.0, 8.0, 90.0, 24.0)];
+        assert_eq!(pair_labels(&labels, &controls), vec![None, Some(0)]);
+
+        // Two boxes after one label: the nearer wins
+        let controls = [Rectangle::new(250.0, 8.0, 90.0, 24.0), Rectangle::new(145.0, 8.0, 90.0, 24.0)];
+        assert_eq!(pair_labels(&labels, &controls), vec![None, Some(0)]);
+    }
+}
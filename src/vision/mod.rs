@@ -1,11 +1,20 @@
 // Computer vision module with custom implementations
 // Replaces heavy AI/ML frameworks with efficient custom algorithms
 
-use crate::utils::geometry::{Point, Rectangle};
+use crate::utils::geometry::{Point, Polygon, Rectangle};
 use crate::utils::image_processing::{Image, sobel_edge_detection, threshold, find_connected_components};
 use std::collections::HashMap;
 
+pub mod caret;
+pub mod color;
+pub mod frame_channel;
+pub mod hierarchy;
+pub mod labels;
+pub mod modal;
+pub mod occlusion;
+pub mod refine;
 pub mod screen_capture;
+pub mod scroll;
 pub mod ui_detection;
 pub mod text_recognition;
 
@@ -32,10 +41,17 @@ impl Default for VisionConfig {
 
 #[derive(Debug, Clone)]
 pub struct UIElement {
+    /// Axis-aligned box; the bounding box of `shape` when there is one
     pub bounds: Rectangle,
+    /// Exact outline when the element is not an axis-aligned box (rotated
+    /// canvas controls, segmentation masks)
+    pub shape: Option<Polygon>,
     pub element_type: ElementType,
     pub confidence: f64,
     pub properties: HashMap<String, String>,
+    /// Index of the label that names this control, in the same list (see
+    /// `labels::associate_labels`)
+    pub associated_label: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,6 +63,12 @@ pub enum ElementType {
     Window,
     Icon,
     Image,
+    Checkbox,
+    Radio,
+    Slider,
+    Tab,
+    Dropdown,
+    Toggle,
     Unknown,
 }
 
@@ -60,6 +82,12 @@ impl std::fmt::Display for ElementType {
             ElementType::Window => write!(f, "Window"),
             ElementType::Icon => write!(f, "Icon"),
             ElementType::Image => write!(f, "Image"),
+            ElementType::Checkbox => write!(f, "Checkbox"),
+            ElementType::Radio => write!(f, "Radio"),
+            ElementType::Slider => write!(f, "Slider"),
+            ElementType::Tab => write!(f, "Tab"),
+            ElementType::Dropdown => write!(f, "Dropdown"),
+            ElementType::Toggle => write!(f, "Toggle"),
             ElementType::Unknown => write!(f, "Unknown"),
         }
     }
@@ -104,6 +132,7 @@ impl VisionPipeline {
 
         // Step 4: Filter and refine results
         elements = self.filter_elements(elements);
+        labels::associate_labels(&mut elements);
         
         // Cache results
         self.cache.set(image_hash, elements.clone());
@@ -202,9 +231,11 @@ impl VisionPipeline {
         
         Ok(UIElement {
             bounds: *bounds,
+            shape: None,
             element_type,
             confidence,
             properties,
+            associated_label: None,
         })
     }
 
@@ -422,6 +453,7 @@ mod tests {
     fn test_element_type_display() {
         assert_eq!(format!("{}", ElementType::Button), "Button");
         assert_eq!(format!("{}", ElementType::TextBox), "TextBox");
+        assert_eq!(format!("{}", ElementType::Dropdown), "Dropdown");
     }
 
     #[test]
@@ -464,22 +496,28 @@ mod tests {
         let elements = vec![
             UIElement {
                 bounds: Rectangle::new(0.0, 0.0, 10.0, 10.0),
+                shape: None,
                 element_type: ElementType::Button,
                 confidence: 0.8,
                 properties: HashMap::new(),
+                associated_label: None,
             },
             UIElement {
                 // Overlaps the first element by 64% (the filter threshold is 50%)
                 bounds: Rectangle::new(2.0, 2.0, 10.0, 10.0),
+                shape: None,
                 element_type: ElementType::Button,
                 confidence: 0.6,
                 properties: HashMap::new(),
+                associated_label: None,
             },
             UIElement {
                 bounds: Rectangle::new(20.0, 20.0, 10.0, 10.0), // No overlap
+                shape: None,
                 element_type: ElementType::TextBox,
                 confidence: 0.7,
                 properties: HashMap::new(),
+                associated_label: None,
             },
         ];
         
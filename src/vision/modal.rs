@@ -0,0 +1,144 @@
+// Modal dialog detection
+// A modal dialog blocks the UI behind it: clicks outside it do nothing, or
+// land on controls that look enabled but are not. Toolkits mark modals in a
+// few consistent ways. The dialog sits near the middle of the screen, its
+// edge stands out from what surrounds it (a border or shadow), and the rest
+// of the screen is often dimmed. A centered container with either of the
+// other cues is taken to be a modal.
+
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// How far a modal's center may be from the screen's, as a fraction of the
+/// screen's width and height
+const CENTER_TOLERANCE: (f64, f64) = (0.1, 0.2);
+/// Share of the screen a modal covers, at least and at most
+const AREA_RANGE: (f64, f64) = (0.02, 0.6);
+/// Luminance step across the edge that counts as a border
+const BORDER_CONTRAST: i32 = 30;
+/// Share of the edge samples that must show the step
+const BORDER_COVERAGE: f64 = 0.7;
+/// Pixels between the sample just inside the edge and the one just outside
+const BORDER_REACH: f64 = 3.0;
+/// The background counts as dimmed below this fraction of the dialog's brightness
+const DIM_RATIO: f64 = 0.7;
+/// Samples per side along the edge and per axis over the screen
+const SAMPLES: usize = 24;
+
+/// What made a container look modal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModalCues {
+    pub centered: bool,
+    /// Share of the edge where inside and outside differ by `BORDER_CONTRAST`
+    pub border: f64,
+    /// Mean luminance outside the container over the mean inside it
+    pub background_ratio: f64,
+}
+
+impl ModalCues {
+    pub fn is_modal(&self) -> bool {
+        self.centered && (self.border >= BORDER_COVERAGE || self.background_ratio < DIM_RATIO)
+    }
+
+    /// How strongly the cues agree, for choosing between candidates
+    fn score(&self) -> f64 {
+        self.border + (1.0 - self.background_ratio).max(0.0)
+    }
+}
+
+/// Cues for `rect` being a modal on `image`, or `None` when its size rules it out
+pub fn modal_cues(image: &Image, rect: &Rectangle) -> Option<ModalCues> {
+    let (width, height) = (image.width as f64, image.height as f64);
+    let share = rect.area() / (width * height);
+    if width == 0.0 || height == 0.0 || share < AREA_RANGE.0 || share > AREA_RANGE.1 {
+        return None;
+    }
+    let (cx, cy) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+    let centered = (cx - width / 2.0).abs() <= width * CENTER_TOLERANCE.0 && (cy - height / 2.0).abs() <= height * CENTER_TOLERANCE.1;
+
+    // Pairs of points straddling each side: (inside, outside)
+    let steps = (0..SAMPLES).map(|i| (i as f64 + 0.5) / SAMPLES as f64);
+    let (left, top, right, bottom) = (rect.x, rect.y, rect.x + rect.width, rect.y + rect.height);
+    let pairs: Vec<((f64, f64), (f64, f64))> = steps
+        .flat_map(|t| {
+            let (x, y) = (left + t * rect.width, top + t * rect.height);
+            [
+                ((x, top + BORDER_REACH), (x, top - BORDER_REACH)),
+                ((x, bottom - BORDER_REACH), (x, bottom + BORDER_REACH)),
+                ((left + BORDER_REACH, y), (left - BORDER_REACH, y)),
+                ((right - BORDER_REACH, y), (right + BORDER_REACH, y)),
+            ]
+        })
+        .collect();
+    let measured: Vec<(i32, i32)> = pairs.iter().filter_map(|&(inside, outside)| Some((luma(image, inside)?, luma(image, outside)?))).collect();
+    let stepped = measured.iter().filter(|(inside, outside)| (inside - outside).abs() >= BORDER_CONTRAST).count();
+    let border = if measured.is_empty() { 0.0 } else { stepped as f64 / measured.len() as f64 };
+
+    let (mut inside, mut outside) = (Vec::new(), Vec::new());
+    for row in 0..SAMPLES {
+        for column in 0..SAMPLES {
+            let point = ((column as f64 + 0.5) * width / SAMPLES as f64, (row as f64 + 0.5) * height / SAMPLES as f64);
+            let Some(value) = luma(image, point) else { continue };
+            let within = point.0 >= left && point.0 < right && point.1 >= top && point.1 < bottom;
+            if within { &mut inside } else { &mut outside }.push(f64::from(value));
+        }
+    }
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len().max(1) as f64;
+    let background_ratio = match mean(&inside) {
+        bright if bright > 0.0 && !outside.is_empty() => mean(&outside) / bright,
+        _ => 1.0,
+    };
+    Some(ModalCues { centered, border, background_ratio })
+}
+
+/// Index of the container in `candidates` most likely to be an open modal
+pub fn find_modal(image: &Image, candidates: &[Rectangle]) -> Option<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rect)| Some((i, modal_cues(image, rect)?)))
+        .filter(|(_, cues)| cues.is_modal())
+        .max_by(|a, b| a.1.score().total_cmp(&b.1.score()))
+        .map(|(i, _)| i)
+}
+
+fn luma(image: &Image, (x, y): (f64, f64)) -> Option<i32> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    image.get_pixel(x as usize, y as usize).map(|p| match p.len() {
+        1 | 2 => i32::from(p[0]),
+        _ => (i32::from(p[0]) * 299 + i32::from(p[1]) * 587 + i32::from(p[2]) * 114) / 1000,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 400x300 screen of `background` with a 160x100 box of `dialog` at (x, y)
+    fn screen(background: u8, dialog: u8, x: usize, y: usize) -> Image {
+        let mut data = vec![background; 400 * 300 * 3];
+        for row in y..y + 100 {
+            for column in x..x + 160 {
+                let at = (row * 400 + column) * 3;
+                data[at..at + 3].fill(dialog);
+            }
+        }
+        Image::from_rgb_data(400, 300, data)
+    }
+
+    #[test]
+    fn test_centered_dialog_over_dimmed_screen_is_modal() {
+        let dialog = Rectangle::new(120.0, 100.0, 160.0, 100.0);
+        let dimmed = screen(90, 240, 120, 100);
+        let cues = modal_cues(&dimmed, &dialog).unwrap();
+        assert!(cues.centered && cues.border > 0.9 && cues.background_ratio < 0.5, "{:?}", cues);
+        assert_eq!(find_modal(&dimmed, &[Rectangle::new(0.0, 0.0, 400.0, 300.0), dialog]), Some(1));
+
+        // The same box in a corner is a panel, and one that blends in has no edge
+        let corner = Rectangle::new(0.0, 0.0, 160.0, 100.0);
+        assert!(!modal_cues(&screen(90, 240, 0, 0), &corner).unwrap().is_modal());
+        assert!(!modal_cues(&screen(240, 240, 120, 100), &dialog).unwrap().is_modal());
+    }
+}
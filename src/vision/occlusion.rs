@@ -0,0 +1,75 @@
+// Occlusion of elements by windows stacked in front of them
+// Detectors report every window they can see, including the visible parts of
+// ones further back, so without the stacking order a control in a rear window
+// looks as clickable as one in front. The order comes from a `z_order`
+// attribute on top-level windows (0 = frontmost) as reported by the OS window
+// list or an accessibility adapter; coverage is estimated on a sample grid.
+
+use crate::utils::geometry::{Point, Rectangle};
+
+/// Sample points per side of the grid laid over an element
+const SAMPLES_PER_SIDE: usize = 8;
+
+/// Fraction of `rect` that lies under any of `covering`
+pub fn covered_fraction(rect: &Rectangle, covering: &[Rectangle]) -> f64 {
+    if covering.is_empty() || rect.area() <= 0.0 {
+        return 0.0;
+    }
+    let samples = sample_points(rect);
+    let covered = samples.iter().filter(|p| is_covered(p, covering)).count();
+    covered as f64 / samples.len() as f64
+}
+
+/// Whether `point` lies under any of `covering`
+pub fn is_covered(point: &Point, covering: &[Rectangle]) -> bool {
+    covering.iter().any(|r| r.contains_point(point))
+}
+
+/// A point of `rect` that none of `covering` hides, scanning row by row from
+/// the top-left, or `None` when it is covered everywhere
+pub fn visible_point(rect: &Rectangle, covering: &[Rectangle]) -> Option<Point> {
+    sample_points(rect).into_iter().find(|p| !is_covered(p, covering))
+}
+
+/// Centers of a `SAMPLES_PER_SIDE` grid of cells over `rect`
+fn sample_points(rect: &Rectangle) -> Vec<Point> {
+    let (step_x, step_y) = (rect.width / SAMPLES_PER_SIDE as f64, rect.height / SAMPLES_PER_SIDE as f64);
+    (0..SAMPLES_PER_SIDE)
+        .flat_map(|row| {
+            (0..SAMPLES_PER_SIDE).map(move |column| {
+                Point::new(rect.x + (column as f64 + 0.5) * step_x, rect.y + (row as f64 + 0.5) * step_y)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covered_fraction_counts_overlapping_windows_once() {
+        let button = Rectangle::new(0.0, 0.0, 80.0, 40.0);
+        assert_eq!(covered_fraction(&button, &[]), 0.0);
+
+        let left_half = Rectangle::new(-100.0, -100.0, 140.0, 300.0);
+        assert_eq!(covered_fraction(&button, &[left_half]), 0.5);
+        // A second window over the same half hides nothing more
+        let also_left = Rectangle::new(0.0, 0.0, 40.0, 40.0);
+        assert_eq!(covered_fraction(&button, &[left_half, also_left]), 0.5);
+
+        let everything = Rectangle::new(-10.0, -10.0, 200.0, 200.0);
+        assert_eq!(covered_fraction(&button, &[everything]), 1.0);
+    }
+
+    #[test]
+    fn test_visible_point_avoids_covering_windows() {
+        let title_bar = Rectangle::new(0.0, 0.0, 400.0, 40.0);
+        let front = Rectangle::new(-50.0, -50.0, 300.0, 300.0);
+        let point = visible_point(&title_bar, &[front]).unwrap();
+        assert!(point.x > 250.0 && point.y < 40.0, "{:?}", point);
+
+        let over_all = Rectangle::new(-1.0, -1.0, 500.0, 100.0);
+        assert!(visible_point(&title_bar, &[over_all]).is_none());
+    }
+}
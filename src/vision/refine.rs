@@ -0,0 +1,129 @@
+// Bounding-box refinement: detectors (especially remote models working on a
+// downscaled frame) often place a box a few pixels off, cutting off a button's
+// border. Each side is snapped independently to the strongest luminance edge
+// within a few pixels, so overlays hug the element and click points keep their
+// margin from its edges.
+
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// How far (in pixels) a side may move
+pub const SNAP_RADIUS: usize = 5;
+/// Mean luminance step along a side below which it is not an edge
+const MIN_EDGE_STRENGTH: f64 = 20.0;
+/// Fraction of each side ignored at both ends, where corners and neighbours blur the edge
+const SIDE_INSET: f64 = 0.1;
+
+/// `rect` with each side moved to the strongest edge within `radius` pixels.
+/// Sides with no clear edge nearby stay where they are.
+pub fn snap_to_edges(image: &Image, rect: &Rectangle, radius: usize) -> Rectangle {
+    let (width, height) = (image.width as i64, image.height as i64);
+    let x0 = rect.x.round() as i64;
+    let y0 = rect.y.round() as i64;
+    let x1 = (rect.x + rect.width).round() as i64;
+    let y1 = (rect.y + rect.height).round() as i64;
+    if x1 - x0 < 3 || y1 - y0 < 3 || width < 2 || height < 2 {
+        return *rect;
+    }
+
+    let luma = |x: i64, y: i64| -> f64 {
+        image.get_pixel(x as usize, y as usize).map_or(0.0, |p| match p.len() {
+            1 | 2 => p[0] as f64,
+            _ => 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64,
+        })
+    };
+    let inset = |start: i64, end: i64| {
+        let margin = ((end - start) as f64 * SIDE_INSET) as i64;
+        (start + margin, end - margin)
+    };
+    let (sx0, sx1) = inset(x0, x1);
+    let (sy0, sy1) = inset(y0, y1);
+
+    // Boundary `b` lies between pixel rows b-1 and b (columns for vertical sides)
+    let row_edge = |b: i64| {
+        let total: f64 = (sx0.max(0)..sx1.min(width)).map(|x| (luma(x, b) - luma(x, b - 1)).abs()).sum();
+        total / (sx1 - sx0).max(1) as f64
+    };
+    let column_edge = |b: i64| {
+        let total: f64 = (sy0.max(0)..sy1.min(height)).map(|y| (luma(b, y) - luma(b - 1, y)).abs()).sum();
+        total / (sy1 - sy0).max(1) as f64
+    };
+
+    let radius = radius as i64;
+    // Candidates are ordered outward-first so ties keep the element whole
+    let top = snap_side(y0, (y0 - radius..=y0 + radius).filter(|b| (1..height).contains(b)), &row_edge);
+    let bottom = snap_side(y1, (y1 - radius..=y1 + radius).rev().filter(|b| (1..height).contains(b)), &row_edge);
+    let left = snap_side(x0, (x0 - radius..=x0 + radius).filter(|b| (1..width).contains(b)), &column_edge);
+    let right = snap_side(x1, (x1 - radius..=x1 + radius).rev().filter(|b| (1..width).contains(b)), &column_edge);
+
+    if right - left < 2 || bottom - top < 2 {
+        return *rect;
+    }
+    Rectangle::new(left as f64, top as f64, (right - left) as f64, (bottom - top) as f64)
+}
+
+/// Strongest boundary among `candidates` (first wins ties), or `current` when none is an edge
+fn snap_side(current: i64, candidates: impl Iterator<Item = i64>, strength: &impl Fn(i64) -> f64) -> i64 {
+    candidates
+        .map(|b| (b, strength(b)))
+        .filter(|(_, s)| *s >= MIN_EDGE_STRENGTH)
+        .fold(None, |best: Option<(i64, f64)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })
+        .map_or(current, |(b, _)| b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Light background with dark-bordered buttons
+    fn screen(buttons: &[Rectangle]) -> Image {
+        let mut image = Image::from_rgb_data(400, 200, vec![236; 400 * 200 * 3]);
+        for b in buttons {
+            let (x0, y0) = (b.x as usize, b.y as usize);
+            let (x1, y1) = (x0 + b.width as usize, y0 + b.height as usize);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let border = x == x0 || x == x1 - 1 || y == y0 || y == y1 - 1;
+                    image.set_pixel(x, y, if border { &[40, 70, 140] } else { &[70, 120, 200] });
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_snapping_recovers_jittered_boxes() {
+        let truth = [
+            Rectangle::new(20.0, 20.0, 100.0, 30.0),
+            Rectangle::new(160.0, 40.0, 80.0, 24.0),
+            Rectangle::new(280.0, 100.0, 90.0, 60.0),
+        ];
+        let image = screen(&truth);
+        let jitter = [(3.0, -2.0, -4.0, 3.0), (-4.0, 3.0, 5.0, -3.0), (2.0, 4.0, -5.0, -4.0)];
+
+        let (mut before, mut after) = (0.0, 0.0);
+        for (t, (dx, dy, dw, dh)) in truth.iter().zip(jitter) {
+            let detected = Rectangle::new(t.x + dx, t.y + dy, t.width + dw, t.height + dh);
+            let snapped = snap_to_edges(&image, &detected, SNAP_RADIUS);
+            before += t.iou(&detected) / truth.len() as f64;
+            after += t.iou(&snapped) / truth.len() as f64;
+            assert_eq!(&snapped, t, "{:?} snapped to {:?}", detected, snapped);
+        }
+        assert!(before < 0.9 && after > 0.99, "mean IoU {:.3} -> {:.3}", before, after);
+    }
+
+    #[test]
+    fn test_sides_without_edges_stay_put() {
+        let image = screen(&[]);
+        let rect = Rectangle::new(50.0, 50.0, 60.0, 30.0);
+        assert_eq!(snap_to_edges(&image, &rect, SNAP_RADIUS), rect);
+
+        // Too far from the real edge to snap
+        let image = screen(&[Rectangle::new(20.0, 20.0, 100.0, 30.0)]);
+        let far = Rectangle::new(20.0, 20.0, 100.0, 40.0);
+        assert_eq!(snap_to_edges(&image, &far, SNAP_RADIUS).height, 40.0);
+    }
+}
@@ -1,8 +1,13 @@
 // Screen capture functionality with minimal dependencies
 // Cross-platform screen capture implementation
 
+use crate::utils::geometry::Rectangle;
 use crate::utils::image_processing::Image;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use log::{debug, warn};
+
+use super::frame_channel::FramePublisher;
 
 #[derive(Debug, Clone)]
 pub struct CaptureConfig {
@@ -31,10 +36,27 @@ impl Default for CaptureConfig {
     }
 }
 
+/// Supplies frames in place of the screen, e.g. a simulated desktop
+pub trait FrameSource: Send {
+    fn capture(&mut self) -> Result<Image, CaptureError>;
+
+    /// Only `rect` of the frame; sources that can grab part of one cheaply override this
+    fn capture_region(&mut self, rect: &Rectangle) -> Result<Image, CaptureError> {
+        Ok(self.capture()?.crop(rect))
+    }
+}
+
+/// A frame source shared by every capture that should see it
+pub type SharedFrameSource = Arc<Mutex<dyn FrameSource>>;
+
 pub struct ScreenCapture {
     config: CaptureConfig,
     last_capture_time: Option<Instant>,
     frame_interval: Duration,
+    /// Where every captured frame is also published for other processes
+    frame_channel: Option<FramePublisher>,
+    /// Captured instead of the screen when set
+    source: Option<SharedFrameSource>,
 }
 
 impl ScreenCapture {
@@ -45,9 +67,30 @@ impl ScreenCapture {
             config,
             last_capture_time: None,
             frame_interval,
+            frame_channel: None,
+            source: None,
         }
     }
 
+    /// Capture from `source` instead of the screen, or from the screen again with `None`
+    pub fn set_source(&mut self, source: Option<SharedFrameSource>) {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> Option<&SharedFrameSource> {
+        self.source.as_ref()
+    }
+
+    /// Publish every frame this capture takes to `channel`; the channel closes
+    /// when the last capture sharing it is dropped
+    pub fn share_frames(&mut self, channel: Option<FramePublisher>) {
+        self.frame_channel = channel;
+    }
+
+    pub fn frame_channel(&self) -> Option<&FramePublisher> {
+        self.frame_channel.as_ref()
+    }
+
     pub fn capture_screen(&mut self) -> Result<Image, CaptureError> {
         // Rate limiting
         if let Some(last_time) = self.last_capture_time {
@@ -57,15 +100,47 @@ impl ScreenCapture {
             }
         }
 
-        let image = match self.config.capture_region {
-            Some(ref region) => self.capture_region(region)?,
+        let full_screen = match &self.source {
+            Some(source) => source.lock().unwrap_or_else(std::sync::PoisonError::into_inner).capture()?,
             None => self.capture_full_screen()?,
         };
+        let image = match self.config.capture_region {
+            Some(ref region) => crop_to_region(&full_screen, region),
+            None => full_screen,
+        };
 
         self.last_capture_time = Some(Instant::now());
+        if let Some(channel) = &self.frame_channel {
+            if let Err(e) = channel.publish(&image) {
+                warn!("Could not publish frame: {}", e);
+            }
+        }
         Ok(image)
     }
 
+    /// Capture only `rect`, in frame coordinates, e.g. to look at a click
+    /// target again without grabbing the whole screen. Not rate limited and
+    /// not published to the frame channel, since it is not a whole frame.
+    pub fn capture_area(&mut self, rect: &Rectangle) -> Result<Image, CaptureError> {
+        let rect = match &self.config.capture_region {
+            Some(region) => Rectangle::new(rect.x + region.x as f64, rect.y + region.y as f64, rect.width, rect.height),
+            None => *rect,
+        };
+        match &self.source {
+            Some(source) => source.lock().unwrap_or_else(std::sync::PoisonError::into_inner).capture_region(&rect),
+            None => self.capture_screen_area(&rect),
+        }
+    }
+
+    fn capture_screen_area(&self, rect: &Rectangle) -> Result<Image, CaptureError> {
+        // Placeholder implementation
+        // Real implementation would copy only `rect`:
+        // - Windows: BitBlt of that rectangle from the screen DC
+        // - X11: XGetImage with the rectangle
+        // - macOS: CGDisplayCreateImageForRect
+        Ok(self.capture_full_screen()?.crop(rect))
+    }
+
     #[cfg(target_os = "windows")]
     fn capture_full_screen(&self) -> Result<Image, CaptureError> {
         // Simplified Windows implementation
@@ -93,20 +168,6 @@ impl ScreenCapture {
         self.create_dummy_screen()
     }
 
-    fn capture_region(&self, region: &CaptureRegion) -> Result<Image, CaptureError> {
-        let full_screen = self.capture_full_screen()?;
-        
-        // Crop to the specified region
-        let crop_rect = crate::utils::geometry::Rectangle::new(
-            region.x as f64,
-            region.y as f64,
-            region.width as f64,
-            region.height as f64,
-        );
-        
-        Ok(full_screen.crop(&crop_rect))
-    }
-
     #[cfg(target_os = "windows")]
     fn windows_capture_screen(&self) -> Result<Image, CaptureError> {
         // Placeholder implementation
@@ -116,7 +177,7 @@ impl ScreenCapture {
         // - BitBlt to copy screen content
         // - GetDIBits to get raw pixel data
         
-        println!("Windows screen capture - would use GDI/DXGI");
+        debug!("Windows screen capture - would use GDI/DXGI");
         self.create_test_pattern(1920, 1080)
     }
 
@@ -127,7 +188,7 @@ impl ScreenCapture {
         // - X11: XGetImage with root window
         // - Wayland: wlr-screencopy or similar protocol
         
-        println!("Linux screen capture - would use X11/Wayland");
+        debug!("Linux screen capture - would use X11/Wayland");
         self.create_test_pattern(1920, 1080)
     }
 
@@ -138,13 +199,13 @@ impl ScreenCapture {
         // - CGDisplayCreateImage
         // - CGImageGetDataProvider and CGDataProviderCopyData
         
-        println!("macOS screen capture - would use Core Graphics");
+        debug!("macOS screen capture - would use Core Graphics");
         self.create_test_pattern(1920, 1080)
     }
 
     #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
     fn create_dummy_screen(&self) -> Result<Image, CaptureError> {
-        println!("Unsupported platform - creating dummy screen");
+        debug!("Unsupported platform - creating dummy screen");
         self.create_test_pattern(1920, 1080)
     }
 
@@ -204,7 +265,11 @@ impl ScreenCapture {
     }
 
     pub fn list_displays(&self) -> Result<Vec<DisplayInfo>, CaptureError> {
-        // Placeholder - would enumerate actual displays on each platform
+        use crate::core::displays::{DisplayProbe, SystemDisplayProbe};
+        if let Some(displays) = SystemDisplayProbe.displays() {
+            return Ok(displays);
+        }
+        // Placeholder where the platform can't be asked
         Ok(vec![DisplayInfo {
             id: 0,
             name: "Primary Display".to_string(),
@@ -218,12 +283,12 @@ impl ScreenCapture {
 
     pub fn capture_window(&self, window_id: u64) -> Result<Image, CaptureError> {
         // Placeholder for window-specific capture
-        println!("Window capture for ID: {}", window_id);
+        debug!("Window capture for ID: {}", window_id);
         self.create_test_pattern(800, 600)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DisplayInfo {
     pub id: u32,
     pub name: String,
@@ -234,6 +299,22 @@ pub struct DisplayInfo {
     pub is_primary: bool,
 }
 
+impl DisplayInfo {
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && ((x - self.x) as u32) < self.width && ((y - self.y) as u32) < self.height
+    }
+}
+
+fn crop_to_region(full_screen: &Image, region: &CaptureRegion) -> Image {
+    let crop_rect = crate::utils::geometry::Rectangle::new(
+        region.x as f64,
+        region.y as f64,
+        region.width as f64,
+        region.height as f64,
+    );
+    full_screen.crop(&crop_rect)
+}
+
 // Async screen capture for non-blocking operation
 pub struct AsyncScreenCapture {
     capture: ScreenCapture,
@@ -260,6 +341,8 @@ impl AsyncScreenCapture {
         let (tx, rx) = std::sync::mpsc::channel();
         let should_stop = self.should_stop.clone();
         let mut capture = ScreenCapture::new(self.capture.config.clone());
+        capture.share_frames(self.capture.frame_channel().cloned());
+        capture.set_source(self.capture.source().cloned());
 
         let handle = std::thread::spawn(move || {
             while !should_stop.load(std::sync::atomic::Ordering::Relaxed) {
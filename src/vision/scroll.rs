@@ -0,0 +1,163 @@
+// Scroll delta estimation
+// After a scroll, what was analyzed is still on screen, only moved. Each of a
+// few vertical strips of the scrolled area is reduced to a profile of mean
+// brightness per row, and the profiles before and after are cross-correlated:
+// the shift where they agree best is how far the content moved. A shift can
+// then be checked on individual elements by comparing where an element was
+// with where it should be now.
+
+use crate::utils::geometry::Rectangle;
+use crate::utils::image_processing::Image;
+
+/// Vertical strips of the area profiled separately, so a column of icons
+/// and a column of text both count
+const STRIPS: usize = 3;
+/// Rows the before and after profiles must share for a shift to be measured
+const MIN_OVERLAP_ROWS: usize = 16;
+/// Shifts whose correlation is this close to the best are equally good;
+/// the one nearest the expected shift wins (rows of a list repeat)
+const TIE_MARGIN: f64 = 0.01;
+
+/// How far the content of an area moved between two frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollShift {
+    /// Pixels the content moved up; negative when it moved down
+    pub dy: i32,
+    /// Normalized cross-correlation of the profiles at that shift, -1.0..=1.0
+    pub correlation: f64,
+}
+
+/// The shift of `area`'s content from `before` to `after`, searched up to
+/// `max_shift` pixels either way. `None` when the area is featureless or
+/// the frames differ in size.
+pub fn estimate_shift(before: &Image, after: &Image, area: &Rectangle, max_shift: usize, expected: i32) -> Option<ScrollShift> {
+    if before.width != after.width || before.height != after.height {
+        return None;
+    }
+    let (x0, y0, x1, y1) = pixel_bounds(before, area)?;
+    let rows = y1 - y0;
+    if rows < MIN_OVERLAP_ROWS || x1 - x0 < STRIPS {
+        return None;
+    }
+    let strip_width = (x1 - x0) / STRIPS;
+    let strips: Vec<(usize, usize)> = (0..STRIPS).map(|s| (x0 + s * strip_width, x0 + (s + 1) * strip_width)).collect();
+    let profile = |image: &Image| -> Vec<Vec<f64>> { strips.iter().map(|&(a, b)| row_profile(image, a, b, y0, y1)).collect() };
+    let (old, new) = (profile(before), profile(after));
+
+    let max_shift = max_shift.min(rows - MIN_OVERLAP_ROWS) as i32;
+    let scored: Vec<(i32, f64)> = (-max_shift..=max_shift)
+        .filter_map(|dy| Some((dy, correlation(&old, &new, dy)?)))
+        .collect();
+    let best = scored.iter().map(|(_, c)| *c).fold(f64::NEG_INFINITY, f64::max);
+    scored
+        .into_iter()
+        .filter(|(_, c)| *c >= best - TIE_MARGIN)
+        .min_by_key(|(dy, _)| (dy - expected).abs())
+        .map(|(dy, correlation)| ScrollShift { dy, correlation })
+}
+
+/// Fraction of the pixels of `rect` in `before` that are within `tolerance`
+/// of the pixels `dy` rows higher in `after`
+pub fn shifted_match(before: &Image, after: &Image, rect: &Rectangle, dy: i32, tolerance: u8) -> f64 {
+    let Some((x0, y0, x1, y1)) = pixel_bounds(before, rect) else {
+        return 0.0;
+    };
+    let mut matching = 0usize;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let moved = (y as i64 - dy as i64).try_into().ok().and_then(|y: usize| after.get_pixel(x, y));
+            matching += match (before.get_pixel(x, y), moved) {
+                (Some(a), Some(b)) if a.len() == b.len() => a.iter().zip(b).all(|(p, q)| p.abs_diff(*q) <= tolerance) as usize,
+                _ => 0,
+            };
+        }
+    }
+    matching as f64 / ((x1 - x0) * (y1 - y0)) as f64
+}
+
+/// `rect` clamped to the image, as `(x0, y0, x1, y1)`; `None` when nothing is left
+fn pixel_bounds(image: &Image, rect: &Rectangle) -> Option<(usize, usize, usize, usize)> {
+    let x0 = rect.x.max(0.0) as usize;
+    let y0 = rect.y.max(0.0) as usize;
+    let x1 = ((rect.x + rect.width).max(0.0) as usize).min(image.width);
+    let y1 = ((rect.y + rect.height).max(0.0) as usize).min(image.height);
+    (x1 > x0 && y1 > y0).then_some((x0, y0, x1, y1))
+}
+
+/// Mean brightness of each row from `y0` to `y1` between columns `x0` and `x1`
+fn row_profile(image: &Image, x0: usize, x1: usize, y0: usize, y1: usize) -> Vec<f64> {
+    (y0..y1)
+        .map(|y| {
+            let sum: u64 = (x0..x1)
+                .filter_map(|x| image.get_pixel(x, y))
+                .map(|p| p.iter().take(3).map(|&c| c as u64).sum::<u64>() / p.len().clamp(1, 3) as u64)
+                .sum();
+            sum as f64 / (x1 - x0) as f64
+        })
+        .collect()
+}
+
+/// Normalized cross-correlation of the old profiles against the new ones
+/// moved up `dy` rows, over all strips together
+fn correlation(old: &[Vec<f64>], new: &[Vec<f64>], dy: i32) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = old
+        .iter()
+        .zip(new)
+        .flat_map(|(old, new)| {
+            (0..old.len()).filter_map(move |row| {
+                let moved = usize::try_from(row as i64 - dy as i64).ok()?;
+                Some((old[row], *new.get(moved)?))
+            })
+        })
+        .collect();
+    if pairs.len() < MIN_OVERLAP_ROWS * old.len() {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let (mean_a, mean_b) = (pairs.iter().map(|p| p.0).sum::<f64>() / n, pairs.iter().map(|p| p.1).sum::<f64>() / n);
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (a, b) in &pairs {
+        cov += (a - mean_a) * (b - mean_b);
+        var_a += (a - mean_a).powi(2);
+        var_b += (b - mean_b).powi(2);
+    }
+    (var_a > f64::EPSILON && var_b > f64::EPSILON).then(|| cov / (var_a * var_b).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A list of 24-pixel rows of varying shade, its content moved up `offset` pixels
+    fn list(offset: usize) -> Image {
+        let mut image = Image::new(200, 300, 3);
+        for y in 0..300 {
+            let content = y + offset;
+            let shade = ((content / 24) * 37 % 200) as u8 + (content % 24 < 3) as u8 * 40;
+            for x in 0..200 {
+                let ink = if (x + content) % 11 < 4 && content % 24 > 6 { 60 } else { 0 };
+                image.set_pixel(x, y, &[shade.saturating_add(ink); 3]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_estimates_shift_of_scrolled_content() {
+        let area = Rectangle::new(0.0, 0.0, 200.0, 300.0);
+        let shift = estimate_shift(&list(0), &list(70), &area, 200, 80).unwrap();
+        assert_eq!(shift.dy, 70);
+        assert!(shift.correlation > 0.95);
+        assert_eq!(estimate_shift(&list(50), &list(10), &area, 200, -40).unwrap().dy, -40);
+        // Nothing to correlate on a blank area
+        assert!(estimate_shift(&Image::new(200, 300, 3), &Image::new(200, 300, 3), &area, 200, 0).is_none());
+    }
+
+    #[test]
+    fn test_shifted_match_checks_elements_at_their_new_place() {
+        let (before, after) = (list(0), list(70));
+        let row = Rectangle::new(10.0, 120.0, 150.0, 20.0);
+        assert!(shifted_match(&before, &after, &row, 70, 8) > 0.99);
+        assert!(shifted_match(&before, &after, &row, 0, 8) < 0.9);
+    }
+}
@@ -3,6 +3,7 @@
 
 use crate::utils::geometry::{Point, Rectangle};
 use crate::utils::image_processing::{Image, threshold};
+use crate::utils::text;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -178,11 +179,11 @@ impl TextRecognizer {
     fn calculate_horizontal_projection(&self, binary: &Image) -> Vec<usize> {
         let mut projection = vec![0; binary.height];
         
-        for y in 0..binary.height {
+        for (y, count) in projection.iter_mut().enumerate() {
             for x in 0..binary.width {
                 if let Some(pixel) = binary.get_pixel(x, y) {
                     if pixel[0] > 0 {
-                        projection[y] += 1;
+                        *count += 1;
                     }
                 }
             }
@@ -271,11 +272,11 @@ impl TextRecognizer {
     fn calculate_vertical_projection(&self, binary: &Image) -> Vec<usize> {
         let mut projection = vec![0; binary.width];
         
-        for x in 0..binary.width {
+        for (x, count) in projection.iter_mut().enumerate() {
             for y in 0..binary.height {
                 if let Some(pixel) = binary.get_pixel(x, y) {
                     if pixel[0] > 0 {
-                        projection[x] += 1;
+                        *count += 1;
                     }
                 }
             }
@@ -401,7 +402,7 @@ impl TextRecognizer {
             ],
         };
         
-        self.character_templates.entry('A').or_insert_with(Vec::new).push(a_pattern);
+        self.character_templates.entry('A').or_default().push(a_pattern);
         
         // Template for 'O'
         let o_pattern = Pattern {
@@ -423,14 +424,14 @@ impl TextRecognizer {
             ],
         };
         
-        self.character_templates.entry('O').or_insert_with(Vec::new).push(o_pattern);
+        self.character_templates.entry('O').or_default().push(o_pattern);
         
         // Add more basic templates as needed...
         // For a full implementation, you would want templates for all alphanumeric characters
     }
 
     pub fn add_character_template(&mut self, character: char, pattern: Pattern) {
-        self.character_templates.entry(character).or_insert_with(Vec::new).push(pattern);
+        self.character_templates.entry(character).or_default().push(pattern);
     }
 }
 
@@ -456,7 +457,7 @@ pub fn find_text_containing(image: &Image, search_text: &str) -> Result<Vec<Text
     let regions = recognizer.recognize_text(image)?;
     
     Ok(regions.into_iter()
-        .filter(|region| region.text.to_lowercase().contains(&search_text.to_lowercase()))
+        .filter(|region| text::contains_folded(&region.text, search_text))
         .collect())
 }
 
@@ -568,7 +569,7 @@ mod tests {
         if let Some(templates) = recognizer.character_templates.get(&'A') {
             if let Some(template) = templates.first() {
                 let confidence = recognizer.match_template(&test_image, template);
-                assert!(confidence >= 0.0 && confidence <= 1.0);
+                assert!((0.0..=1.0).contains(&confidence));
             }
         }
     }
@@ -2,7 +2,9 @@
 // Advanced detection for different UI component types
 
 use crate::utils::geometry::Rectangle;
-use crate::utils::image_processing::{Image, sobel_edge_detection, threshold, gaussian_blur};
+use crate::utils::image_processing::{
+    Image, sobel_edge_detection, threshold, gaussian_blur, find_connected_components, calculate_histogram,
+};
 use super::{UIElement, ElementType, VisionError};
 use std::collections::HashMap;
 
@@ -11,6 +13,7 @@ pub struct UIDetector {
     text_detector: TextDetector,
     window_detector: WindowDetector,
     menu_detector: MenuDetector,
+    control_detector: ControlDetector,
 }
 
 impl UIDetector {
@@ -20,6 +23,7 @@ impl UIDetector {
             text_detector: TextDetector::new(),
             window_detector: WindowDetector::new(),
             menu_detector: MenuDetector::new(),
+            control_detector: ControlDetector::new(),
         }
     }
 
@@ -31,6 +35,7 @@ impl UIDetector {
         elements.extend(self.text_detector.detect(image)?);
         elements.extend(self.window_detector.detect(image)?);
         elements.extend(self.menu_detector.detect(image)?);
+        elements.extend(self.control_detector.detect(image)?);
 
         // Sort by confidence
         elements.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap());
@@ -53,6 +58,10 @@ impl UIDetector {
     pub fn detect_menus(&self, image: &Image) -> Result<Vec<UIElement>, VisionError> {
         self.menu_detector.detect(image)
     }
+
+    pub fn detect_controls(&self, image: &Image) -> Result<Vec<UIElement>, VisionError> {
+        self.control_detector.detect(image)
+    }
 }
 
 impl Default for UIDetector {
@@ -101,9 +110,11 @@ impl ButtonDetector {
                     
                     buttons.push(UIElement {
                         bounds: candidate,
+                        shape: None,
                         element_type: ElementType::Button,
                         confidence,
                         properties,
+                        associated_label: None,
                     });
                 }
             }
@@ -291,10 +302,9 @@ impl ButtonDetector {
             }
         }
         
-        if count > 0 {
-            ((sum_r / count) as u8, (sum_g / count) as u8, (sum_b / count) as u8)
-        } else {
-            (128, 128, 128)
+        match (sum_r.checked_div(count), sum_g.checked_div(count), sum_b.checked_div(count)) {
+            (Some(r), Some(g), Some(b)) => (r as u8, g as u8, b as u8),
+            _ => (128, 128, 128),
         }
     }
 
@@ -324,6 +334,12 @@ impl ButtonDetector {
     }
 }
 
+impl Default for ButtonDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Text element detection (labels, text boxes, etc.)
 pub struct TextDetector {
     min_text_height: f64,
@@ -362,9 +378,11 @@ impl TextDetector {
                 
                 text_elements.push(UIElement {
                     bounds: region,
+                    shape: None,
                     element_type,
                     confidence,
                     properties: HashMap::new(),
+                    associated_label: None,
                 });
             }
         }
@@ -515,6 +533,12 @@ impl TextDetector {
     }
 }
 
+impl Default for TextDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Window detection for application windows
 pub struct WindowDetector {
     min_window_size: f64,
@@ -543,9 +567,11 @@ impl WindowDetector {
                 if confidence > 0.4 {
                     windows.push(UIElement {
                         bounds: candidate,
+                        shape: None,
                         element_type: ElementType::Window,
                         confidence,
                         properties: HashMap::new(),
+                        associated_label: None,
                     });
                 }
             }
@@ -598,7 +624,7 @@ impl WindowDetector {
         
         // Find right edge
         for x in start_x + 200..binary.width {
-            if self.has_vertical_edge(&binary, x, start_y, 50) {
+            if self.has_vertical_edge(binary, x, start_y, 50) {
                 width = x - start_x;
                 break;
             }
@@ -606,7 +632,7 @@ impl WindowDetector {
         
         // Find bottom edge
         for y in start_y + 150..binary.height {
-            if self.has_horizontal_edge(&binary, start_x, y, width) {
+            if self.has_horizontal_edge(binary, start_x, y, width) {
                 height = y - start_y;
                 break;
             }
@@ -655,6 +681,12 @@ impl WindowDetector {
     }
 }
 
+impl Default for WindowDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Menu detection for dropdown menus, context menus, etc.
 pub struct MenuDetector;
 
@@ -675,15 +707,362 @@ impl MenuDetector {
     }
 }
 
+impl Default for MenuDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Small interactive controls: checkboxes, radios, toggles, sliders, dropdowns and tab strips.
+// Works on an "ink" mask (pixels that stand out from the dominant background
+// shade) so the same shape tests hold in light and dark themes.
+pub struct ControlDetector {
+    min_contrast: u8,
+    min_component_pixels: usize,
+}
+
+/// Inclusive pixel bounds of an ink component
+#[derive(Debug, Clone, Copy)]
+struct InkBounds {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl InkBounds {
+    fn width(&self) -> usize {
+        self.x1 - self.x0 + 1
+    }
+
+    fn height(&self) -> usize {
+        self.y1 - self.y0 + 1
+    }
+
+    fn to_rectangle(self) -> Rectangle {
+        Rectangle::new(self.x0 as f64, self.y0 as f64, self.width() as f64, self.height() as f64)
+    }
+}
+
+struct InkMask {
+    binary: Image,
+}
+
+impl InkMask {
+    fn new(gray: &Image, min_contrast: u8) -> Self {
+        // The most common shade is taken as the background
+        let histogram = calculate_histogram(gray, 0);
+        let background = histogram
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, &count)| count)
+            .map(|(value, _)| value as i32)
+            .unwrap_or(255);
+
+        let mut binary = Image::new(gray.width, gray.height, 1);
+        for y in 0..gray.height {
+            for x in 0..gray.width {
+                if let Some(pixel) = gray.get_pixel(x, y) {
+                    if (pixel[0] as i32 - background).abs() > min_contrast as i32 {
+                        binary.set_pixel(x, y, &[255]);
+                    }
+                }
+            }
+        }
+        Self { binary }
+    }
+
+    fn is_ink(&self, x: usize, y: usize) -> bool {
+        self.binary.get_pixel(x, y).is_some_and(|p| p[0] > 0)
+    }
+
+    /// Fraction of ink in an inclusive rectangle
+    fn fill(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> f64 {
+        if x1 < x0 || y1 < y0 {
+            return 0.0;
+        }
+        let mut ink = 0;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                if self.is_ink(x, y) {
+                    ink += 1;
+                }
+            }
+        }
+        ink as f64 / ((x1 - x0 + 1) * (y1 - y0 + 1)) as f64
+    }
+
+    fn row_fill(&self, y: usize, x0: usize, x1: usize) -> f64 {
+        self.fill(x0, y, x1, y)
+    }
+
+    fn col_fill(&self, x: usize, y0: usize, y1: usize) -> f64 {
+        self.fill(x, y0, x, y1)
+    }
+
+    /// All four 2x2 corner patches are empty, as for circles and pills
+    fn has_round_corners(&self, b: &InkBounds) -> bool {
+        [(b.x0, b.y0), (b.x1 - 1, b.y0), (b.x0, b.y1 - 1), (b.x1 - 1, b.y1 - 1)]
+            .iter()
+            .all(|&(x, y)| self.fill(x, y, x + 1, y + 1) == 0.0)
+    }
+
+    /// Top, bottom, left and right borders are drawn
+    fn has_box_outline(&self, b: &InkBounds) -> bool {
+        self.row_fill(b.y0, b.x0, b.x1) >= 0.8
+            && self.row_fill(b.y1, b.x0, b.x1) >= 0.8
+            && self.col_fill(b.x0, b.y0, b.y1) >= 0.8
+            && self.col_fill(b.x1, b.y0, b.y1) >= 0.8
+    }
+}
+
+impl ControlDetector {
+    pub fn new() -> Self {
+        Self {
+            min_contrast: 60,
+            min_component_pixels: 12,
+        }
+    }
+
+    pub fn detect(&self, image: &Image) -> Result<Vec<UIElement>, VisionError> {
+        let gray = image.to_grayscale();
+        let mask = InkMask::new(&gray, self.min_contrast);
+
+        let mut controls = Vec::new();
+        for component in find_connected_components(&mask.binary) {
+            if component.len() < self.min_component_pixels {
+                continue;
+            }
+            let bounds = ink_bounds(&component);
+            controls.extend(self.classify(&mask, &bounds));
+        }
+
+        // Knobs, dots and checkmarks are separate components; keep only the outer control
+        let outer: Vec<Rectangle> = controls.iter().map(|c| c.bounds).collect();
+        controls.retain(|control| {
+            !outer.iter().any(|other| *other != control.bounds && contains_rect(other, &control.bounds))
+        });
+
+        Ok(controls)
+    }
+
+    fn classify(&self, mask: &InkMask, b: &InkBounds) -> Vec<UIElement> {
+        let (w, h) = (b.width(), b.height());
+        let aspect = w as f64 / h as f64;
+
+        if (10..=30).contains(&w) && (10..=30).contains(&h) && (0.8..=1.25).contains(&aspect) {
+            return self.detect_check_control(mask, b).into_iter().collect();
+        }
+        if (24..=80).contains(&w) && (12..=40).contains(&h) && (1.5..=3.0).contains(&aspect) {
+            if let Some(toggle) = self.detect_toggle(mask, b) {
+                return vec![toggle];
+            }
+        }
+        if w >= 60 && (4..=32).contains(&h) && aspect >= 4.0 {
+            if let Some(slider) = self.detect_slider(mask, b) {
+                return vec![slider];
+            }
+        }
+        if (18..=48).contains(&h) && aspect >= 2.5 {
+            let tabs = self.detect_tab_strip(mask, b);
+            if !tabs.is_empty() {
+                return tabs;
+            }
+            if let Some(dropdown) = self.detect_dropdown(mask, b) {
+                return vec![dropdown];
+            }
+        }
+        Vec::new()
+    }
+
+    /// Square box -> checkbox, ring -> radio; ink inside means checked
+    fn detect_check_control(&self, mask: &InkMask, b: &InkBounds) -> Option<UIElement> {
+        let (cx, cy) = ((b.x0 + b.x1) / 2, (b.y0 + b.y1) / 2);
+
+        if !mask.has_round_corners(b) && mask.has_box_outline(b) {
+            let checked = mask.fill(b.x0 + 3, b.y0 + 3, b.x1 - 3, b.y1 - 3) > 0.1;
+            return Some(control(ElementType::Checkbox, b, 0.8, &[("checked", bool_str(checked))]));
+        }
+
+        let ring_sides = mask.is_ink(cx, b.y0) && mask.is_ink(cx, b.y1) && mask.is_ink(b.x0, cy) && mask.is_ink(b.x1, cy);
+        // A ring leaves a gap between its edge and the center; a solid disk doesn't
+        let has_gap = (b.x0..cx).any(|x| !mask.is_ink(x, cy));
+        if mask.has_round_corners(b) && ring_sides && has_gap {
+            let r = (b.width() / 6).max(1);
+            let checked = mask.fill(cx - r, cy - r, cx + r, cy + r) > 0.5;
+            return Some(control(ElementType::Radio, b, 0.75, &[("checked", bool_str(checked))]));
+        }
+
+        None
+    }
+
+    /// Pill-shaped track with a knob at one end; knob on the right means "on"
+    fn detect_toggle(&self, mask: &InkMask, b: &InkBounds) -> Option<UIElement> {
+        let (cx, cy) = ((b.x0 + b.x1) / 2, (b.y0 + b.y1) / 2);
+        if !mask.has_round_corners(b) || !mask.is_ink(b.x0, cy) || !mask.is_ink(b.x1, cy) {
+            return None;
+        }
+
+        let band = (b.height() / 4).max(1);
+        let left = mask.fill(b.x0 + 2, cy - band, cx - 1, cy + band);
+        let right = mask.fill(cx + 1, cy - band, b.x1 - 2, cy + band);
+        if (left - right).abs() < 0.1 {
+            return None;
+        }
+
+        // Filled tracks show the knob as a hole; outlined tracks show it as ink
+        let filled_track = mask.is_ink(cx, cy);
+        let knob_right = if filled_track { right < left } else { right > left };
+        let state = if knob_right { "on" } else { "off" };
+        Some(control(ElementType::Toggle, b, 0.75, &[("state", state)]))
+    }
+
+    /// Thin centered track with one taller run of columns (the thumb)
+    fn detect_slider(&self, mask: &InkMask, b: &InkBounds) -> Option<UIElement> {
+        let h = b.height();
+        let band_top = b.y0 + h / 3;
+        let band_bottom = b.y1 - h / 3;
+
+        let mut track_columns = 0;
+        let mut thumb_columns = Vec::new();
+        for x in b.x0..=b.x1 {
+            let ink_rows: Vec<usize> = (b.y0..=b.y1).filter(|&y| mask.is_ink(x, y)).collect();
+            if ink_rows.len() as f64 >= 0.6 * h as f64 {
+                thumb_columns.push(x);
+            } else if !ink_rows.is_empty()
+                && ink_rows.iter().all(|&y| y + 1 >= band_top && y <= band_bottom + 1)
+            {
+                track_columns += 1;
+            }
+        }
+
+        let thumb_width = thumb_columns.len();
+        let contiguous = thumb_columns.last().zip(thumb_columns.first()).is_some_and(|(last, first)| last - first + 1 == thumb_width);
+        if !contiguous || thumb_width < 4 || thumb_width > b.width() / 4 || (track_columns as f64) < 0.6 * b.width() as f64 {
+            return None;
+        }
+
+        let thumb_center = (thumb_columns[0] + thumb_columns[thumb_width - 1]) as f64 / 2.0;
+        let value = ((thumb_center - b.x0 as f64) / (b.width() - 1) as f64).clamp(0.0, 1.0);
+        Some(control(ElementType::Slider, b, 0.7, &[("value", &format!("{:.2}", value))]))
+    }
+
+    /// Outlined box whose right end holds a downward chevron
+    fn detect_dropdown(&self, mask: &InkMask, b: &InkBounds) -> Option<UIElement> {
+        if !mask.has_box_outline(b) {
+            return None;
+        }
+
+        let region_x0 = b.x1 + 1 - b.height();
+        let spans: Vec<usize> = (b.y0 + 3..=b.y1 - 3)
+            .filter_map(|y| {
+                let xs: Vec<usize> = (region_x0..=b.x1 - 3).filter(|&x| mask.is_ink(x, y)).collect();
+                Some(xs.last()? - xs.first()? + 1)
+            })
+            .collect();
+        let (first, last) = (*spans.first()?, *spans.last()?);
+        if spans.len() < 3 || first < last + 2 {
+            return None;
+        }
+
+        Some(control(ElementType::Dropdown, b, 0.75, &[("expanded", "false")]))
+    }
+
+    /// Strip of boxes separated by full-height dividers; the tab with an
+    /// open bottom edge is the selected one
+    fn detect_tab_strip(&self, mask: &InkMask, b: &InkBounds) -> Vec<UIElement> {
+        if mask.row_fill(b.y0, b.x0, b.x1) < 0.8 {
+            return Vec::new();
+        }
+
+        // Group adjacent full-height columns into dividers
+        let mut dividers: Vec<(usize, usize)> = Vec::new();
+        for x in b.x0..=b.x1 {
+            if mask.col_fill(x, b.y0, b.y1) < 0.8 {
+                continue;
+            }
+            match dividers.last_mut() {
+                Some((_, end)) if *end + 1 == x => *end = x,
+                _ => dividers.push((x, x)),
+            }
+        }
+        let spans_strip = dividers.first().is_some_and(|d| d.0 <= b.x0 + 1)
+            && dividers.last().is_some_and(|d| d.1 + 1 >= b.x1);
+        if dividers.len() < 3 || !spans_strip {
+            return Vec::new();
+        }
+
+        let segments: Vec<InkBounds> = dividers
+            .windows(2)
+            .map(|pair| InkBounds { x0: pair[0].1 + 1, y0: b.y0, x1: pair[1].0 - 1, y1: b.y1 })
+            .collect();
+        if segments.iter().any(|s| s.x1 < s.x0 || s.width() < 30) {
+            return Vec::new();
+        }
+
+        let closed: Vec<bool> = segments.iter().map(|s| mask.row_fill(s.y1, s.x0, s.x1) >= 0.8).collect();
+        let open_count = closed.iter().filter(|c| !**c).count();
+
+        segments
+            .iter()
+            .zip(&closed)
+            .enumerate()
+            .map(|(index, (segment, closed))| {
+                let selected = open_count == 1 && !closed;
+                control(ElementType::Tab, segment, 0.7, &[("index", &index.to_string()), ("selected", bool_str(selected))])
+            })
+            .collect()
+    }
+}
+
+impl Default for ControlDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn control(element_type: ElementType, bounds: &InkBounds, confidence: f64, properties: &[(&str, &str)]) -> UIElement {
+    UIElement {
+        bounds: bounds.to_rectangle(),
+        shape: None,
+        element_type,
+        confidence,
+        properties: properties.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        associated_label: None,
+    }
+}
+
+fn bool_str(value: bool) -> &'static str {
+    if value { "true" } else { "false" }
+}
+
+fn ink_bounds(points: &[crate::utils::geometry::Point]) -> InkBounds {
+    let mut bounds = InkBounds { x0: usize::MAX, y0: usize::MAX, x1: 0, y1: 0 };
+    for point in points {
+        let (x, y) = (point.x as usize, point.y as usize);
+        bounds.x0 = bounds.x0.min(x);
+        bounds.y0 = bounds.y0.min(y);
+        bounds.x1 = bounds.x1.max(x);
+        bounds.y1 = bounds.y1.max(y);
+    }
+    bounds
+}
+
+fn contains_rect(outer: &Rectangle, inner: &Rectangle) -> bool {
+    inner.x >= outer.x
+        && inner.y >= outer.y
+        && inner.x + inner.width <= outer.x + outer.width
+        && inner.y + inner.height <= outer.y + outer.height
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_ui_detector_creation() {
-        let _detector = UIDetector::new();
         // Just verify it can be created without panicking
-        assert!(true);
+        let _detector = UIDetector::new();
     }
 
     #[test]
@@ -716,4 +1095,135 @@ mod tests {
         assert!(!detector.is_valid_button_size(&too_small));
         assert!(!detector.is_valid_button_size(&too_large));
     }
-}
\ No newline at end of file
+
+    fn canvas(width: usize, height: usize) -> Image {
+        let mut image = Image::new(width, height, 3);
+        fill(&mut image, 0, 0, width, height, 255);
+        image
+    }
+
+    fn fill(image: &mut Image, x: usize, y: usize, w: usize, h: usize, shade: u8) {
+        for py in y..y + h {
+            for px in x..x + w {
+                image.set_pixel(px, py, &[shade, shade, shade]);
+            }
+        }
+    }
+
+    fn outline(image: &mut Image, x: usize, y: usize, w: usize, h: usize) {
+        fill(image, x, y, w, 1, 40);
+        fill(image, x, y + h - 1, w, 1, 40);
+        fill(image, x, y, 1, h, 40);
+        fill(image, x + w - 1, y, 1, h, 40);
+    }
+
+    /// Pixels within `outer` of the center, excluding those within `inner`
+    fn ring(image: &mut Image, cx: f64, cy: f64, outer: f64, inner: f64) {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let d = ((x as f64 - cx).powi(2) + (y as f64 - cy).powi(2)).sqrt();
+                if d <= outer && d > inner {
+                    image.set_pixel(x, y, &[40, 40, 40]);
+                }
+            }
+        }
+    }
+
+    fn detect(image: &Image) -> Vec<UIElement> {
+        ControlDetector::new().detect(image).unwrap()
+    }
+
+    #[test]
+    fn test_checkbox_state() {
+        let mut image = canvas(80, 40);
+        outline(&mut image, 10, 10, 16, 16);
+        outline(&mut image, 50, 10, 16, 16);
+        // Checkmark inside the second box
+        for i in 0..6 {
+            fill(&mut image, 54 + i, 17 + i / 2, 1, 2, 40);
+        }
+
+        let controls = detect(&image);
+        assert_eq!(controls.len(), 2);
+        assert!(controls.iter().all(|c| c.element_type == ElementType::Checkbox));
+        let left = controls.iter().find(|c| c.bounds.x < 40.0).unwrap();
+        let right = controls.iter().find(|c| c.bounds.x > 40.0).unwrap();
+        assert_eq!(left.properties["checked"], "false");
+        assert_eq!(right.properties["checked"], "true");
+    }
+
+    #[test]
+    fn test_radio_state() {
+        let mut image = canvas(60, 30);
+        ring(&mut image, 15.0, 15.0, 8.0, 6.5);
+        ring(&mut image, 45.0, 15.0, 8.0, 6.5);
+        ring(&mut image, 45.0, 15.0, 3.5, -1.0);
+
+        let controls = detect(&image);
+        assert_eq!(controls.len(), 2);
+        assert!(controls.iter().all(|c| c.element_type == ElementType::Radio));
+        let checked: Vec<&str> = controls.iter().map(|c| c.properties["checked"].as_str()).collect();
+        assert!(checked.contains(&"true") && checked.contains(&"false"));
+    }
+
+    #[test]
+    fn test_toggle_state() {
+        // Filled pill with the knob (a hole) on the right: on
+        let mut image = canvas(60, 30);
+        fill(&mut image, 15, 5, 30, 20, 40);
+        fill(&mut image, 10, 8, 40, 14, 40);
+        ring(&mut image, 37.0, 15.0, 6.0, -1.0);
+        for y in 8..22 {
+            for x in 31..44 {
+                let d = ((x as f64 - 37.0).powi(2) + (y as f64 - 15.0).powi(2)).sqrt();
+                if d <= 5.0 {
+                    image.set_pixel(x, y, &[255, 255, 255]);
+                }
+            }
+        }
+
+        let controls = detect(&image);
+        assert_eq!(controls.len(), 1);
+        assert_eq!(controls[0].element_type, ElementType::Toggle);
+        assert_eq!(controls[0].properties["state"], "on");
+    }
+
+    #[test]
+    fn test_slider_value() {
+        let mut image = canvas(140, 30);
+        fill(&mut image, 10, 14, 120, 3, 40); // track
+        fill(&mut image, 36, 6, 8, 18, 40); // thumb at ~25%
+
+        let controls = detect(&image);
+        assert_eq!(controls.len(), 1);
+        assert_eq!(controls[0].element_type, ElementType::Slider);
+        let value: f64 = controls[0].properties["value"].parse().unwrap();
+        assert!((value - 0.25).abs() < 0.05, "value {}", value);
+    }
+
+    #[test]
+    fn test_dropdown_and_tab_strip() {
+        let mut image = canvas(260, 100);
+        // Dropdown with a chevron at its right end
+        outline(&mut image, 10, 10, 120, 26);
+        for i in 0..5 {
+            fill(&mut image, 111 + i, 19 + i, 1, 1, 40);
+            fill(&mut image, 119 - i, 19 + i, 1, 1, 40);
+        }
+
+        // Three tabs sharing borders; the middle one has no bottom edge
+        outline(&mut image, 10, 60, 181, 28);
+        fill(&mut image, 70, 60, 1, 28, 40);
+        fill(&mut image, 130, 60, 1, 28, 40);
+        fill(&mut image, 71, 87, 59, 1, 255);
+
+        let controls = detect(&image);
+        let dropdowns: Vec<_> = controls.iter().filter(|c| c.element_type == ElementType::Dropdown).collect();
+        let tabs: Vec<_> = controls.iter().filter(|c| c.element_type == ElementType::Tab).collect();
+        assert_eq!(dropdowns.len(), 1);
+        assert_eq!(tabs.len(), 3);
+        let selected: Vec<_> = tabs.iter().filter(|t| t.properties["selected"] == "true").collect();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].properties["index"], "1");
+    }
+}
@@ -0,0 +1,60 @@
+// The headless `luna-analyze` binary: analysis documents with timings for
+// each image, and failures that don't stop the rest.
+
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value;
+
+use luna::core::schema::{self, AnalysisRecord};
+
+fn screenshot(path: &Path) {
+    image::RgbImage::from_fn(160, 100, |x, y| {
+        let on_button = (40..120).contains(&x) && (40..64).contains(&y);
+        image::Rgb(if on_button { [40, 90, 200] } else { [245, 245, 245] })
+    })
+    .save(path)
+    .unwrap();
+}
+
+fn documents(stdout: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(stdout).lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+#[test]
+fn images_are_analyzed_and_timed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("screen.png");
+    screenshot(&path);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_luna-analyze"))
+        .args(["--json", "--preset", "fast", "--repeat", "2"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    let documents = documents(&output.stdout);
+    assert_eq!(documents.len(), 1);
+    let record: AnalysisRecord = schema::from_document(&documents[0]).unwrap();
+    assert_eq!(record.screen_size, [160, 100]);
+    assert_eq!((record.source.as_deref(), record.preset.as_deref()), (Some(path.to_str().unwrap()), Some("fast")));
+    assert_eq!(record.timings.unwrap().analysis_ms.len(), 2);
+}
+
+#[test]
+fn a_failed_image_is_reported_and_the_rest_still_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("screen.png");
+    screenshot(&path);
+    let missing = dir.path().join("missing.png");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_luna-analyze")).arg("--json").arg(&missing).arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let documents = documents(&output.stdout);
+    assert_eq!(documents[0]["schema"], "luna.error/v1");
+    assert!(documents[0]["error"].as_str().unwrap().starts_with(missing.to_str().unwrap()), "{}", documents[0]);
+    assert_eq!(documents[1]["schema"], "luna.analyze/v1");
+
+    let usage = Command::new(env!("CARGO_BIN_EXE_luna-analyze")).args(["--preset", "slow", "x.png"]).output().unwrap();
+    assert_eq!(usage.status.code(), Some(2));
+}
@@ -0,0 +1,18 @@
+// Replays the recorded frames under tests/corpus/ through the planner and
+// safety checks with the model replaced by its recorded output.
+
+use std::path::Path;
+
+use luna::core::replay::CorpusCase;
+use luna::{Luna, LunaConfig};
+
+#[test]
+fn recorded_frames_plan_as_expected() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let cases = CorpusCase::load_all(&root).unwrap();
+    assert!(!cases.is_empty(), "no corpus cases under {}", root.display());
+
+    let mut luna = Luna::new(LunaConfig::default()).unwrap();
+    let failures: Vec<String> = cases.iter().flat_map(|case| case.run(&mut luna).unwrap()).collect();
+    assert!(failures.is_empty(), "{} corpus expectation(s) failed:\n{}", failures.len(), failures.join("\n"));
+}
@@ -0,0 +1,80 @@
+// Types a corpus of strings on the keyboard layouts recorded under
+// tests/layouts/ and checks that the key strokes produce the same text.
+// The expected fallbacks document which characters each layout cannot
+// type with keys and sends as Unicode instead.
+
+use std::path::Path;
+
+use luna::input::layout::{KeyboardLayout, Keystroke};
+use luna::input::{BasicSafetyChecker, InputController};
+
+const CORPUS: &[&str] = &[
+    "Hello, World!",
+    "user.name+tag@example.com",
+    "C:\\Users\\Ada\\notes (1).txt",
+    "~/.config/luna/config.toml",
+    "{\"key\": [1, 2], 'x': `y`}",
+    "a^2 + b^2 = c^2; 50% off | #1 deal $5 & more?",
+    "1/2 < 3/4 > 0_0 - \"quoted\"",
+    "Tab\tseparated\nand two lines",
+    "Prix : 12,50 € (TTC) - déjà payé à l'hôtel",
+    "Grüße aus Köln, Straße 3 ³ ²",
+    "naïve café, µs, 20 °C, £10",
+    "Øresund 東京",
+];
+
+/// Characters of the corpus that each layout sends as Unicode, in order of
+/// first appearance. Dead keys count as missing: '~' and '`' on AZERTY, '^'
+/// and '`' on QWERTZ.
+const FALLBACKS: &[(&str, &str)] = &[
+    ("qwerty", "€éàôüßö³²ïµ°£Ø東京"),
+    ("azerty", "~`ôüßö³ïØ東京"),
+    ("qwertz", "`^éàôï£Ø東京"),
+    ("dvorak", "€éàôüßö³²ïµ°£Ø東京"),
+];
+
+fn load(name: &str) -> KeyboardLayout {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/layouts").join(format!("{}.txt", name));
+    let text = std::fs::read_to_string(&path).unwrap();
+    KeyboardLayout::parse(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+#[test]
+fn corpus_round_trips_on_every_layout() {
+    for (name, expected_fallbacks) in FALLBACKS {
+        let layout = load(name);
+        assert_eq!(layout.name, *name);
+        let mut controller = InputController::new(Box::new(BasicSafetyChecker::new()));
+        controller.set_layout(Some(layout.clone()));
+
+        let mut fallbacks = String::new();
+        for text in CORPUS {
+            let strokes = controller.keystrokes_for(text);
+            assert_eq!(layout.replay(&strokes), *text, "{} typed {:?} wrong", name, text);
+            for c in layout.fallbacks(text) {
+                if !fallbacks.contains(c) {
+                    fallbacks.push(c);
+                }
+            }
+        }
+        assert_eq!(fallbacks, *expected_fallbacks, "{} falls back for different characters", name);
+    }
+}
+
+#[test]
+fn layouts_type_the_same_character_with_different_keys() {
+    let key = |layout: &KeyboardLayout, c: char| layout.keystrokes(&c.to_string())[0];
+    let (qwerty, azerty, qwertz, dvorak) = (load("qwerty"), load("azerty"), load("qwertz"), load("dvorak"));
+
+    // The physical A key types 'q' on AZERTY; 'a' lives where QWERTY has 'q'
+    assert_eq!(key(&qwerty, 'a'), Keystroke::Key { scan_code: 0x1E, shift: false, altgr: false });
+    assert_eq!(key(&azerty, 'a'), Keystroke::Key { scan_code: 0x10, shift: false, altgr: false });
+    assert_eq!(key(&qwertz, 'z'), Keystroke::Key { scan_code: 0x15, shift: false, altgr: false });
+    assert_eq!(key(&dvorak, 's'), Keystroke::Key { scan_code: 0x27, shift: false, altgr: false });
+    // Digits need Shift on AZERTY; '@' needs AltGr on QWERTZ
+    assert_eq!(key(&azerty, '1'), Keystroke::Key { scan_code: 0x02, shift: true, altgr: false });
+    assert_eq!(key(&qwertz, '@'), Keystroke::Key { scan_code: 0x10, shift: false, altgr: true });
+    // '^' is a dead key on QWERTZ, so it is injected rather than composed
+    assert_eq!(key(&qwertz, '^'), Keystroke::Unicode('^'));
+    assert_eq!(key(&azerty, '^'), Keystroke::Key { scan_code: 0x0A, shift: false, altgr: true });
+}
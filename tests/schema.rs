@@ -0,0 +1,209 @@
+// Checks the versioned JSON documents against their JSON Schemas. The
+// schemas in schemas/ must match what the code generates, every document
+// recorded in tests/schema/ must still read and fit its schema, and
+// documents written now must round-trip and declare every field they use.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use luna::core::schema::{self, AnalysisRecord, CommandRecord, ErrorRecord, EventRecord, FindRecord, Record};
+use luna::core::restore::{RestoreOutcome, RestoreStep, RestoreTarget};
+use luna::core::tracker::ElementDelta;
+use luna::core::{ElementBounds, LunaAction, LunaEvent, ScreenAnalysis, ScreenElement, ShotTarget};
+use luna::utils::geometry::{Point, Polygon};
+use luna::{ExecuteOptions, Luna, LunaConfig};
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn schema_of(document: &Value) -> Value {
+    let tag = document["schema"].as_str().unwrap();
+    let file = format!("{}.json", tag.replace('/', "."));
+    schema::json_schemas().into_iter().find(|(name, _)| *name == file).unwrap_or_else(|| panic!("no schema for {}", tag)).1
+}
+
+/// Whether `value` fits `schema`, for the keywords the generated schemas use
+fn validate(schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+    if let Some(expected) = schema.get("const") {
+        return if expected == value { Ok(()) } else { Err(format!("{}: expected {}, got {}", at, expected, value)) };
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            return Err(format!("{}: {} is not one of {:?}", at, value, values));
+        }
+    }
+    if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+        if !options.iter().any(|option| validate(option, value, at).is_ok()) {
+            return Err(format!("{}: {} fits none of the alternatives", at, value));
+        }
+    }
+    if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+        let fits = options.iter().filter(|option| validate(option, value, at).is_ok()).count();
+        if fits != 1 {
+            return Err(format!("{}: {} fits {} variants, not one", at, value, fits));
+        }
+    }
+    let type_ok = match schema.get("type").and_then(Value::as_str) {
+        None => true,
+        Some("object") => value.is_object(),
+        Some("array") => value.is_array(),
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        Some("null") => value.is_null(),
+        Some(other) => return Err(format!("{}: unknown type {}", at, other)),
+    };
+    if !type_ok {
+        return Err(format!("{}: {} is not {}", at, value, schema["type"]));
+    }
+    for required in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+        if value.get(required.as_str().unwrap()).is_none() {
+            return Err(format!("{}: missing {}", at, required));
+        }
+    }
+    if let Some(fields) = value.as_object() {
+        for (name, field) in fields {
+            let path = format!("{}.{}", at, name);
+            match (schema.get("properties").and_then(|p| p.get(name)), schema.get("additionalProperties")) {
+                (Some(property), _) | (None, Some(property)) => validate(property, field, &path)?,
+                (None, None) => {}
+            }
+        }
+    }
+    if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+        for (index, item) in values.iter().enumerate() {
+            validate(items, item, &format!("{}[{}]", at, index))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fields of `value` that `schema` doesn't declare: what the code writes
+/// that the schema forgot. Free-form objects declare anything.
+fn undeclared(schema: &Value, value: &Value, at: &str) -> Vec<String> {
+    let branches = ["anyOf", "oneOf"].iter().filter_map(|k| schema.get(*k)?.as_array()).flatten();
+    if let Some(branch) = branches.clone().find(|branch| validate(branch, value, at).is_ok()) {
+        // Fields declared next to the alternatives are shared by all of them
+        let shared = |path: &String| {
+            let name = path.strip_prefix(at).and_then(|rest| rest.strip_prefix('.')).unwrap_or_default();
+            schema.get("properties").is_some_and(|p| p.get(name).is_some())
+        };
+        return undeclared(branch, value, at).into_iter().filter(|path| !shared(path)).collect();
+    }
+    let mut missing = Vec::new();
+    if let Some(fields) = value.as_object() {
+        let open = schema.get("additionalProperties").is_some() || schema.get("properties").is_none();
+        for (name, field) in fields {
+            match schema.get("properties").and_then(|p| p.get(name)) {
+                Some(property) => missing.extend(undeclared(property, field, &format!("{}.{}", at, name))),
+                None if !open => missing.push(format!("{}.{}", at, name)),
+                None => {}
+            }
+        }
+    }
+    if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+        missing.extend(values.iter().flat_map(|item| undeclared(items, item, &format!("{}[]", at))));
+    }
+    missing
+}
+
+/// Read `document` with the record of its schema and write it back
+fn reread(document: &Value) -> Value {
+    fn again<R: Record>(document: &Value) -> Value {
+        let record: R = schema::from_document(document).unwrap_or_else(|e| panic!("{}: {}", document, e));
+        schema::to_document(&record)
+    }
+    match document["schema"].as_str().unwrap().split('/').next().unwrap() {
+        CommandRecord::NAME => again::<CommandRecord>(document),
+        AnalysisRecord::NAME => again::<AnalysisRecord>(document),
+        FindRecord::NAME => again::<FindRecord>(document),
+        EventRecord::NAME => again::<EventRecord>(document),
+        ErrorRecord::NAME => again::<ErrorRecord>(document),
+        other => panic!("unknown schema {}", other),
+    }
+}
+
+fn check_written(document: &Value) {
+    let schema = schema_of(document);
+    validate(&schema, document, "$").unwrap_or_else(|e| panic!("{}\n{}", e, document));
+    let missing = undeclared(&schema, document, "$");
+    assert!(missing.is_empty(), "{} writes fields its schema doesn't declare: {:?}", document["schema"], missing);
+    assert_eq!(reread(document), *document, "round trip changed the document");
+}
+
+#[test]
+fn committed_schemas_match_the_code() {
+    for (name, generated) in schema::json_schemas() {
+        let path = root().join("schemas").join(&name);
+        let committed: Value = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_else(|| panic!("{} is missing; run `cargo run -- schema`", path.display()));
+        assert_eq!(committed, generated, "{} is out of date; run `cargo run -- schema`", name);
+    }
+}
+
+#[test]
+fn released_documents_still_read() {
+    let text = std::fs::read_to_string(root().join("tests/schema/v1.jsonl")).unwrap();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let document: Value = serde_json::from_str(line).unwrap();
+        validate(&schema_of(&document), &document, "$").unwrap_or_else(|e| panic!("{}\n{}", e, line));
+        // Reading fills in fields added since; writing it again still fits
+        check_written(&reread(&document));
+    }
+}
+
+#[test]
+fn written_documents_round_trip() {
+    let mut luna = Luna::new(LunaConfig::default()).unwrap();
+    let options = ExecuteOptions { dry_run: true, ..ExecuteOptions::default() };
+    let mut result = luna.execute_command("press ctrl+s", &options).unwrap();
+    result.restoration = vec![RestoreStep { target: RestoreTarget::Focus, outcome: RestoreOutcome::Skipped, detail: "window 42 has closed".to_string() }];
+    check_written(&schema::to_document(&CommandRecord::new("press ctrl+s", &result)));
+
+    let element = |element_type: &str, text: Option<&str>, parent, children| ScreenElement {
+        element_type: element_type.to_string(),
+        bounds: ElementBounds::new(10, 20, 300, 200),
+        shape: None,
+        confidence: 0.75,
+        text: text.map(String::from),
+        attributes: [("label".to_string(), "Name".to_string())].into(),
+        parent,
+        children,
+    };
+    let mut round = element("button", None, Some(0), Vec::new());
+    round.shape = Some(Polygon::new(vec![Point::new(10.0, 20.0), Point::new(40.0, 20.0), Point::new(25.0, 45.0)]));
+    let analysis = ScreenAnalysis {
+        elements: vec![element("dialog", Some("Save changes?"), None, vec![1]), round],
+        confidence: 0.5,
+        processing_time_ms: 40,
+        screen_size: (1920, 1080),
+        context: Default::default(),
+    };
+    let document = schema::to_document(&AnalysisRecord::from(&analysis));
+    check_written(&document);
+    let read = ScreenAnalysis::from(schema::from_document::<AnalysisRecord>(&document).unwrap());
+    assert_eq!(read.elements[1].shape.as_ref().map(|s| s.points.len()), Some(3));
+    assert_eq!(read.elements[0].children, [1]);
+
+    let click = LunaAction::Click { x: 1, y: 2 };
+    let shot = LunaAction::Screenshot { region: None, target: ShotTarget::File("out.png".into()) };
+    for event in [
+        LunaEvent::CommandReceived { command: "click save".to_string() },
+        LunaEvent::AnalysisComplete { analysis },
+        LunaEvent::ElementsChanged { delta: ElementDelta::default() },
+        LunaEvent::ActionsPlanned { actions: vec![click.clone(), shot] },
+        LunaEvent::ActionExecuted { action: click.clone(), success: false },
+        LunaEvent::StaleFrame { action: click, changed_fraction: 0.25 },
+        LunaEvent::Error { error: "boom".to_string() },
+    ] {
+        check_written(&schema::to_document(&EventRecord::from(&event)));
+    }
+
+    let error = anyhow::Error::from(luna::LunaError::Timeout("waited 10s".to_string()));
+    check_written(&schema::to_document(&ErrorRecord::from(&error)));
+}
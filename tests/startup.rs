@@ -0,0 +1,35 @@
+// Subsystems come up when first used: analyzing a saved screenshot touches
+// neither the screen nor the input devices.
+
+use luna::{Luna, LunaConfig};
+
+fn initialized(luna: &Luna) -> Vec<&'static str> {
+    luna.startup_times().iter().map(|(subsystem, _)| *subsystem).collect()
+}
+
+#[test]
+fn subsystems_start_on_first_use() {
+    let mut luna = Luna::new(LunaConfig::default()).unwrap();
+    assert_eq!(initialized(&luna), ["core"]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("screen.png");
+    image::RgbImage::from_fn(320, 200, |x, y| {
+        let on_button = (100..220).contains(&x) && (80..120).contains(&y);
+        image::Rgb(if on_button { [40, 90, 200] } else { [245, 245, 245] })
+    })
+    .save(&path)
+    .unwrap();
+    let analysis = luna.analyze_image_file(&path).unwrap();
+    assert_eq!(analysis.screen_size, (320, 200));
+    assert_eq!(initialized(&luna), ["core"]);
+
+    // A keyboard-only command needs input but no capture
+    luna.process_command("press ctrl+s").unwrap();
+    let started = initialized(&luna);
+    assert!(started.contains(&"input") && !started.contains(&"capture"), "{:?}", started);
+
+    luna.analyze_current_screen().unwrap();
+    luna.ensure_capture().unwrap();
+    assert_eq!(initialized(&luna).iter().filter(|s| **s == "capture").count(), 1);
+}